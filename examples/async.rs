@@ -1,12 +1,11 @@
 extern crate rusb;
-use rusb::{
-    Context, Device, DeviceDescriptor, DeviceHandle, Direction, Result, TransferType, UsbContext,
-};
+use rusb::{Context, Device, DeviceDescriptor, DeviceHandle, UsbContext};
 use std::str::FromStr;
 use std::time::Duration;
 
 #[derive(Debug)]
 struct Endpoint {
+    #[allow(dead_code)]
     config: u8,
     iface: u8,
     setting: u8,
@@ -26,8 +25,8 @@ fn main() {
 
     match Context::new() {
         Ok(mut context) => match open_device(&mut context, vid, pid) {
-            Some((mut device, device_desc, mut handle)) => {
-                read_device(&context, &mut device, &device_desc, &mut handle).unwrap()
+            Some((device, device_desc, mut handle)) => {
+                read_device(&context, &device, &device_desc, &mut handle).unwrap()
             }
             None => println!("could not find device {:04x}:{:04x}", vid, pid),
         },
@@ -165,8 +164,8 @@ fn read_endpoint<T: UsbContext>(
     }
 }
 
-fn configure_endpoint<'a, T: UsbContext>(
-    handle: &'a mut rusb::DeviceHandle<T>,
+fn configure_endpoint<T: UsbContext>(
+    handle: &mut rusb::DeviceHandle<T>,
     endpoint: &Endpoint,
 ) -> rusb::Result<()> {
     handle.set_auto_detach_kernel_driver(true).unwrap();
@@ -1,3 +1,6 @@
+// the example performs synchronous transfers, deprecated with the `async-only` feature
+#![allow(deprecated)]
+
 use rusb::{
     ConfigDescriptor, DeviceDescriptor, DeviceHandle, DeviceList, EndpointDescriptor,
     InterfaceDescriptor, Language, Result, Speed, UsbContext,
@@ -17,7 +20,7 @@ fn main() {
 fn list_devices() -> Result<()> {
     let timeout = Duration::from_secs(1);
 
-    for device in DeviceList::new()?.iter() {
+    for device in DeviceList::new()?.sorted_by_topology() {
         let device_desc = match device.device_descriptor() {
             Ok(d) => d,
             Err(_) => continue,
@@ -27,7 +30,7 @@ fn list_devices() -> Result<()> {
             match device.open() {
                 Ok(h) => match h.read_languages(timeout) {
                     Ok(l) => {
-                        if l.len() > 0 {
+                        if !l.is_empty() {
                             Some(UsbDevice {
                                 handle: h,
                                 language: l[0],
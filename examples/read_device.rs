@@ -1,3 +1,6 @@
+// the example performs synchronous transfers, deprecated with the `async-only` feature
+#![allow(deprecated)]
+
 use std::{slice, str::FromStr, time::Duration};
 
 use rusb::{
@@ -24,43 +27,18 @@ fn main() {
     let pid: u16 = FromStr::from_str(args[2].as_ref()).unwrap();
 
     match Context::new() {
-        Ok(mut context) => match open_device(&mut context, vid, pid) {
-            Some((mut device, device_desc, mut handle)) => {
+        Ok(context) => match context.find_devices().vendor_id(vid).product_id(pid).open() {
+            Ok(mut handle) => {
+                let mut device = handle.device();
+                let device_desc = device.device_descriptor().unwrap();
                 read_device(&mut device, &device_desc, &mut handle).unwrap()
             }
-            None => println!("could not find device {:04x}:{:04x}", vid, pid),
+            Err(_) => println!("could not find device {:04x}:{:04x}", vid, pid),
         },
         Err(e) => panic!("could not initialize libusb: {}", e),
     }
 }
 
-fn open_device<T: UsbContext>(
-    context: &mut T,
-    vid: u16,
-    pid: u16,
-) -> Option<(Device<T>, DeviceDescriptor, DeviceHandle<T>)> {
-    let devices = match context.devices() {
-        Ok(d) => d,
-        Err(_) => return None,
-    };
-
-    for device in devices.iter() {
-        let device_desc = match device.device_descriptor() {
-            Ok(d) => d,
-            Err(_) => continue,
-        };
-
-        if device_desc.vendor_id() == vid && device_desc.product_id() == pid {
-            match device.open() {
-                Ok(handle) => return Some((device, device_desc, handle)),
-                Err(_) => continue,
-            }
-        }
-    }
-
-    None
-}
-
 fn read_device<T: UsbContext>(
     device: &mut Device<T>,
     device_desc: &DeviceDescriptor,
@@ -74,7 +52,7 @@ fn read_device<T: UsbContext>(
     println!("Active configuration: {}", handle.active_configuration()?);
     println!("Languages: {:?}", languages);
 
-    if languages.len() > 0 {
+    if !languages.is_empty() {
         let language = languages[0];
 
         println!(
@@ -162,8 +140,7 @@ fn read_endpoint<T: UsbContext>(
     match configure_endpoint(handle, &endpoint) {
         Ok(_) => {
             let mut vec = Vec::<u8>::with_capacity(256);
-            let buf =
-                unsafe { slice::from_raw_parts_mut((&mut vec[..]).as_mut_ptr(), vec.capacity()) };
+            let buf = unsafe { slice::from_raw_parts_mut(vec[..].as_mut_ptr(), vec.capacity()) };
 
             let timeout = Duration::from_secs(1);
 
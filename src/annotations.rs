@@ -0,0 +1,288 @@
+use std::{
+    any::{Any, TypeId},
+    collections::HashMap,
+    fmt,
+    marker::PhantomData,
+    sync::{Arc, Mutex, MutexGuard},
+};
+
+use libusb1_sys::{libusb_context, libusb_device, libusb_ref_device, libusb_unref_device};
+
+/// The annotated devices, keyed by the address of their `libusb_device`.
+///
+/// Each entry holds a reference to its device, so the address can't be reused by another device
+/// while annotations are attached to it.
+static REGISTRY: Mutex<Option<HashMap<usize, Entry>>> = Mutex::new(None);
+
+struct Entry {
+    context: usize,
+    values: TypeMap,
+}
+
+/// Values keyed by their type.
+#[derive(Default)]
+struct TypeMap {
+    values: HashMap<TypeId, Arc<dyn Any + Send + Sync>>,
+}
+
+impl TypeMap {
+    fn insert<A: Any + Send + Sync>(&mut self, value: A) -> Option<Arc<A>> {
+        self.values
+            .insert(TypeId::of::<A>(), Arc::new(value))
+            .and_then(|old| old.downcast().ok())
+    }
+
+    fn get<A: Any + Send + Sync>(&self) -> Option<Arc<A>> {
+        self.values
+            .get(&TypeId::of::<A>())
+            .and_then(|value| value.clone().downcast().ok())
+    }
+
+    fn remove<A: Any + Send + Sync>(&mut self) -> Option<Arc<A>> {
+        self.values
+            .remove(&TypeId::of::<A>())
+            .and_then(|old| old.downcast().ok())
+    }
+
+    fn len(&self) -> usize {
+        self.values.len()
+    }
+}
+
+fn registry() -> MutexGuard<'static, Option<HashMap<usize, Entry>>> {
+    REGISTRY.lock().unwrap_or_else(|p| p.into_inner())
+}
+
+/// User data attached to a device, see
+/// [`Device::annotations`](struct.Device.html#method.annotations).
+///
+/// Annotations are keyed by their type, so a framework typically defines its own type and stores
+/// one value of it per device. They belong to the device rather than to the `Device` they were
+/// inserted through: every `Device` and `DeviceHandle` for the same device, e.g. one listed
+/// again later or delivered by a hotplug callback, sees them.
+///
+/// While annotated, a device stays referenced, even after it was unplugged. Annotations are
+/// dropped with [`clear`](#method.clear), once the last one is removed, or when the context the
+/// device belongs to is dropped.
+///
+/// ```no_run
+/// use rusb::{Context, UsbContext};
+///
+/// struct Role(&'static str);
+///
+/// # fn main() -> rusb::Result<()> {
+/// let context = Context::new()?;
+/// for device in context.devices()?.iter() {
+///     device.annotations().insert(Role("sensor"));
+/// }
+///
+/// for device in context.devices()?.iter() {
+///     if let Some(role) = device.annotations().get::<Role>() {
+///         println!("{:?}: {}", device, role.0);
+///     }
+/// }
+/// # Ok(())
+/// # }
+/// ```
+pub struct Annotations<'a> {
+    context: *mut libusb_context,
+    device: *mut libusb_device,
+    _device: PhantomData<&'a ()>,
+}
+
+impl<'a> Annotations<'a> {
+    /// # Safety
+    ///
+    /// `device` must be a device of `context`, referenced for at least `'a`.
+    pub(crate) unsafe fn new(
+        context: *mut libusb_context,
+        device: *mut libusb_device,
+    ) -> Annotations<'a> {
+        Annotations {
+            context,
+            device,
+            _device: PhantomData,
+        }
+    }
+
+    /// Attaches `value`, replacing the value of the same type, which is returned.
+    pub fn insert<A: Any + Send + Sync>(&self, value: A) -> Option<Arc<A>> {
+        let mut registry = registry();
+        let entry = registry
+            .get_or_insert_with(HashMap::new)
+            .entry(self.device as usize)
+            .or_insert_with(|| {
+                unsafe { libusb_ref_device(self.device) };
+                Entry {
+                    context: self.context as usize,
+                    values: TypeMap::default(),
+                }
+            });
+        entry.values.insert(value)
+    }
+
+    /// Returns the value of type `A`, if one is attached.
+    pub fn get<A: Any + Send + Sync>(&self) -> Option<Arc<A>> {
+        let registry = registry();
+        registry
+            .as_ref()?
+            .get(&(self.device as usize))?
+            .values
+            .get()
+    }
+
+    /// Indicates whether a value of type `A` is attached.
+    pub fn contains<A: Any + Send + Sync>(&self) -> bool {
+        self.get::<A>().is_some()
+    }
+
+    /// Detaches the value of type `A`, and returns it.
+    pub fn remove<A: Any + Send + Sync>(&self) -> Option<Arc<A>> {
+        let mut registry = registry();
+        let devices = registry.as_mut()?;
+        let entry = devices.get_mut(&(self.device as usize))?;
+        let value = entry.values.remove();
+        if entry.values.len() == 0 {
+            devices.remove(&(self.device as usize));
+            unsafe { libusb_unref_device(self.device) };
+        }
+        value
+    }
+
+    /// Detaches every value.
+    pub fn clear(&self) {
+        let entry = registry()
+            .as_mut()
+            .and_then(|devices| devices.remove(&(self.device as usize)));
+        // the values are dropped without the lock held, in case their drop uses annotations
+        if let Some(entry) = entry {
+            drop(entry);
+            unsafe { libusb_unref_device(self.device) };
+        }
+    }
+
+    /// Returns the number of attached values.
+    pub fn len(&self) -> usize {
+        let registry = registry();
+        registry
+            .as_ref()
+            .and_then(|devices| devices.get(&(self.device as usize)))
+            .map_or(0, |entry| entry.values.len())
+    }
+
+    /// Indicates whether no value is attached.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl<'a> fmt::Debug for Annotations<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Annotations")
+            .field("len", &self.len())
+            .finish()
+    }
+}
+
+/// Drops the annotations of the devices of `context`, which is about to be exited.
+pub(crate) fn forget_context(context: *mut libusb_context) {
+    // the values are dropped without the lock held, in case their drop uses annotations
+    for (device, entry) in take_entries(context as usize) {
+        drop(entry);
+        unsafe { libusb_unref_device(device as *mut libusb_device) };
+    }
+}
+
+/// Removes the entries of the devices of `context` from the registry, and returns them with the
+/// address of their device.
+fn take_entries(context: usize) -> Vec<(usize, Entry)> {
+    let mut registry = registry();
+    let devices = match registry.as_mut() {
+        Some(devices) => devices,
+        None => return Vec::new(),
+    };
+
+    let forgotten: Vec<usize> = devices
+        .iter()
+        .filter(|(_, entry)| entry.context == context)
+        .map(|(&device, _)| device)
+        .collect();
+    forgotten
+        .into_iter()
+        .filter_map(|device| devices.remove(&device).map(|entry| (device, entry)))
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[derive(Debug, PartialEq)]
+    struct Role(&'static str);
+
+    #[test]
+    fn it_keys_values_by_type() {
+        let mut values = TypeMap::default();
+
+        assert_eq!(None, values.insert(Role("sensor")));
+        assert_eq!(None, values.insert(7u32));
+        assert_eq!(2, values.len());
+
+        assert_eq!(
+            Some(Role("sensor")),
+            values.get::<Role>().map(|r| Role(r.0))
+        );
+        assert_eq!(Some(7), values.get::<u32>().map(|v| *v));
+        assert_eq!(None, values.get::<u64>());
+    }
+
+    #[test]
+    fn it_returns_replaced_and_removed_values() {
+        let mut values = TypeMap::default();
+        values.insert(Role("sensor"));
+
+        assert_eq!(
+            Some(Arc::new(Role("sensor"))),
+            values.insert(Role("actuator"))
+        );
+        assert_eq!(Some(Arc::new(Role("actuator"))), values.remove::<Role>());
+        assert_eq!(None, values.remove::<Role>());
+        assert_eq!(0, values.len());
+    }
+
+    /// Looks at the registry when dropped.
+    struct Inspector;
+
+    impl Drop for Inspector {
+        fn drop(&mut self) {
+            let _ = registry().as_ref().map(HashMap::len);
+        }
+    }
+
+    #[test]
+    fn it_takes_the_entries_of_a_context_out_of_the_registry() {
+        // addresses no real context or device can have
+        let (context, other) = (usize::MAX - 15, usize::MAX - 31);
+        for &(device, context) in &[(1, context), (3, other), (5, context)] {
+            let mut values = TypeMap::default();
+            values.insert(Inspector);
+            registry()
+                .get_or_insert_with(HashMap::new)
+                .insert(usize::MAX - device, Entry { context, values });
+        }
+
+        let mut taken: Vec<usize> = take_entries(context)
+            .into_iter()
+            .map(|(device, entry)| {
+                // dropping it here would deadlock if the registry was still locked
+                drop(entry);
+                usize::MAX - device
+            })
+            .collect();
+        taken.sort_unstable();
+
+        assert_eq!(vec![1, 5], taken);
+        let left = registry().as_mut().unwrap().remove(&(usize::MAX - 3));
+        assert_eq!(Some(other), left.map(|entry| entry.context));
+    }
+}
@@ -1,9 +1,21 @@
 use libc::{c_int, c_uchar, c_uint, c_void};
 use std::cell::UnsafeCell;
-use std::collections::{HashSet, VecDeque};
-use std::{marker::PhantomData, mem, slice, sync::Mutex, time::Duration};
+use std::collections::{HashMap, VecDeque};
+use std::convert::TryFrom;
+use std::{
+    marker::PhantomData,
+    mem, slice,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        mpsc::{self, Receiver, Sender},
+        Condvar, Mutex, MutexGuard,
+    },
+    time::{Duration, Instant},
+};
 
-use crate::{constants::*, Context, DeviceHandle, Error, Result, UsbContext};
+use crate::{
+    constants::*, setup_packet::SetupPacket, Context, DeviceHandle, Error, Result, UsbContext,
+};
 
 /// An asynchronous transfer that is not currently pending.
 /// Specifies the data necessary to perform a transfer on a specified endpoint, and holds the
@@ -12,6 +24,34 @@ pub struct Transfer<'d, T: UsbContext> {
     _handle: PhantomData<&'d DeviceHandle<T>>, // transfer.dev_handle
     _buffer: PhantomData<&'d mut [u8]>,        // transfer.data
     transfer: *mut libusb1_sys::libusb_transfer,
+    id: Option<TransferId>,
+    /// An OUT transfer on a handle opened read-only, refused when submitted.
+    refused: bool,
+}
+
+// A transfer that isn't pending is owned data: the handle it borrows is `Sync`, and libusb lets
+// any thread submit, inspect and free it.
+unsafe impl<'d, T: UsbContext> Send for Transfer<'d, T> {}
+unsafe impl<'d, T: UsbContext> Sync for Transfer<'d, T> {}
+
+/// Identifies a submission of a transfer to an [`AsyncGroup`](struct.AsyncGroup.html).
+///
+/// Every submission gets a new ID, unique within the process, so an ID never refers to a later
+/// request even once its transfer has been returned and resubmitted. A transfer resubmitted from
+/// the completion handler keeps its ID.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct TransferId(u64);
+
+impl TransferId {
+    fn next() -> TransferId {
+        static NEXT: AtomicU64 = AtomicU64::new(1);
+        TransferId(NEXT.fetch_add(1, Ordering::Relaxed))
+    }
+
+    /// Returns the ID as a number, e.g. to correlate it in logs.
+    pub fn as_u64(self) -> u64 {
+        self.0
+    }
 }
 
 /// The status of a Transfer returned by wait_any.
@@ -35,11 +75,25 @@ pub enum TransferStatus {
     /// Device was disconnected
     NoDevice = LIBUSB_TRANSFER_NO_DEVICE as isize,
 
-    /// Device sent more data than requested
+    /// Device sent more data than requested. See
+    /// [`Error::Overflow`](enum.Error.html#variant.Overflow) for how to recover.
     Overflow = LIBUSB_TRANSFER_OVERFLOW as isize,
 
     /// No status, not yet submitted
-    Unknown = -1 as isize,
+    Unknown = -1,
+}
+
+/// A scheduling hint for transfers submitted to an [`AsyncGroup`](struct.AsyncGroup.html).
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum Priority {
+    /// Submitted right away. Control and interrupt OUT transfers with this priority are used to
+    /// measure the latency of the device.
+    Normal,
+
+    /// Held back while the latency of the device's normal control and interrupt OUT transfers is
+    /// above the group's threshold, see
+    /// [`AsyncGroup::set_background_throttle`](struct.AsyncGroup.html#method.set_background_throttle).
+    Background,
 }
 
 impl<'d, T: UsbContext> Transfer<'d, T> {
@@ -48,11 +102,13 @@ impl<'d, T: UsbContext> Transfer<'d, T> {
         endpoint: u8,
         transfer_type: c_uchar,
         buffer: &'d mut [u8],
+        iso_packets: usize,
         timeout: Duration,
     ) -> Transfer<'d, T> {
         let timeout_ms = timeout.as_secs() * 1000 + timeout.subsec_nanos() as u64 / 1_000_000;
         unsafe {
-            let t = libusb1_sys::libusb_alloc_transfer(0);
+            let t = libusb1_sys::libusb_alloc_transfer(iso_packets as c_int);
+            (*t).num_iso_packets = iso_packets as c_int;
             (*t).status = -1;
             (*t).dev_handle = handle.as_raw();
             (*t).endpoint = endpoint as c_uchar;
@@ -62,8 +118,17 @@ impl<'d, T: UsbContext> Transfer<'d, T> {
             (*t).length = buffer.len() as i32;
             (*t).actual_length = 0;
 
+            #[cfg(feature = "leak-detection")]
+            crate::leak_detection::track(
+                crate::leak_detection::ResourceKind::Transfer,
+                t,
+                handle.context().as_raw(),
+            );
+
             Transfer {
                 transfer: t,
+                id: None,
+                refused: refuses(handle, t),
                 _handle: PhantomData,
                 _buffer: PhantomData,
             }
@@ -77,7 +142,39 @@ impl<'d, T: UsbContext> Transfer<'d, T> {
         buffer: &'d mut [u8],
         timeout: Duration,
     ) -> Transfer<'d, T> {
-        Transfer::new(handle, endpoint, LIBUSB_TRANSFER_TYPE_BULK, buffer, timeout)
+        Transfer::new(
+            handle,
+            endpoint,
+            LIBUSB_TRANSFER_TYPE_BULK,
+            buffer,
+            0,
+            timeout,
+        )
+    }
+
+    /// Creates an asynchronous bulk transfer on the stream `stream_id` of a SuperSpeed bulk
+    /// endpoint, but does not submit it.
+    ///
+    /// The stream must have been allocated with
+    /// [`DeviceHandle::alloc_streams`](struct.DeviceHandle.html#method.alloc_streams); otherwise
+    /// the transfer fails when submitted.
+    pub fn bulk_stream(
+        handle: &'d DeviceHandle<T>,
+        endpoint: u8,
+        stream_id: u32,
+        buffer: &'d mut [u8],
+        timeout: Duration,
+    ) -> Transfer<'d, T> {
+        let transfer = Transfer::new(
+            handle,
+            endpoint,
+            LIBUSB_TRANSFER_TYPE_BULK_STREAM,
+            buffer,
+            0,
+            timeout,
+        );
+        unsafe { libusb1_sys::libusb_transfer_set_stream_id(transfer.transfer, stream_id) };
+        transfer
     }
 
     /// Creates an asynchronous interrupt transfer, but does not submit it.
@@ -92,25 +189,187 @@ impl<'d, T: UsbContext> Transfer<'d, T> {
             endpoint,
             LIBUSB_TRANSFER_TYPE_INTERRUPT,
             buffer,
+            0,
             timeout,
         )
     }
 
+    /// Creates an asynchronous control transfer, but does not submit it.
+    ///
+    /// The first 8 bytes of `buffer` are reserved for the setup packet, which is built from the
+    /// other arguments; the rest is the data stage: the data to send for an OUT request, or room
+    /// for the data to receive for an IN request, as given by the direction in `request_type`.
+    /// Once the transfer completed, [`control_data`](#method.control_data) returns the data
+    /// stage without the setup packet.
+    ///
+    /// ## Panics
+    ///
+    /// Panics if `buffer` is shorter than the setup packet, or its data stage is longer than
+    /// 65535 bytes.
+    pub fn control(
+        handle: &'d DeviceHandle<T>,
+        request_type: u8,
+        request: u8,
+        value: u16,
+        index: u16,
+        buffer: &'d mut [u8],
+        timeout: Duration,
+    ) -> Transfer<'d, T> {
+        write_setup(buffer, request_type, request, value, index);
+
+        Transfer::new(handle, 0, LIBUSB_TRANSFER_TYPE_CONTROL, buffer, 0, timeout)
+    }
+
+    /// Creates an asynchronous isochronous transfer of `packets` packets of `packet_size` bytes,
+    /// but does not submit it.
+    ///
+    /// The packets are laid out back to back at the start of `buffer`. `packet_size` is usually
+    /// the maximum packet size of the endpoint in the current alternate setting, multiplied by
+    /// the number of transactions per microframe for high-bandwidth endpoints, see
+    /// [`Device::max_iso_packet_size`](struct.Device.html#method.max_iso_packet_size). The
+    /// outcome of each packet is available with [`iso_packet`](#method.iso_packet) once the
+    /// transfer completed; [`actual`](#method.actual) is not meaningful for isochronous
+    /// transfers.
+    ///
+    /// ## Panics
+    ///
+    /// Panics if `buffer` is shorter than `packets * packet_size` bytes, or `packet_size` doesn't
+    /// fit in 32 bits.
+    pub fn isochronous(
+        handle: &'d DeviceHandle<T>,
+        endpoint: u8,
+        buffer: &'d mut [u8],
+        packets: usize,
+        packet_size: usize,
+        timeout: Duration,
+    ) -> Transfer<'d, T> {
+        let total = packets.checked_mul(packet_size);
+        assert!(
+            total.is_some_and(|total| total <= buffer.len()),
+            "the buffer is too small for the packets"
+        );
+        let packet_size = c_uint::try_from(packet_size).expect("the packet size is too large");
+
+        let transfer = Transfer::new(
+            handle,
+            endpoint,
+            LIBUSB_TRANSFER_TYPE_ISOCHRONOUS,
+            buffer,
+            packets,
+            timeout,
+        );
+        for index in 0..packets {
+            unsafe { (*iso_packet_desc(transfer.transfer, index)).length = packet_size };
+        }
+        transfer
+    }
+
+    /// Creates an asynchronous transfer from a `libusb_transfer` prepared by the caller, but does
+    /// not submit it.
+    ///
+    /// This is an escape hatch for transfer types and flags that are not covered by the other
+    /// constructors. The caller fills in the endpoint, type, timeout, flags and any isochronous
+    /// packet descriptors; rusb takes ownership of the transfer and manages the rest in the same
+    /// way as for its own transfers:
+    ///
+    /// * `dev_handle`, `buffer` and `length` are overwritten from `handle` and `buffer`, so the
+    ///   borrow checker keeps both alive for as long as the transfer exists.
+    /// * `callback` and `user_data` are overwritten by [`AsyncGroup::submit`], which routes the
+    ///   completion back to [`AsyncGroup::wait_any`].
+    /// * The `LIBUSB_TRANSFER_FREE_BUFFER` and `LIBUSB_TRANSFER_FREE_TRANSFER` flags are cleared,
+    ///   because the buffer is borrowed and the transfer is freed when the `Transfer` is dropped.
+    ///   Other flags, e.g. `LIBUSB_TRANSFER_SHORT_NOT_OK`, are kept. See
+    ///   [`into_raw`](#method.into_raw) for taking the transfer back.
+    ///
+    /// # Safety
+    ///
+    /// `transfer` must be a non-null pointer returned by `libusb_alloc_transfer` that is not
+    /// owned by anything else and is not currently submitted. Any fields left for libusb to
+    /// interpret (for example `num_iso_packets` and `iso_packet_desc`) must be consistent with
+    /// `buffer` and with the number of packets the transfer was allocated with.
+    pub unsafe fn from_raw_parts(
+        handle: &'d DeviceHandle<T>,
+        buffer: &'d mut [u8],
+        transfer: *mut libusb1_sys::libusb_transfer,
+    ) -> Transfer<'d, T> {
+        (*transfer).status = -1;
+        (*transfer).dev_handle = handle.as_raw();
+        (*transfer).flags &= !(LIBUSB_TRANSFER_FREE_BUFFER | LIBUSB_TRANSFER_FREE_TRANSFER);
+        (*transfer).buffer = buffer.as_mut_ptr();
+        (*transfer).length = buffer.len() as i32;
+        (*transfer).actual_length = 0;
+
+        #[cfg(feature = "leak-detection")]
+        crate::leak_detection::track(
+            crate::leak_detection::ResourceKind::Transfer,
+            transfer,
+            handle.context().as_raw(),
+        );
+
+        Transfer {
+            transfer,
+            id: None,
+            refused: refuses(handle, transfer),
+            _handle: PhantomData,
+            _buffer: PhantomData,
+        }
+    }
+
+    /// Gives up the ownership of the `libusb_transfer`, and returns it.
+    ///
+    /// This is the counterpart of [`from_raw_parts`](#method.from_raw_parts) for code managing
+    /// transfers itself, e.g. a pool of transfers allocated once and reused with different
+    /// handles and buffers. rusb no longer frees the transfer, so its ownership maps to the
+    /// transfer flags as follows:
+    ///
+    /// * Without `LIBUSB_TRANSFER_FREE_TRANSFER`, the caller frees the transfer with
+    ///   `libusb_free_transfer`, or hands it back to rusb with `from_raw_parts`, which clears the
+    ///   flag again.
+    /// * With `LIBUSB_TRANSFER_FREE_TRANSFER`, set by the caller before submitting the transfer
+    ///   itself, libusb frees it after its completion callback returns.
+    /// * `LIBUSB_TRANSFER_FREE_BUFFER` makes libusb `free()` the buffer, so it must only be set
+    ///   once the buffer was replaced with one allocated with `malloc`: the buffer rusb set is
+    ///   borrowed, and its borrow ends here.
+    ///
+    /// The handle and buffer the transfer points to are no longer borrowed either, so the caller
+    /// must not submit the transfer once they are gone. A pending transfer can't be turned into a
+    /// raw one: [`AsyncGroup`](struct.AsyncGroup.html) only returns transfers that completed.
+    pub fn into_raw(self) -> *mut libusb1_sys::libusb_transfer {
+        #[cfg(feature = "leak-detection")]
+        crate::leak_detection::untrack(self.transfer);
+
+        let transfer = self.transfer;
+        mem::forget(self);
+        transfer
+    }
+
+    /// Get the raw `libusb_transfer` pointer, for advanced use in unsafe code.
+    ///
+    /// The transfer remains owned by this `Transfer`. Its `dev_handle`, `buffer`, `length`,
+    /// `callback` and `user_data` fields are managed by rusb and must not be changed.
+    pub fn as_raw(&self) -> *mut libusb1_sys::libusb_transfer {
+        self.transfer
+    }
+
+    /// Returns the address of the endpoint the transfer is for.
+    pub fn endpoint(&self) -> u8 {
+        unsafe { (*self.transfer).endpoint }
+    }
+
+    /// Returns the ID of the submission that completed, for a transfer returned by
+    /// [`AsyncGroup::wait_any`](struct.AsyncGroup.html#method.wait_any), or `None` for a transfer
+    /// that was never submitted.
+    pub fn id(&self) -> Option<TransferId> {
+        self.id
+    }
+
     /// Gets the status of a completed transfer.
     pub fn status(&self) -> TransferStatus {
-        match unsafe { (*self.transfer).status } {
-            LIBUSB_TRANSFER_COMPLETED => TransferStatus::Success,
-            LIBUSB_TRANSFER_ERROR => TransferStatus::Error,
-            LIBUSB_TRANSFER_TIMED_OUT => TransferStatus::Timeout,
-            LIBUSB_TRANSFER_CANCELLED => TransferStatus::Cancelled,
-            LIBUSB_TRANSFER_STALL => TransferStatus::Stall,
-            LIBUSB_TRANSFER_NO_DEVICE => TransferStatus::NoDevice,
-            _ => TransferStatus::Unknown,
-        }
+        unsafe { status_of(self.transfer) }
     }
 
     /// Access the buffer of a transfer.
-    pub fn buffer(&mut self) -> &'d mut [u8] {
+    pub fn buffer(&mut self) -> &mut [u8] {
         unsafe {
             slice::from_raw_parts_mut((*self.transfer).buffer, (*self.transfer).length as usize)
         }
@@ -125,8 +384,20 @@ impl<'d, T: UsbContext> Transfer<'d, T> {
         }
     }
 
+    /// Sets the number of bytes of the buffer to transfer, e.g. to reuse a transfer for shorter
+    /// data without giving up the rest of its buffer.
+    ///
+    /// # Safety
+    ///
+    /// The buffer the transfer was created with, or last given to `set_buffer`, must be at least
+    /// `len` bytes long.
+    pub(crate) unsafe fn set_length(&mut self, len: usize) {
+        (*self.transfer).length = len as i32;
+        (*self.transfer).actual_length = 0;
+    }
+
     /// Access the slice of the buffer containing actual data received on an IN transfer.
-    pub fn actual(&mut self) -> &'d mut [u8] {
+    pub fn actual(&mut self) -> &mut [u8] {
         unsafe {
             slice::from_raw_parts_mut(
                 (*self.transfer).buffer,
@@ -134,18 +405,154 @@ impl<'d, T: UsbContext> Transfer<'d, T> {
             )
         }
     }
+
+    /// Returns the stream ID of a transfer created with [`bulk_stream`](#method.bulk_stream), or
+    /// `None` for the other transfer types.
+    pub fn stream_id(&self) -> Option<u32> {
+        unsafe {
+            if (*self.transfer).transfer_type == LIBUSB_TRANSFER_TYPE_BULK_STREAM {
+                Some(libusb1_sys::libusb_transfer_get_stream_id(self.transfer))
+            } else {
+                None
+            }
+        }
+    }
+
+    /// Access the data stage of a control transfer: the data received by a completed IN request,
+    /// or sent by a completed OUT request, without the setup packet.
+    ///
+    /// Empty for other transfer types.
+    pub fn control_data(&mut self) -> &mut [u8] {
+        unsafe {
+            let transfer = &*self.transfer;
+            if transfer.transfer_type != LIBUSB_TRANSFER_TYPE_CONTROL {
+                return &mut [];
+            }
+
+            let room = (transfer.length as usize).saturating_sub(SetupPacket::SIZE);
+            let len = (transfer.actual_length.max(0) as usize).min(room);
+            if len == 0 {
+                return &mut [];
+            }
+            slice::from_raw_parts_mut(transfer.buffer.add(SetupPacket::SIZE), len)
+        }
+    }
+
+    /// Returns the number of isochronous packets of the transfer, zero for other transfer types.
+    pub fn num_iso_packets(&self) -> usize {
+        unsafe { (*self.transfer).num_iso_packets.max(0) as usize }
+    }
+
+    /// Returns the isochronous packet at `index`, or `None` if there is no such packet.
+    ///
+    /// Each packet of a completed transfer has its own status: a transfer can succeed as a whole
+    /// while some of its packets failed, e.g. because of a CRC error.
+    pub fn iso_packet(&self, index: usize) -> Option<IsoPacket<'_>> {
+        if index >= self.num_iso_packets() {
+            return None;
+        }
+
+        unsafe {
+            let offset: usize = (0..index)
+                .map(|i| (*iso_packet_desc(self.transfer, i)).length as usize)
+                .sum();
+            let desc = &*iso_packet_desc(self.transfer, index);
+            let actual = (desc.actual_length as usize).min(desc.length as usize);
+
+            Some(IsoPacket {
+                length: desc.length as usize,
+                status: status_from(desc.status),
+                data: slice::from_raw_parts((*self.transfer).buffer.add(offset), actual),
+            })
+        }
+    }
+}
+
+/// Writes the setup packet of a control transfer at the start of `buffer`, with `wLength` set
+/// from the room left after it.
+fn write_setup(buffer: &mut [u8], request_type: u8, request: u8, value: u16, index: u16) {
+    assert!(
+        buffer.len() >= SetupPacket::SIZE,
+        "the buffer is too small for the setup packet"
+    );
+    let length =
+        u16::try_from(buffer.len() - SetupPacket::SIZE).expect("the data stage is too large");
+
+    SetupPacket::new(request_type, request, value, index, length).write_to(buffer);
+}
+
+/// An isochronous packet of a [`Transfer`](struct.Transfer.html).
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct IsoPacket<'a> {
+    length: usize,
+    status: TransferStatus,
+    data: &'a [u8],
+}
+
+impl<'a> IsoPacket<'a> {
+    /// Returns the number of bytes the packet can hold.
+    pub fn length(&self) -> usize {
+        self.length
+    }
+
+    /// Returns the status of the packet.
+    pub fn status(&self) -> TransferStatus {
+        self.status
+    }
+
+    /// Returns the number of bytes actually transferred.
+    pub fn actual_length(&self) -> usize {
+        self.data.len()
+    }
+
+    /// Returns the data actually transferred, e.g. the samples received on an IN endpoint.
+    pub fn data(&self) -> &'a [u8] {
+        self.data
+    }
+}
+
+/// Returns the descriptor of the isochronous packet at `index`.
+unsafe fn iso_packet_desc(
+    transfer: *mut libusb1_sys::libusb_transfer,
+    index: usize,
+) -> *mut libusb1_sys::libusb_iso_packet_descriptor {
+    let first = std::ptr::addr_of_mut!((*transfer).iso_packet_desc)
+        as *mut libusb1_sys::libusb_iso_packet_descriptor;
+    first.add(index)
 }
 
 impl<'d, T: UsbContext> Drop for Transfer<'d, T> {
     fn drop(&mut self) {
+        #[cfg(feature = "leak-detection")]
+        crate::leak_detection::untrack(self.transfer);
+
         unsafe {
             libusb1_sys::libusb_free_transfer(self.transfer);
         }
     }
 }
 
+unsafe fn status_of(transfer: *mut libusb1_sys::libusb_transfer) -> TransferStatus {
+    status_from((*transfer).status)
+}
+
+fn status_from(status: c_int) -> TransferStatus {
+    match status {
+        LIBUSB_TRANSFER_COMPLETED => TransferStatus::Success,
+        LIBUSB_TRANSFER_ERROR => TransferStatus::Error,
+        LIBUSB_TRANSFER_TIMED_OUT => TransferStatus::Timeout,
+        LIBUSB_TRANSFER_CANCELLED => TransferStatus::Cancelled,
+        LIBUSB_TRANSFER_STALL => TransferStatus::Stall,
+        LIBUSB_TRANSFER_NO_DEVICE => TransferStatus::NoDevice,
+        LIBUSB_TRANSFER_OVERFLOW => TransferStatus::Overflow,
+        _ => TransferStatus::Unknown,
+    }
+}
+
+type CompletionHandler<'d, T> = Box<dyn FnMut(&mut Completion<'_, 'd, T>) + Send + 'd>;
+
 /// Internal type holding data touched by libusb completion callback.
-struct CallbackData {
+struct CallbackData<'d, T: UsbContext> {
     /// Transfers that have completed, but haven't yet been returned from `wait_any`.
     completed: Mutex<VecDeque<*mut libusb1_sys::libusb_transfer>>,
 
@@ -154,42 +561,487 @@ struct CallbackData {
     /// Mutex above, but can't be included in it because libusb reads it
     /// without the lock held.
     flag: UnsafeCell<c_int>,
+
+    /// Announces submissions to the event side. Submitting only sends on this channel, so it
+    /// never waits for a lock held by the thread handling completions.
+    submissions: Sender<Submission>,
+
+    /// The receiving end of `submissions`, drained into `book` by the event side.
+    received: Mutex<Receiver<Submission>>,
+
+    /// The state of the pending transfers, only touched by the event side: the callback and the
+    /// methods of the group that collect or cancel transfers.
+    book: Mutex<Book>,
+
+    /// Whether background transfers are held back, mirrored from `book` for submissions.
+    throttled: AtomicBool,
+
+    /// Called from the callback for every completed transfer, see
+    /// `AsyncGroup::set_completion_handler`. It is taken out of its slot while it runs, so that
+    /// no lock is held then.
+    handler: Mutex<HandlerSlot<CompletionHandler<'d, T>>>,
+
+    /// Signalled whenever the handler is done running.
+    handler_done: Condvar,
+}
+
+impl<'d, T: UsbContext> CallbackData<'d, T> {
+    /// Locks the book, after recording the submissions announced so far.
+    ///
+    /// A background transfer may have been held back just as the group stopped being throttled;
+    /// it is submitted here.
+    fn book(&self) -> MutexGuard<'_, Book> {
+        let mut book = self.book.lock().unwrap();
+        let received = self.received.lock().unwrap();
+        while let Ok(submission) = received.try_recv() {
+            book.record(submission);
+        }
+        drop(received);
+
+        for held in book.throttle.release() {
+            unsafe { submit_held(self, held) };
+        }
+        book
+    }
+
+    /// Mirrors the throttling state of `book` for submissions.
+    fn mirror(&self, book: &Book) {
+        self.throttled
+            .store(book.throttle.is_throttled(), Ordering::Relaxed);
+    }
+}
+
+/// Holds the completion handler of a group, and tracks it while it runs outside the slot.
+struct HandlerSlot<H> {
+    handler: Option<H>,
+    /// Bumped whenever the handler is set or cleared, so that a handler replaced while it ran
+    /// isn't put back.
+    generation: u64,
+    running: bool,
+}
+
+impl<H> HandlerSlot<H> {
+    fn new() -> HandlerSlot<H> {
+        HandlerSlot {
+            handler: None,
+            generation: 0,
+            running: false,
+        }
+    }
+
+    /// Takes the handler out to run it, with the generation to put it back with.
+    fn take(&mut self) -> Option<(H, u64)> {
+        let handler = self.handler.take()?;
+        self.running = true;
+        Some((handler, self.generation))
+    }
+
+    /// Puts a handler that is done running back, unless another was set or the handler was
+    /// cleared meanwhile, in which case it is returned to be dropped.
+    fn put_back(&mut self, handler: H, generation: u64) -> Option<H> {
+        self.running = false;
+        if generation == self.generation {
+            self.handler = Some(handler);
+            None
+        } else {
+            Some(handler)
+        }
+    }
+
+    /// Sets or clears the handler, and returns the one it replaces, if it wasn't running.
+    fn replace(&mut self, handler: Option<H>) -> Option<H> {
+        self.generation += 1;
+        mem::replace(&mut self.handler, handler)
+    }
+}
+
+/// A submission announced to the event side of a group.
+enum Submission {
+    /// The transfer is about to be submitted; `started` is set for interactive transfers.
+    Submitted {
+        transfer: *mut libusb1_sys::libusb_transfer,
+        id: TransferId,
+        started: Option<Instant>,
+    },
+
+    /// The submission announced last for the transfer failed, so it isn't pending.
+    Failed(*mut libusb1_sys::libusb_transfer),
+
+    /// The background transfer is held back until the group isn't throttled anymore.
+    Held {
+        transfer: *mut libusb1_sys::libusb_transfer,
+        id: TransferId,
+    },
+}
+
+/// The pending transfers of a group.
+struct Book {
+    /// The pending transfers, with the ID of their submission. We need to keep track of them so
+    /// they can be cancelled on drop.
+    pending: HashMap<*mut libusb1_sys::libusb_transfer, TransferId>,
+
+    /// Latency tracking and the background transfers held back because of it.
+    throttle: Throttle,
+}
+
+impl Book {
+    fn record(&mut self, submission: Submission) {
+        match submission {
+            Submission::Submitted {
+                transfer,
+                id,
+                started,
+            } => {
+                self.pending.insert(transfer, id);
+                if let Some(started) = started {
+                    self.throttle.started.insert(transfer, started);
+                }
+            }
+            Submission::Failed(transfer) => {
+                self.pending.remove(&transfer);
+                self.throttle.started.remove(&transfer);
+            }
+            Submission::Held { transfer, id } => {
+                self.pending.insert(transfer, id);
+                self.throttle.held.push_back(transfer);
+            }
+        }
+    }
+}
+
+/// Holds back background transfers while interactive transfers are slow.
+///
+/// The latency of interactive transfers is smoothed with an exponential moving average, so a
+/// single slow transfer doesn't stall the background traffic.
+struct Throttle {
+    threshold: Option<Duration>,
+    latency: Option<Duration>,
+    started: HashMap<*mut libusb1_sys::libusb_transfer, Instant>,
+    held: VecDeque<*mut libusb1_sys::libusb_transfer>,
+}
+
+impl Throttle {
+    fn new() -> Throttle {
+        Throttle {
+            threshold: None,
+            latency: None,
+            started: HashMap::new(),
+            held: VecDeque::new(),
+        }
+    }
+
+    /// Indicates whether background transfers have to wait.
+    ///
+    /// Once no interactive transfer is in flight anymore, nothing can bring the latency back
+    /// down, so background transfers are let through.
+    fn is_throttled(&self) -> bool {
+        match (self.threshold, self.latency) {
+            (Some(threshold), Some(latency)) => latency > threshold && !self.started.is_empty(),
+            _ => false,
+        }
+    }
+
+    fn sample(&mut self, latency: Duration) {
+        self.latency = Some(match self.latency {
+            Some(average) => (average * 7 + latency) / 8,
+            None => latency,
+        });
+    }
+
+    /// Records the completion of a transfer, and returns the background transfers that can be
+    /// submitted now.
+    fn completed(
+        &mut self,
+        transfer: *mut libusb1_sys::libusb_transfer,
+    ) -> Vec<*mut libusb1_sys::libusb_transfer> {
+        if let Some(start) = self.started.remove(&transfer) {
+            self.sample(start.elapsed());
+        }
+
+        self.release()
+    }
+
+    /// Returns the held transfers that can be submitted now.
+    fn release(&mut self) -> Vec<*mut libusb1_sys::libusb_transfer> {
+        if self.is_throttled() {
+            Vec::new()
+        } else {
+            self.held.drain(..).collect()
+        }
+    }
+}
+
+/// Indicates whether a transfer's completion time reflects the responsiveness of the device,
+/// rather than how long the device had nothing to send.
+unsafe fn is_interactive(transfer: *mut libusb1_sys::libusb_transfer) -> bool {
+    match (*transfer).transfer_type {
+        LIBUSB_TRANSFER_TYPE_CONTROL => true,
+        LIBUSB_TRANSFER_TYPE_INTERRUPT => {
+            (*transfer).endpoint & LIBUSB_ENDPOINT_DIR_MASK == LIBUSB_ENDPOINT_OUT
+        }
+        _ => false,
+    }
 }
 
 /// An AsyncGroup manages outstanding asynchronous transfers.
+///
+/// A group can be moved to another thread, even with transfers pending, but it can't be shared.
 pub struct AsyncGroup<'d, T: UsbContext> {
     context: &'d Context,
 
     /// The data touched by the callback, boxed to keep a consistent address if the AsyncGroup
     /// is moved while transfers are active.
-    callback_data: Box<CallbackData>,
-
-    /// The set of pending transfers. We need to keep track of them so they can be cancelled on
-    /// drop.
-    pending: HashSet<*mut libusb1_sys::libusb_transfer>,
+    callback_data: Box<CallbackData<'d, T>>,
     _phantom: PhantomData<&'d T>,
 }
 
+/// A completed transfer, as seen by the completion handler of an
+/// [`AsyncGroup`](struct.AsyncGroup.html).
+///
+/// Unless the handler resubmits it, the transfer is returned by `wait_any` once the handler
+/// returns.
+pub struct Completion<'a, 'd, T: UsbContext> {
+    transfer: *mut libusb1_sys::libusb_transfer,
+    callback_data: &'a CallbackData<'d, T>,
+    resubmitted: bool,
+}
+
+impl<'a, 'd, T: UsbContext> Completion<'a, 'd, T> {
+    /// Returns the address of the endpoint the transfer is for.
+    pub fn endpoint(&self) -> u8 {
+        unsafe { (*self.transfer).endpoint }
+    }
+
+    /// Returns the ID of the submission that completed.
+    pub fn id(&self) -> TransferId {
+        self.callback_data.book().pending[&self.transfer]
+    }
+
+    /// Gets the status of the transfer.
+    pub fn status(&self) -> TransferStatus {
+        unsafe { status_of(self.transfer) }
+    }
+
+    /// Access the slice of the buffer containing actual data received on an IN transfer.
+    pub fn actual(&self) -> &[u8] {
+        unsafe {
+            slice::from_raw_parts(
+                (*self.transfer).buffer,
+                (*self.transfer).actual_length as usize,
+            )
+        }
+    }
+
+    /// Access the buffer of the transfer, e.g. to fill in the next data of an OUT transfer before
+    /// resubmitting it.
+    pub fn buffer(&mut self) -> &mut [u8] {
+        unsafe {
+            slice::from_raw_parts_mut((*self.transfer).buffer, (*self.transfer).length as usize)
+        }
+    }
+
+    /// Resubmits the transfer right away, without waiting for `wait_any` to return it.
+    ///
+    /// On success the transfer stays pending and is not returned by `wait_any` for this
+    /// completion. On failure it is returned as usual, with the status of this completion.
+    pub fn resubmit(&mut self) -> Result<()> {
+        if self.resubmitted {
+            return Err(Error::Busy);
+        }
+
+        try_unsafe!(libusb1_sys::libusb_submit_transfer(self.transfer));
+        self.resubmitted = true;
+        Ok(())
+    }
+
+    /// Submits another transfer to the group.
+    pub fn submit(&mut self, t: Transfer<'d, T>) -> Result<TransferId> {
+        unsafe { submit(self.callback_data, t) }
+    }
+}
+
+/// Returns whether `transfer` writes to the device while `handle` was opened read-only.
+unsafe fn refuses<T: UsbContext>(
+    handle: &DeviceHandle<T>,
+    transfer: *const libusb1_sys::libusb_transfer,
+) -> bool {
+    if !handle.open_options().is_read_only() {
+        return false;
+    }
+
+    if (*transfer).transfer_type == LIBUSB_TRANSFER_TYPE_CONTROL {
+        // the direction is in bmRequestType, at the start of the setup packet
+        (*transfer).length < 1
+            || *(*transfer).buffer & LIBUSB_ENDPOINT_DIR_MASK != LIBUSB_ENDPOINT_IN
+    } else {
+        (*transfer).endpoint & LIBUSB_ENDPOINT_DIR_MASK == LIBUSB_ENDPOINT_OUT
+    }
+}
+
 /// The libusb transfer completion callback. Careful: libusb may call this on any thread!
-extern "system" fn async_group_callback(transfer: *mut libusb1_sys::libusb_transfer) {
-    unsafe {
-        let callback_data: &CallbackData = &*((*transfer).user_data as *const CallbackData);
-        let mut completed = callback_data.completed.lock().unwrap();
-        completed.push_back(transfer);
-        *(callback_data.flag.get()) = 1;
+extern "system" fn async_group_callback<T: UsbContext>(
+    transfer: *mut libusb1_sys::libusb_transfer,
+) {
+    // a panic, e.g. of the completion handler, is raised again by `wait_any`
+    crate::event_loop::catch_callback_panic(|| unsafe { group_transfer_completed::<T>(transfer) });
+}
+
+/// Passes a completed transfer to the completion handler, then queues it unless it was resubmitted.
+unsafe fn group_transfer_completed<T: UsbContext>(transfer: *mut libusb1_sys::libusb_transfer) {
+    // the lifetime only matters to the borrow checker while transfers are created
+    let callback_data: &CallbackData<'static, T> =
+        &*((*transfer).user_data as *const CallbackData<'static, T>);
+
+    let mut completion = Completion {
+        transfer,
+        callback_data,
+        resubmitted: false,
+    };
+    let released = {
+        let mut book = callback_data.book();
+        let released = book.throttle.completed(transfer);
+        callback_data.mirror(&book);
+        released
+    };
+    for held in released {
+        submit_held(callback_data, held);
+    }
+
+    // taken out, so that setting or clearing the handler doesn't wait for the lock while it
+    // runs; libusb runs one completion callback at a time, so no other completion misses it
+    let taken = callback_data.handler.lock().unwrap().take();
+    if let Some((mut handler, generation)) = taken {
+        // caught here too, so that the handler is put back even if it panics
+        crate::event_loop::catch_callback_panic(|| handler(&mut completion));
+        let replaced = callback_data
+            .handler
+            .lock()
+            .unwrap()
+            .put_back(handler, generation);
+        drop(replaced);
+        callback_data.handler_done.notify_all();
+    }
+    if completion.resubmitted {
+        return;
     }
+
+    complete(callback_data, transfer);
+}
+
+/// Queues a finished transfer for `wait_any`.
+unsafe fn complete<T: UsbContext>(
+    callback_data: &CallbackData<'_, T>,
+    transfer: *mut libusb1_sys::libusb_transfer,
+) {
+    let mut completed = callback_data.completed.lock().unwrap();
+    completed.push_back(transfer);
+    *(callback_data.flag.get()) = 1;
 }
 
+/// Submits a background transfer that was held back. It is already registered as pending, so a
+/// failure is reported through `wait_any` like a failed transfer.
+unsafe fn submit_held<T: UsbContext>(
+    callback_data: &CallbackData<'_, T>,
+    transfer: *mut libusb1_sys::libusb_transfer,
+) {
+    if libusb1_sys::libusb_submit_transfer(transfer) != 0 {
+        (*transfer).status = LIBUSB_TRANSFER_ERROR;
+        complete(callback_data, transfer);
+    }
+}
+
+/// Submits a transfer so that it completes into `callback_data`.
+unsafe fn submit<'d, T: UsbContext>(
+    callback_data: &CallbackData<'d, T>,
+    t: Transfer<'d, T>,
+) -> Result<TransferId> {
+    if t.refused {
+        return Err(Error::Access);
+    }
+    (*t.transfer).user_data = callback_data as *const _ as *mut c_void;
+    (*t.transfer).callback = async_group_callback::<T>;
+
+    // announced first, so a completion racing with this function finds it pending
+    let id = TransferId::next();
+    let started = if is_interactive(t.transfer) {
+        Some(Instant::now())
+    } else {
+        None
+    };
+    announce(
+        callback_data,
+        Submission::Submitted {
+            transfer: t.transfer,
+            id,
+            started,
+        },
+    );
+
+    let res = libusb1_sys::libusb_submit_transfer(t.transfer);
+    if res != 0 {
+        announce(callback_data, Submission::Failed(t.transfer));
+        return Err(crate::error::from_libusb(res));
+    }
+    mem::forget(t);
+    Ok(id)
+}
+
+fn announce<T: UsbContext>(callback_data: &CallbackData<'_, T>, submission: Submission) {
+    // the receiver lives as long as the sender, in the same `CallbackData`
+    callback_data.submissions.send(submission).ok();
+}
+
+/// Submits a background transfer, or holds it back while the group is throttled.
+unsafe fn submit_background<'d, T: UsbContext>(
+    callback_data: &CallbackData<'d, T>,
+    t: Transfer<'d, T>,
+) -> Result<TransferId> {
+    if t.refused {
+        return Err(Error::Access);
+    }
+    if !callback_data.throttled.load(Ordering::Relaxed) {
+        return submit(callback_data, t);
+    }
+
+    (*t.transfer).user_data = callback_data as *const _ as *mut c_void;
+    (*t.transfer).callback = async_group_callback::<T>;
+
+    let id = TransferId::next();
+    announce(
+        callback_data,
+        Submission::Held {
+            transfer: t.transfer,
+            id,
+        },
+    );
+    mem::forget(t);
+    Ok(id)
+}
+
+// The callback data stays boxed at the same address, its completion handler is `Send`, and the
+// pending transfers are completed by whichever thread handles events anyway.
+unsafe impl<'d, T: UsbContext> Send for AsyncGroup<'d, T> {}
+
 impl<'d, T: UsbContext> AsyncGroup<'d, T> {
     /// Creates an AsyncGroup to process transfers for devices from the given context.
     pub fn new(context: &'d Context) -> AsyncGroup<'d, T> {
+        let (submissions, received) = mpsc::channel();
+
         AsyncGroup {
-            context: context,
+            context,
             callback_data: Box::new(CallbackData {
                 completed: Mutex::new(VecDeque::new()),
                 flag: UnsafeCell::new(0),
+                submissions,
+                received: Mutex::new(received),
+                book: Mutex::new(Book {
+                    pending: HashMap::new(),
+                    throttle: Throttle::new(),
+                }),
+                throttled: AtomicBool::new(false),
+                handler: Mutex::new(HandlerSlot::new()),
+                handler_done: Condvar::new(),
             }),
-            pending: HashSet::new(),
             _phantom: PhantomData,
         }
     }
@@ -197,68 +1049,311 @@ impl<'d, T: UsbContext> AsyncGroup<'d, T> {
     /// Starts a transfer.
     ///
     /// The Transfer is owned by the AsyncGroup while it is pending, and is
-    /// returned from `wait_any` when it completes or fails.
-    pub fn submit(&mut self, t: Transfer<'d, T>) -> Result<()> {
-        unsafe {
-            (*t.transfer).user_data = &mut *self.callback_data as *mut _ as *mut c_void;
-            (*t.transfer).callback = async_group_callback;
+    /// returned from `wait_any` when it completes or fails. The returned ID identifies this
+    /// submission, see [`cancel`](#method.cancel) and [`Transfer::id`](struct.Transfer.html#method.id).
+    pub fn submit(&mut self, t: Transfer<'d, T>) -> Result<TransferId> {
+        unsafe { submit(&self.callback_data, t) }
+    }
+
+    /// Starts a transfer with the given scheduling priority.
+    ///
+    /// [`Priority::Normal`](enum.Priority.html#variant.Normal) is the same as
+    /// [`submit`](#method.submit). A [`Priority::Background`](enum.Priority.html#variant.Background)
+    /// transfer, e.g. a bulk log download, is held back in the group while its normal control and
+    /// interrupt OUT transfers are slower than the threshold set with
+    /// [`set_background_throttle`](#method.set_background_throttle), and submitted once they
+    /// recover. Held transfers count as pending: they are returned by `wait_any` when they
+    /// complete, and cancelled by `cancel_all`.
+    ///
+    /// Only the first submission is throttled; a background transfer resubmitted from the
+    /// completion handler is submitted right away.
+    pub fn submit_with_priority(
+        &mut self,
+        t: Transfer<'d, T>,
+        priority: Priority,
+    ) -> Result<TransferId> {
+        match priority {
+            Priority::Normal => self.submit(t),
+            Priority::Background => unsafe { submit_background(&self.callback_data, t) },
         }
-        try_unsafe!(libusb1_sys::libusb_submit_transfer(t.transfer));
-        self.pending.insert(t.transfer);
-        mem::forget(t);
-        Ok(())
+    }
+
+    /// Sets the latency of normal control and interrupt OUT transfers above which background
+    /// transfers are held back, or disables throttling with `None` (the default).
+    ///
+    /// The latency is averaged over the recent transfers of the group, from submission to
+    /// completion. Interrupt IN transfers aren't considered, since they complete only when the
+    /// device has something to report.
+    pub fn set_background_throttle(&mut self, threshold: Option<Duration>) {
+        let released = {
+            let mut book = self.callback_data.book();
+            book.throttle.threshold = threshold;
+            let released = book.throttle.release();
+            self.callback_data.mirror(&book);
+            released
+        };
+
+        for transfer in released {
+            unsafe { submit_held(&self.callback_data, transfer) };
+        }
+    }
+
+    /// Returns the averaged latency of the group's normal control and interrupt OUT transfers, if
+    /// any has completed yet.
+    pub fn interactive_latency(&self) -> Option<Duration> {
+        self.callback_data.book().throttle.latency
+    }
+
+    /// Sets a function called for every completed transfer, directly from the libusb completion
+    /// callback.
+    ///
+    /// Going through `wait_any` adds a round trip through the thread waiting on it before a
+    /// transfer can be resubmitted. Isochronous and interrupt pipelines that have to stay full
+    /// can instead resubmit transfers, or submit new ones, from the handler with
+    /// [`Completion::resubmit`](struct.Completion.html#method.resubmit) and
+    /// [`Completion::submit`](struct.Completion.html#method.submit). Transfers the handler
+    /// doesn't resubmit are returned by `wait_any` as usual.
+    ///
+    /// The handler runs on whichever thread is handling events for the context when the transfer
+    /// completes, which is not necessarily the one calling `wait_any`. No lock of the group is
+    /// held while it runs, so submitting from it can't deadlock, but it must not:
+    ///
+    /// * handle events, e.g. through `wait_any`, `UsbContext::handle_events` or the synchronous
+    ///   transfer functions of `DeviceHandle`, which block on the event handling the handler
+    ///   is part of,
+    /// * take long, since it delays every other completion on the context.
+    ///
+    /// A handler that resubmits every transfer keeps them pending forever, so `wait_any` only
+    /// returns once a transfer fails to be resubmitted. Drive events with
+    /// `UsbContext::handle_events` instead in that case.
+    ///
+    /// If the previous handler is running, this waits until it returns, so that it is done
+    /// submitting once this returns.
+    pub fn set_completion_handler<F>(&mut self, handler: F)
+    where
+        F: FnMut(&mut Completion<'_, 'd, T>) + Send + 'd,
+    {
+        self.replace_completion_handler(Some(Box::new(handler)));
+    }
+
+    /// Removes the completion handler.
+    ///
+    /// If the handler is running, this waits until it returns, so that it is done submitting
+    /// once this returns.
+    pub fn clear_completion_handler(&mut self) {
+        self.replace_completion_handler(None);
+    }
+
+    fn replace_completion_handler(&mut self, handler: Option<CompletionHandler<'d, T>>) {
+        let mut slot = self.callback_data.handler.lock().unwrap();
+        let replaced = slot.replace(handler);
+        while slot.running {
+            slot = self.callback_data.handler_done.wait(slot).unwrap();
+        }
+        drop(slot);
+        drop(replaced);
     }
 
     /// Waits for any pending transfer to complete, and return it.
     pub fn wait_any(&mut self) -> Result<Transfer<'d, T>> {
-        if self.pending.len() == 0 {
+        match self.wait_until(None)? {
+            Some(transfer) => Ok(transfer),
+            None => unreachable!("waiting without a deadline returned no transfer"),
+        }
+    }
+
+    /// Waits up to `timeout` for any pending transfer to complete, and returns it.
+    ///
+    /// Unlike `wait_any`, this returns even if the device stopped responding to transfers
+    /// submitted without a timeout of their own. The transfers are still pending then; use
+    /// [`cancel_all`](#method.cancel_all) to abort them.
+    ///
+    /// ## Errors
+    ///
+    /// * `Timeout` if no transfer completed in time.
+    /// * `NotFound` if no transfer is pending.
+    pub fn wait_any_timeout(&mut self, timeout: Duration) -> Result<Transfer<'d, T>> {
+        self.wait_until(Some(Instant::now() + timeout))?
+            .ok_or(Error::Timeout)
+    }
+
+    /// Returns a transfer that already completed, if any, without waiting.
+    ///
+    /// The events that are ready are handled first, so transfers complete even if no other
+    /// thread handles events for the context, and the caller can interleave other work.
+    ///
+    /// ## Errors
+    ///
+    /// * `NotFound` if no transfer is pending.
+    pub fn try_wait_any(&mut self) -> Result<Option<Transfer<'d, T>>> {
+        self.wait_until(Some(Instant::now()))
+    }
+
+    /// Waits for any pending transfer to complete until `deadline`, or forever without one.
+    ///
+    /// Events are handled at least once, even if the deadline passed already.
+    fn wait_until(&mut self, deadline: Option<Instant>) -> Result<Option<Transfer<'d, T>>> {
+        if self.callback_data.book().pending.is_empty() {
             // Otherwise this function would block forever waiting for a transfer to complete
             return Err(Error::NotFound);
         }
 
-        {
-            let transfer;
-            loop {
-                {
-                    let mut completed = self.callback_data.completed.lock().unwrap();
-                    if let Some(t) = completed.pop_front() {
-                        transfer = t;
-                        break;
+        let mut handled = false;
+        let transfer;
+        loop {
+            {
+                let mut completed = self.callback_data.completed.lock().unwrap();
+                if let Some(t) = completed.pop_front() {
+                    transfer = t;
+                    break;
+                }
+                unsafe { *self.callback_data.flag.get() = 0 };
+            }
+
+            let res = match deadline {
+                None => unsafe {
+                    libusb1_sys::libusb_handle_events_completed(
+                        self.context.as_raw(),
+                        self.callback_data.flag.get(),
+                    )
+                },
+                Some(deadline) => {
+                    let now = Instant::now();
+                    if handled && now >= deadline {
+                        return Ok(None);
+                    }
+                    let remaining = deadline.saturating_duration_since(now);
+                    let tv = libc::timeval {
+                        tv_sec: remaining.as_secs() as _,
+                        tv_usec: remaining.subsec_micros() as _,
+                    };
+                    handled = true;
+                    unsafe {
+                        libusb1_sys::libusb_handle_events_timeout_completed(
+                            self.context.as_raw(),
+                            &tv,
+                            self.callback_data.flag.get(),
+                        )
                     }
-                    unsafe { *self.callback_data.flag.get() = 0 };
                 }
-                try_unsafe!(libusb1_sys::libusb_handle_events_completed(
-                    self.context.as_raw(),
-                    self.callback_data.flag.get()
-                ));
+            };
+            // the panic of a callback comes first, it may be what failed the event handling
+            crate::event_loop::resume_callback_panic();
+            if res != 0 {
+                return Err(crate::error::from_libusb(res));
             }
+        }
+
+        let id = match self.callback_data.book().pending.remove(&transfer) {
+            Some(id) => id,
+            None => panic!("Got a completion for a transfer that wasn't pending"),
+        };
+
+        Ok(Some(Transfer {
+            transfer,
+            id: Some(id),
+            refused: false,
+            _handle: PhantomData,
+            _buffer: PhantomData,
+        }))
+    }
 
-            if !self.pending.remove(&transfer) {
-                panic!("Got a completion for a transfer that wasn't pending");
+    /// Requests the cancellation of a pending transfer.
+    ///
+    /// This returns right away; the transfer is returned by `wait_any` once the cancellation
+    /// completes, with a `Cancelled` status, or with its actual status if it completed before it
+    /// could be cancelled. Other transfers of the group are not affected. Held background
+    /// transfers are cancelled without being submitted.
+    ///
+    /// ## Errors
+    ///
+    /// * `NotFound` if no transfer of this group is pending with this ID, e.g. because it was
+    ///   already returned by `wait_any`.
+    pub fn cancel(&mut self, id: TransferId) -> Result<()> {
+        let (transfer, was_held) = {
+            let mut book = self.callback_data.book();
+            let transfer = match book
+                .pending
+                .iter()
+                .find(|(_, &pending_id)| pending_id == id)
+            {
+                Some((&transfer, _)) => transfer,
+                None => return Err(Error::NotFound),
+            };
+
+            match book.throttle.held.iter().position(|&t| t == transfer) {
+                Some(position) => {
+                    book.throttle.held.remove(position);
+                    (transfer, true)
+                }
+                None => (transfer, false),
             }
+        };
+        if was_held {
+            unsafe {
+                (*transfer).status = LIBUSB_TRANSFER_CANCELLED;
+                complete(&self.callback_data, transfer);
+            }
+            return Ok(());
+        }
 
-            Ok(Transfer {
-                transfer: transfer,
-                _handle: PhantomData,
-                _buffer: PhantomData,
-            })
+        match unsafe { libusb1_sys::libusb_cancel_transfer(transfer) } {
+            // already completed, but not collected by `wait_any` yet
+            0 | LIBUSB_ERROR_NOT_FOUND => Ok(()),
+            err => Err(crate::error::from_libusb(err)),
         }
     }
 
-    /// Cancels all pending transfers.
+    /// Cancels all pending transfers, and waits for the cancellations to complete.
     ///
     /// Throws away any received data and errors on transfers that have completed, but haven't been
-    /// collected by `wait_any`.
+    /// collected by `wait_any`. The completion handler is removed first, so cancelled transfers
+    /// aren't resubmitted.
+    ///
+    /// Every transfer of the group has completed when this returns, even on error, so their
+    /// buffers can be freed right after.
+    ///
+    /// ## Errors
+    ///
+    /// The first error cancelling a transfer or handling events, returned once all transfers
+    /// completed. A transfer of a device that was unplugged can't be cancelled, but completes
+    /// with a `NoDevice` status anyway, so this is not an error.
     pub fn cancel_all(&mut self) -> Result<()> {
-        for &transfer in self.pending.iter() {
-            try_unsafe!(libusb1_sys::libusb_cancel_transfer(transfer))
+        self.clear_completion_handler();
+
+        let held: Vec<_> = self.callback_data.book().throttle.held.drain(..).collect();
+        for transfer in held {
+            unsafe {
+                (*transfer).status = LIBUSB_TRANSFER_CANCELLED;
+                complete(&self.callback_data, transfer);
+            }
         }
 
-        while self.pending.len() > 0 {
-            self.wait_any()?;
+        let mut result = Ok(());
+        let pending: Vec<_> = self.callback_data.book().pending.keys().copied().collect();
+        for transfer in pending {
+            match unsafe { libusb1_sys::libusb_cancel_transfer(transfer) } {
+                // already completed, or one of the held transfers, or completing as the device is
+                // gone
+                0 | LIBUSB_ERROR_NOT_FOUND | LIBUSB_ERROR_NO_DEVICE => (),
+                err => {
+                    if result.is_ok() {
+                        result = Err(crate::error::from_libusb(err));
+                    }
+                }
+            }
         }
 
-        Ok(())
+        // libusb owns the transfers until they complete, so they are waited for whatever failed
+        while !self.callback_data.book().pending.is_empty() {
+            if let Err(e) = self.wait_any() {
+                if result.is_ok() {
+                    result = Err(e);
+                }
+            }
+        }
+
+        result
     }
 }
 
@@ -267,3 +1362,175 @@ impl<'d, T: UsbContext> AsyncGroup<'d, T> {
 //         self.cancel_all().ok();
 //     }
 // }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn transfer(n: usize) -> *mut libusb1_sys::libusb_transfer {
+        n as *mut libusb1_sys::libusb_transfer
+    }
+
+    #[test]
+    fn it_writes_setup_packets() {
+        let mut buffer = [0xFF; 12];
+        write_setup(&mut buffer, 0xC0, 0x01, 0x1234, 0x0002);
+
+        assert_eq!(
+            [0xC0, 0x01, 0x34, 0x12, 0x02, 0x00, 0x04, 0x00],
+            buffer[..8]
+        );
+        assert_eq!([0xFF; 4], buffer[8..]);
+    }
+
+    #[test]
+    #[should_panic(expected = "too small for the setup packet")]
+    fn it_refuses_buffers_without_room_for_the_setup_packet() {
+        write_setup(&mut [0; 4], 0x40, 0x01, 0, 0);
+    }
+
+    #[test]
+    fn it_never_reuses_transfer_ids() {
+        let ids: Vec<TransferId> = (0..100).map(|_| TransferId::next()).collect();
+        let unique: std::collections::HashSet<_> = ids.iter().collect();
+
+        assert_eq!(ids.len(), unique.len());
+        assert!(ids.windows(2).all(|w| w[0] < w[1]));
+    }
+
+    #[test]
+    fn it_is_not_throttled_without_threshold() {
+        let mut throttle = Throttle::new();
+        throttle.started.insert(transfer(1), Instant::now());
+        throttle.sample(Duration::from_secs(1));
+
+        assert!(!throttle.is_throttled());
+    }
+
+    #[test]
+    fn it_is_throttled_while_latency_is_above_threshold() {
+        let mut throttle = Throttle::new();
+        throttle.threshold = Some(Duration::from_millis(10));
+        throttle.started.insert(transfer(1), Instant::now());
+
+        throttle.sample(Duration::from_millis(5));
+        assert!(!throttle.is_throttled());
+
+        throttle.sample(Duration::from_millis(200));
+        assert!(throttle.is_throttled());
+    }
+
+    #[test]
+    fn it_averages_latency() {
+        let mut throttle = Throttle::new();
+        throttle.sample(Duration::from_millis(8));
+        throttle.sample(Duration::from_millis(16));
+
+        assert_eq!(Some(Duration::from_millis(9)), throttle.latency);
+    }
+
+    #[test]
+    fn it_releases_held_transfers_when_no_interactive_transfer_is_in_flight() {
+        let mut throttle = Throttle::new();
+        throttle.threshold = Some(Duration::from_millis(10));
+        throttle.sample(Duration::from_secs(1));
+        throttle.started.insert(transfer(1), Instant::now());
+        throttle.started.insert(transfer(2), Instant::now());
+        throttle.held.push_back(transfer(3));
+
+        assert!(throttle.completed(transfer(1)).is_empty());
+        assert_eq!(vec![transfer(3)], throttle.completed(transfer(2)));
+        assert!(throttle.held.is_empty());
+    }
+
+    fn book() -> Book {
+        Book {
+            pending: HashMap::new(),
+            throttle: Throttle::new(),
+        }
+    }
+
+    #[test]
+    fn it_records_submissions_as_pending() {
+        let mut book = book();
+        let id = TransferId::next();
+        let started = Instant::now();
+
+        book.record(Submission::Submitted {
+            transfer: transfer(1),
+            id,
+            started: Some(started),
+        });
+
+        assert_eq!(Some(&id), book.pending.get(&transfer(1)));
+        assert_eq!(Some(&started), book.throttle.started.get(&transfer(1)));
+    }
+
+    #[test]
+    fn it_forgets_failed_submissions() {
+        let mut book = book();
+        book.record(Submission::Submitted {
+            transfer: transfer(1),
+            id: TransferId::next(),
+            started: Some(Instant::now()),
+        });
+
+        book.record(Submission::Failed(transfer(1)));
+
+        assert!(book.pending.is_empty());
+        assert!(book.throttle.started.is_empty());
+    }
+
+    #[test]
+    fn it_records_held_transfers_as_pending() {
+        let mut book = book();
+        let id = TransferId::next();
+
+        book.record(Submission::Held {
+            transfer: transfer(1),
+            id,
+        });
+
+        assert_eq!(Some(&id), book.pending.get(&transfer(1)));
+        assert_eq!(vec![transfer(1)], Vec::from(book.throttle.held.clone()));
+    }
+
+    #[test]
+    fn it_puts_the_handler_back_after_it_ran() {
+        let mut slot = HandlerSlot::new();
+        slot.replace(Some(1));
+
+        let (handler, generation) = slot.take().unwrap();
+        assert!(slot.running);
+        assert_eq!(None, slot.take());
+
+        assert_eq!(None, slot.put_back(handler, generation));
+        assert!(!slot.running);
+        assert_eq!(Some(1), slot.handler);
+    }
+
+    #[test]
+    fn it_drops_a_handler_replaced_while_it_ran() {
+        let mut slot = HandlerSlot::new();
+        slot.replace(Some(1));
+
+        let (handler, generation) = slot.take().unwrap();
+        assert_eq!(None, slot.replace(Some(2)));
+
+        assert_eq!(Some(1), slot.put_back(handler, generation));
+        assert_eq!(Some(2), slot.handler);
+    }
+
+    #[test]
+    fn it_drops_a_handler_cleared_while_it_ran() {
+        let mut slot = HandlerSlot::new();
+        slot.replace(Some(1));
+
+        let (handler, generation) = slot.take().unwrap();
+        slot.replace(None);
+        slot.replace(None);
+
+        assert_eq!(Some(1), slot.put_back(handler, generation));
+        assert_eq!(None, slot.handler);
+    }
+}
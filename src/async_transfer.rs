@@ -0,0 +1,588 @@
+use std::{
+    fmt,
+    future::Future,
+    pin::Pin,
+    ptr,
+    sync::{Arc, Mutex},
+    task::{Context as TaskContext, Poll, Waker},
+    time::Duration,
+};
+
+use libc::{c_int, c_uint, c_void};
+use libusb1_sys::{constants::*, *};
+
+use crate::{
+    buffer_allocator::{self, BufferAllocator},
+    error,
+    setup_packet::SetupPacket,
+    DeviceHandle, Error, Result, UsbContext,
+};
+
+/// The state shared by a future and the completion callback of its transfer.
+#[derive(Default)]
+struct State {
+    completed: bool,
+
+    /// The future was dropped before its transfer completed; the callback frees the transfer.
+    orphaned: bool,
+
+    waker: Option<Waker>,
+
+    /// The device handle of the transfer, moved here from an orphaned future so the callback
+    /// releases it.
+    keepalive: Option<Box<dyn Send>>,
+}
+
+/// The state of a transfer, and its buffer.
+type Shared = (Mutex<State>, Mutex<Vec<u8>>);
+
+/// A transfer awaited by one of the public futures, which owns its buffer and device handle.
+struct Pending {
+    transfer: *mut libusb_transfer,
+
+    /// Holds the data of the transfer until it completes. Shared with the callback, so that it
+    /// outlives a future dropped before then.
+    shared: Arc<Shared>,
+
+    /// Reported by the first poll instead of submitting, e.g. because the transfer couldn't be
+    /// allocated.
+    error: Option<Error>,
+
+    submitted: bool,
+    finished: bool,
+
+    /// Keeps the device handle open until the transfer completed, even if the future is
+    /// forgotten rather than dropped.
+    keepalive: Option<Box<dyn Send>>,
+}
+
+// the transfer is only touched by the future, until it is submitted, and by the callback, until
+// it completes
+unsafe impl Send for Pending {}
+
+impl Pending {
+    fn new<T: UsbContext + 'static>(
+        handle: &Arc<DeviceHandle<T>>,
+        transfer_type: u8,
+        endpoint: u8,
+        mut buffer: Vec<u8>,
+        timeout: Duration,
+    ) -> Pending {
+        let transfer = unsafe { libusb_alloc_transfer(0) };
+        if transfer.is_null() {
+            return Pending::failed(Error::NoMem, buffer);
+        }
+
+        unsafe {
+            (*transfer).dev_handle = handle.as_raw();
+            (*transfer).endpoint = endpoint;
+            (*transfer).transfer_type = transfer_type;
+            (*transfer).timeout = timeout.as_millis().min(c_uint::MAX as u128) as c_uint;
+            // the heap allocation of the vector doesn't move when the vector does
+            (*transfer).buffer = buffer.as_mut_ptr();
+            (*transfer).length = buffer.len() as c_int;
+            (*transfer).callback = completed_callback;
+        }
+
+        Pending {
+            transfer,
+            shared: Arc::new((Mutex::new(State::default()), Mutex::new(buffer))),
+            error: None,
+            submitted: false,
+            finished: false,
+            keepalive: Some(Box::new(handle.clone())),
+        }
+    }
+
+    /// Returns a transfer that fails with `error` when polled, giving `buffer` back.
+    fn failed(error: Error, buffer: Vec<u8>) -> Pending {
+        Pending {
+            transfer: ptr::null_mut(),
+            shared: Arc::new((Mutex::new(State::default()), Mutex::new(buffer))),
+            error: Some(error),
+            submitted: false,
+            finished: false,
+            keepalive: None,
+        }
+    }
+
+    /// Submits the transfer on the first poll, and returns its buffer and outcome, the
+    /// transferred length or an error, once it completed.
+    fn poll(&mut self, cx: &mut TaskContext<'_>) -> Poll<(Vec<u8>, Result<usize>)> {
+        assert!(!self.finished, "transfer future polled after completion");
+
+        if let Some(error) = self.error {
+            self.finished = true;
+            return Poll::Ready((self.take_buffer(), Err(error)));
+        }
+
+        if !self.submitted {
+            *lock(&self.shared.0) = State {
+                waker: Some(cx.waker().clone()),
+                ..State::default()
+            };
+
+            let user_data = Arc::into_raw(self.shared.clone());
+            unsafe {
+                (*self.transfer).user_data = user_data as *mut c_void;
+                let res = libusb_submit_transfer(self.transfer);
+                if res < 0 {
+                    drop(Arc::from_raw(user_data));
+                    self.finished = true;
+                    return Poll::Ready((self.take_buffer(), Err(error::from_libusb(res))));
+                }
+            }
+            self.submitted = true;
+            return Poll::Pending;
+        }
+
+        let mut state = lock(&self.shared.0);
+        if !state.completed {
+            state.waker = Some(cx.waker().clone());
+            return Poll::Pending;
+        }
+        drop(state);
+
+        self.finished = true;
+        let (status, actual) = unsafe { ((*self.transfer).status, (*self.transfer).actual_length) };
+        let result = match status_error(status) {
+            None => Ok(actual.max(0) as usize),
+            Some(e) => Err(e),
+        };
+
+        Poll::Ready((self.take_buffer(), result))
+    }
+
+    fn take_buffer(&self) -> Vec<u8> {
+        std::mem::take(&mut *lock(&self.shared.1))
+    }
+}
+
+impl Drop for Pending {
+    fn drop(&mut self) {
+        if self.submitted {
+            let mut state = lock(&self.shared.0);
+            if !state.completed {
+                // the callback frees the transfer and releases the handle once the cancellation
+                // completes
+                state.orphaned = true;
+                state.keepalive = self.keepalive.take();
+                unsafe { libusb_cancel_transfer(self.transfer) };
+                return;
+            }
+        }
+
+        if !self.transfer.is_null() {
+            unsafe { libusb_free_transfer(self.transfer) };
+        }
+    }
+}
+
+extern "system" fn completed_callback(transfer: *mut libusb_transfer) {
+    unsafe {
+        let shared = Arc::from_raw((*transfer).user_data as *const Shared);
+
+        let mut state = lock(&shared.0);
+        state.completed = true;
+        if state.orphaned {
+            libusb_free_transfer(transfer);
+            return;
+        }
+
+        let waker = state.waker.take();
+        drop(state);
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+    }
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> std::sync::MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|p| p.into_inner())
+}
+
+/// Returns the error corresponding to the status of a completed transfer.
+fn status_error(status: c_int) -> Option<Error> {
+    match status {
+        LIBUSB_TRANSFER_COMPLETED => None,
+        LIBUSB_TRANSFER_TIMED_OUT => Some(Error::Timeout),
+        LIBUSB_TRANSFER_CANCELLED => Some(Error::Interrupted),
+        LIBUSB_TRANSFER_STALL => Some(Error::Pipe),
+        LIBUSB_TRANSFER_NO_DEVICE => Some(Error::NoDevice),
+        LIBUSB_TRANSFER_OVERFLOW => Some(Error::Overflow),
+        _ => Some(Error::Io),
+    }
+}
+
+/// Fills `buffer` with the setup packet of a control transfer, followed by `data` or room for
+/// `len` bytes.
+fn control_buffer(
+    mut buffer: Vec<u8>,
+    request_type: u8,
+    request: u8,
+    value: u16,
+    index: u16,
+    data: &[u8],
+    len: usize,
+) -> Vec<u8> {
+    buffer.clear();
+    buffer.reserve(SetupPacket::SIZE + len);
+    buffer.extend_from_slice(
+        &SetupPacket::new(request_type, request, value, index, len as u16).to_bytes(),
+    );
+    buffer.extend_from_slice(data);
+    buffer.resize(SetupPacket::SIZE + len, 0);
+    buffer
+}
+
+/// A transfer reading from the device, resolving to the data received.
+///
+/// Created by the `*_async` read methods of [`DeviceHandle`](struct.DeviceHandle.html). The
+/// transfer is submitted when the future is first polled, and the future resolves once libusb
+/// calls its completion callback, which wakes the task. This doesn't depend on any executor, so
+/// the future can be awaited from tokio, async-std or a hand-written `block_on`, but the events
+/// of the context have to be handled for the callback to run, e.g. by a
+/// [`ContextPool`](struct.ContextPool.html) or a thread calling `handle_events` in a loop.
+///
+/// The future holds a reference to its device handle, which stays open until the transfer
+/// completed. Dropping the future cancels the transfer without waiting for the cancellation, and
+/// the handle is released once it completed.
+#[must_use = "futures do nothing unless polled"]
+pub struct ReadFuture {
+    pending: Pending,
+    offset: usize,
+    allocator: Option<Arc<dyn BufferAllocator>>,
+}
+
+impl ReadFuture {
+    pub(crate) fn new<T: UsbContext + 'static>(
+        handle: &Arc<DeviceHandle<T>>,
+        transfer_type: u8,
+        endpoint: u8,
+        len: usize,
+        timeout: Duration,
+        allocator: Option<Arc<dyn BufferAllocator>>,
+    ) -> ReadFuture {
+        let buffer = buffer_allocator::allocate(allocator.as_ref(), len);
+        ReadFuture {
+            pending: Pending::new(handle, transfer_type, endpoint, buffer, timeout),
+            offset: 0,
+            allocator,
+        }
+    }
+
+    pub(crate) fn failed(error: Error) -> ReadFuture {
+        ReadFuture {
+            pending: Pending::failed(error, Vec::new()),
+            offset: 0,
+            allocator: None,
+        }
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) fn control<T: UsbContext + 'static>(
+        handle: &Arc<DeviceHandle<T>>,
+        request_type: u8,
+        request: u8,
+        value: u16,
+        index: u16,
+        len: usize,
+        timeout: Duration,
+        allocator: Option<Arc<dyn BufferAllocator>>,
+    ) -> ReadFuture {
+        let buffer = buffer_allocator::allocate(allocator.as_ref(), 0);
+        let buffer = control_buffer(buffer, request_type, request, value, index, &[], len);
+        ReadFuture {
+            pending: Pending::new(handle, LIBUSB_TRANSFER_TYPE_CONTROL, 0, buffer, timeout),
+            offset: SetupPacket::SIZE,
+            allocator,
+        }
+    }
+}
+
+impl Future for ReadFuture {
+    type Output = Result<Vec<u8>>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut TaskContext<'_>) -> Poll<Self::Output> {
+        let this = self.get_mut();
+        let offset = this.offset;
+        let allocator = &this.allocator;
+
+        this.pending.poll(cx).map(|(mut buffer, res)| match res {
+            Ok(actual) => {
+                buffer.truncate((offset + actual).min(buffer.len()));
+                buffer.drain(..offset);
+                Ok(buffer)
+            }
+            Err(e) => {
+                buffer_allocator::release(allocator.as_ref(), buffer);
+                Err(e)
+            }
+        })
+    }
+}
+
+/// A transfer writing to the device, resolving to the number of bytes written.
+///
+/// Created by the `*_async` write methods of [`DeviceHandle`](struct.DeviceHandle.html), and
+/// submitted and cancelled like a [`ReadFuture`](struct.ReadFuture.html).
+#[must_use = "futures do nothing unless polled"]
+pub struct WriteFuture {
+    pending: Pending,
+    allocator: Option<Arc<dyn BufferAllocator>>,
+}
+
+impl WriteFuture {
+    pub(crate) fn new<T: UsbContext + 'static>(
+        handle: &Arc<DeviceHandle<T>>,
+        transfer_type: u8,
+        endpoint: u8,
+        data: Vec<u8>,
+        timeout: Duration,
+    ) -> WriteFuture {
+        WriteFuture {
+            pending: Pending::new(handle, transfer_type, endpoint, data, timeout),
+            allocator: None,
+        }
+    }
+
+    pub(crate) fn failed(error: Error) -> WriteFuture {
+        WriteFuture {
+            pending: Pending::failed(error, Vec::new()),
+            allocator: None,
+        }
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) fn control<T: UsbContext + 'static>(
+        handle: &Arc<DeviceHandle<T>>,
+        request_type: u8,
+        request: u8,
+        value: u16,
+        index: u16,
+        data: &[u8],
+        timeout: Duration,
+        allocator: Option<Arc<dyn BufferAllocator>>,
+    ) -> WriteFuture {
+        let buffer = buffer_allocator::allocate(allocator.as_ref(), 0);
+        let buffer = control_buffer(
+            buffer,
+            request_type,
+            request,
+            value,
+            index,
+            data,
+            data.len(),
+        );
+        WriteFuture {
+            pending: Pending::new(handle, LIBUSB_TRANSFER_TYPE_CONTROL, 0, buffer, timeout),
+            allocator,
+        }
+    }
+}
+
+impl Future for WriteFuture {
+    type Output = Result<usize>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut TaskContext<'_>) -> Poll<Self::Output> {
+        let this = self.get_mut();
+        let allocator = &this.allocator;
+
+        // the buffer of a bulk or interrupt write came from the application, and is dropped
+        this.pending.poll(cx).map(|(buffer, res)| {
+            buffer_allocator::release(allocator.as_ref(), buffer);
+            res
+        })
+    }
+}
+
+/// A bulk or interrupt transfer owning its device handle and buffer, resolving to the buffer
+/// and the outcome of the transfer.
+///
+/// Unlike [`Transfer`](struct.Transfer.html), which borrows both, an owned transfer can be stored
+/// in long-lived structs or moved to another thread. The transfer reads into the whole buffer
+/// from an IN endpoint, or writes the whole buffer to an OUT endpoint, and the buffer is given
+/// back whatever the outcome, so it can be reused for the next transfer. The received data is at
+/// the start of the buffer, with the length returned on success.
+///
+/// The transfer is submitted and completed like a [`ReadFuture`](struct.ReadFuture.html). If the
+/// future is dropped before the transfer completed, it keeps the handle open until the
+/// cancellation completed.
+///
+/// ## Errors
+///
+/// The errors are those of the synchronous transfers, e.g.
+/// [`DeviceHandle::read_bulk`](struct.DeviceHandle.html#method.read_bulk), and `Access` for an
+/// OUT transfer on a handle opened read-only.
+#[must_use = "futures do nothing unless polled"]
+pub struct OwnedTransfer<T: UsbContext + 'static> {
+    pending: Pending,
+    handle: Arc<DeviceHandle<T>>,
+}
+
+impl<T: UsbContext + 'static> OwnedTransfer<T> {
+    /// Creates a bulk transfer, submitted when first polled.
+    pub fn bulk(
+        handle: Arc<DeviceHandle<T>>,
+        endpoint: u8,
+        buffer: Vec<u8>,
+        timeout: Duration,
+    ) -> OwnedTransfer<T> {
+        OwnedTransfer::new(handle, LIBUSB_TRANSFER_TYPE_BULK, endpoint, buffer, timeout)
+    }
+
+    /// Creates a bulk transfer on the stream `stream_id` of a SuperSpeed bulk endpoint, submitted
+    /// when first polled.
+    ///
+    /// The stream must have been allocated with
+    /// [`DeviceHandle::alloc_streams`](struct.DeviceHandle.html#method.alloc_streams); otherwise
+    /// the transfer fails when polled.
+    pub fn bulk_stream(
+        handle: Arc<DeviceHandle<T>>,
+        endpoint: u8,
+        stream_id: u32,
+        buffer: Vec<u8>,
+        timeout: Duration,
+    ) -> OwnedTransfer<T> {
+        let transfer = OwnedTransfer::new(
+            handle,
+            LIBUSB_TRANSFER_TYPE_BULK_STREAM,
+            endpoint,
+            buffer,
+            timeout,
+        );
+        if !transfer.pending.transfer.is_null() {
+            unsafe { libusb_transfer_set_stream_id(transfer.pending.transfer, stream_id) };
+        }
+        transfer
+    }
+
+    /// Creates an interrupt transfer, submitted when first polled.
+    pub fn interrupt(
+        handle: Arc<DeviceHandle<T>>,
+        endpoint: u8,
+        buffer: Vec<u8>,
+        timeout: Duration,
+    ) -> OwnedTransfer<T> {
+        OwnedTransfer::new(
+            handle,
+            LIBUSB_TRANSFER_TYPE_INTERRUPT,
+            endpoint,
+            buffer,
+            timeout,
+        )
+    }
+
+    fn new(
+        handle: Arc<DeviceHandle<T>>,
+        transfer_type: u8,
+        endpoint: u8,
+        buffer: Vec<u8>,
+        timeout: Duration,
+    ) -> OwnedTransfer<T> {
+        let pending = if handle.open_options().is_read_only()
+            && endpoint & LIBUSB_ENDPOINT_DIR_MASK == LIBUSB_ENDPOINT_OUT
+        {
+            Pending::failed(Error::Access, buffer)
+        } else {
+            Pending::new(&handle, transfer_type, endpoint, buffer, timeout)
+        };
+
+        OwnedTransfer { pending, handle }
+    }
+
+    /// Returns the handle the transfer is performed on.
+    pub fn handle(&self) -> &Arc<DeviceHandle<T>> {
+        &self.handle
+    }
+}
+
+impl<T: UsbContext + 'static> Future for OwnedTransfer<T> {
+    type Output = (Vec<u8>, Result<usize>);
+
+    fn poll(self: Pin<&mut Self>, cx: &mut TaskContext<'_>) -> Poll<Self::Output> {
+        self.get_mut().pending.poll(cx)
+    }
+}
+
+impl<T: UsbContext + 'static> fmt::Debug for OwnedTransfer<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("OwnedTransfer")
+            .field("submitted", &self.pending.submitted)
+            .field("finished", &self.pending.finished)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::{
+        sync::atomic::{AtomicUsize, Ordering},
+        task::Wake,
+    };
+
+    struct CountingWaker(AtomicUsize);
+
+    impl Wake for CountingWaker {
+        fn wake(self: Arc<Self>) {
+            self.0.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    #[test]
+    fn it_wakes_the_task_on_completion() {
+        let counter = Arc::new(CountingWaker(AtomicUsize::new(0)));
+        let shared: Arc<Shared> = Arc::default();
+        lock(&shared.0).waker = Some(Waker::from(counter.clone()));
+
+        // the callback only reads the user data
+        let mut transfer = std::mem::MaybeUninit::<libusb_transfer>::zeroed();
+        unsafe {
+            (*transfer.as_mut_ptr()).user_data = Arc::into_raw(shared.clone()) as *mut c_void;
+        }
+        completed_callback(transfer.as_mut_ptr());
+
+        assert!(lock(&shared.0).completed);
+        assert_eq!(1, counter.0.load(Ordering::SeqCst));
+        // the reference held by the callback was released
+        assert_eq!(1, Arc::strong_count(&shared));
+    }
+
+    #[test]
+    fn it_gives_the_buffer_back_on_failure() {
+        let mut pending = Pending::failed(Error::Access, vec![1, 2, 3]);
+        let waker = Waker::from(Arc::new(CountingWaker(AtomicUsize::new(0))));
+
+        match pending.poll(&mut TaskContext::from_waker(&waker)) {
+            Poll::Ready((buffer, result)) => {
+                assert_eq!(vec![1, 2, 3], buffer);
+                assert_eq!(Err(Error::Access), result);
+            }
+            Poll::Pending => panic!("a failed transfer is ready"),
+        }
+    }
+
+    #[test]
+    fn it_builds_control_setup_packets() {
+        let buffer = control_buffer(Vec::new(), 0x40, 0x01, 0x1234, 0x0002, &[0xaa, 0xbb], 2);
+        assert_eq!(
+            vec![0x40, 0x01, 0x34, 0x12, 0x02, 0x00, 0x02, 0x00, 0xaa, 0xbb],
+            buffer
+        );
+
+        let buffer = control_buffer(Vec::new(), 0xc0, 0x02, 0, 0, &[], 4);
+        assert_eq!(vec![0xc0, 0x02, 0, 0, 0, 0, 0x04, 0, 0, 0, 0, 0], buffer);
+    }
+
+    #[test]
+    fn it_maps_transfer_statuses_to_errors() {
+        assert_eq!(None, status_error(LIBUSB_TRANSFER_COMPLETED));
+        assert_eq!(
+            Some(Error::Timeout),
+            status_error(LIBUSB_TRANSFER_TIMED_OUT)
+        );
+        assert_eq!(Some(Error::Pipe), status_error(LIBUSB_TRANSFER_STALL));
+        assert_eq!(Some(Error::Io), status_error(LIBUSB_TRANSFER_ERROR));
+    }
+}
@@ -0,0 +1,230 @@
+//! USB device authorization on Linux.
+//!
+//! The Linux kernel only binds drivers to devices and interfaces that are authorized. Devices are
+//! authorized by default, but a hub can be told to leave newly attached devices unauthorized
+//! until userspace approves them, which is the basis of USB allow-listing tools. This module
+//! exposes the `authorized`, `authorized_default` and `interface_authorized` attributes from
+//! sysfs.
+//!
+//! Changing authorization requires write access to sysfs, which normally means running as root.
+//! Failures to read or write the attributes are reported as `Error::Access` (permission denied),
+//! `Error::NotFound` (no such sysfs entry, e.g. the device was unplugged or the kernel is too
+//! old) or `Error::Io`.
+
+use std::{fs, path::PathBuf};
+
+use crate::{
+    device::Device,
+    error::{self, Error},
+    hotplug::Hotplug,
+    UsbContext,
+};
+
+const SYSFS_USB_DEVICES: &str = "/sys/bus/usb/devices";
+
+/// Which devices a hub authorizes automatically when they are attached.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum AuthorizedDefault {
+    /// New devices are left unauthorized.
+    None,
+
+    /// New devices are authorized (the kernel's default).
+    All,
+
+    /// Only devices attached to internal ports are authorized.
+    Internal,
+}
+
+/// Returns the sysfs directory of a device, e.g. `/sys/bus/usb/devices/1-4.2`.
+pub fn sysfs_path<T: UsbContext>(device: &Device<T>) -> crate::Result<PathBuf> {
+    Ok(PathBuf::from(SYSFS_USB_DEVICES)
+        .join(sysfs_name(device.bus_number(), &device.port_numbers()?)))
+}
+
+/// Indicates whether a device is authorized.
+pub fn is_authorized<T: UsbContext>(device: &Device<T>) -> crate::Result<bool> {
+    read_flag(sysfs_path(device)?.join("authorized"))
+}
+
+/// Authorizes or deauthorizes a device.
+///
+/// Deauthorizing a device unbinds all its drivers and makes it unusable until it is authorized
+/// again.
+pub fn set_authorized<T: UsbContext>(device: &Device<T>, authorized: bool) -> crate::Result<()> {
+    write_value(sysfs_path(device)?.join("authorized"), flag(authorized))
+}
+
+/// Indicates whether an interface of the device's active configuration is authorized.
+pub fn is_interface_authorized<T: UsbContext>(
+    device: &Device<T>,
+    config: u8,
+    iface: u8,
+) -> crate::Result<bool> {
+    read_flag(interface_path(device, config, iface)?.join("authorized"))
+}
+
+/// Authorizes or deauthorizes an interface of the device's active configuration.
+///
+/// This requires Linux 4.4 or newer.
+pub fn set_interface_authorized<T: UsbContext>(
+    device: &Device<T>,
+    config: u8,
+    iface: u8,
+    authorized: bool,
+) -> crate::Result<()> {
+    write_value(
+        interface_path(device, config, iface)?.join("authorized"),
+        flag(authorized),
+    )
+}
+
+/// Returns which devices the root hub of `bus` authorizes automatically.
+pub fn authorized_default(bus: u8) -> crate::Result<AuthorizedDefault> {
+    let path = root_hub_path(bus).join("authorized_default");
+    let value = fs::read_to_string(path).map_err(|e| error::from_io_error(&e))?;
+
+    parse_authorized_default(&value)
+}
+
+/// Sets which devices the root hub of `bus` authorizes automatically.
+///
+/// Setting this to `AuthorizedDefault::None` is how allow-listing is usually enabled: new devices
+/// stay unauthorized until [`set_authorized`](fn.set_authorized.html) is called for them.
+pub fn set_authorized_default(bus: u8, policy: AuthorizedDefault) -> crate::Result<()> {
+    let value = match policy {
+        AuthorizedDefault::None => "0",
+        AuthorizedDefault::All => "1",
+        AuthorizedDefault::Internal => "2",
+    };
+
+    write_value(root_hub_path(bus).join("authorized_default"), value)
+}
+
+/// Sets whether new interfaces on the devices of `bus` are authorized automatically.
+///
+/// This requires Linux 4.4 or newer.
+pub fn set_interface_authorized_default(bus: u8, authorized: bool) -> crate::Result<()> {
+    write_value(
+        root_hub_path(bus).join("interface_authorized_default"),
+        flag(authorized),
+    )
+}
+
+/// A hotplug handler that reports devices arriving without authorization.
+///
+/// Register it with a [`HotplugBuilder`](../struct.HotplugBuilder.html) to be notified of
+/// every device that needs a decision from the allow-listing policy. Devices whose authorization
+/// state can't be read are not reported.
+pub struct UnauthorizedArrivals<F> {
+    callback: F,
+}
+
+impl<F> UnauthorizedArrivals<F> {
+    /// Creates a handler calling `callback` for each unauthorized device that arrives.
+    pub fn new(callback: F) -> Self {
+        UnauthorizedArrivals { callback }
+    }
+}
+
+impl<T: UsbContext, F: FnMut(Device<T>)> Hotplug<T> for UnauthorizedArrivals<F> {
+    fn device_arrived(&mut self, device: Device<T>) {
+        if let Ok(false) = is_authorized(&device) {
+            (self.callback)(device);
+        }
+    }
+
+    fn device_left(&mut self, _device: Device<T>) {}
+}
+
+fn sysfs_name(bus: u8, ports: &[u8]) -> String {
+    if ports.is_empty() {
+        return format!("usb{}", bus);
+    }
+
+    let ports: Vec<String> = ports.iter().map(|p| p.to_string()).collect();
+    format!("{}-{}", bus, ports.join("."))
+}
+
+fn interface_path<T: UsbContext>(
+    device: &Device<T>,
+    config: u8,
+    iface: u8,
+) -> crate::Result<PathBuf> {
+    let name = sysfs_name(device.bus_number(), &device.port_numbers()?);
+
+    Ok(PathBuf::from(SYSFS_USB_DEVICES).join(format!("{}:{}.{}", name, config, iface)))
+}
+
+fn root_hub_path(bus: u8) -> PathBuf {
+    PathBuf::from(SYSFS_USB_DEVICES).join(sysfs_name(bus, &[]))
+}
+
+fn flag(value: bool) -> &'static str {
+    if value {
+        "1"
+    } else {
+        "0"
+    }
+}
+
+fn read_flag(path: PathBuf) -> crate::Result<bool> {
+    let value = fs::read_to_string(path).map_err(|e| error::from_io_error(&e))?;
+
+    parse_flag(&value)
+}
+
+fn write_value(path: PathBuf, value: &str) -> crate::Result<()> {
+    fs::write(path, value).map_err(|e| error::from_io_error(&e))
+}
+
+fn parse_flag(value: &str) -> crate::Result<bool> {
+    match value.trim() {
+        "0" => Ok(false),
+        "1" => Ok(true),
+        _ => Err(Error::Other),
+    }
+}
+
+fn parse_authorized_default(value: &str) -> crate::Result<AuthorizedDefault> {
+    match value.trim() {
+        "0" => Ok(AuthorizedDefault::None),
+        "2" => Ok(AuthorizedDefault::Internal),
+        // older kernels report -1 for "authorize everything but wireless devices"
+        "1" | "-1" => Ok(AuthorizedDefault::All),
+        _ => Err(Error::Other),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn it_names_root_hubs() {
+        assert_eq!("usb3", sysfs_name(3, &[]));
+    }
+
+    #[test]
+    fn it_names_devices_by_port_chain() {
+        assert_eq!("1-4", sysfs_name(1, &[4]));
+        assert_eq!("2-1.3.2", sysfs_name(2, &[1, 3, 2]));
+    }
+
+    #[test]
+    fn it_parses_flags() {
+        assert_eq!(Ok(true), parse_flag("1\n"));
+        assert_eq!(Ok(false), parse_flag("0\n"));
+        assert_eq!(Err(Error::Other), parse_flag("yes"));
+    }
+
+    #[test]
+    fn it_parses_authorized_default() {
+        assert_eq!(Ok(AuthorizedDefault::None), parse_authorized_default("0\n"));
+        assert_eq!(Ok(AuthorizedDefault::All), parse_authorized_default("1\n"));
+        assert_eq!(Ok(AuthorizedDefault::All), parse_authorized_default("-1\n"));
+        assert_eq!(
+            Ok(AuthorizedDefault::Internal),
+            parse_authorized_default("2\n")
+        );
+    }
+}
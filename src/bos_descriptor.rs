@@ -0,0 +1,362 @@
+use std::{fmt, slice, time::Duration};
+
+use libusb1_sys::*;
+
+use crate::fields::Speed;
+
+const CAP_USB_2_0_EXTENSION: u8 = 0x02;
+const CAP_SUPERSPEED_USB: u8 = 0x03;
+const CAP_CONTAINER_ID: u8 = 0x04;
+
+/// The layout of `libusb_bos_descriptor`, including the flexible array member holding the
+/// capabilities, which libusb1-sys leaves out.
+#[allow(non_snake_case)]
+#[repr(C)]
+struct libusb_bos_descriptor_with_caps {
+    bLength: u8,
+    bDescriptorType: u8,
+    wTotalLength: u16,
+    bNumDeviceCaps: u8,
+    dev_capability: [*mut libusb_bos_dev_capability_descriptor; 0],
+}
+
+/// Describes the Binary Object Store (BOS) of a device, i.e. its device capabilities.
+///
+/// Read with [`DeviceHandle::bos_descriptor`](struct.DeviceHandle.html#method.bos_descriptor).
+/// The capabilities defined by the USB specification are decoded by
+/// [`DeviceCapability`](struct.DeviceCapability.html); others, e.g. vendor-specific or platform
+/// capabilities, are available raw.
+pub struct BosDescriptor {
+    descriptor: *const libusb_bos_descriptor,
+}
+
+impl Drop for BosDescriptor {
+    fn drop(&mut self) {
+        unsafe {
+            libusb_free_bos_descriptor(self.descriptor as *mut libusb_bos_descriptor);
+        }
+    }
+}
+
+unsafe impl Sync for BosDescriptor {}
+unsafe impl Send for BosDescriptor {}
+
+impl BosDescriptor {
+    /// Returns the total length of the BOS descriptor and its capabilities.
+    pub fn total_length(&self) -> u16 {
+        unsafe { (*self.descriptor).wTotalLength }
+    }
+
+    /// Returns the number of device capabilities.
+    pub fn num_capabilities(&self) -> u8 {
+        unsafe { (*self.descriptor).bNumDeviceCaps }
+    }
+
+    /// Returns an iterator over the device capabilities.
+    pub fn capabilities(&self) -> Capabilities<'_> {
+        let caps = unsafe {
+            let descriptor = &*(self.descriptor as *const libusb_bos_descriptor_with_caps);
+            slice::from_raw_parts(
+                descriptor.dev_capability.as_ptr(),
+                descriptor.bNumDeviceCaps as usize,
+            )
+        };
+
+        Capabilities { iter: caps.iter() }
+    }
+
+    /// Returns the USB 2.0 extension capability, if the device declares one.
+    pub fn usb_2_0_extension(&self) -> Option<Usb2Extension> {
+        self.capabilities().find_map(|c| c.usb_2_0_extension())
+    }
+
+    /// Returns the SuperSpeed USB capability, if the device declares one.
+    pub fn superspeed(&self) -> Option<SuperSpeedCapability> {
+        self.capabilities().find_map(|c| c.superspeed())
+    }
+
+    /// Returns the container ID, if the device declares one.
+    pub fn container_id(&self) -> Option<ContainerId> {
+        self.capabilities().find_map(|c| c.container_id())
+    }
+}
+
+impl fmt::Debug for BosDescriptor {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        let mut debug = fmt.debug_struct("BosDescriptor");
+
+        let descriptor: &libusb_bos_descriptor = unsafe { &*self.descriptor };
+
+        debug.field("bLength", &descriptor.bLength);
+        debug.field("bDescriptorType", &descriptor.bDescriptorType);
+        debug.field("wTotalLength", &descriptor.wTotalLength);
+        debug.field("bNumDeviceCaps", &descriptor.bNumDeviceCaps);
+        debug.field("capabilities", &self.capabilities().collect::<Vec<_>>());
+
+        debug.finish()
+    }
+}
+
+/// Iterator over the capabilities of a BOS descriptor.
+pub struct Capabilities<'a> {
+    iter: slice::Iter<'a, *mut libusb_bos_dev_capability_descriptor>,
+}
+
+impl<'a> Iterator for Capabilities<'a> {
+    type Item = DeviceCapability<'a>;
+
+    fn next(&mut self) -> Option<DeviceCapability<'a>> {
+        self.iter.next().map(|&cap| unsafe {
+            // libusb copies each capability whole, right after its header
+            DeviceCapability {
+                raw: slice::from_raw_parts(cap as *const u8, (*cap).bLength as usize),
+            }
+        })
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.iter.size_hint()
+    }
+}
+
+/// A device capability of a BOS descriptor.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct DeviceCapability<'a> {
+    raw: &'a [u8],
+}
+
+impl<'a> DeviceCapability<'a> {
+    /// Returns the capability type, e.g. `0x02` for the USB 2.0 extension.
+    pub fn capability_type(&self) -> u8 {
+        self.raw.get(2).copied().unwrap_or(0)
+    }
+
+    /// Returns the whole capability descriptor, including its header.
+    pub fn raw(&self) -> &'a [u8] {
+        self.raw
+    }
+
+    /// Returns the capability-specific data following the header.
+    pub fn data(&self) -> &'a [u8] {
+        self.raw.get(3..).unwrap_or(&[])
+    }
+
+    /// Decodes the capability, if it is a USB 2.0 extension.
+    pub fn usb_2_0_extension(&self) -> Option<Usb2Extension> {
+        match (self.capability_type(), self.data()) {
+            (CAP_USB_2_0_EXTENSION, [a0, a1, a2, a3, ..]) => Some(Usb2Extension {
+                attributes: u32::from_le_bytes([*a0, *a1, *a2, *a3]),
+            }),
+            _ => None,
+        }
+    }
+
+    /// Decodes the capability, if it is a SuperSpeed USB capability.
+    pub fn superspeed(&self) -> Option<SuperSpeedCapability> {
+        match (self.capability_type(), self.data()) {
+            (CAP_SUPERSPEED_USB, [attributes, s0, s1, functionality, u1, u2_0, u2_1, ..]) => {
+                Some(SuperSpeedCapability {
+                    attributes: *attributes,
+                    speeds_supported: u16::from_le_bytes([*s0, *s1]),
+                    functionality_support: *functionality,
+                    u1_exit_latency: *u1,
+                    u2_exit_latency: u16::from_le_bytes([*u2_0, *u2_1]),
+                })
+            }
+            _ => None,
+        }
+    }
+
+    /// Decodes the capability, if it is a container ID.
+    pub fn container_id(&self) -> Option<ContainerId> {
+        match (self.capability_type(), self.data()) {
+            (CAP_CONTAINER_ID, [_, id @ ..]) if id.len() >= 16 => {
+                let mut bytes = [0; 16];
+                bytes.copy_from_slice(&id[..16]);
+                Some(ContainerId(bytes))
+            }
+            _ => None,
+        }
+    }
+}
+
+/// The USB 2.0 extension capability, declaring link power management (LPM) support.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct Usb2Extension {
+    attributes: u32,
+}
+
+impl Usb2Extension {
+    /// Returns the raw `bmAttributes` field.
+    pub fn attributes(&self) -> u32 {
+        self.attributes
+    }
+
+    /// Indicates whether the device supports link power management (the L1 state).
+    pub fn supports_lpm(&self) -> bool {
+        self.attributes & 0x02 != 0
+    }
+
+    /// Indicates whether the device uses Best Effort Service Latency (BESL) values rather than
+    /// the older HIRD values.
+    pub fn supports_besl(&self) -> bool {
+        self.attributes & 0x04 != 0
+    }
+
+    /// Returns the recommended baseline BESL value, if the device declares one.
+    pub fn baseline_besl(&self) -> Option<u8> {
+        if self.attributes & 0x08 != 0 {
+            Some((self.attributes >> 8 & 0x0F) as u8)
+        } else {
+            None
+        }
+    }
+
+    /// Returns the recommended deep BESL value, if the device declares one.
+    pub fn deep_besl(&self) -> Option<u8> {
+        if self.attributes & 0x10 != 0 {
+            Some((self.attributes >> 12 & 0x0F) as u8)
+        } else {
+            None
+        }
+    }
+}
+
+/// The SuperSpeed USB capability, declaring the speeds and link states a device supports.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct SuperSpeedCapability {
+    attributes: u8,
+    speeds_supported: u16,
+    functionality_support: u8,
+    u1_exit_latency: u8,
+    u2_exit_latency: u16,
+}
+
+impl SuperSpeedCapability {
+    /// Indicates whether the device can generate Latency Tolerance Messages (LTM).
+    pub fn supports_ltm(&self) -> bool {
+        self.attributes & 0x02 != 0
+    }
+
+    /// Returns the raw `wSpeedsSupported` field.
+    pub fn speeds_supported(&self) -> u16 {
+        self.speeds_supported
+    }
+
+    /// Indicates whether the device supports operating at `speed`.
+    pub fn supports_speed(&self, speed: Speed) -> bool {
+        match speed {
+            Speed::Low => self.speeds_supported & 0x01 != 0,
+            Speed::Full => self.speeds_supported & 0x02 != 0,
+            Speed::High => self.speeds_supported & 0x04 != 0,
+            Speed::Super => self.speeds_supported & 0x08 != 0,
+            Speed::Unknown => false,
+        }
+    }
+
+    /// Returns the lowest speed at which all the functionality of the device is available.
+    pub fn functionality_support(&self) -> Speed {
+        match self.functionality_support {
+            0 => Speed::Low,
+            1 => Speed::Full,
+            2 => Speed::High,
+            3 => Speed::Super,
+            _ => Speed::Unknown,
+        }
+    }
+
+    /// Returns the time the device takes to leave U1.
+    pub fn u1_exit_latency(&self) -> Duration {
+        Duration::from_micros(u64::from(self.u1_exit_latency))
+    }
+
+    /// Returns the time the device takes to leave U2.
+    pub fn u2_exit_latency(&self) -> Duration {
+        Duration::from_micros(u64::from(self.u2_exit_latency))
+    }
+}
+
+/// The container ID capability, a UUID identifying a physical device across the buses it is
+/// connected to, e.g. the USB 2.0 and SuperSpeed halves of a USB 3 device.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct ContainerId([u8; 16]);
+
+impl ContainerId {
+    /// Returns the UUID, as stored in the descriptor.
+    pub fn bytes(&self) -> [u8; 16] {
+        self.0
+    }
+}
+
+#[doc(hidden)]
+pub(crate) unsafe fn from_libusb(bos: *const libusb_bos_descriptor) -> BosDescriptor {
+    BosDescriptor { descriptor: bos }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn it_decodes_usb2_extension() {
+        // LPM, BESL, baseline BESL 4, deep BESL 10
+        let cap = DeviceCapability {
+            raw: &[0x07, 0x10, 0x02, 0x1E, 0xA4, 0x00, 0x00],
+        };
+
+        let ext = cap.usb_2_0_extension().unwrap();
+        assert!(ext.supports_lpm());
+        assert!(ext.supports_besl());
+        assert_eq!(Some(4), ext.baseline_besl());
+        assert_eq!(Some(10), ext.deep_besl());
+        assert_eq!(None, cap.superspeed());
+    }
+
+    #[test]
+    fn it_decodes_superspeed_capability() {
+        // full, high and super speed, fully functional from full speed, U1 10us, U2 2047us
+        let cap = DeviceCapability {
+            raw: &[0x0A, 0x10, 0x03, 0x00, 0x0E, 0x00, 0x01, 0x0A, 0xFF, 0x07],
+        };
+
+        let ss = cap.superspeed().unwrap();
+        assert!(!ss.supports_ltm());
+        assert!(!ss.supports_speed(Speed::Low));
+        assert!(ss.supports_speed(Speed::High) && ss.supports_speed(Speed::Super));
+        assert_eq!(Speed::Full, ss.functionality_support());
+        assert_eq!(Duration::from_micros(10), ss.u1_exit_latency());
+        assert_eq!(Duration::from_micros(2047), ss.u2_exit_latency());
+    }
+
+    #[test]
+    fn it_decodes_container_id() {
+        let mut raw = vec![0x14, 0x10, 0x04, 0x00];
+        raw.extend(1..=16);
+        let cap = DeviceCapability { raw: &raw };
+
+        let id = cap.container_id().unwrap();
+        assert_eq!(1, id.bytes()[0]);
+        assert_eq!(16, id.bytes()[15]);
+    }
+
+    #[test]
+    fn it_exposes_other_capabilities_raw() {
+        let cap = DeviceCapability {
+            raw: &[0x08, 0x10, 0x05, 0x00, 0xAA, 0xBB, 0xCC, 0xDD],
+        };
+
+        assert_eq!(0x05, cap.capability_type());
+        assert_eq!(&[0x00, 0xAA, 0xBB, 0xCC, 0xDD], cap.data());
+        assert_eq!(None, cap.usb_2_0_extension());
+        assert_eq!(None, cap.container_id());
+    }
+
+    #[test]
+    fn it_ignores_truncated_capabilities() {
+        let cap = DeviceCapability {
+            raw: &[0x05, 0x10, 0x02, 0x1E, 0xA4],
+        };
+
+        assert_eq!(None, cap.usb_2_0_extension());
+    }
+}
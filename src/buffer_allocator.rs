@@ -0,0 +1,139 @@
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use libusb1_sys::libusb_context;
+
+/// The allocators installed on contexts, keyed by the address of their `libusb_context`.
+static ALLOCATORS: Mutex<Vec<(usize, Arc<dyn BufferAllocator>)>> = Mutex::new(Vec::new());
+
+/// Provides the buffers rusb allocates itself for a context, see
+/// [`UsbOption::buffer_allocator`](struct.UsbOption.html#method.buffer_allocator).
+///
+/// This covers the buffers of the futures returned by the `*_async` read and control methods of
+/// [`DeviceHandle`](struct.DeviceHandle.html), the setup packet of interruptible control
+/// transfers, and the HID report descriptor read by
+/// [`DeviceHandle::hid_report_descriptor`](struct.DeviceHandle.html#method.hid_report_descriptor).
+/// Buffers supplied by the application, e.g. to [`AsyncGroup`](struct.AsyncGroup.html), are left
+/// alone.
+///
+/// An allocator typically hands out buffers from a pool or an arena filled up front, so that
+/// transfers don't allocate on a latency-critical path.
+///
+/// ```
+/// use std::sync::{Arc, Mutex};
+/// use rusb::{BufferAllocator, Context, UsbOption};
+///
+/// #[derive(Default)]
+/// struct Pool(Mutex<Vec<Vec<u8>>>);
+///
+/// impl BufferAllocator for Pool {
+///     fn allocate(&self, len: usize) -> Vec<u8> {
+///         self.0.lock().unwrap().pop().unwrap_or_else(|| Vec::with_capacity(len))
+///     }
+///
+///     fn release(&self, buffer: Vec<u8>) {
+///         self.0.lock().unwrap().push(buffer);
+///     }
+/// }
+///
+/// # fn main() -> rusb::Result<()> {
+/// # return Ok(());
+/// let context = Context::with_options(&[UsbOption::buffer_allocator(Arc::new(Pool::default()))])?;
+/// # Ok(())
+/// # }
+/// ```
+pub trait BufferAllocator: Send + Sync {
+    /// Returns a buffer for `len` bytes.
+    ///
+    /// The buffer doesn't need to have the right length nor to be zeroed: rusb clears it and
+    /// fills it with `len` zeros, which doesn't allocate if its capacity is large enough.
+    fn allocate(&self, len: usize) -> Vec<u8>;
+
+    /// Takes back a buffer that rusb allocated and no longer uses, i.e. one that wasn't handed
+    /// over to the application. The default implementation drops it.
+    fn release(&self, buffer: Vec<u8>) {
+        drop(buffer);
+    }
+}
+
+fn allocators() -> MutexGuard<'static, Vec<(usize, Arc<dyn BufferAllocator>)>> {
+    ALLOCATORS.lock().unwrap_or_else(|p| p.into_inner())
+}
+
+/// Installs `allocator` on `context`, or removes the allocator of `context` if `None`.
+pub(crate) fn set(context: *mut libusb_context, allocator: Option<Arc<dyn BufferAllocator>>) {
+    let mut allocators = allocators();
+    allocators.retain(|(c, _)| *c != context as usize);
+    if let Some(allocator) = allocator {
+        allocators.push((context as usize, allocator));
+    }
+}
+
+/// Returns the allocator installed on `context`, if any.
+pub(crate) fn get(context: *mut libusb_context) -> Option<Arc<dyn BufferAllocator>> {
+    allocators()
+        .iter()
+        .find(|(c, _)| *c == context as usize)
+        .map(|(_, allocator)| allocator.clone())
+}
+
+/// Returns a buffer of `len` zeros, from `allocator` if there is one.
+pub(crate) fn allocate(allocator: Option<&Arc<dyn BufferAllocator>>, len: usize) -> Vec<u8> {
+    match allocator {
+        Some(allocator) => {
+            let mut buffer = allocator.allocate(len);
+            buffer.clear();
+            buffer.resize(len, 0);
+            buffer
+        }
+        None => vec![0; len],
+    }
+}
+
+/// Hands `buffer` back to `allocator`, or drops it if there is none.
+pub(crate) fn release(allocator: Option<&Arc<dyn BufferAllocator>>, buffer: Vec<u8>) {
+    if let Some(allocator) = allocator {
+        allocator.release(buffer);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[derive(Default)]
+    struct Pool(Mutex<Vec<Vec<u8>>>);
+
+    impl BufferAllocator for Pool {
+        fn allocate(&self, len: usize) -> Vec<u8> {
+            let buffer = self.0.lock().unwrap().pop();
+            buffer.unwrap_or_else(|| Vec::with_capacity(len))
+        }
+
+        fn release(&self, buffer: Vec<u8>) {
+            self.0.lock().unwrap().push(buffer);
+        }
+    }
+
+    #[test]
+    fn it_zeroes_recycled_buffers() {
+        let pool: Arc<dyn BufferAllocator> = Arc::new(Pool::default());
+
+        release(Some(&pool), vec![0xaa; 8]);
+        let buffer = allocate(Some(&pool), 4);
+
+        assert_eq!(vec![0; 4], buffer);
+        assert!(buffer.capacity() >= 8);
+    }
+
+    #[test]
+    fn it_keeps_allocators_per_context() {
+        let (a, b) = (0x1000 as *mut libusb_context, 0x2000 as *mut libusb_context);
+
+        set(a, Some(Arc::new(Pool::default())));
+        assert!(get(a).is_some());
+        assert!(get(b).is_none());
+
+        set(a, None);
+        assert!(get(a).is_none());
+    }
+}
@@ -0,0 +1,558 @@
+//! A small, stable C ABI over rusb's high-level APIs.
+//!
+//! This module is compiled with the `capi` feature and is laid out for
+//! [cargo-c](https://github.com/lu-zero/cargo-c), which builds it into a C library with a
+//! generated `rusb.h` header (`cargo cinstall --features capi`).
+//!
+//! The ABI exposes contexts, device handles and [`InPipe`](../struct.InPipe.html)s as opaque
+//! pointers. Every function returning `int` returns `0` on success or a negative libusb error
+//! code (`LIBUSB_ERROR_*`) on failure, which [`rusb_strerror`](fn.rusb_strerror.html) describes.
+//! Output parameters are only written on success.
+//!
+//! Objects must be freed with their matching `*_free`/`rusb_close` function. A handle may be
+//! closed while pipes started from it are still running; the device is closed once the last
+//! pipe is freed.
+
+use std::{
+    ffi::{CStr, CString},
+    os::raw::{c_char, c_int},
+    ptr, slice,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use libusb1_sys::constants::*;
+
+use crate::{
+    error::{self, Error},
+    pipe::{InPipe, InPipeBuilder},
+    Context, DeviceHandle, UsbContext,
+};
+
+/// An opaque `libusb` context.
+pub struct RusbContext {
+    context: Context,
+}
+
+/// An opaque open device.
+pub struct RusbHandle {
+    handle: Arc<DeviceHandle<Context>>,
+}
+
+/// An opaque IN pipe.
+pub struct RusbInPipe {
+    pipe: InPipe,
+}
+
+/// Creates a context.
+///
+/// # Safety
+///
+/// `out` must be a valid pointer to write the context to.
+#[no_mangle]
+pub unsafe extern "C" fn rusb_context_new(out: *mut *mut RusbContext) -> c_int {
+    if out.is_null() {
+        return error_code(Error::InvalidParam);
+    }
+
+    match Context::new() {
+        Ok(context) => {
+            *out = Box::into_raw(Box::new(RusbContext { context }));
+            0
+        }
+        Err(e) => error_code(e),
+    }
+}
+
+/// Frees a context. Handles opened from it remain usable.
+///
+/// # Safety
+///
+/// `context` must be null or a pointer returned by `rusb_context_new` that hasn't been freed.
+#[no_mangle]
+pub unsafe extern "C" fn rusb_context_free(context: *mut RusbContext) {
+    if !context.is_null() {
+        drop(Box::from_raw(context));
+    }
+}
+
+/// Opens the first device with the given vendor and product ID.
+///
+/// Returns `LIBUSB_ERROR_NOT_FOUND` if no such device is attached.
+///
+/// # Safety
+///
+/// `context` must be a live context and `out` a valid pointer to write the handle to.
+#[no_mangle]
+pub unsafe extern "C" fn rusb_open(
+    context: *const RusbContext,
+    vendor_id: u16,
+    product_id: u16,
+    out: *mut *mut RusbHandle,
+) -> c_int {
+    if context.is_null() || out.is_null() {
+        return error_code(Error::InvalidParam);
+    }
+
+    let devices = match (*context).context.devices() {
+        Ok(devices) => devices,
+        Err(e) => return error_code(e),
+    };
+
+    for device in devices.iter() {
+        let matches = device
+            .device_descriptor()
+            .map(|d| d.vendor_id() == vendor_id && d.product_id() == product_id)
+            .unwrap_or(false);
+
+        if matches {
+            return match device.open() {
+                Ok(handle) => {
+                    *out = Box::into_raw(Box::new(RusbHandle {
+                        handle: Arc::new(handle),
+                    }));
+                    0
+                }
+                Err(e) => error_code(e),
+            };
+        }
+    }
+
+    error_code(Error::NotFound)
+}
+
+/// Closes a handle.
+///
+/// # Safety
+///
+/// `handle` must be null or a pointer returned by `rusb_open` that hasn't been closed.
+#[no_mangle]
+pub unsafe extern "C" fn rusb_close(handle: *mut RusbHandle) {
+    if !handle.is_null() {
+        drop(Box::from_raw(handle));
+    }
+}
+
+/// Claims an interface.
+///
+/// Interfaces must be claimed before starting pipes on the handle; this returns
+/// `LIBUSB_ERROR_BUSY` while a pipe is running.
+///
+/// # Safety
+///
+/// `handle` must be a live handle.
+#[no_mangle]
+pub unsafe extern "C" fn rusb_claim_interface(handle: *mut RusbHandle, iface: u8) -> c_int {
+    with_handle_mut(handle, |h| h.claim_interface(iface))
+}
+
+/// Releases an interface.
+///
+/// Returns `LIBUSB_ERROR_BUSY` while a pipe is running.
+///
+/// # Safety
+///
+/// `handle` must be a live handle.
+#[no_mangle]
+pub unsafe extern "C" fn rusb_release_interface(handle: *mut RusbHandle, iface: u8) -> c_int {
+    with_handle_mut(handle, |h| h.release_interface(iface))
+}
+
+/// Reads from a bulk endpoint.
+///
+/// # Safety
+///
+/// `handle` must be a live handle, `buf` must be valid for writing `len` bytes and
+/// `transferred` must be null or valid for writing.
+#[no_mangle]
+#[cfg_attr(feature = "async-only", allow(deprecated))]
+pub unsafe extern "C" fn rusb_read_bulk(
+    handle: *const RusbHandle,
+    endpoint: u8,
+    buf: *mut u8,
+    len: usize,
+    timeout_ms: u32,
+    transferred: *mut usize,
+) -> c_int {
+    read(handle, buf, len, transferred, |h, buf| {
+        h.read_bulk(endpoint, buf, timeout(timeout_ms))
+    })
+}
+
+/// Writes to a bulk endpoint.
+///
+/// # Safety
+///
+/// `handle` must be a live handle, `buf` must be valid for reading `len` bytes and
+/// `transferred` must be null or valid for writing.
+#[no_mangle]
+#[cfg_attr(feature = "async-only", allow(deprecated))]
+pub unsafe extern "C" fn rusb_write_bulk(
+    handle: *const RusbHandle,
+    endpoint: u8,
+    buf: *const u8,
+    len: usize,
+    timeout_ms: u32,
+    transferred: *mut usize,
+) -> c_int {
+    write(handle, buf, len, transferred, |h, buf| {
+        h.write_bulk(endpoint, buf, timeout(timeout_ms))
+    })
+}
+
+/// Reads from an interrupt endpoint.
+///
+/// # Safety
+///
+/// `handle` must be a live handle, `buf` must be valid for writing `len` bytes and
+/// `transferred` must be null or valid for writing.
+#[no_mangle]
+#[cfg_attr(feature = "async-only", allow(deprecated))]
+pub unsafe extern "C" fn rusb_read_interrupt(
+    handle: *const RusbHandle,
+    endpoint: u8,
+    buf: *mut u8,
+    len: usize,
+    timeout_ms: u32,
+    transferred: *mut usize,
+) -> c_int {
+    read(handle, buf, len, transferred, |h, buf| {
+        h.read_interrupt(endpoint, buf, timeout(timeout_ms))
+    })
+}
+
+/// Writes to an interrupt endpoint.
+///
+/// # Safety
+///
+/// `handle` must be a live handle, `buf` must be valid for reading `len` bytes and
+/// `transferred` must be null or valid for writing.
+#[no_mangle]
+#[cfg_attr(feature = "async-only", allow(deprecated))]
+pub unsafe extern "C" fn rusb_write_interrupt(
+    handle: *const RusbHandle,
+    endpoint: u8,
+    buf: *const u8,
+    len: usize,
+    timeout_ms: u32,
+    transferred: *mut usize,
+) -> c_int {
+    write(handle, buf, len, transferred, |h, buf| {
+        h.write_interrupt(endpoint, buf, timeout(timeout_ms))
+    })
+}
+
+/// Performs a control transfer with an IN data stage.
+///
+/// # Safety
+///
+/// `handle` must be a live handle, `buf` must be valid for writing `len` bytes and
+/// `transferred` must be null or valid for writing.
+#[no_mangle]
+#[cfg_attr(feature = "async-only", allow(deprecated))]
+pub unsafe extern "C" fn rusb_read_control(
+    handle: *const RusbHandle,
+    request_type: u8,
+    request: u8,
+    value: u16,
+    index: u16,
+    buf: *mut u8,
+    len: usize,
+    timeout_ms: u32,
+    transferred: *mut usize,
+) -> c_int {
+    read(handle, buf, len, transferred, |h, buf| {
+        h.read_control(
+            request_type,
+            request,
+            value,
+            index,
+            buf,
+            timeout(timeout_ms),
+        )
+    })
+}
+
+/// Performs a control transfer with an OUT or no data stage.
+///
+/// # Safety
+///
+/// `handle` must be a live handle, `buf` must be valid for reading `len` bytes and
+/// `transferred` must be null or valid for writing.
+#[no_mangle]
+#[cfg_attr(feature = "async-only", allow(deprecated))]
+pub unsafe extern "C" fn rusb_write_control(
+    handle: *const RusbHandle,
+    request_type: u8,
+    request: u8,
+    value: u16,
+    index: u16,
+    buf: *const u8,
+    len: usize,
+    timeout_ms: u32,
+    transferred: *mut usize,
+) -> c_int {
+    write(handle, buf, len, transferred, |h, buf| {
+        h.write_control(
+            request_type,
+            request,
+            value,
+            index,
+            buf,
+            timeout(timeout_ms),
+        )
+    })
+}
+
+/// Starts reading an IN endpoint continuously on a background thread.
+///
+/// `interrupt` selects an interrupt endpoint instead of a bulk endpoint. `transfer_size` is the
+/// size of each read, or `0` for the default.
+///
+/// # Safety
+///
+/// `handle` must be a live handle and `out` a valid pointer to write the pipe to.
+#[no_mangle]
+pub unsafe extern "C" fn rusb_in_pipe_start(
+    handle: *const RusbHandle,
+    endpoint: u8,
+    interrupt: bool,
+    transfer_size: usize,
+    out: *mut *mut RusbInPipe,
+) -> c_int {
+    if handle.is_null() || out.is_null() {
+        return error_code(Error::InvalidParam);
+    }
+
+    let mut builder = if interrupt {
+        InPipeBuilder::interrupt(endpoint)
+    } else {
+        InPipeBuilder::bulk(endpoint)
+    };
+    if transfer_size > 0 {
+        builder = builder.transfer_size(transfer_size);
+    }
+
+    match builder.start((*handle).handle.clone()) {
+        Ok(pipe) => {
+            *out = Box::into_raw(Box::new(RusbInPipe { pipe }));
+            0
+        }
+        Err(e) => error_code(e),
+    }
+}
+
+/// Waits up to `timeout_ms` milliseconds for the next buffer of data from a pipe.
+///
+/// Returns `LIBUSB_ERROR_TIMEOUT` if no data arrived in time, and `LIBUSB_ERROR_OVERFLOW` if the
+/// data didn't fit in `buf`, in which case it is discarded. Once the pipe has stopped on an
+/// error and delivered it, `LIBUSB_ERROR_NOT_FOUND` is returned.
+///
+/// # Safety
+///
+/// `pipe` must be a live pipe, `buf` must be valid for writing `len` bytes and `received` must
+/// be null or valid for writing.
+#[no_mangle]
+pub unsafe extern "C" fn rusb_in_pipe_recv(
+    pipe: *const RusbInPipe,
+    buf: *mut u8,
+    len: usize,
+    timeout_ms: u32,
+    received: *mut usize,
+) -> c_int {
+    if pipe.is_null() || (buf.is_null() && len > 0) {
+        return error_code(Error::InvalidParam);
+    }
+
+    match (*pipe).pipe.recv_timeout(timeout(timeout_ms)) {
+        Ok(data) if data.len() > len => error_code(Error::Overflow),
+        Ok(data) => {
+            if !data.is_empty() {
+                ptr::copy_nonoverlapping(data.as_ptr(), buf, data.len());
+            }
+            if !received.is_null() {
+                *received = data.len();
+            }
+            0
+        }
+        Err(e) => error_code(e),
+    }
+}
+
+/// Stops and frees a pipe.
+///
+/// # Safety
+///
+/// `pipe` must be null or a pointer returned by `rusb_in_pipe_start` that hasn't been freed.
+#[no_mangle]
+pub unsafe extern "C" fn rusb_in_pipe_free(pipe: *mut RusbInPipe) {
+    if !pipe.is_null() {
+        drop(Box::from_raw(pipe));
+    }
+}
+
+/// Returns a static, NUL-terminated description of an error code.
+#[no_mangle]
+pub extern "C" fn rusb_strerror(code: c_int) -> *const c_char {
+    strerror(error::from_libusb(code)).as_ptr()
+}
+
+/// The descriptions of the errors returned by `rusb_strerror`, keyed by error. Each is
+/// [`Error::strerror`](../enum.Error.html#method.strerror), NUL-terminated once and kept for
+/// the lifetime of the process.
+static MESSAGES: Mutex<Vec<(Error, &'static CStr)>> = Mutex::new(Vec::new());
+
+fn strerror(error: Error) -> &'static CStr {
+    let mut messages = MESSAGES.lock().unwrap_or_else(|p| p.into_inner());
+    if let Some((_, message)) = messages.iter().find(|(e, _)| *e == error) {
+        return message;
+    }
+
+    // the descriptions don't contain NUL bytes
+    let message: &'static CStr = Box::leak(
+        CString::new(error.strerror())
+            .unwrap_or_default()
+            .into_boxed_c_str(),
+    );
+    messages.push((error, message));
+    message
+}
+
+fn error_code(error: Error) -> c_int {
+    match error {
+        Error::Success => 0,
+        Error::Io => LIBUSB_ERROR_IO,
+        Error::InvalidParam => LIBUSB_ERROR_INVALID_PARAM,
+        Error::Access => LIBUSB_ERROR_ACCESS,
+        Error::NoDevice => LIBUSB_ERROR_NO_DEVICE,
+        Error::NotFound => LIBUSB_ERROR_NOT_FOUND,
+        Error::Busy => LIBUSB_ERROR_BUSY,
+        Error::Timeout => LIBUSB_ERROR_TIMEOUT,
+        Error::Overflow => LIBUSB_ERROR_OVERFLOW,
+        Error::Pipe => LIBUSB_ERROR_PIPE,
+        Error::Interrupted => LIBUSB_ERROR_INTERRUPTED,
+        Error::NoMem => LIBUSB_ERROR_NO_MEM,
+        Error::NotSupported => LIBUSB_ERROR_NOT_SUPPORTED,
+        Error::Other => LIBUSB_ERROR_OTHER,
+    }
+}
+
+fn timeout(timeout_ms: u32) -> Duration {
+    Duration::from_millis(u64::from(timeout_ms))
+}
+
+unsafe fn with_handle_mut<F>(handle: *mut RusbHandle, f: F) -> c_int
+where
+    F: FnOnce(&mut DeviceHandle<Context>) -> crate::Result<()>,
+{
+    if handle.is_null() {
+        return error_code(Error::InvalidParam);
+    }
+
+    match Arc::get_mut(&mut (*handle).handle) {
+        Some(h) => f(h).map_or_else(error_code, |_| 0),
+        None => error_code(Error::Busy),
+    }
+}
+
+unsafe fn read<F>(
+    handle: *const RusbHandle,
+    buf: *mut u8,
+    len: usize,
+    transferred: *mut usize,
+    f: F,
+) -> c_int
+where
+    F: FnOnce(&DeviceHandle<Context>, &mut [u8]) -> crate::Result<usize>,
+{
+    if handle.is_null() || (buf.is_null() && len > 0) {
+        return error_code(Error::InvalidParam);
+    }
+
+    let buf = if len > 0 {
+        slice::from_raw_parts_mut(buf, len)
+    } else {
+        &mut []
+    };
+
+    finish(f(&(*handle).handle, buf), transferred)
+}
+
+unsafe fn write<F>(
+    handle: *const RusbHandle,
+    buf: *const u8,
+    len: usize,
+    transferred: *mut usize,
+    f: F,
+) -> c_int
+where
+    F: FnOnce(&DeviceHandle<Context>, &[u8]) -> crate::Result<usize>,
+{
+    if handle.is_null() || (buf.is_null() && len > 0) {
+        return error_code(Error::InvalidParam);
+    }
+
+    let buf = if len > 0 {
+        slice::from_raw_parts(buf, len)
+    } else {
+        &[]
+    };
+
+    finish(f(&(*handle).handle, buf), transferred)
+}
+
+unsafe fn finish(res: crate::Result<usize>, transferred: *mut usize) -> c_int {
+    match res {
+        Ok(n) => {
+            if !transferred.is_null() {
+                *transferred = n;
+            }
+            0
+        }
+        Err(e) => error_code(e),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn it_describes_error_codes() {
+        let message = unsafe { CStr::from_ptr(rusb_strerror(LIBUSB_ERROR_TIMEOUT)) };
+        assert_eq!(Error::Timeout.strerror(), message.to_str().unwrap());
+
+        let message = unsafe { CStr::from_ptr(rusb_strerror(0)) };
+        assert_eq!(Error::Success.strerror(), message.to_str().unwrap());
+
+        let message = unsafe { CStr::from_ptr(rusb_strerror(-1000)) };
+        assert_eq!(Error::Other.strerror(), message.to_str().unwrap());
+    }
+
+    #[test]
+    fn it_returns_the_same_description_for_an_error() {
+        assert_eq!(
+            rusb_strerror(LIBUSB_ERROR_PIPE),
+            rusb_strerror(LIBUSB_ERROR_PIPE)
+        );
+        assert_eq!(rusb_strerror(-1000), rusb_strerror(-2000));
+    }
+
+    #[test]
+    fn it_rejects_null_pointers() {
+        unsafe {
+            assert_eq!(
+                LIBUSB_ERROR_INVALID_PARAM,
+                rusb_context_new(ptr::null_mut())
+            );
+            assert_eq!(
+                LIBUSB_ERROR_INVALID_PARAM,
+                rusb_read_bulk(ptr::null(), 0x81, ptr::null_mut(), 0, 0, ptr::null_mut())
+            );
+            assert_eq!(
+                LIBUSB_ERROR_INVALID_PARAM,
+                rusb_claim_interface(ptr::null_mut(), 0)
+            );
+        }
+    }
+}
@@ -0,0 +1,288 @@
+//! Helpers for USB Communications Device Class (CDC) devices.
+//!
+//! CDC devices report line and network events through notifications on the interrupt IN
+//! endpoint of their communications interface. [`Notification::parse`] decodes the data of one
+//! such transfer:
+//!
+//! ```no_run
+//! # fn main() -> rusb::Result<()> {
+//! # let handle: rusb::DeviceHandle<rusb::GlobalContext> = unimplemented!();
+//! use rusb::cdc::Notification;
+//! use std::time::Duration;
+//!
+//! let mut buf = [0u8; 64];
+//! let len = handle.read_interrupt(0x83, &mut buf, Duration::from_secs(1))?;
+//!
+//! if let Notification::SerialState { state, .. } = Notification::parse(&buf[..len])? {
+//!     println!("carrier detect: {}", state.dcd());
+//! }
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! A notification longer than the endpoint's maximum packet size, e.g. `SERIAL_STATE` on an
+//! endpoint with 8-byte packets, spans several packets. Reading with a buffer larger than the
+//! notification receives all of them in one transfer, since the transfer only ends on a short
+//! packet.
+
+use crate::error::Error;
+
+/// `NETWORK_CONNECTION` notification code.
+pub const NETWORK_CONNECTION: u8 = 0x00;
+
+/// `RESPONSE_AVAILABLE` notification code.
+pub const RESPONSE_AVAILABLE: u8 = 0x01;
+
+/// `SERIAL_STATE` notification code.
+pub const SERIAL_STATE: u8 = 0x20;
+
+/// `CONNECTION_SPEED_CHANGE` notification code.
+pub const CONNECTION_SPEED_CHANGE: u8 = 0x2A;
+
+const HEADER_SIZE: usize = 8;
+
+/// The `bmRequestType` of notifications: device to host, class request, interface recipient.
+const NOTIFICATION_REQUEST_TYPE: u8 = 0xA1;
+
+/// A notification sent by a CDC device on its interrupt endpoint.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Notification {
+    /// The network connection of an ECM or NCM function went up or down.
+    NetworkConnection {
+        /// The communications interface the notification is for.
+        interface: u16,
+
+        /// Indicates whether the network is connected.
+        connected: bool,
+    },
+
+    /// The device has a response ready for `GET_ENCAPSULATED_RESPONSE`, e.g. an AT command
+    /// reply or an MBIM message.
+    ResponseAvailable {
+        /// The communications interface the notification is for.
+        interface: u16,
+    },
+
+    /// The state of the serial line changed, or an error was detected on it.
+    SerialState {
+        /// The communications interface the notification is for.
+        interface: u16,
+
+        /// The new state of the line.
+        state: SerialState,
+    },
+
+    /// The connection speeds of an ECM or NCM function changed.
+    ConnectionSpeedChange {
+        /// The communications interface the notification is for.
+        interface: u16,
+
+        /// The downstream bit rate, in bits per second.
+        downstream: u32,
+
+        /// The upstream bit rate, in bits per second.
+        upstream: u32,
+    },
+
+    /// Any other notification, left undecoded.
+    Other {
+        /// The `bNotification` code.
+        code: u8,
+
+        /// The `wValue` field.
+        value: u16,
+
+        /// The `wIndex` field, usually the interface.
+        index: u16,
+
+        /// The data following the header.
+        data: Vec<u8>,
+    },
+}
+
+impl Notification {
+    /// Decodes a notification from the data of an interrupt transfer.
+    ///
+    /// Data beyond `wLength` bytes after the header is ignored.
+    ///
+    /// ## Errors
+    ///
+    /// * `Other` if the data doesn't start with a class notification header, is shorter than its
+    ///   `wLength`, or is too short for the notification it announces.
+    pub fn parse(data: &[u8]) -> crate::Result<Notification> {
+        if data.len() < HEADER_SIZE || data[0] != NOTIFICATION_REQUEST_TYPE {
+            return Err(Error::Other);
+        }
+
+        let code = data[1];
+        let value = u16::from_le_bytes([data[2], data[3]]);
+        let index = u16::from_le_bytes([data[4], data[5]]);
+        let length = u16::from_le_bytes([data[6], data[7]]) as usize;
+
+        let payload = data[HEADER_SIZE..].get(..length).ok_or(Error::Other)?;
+
+        match code {
+            NETWORK_CONNECTION => Ok(Notification::NetworkConnection {
+                interface: index,
+                connected: value != 0,
+            }),
+            RESPONSE_AVAILABLE => Ok(Notification::ResponseAvailable { interface: index }),
+            SERIAL_STATE => match payload {
+                [low, high, ..] => Ok(Notification::SerialState {
+                    interface: index,
+                    state: SerialState(u16::from_le_bytes([*low, *high])),
+                }),
+                _ => Err(Error::Other),
+            },
+            CONNECTION_SPEED_CHANGE => match payload {
+                [d0, d1, d2, d3, u0, u1, u2, u3, ..] => Ok(Notification::ConnectionSpeedChange {
+                    interface: index,
+                    downstream: u32::from_le_bytes([*d0, *d1, *d2, *d3]),
+                    upstream: u32::from_le_bytes([*u0, *u1, *u2, *u3]),
+                }),
+                _ => Err(Error::Other),
+            },
+            _ => Ok(Notification::Other {
+                code,
+                value,
+                index,
+                data: payload.to_vec(),
+            }),
+        }
+    }
+}
+
+/// The UART state bitmap of a `SERIAL_STATE` notification.
+///
+/// The first four signals reflect the current state of the line. The error flags are reported
+/// once per event: the device sends another notification to clear them.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct SerialState(u16);
+
+impl SerialState {
+    /// Returns the raw bitmap.
+    pub fn bits(self) -> u16 {
+        self.0
+    }
+
+    /// Data carrier detect (`bRxCarrier`).
+    pub fn dcd(self) -> bool {
+        self.0 & 0x01 != 0
+    }
+
+    /// Data set ready (`bTxCarrier`).
+    pub fn dsr(self) -> bool {
+        self.0 & 0x02 != 0
+    }
+
+    /// A break was detected (`bBreak`).
+    pub fn break_detected(self) -> bool {
+        self.0 & 0x04 != 0
+    }
+
+    /// Ring indicator (`bRingSignal`).
+    pub fn ring(self) -> bool {
+        self.0 & 0x08 != 0
+    }
+
+    /// A framing error occurred (`bFraming`).
+    pub fn framing_error(self) -> bool {
+        self.0 & 0x10 != 0
+    }
+
+    /// A parity error occurred (`bParity`).
+    pub fn parity_error(self) -> bool {
+        self.0 & 0x20 != 0
+    }
+
+    /// Received data was discarded because of an overrun in the device (`bOverRun`).
+    pub fn overrun(self) -> bool {
+        self.0 & 0x40 != 0
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn it_decodes_serial_state() {
+        let data = [0xA1, 0x20, 0x00, 0x00, 0x01, 0x00, 0x02, 0x00, 0x23, 0x00];
+
+        match Notification::parse(&data).unwrap() {
+            Notification::SerialState { interface, state } => {
+                assert_eq!(1, interface);
+                assert!(state.dcd());
+                assert!(state.dsr());
+                assert!(!state.ring());
+                assert!(state.parity_error());
+                assert!(!state.overrun());
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn it_decodes_network_connection() {
+        let data = [0xA1, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00];
+
+        assert_eq!(
+            Ok(Notification::NetworkConnection {
+                interface: 0,
+                connected: true
+            }),
+            Notification::parse(&data)
+        );
+    }
+
+    #[test]
+    fn it_decodes_response_available() {
+        let data = [0xA1, 0x01, 0x00, 0x00, 0x02, 0x00, 0x00, 0x00];
+
+        assert_eq!(
+            Ok(Notification::ResponseAvailable { interface: 2 }),
+            Notification::parse(&data)
+        );
+    }
+
+    #[test]
+    fn it_decodes_connection_speed_change() {
+        let mut data = vec![0xA1, 0x2A, 0x00, 0x00, 0x00, 0x00, 0x08, 0x00];
+        data.extend_from_slice(&100_000_000u32.to_le_bytes());
+        data.extend_from_slice(&10_000_000u32.to_le_bytes());
+
+        assert_eq!(
+            Ok(Notification::ConnectionSpeedChange {
+                interface: 0,
+                downstream: 100_000_000,
+                upstream: 10_000_000
+            }),
+            Notification::parse(&data)
+        );
+    }
+
+    #[test]
+    fn it_keeps_unknown_notifications() {
+        let data = [0xA1, 0x42, 0x05, 0x00, 0x01, 0x00, 0x01, 0x00, 0x99, 0xFF];
+
+        assert_eq!(
+            Ok(Notification::Other {
+                code: 0x42,
+                value: 5,
+                index: 1,
+                data: vec![0x99]
+            }),
+            Notification::parse(&data)
+        );
+    }
+
+    #[test]
+    fn it_rejects_malformed_notifications() {
+        assert!(Notification::parse(&[0xA1, 0x20]).is_err());
+        assert!(Notification::parse(&[0x21, 0x01, 0, 0, 0, 0, 0, 0]).is_err());
+        // SERIAL_STATE announcing 2 bytes but delivering 1
+        assert!(Notification::parse(&[0xA1, 0x20, 0, 0, 0, 0, 0x02, 0x00, 0x01]).is_err());
+        // SERIAL_STATE with a too short wLength
+        assert!(Notification::parse(&[0xA1, 0x20, 0, 0, 0, 0, 0x01, 0x00, 0x01]).is_err());
+    }
+}
@@ -0,0 +1,397 @@
+//! Typed parsing of the class-specific descriptors found in 'extra' bytes.
+//!
+//! Class-specific descriptors follow the interface descriptor they belong to, so libusb leaves
+//! them in [`InterfaceDescriptor::extra`](../struct.InterfaceDescriptor.html#method.extra). Their
+//! meaning depends on the class of the interface, e.g. a `CS_INTERFACE` descriptor of subtype 1
+//! is a call management descriptor on a CDC interface but an audio control header on an audio
+//! interface. [`ClassDescriptors`] decodes them for the CDC, HID and audio classes, and yields
+//! every other descriptor as [`ClassDescriptor::Unknown`]:
+//!
+//! ```no_run
+//! # fn main() -> rusb::Result<()> {
+//! # let device: rusb::Device<rusb::GlobalContext> = unimplemented!();
+//! use rusb::class_descriptors::ClassDescriptor;
+//!
+//! let config = device.active_config_descriptor()?;
+//! for interface in config.interfaces() {
+//!     for setting in interface.descriptors() {
+//!         for descriptor in setting.class_descriptors() {
+//!             if let ClassDescriptor::Hid(hid) = descriptor {
+//!                 println!("report descriptor: {:?} bytes", hid.report_descriptor_length());
+//!             }
+//!         }
+//!     }
+//! }
+//! # Ok(())
+//! # }
+//! ```
+
+use libusb1_sys::constants::{LIBUSB_CLASS_AUDIO, LIBUSB_CLASS_COMM, LIBUSB_CLASS_HID};
+
+use crate::{descriptor_view::ExtraDescriptors, fields::DescriptorType};
+
+/// `bDescriptorType` of class-specific interface descriptors.
+const CS_INTERFACE: u8 = 0x24;
+
+const CDC_HEADER: u8 = 0x00;
+const CDC_CALL_MANAGEMENT: u8 = 0x01;
+const CDC_ACM: u8 = 0x02;
+const CDC_UNION: u8 = 0x06;
+const CDC_ETHERNET: u8 = 0x0F;
+
+const AUDIO_CONTROL: u8 = 0x01;
+const AUDIO_STREAMING: u8 = 0x02;
+const AUDIO_HEADER: u8 = 0x01;
+const AUDIO_GENERAL: u8 = 0x01;
+
+/// The first version of the audio class whose descriptors have the UAC2 layout.
+const UAC2: u16 = 0x0200;
+
+/// A class-specific descriptor.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ClassDescriptor<'a> {
+    /// The CDC header functional descriptor, which starts the functional descriptors.
+    CdcHeader {
+        /// The version of the CDC specification, in BCD, e.g. `0x0110`.
+        cdc_version: u16,
+    },
+
+    /// The CDC call management functional descriptor.
+    CdcCallManagement {
+        /// `bmCapabilities`: bit 0 if the device handles call management itself, bit 1 if it
+        /// does so over the data interface.
+        capabilities: u8,
+
+        /// The data interface used for call management.
+        data_interface: u8,
+    },
+
+    /// The CDC abstract control management functional descriptor of an ACM (serial) function.
+    CdcAcm {
+        /// `bmCapabilities`: bit 1 for `SET_LINE_CODING` and the related requests, bit 2 for
+        /// `SEND_BREAK`.
+        capabilities: u8,
+    },
+
+    /// The CDC union functional descriptor, grouping the interfaces of a function.
+    CdcUnion {
+        /// The controlling interface, usually the communications interface.
+        control_interface: u8,
+
+        /// The subordinate interfaces, usually the data interface.
+        subordinate_interfaces: &'a [u8],
+    },
+
+    /// The CDC Ethernet networking functional descriptor of an ECM function.
+    CdcEthernet {
+        /// The index of the string descriptor holding the MAC address.
+        mac_address_index: u8,
+
+        /// `bmEthernetStatistics`, the statistics the device collects.
+        statistics: u32,
+
+        /// The maximum segment size, usually 1514.
+        max_segment_size: u16,
+
+        /// `wNumberMCFilters`, the multicast filters the device supports.
+        multicast_filters: u16,
+
+        /// The number of power management pattern filters.
+        power_filters: u8,
+    },
+
+    /// The HID descriptor.
+    Hid(HidDescriptor<'a>),
+
+    /// The header of the audio control interface, followed by its units and terminals.
+    AudioControlHeader {
+        /// The version of the audio class specification, in BCD, e.g. `0x0100` for UAC1 and
+        /// `0x0200` for UAC2.
+        audio_version: u16,
+
+        /// The total length of the class-specific audio control descriptors, header included.
+        total_length: u16,
+
+        /// The streaming interfaces of the function. Only UAC1 lists them; UAC2 functions
+        /// group their interfaces with an interface association instead.
+        streaming_interfaces: &'a [u8],
+    },
+
+    /// The general descriptor of an audio streaming interface.
+    AudioStreamingGeneral {
+        /// The terminal the interface is connected to.
+        terminal_link: u8,
+
+        /// The rest of the descriptor, whose layout depends on the version of the audio class,
+        /// e.g. `bDelay` and `wFormatTag` for UAC1.
+        data: &'a [u8],
+    },
+
+    /// Any other descriptor, e.g. an audio unit or terminal, or a descriptor of another class.
+    Unknown {
+        /// The type of the descriptor.
+        descriptor_type: DescriptorType,
+
+        /// The descriptor, header included.
+        raw: &'a [u8],
+    },
+}
+
+/// A HID descriptor, describing the class descriptors of a HID interface.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HidDescriptor<'a> {
+    raw: &'a [u8],
+}
+
+impl<'a> HidDescriptor<'a> {
+    /// Returns the version of the HID specification, in BCD, e.g. `0x0111`.
+    pub fn hid_version(&self) -> u16 {
+        u16::from_le_bytes([self.raw[2], self.raw[3]])
+    }
+
+    /// Returns the country code of localized hardware, 0 if the hardware isn't localized.
+    pub fn country_code(&self) -> u8 {
+        self.raw[4]
+    }
+
+    /// Returns the type and length of each class descriptor of the interface, e.g. the report
+    /// descriptor, which are read with
+    /// [`DeviceHandle::read_interface_descriptor`](../struct.DeviceHandle.html#method.read_interface_descriptor).
+    pub fn descriptors(&self) -> impl Iterator<Item = (DescriptorType, u16)> + 'a {
+        let count = self.raw[5] as usize;
+        self.raw[6..].chunks_exact(3).take(count).map(|entry| {
+            (
+                DescriptorType::from(entry[0]),
+                u16::from_le_bytes([entry[1], entry[2]]),
+            )
+        })
+    }
+
+    /// Returns the length of the report descriptor, which is needed to read it.
+    pub fn report_descriptor_length(&self) -> Option<u16> {
+        self.descriptors()
+            .find(|(descriptor_type, _)| *descriptor_type == DescriptorType::Report)
+            .map(|(_, len)| len)
+    }
+
+    /// Returns the descriptor, header included.
+    pub fn raw(&self) -> &'a [u8] {
+        self.raw
+    }
+}
+
+/// Iterator over the class-specific descriptors in 'extra' bytes.
+///
+/// Returned by
+/// [`InterfaceDescriptor::class_descriptors`](../struct.InterfaceDescriptor.html#method.class_descriptors).
+/// Descriptors too short for their type are yielded as
+/// [`ClassDescriptor::Unknown`](enum.ClassDescriptor.html#variant.Unknown), and iteration stops
+/// like [`ExtraDescriptors`](../struct.ExtraDescriptors.html) at the first malformed length.
+#[derive(Debug, Clone)]
+pub struct ClassDescriptors<'a> {
+    class_code: u8,
+    sub_class_code: u8,
+    descriptors: ExtraDescriptors<'a>,
+}
+
+impl<'a> ClassDescriptors<'a> {
+    /// Iterates over the descriptors in `raw`, the 'extra' bytes of an interface with the given
+    /// class and sub class codes.
+    pub fn new(class_code: u8, sub_class_code: u8, raw: &'a [u8]) -> ClassDescriptors<'a> {
+        ClassDescriptors {
+            class_code,
+            sub_class_code,
+            descriptors: ExtraDescriptors::new(raw),
+        }
+    }
+
+    fn parse(&self, descriptor_type: DescriptorType, raw: &'a [u8]) -> Option<ClassDescriptor<'a>> {
+        match (self.class_code, u8::from(descriptor_type)) {
+            (LIBUSB_CLASS_COMM, CS_INTERFACE) => parse_cdc(raw),
+            (LIBUSB_CLASS_HID, _) if descriptor_type == DescriptorType::Hid => parse_hid(raw),
+            (LIBUSB_CLASS_AUDIO, CS_INTERFACE) => parse_audio(self.sub_class_code, raw),
+            _ => None,
+        }
+    }
+}
+
+impl<'a> Iterator for ClassDescriptors<'a> {
+    type Item = ClassDescriptor<'a>;
+
+    fn next(&mut self) -> Option<ClassDescriptor<'a>> {
+        let (descriptor_type, raw) = self.descriptors.next()?;
+        Some(
+            self.parse(descriptor_type, raw)
+                .unwrap_or(ClassDescriptor::Unknown {
+                    descriptor_type,
+                    raw,
+                }),
+        )
+    }
+}
+
+fn parse_cdc(raw: &[u8]) -> Option<ClassDescriptor<'_>> {
+    match *raw.get(2)? {
+        CDC_HEADER if raw.len() >= 5 => Some(ClassDescriptor::CdcHeader {
+            cdc_version: u16::from_le_bytes([raw[3], raw[4]]),
+        }),
+        CDC_CALL_MANAGEMENT if raw.len() >= 5 => Some(ClassDescriptor::CdcCallManagement {
+            capabilities: raw[3],
+            data_interface: raw[4],
+        }),
+        CDC_ACM if raw.len() >= 4 => Some(ClassDescriptor::CdcAcm {
+            capabilities: raw[3],
+        }),
+        CDC_UNION if raw.len() >= 4 => Some(ClassDescriptor::CdcUnion {
+            control_interface: raw[3],
+            subordinate_interfaces: &raw[4..],
+        }),
+        CDC_ETHERNET if raw.len() >= 13 => Some(ClassDescriptor::CdcEthernet {
+            mac_address_index: raw[3],
+            statistics: u32::from_le_bytes([raw[4], raw[5], raw[6], raw[7]]),
+            max_segment_size: u16::from_le_bytes([raw[8], raw[9]]),
+            multicast_filters: u16::from_le_bytes([raw[10], raw[11]]),
+            power_filters: raw[12],
+        }),
+        _ => None,
+    }
+}
+
+fn parse_hid(raw: &[u8]) -> Option<ClassDescriptor<'_>> {
+    if raw.len() < 6 {
+        return None;
+    }
+    Some(ClassDescriptor::Hid(HidDescriptor { raw }))
+}
+
+fn parse_audio(sub_class_code: u8, raw: &[u8]) -> Option<ClassDescriptor<'_>> {
+    match (sub_class_code, *raw.get(2)?) {
+        (AUDIO_CONTROL, AUDIO_HEADER) if raw.len() >= 5 => {
+            let audio_version = u16::from_le_bytes([raw[3], raw[4]]);
+            if audio_version >= UAC2 {
+                // bcdADC, bCategory, wTotalLength, bmControls
+                if raw.len() < 9 {
+                    return None;
+                }
+                Some(ClassDescriptor::AudioControlHeader {
+                    audio_version,
+                    total_length: u16::from_le_bytes([raw[6], raw[7]]),
+                    streaming_interfaces: &[],
+                })
+            } else {
+                // bcdADC, wTotalLength, bInCollection, baInterfaceNr
+                let count = *raw.get(7)? as usize;
+                Some(ClassDescriptor::AudioControlHeader {
+                    audio_version,
+                    total_length: u16::from_le_bytes([raw[5], raw[6]]),
+                    streaming_interfaces: raw.get(8..8 + count)?,
+                })
+            }
+        }
+        (AUDIO_STREAMING, AUDIO_GENERAL) if raw.len() >= 4 => {
+            Some(ClassDescriptor::AudioStreamingGeneral {
+                terminal_link: raw[3],
+                data: &raw[4..],
+            })
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn it_parses_cdc_functional_descriptors() {
+        #[rustfmt::skip]
+        let extra = [
+            0x05, 0x24, 0x00, 0x10, 0x01,
+            0x05, 0x24, 0x01, 0x00, 0x01,
+            0x04, 0x24, 0x02, 0x02,
+            0x05, 0x24, 0x06, 0x00, 0x01,
+            0x04, 0x24, 0x07, 0x00,
+        ];
+
+        let descriptors: Vec<_> = ClassDescriptors::new(LIBUSB_CLASS_COMM, 2, &extra).collect();
+        assert_eq!(
+            vec![
+                ClassDescriptor::CdcHeader {
+                    cdc_version: 0x0110
+                },
+                ClassDescriptor::CdcCallManagement {
+                    capabilities: 0,
+                    data_interface: 1
+                },
+                ClassDescriptor::CdcAcm { capabilities: 2 },
+                ClassDescriptor::CdcUnion {
+                    control_interface: 0,
+                    subordinate_interfaces: &[1]
+                },
+                ClassDescriptor::Unknown {
+                    descriptor_type: DescriptorType::Other(0x24),
+                    raw: &[0x04, 0x24, 0x07, 0x00]
+                },
+            ],
+            descriptors
+        );
+    }
+
+    #[test]
+    fn it_parses_hid_descriptors() {
+        let extra = [0x09, 0x21, 0x11, 0x01, 0x00, 0x01, 0x22, 0x3F, 0x00];
+
+        match ClassDescriptors::new(LIBUSB_CLASS_HID, 0, &extra).next() {
+            Some(ClassDescriptor::Hid(hid)) => {
+                assert_eq!(0x0111, hid.hid_version());
+                assert_eq!(0, hid.country_code());
+                assert_eq!(Some(63), hid.report_descriptor_length());
+            }
+            other => panic!("unexpected descriptor: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn it_parses_audio_headers_of_both_versions() {
+        let uac1 = [0x0A, 0x24, 0x01, 0x00, 0x01, 0x28, 0x00, 0x02, 0x01, 0x02];
+        assert_eq!(
+            Some(ClassDescriptor::AudioControlHeader {
+                audio_version: 0x0100,
+                total_length: 0x28,
+                streaming_interfaces: &[1, 2],
+            }),
+            ClassDescriptors::new(LIBUSB_CLASS_AUDIO, AUDIO_CONTROL, &uac1).next()
+        );
+
+        let uac2 = [0x09, 0x24, 0x01, 0x00, 0x02, 0x08, 0x40, 0x00, 0x00];
+        assert_eq!(
+            Some(ClassDescriptor::AudioControlHeader {
+                audio_version: 0x0200,
+                total_length: 0x40,
+                streaming_interfaces: &[],
+            }),
+            ClassDescriptors::new(LIBUSB_CLASS_AUDIO, AUDIO_CONTROL, &uac2).next()
+        );
+
+        // the same bytes on a streaming interface are a general descriptor
+        assert_eq!(
+            Some(ClassDescriptor::AudioStreamingGeneral {
+                terminal_link: 0,
+                data: &uac2[4..],
+            }),
+            ClassDescriptors::new(LIBUSB_CLASS_AUDIO, AUDIO_STREAMING, &uac2).next()
+        );
+    }
+
+    #[test]
+    fn it_yields_descriptors_of_other_classes_as_unknown() {
+        let extra = [0x05, 0x24, 0x00, 0x10, 0x01];
+        assert_eq!(
+            Some(ClassDescriptor::Unknown {
+                descriptor_type: DescriptorType::Other(0x24),
+                raw: &extra,
+            }),
+            ClassDescriptors::new(0xFF, 0, &extra).next()
+        );
+    }
+}
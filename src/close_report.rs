@@ -0,0 +1,73 @@
+use crate::error::Error;
+
+/// What [`DeviceHandle::close_gracefully`](struct.DeviceHandle.html#method.close_gracefully) did
+/// while tearing down a handle.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CloseReport {
+    released: Vec<u8>,
+    reattached: Vec<u8>,
+    failures: Vec<(u8, Error)>,
+}
+
+impl CloseReport {
+    pub(crate) fn released(&mut self, iface: u8) {
+        self.released.push(iface);
+    }
+
+    pub(crate) fn reattached(&mut self, iface: u8) {
+        self.reattached.push(iface);
+    }
+
+    pub(crate) fn failed(&mut self, iface: u8, error: Error) {
+        self.failures.push((iface, error));
+    }
+
+    /// Returns the interfaces that were released.
+    pub fn released_interfaces(&self) -> &[u8] {
+        &self.released
+    }
+
+    /// Returns the interfaces whose kernel driver was reattached.
+    pub fn reattached_drivers(&self) -> &[u8] {
+        &self.reattached
+    }
+
+    /// Returns the interfaces that could not be released or have their kernel driver reattached,
+    /// with the error that occurred.
+    ///
+    /// A `NoDevice` error here usually just means the device was unplugged before it was closed.
+    pub fn failures(&self) -> &[(u8, Error)] {
+        &self.failures
+    }
+
+    /// Indicates whether every step of the teardown succeeded.
+    pub fn is_clean(&self) -> bool {
+        self.failures.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn it_is_clean_without_failures() {
+        let mut report = CloseReport::default();
+        report.released(0);
+        report.reattached(0);
+
+        assert!(report.is_clean());
+        assert_eq!(&[0], report.released_interfaces());
+        assert_eq!(&[0], report.reattached_drivers());
+    }
+
+    #[test]
+    fn it_records_failures() {
+        let mut report = CloseReport::default();
+        report.released(0);
+        report.failed(1, Error::NoDevice);
+
+        assert!(!report.is_clean());
+        assert_eq!(&[(1, Error::NoDevice)], report.failures());
+    }
+}
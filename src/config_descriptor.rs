@@ -2,7 +2,11 @@ use std::{fmt, slice};
 
 use libusb1_sys::*;
 
-use crate::interface_descriptor::{self, Interface};
+use crate::{
+    descriptor_view::ExtraDescriptors,
+    fields::{DescriptorType, Speed},
+    interface_descriptor::{self, Interface},
+};
 
 /// Describes a configuration.
 pub struct ConfigDescriptor {
@@ -27,10 +31,23 @@ impl ConfigDescriptor {
     }
 
     /// Returns the device's maximum power consumption (in milliamps) in this configuration.
+    ///
+    /// This assumes the 2mA units of `bMaxPower` used below SuperSpeed; see
+    /// [`max_power_ma`](#method.max_power_ma) for devices that may be running at SuperSpeed.
     pub fn max_power(&self) -> u16 {
         unsafe { u16::from((*self.descriptor).bMaxPower) * 2 }
     }
 
+    /// Returns the device's maximum power consumption (in milliamps) in this configuration, when
+    /// operating at `speed`.
+    ///
+    /// `bMaxPower` is expressed in units of 2mA at low, full and high speed, and in units of 8mA
+    /// at SuperSpeed. Pass the speed reported by
+    /// [`Device::speed`](struct.Device.html#method.speed).
+    pub fn max_power_ma(&self, speed: Speed) -> u16 {
+        max_power_ma(unsafe { (*self.descriptor).bMaxPower }, speed)
+    }
+
     /// Indicates if the device is self-powered in this configuration.
     pub fn self_powered(&self) -> bool {
         unsafe { (*self.descriptor).bmAttributes & 0x40 != 0 }
@@ -57,7 +74,7 @@ impl ConfigDescriptor {
     }
 
     /// Returns a collection of the configuration's interfaces.
-    pub fn interfaces(&self) -> Interfaces {
+    pub fn interfaces(&self) -> Interfaces<'_> {
         let interfaces = unsafe {
             slice::from_raw_parts(
                 (*self.descriptor).interface,
@@ -82,6 +99,127 @@ impl ConfigDescriptor {
             }
         }
     }
+
+    /// Returns the descriptors in the 'extra' bytes, e.g. interface association descriptors.
+    pub fn extra_descriptors(&self) -> ExtraDescriptors<'_> {
+        ExtraDescriptors::new(self.extra().unwrap_or(&[]))
+    }
+
+    /// Returns the configuration's interface associations, which group the interfaces of one
+    /// function of a composite device, e.g. the control and data interfaces of a CDC-ACM port.
+    ///
+    /// libusb leaves interface association descriptors in the 'extra' bytes of whichever
+    /// descriptor precedes them, so they are gathered from the configuration, its interfaces and
+    /// their endpoints, in the order the device reported them.
+    pub fn interface_associations(&self) -> Vec<InterfaceAssociation> {
+        let mut extras = vec![self.extra().unwrap_or(&[])];
+        unsafe {
+            for interface in raw_slice(
+                (*self.descriptor).interface,
+                (*self.descriptor).bNumInterfaces as usize,
+            ) {
+                for setting in raw_slice(interface.altsetting, interface.num_altsetting as usize) {
+                    extras.push(raw_slice(setting.extra, setting.extra_length as usize));
+                    for endpoint in raw_slice(setting.endpoint, setting.bNumEndpoints as usize) {
+                        extras.push(raw_slice(endpoint.extra, endpoint.extra_length as usize));
+                    }
+                }
+            }
+        }
+
+        extras
+            .into_iter()
+            .flat_map(ExtraDescriptors::new)
+            .filter_map(|(descriptor_type, raw)| match descriptor_type {
+                DescriptorType::InterfaceAssociation => InterfaceAssociation::from_bytes(raw),
+                _ => None,
+            })
+            .collect()
+    }
+}
+
+/// Returns the `len` items at `data`, tolerating the null pointers libusb uses for empty arrays.
+unsafe fn raw_slice<'a, T>(data: *const T, len: usize) -> &'a [T] {
+    if data.is_null() || len == 0 {
+        &[]
+    } else {
+        slice::from_raw_parts(data, len)
+    }
+}
+
+/// An interface association descriptor, grouping consecutive interfaces into one function, as
+/// returned by
+/// [`ConfigDescriptor::interface_associations`](struct.ConfigDescriptor.html#method.interface_associations).
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct InterfaceAssociation {
+    first_interface: u8,
+    interface_count: u8,
+    class_code: u8,
+    sub_class_code: u8,
+    protocol_code: u8,
+    function_string_index: u8,
+}
+
+impl InterfaceAssociation {
+    /// Parses an interface association descriptor, header included.
+    pub(crate) fn from_bytes(raw: &[u8]) -> Option<InterfaceAssociation> {
+        match *raw {
+            [8..=255, 0x0B, first_interface, interface_count, class_code, sub_class_code, protocol_code, function_string_index, ..] => {
+                Some(InterfaceAssociation {
+                    first_interface,
+                    interface_count,
+                    class_code,
+                    sub_class_code,
+                    protocol_code,
+                    function_string_index,
+                })
+            }
+            _ => None,
+        }
+    }
+
+    /// Returns the number of the function's first interface.
+    pub fn first_interface(&self) -> u8 {
+        self.first_interface
+    }
+
+    /// Returns the number of consecutive interfaces in the function.
+    pub fn interface_count(&self) -> u8 {
+        self.interface_count
+    }
+
+    /// Returns the numbers of the function's interfaces.
+    pub fn interfaces(&self) -> std::ops::Range<u8> {
+        self.first_interface..self.first_interface.saturating_add(self.interface_count)
+    }
+
+    /// Indicates whether the interface with number `interface` belongs to the function.
+    pub fn contains(&self, interface: u8) -> bool {
+        self.interfaces().contains(&interface)
+    }
+
+    /// Returns the function's class code.
+    pub fn class_code(&self) -> u8 {
+        self.class_code
+    }
+
+    /// Returns the function's sub class code.
+    pub fn sub_class_code(&self) -> u8 {
+        self.sub_class_code
+    }
+
+    /// Returns the function's protocol code.
+    pub fn protocol_code(&self) -> u8 {
+        self.protocol_code
+    }
+
+    /// Returns the index of the string descriptor that describes the function.
+    pub fn function_string_index(&self) -> Option<u8> {
+        match self.function_string_index {
+            0 => None,
+            n => Some(n),
+        }
+    }
 }
 
 impl fmt::Debug for ConfigDescriptor {
@@ -123,15 +261,75 @@ impl<'a> Iterator for Interfaces<'a> {
     }
 }
 
+/// Converts a `bMaxPower` value to milliamps.
+pub(crate) fn max_power_ma(max_power: u8, speed: Speed) -> u16 {
+    match speed {
+        Speed::Super => u16::from(max_power) * 8,
+        _ => u16::from(max_power) * 2,
+    }
+}
+
+/// A summary of a device's power requirements in its active configuration, as returned by
+/// [`Device::power_draw`](struct.Device.html#method.power_draw).
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct PowerDraw {
+    configuration: u8,
+    speed: Speed,
+    max_power_ma: u16,
+    self_powered: bool,
+    remote_wakeup: bool,
+}
+
+impl PowerDraw {
+    pub(crate) fn new(config: &ConfigDescriptor, speed: Speed) -> PowerDraw {
+        PowerDraw {
+            configuration: config.number(),
+            speed,
+            max_power_ma: config.max_power_ma(speed),
+            self_powered: config.self_powered(),
+            remote_wakeup: config.remote_wakeup(),
+        }
+    }
+
+    /// Returns the number of the configuration the summary is for.
+    pub fn configuration(&self) -> u8 {
+        self.configuration
+    }
+
+    /// Returns the speed the device operates at, which determines the units of `bMaxPower`.
+    pub fn speed(&self) -> Speed {
+        self.speed
+    }
+
+    /// Returns the maximum current (in milliamps) the device draws from the bus.
+    pub fn max_power_ma(&self) -> u16 {
+        self.max_power_ma
+    }
+
+    /// Indicates if the device is self-powered, in which case it may draw less than
+    /// [`max_power_ma`](#method.max_power_ma) from the bus.
+    pub fn self_powered(&self) -> bool {
+        self.self_powered
+    }
+
+    /// Indicates if the device has remote wakeup capability.
+    pub fn remote_wakeup(&self) -> bool {
+        self.remote_wakeup
+    }
+}
+
 #[doc(hidden)]
 pub(crate) unsafe fn from_libusb(config: *const libusb_config_descriptor) -> ConfigDescriptor {
     ConfigDescriptor { descriptor: config }
 }
 
 #[cfg(test)]
+#[allow(clippy::bool_assert_comparison)]
 mod test {
     use std::mem;
 
+    use crate::fields::Speed;
+
     // The Drop trait impl calls libusb_free_config_descriptor(), which would attempt to free
     // unallocated memory for a stack-allocated config descriptor. Allocating a config descriptor
     // is not a simple malloc()/free() inside libusb. Mimicking libusb's allocation would be
@@ -146,6 +344,36 @@ mod test {
         }};
     }
 
+    #[test]
+    fn it_finds_interface_associations_in_every_extra() {
+        // CDC-ACM on interfaces 0 and 1, then a vendor function on interface 2
+        let acm = [8u8, 0x0B, 0, 2, 0x02, 0x02, 0x01, 4];
+        let vendor = [5u8, 0x24, 0x00, 0x10, 0x01, 8, 0x0B, 2, 1, 0xFF, 0, 0, 0];
+
+        let mut config = config_descriptor!(interface!(interface_descriptor!(
+            endpoint_descriptor!(extra: vendor.as_ptr(), extra_length: vendor.len() as i32)
+        )));
+        config.extra = acm.as_ptr();
+        config.extra_length = acm.len() as i32;
+
+        with_config!(config: config => {
+            let associations = config.interface_associations();
+            assert_eq!(2, associations.len());
+
+            assert_eq!(0..2, associations[0].interfaces());
+            assert_eq!((2, 2, 1), (
+                associations[0].class_code(),
+                associations[0].sub_class_code(),
+                associations[0].protocol_code(),
+            ));
+            assert_eq!(Some(4), associations[0].function_string_index());
+
+            assert!(associations[1].contains(2) && !associations[1].contains(1));
+            assert_eq!(0xFF, associations[1].class_code());
+            assert_eq!(None, associations[1].function_string_index());
+        });
+    }
+
     #[test]
     fn it_has_number() {
         with_config!(config: config_descriptor!(bConfigurationValue: 42) => {
@@ -160,6 +388,26 @@ mod test {
         });
     }
 
+    #[test]
+    fn it_has_max_power_in_milliamps_for_speed() {
+        with_config!(config: config_descriptor!(bMaxPower: 50) => {
+            assert_eq!(100, config.max_power_ma(Speed::High));
+            assert_eq!(100, config.max_power_ma(Speed::Unknown));
+            assert_eq!(400, config.max_power_ma(Speed::Super));
+        });
+    }
+
+    #[test]
+    fn it_summarizes_power_draw() {
+        with_config!(config: config_descriptor!(bConfigurationValue: 2, bMaxPower: 112, bmAttributes: 0b1010_0000) => {
+            let power = super::PowerDraw::new(&config, Speed::Super);
+            assert_eq!(2, power.configuration());
+            assert_eq!(896, power.max_power_ma());
+            assert!(!power.self_powered());
+            assert!(power.remote_wakeup());
+        });
+    }
+
     #[test]
     fn it_interprets_self_powered_bit_in_attributes() {
         with_config!(config: config_descriptor!(bmAttributes: 0b0000_0000) => {
@@ -1,15 +1,44 @@
-use libc::{c_int, c_void, timeval};
-
-use std::{mem, ptr, sync::Arc, sync::Once, time::Duration};
+use libc::{c_int, timeval};
+
+use std::{
+    mem, ptr,
+    sync::Once,
+    sync::{Arc, Mutex},
+    thread,
+    time::{Duration, Instant},
+};
 
 use crate::{
+    annotations, buffer_allocator,
     device::{self, Device},
+    device_descriptor::DeviceDescriptor,
+    device_filter::DeviceFilter,
     device_handle::{self, DeviceHandle},
     device_list::DeviceList,
+    device_policy::{self, DevicePolicy},
     error,
+    event_loop::{self, EventLoop},
+    hotplug::{self, Hotplug, HotplugBuilder, HotplugEvents, Registration},
+    keys::{DeviceKey, HandleKey, KeyRegistry},
+    managed_context::DeviceId,
+    pollfd::{self, PollFd, PollFdNotifier, PollFdRegistration},
+    topology::{self, TopologyNode},
 };
 use libusb1_sys::{constants::*, *};
 
+#[cfg(unix)]
+use std::os::unix::io::RawFd;
+
+// available since libusb 1.0.23, but not bound by libusb1-sys
+#[cfg(unix)]
+extern "system" {
+    fn libusb_wrap_sys_device(
+        ctx: *mut libusb_context,
+        sys_dev: isize,
+        dev_handle: *mut *mut libusb_device_handle,
+    ) -> c_int;
+}
+
 #[cfg(windows)]
 type Seconds = ::libc::c_long;
 #[cfg(windows)]
@@ -29,29 +58,49 @@ pub struct Context {
     context: Arc<ContextInner>,
 }
 
-#[derive(Eq, PartialEq)]
 struct ContextInner {
     inner: ptr::NonNull<libusb_context>,
+    keys: Mutex<KeyRegistry>,
+
+    /// Whether the context is exited on drop, i.e. it isn't borrowed from foreign code.
+    owned: bool,
 }
 
+impl PartialEq for ContextInner {
+    fn eq(&self, other: &Self) -> bool {
+        self.inner == other.inner
+    }
+}
+
+impl Eq for ContextInner {}
+
 impl Drop for ContextInner {
     /// Closes the `libusb` context.
     fn drop(&mut self) {
-        unsafe {
-            libusb_exit(self.inner.as_ptr());
+        if let Ok(mut keys) = self.keys.lock() {
+            keys.clear();
         }
-    }
-}
 
-unsafe impl Sync for Context {}
-unsafe impl Send for Context {}
+        // a borrowed context stays alive, but another one may later be allocated at its address
+        device_policy::set(self.inner.as_ptr(), None);
+        buffer_allocator::set(self.inner.as_ptr(), None);
+        annotations::forget_context(self.inner.as_ptr());
+
+        if self.owned {
+            #[cfg(feature = "leak-detection")]
+            crate::leak_detection::report_context(self.inner.as_ptr());
 
-pub trait Hotplug<T: UsbContext> {
-    fn device_arrived(&mut self, device: Device<T>);
-    fn device_left(&mut self, device: Device<T>);
+            unsafe {
+                libusb_exit(self.inner.as_ptr());
+            }
+        }
+        hotplug::free_retired(self.inner.as_ptr(), self.owned);
+        pollfd::forget_context(self.inner.as_ptr(), self.owned);
+    }
 }
 
-pub type Registration = c_int;
+unsafe impl Sync for ContextInner {}
+unsafe impl Send for ContextInner {}
 
 pub trait UsbContext: Clone + Sized {
     /// Get the raw libusb_context pointer, for advanced use in unsafe code.
@@ -62,6 +111,36 @@ pub trait UsbContext: Clone + Sized {
         DeviceList::new_with_context(self.clone())
     }
 
+    /// Arranges the devices attached to the system into a tree per bus, from the root hub down
+    /// to the devices behind each hub.
+    ///
+    /// Devices are placed by their bus and port numbers. A device whose hub isn't listed, e.g.
+    /// because the platform doesn't report hubs or a [device policy](#method.set_device_policy)
+    /// refuses it, appears under its closest listed ancestor, or as a root itself.
+    fn topology(&self) -> crate::Result<Vec<TopologyNode<Self>>> {
+        Ok(topology::build(self.devices()?.sorted_by_topology()))
+    }
+
+    /// Installs `policy`, restricting which devices the context touches.
+    ///
+    /// Refused devices are left out of [`devices`](#method.devices) and of hotplug events, and
+    /// opening them, e.g. through a `Device` obtained before the policy was installed, fails
+    /// with `Access`. The policy replaces any previous one, and applies to every clone of the
+    /// context.
+    fn set_device_policy(&self, policy: DevicePolicy) {
+        device_policy::set(self.as_raw(), Some(policy));
+    }
+
+    /// Removes the device policy, so the context touches every device again.
+    fn clear_device_policy(&self) {
+        device_policy::set(self.as_raw(), None);
+    }
+
+    /// Returns the device policy installed on the context, if any.
+    fn device_policy(&self) -> Option<DevicePolicy> {
+        device_policy::get(self.as_raw()).map(|policy| (*policy).clone())
+    }
+
     /// Convenience function to open a device by its vendor ID and product ID.
     ///
     /// This function is provided as a convenience for building prototypes without having to
@@ -79,9 +158,195 @@ pub trait UsbContext: Clone + Sized {
             unsafe { libusb_open_device_with_vid_pid(self.as_raw(), vendor_id, product_id) };
 
         if handle.is_null() {
-            None
-        } else {
-            Some(unsafe { device_handle::from_libusb(self.clone(), handle) })
+            return None;
+        }
+        if !unsafe { device_policy::permits(self.as_raw(), libusb_get_device(handle)) } {
+            unsafe { libusb_close(handle) };
+            return None;
+        }
+
+        Some(unsafe { device_handle::from_libusb(self.clone(), handle) })
+    }
+
+    /// Opens the USB device behind a file descriptor obtained outside of `libusb`.
+    ///
+    /// This is how devices are opened where the process can't open the device nodes itself,
+    /// e.g. on Android, where the USB manager hands the application an already open file
+    /// descriptor. The context should be created with
+    /// [`UsbOption::no_device_discovery`](struct.UsbOption.html#method.no_device_discovery) in
+    /// that case, otherwise creating it fails while scanning for devices.
+    ///
+    /// The descriptor is not closed when the handle is dropped.
+    ///
+    /// ## Errors
+    ///
+    /// * `Access` if the device policy of the context refuses the device, see
+    ///   [`set_device_policy`](#method.set_device_policy).
+    /// * `NotSupported` if the platform backend can't wrap file descriptors.
+    /// * Any error returned while reading the device's descriptors through `fd`.
+    ///
+    /// # Safety
+    ///
+    /// `fd` must be an open file descriptor of a USB device node, and it must stay open until
+    /// the returned handle is dropped.
+    #[cfg(unix)]
+    unsafe fn open_device_with_fd(&self, fd: RawFd) -> crate::Result<DeviceHandle<Self>> {
+        let mut handle = mem::MaybeUninit::<*mut libusb_device_handle>::uninit();
+
+        match libusb_wrap_sys_device(self.as_raw(), fd as isize, handle.as_mut_ptr()) {
+            0 => (),
+            err => return Err(error::from_libusb(err)),
+        }
+
+        let handle = handle.assume_init();
+        if !device_policy::permits(self.as_raw(), libusb_get_device(handle)) {
+            libusb_close(handle);
+            return Err(error::Error::Access);
+        }
+
+        Ok(device_handle::from_libusb(self.clone(), handle))
+    }
+
+    /// Opens the device with the given vendor ID, product ID and serial number.
+    ///
+    /// Setups with several identical devices tell them apart by their serial number, which can
+    /// only be read from an open device. Each device matching `vendor_id` and `product_id` is
+    /// opened in turn, until one reports `serial_number`.
+    ///
+    /// ## Errors
+    ///
+    /// * `NotFound` if no device matches.
+    /// * The error opening a candidate, e.g. `Access`, if no device matches but some candidate
+    ///   could not be opened, since it might have been the one.
+    #[cfg_attr(feature = "async-only", deprecated(note = "blocks the calling thread"))]
+    fn open_device_with_serial(
+        &self,
+        vendor_id: u16,
+        product_id: u16,
+        serial_number: &str,
+    ) -> crate::Result<DeviceHandle<Self>> {
+        self.find_devices()
+            .vendor_id(vendor_id)
+            .product_id(product_id)
+            .serial(serial_number)
+            .open()
+    }
+
+    /// Returns the devices with the given vendor ID, product ID and serial number.
+    ///
+    /// Like [`open_device_with_serial`](#method.open_device_with_serial), each candidate is
+    /// opened to read its serial number, and closed again. Devices that can't be opened are
+    /// left out. Usually at most one device is returned, but cheap devices sometimes share a
+    /// serial number.
+    #[cfg_attr(feature = "async-only", deprecated(note = "blocks the calling thread"))]
+    fn devices_with_serial(
+        &self,
+        vendor_id: u16,
+        product_id: u16,
+        serial_number: &str,
+    ) -> crate::Result<Vec<Device<Self>>> {
+        self.find_devices()
+            .vendor_id(vendor_id)
+            .product_id(product_id)
+            .serial(serial_number)
+            .devices()
+    }
+
+    /// Opens the device identified by `id`, e.g. after it was disconnected and plugged back
+    /// into the same port, or after the program restarted.
+    ///
+    /// The devices are enumerated again. If `id` holds a serial number, the device on its port
+    /// is opened to check it.
+    ///
+    /// ## Errors
+    ///
+    /// * `NoDevice` if no attached device matches `id`.
+    /// * Any error opening the device or reading its serial number.
+    #[cfg_attr(feature = "async-only", deprecated(note = "blocks the calling thread"))]
+    fn open_by_id(&self, id: &DeviceId) -> crate::Result<DeviceHandle<Self>> {
+        id.open(self)
+    }
+
+    /// Returns a filter finding the devices that match a set of criteria, see
+    /// [`DeviceFilter`](struct.DeviceFilter.html).
+    fn find_devices(&self) -> DeviceFilter<Self> {
+        DeviceFilter::new(self.clone())
+    }
+
+    /// Triggers a device to re-enumerate, waits for it, and returns the device it re-enumerated
+    /// as.
+    ///
+    /// Devices switching between an application and a bootloader (e.g. for DFU) disconnect and
+    /// come back as a new device, often with a different vendor and product ID. `trigger` makes
+    /// the switch, e.g. by sending a DFU_DETACH request: it is called once the attached devices
+    /// were listed, so the new device can't come back before the call noticed it was missing.
+    /// This then waits for `old_device` to disappear, and for a device that wasn't attached
+    /// before the switch to appear and be accepted by `filter`. Devices that were already
+    /// attached are never returned, so another device of the same kind can't be mistaken for the
+    /// re-enumerated one.
+    ///
+    /// `filter` is called with each candidate device and its descriptor. To rule out a device of
+    /// the expected kind being plugged in elsewhere at the same time, it can also compare the bus
+    /// number and port numbers with those of `old_device`.
+    ///
+    /// The device list is polled rather than relying on hotplug support, which is not available
+    /// on every platform.
+    ///
+    /// ## Errors
+    ///
+    /// * `Timeout` if the device did not disappear, or no matching device appeared, within
+    ///   `timeout` of `trigger` returning.
+    /// * Any error returned by `trigger`. A device may disconnect before acknowledging the
+    ///   request making it switch; a trigger expecting that should ignore the resulting error.
+    fn wait_for_reenumeration<S, F>(
+        &self,
+        old_device: &Device<Self>,
+        trigger: S,
+        mut filter: F,
+        timeout: Duration,
+    ) -> crate::Result<Device<Self>>
+    where
+        S: FnOnce() -> crate::Result<()>,
+        F: FnMut(&Device<Self>, &DeviceDescriptor) -> bool,
+    {
+        const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+        // keeps the initial devices referenced, so their addresses can't be reused by new ones
+        let initial = self.devices()?;
+        let is_initial =
+            |device: &Device<Self>| initial.iter().any(|d| d.as_raw() == device.as_raw());
+
+        trigger()?;
+        let deadline = Instant::now() + timeout;
+
+        let mut gone = false;
+
+        loop {
+            let devices = self.devices()?;
+
+            if !gone {
+                gone = !devices.iter().any(|d| d.as_raw() == old_device.as_raw());
+            }
+
+            if gone {
+                for device in devices.iter() {
+                    if device.as_raw() == old_device.as_raw() || is_initial(&device) {
+                        continue;
+                    }
+
+                    if let Ok(descriptor) = device.device_descriptor() {
+                        if filter(&device, &descriptor) {
+                            return Ok(device);
+                        }
+                    }
+                }
+            }
+
+            let now = Instant::now();
+            if now >= deadline {
+                return Err(error::Error::Timeout);
+            }
+            thread::sleep(POLL_INTERVAL.min(deadline - now));
         }
     }
 
@@ -92,46 +357,98 @@ pub trait UsbContext: Clone + Sized {
         }
     }
 
+    /// Returns a builder registering hotplug callbacks on this context, see
+    /// [`HotplugBuilder`](struct.HotplugBuilder.html).
+    fn hotplug(&self) -> HotplugBuilder<Self> {
+        HotplugBuilder::new(self.clone())
+    }
+
+    /// Registers a hotplug callback for the devices matching the given IDs and class.
+    ///
+    /// The callback stays registered until the registration is passed to
+    /// [`unregister_callback`](#method.unregister_callback), even if it is dropped. Use
+    /// [`hotplug`](#method.hotplug) for more options, and for a registration deregistering its
+    /// callback when dropped.
     fn register_callback(
         &self,
         vendor_id: Option<u16>,
         product_id: Option<u16>,
         class: Option<u8>,
         callback: Box<dyn Hotplug<Self>>,
-    ) -> crate::Result<Registration> {
-        let mut handle: libusb_hotplug_callback_handle = 0;
-        let callback = CallbackData {
-            context: self.clone(),
-            hotplug: callback,
-        };
-        let to = Box::new(callback);
-        let n = unsafe {
-            libusb_hotplug_register_callback(
-                self.as_raw(),
-                LIBUSB_HOTPLUG_EVENT_DEVICE_ARRIVED | LIBUSB_HOTPLUG_EVENT_DEVICE_LEFT,
-                LIBUSB_HOTPLUG_NO_FLAGS,
-                vendor_id
-                    .map(c_int::from)
-                    .unwrap_or(LIBUSB_HOTPLUG_MATCH_ANY),
-                product_id
-                    .map(c_int::from)
-                    .unwrap_or(LIBUSB_HOTPLUG_MATCH_ANY),
-                class.map(c_int::from).unwrap_or(LIBUSB_HOTPLUG_MATCH_ANY),
-                hotplug_callback::<Self>,
-                Box::into_raw(to) as *mut c_void,
-                &mut handle,
-            )
-        };
-        if n < 0 {
-            Err(error::from_libusb(n))
-        } else {
-            Ok(handle)
-        }
+    ) -> crate::Result<Registration<Self>> {
+        self.hotplug()
+            .matching(vendor_id, product_id, class)
+            .register(callback)
+            .map(Registration::keep_registered)
     }
 
-    fn unregister_callback(&self, reg: Registration) {
-        // TODO: fix handler leak
-        unsafe { libusb_hotplug_deregister_callback(self.as_raw(), reg) }
+    /// Returns the hotplug events of all devices, see
+    /// [`HotplugEvents`](struct.HotplugEvents.html).
+    ///
+    /// Use [`HotplugBuilder::events`](struct.HotplugBuilder.html#method.events) to only get the
+    /// events of some devices.
+    fn hotplug_events(&self) -> crate::Result<HotplugEvents<Self>>
+    where
+        Self: 'static,
+    {
+        self.hotplug().events()
+    }
+
+    /// Deregisters a hotplug callback.
+    fn unregister_callback(&self, reg: Registration<Self>) {
+        reg.deregister();
+    }
+
+    /// Starts a thread handling the events of this context, until the returned guard is
+    /// dropped, see [`EventLoop`](struct.EventLoop.html).
+    ///
+    /// This is what asynchronous transfers and hotplug callbacks need to make progress, without
+    /// calling [`handle_events`](#method.handle_events) from a hand-rolled thread.
+    fn spawn_event_loop(&self) -> EventLoop<Self>
+    where
+        Self: Send + 'static,
+    {
+        EventLoop::spawn(self.clone())
+    }
+
+    /// Returns the file descriptors `libusb` needs polled to handle the events of this context,
+    /// see [`PollFd`](struct.PollFd.html).
+    ///
+    /// ## Errors
+    ///
+    /// * `NotSupported` on platforms where `libusb` doesn't use file descriptors, e.g. Windows.
+    fn pollfds(&self) -> crate::Result<Vec<PollFd>> {
+        pollfd::pollfds(self.as_raw())
+    }
+
+    /// Calls `notifier` whenever `libusb` adds or removes a file descriptor, so an external
+    /// event loop can keep its registrations in sync with [`pollfds`](#method.pollfds).
+    ///
+    /// Registering a notifier replaces the previous one, see
+    /// [`PollFdRegistration`](struct.PollFdRegistration.html).
+    fn set_pollfd_notifiers(&self, notifier: Box<dyn PollFdNotifier>) -> PollFdRegistration<Self> {
+        PollFdRegistration::register(self, notifier)
+    }
+
+    /// Returns how long an external event loop may wait on the file descriptors before it must
+    /// call [`handle_pending_events`](#method.handle_pending_events) anyway, or `None` if no
+    /// timeout is pending.
+    ///
+    /// Always `None` if [`pollfds_handle_timeouts`](#method.pollfds_handle_timeouts).
+    fn next_timeout(&self) -> crate::Result<Option<Duration>> {
+        pollfd::next_timeout(self.as_raw())
+    }
+
+    /// Indicates whether timeouts are reported through the file descriptors, e.g. with a
+    /// `timerfd` on Linux, so an external event loop needs no timer of its own.
+    fn pollfds_handle_timeouts(&self) -> bool {
+        unsafe { libusb_pollfds_handle_timeouts(self.as_raw()) != 0 }
+    }
+
+    /// Handles the events that are ready without waiting, for external event loops to call when
+    /// a file descriptor is ready or a timeout expired.
+    fn handle_pending_events(&self) -> crate::Result<()> {
+        self.handle_events(Some(Duration::from_secs(0)))
     }
 
     fn handle_events(&self, timeout: Option<Duration>) -> crate::Result<()> {
@@ -147,6 +464,7 @@ pub trait UsbContext: Clone + Sized {
                 None => libusb_handle_events_completed(self.as_raw(), ptr::null_mut()),
             }
         };
+        event_loop::resume_callback_panic();
         if n < 0 {
             Err(error::from_libusb(n as c_int))
         } else {
@@ -183,11 +501,6 @@ impl UsbContext for GlobalContext {
     }
 }
 
-struct CallbackData<T: UsbContext> {
-    context: T,
-    hotplug: Box<dyn Hotplug<T>>,
-}
-
 impl Context {
     /// Opens a new `libusb` context.
     pub fn new() -> crate::Result<Self> {
@@ -195,44 +508,123 @@ impl Context {
 
         try_unsafe!(libusb_init(context.as_mut_ptr()));
 
-        Ok(Context {
-            context: unsafe {
-                Arc::new(ContextInner {
-                    inner: ptr::NonNull::new_unchecked(context.assume_init()),
-                })
-            },
-        })
+        Ok(unsafe { Context::from_raw(context.assume_init()) })
+    }
+
+    /// Wraps a `libusb` context created by foreign code, taking ownership of it.
+    ///
+    /// The context is exited with `libusb_exit` once the returned `Context`, its clones and every
+    /// device and handle obtained from them are dropped. This is meant for handing a context over
+    /// to rusb; use [`from_raw_borrowed`](#method.from_raw_borrowed) to share one that the
+    /// foreign code keeps using.
+    ///
+    /// # Safety
+    ///
+    /// `raw` must be a non-null context returned by `libusb_init` that nothing else exits.
+    pub unsafe fn from_raw(raw: *mut libusb_context) -> Context {
+        #[cfg(feature = "leak-detection")]
+        crate::leak_detection::track(crate::leak_detection::ResourceKind::Context, raw, raw);
+
+        Context::wrap(raw, true)
+    }
+
+    /// Wraps a `libusb` context created and owned by foreign code.
+    ///
+    /// rusb never exits the context. This lets rusb be introduced incrementally into a C or C++
+    /// code base that manages its own context: both sides can use the context concurrently, as
+    /// libusb allows. What rusb keeps for the context, e.g. its device policy, buffer allocator
+    /// and annotations, is still removed once the returned `Context` and its clones are dropped.
+    ///
+    /// # Safety
+    ///
+    /// `raw` must be a non-null context returned by `libusb_init`, and it must not be exited
+    /// while the returned `Context`, any of its clones, or any device, handle or transfer obtained
+    /// from them is alive.
+    pub unsafe fn from_raw_borrowed(raw: *mut libusb_context) -> Context {
+        Context::wrap(raw, false)
+    }
+
+    unsafe fn wrap(raw: *mut libusb_context, owned: bool) -> Context {
+        Context {
+            context: Arc::new(ContextInner {
+                inner: ptr::NonNull::new_unchecked(raw),
+                keys: Mutex::new(KeyRegistry::default()),
+                owned,
+            }),
+        }
+    }
+
+    /// Indicates whether rusb exits the context when it is dropped, i.e. it was not created with
+    /// [`from_raw_borrowed`](#method.from_raw_borrowed).
+    pub fn is_owned(&self) -> bool {
+        self.context.owned
     }
 
     /// Creates a new `libusb` context and sets runtime options.
+    ///
+    /// Options that `libusb` only honours before initialization, such as
+    /// [`UsbOption::no_device_discovery`](struct.UsbOption.html#method.no_device_discovery), are
+    /// set as process-wide defaults first.
     pub fn with_options(opts: &[crate::UsbOption]) -> crate::Result<Self> {
+        for opt in opts.iter().filter(|opt| opt.is_default()) {
+            opt.apply_default()?;
+        }
+
         let mut this = Self::new()?;
 
-        for opt in opts {
+        for opt in opts.iter().filter(|opt| !opt.is_default()) {
             opt.apply(&mut this)?;
         }
 
         Ok(this)
     }
-}
 
-extern "system" fn hotplug_callback<T: UsbContext>(
-    _ctx: *mut libusb_context,
-    device: *mut libusb_device,
-    event: libusb_hotplug_event,
-    reg: *mut c_void,
-) -> c_int {
-    unsafe {
-        let mut reg = Box::<CallbackData<T>>::from_raw(reg as _);
-        let device = device::from_libusb(reg.context.clone(), device);
-        match event {
-            LIBUSB_HOTPLUG_EVENT_DEVICE_ARRIVED => reg.hotplug.device_arrived(device),
-            LIBUSB_HOTPLUG_EVENT_DEVICE_LEFT => reg.hotplug.device_left(device),
-            _ => (),
-        }
-        mem::forget(reg);
+    /// Returns the key identifying `device`, registering it if needed.
+    ///
+    /// The same device always gets the same key. Registered devices stay referenced until they
+    /// are forgotten with [`forget_device`](#method.forget_device) or the context is dropped.
+    pub fn device_key(&self, device: &Device<Context>) -> DeviceKey {
+        self.keys().device_key(device.as_raw())
+    }
+
+    /// Returns the registered device identified by `key`.
+    pub fn device_by_key(&self, key: DeviceKey) -> Option<Device<Context>> {
+        let device = self.keys().device(key)?;
+
+        Some(unsafe { device::from_libusb(self.clone(), device) })
+    }
+
+    /// Unregisters a device. Returns false if the key wasn't registered.
+    pub fn forget_device(&self, key: DeviceKey) -> bool {
+        self.keys().forget_device(key)
+    }
+
+    /// Registers an open handle and returns the key identifying it.
+    ///
+    /// The context owns registered handles: they stay open, and keep the context alive, until
+    /// they are removed with [`remove_handle`](#method.remove_handle).
+    pub fn register_handle(&self, handle: DeviceHandle<Context>) -> HandleKey {
+        self.keys().insert_handle(Arc::new(handle))
+    }
+
+    /// Returns the registered handle identified by `key`.
+    pub fn handle_by_key(&self, key: HandleKey) -> Option<Arc<DeviceHandle<Context>>> {
+        self.keys().handle(key)
+    }
+
+    /// Unregisters a handle, returning it. The device is closed once the last clone of the
+    /// returned `Arc` is dropped.
+    pub fn remove_handle(&self, key: HandleKey) -> Option<Arc<DeviceHandle<Context>>> {
+        self.keys().remove_handle(key)
+    }
+
+    fn keys(&self) -> std::sync::MutexGuard<'_, KeyRegistry> {
+        // the registry stays consistent even if a panic happened while it was locked
+        self.context
+            .keys
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
     }
-    0
 }
 
 /// Library logging levels.
@@ -267,3 +659,73 @@ impl LogLevel {
         }
     }
 }
+
+/// Indicates whether the device behind `handle` reports `serial_number`, read in the first
+/// language the device lists, so that serial numbers that aren't ASCII match too.
+#[cfg_attr(feature = "async-only", allow(deprecated))]
+pub(crate) fn has_serial_number<T: UsbContext>(
+    handle: &DeviceHandle<T>,
+    descriptor: &DeviceDescriptor,
+    serial_number: &str,
+) -> bool {
+    serial_matches(descriptor, serial_number, |index| {
+        handle.strings().read(index)
+    })
+}
+
+/// Indicates whether the serial number of the device described by `descriptor`, read with `read`
+/// if it has one, is `serial_number`.
+fn serial_matches(
+    descriptor: &DeviceDescriptor,
+    serial_number: &str,
+    read: impl FnOnce(u8) -> crate::Result<String>,
+) -> bool {
+    match descriptor.serial_number_string_index().map(read) {
+        Some(Ok(serial)) => serial == serial_number,
+        Some(Err(_)) | None => false,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn descriptor(serial_index: u8) -> DeviceDescriptor {
+        crate::device_descriptor::from_libusb(device_descriptor!(iSerialNumber: serial_index))
+    }
+
+    #[test]
+    fn it_forgets_the_settings_of_borrowed_contexts() {
+        let raw = 0x1000 as *mut libusb_context;
+        // a borrowed context is never exited, so libusb isn't called on the fake address
+        let context = unsafe { Context::from_raw_borrowed(raw) };
+        context.set_device_policy(DevicePolicy::deny_all());
+        assert!(device_policy::get(raw).is_some());
+
+        drop(context);
+        assert!(device_policy::get(raw).is_none());
+    }
+
+    #[test]
+    fn it_matches_serial_numbers_beyond_ascii() {
+        let read = |index| {
+            assert_eq!(3, index);
+            Ok("Zähler-01".to_string())
+        };
+
+        assert!(serial_matches(&descriptor(3), "Zähler-01", read));
+        assert!(!serial_matches(&descriptor(3), "Z?hler-01", read));
+    }
+
+    #[test]
+    fn it_does_not_match_unreadable_or_missing_serial_numbers() {
+        assert!(!serial_matches(&descriptor(3), "A1", |_| Err(
+            crate::Error::Pipe
+        )));
+        assert!(!serial_matches(
+            &descriptor(0),
+            "",
+            |_| -> crate::Result<String> { panic!("read a missing serial number") }
+        ));
+    }
+}
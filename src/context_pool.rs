@@ -0,0 +1,460 @@
+use std::{
+    collections::hash_map::DefaultHasher,
+    fmt,
+    hash::{Hash, Hasher},
+    sync::{
+        atomic::{AtomicBool, AtomicI32, Ordering},
+        mpsc::{self, Receiver, Sender},
+        Arc, Mutex,
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, SystemTime},
+};
+
+use libc::c_int;
+use libusb1_sys::{constants::LIBUSB_ERROR_INTERRUPTED, libusb_handle_events_completed};
+
+use crate::{
+    context::{Context, UsbContext},
+    device::Device,
+    error::{self, Error},
+    event_loop::{self, libusb_interrupt_event_handler},
+    hotplug::{Hotplug, Registration},
+};
+
+/// A set of `libusb` contexts, each with its own event-handling thread, that devices are spread
+/// across.
+///
+/// A single context serializes the event handling of every device opened from it on one thread.
+/// Hosts driving dozens of busy devices can shard them across several contexts instead, so that
+/// completions for different devices are processed in parallel.
+///
+/// Every device is assigned to exactly one shard, based on its bus number and port path, so a
+/// device keeps its shard when it is re-enumerated or re-plugged into the same port.
+/// [`devices`](#method.devices) lists each device once, already bound to the context of its shard,
+/// and hotplug callbacks registered with [`register_callback`](#method.register_callback) see a
+/// single stream of events covering all shards.
+///
+/// A shard whose thread panics, e.g. in a hotplug callback, stops handling events unless
+/// [`set_restart_on_panic`](#method.set_restart_on_panic) is enabled. The panic is reported
+/// through [`event_errors`](#method.event_errors) either way.
+pub struct ContextPool {
+    contexts: Vec<Context>,
+    stop: Arc<AtomicI32>,
+    restart: Arc<AtomicBool>,
+    running: Arc<Vec<AtomicBool>>,
+    threads: Vec<JoinHandle<()>>,
+    subscribers: Arc<Mutex<Vec<Sender<EventError>>>>,
+}
+
+/// An error returned by `libusb` while a [`ContextPool`](struct.ContextPool.html) thread was
+/// handling events, e.g. `Io` after a hub disappeared, or a panic of the thread.
+///
+/// Received through [`ContextPool::event_errors`](struct.ContextPool.html#method.event_errors).
+#[derive(Clone)]
+pub struct EventError {
+    shard: usize,
+    context: Context,
+    error: Error,
+    panic: Option<String>,
+    consecutive: u32,
+    time: SystemTime,
+}
+
+impl EventError {
+    /// Returns the shard whose thread hit the error.
+    pub fn shard(&self) -> usize {
+        self.shard
+    }
+
+    /// Returns the context of the shard, e.g. to close and reopen its devices.
+    pub fn context(&self) -> &Context {
+        &self.context
+    }
+
+    /// Returns the error, `Other` if the thread panicked.
+    pub fn error(&self) -> Error {
+        self.error
+    }
+
+    /// Returns the message of the panic, if the thread panicked rather than `libusb` returning
+    /// an error.
+    ///
+    /// The shard only handles events again if the pool restarts panicking threads, see
+    /// [`ContextPool::is_running`](struct.ContextPool.html#method.is_running).
+    pub fn panic(&self) -> Option<&str> {
+        self.panic.as_deref()
+    }
+
+    /// Returns how many times in a row handling events failed on this shard, including this
+    /// time. A count that keeps growing means the context is unusable.
+    pub fn consecutive(&self) -> u32 {
+        self.consecutive
+    }
+
+    /// Returns when the error happened.
+    pub fn time(&self) -> SystemTime {
+        self.time
+    }
+}
+
+impl fmt::Debug for EventError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("EventError")
+            .field("shard", &self.shard)
+            .field("error", &self.error)
+            .field("panic", &self.panic)
+            .field("consecutive", &self.consecutive)
+            .field("time", &self.time)
+            .finish()
+    }
+}
+
+/// How long a shard waits after an error before handling events again, so a persistent error
+/// doesn't turn into a busy loop.
+const ERROR_BACKOFF: Duration = Duration::from_millis(100);
+
+/// Identifies a hotplug callback registered on all contexts of a pool.
+#[derive(Debug)]
+pub struct PoolRegistration {
+    registrations: Vec<Registration<Context>>,
+}
+
+impl ContextPool {
+    /// Creates a pool of `shards` contexts and starts their event-handling threads.
+    ///
+    /// Returns `Error::InvalidParam` if `shards` is zero.
+    pub fn new(shards: usize) -> crate::Result<ContextPool> {
+        if shards == 0 {
+            return Err(Error::InvalidParam);
+        }
+
+        let contexts = (0..shards)
+            .map(|_| Context::new())
+            .collect::<crate::Result<Vec<_>>>()?;
+
+        let stop = Arc::new(AtomicI32::new(0));
+        let restart = Arc::new(AtomicBool::new(false));
+        let running = Arc::new(
+            (0..shards)
+                .map(|_| AtomicBool::new(true))
+                .collect::<Vec<_>>(),
+        );
+        let subscribers = Arc::new(Mutex::new(Vec::new()));
+        let threads = contexts
+            .iter()
+            .enumerate()
+            .map(|(shard, context)| {
+                let context = context.clone();
+                let stop = stop.clone();
+                let restart = restart.clone();
+                let running = running.clone();
+                let subscribers = subscribers.clone();
+                thread::spawn(move || {
+                    event_loop::supervise(
+                        || handle_events(shard, &context, &stop, &subscribers),
+                        |message| {
+                            publish(
+                                &subscribers,
+                                EventError {
+                                    shard,
+                                    context: context.clone(),
+                                    error: Error::Other,
+                                    panic: Some(message),
+                                    consecutive: 1,
+                                    time: SystemTime::now(),
+                                },
+                            );
+                            restart.load(Ordering::SeqCst)
+                        },
+                    );
+                    running[shard].store(false, Ordering::SeqCst);
+                })
+            })
+            .collect();
+
+        Ok(ContextPool {
+            contexts,
+            stop,
+            restart,
+            running,
+            threads,
+            subscribers,
+        })
+    }
+
+    /// Sets whether the thread of a shard handles events again after a panic, rather than
+    /// stopping.
+    ///
+    /// Disabled by default. Enabling it doesn't restart the shards that already stopped.
+    pub fn set_restart_on_panic(&self, restart: bool) {
+        self.restart.store(restart, Ordering::SeqCst);
+    }
+
+    /// Indicates whether the thread of `shard` still handles events, i.e. it didn't stop after a
+    /// panic.
+    ///
+    /// Transfers of the devices of a stopped shard never complete; recreate the pool to recover.
+    ///
+    /// ## Panics
+    ///
+    /// Panics if `shard` is out of range.
+    pub fn is_running(&self, shard: usize) -> bool {
+        self.running[shard].load(Ordering::SeqCst)
+    }
+
+    /// Returns a channel receiving the errors hit by the event-handling threads of the pool.
+    ///
+    /// The threads keep handling events after an error, so a transient failure doesn't stop the
+    /// shard, but the application gets to decide whether to recreate the pool, reopen devices,
+    /// or raise an alert. A panic of a thread is reported too, see
+    /// [`EventError::panic`](struct.EventError.html#method.panic). Every call returns a new
+    /// receiver getting all the errors from then on; errors happening while no receiver exists
+    /// are dropped. `Interrupted` is not reported.
+    pub fn event_errors(&self) -> Receiver<EventError> {
+        let (sender, receiver) = mpsc::channel();
+        self.subscribers.lock().unwrap().push(sender);
+        receiver
+    }
+
+    /// Returns the number of shards.
+    pub fn len(&self) -> usize {
+        self.contexts.len()
+    }
+
+    /// Returns false; a pool always has at least one shard.
+    pub fn is_empty(&self) -> bool {
+        self.contexts.is_empty()
+    }
+
+    /// Returns the contexts of the pool, indexed by shard.
+    pub fn contexts(&self) -> &[Context] {
+        &self.contexts
+    }
+
+    /// Returns the shard a device is assigned to.
+    pub fn shard_of<T: UsbContext>(&self, device: &Device<T>) -> usize {
+        let ports = device.port_numbers().unwrap_or_default();
+        shard_index(device.bus_number(), &ports, self.contexts.len())
+    }
+
+    /// Returns the context a device is assigned to.
+    pub fn context_for<T: UsbContext>(&self, device: &Device<T>) -> &Context {
+        &self.contexts[self.shard_of(device)]
+    }
+
+    /// Lists the devices attached to the system.
+    ///
+    /// Each device is listed once and belongs to the context of its shard, so handles opened from
+    /// it have their events processed by that shard's thread.
+    pub fn devices(&self) -> crate::Result<Vec<Device<Context>>> {
+        let mut devices = Vec::new();
+
+        for (shard, context) in self.contexts.iter().enumerate() {
+            for device in context.devices()?.iter() {
+                if self.shard_of(&device) == shard {
+                    devices.push(device);
+                }
+            }
+        }
+
+        Ok(devices)
+    }
+
+    /// Registers a hotplug callback receiving the events of all shards.
+    ///
+    /// The callback is invoked once per event, with the device bound to the context of its shard.
+    /// Because shards handle events on separate threads, the callback must be `Send`; calls to it
+    /// are serialized.
+    pub fn register_callback(
+        &self,
+        vendor_id: Option<u16>,
+        product_id: Option<u16>,
+        class: Option<u8>,
+        callback: Box<dyn Hotplug<Context> + Send>,
+    ) -> crate::Result<PoolRegistration> {
+        let callback = Arc::new(Mutex::new(callback));
+        let mut registration = PoolRegistration {
+            registrations: Vec::new(),
+        };
+
+        for (shard, context) in self.contexts.iter().enumerate() {
+            let forward = ShardHotplug {
+                shard,
+                shards: self.contexts.len(),
+                callback: callback.clone(),
+            };
+
+            let registered = context
+                .hotplug()
+                .matching(vendor_id, product_id, class)
+                .register(Box::new(forward));
+            match registered {
+                Ok(reg) => registration.registrations.push(reg),
+                Err(e) => {
+                    self.unregister_callback(registration);
+                    return Err(e);
+                }
+            }
+        }
+
+        Ok(registration)
+    }
+
+    /// Deregisters a hotplug callback from all shards.
+    pub fn unregister_callback(&self, registration: PoolRegistration) {
+        for (context, reg) in self.contexts.iter().zip(registration.registrations) {
+            context.unregister_callback(reg);
+        }
+    }
+}
+
+impl Drop for ContextPool {
+    fn drop(&mut self) {
+        self.stop.store(1, Ordering::SeqCst);
+        // wakes the threads up if they are waiting for events
+        for context in &self.contexts {
+            unsafe { libusb_interrupt_event_handler(context.as_raw()) };
+        }
+        for thread in self.threads.drain(..) {
+            thread.join().ok();
+        }
+    }
+}
+
+struct ShardHotplug {
+    shard: usize,
+    shards: usize,
+    callback: Arc<Mutex<Box<dyn Hotplug<Context> + Send>>>,
+}
+
+impl ShardHotplug {
+    fn owns(&self, device: &Device<Context>) -> bool {
+        let ports = device.port_numbers().unwrap_or_default();
+        shard_index(device.bus_number(), &ports, self.shards) == self.shard
+    }
+}
+
+impl Hotplug<Context> for ShardHotplug {
+    fn device_arrived(&mut self, device: Device<Context>) {
+        if self.owns(&device) {
+            // a callback that panicked keeps getting events if the shard was restarted
+            let mut callback = self.callback.lock().unwrap_or_else(|p| p.into_inner());
+            callback.device_arrived(device);
+        }
+    }
+
+    fn device_left(&mut self, device: Device<Context>) {
+        if self.owns(&device) {
+            let mut callback = self.callback.lock().unwrap_or_else(|p| p.into_inner());
+            callback.device_left(device);
+        }
+    }
+}
+
+/// The loop of a shard's event-handling thread.
+///
+/// The thread sleeps in libusb until an event arrives, or until the next transfer timeout libusb
+/// tracks itself, rather than waking up periodically to check `stop`. Dropping the pool sets
+/// `stop` and interrupts the wait.
+fn handle_events(
+    shard: usize,
+    context: &Context,
+    stop: &AtomicI32,
+    subscribers: &Mutex<Vec<Sender<EventError>>>,
+) {
+    let mut consecutive = 0;
+
+    // libusb reads the flag once it holds the event lock, so a stop requested while another
+    // thread handles the events of the context is noticed too
+    let completed = stop as *const AtomicI32 as *mut c_int;
+    while stop.load(Ordering::SeqCst) == 0 {
+        let n = unsafe { libusb_handle_events_completed(context.as_raw(), completed) };
+        event_loop::resume_callback_panic();
+        match n {
+            0 | LIBUSB_ERROR_INTERRUPTED => consecutive = 0,
+            n => {
+                let error = error::from_libusb(n);
+                consecutive += 1;
+                publish(
+                    subscribers,
+                    EventError {
+                        shard,
+                        context: context.clone(),
+                        error,
+                        panic: None,
+                        consecutive,
+                        time: SystemTime::now(),
+                    },
+                );
+                thread::sleep(ERROR_BACKOFF);
+            }
+        }
+    }
+}
+
+/// Sends an error to every receiver, forgetting the receivers that were dropped.
+fn publish(subscribers: &Mutex<Vec<Sender<EventError>>>, error: EventError) {
+    if let Ok(mut subscribers) = subscribers.lock() {
+        subscribers.retain(|sender| sender.send(error.clone()).is_ok());
+    }
+}
+
+fn shard_index(bus: u8, ports: &[u8], shards: usize) -> usize {
+    let mut hasher = DefaultHasher::new();
+    bus.hash(&mut hasher);
+    ports.hash(&mut hasher);
+    (hasher.finish() % shards as u64) as usize
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn it_publishes_errors_to_live_receivers() {
+        let subscribers = Mutex::new(Vec::new());
+        let (first, kept) = mpsc::channel();
+        let (second, dropped) = mpsc::channel();
+        subscribers.lock().unwrap().push(first);
+        subscribers.lock().unwrap().push(second);
+        drop(dropped);
+
+        let error = EventError {
+            shard: 1,
+            // never used, and borrowed contexts are not exited
+            context: unsafe { Context::from_raw_borrowed(std::ptr::NonNull::dangling().as_ptr()) },
+            error: Error::Io,
+            panic: None,
+            consecutive: 1,
+            time: SystemTime::now(),
+        };
+        publish(&subscribers, error);
+
+        let received = kept.try_recv().unwrap();
+        assert_eq!(
+            (1, Error::Io, 1),
+            (received.shard(), received.error(), received.consecutive())
+        );
+        assert_eq!(1, subscribers.lock().unwrap().len());
+    }
+
+    #[test]
+    fn it_assigns_devices_to_a_valid_shard() {
+        for bus in 0..4 {
+            for port in 0..8 {
+                assert!(shard_index(bus, &[port], 3) < 3);
+            }
+        }
+    }
+
+    #[test]
+    fn it_assigns_the_same_port_to_the_same_shard() {
+        assert_eq!(shard_index(1, &[2, 3], 4), shard_index(1, &[2, 3], 4));
+    }
+
+    #[test]
+    fn it_uses_a_single_shard_when_there_is_one() {
+        assert_eq!(0, shard_index(7, &[1, 2, 3], 1));
+    }
+}
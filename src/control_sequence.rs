@@ -0,0 +1,284 @@
+use std::{error::Error as StdError, fmt, time::Duration};
+
+use crate::{device_io::DeviceIo, error::Error};
+
+type Verify<'a> = Box<dyn Fn(&[u8]) -> bool + 'a>;
+
+/// A step of a control request sequence run by
+/// [`DeviceHandle::run_sequence`](struct.DeviceHandle.html#method.run_sequence).
+///
+/// The direction of the request is taken from `request_type`: IN requests read up to `length`
+/// bytes, OUT requests write their data.
+pub struct ControlRequest<'a> {
+    request_type: u8,
+    request: u8,
+    value: u16,
+    index: u16,
+    data: Data<'a>,
+    verify: Option<Verify<'a>>,
+}
+
+enum Data<'a> {
+    Read(usize),
+    Write(&'a [u8]),
+}
+
+impl<'a> ControlRequest<'a> {
+    /// Creates a request reading up to `length` bytes.
+    pub fn read(
+        request_type: u8,
+        request: u8,
+        value: u16,
+        index: u16,
+        length: usize,
+    ) -> ControlRequest<'a> {
+        ControlRequest::new(request_type, request, value, index, Data::Read(length))
+    }
+
+    /// Creates a request writing `data`.
+    pub fn write(
+        request_type: u8,
+        request: u8,
+        value: u16,
+        index: u16,
+        data: &'a [u8],
+    ) -> ControlRequest<'a> {
+        ControlRequest::new(request_type, request, value, index, Data::Write(data))
+    }
+
+    fn new(request_type: u8, request: u8, value: u16, index: u16, data: Data<'a>) -> Self {
+        ControlRequest {
+            request_type,
+            request,
+            value,
+            index,
+            data,
+            verify: None,
+        }
+    }
+
+    /// Checks the response of the request before moving on to the next step; the sequence fails
+    /// at this step if `verify` returns false.
+    ///
+    /// `verify` is called with the data read, or with the data written for OUT requests.
+    pub fn verify<F>(mut self, verify: F) -> Self
+    where
+        F: Fn(&[u8]) -> bool + 'a,
+    {
+        self.verify = Some(Box::new(verify));
+        self
+    }
+
+    #[cfg_attr(feature = "async-only", allow(deprecated))]
+    fn run<D: DeviceIo + ?Sized>(&self, io: &D, timeout: Duration) -> Result<Vec<u8>, Failure> {
+        let response = match self.data {
+            Data::Read(length) => {
+                let mut buf = vec![0; length];
+                let len = io.read_control(
+                    self.request_type,
+                    self.request,
+                    self.value,
+                    self.index,
+                    &mut buf,
+                    timeout,
+                )?;
+                buf.truncate(len);
+                buf
+            }
+            Data::Write(data) => {
+                let len = io.write_control(
+                    self.request_type,
+                    self.request,
+                    self.value,
+                    self.index,
+                    data,
+                    timeout,
+                )?;
+                data[..len].to_vec()
+            }
+        };
+
+        match self.verify {
+            Some(ref verify) if !verify(&response) => Err(Failure::Rejected(response)),
+            _ => Ok(response),
+        }
+    }
+}
+
+impl<'a> fmt::Debug for ControlRequest<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut s = f.debug_struct("ControlRequest");
+        s.field("request_type", &self.request_type)
+            .field("request", &self.request)
+            .field("value", &self.value)
+            .field("index", &self.index);
+        match self.data {
+            Data::Read(length) => s.field("length", &length),
+            Data::Write(data) => s.field("data", &data),
+        };
+        s.field("verify", &self.verify.is_some()).finish()
+    }
+}
+
+enum Failure {
+    Transfer(Error),
+    Rejected(Vec<u8>),
+}
+
+impl From<Error> for Failure {
+    fn from(error: Error) -> Failure {
+        Failure::Transfer(error)
+    }
+}
+
+/// The failure of a step of a control request sequence.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SequenceError {
+    step: usize,
+    error: Option<Error>,
+    response: Vec<u8>,
+    completed: Vec<Vec<u8>>,
+}
+
+impl SequenceError {
+    /// Returns the index of the step that failed.
+    pub fn step(&self) -> usize {
+        self.step
+    }
+
+    /// Returns the error of the failed transfer, or `None` if the transfer succeeded but its
+    /// response was rejected by the step's verification.
+    pub fn error(&self) -> Option<Error> {
+        self.error
+    }
+
+    /// Returns the response rejected by the step's verification; empty if the transfer failed.
+    pub fn response(&self) -> &[u8] {
+        &self.response
+    }
+
+    /// Returns the responses of the steps that completed before the failure.
+    pub fn completed(&self) -> &[Vec<u8>] {
+        &self.completed
+    }
+}
+
+impl fmt::Display for SequenceError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.error {
+            Some(error) => write!(f, "control request {} failed: {}", self.step, error),
+            None => write!(
+                f,
+                "control request {} returned an unexpected response",
+                self.step
+            ),
+        }
+    }
+}
+
+impl StdError for SequenceError {
+    fn source(&self) -> Option<&(dyn StdError + 'static)> {
+        self.error.as_ref().map(|e| e as &(dyn StdError + 'static))
+    }
+}
+
+/// Runs `steps` in order, stopping at the first one that fails.
+pub(crate) fn run<D: DeviceIo + ?Sized>(
+    io: &D,
+    steps: &[ControlRequest<'_>],
+    timeout: Duration,
+) -> Result<Vec<Vec<u8>>, SequenceError> {
+    let mut completed = Vec::with_capacity(steps.len());
+
+    for (step, request) in steps.iter().enumerate() {
+        let failure = match request.run(io, timeout) {
+            Ok(response) => {
+                completed.push(response);
+                continue;
+            }
+            Err(failure) => failure,
+        };
+
+        let (error, response) = match failure {
+            Failure::Transfer(error) => (Some(error), Vec::new()),
+            Failure::Rejected(response) => (None, response),
+        };
+        return Err(SequenceError {
+            step,
+            error,
+            response,
+            completed,
+        });
+    }
+
+    Ok(completed)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::fake::FakeDevice;
+
+    const TIMEOUT: Duration = Duration::from_secs(1);
+    const VENDOR_IN: u8 = 0xc0;
+    const VENDOR_OUT: u8 = 0x40;
+
+    fn device() -> FakeDevice {
+        let device = FakeDevice::new();
+        device.set_control_handler(|setup, data| match setup.request {
+            0x01 => Ok(()),
+            0x02 => {
+                data.extend_from_slice(&[0xa5, 0x01]);
+                Ok(())
+            }
+            _ => Err(Error::Pipe),
+        });
+        device
+    }
+
+    #[test]
+    fn it_runs_every_step_in_order() {
+        let device = device();
+        let steps = [
+            ControlRequest::write(VENDOR_OUT, 0x01, 0, 0, &[1, 2]),
+            ControlRequest::read(VENDOR_IN, 0x02, 0, 0, 8).verify(|r| r[0] == 0xa5),
+            ControlRequest::write(VENDOR_OUT, 0x01, 1, 0, &[3]),
+        ];
+
+        let responses = run(&device, &steps, TIMEOUT).unwrap();
+
+        assert_eq!(vec![vec![1, 2], vec![0xa5, 0x01], vec![3]], responses);
+        let writes = device.take_control_writes();
+        assert_eq!(
+            vec![vec![1, 2], vec![3]],
+            writes.into_iter().map(|w| w.1).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn it_stops_at_the_failing_step() {
+        let device = device();
+        let steps = [
+            ControlRequest::write(VENDOR_OUT, 0x01, 0, 0, &[1]),
+            ControlRequest::write(VENDOR_OUT, 0x7f, 0, 0, &[2]),
+            ControlRequest::write(VENDOR_OUT, 0x01, 0, 0, &[3]),
+        ];
+
+        let error = run(&device, &steps, TIMEOUT).unwrap_err();
+
+        assert_eq!((1, Some(Error::Pipe)), (error.step(), error.error()));
+        assert_eq!(&[vec![1]], error.completed());
+        assert_eq!(1, device.take_control_writes().len());
+    }
+
+    #[test]
+    fn it_reports_rejected_responses() {
+        let device = device();
+        let steps = [ControlRequest::read(VENDOR_IN, 0x02, 0, 0, 8).verify(|r| r == [0xa5, 0x02])];
+
+        let error = run(&device, &steps, TIMEOUT).unwrap_err();
+
+        assert_eq!((0, None), (error.step(), error.error()));
+        assert_eq!(&[0xa5, 0x01], error.response());
+    }
+}
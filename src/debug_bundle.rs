@@ -0,0 +1,220 @@
+//! A plain-text dump of everything rusb can find out about a device, meant to be attached to bug
+//! reports.
+
+use std::{fmt::Write as _, fs, path::Path, time::Duration};
+
+use crate::{
+    device::Device,
+    device_handle::DeviceHandle,
+    error::{self, Error},
+    version::version,
+    UsbContext,
+};
+
+const TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Writes the bundle of `device` to `path`.
+pub(crate) fn export<T: UsbContext>(device: &Device<T>, path: &Path) -> crate::Result<()> {
+    let bundle = render(device);
+    fs::write(path, bundle).map_err(|e| error::from_io_error(&e))
+}
+
+#[cfg_attr(feature = "async-only", allow(deprecated))]
+fn render<T: UsbContext>(device: &Device<T>) -> String {
+    let mut out = String::new();
+
+    let libusb = version();
+    writeln!(out, "rusb debug bundle").ok();
+    writeln!(out, "rusb: {}", env!("CARGO_PKG_VERSION")).ok();
+    writeln!(
+        out,
+        "libusb: {}.{}.{}.{}{}",
+        libusb.major(),
+        libusb.minor(),
+        libusb.micro(),
+        libusb.nano(),
+        libusb.rc().unwrap_or("")
+    )
+    .ok();
+    writeln!(
+        out,
+        "platform: {} {}",
+        std::env::consts::OS,
+        std::env::consts::ARCH
+    )
+    .ok();
+
+    section(&mut out, "topology");
+    writeln!(out, "bus: {}", device.bus_number()).ok();
+    writeln!(out, "address: {}", device.address()).ok();
+    match device.port_numbers() {
+        Ok(ports) => {
+            let ports: Vec<String> = ports.iter().map(|p| p.to_string()).collect();
+            writeln!(out, "ports: {}", ports.join(".")).ok();
+        }
+        Err(e) => unavailable(&mut out, "ports", e),
+    }
+    writeln!(out, "speed: {:?}", device.speed()).ok();
+
+    section(&mut out, "device descriptor");
+    let descriptor = match device.device_descriptor() {
+        Ok(descriptor) => descriptor,
+        Err(e) => {
+            unavailable(&mut out, "descriptor", e);
+            return out;
+        }
+    };
+    hex_dump(&mut out, &descriptor.to_bytes());
+
+    let handle = match device.open() {
+        Ok(handle) => Some(handle),
+        Err(e) => {
+            section(&mut out, "open");
+            unavailable(&mut out, "handle", e);
+            writeln!(
+                out,
+                "(configuration, BOS and string descriptors need an open handle)"
+            )
+            .ok();
+            None
+        }
+    };
+
+    section(&mut out, "profile");
+    match handle.as_ref() {
+        Some(handle) => writeln!(out, "{}", handle.profile_string()).ok(),
+        None => writeln!(out, "{}", device.quick_profile()).ok(),
+    };
+
+    if let Some(handle) = handle.as_ref() {
+        let mut buf = Vec::new();
+        for index in 0..descriptor.num_configurations() {
+            section(&mut out, &format!("configuration descriptor {}", index));
+            match handle.read_config_descriptor_raw(index, &mut buf, TIMEOUT) {
+                Ok(()) => hex_dump(&mut out, &buf),
+                Err(e) => unavailable(&mut out, "descriptor", e),
+            }
+        }
+
+        section(&mut out, "bos descriptor");
+        match handle.read_bos_descriptor_raw(&mut buf, TIMEOUT) {
+            Ok(()) => hex_dump(&mut out, &buf),
+            Err(e) => unavailable(&mut out, "descriptor", e),
+        }
+
+        match handle.read_strings_all_languages(&descriptor, TIMEOUT) {
+            Ok(languages) => {
+                for (lang_id, strings) in languages {
+                    section(&mut out, &format!("strings 0x{:04x}", lang_id));
+                    for (name, string) in [
+                        ("manufacturer", strings.manufacturer()),
+                        ("product", strings.product()),
+                        ("serial number", strings.serial_number()),
+                    ] {
+                        match string {
+                            Some(Ok(s)) => writeln!(out, "{}: {:?}", name, s).ok(),
+                            Some(Err(e)) => writeln!(out, "{}: unavailable ({})", name, e).ok(),
+                            None => None,
+                        };
+                    }
+                }
+            }
+            Err(e) => {
+                section(&mut out, "strings");
+                unavailable(&mut out, "languages", e);
+            }
+        }
+    }
+
+    section(&mut out, "drivers");
+    drivers(&mut out, device, handle.as_ref());
+
+    out
+}
+
+fn section(out: &mut String, name: &str) {
+    writeln!(out, "\n[{}]", name).ok();
+}
+
+fn unavailable(out: &mut String, what: &str, e: Error) {
+    writeln!(out, "{}: unavailable ({})", what, e).ok();
+}
+
+fn hex_dump(out: &mut String, data: &[u8]) {
+    for (i, line) in data.chunks(16).enumerate() {
+        let bytes: Vec<String> = line.iter().map(|b| format!("{:02x}", b)).collect();
+        writeln!(out, "{:04x}: {}", i * 16, bytes.join(" ")).ok();
+    }
+}
+
+/// Lists the drivers bound to the device and to the interfaces of its active configuration.
+fn drivers<T: UsbContext>(out: &mut String, device: &Device<T>, handle: Option<&DeviceHandle<T>>) {
+    let config = match device.active_config_descriptor() {
+        Ok(config) => config,
+        Err(e) => {
+            unavailable(out, "active configuration", e);
+            return;
+        }
+    };
+
+    #[cfg(target_os = "linux")]
+    match crate::authorization::sysfs_path(device) {
+        Ok(path) => {
+            writeln!(out, "sysfs: {}", path.display()).ok();
+            writeln!(out, "device: {}", sysfs_driver(&path)).ok();
+        }
+        Err(e) => unavailable(out, "sysfs", e),
+    }
+
+    for interface in config.interfaces() {
+        let number = interface.number();
+
+        #[cfg(target_os = "linux")]
+        if let Ok(path) = crate::authorization::sysfs_path(device) {
+            let name = path.file_name().unwrap_or_default().to_string_lossy();
+            let path = path.with_file_name(format!("{}:{}.{}", name, config.number(), number));
+            writeln!(out, "interface {}: {}", number, sysfs_driver(&path)).ok();
+        }
+
+        if let Some(handle) = handle {
+            match handle.kernel_driver_active(number) {
+                Ok(active) => {
+                    writeln!(out, "interface {} kernel driver active: {}", number, active)
+                }
+                Err(e) => writeln!(
+                    out,
+                    "interface {} kernel driver active: unavailable ({})",
+                    number, e
+                ),
+            }
+            .ok();
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn sysfs_driver(path: &Path) -> String {
+    match fs::read_link(path.join("driver")) {
+        Ok(driver) => driver
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default(),
+        Err(_) => "none".to_string(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn it_dumps_sixteen_bytes_per_line() {
+        let mut out = String::new();
+        hex_dump(&mut out, &(0..20).collect::<Vec<u8>>());
+
+        assert_eq!(
+            "0000: 00 01 02 03 04 05 06 07 08 09 0a 0b 0c 0d 0e 0f\n0010: 10 11 12 13\n",
+            out
+        );
+    }
+}
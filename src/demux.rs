@@ -0,0 +1,165 @@
+use std::{
+    collections::HashMap,
+    sync::mpsc::{self, Receiver, Sender},
+    time::Duration,
+};
+
+use libusb1_sys::constants::*;
+
+use crate::{
+    async_io::{AsyncGroup, Transfer, TransferStatus},
+    fields::TransferType,
+    Context, DeviceHandle, Error, Result, UsbContext,
+};
+
+/// Reads several IN endpoints of one device through a single event loop, delivering each
+/// endpoint's data to its own channel.
+///
+/// Devices often split their traffic across separate data, status and event endpoints. Rather
+/// than dedicating a thread to each endpoint, a demultiplexer keeps transfers queued on all of
+/// them in one [`AsyncGroup`](struct.AsyncGroup.html), and [`poll`](#method.poll) routes every
+/// completion to the receiver returned by [`add_endpoint`](#method.add_endpoint) for its
+/// endpoint. The receivers can be handed to other threads.
+///
+/// Data from one endpoint is always delivered in the order the device sent it, no matter how many
+/// transfers are queued on it. No ordering is implied between different endpoints.
+///
+/// Transfers that time out are resubmitted, after delivering any data they received. When an
+/// endpoint fails, the error is delivered on its channel and its transfers are not resubmitted,
+/// so the channel disconnects once they have all completed. Dropping a receiver likewise retires
+/// the endpoint's transfers as they complete.
+pub struct Demux<'d, T: UsbContext> {
+    group: AsyncGroup<'d, T>,
+    handle: &'d DeviceHandle<T>,
+    outputs: HashMap<u8, Sender<Result<Vec<u8>>>>,
+    in_flight: usize,
+}
+
+impl<'d, T: UsbContext> Demux<'d, T> {
+    /// Creates a demultiplexer for endpoints of `handle`.
+    pub fn new(context: &'d Context, handle: &'d DeviceHandle<T>) -> Demux<'d, T> {
+        Demux {
+            group: AsyncGroup::new(context),
+            handle,
+            outputs: HashMap::new(),
+            in_flight: 0,
+        }
+    }
+
+    /// Starts reading the bulk or interrupt IN `endpoint`, with one transfer per `transfer_size`
+    /// chunk of `buffer`.
+    ///
+    /// Returns the receiver its data is delivered to.
+    ///
+    /// ## Errors
+    ///
+    /// * `InvalidParam` if `endpoint` is not an IN endpoint, `transfer_type` is not bulk or
+    ///   interrupt, `transfer_size` is zero or `buffer` is smaller than `transfer_size`.
+    /// * `Busy` if the endpoint was already added.
+    /// * Any error returned when submitting the transfers.
+    pub fn add_endpoint(
+        &mut self,
+        endpoint: u8,
+        transfer_type: TransferType,
+        buffer: &'d mut [u8],
+        transfer_size: usize,
+        timeout: Duration,
+    ) -> Result<Receiver<Result<Vec<u8>>>> {
+        if endpoint & LIBUSB_ENDPOINT_DIR_MASK != LIBUSB_ENDPOINT_IN
+            || transfer_size == 0
+            || buffer.len() < transfer_size
+            || !matches!(transfer_type, TransferType::Bulk | TransferType::Interrupt)
+        {
+            return Err(Error::InvalidParam);
+        }
+        if self.outputs.contains_key(&endpoint) {
+            return Err(Error::Busy);
+        }
+
+        let (sender, receiver) = mpsc::channel();
+        self.outputs.insert(endpoint, sender);
+
+        for chunk in buffer.chunks_exact_mut(transfer_size) {
+            let transfer = match transfer_type {
+                TransferType::Interrupt => {
+                    Transfer::interrupt(self.handle, endpoint, chunk, timeout)
+                }
+                _ => Transfer::bulk(self.handle, endpoint, chunk, timeout),
+            };
+
+            self.group.submit(transfer)?;
+            self.in_flight += 1;
+        }
+
+        Ok(receiver)
+    }
+
+    /// Returns the number of transfers currently queued across all endpoints.
+    pub fn in_flight(&self) -> usize {
+        self.in_flight
+    }
+
+    /// Waits for the next completion, delivers it to its endpoint's channel and resubmits the
+    /// transfer.
+    ///
+    /// Returns the address of the endpoint the completion was for.
+    ///
+    /// ## Errors
+    ///
+    /// * `NotFound` if no transfers are left in flight.
+    /// * Any error returned when handling events or resubmitting the transfer.
+    pub fn poll(&mut self) -> Result<u8> {
+        let mut transfer = self.group.wait_any()?;
+        self.in_flight -= 1;
+
+        let endpoint = transfer.endpoint();
+        let status = transfer.status();
+
+        let output = match status {
+            TransferStatus::Success => Some(Ok(transfer.actual().to_vec())),
+            TransferStatus::Timeout if !transfer.actual().is_empty() => {
+                Some(Ok(transfer.actual().to_vec()))
+            }
+            TransferStatus::Timeout => None,
+            TransferStatus::Stall => Some(Err(Error::Pipe)),
+            TransferStatus::NoDevice => Some(Err(Error::NoDevice)),
+            TransferStatus::Overflow => Some(Err(Error::Overflow)),
+            TransferStatus::Cancelled => Some(Err(Error::Interrupted)),
+            TransferStatus::Error | TransferStatus::Unknown => Some(Err(Error::Io)),
+        };
+        let failed = matches!(output, Some(Err(_)));
+
+        let connected = match (self.outputs.get(&endpoint), output) {
+            (Some(sender), Some(data)) => sender.send(data).is_ok(),
+            (Some(_), None) => true,
+            (None, _) => false,
+        };
+
+        if failed || !connected {
+            // later completions on this endpoint are discarded, which keeps the order intact
+            self.outputs.remove(&endpoint);
+        } else {
+            self.group.submit(transfer)?;
+            self.in_flight += 1;
+        }
+
+        Ok(endpoint)
+    }
+
+    /// Cancels all queued transfers and disconnects all channels.
+    ///
+    /// This is done automatically when the demultiplexer is dropped.
+    pub fn cancel(&mut self) -> Result<()> {
+        self.outputs.clear();
+        // the transfers completed even if the cancellation failed
+        let res = self.group.cancel_all();
+        self.in_flight = 0;
+        res
+    }
+}
+
+impl<'d, T: UsbContext> Drop for Demux<'d, T> {
+    fn drop(&mut self) {
+        self.cancel().ok();
+    }
+}
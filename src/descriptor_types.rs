@@ -0,0 +1,116 @@
+//! Descriptor types for [`DeviceHandle::get_descriptor`].
+//!
+//! Each type names a descriptor that can be requested with a standard `GET_DESCRIPTOR` request,
+//! along with the shortest valid descriptor of that type, so the request's `wValue` is always
+//! built the same way and the reply is checked before it is used:
+//!
+//! ```no_run
+//! use rusb::descriptor_types;
+//! use std::time::Duration;
+//!
+//! # let handle = rusb::open_device_with_vid_pid(0x1234, 0x5678).unwrap();
+//! let mut buf = [0; 255];
+//! let timeout = Duration::from_secs(1);
+//! let len = handle.get_descriptor::<descriptor_types::String>(1, 0x0409, &mut buf, timeout)?;
+//! # Ok::<(), rusb::Error>(())
+//! ```
+//!
+//! [`DeviceHandle::get_descriptor`]: ../struct.DeviceHandle.html#method.get_descriptor
+
+use libusb1_sys::constants::*;
+
+use crate::error::Error;
+
+/// A descriptor type that can be requested with
+/// [`DeviceHandle::get_descriptor`](../struct.DeviceHandle.html#method.get_descriptor).
+pub trait DescriptorKind {
+    /// The `bDescriptorType` of the descriptor, sent in the high byte of `wValue`.
+    const TYPE: u8;
+
+    /// The smallest valid `bLength` of the descriptor.
+    const MIN_LENGTH: u8;
+}
+
+macro_rules! descriptor_types {
+    ($($(#[$doc:meta])* $name:ident => ($descriptor_type:expr, $min_length:expr),)*) => {
+        $(
+            $(#[$doc])*
+            #[derive(Debug, Copy, Clone)]
+            pub enum $name {}
+
+            impl DescriptorKind for $name {
+                const TYPE: u8 = $descriptor_type;
+                const MIN_LENGTH: u8 = $min_length;
+            }
+        )*
+    };
+}
+
+descriptor_types! {
+    /// The device descriptor. Its index is always zero.
+    Device => (LIBUSB_DT_DEVICE, 18),
+    /// A configuration descriptor, followed by its interface and endpoint descriptors if the
+    /// buffer is large enough. The index is that of the configuration, not its value.
+    Config => (LIBUSB_DT_CONFIG, 9),
+    /// A string descriptor. Index zero holds the language IDs supported by the device.
+    String => (LIBUSB_DT_STRING, 2),
+    /// The Binary Object Store (BOS) descriptor, followed by its device capabilities if the
+    /// buffer is large enough. Its index is always zero.
+    Bos => (LIBUSB_DT_BOS, 5),
+}
+
+/// A class- or vendor-specific descriptor of type `TYPE`, only checked for a valid header.
+#[derive(Debug, Copy, Clone)]
+pub enum Class<const TYPE: u8> {}
+
+impl<const TYPE: u8> DescriptorKind for Class<TYPE> {
+    const TYPE: u8 = TYPE;
+    const MIN_LENGTH: u8 = 2;
+}
+
+/// Checks the header of a descriptor of kind `K` read into a buffer of `capacity` bytes.
+///
+/// The descriptor may be cut short by the buffer, but not by the device.
+pub(crate) fn check<K: DescriptorKind>(data: &[u8], capacity: usize) -> crate::Result<()> {
+    match *data {
+        [length, descriptor_type, ..]
+            if descriptor_type == K::TYPE
+                && length >= K::MIN_LENGTH
+                && data.len() >= usize::from(length).min(capacity) =>
+        {
+            Ok(())
+        }
+        _ => Err(Error::Other),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn it_accepts_a_complete_descriptor() {
+        assert_eq!(Ok(()), check::<String>(&[4, 3, 0x09, 0x04], 255));
+        assert_eq!(Ok(()), check::<Class<0x21>>(&[2, 0x21], 255));
+    }
+
+    #[test]
+    fn it_accepts_a_descriptor_cut_short_by_the_buffer() {
+        assert_eq!(Ok(()), check::<Config>(&[9, 2, 32, 0], 4));
+    }
+
+    #[test]
+    fn it_rejects_the_wrong_type() {
+        assert_eq!(Err(Error::Other), check::<Device>(&[4, 3, 0x09, 0x04], 255));
+    }
+
+    #[test]
+    fn it_rejects_a_truncated_or_short_descriptor() {
+        assert_eq!(Err(Error::Other), check::<String>(&[4, 3, 0x09], 255));
+        assert_eq!(
+            Err(Error::Other),
+            check::<Device>(&[9, 1, 0, 2, 0, 0, 0, 64, 0], 255)
+        );
+        assert_eq!(Err(Error::Other), check::<String>(&[4], 255));
+    }
+}
@@ -0,0 +1,691 @@
+use libusb1_sys::constants::*;
+
+use crate::{
+    config_descriptor, endpoint_descriptor,
+    error::Error,
+    fields::{DescriptorType, Direction, Speed, SyncType, TransferType, UsageType},
+};
+
+pub(crate) const CONFIG_DESCRIPTOR_SIZE: usize = 9;
+const INTERFACE_DESCRIPTOR_SIZE: usize = 9;
+const ENDPOINT_DESCRIPTOR_SIZE: usize = 7;
+
+/// How descriptor parsers cope with malformed data.
+///
+/// Devices in the wild ship descriptors with wrong lengths. Parsing never panics or reads out of
+/// bounds in either mode; the mode only decides whether such descriptors are rejected or salvaged.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum ParseMode {
+    /// Reject any descriptor that doesn't follow the specification with `Error::Other`.
+    Strict,
+
+    /// Keep whatever can be decoded safely:
+    ///
+    /// * a configuration shorter than its `wTotalLength` is parsed up to the end of the data,
+    /// * descriptors are parsed up to the first one whose length is invalid or runs past the end,
+    /// * interface and endpoint descriptors too short for their type are skipped like unknown
+    ///   class-specific descriptors,
+    /// * string descriptors whose `bLength` doesn't match the data are decoded from the data
+    ///   received, and invalid UTF-16 is replaced with U+FFFD.
+    Lenient,
+}
+
+/// A configuration descriptor parsed on demand from its raw bytes.
+///
+/// [`ConfigDescriptor`](struct.ConfigDescriptor.html) asks libusb to parse the whole
+/// configuration into heap allocated structures on every call. A view instead borrows the raw
+/// descriptor, e.g. as read by
+/// [`DeviceHandle::read_config_descriptor_raw`](struct.DeviceHandle.html#method.read_config_descriptor_raw),
+/// and decodes fields only when they are accessed, without allocating. Applications that scan
+/// many devices repeatedly can keep one buffer per device and re-read the views for free.
+///
+/// The descriptor is validated once by [`parse`](#method.parse), so no accessor can fail or read
+/// out of bounds later.
+#[derive(Debug, Copy, Clone)]
+pub struct ConfigDescriptorView<'a> {
+    raw: &'a [u8],
+}
+
+impl<'a> ConfigDescriptorView<'a> {
+    /// Parses a configuration descriptor and the interface, endpoint and class-specific
+    /// descriptors following it.
+    ///
+    /// Bytes beyond `wTotalLength` are ignored.
+    ///
+    /// ## Errors
+    ///
+    /// * `Other` if the data is not a configuration descriptor, is shorter than its
+    ///   `wTotalLength`, or contains a descriptor whose length runs past the end.
+    pub fn parse(raw: &'a [u8]) -> crate::Result<ConfigDescriptorView<'a>> {
+        ConfigDescriptorView::parse_with(raw, ParseMode::Strict)
+    }
+
+    /// Parses a configuration descriptor, coping with malformed data as set by `mode`.
+    ///
+    /// ## Errors
+    ///
+    /// * `Other` if the data doesn't start with a configuration descriptor, or, in strict mode,
+    ///   for the same reasons as [`parse`](#method.parse).
+    pub fn parse_with(raw: &'a [u8], mode: ParseMode) -> crate::Result<ConfigDescriptorView<'a>> {
+        let strict = mode == ParseMode::Strict;
+
+        if raw.len() < CONFIG_DESCRIPTOR_SIZE
+            || (raw[0] as usize) < CONFIG_DESCRIPTOR_SIZE
+            || (raw[0] as usize) > raw.len()
+            || raw[1] != LIBUSB_DT_CONFIG
+        {
+            return Err(Error::Other);
+        }
+
+        let mut total = u16::from_le_bytes([raw[2], raw[3]]) as usize;
+        if total < raw[0] as usize || raw.len() < total {
+            if strict {
+                return Err(Error::Other);
+            }
+            total = raw.len();
+        }
+        let mut raw = &raw[..total];
+
+        let mut offset = 0;
+        while offset < raw.len() {
+            let len = raw[offset] as usize;
+            if len < 2 || offset + len > raw.len() {
+                if strict {
+                    return Err(Error::Other);
+                }
+                raw = &raw[..offset];
+                break;
+            }
+            if strict && len < minimum_length(raw[offset + 1]) {
+                return Err(Error::Other);
+            }
+            offset += len;
+        }
+
+        Ok(ConfigDescriptorView { raw })
+    }
+
+    /// Returns the raw descriptor, `wTotalLength` bytes long.
+    pub fn as_bytes(&self) -> &'a [u8] {
+        self.raw
+    }
+
+    /// Returns the configuration number.
+    pub fn number(&self) -> u8 {
+        self.raw[5]
+    }
+
+    /// Returns the device's maximum power consumption (in milliamps) in this configuration.
+    pub fn max_power(&self) -> u16 {
+        u16::from(self.raw[8]) * 2
+    }
+
+    /// Returns the device's maximum power consumption (in milliamps) in this configuration, when
+    /// operating at `speed`. See
+    /// [`ConfigDescriptor::max_power_ma`](struct.ConfigDescriptor.html#method.max_power_ma).
+    pub fn max_power_ma(&self, speed: Speed) -> u16 {
+        config_descriptor::max_power_ma(self.raw[8], speed)
+    }
+
+    /// Indicates if the device is self-powered in this configuration.
+    pub fn self_powered(&self) -> bool {
+        self.raw[7] & 0x40 != 0
+    }
+
+    /// Indicates if the device has remote wakeup capability in this configuration.
+    pub fn remote_wakeup(&self) -> bool {
+        self.raw[7] & 0x20 != 0
+    }
+
+    /// Returns the index of the string descriptor that describes the configuration.
+    pub fn description_string_index(&self) -> Option<u8> {
+        match self.raw[6] {
+            0 => None,
+            n => Some(n),
+        }
+    }
+
+    /// Returns the number of interfaces for this configuration.
+    pub fn num_interfaces(&self) -> u8 {
+        self.raw[4]
+    }
+
+    /// Returns an iterator over the descriptors of every alternate setting of every interface.
+    pub fn interface_descriptors(&self) -> InterfaceDescriptorViews<'a> {
+        InterfaceDescriptorViews {
+            records: Records::new(&self.raw[self.raw[0] as usize..]),
+        }
+    }
+
+    /// Returns the class-specific descriptors between the configuration descriptor and the first
+    /// interface descriptor.
+    pub fn extra(&self) -> Option<&'a [u8]> {
+        extra(&self.raw[self.raw[0] as usize..], &[LIBUSB_DT_INTERFACE])
+    }
+}
+
+/// Iterator over the interface descriptors of a configuration view.
+#[derive(Debug, Clone)]
+pub struct InterfaceDescriptorViews<'a> {
+    records: Records<'a>,
+}
+
+impl<'a> Iterator for InterfaceDescriptorViews<'a> {
+    type Item = InterfaceDescriptorView<'a>;
+
+    fn next(&mut self) -> Option<InterfaceDescriptorView<'a>> {
+        for (descriptor_type, record, rest) in &mut self.records {
+            if descriptor_type == LIBUSB_DT_INTERFACE {
+                return Some(InterfaceDescriptorView { raw: record, rest });
+            }
+        }
+
+        None
+    }
+}
+
+/// An interface descriptor borrowed from a [`ConfigDescriptorView`](struct.ConfigDescriptorView.html).
+#[derive(Debug, Copy, Clone)]
+pub struct InterfaceDescriptorView<'a> {
+    raw: &'a [u8],
+    rest: &'a [u8],
+}
+
+impl<'a> InterfaceDescriptorView<'a> {
+    /// Returns the interface's number.
+    pub fn interface_number(&self) -> u8 {
+        self.raw[2]
+    }
+
+    /// Returns the alternate setting number.
+    pub fn setting_number(&self) -> u8 {
+        self.raw[3]
+    }
+
+    /// Returns the interface's class code.
+    pub fn class_code(&self) -> u8 {
+        self.raw[5]
+    }
+
+    /// Returns the interface's sub class code.
+    pub fn sub_class_code(&self) -> u8 {
+        self.raw[6]
+    }
+
+    /// Returns the interface's protocol code.
+    pub fn protocol_code(&self) -> u8 {
+        self.raw[7]
+    }
+
+    /// Returns the index of the string descriptor that describes the interface.
+    pub fn description_string_index(&self) -> Option<u8> {
+        match self.raw[8] {
+            0 => None,
+            n => Some(n),
+        }
+    }
+
+    /// Returns the number of endpoints belonging to this interface.
+    pub fn num_endpoints(&self) -> u8 {
+        self.raw[4]
+    }
+
+    /// Returns an iterator over the interface's endpoint descriptors.
+    pub fn endpoint_descriptors(&self) -> EndpointDescriptorViews<'a> {
+        EndpointDescriptorViews {
+            records: Records::new(self.rest),
+        }
+    }
+
+    /// Returns the class-specific descriptors between the interface descriptor and its first
+    /// endpoint descriptor.
+    pub fn extra(&self) -> Option<&'a [u8]> {
+        extra(self.rest, &[LIBUSB_DT_INTERFACE, LIBUSB_DT_ENDPOINT])
+    }
+}
+
+/// Iterator over the endpoint descriptors of an interface view.
+#[derive(Debug, Clone)]
+pub struct EndpointDescriptorViews<'a> {
+    records: Records<'a>,
+}
+
+impl<'a> Iterator for EndpointDescriptorViews<'a> {
+    type Item = EndpointDescriptorView<'a>;
+
+    fn next(&mut self) -> Option<EndpointDescriptorView<'a>> {
+        for (descriptor_type, record, rest) in &mut self.records {
+            match descriptor_type {
+                LIBUSB_DT_ENDPOINT => return Some(EndpointDescriptorView { raw: record, rest }),
+                LIBUSB_DT_INTERFACE => break,
+                _ => {}
+            }
+        }
+
+        // the next interface starts here, so don't look any further
+        self.records = Records::new(&[]);
+        None
+    }
+}
+
+/// An endpoint descriptor borrowed from a [`ConfigDescriptorView`](struct.ConfigDescriptorView.html).
+#[derive(Debug, Copy, Clone)]
+pub struct EndpointDescriptorView<'a> {
+    raw: &'a [u8],
+    rest: &'a [u8],
+}
+
+impl<'a> EndpointDescriptorView<'a> {
+    /// Returns the endpoint's address.
+    pub fn address(&self) -> u8 {
+        self.raw[2]
+    }
+
+    /// Returns the endpoint number.
+    pub fn number(&self) -> u8 {
+        self.raw[2] & 0x07
+    }
+
+    /// Returns the endpoint's direction.
+    pub fn direction(&self) -> Direction {
+        endpoint_descriptor::direction(self.raw[2])
+    }
+
+    /// Returns the endpoint's transfer type.
+    pub fn transfer_type(&self) -> TransferType {
+        endpoint_descriptor::transfer_type(self.raw[3])
+    }
+
+    /// Returns the endpoint's synchronisation mode.
+    ///
+    /// The return value of this method is only valid for isochronous endpoints.
+    pub fn sync_type(&self) -> SyncType {
+        endpoint_descriptor::sync_type(self.raw[3])
+    }
+
+    /// Returns the endpoint's usage type.
+    ///
+    /// The return value of this method is only valid for isochronous endpoints.
+    pub fn usage_type(&self) -> UsageType {
+        endpoint_descriptor::usage_type(self.raw[3])
+    }
+
+    /// Returns the endpoint's maximum packet size.
+    pub fn max_packet_size(&self) -> u16 {
+        u16::from_le_bytes([self.raw[4], self.raw[5]])
+    }
+
+    /// Returns the endpoint's polling interval.
+    pub fn interval(&self) -> u8 {
+        self.raw[6]
+    }
+
+    /// Returns the number of packets the endpoint moves per burst, minus one, as declared by its
+    /// SuperSpeed endpoint companion descriptor.
+    pub fn max_burst(&self) -> Option<u8> {
+        endpoint_descriptor::max_burst(self.extra())
+    }
+
+    /// Returns the number of bytes the endpoint moves per burst, see
+    /// [`EndpointDescriptor::burst_size`](struct.EndpointDescriptor.html#method.burst_size).
+    pub fn burst_size(&self) -> usize {
+        endpoint_descriptor::burst_size(self.max_packet_size(), self.max_burst())
+    }
+
+    /// Returns the class-specific descriptors following the endpoint descriptor, e.g. a
+    /// SuperSpeed endpoint companion descriptor.
+    pub fn extra(&self) -> Option<&'a [u8]> {
+        extra(self.rest, &[LIBUSB_DT_INTERFACE, LIBUSB_DT_ENDPOINT])
+    }
+}
+
+/// Iterator over the descriptors in the unvalidated 'extra' bytes of a descriptor, yielding each
+/// one's type and its bytes, header included.
+///
+/// Iteration stops at the first descriptor whose length is invalid or runs past the end.
+///
+/// ```
+/// use rusb::{DescriptorType, ExtraDescriptors};
+///
+/// // a CDC header functional descriptor, then a SuperSpeed endpoint companion
+/// let extra = [0x05, 0x24, 0x00, 0x10, 0x01, 0x06, 0x30, 0x0F, 0x00, 0x00, 0x00];
+///
+/// let companion = ExtraDescriptors::new(&extra)
+///     .find(|(descriptor_type, _)| *descriptor_type == DescriptorType::SuperSpeedEndpointCompanion);
+/// assert_eq!(Some(0x0F), companion.map(|(_, raw)| raw[2]));
+/// ```
+#[derive(Debug, Clone)]
+pub struct ExtraDescriptors<'a> {
+    raw: &'a [u8],
+}
+
+impl<'a> ExtraDescriptors<'a> {
+    /// Iterates over the descriptors in `raw`, e.g. the bytes returned by
+    /// [`EndpointDescriptor::extra`](struct.EndpointDescriptor.html#method.extra).
+    pub fn new(raw: &'a [u8]) -> ExtraDescriptors<'a> {
+        ExtraDescriptors { raw }
+    }
+}
+
+impl<'a> Iterator for ExtraDescriptors<'a> {
+    type Item = (DescriptorType, &'a [u8]);
+
+    fn next(&mut self) -> Option<(DescriptorType, &'a [u8])> {
+        let len = *self.raw.first()? as usize;
+        if len < 2 || len > self.raw.len() {
+            self.raw = &[];
+            return None;
+        }
+
+        let (record, rest) = self.raw.split_at(len);
+        self.raw = rest;
+        Some((DescriptorType::from(record[1]), record))
+    }
+}
+
+/// Iterator over the descriptors in validated raw data, yielding each descriptor's type, its
+/// bytes and the bytes following it.
+#[derive(Debug, Clone)]
+struct Records<'a> {
+    raw: &'a [u8],
+}
+
+impl<'a> Records<'a> {
+    fn new(raw: &'a [u8]) -> Records<'a> {
+        Records { raw }
+    }
+}
+
+impl<'a> Iterator for Records<'a> {
+    type Item = (u8, &'a [u8], &'a [u8]);
+
+    fn next(&mut self) -> Option<(u8, &'a [u8], &'a [u8])> {
+        if self.raw.len() < 2 {
+            return None;
+        }
+
+        let (record, rest) = self.raw.split_at(self.raw[0] as usize);
+        self.raw = rest;
+
+        // only lenient parsing lets these through; present them as an unknown type
+        let descriptor_type = if record.len() < minimum_length(record[1]) {
+            0
+        } else {
+            record[1]
+        };
+        Some((descriptor_type, record, rest))
+    }
+}
+
+/// Returns the smallest valid length of a descriptor of the given type.
+fn minimum_length(descriptor_type: u8) -> usize {
+    match descriptor_type {
+        LIBUSB_DT_CONFIG => CONFIG_DESCRIPTOR_SIZE,
+        LIBUSB_DT_INTERFACE => INTERFACE_DESCRIPTOR_SIZE,
+        LIBUSB_DT_ENDPOINT => ENDPOINT_DESCRIPTOR_SIZE,
+        _ => 2,
+    }
+}
+
+/// Decodes the UTF-16LE payload of a string descriptor received in `buf`.
+pub(crate) fn parse_string(buf: &[u8], mode: ParseMode) -> crate::Result<String> {
+    let utf16: Vec<u16> = string_payload(buf, mode)?
+        .chunks_exact(2)
+        .map(|chunk| u16::from_le_bytes([chunk[0], chunk[1]]))
+        .collect();
+
+    match mode {
+        ParseMode::Strict => String::from_utf16(&utf16).map_err(|_| Error::Other),
+        ParseMode::Lenient => Ok(String::from_utf16_lossy(&utf16)),
+    }
+}
+
+/// Decodes the `LANGID`s of string descriptor zero received in `buf`.
+pub(crate) fn parse_lang_ids(buf: &[u8], mode: ParseMode) -> crate::Result<Vec<u16>> {
+    Ok(string_payload(buf, mode)?
+        .chunks_exact(2)
+        .map(|chunk| u16::from_le_bytes([chunk[0], chunk[1]]))
+        .collect())
+}
+
+/// Returns the bytes following the header of a string descriptor.
+fn string_payload(buf: &[u8], mode: ParseMode) -> crate::Result<&[u8]> {
+    let len = buf.len();
+    if len >= 2 && buf[0] as usize == len && len & 0x01 == 0 {
+        return Ok(&buf[2..]);
+    }
+
+    match mode {
+        // Consider making this `Error::BadDescriptor` on next breaking change.
+        ParseMode::Strict => Err(Error::Other),
+        ParseMode::Lenient if len < 2 => Err(Error::Other),
+        ParseMode::Lenient => {
+            let end = (buf[0] as usize).clamp(2, len);
+            Ok(&buf[2..end])
+        }
+    }
+}
+
+/// Returns the leading descriptors of `raw` up to the first one of the types in `until`.
+fn extra<'a>(raw: &'a [u8], until: &[u8]) -> Option<&'a [u8]> {
+    let len = Records::new(raw)
+        .take_while(|(descriptor_type, _, _)| !until.contains(descriptor_type))
+        .map(|(_, record, _)| record.len())
+        .sum();
+
+    match len {
+        0 => None,
+        len => Some(&raw[..len]),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[rustfmt::skip]
+    const CONFIG: &[u8] = &[
+        // configuration 1, 2 interfaces, self-powered, 100mA
+        0x09, 0x02, 0x3D, 0x00, 0x02, 0x01, 0x04, 0xC0, 0x32,
+        // interface association
+        0x08, 0x0B, 0x00, 0x02, 0x02, 0x02, 0x01, 0x00,
+        // interface 0, CDC control, 1 endpoint
+        0x09, 0x04, 0x00, 0x00, 0x01, 0x02, 0x02, 0x01, 0x00,
+        // CDC header functional descriptor
+        0x05, 0x24, 0x00, 0x10, 0x01,
+        // interrupt IN endpoint 0x83
+        0x07, 0x05, 0x83, 0x03, 0x08, 0x00, 0x10,
+        // interface 1, CDC data, 2 endpoints
+        0x09, 0x04, 0x01, 0x00, 0x02, 0x0A, 0x00, 0x00, 0x00,
+        // bulk IN endpoint 0x81
+        0x07, 0x05, 0x81, 0x02, 0x00, 0x02, 0x00,
+        // bulk OUT endpoint 0x02
+        0x07, 0x05, 0x02, 0x02, 0x00, 0x02, 0x00,
+    ];
+
+    #[test]
+    fn it_iterates_extra_descriptors() {
+        let types: Vec<DescriptorType> = ExtraDescriptors::new(&CONFIG[..26])
+            .map(|(descriptor_type, _)| descriptor_type)
+            .collect();
+
+        assert_eq!(
+            vec![
+                DescriptorType::Config,
+                DescriptorType::InterfaceAssociation,
+                DescriptorType::Interface,
+            ],
+            types
+        );
+    }
+
+    #[test]
+    fn it_stops_iterating_extra_descriptors_at_invalid_lengths() {
+        assert_eq!(1, ExtraDescriptors::new(&[2, 0x24, 9, 0x24]).count());
+        assert_eq!(0, ExtraDescriptors::new(&[0, 0x24, 2, 0x24]).count());
+    }
+
+    #[test]
+    fn it_parses_configuration_fields() {
+        let config = ConfigDescriptorView::parse(CONFIG).unwrap();
+
+        assert_eq!(1, config.number());
+        assert_eq!(2, config.num_interfaces());
+        assert_eq!(100, config.max_power());
+        assert_eq!(400, config.max_power_ma(Speed::Super));
+        assert!(config.self_powered());
+        assert!(!config.remote_wakeup());
+        assert_eq!(Some(4), config.description_string_index());
+        assert_eq!(Some(&CONFIG[9..17]), config.extra());
+    }
+
+    #[test]
+    fn it_iterates_interfaces_and_endpoints() {
+        let config = ConfigDescriptorView::parse(CONFIG).unwrap();
+        let interfaces: Vec<_> = config.interface_descriptors().collect();
+
+        assert_eq!(2, interfaces.len());
+        assert_eq!(0x02, interfaces[0].class_code());
+        assert_eq!(Some(&CONFIG[26..31]), interfaces[0].extra());
+
+        let endpoints: Vec<_> = interfaces[0].endpoint_descriptors().collect();
+        assert_eq!(1, endpoints.len());
+        assert_eq!(0x83, endpoints[0].address());
+        assert_eq!(TransferType::Interrupt, endpoints[0].transfer_type());
+        assert_eq!(16, endpoints[0].interval());
+
+        let endpoints: Vec<_> = interfaces[1].endpoint_descriptors().collect();
+        assert_eq!(2, endpoints.len());
+        assert_eq!(Direction::In, endpoints[0].direction());
+        assert_eq!(Direction::Out, endpoints[1].direction());
+        assert_eq!(512, endpoints[1].max_packet_size());
+        assert_eq!(None, endpoints[1].extra());
+    }
+
+    #[test]
+    fn it_ignores_trailing_bytes() {
+        let mut raw = CONFIG.to_vec();
+        raw.extend_from_slice(&[0xFF, 0xFF]);
+
+        let config = ConfigDescriptorView::parse(&raw).unwrap();
+        assert_eq!(CONFIG, config.as_bytes());
+    }
+
+    #[test]
+    fn it_rejects_malformed_descriptors() {
+        assert!(ConfigDescriptorView::parse(&CONFIG[..20]).is_err());
+        assert!(ConfigDescriptorView::parse(&CONFIG[9..]).is_err());
+
+        let mut raw = CONFIG.to_vec();
+        raw[9] = 0x40;
+        assert!(ConfigDescriptorView::parse(&raw).is_err());
+
+        let mut raw = CONFIG.to_vec();
+        raw[9] = 0x00;
+        assert!(ConfigDescriptorView::parse(&raw).is_err());
+    }
+
+    #[test]
+    fn it_salvages_truncated_configurations_leniently() {
+        let config = ConfigDescriptorView::parse_with(&CONFIG[..58], ParseMode::Lenient).unwrap();
+
+        // the second bulk endpoint is cut short and dropped
+        assert_eq!(&CONFIG[..54], config.as_bytes());
+        let interfaces: Vec<_> = config.interface_descriptors().collect();
+        assert_eq!(2, interfaces.len());
+        assert_eq!(1, interfaces[1].endpoint_descriptors().count());
+    }
+
+    #[test]
+    fn it_skips_short_endpoints_leniently() {
+        let mut raw = CONFIG.to_vec();
+        // shrink the interrupt endpoint to 5 bytes, padded with an unknown 2-byte descriptor
+        raw[31] = 0x05;
+        raw[36] = 0x02;
+        raw[37] = 0xFF;
+
+        assert!(ConfigDescriptorView::parse(&raw).is_err());
+        let config = ConfigDescriptorView::parse_with(&raw, ParseMode::Lenient).unwrap();
+        let interface = config.interface_descriptors().next().unwrap();
+        assert_eq!(0, interface.endpoint_descriptors().count());
+    }
+
+    #[test]
+    fn it_parses_string_descriptors() {
+        let buf = [0x08, 0x03, b'a', 0, b'b', 0, b'c', 0];
+        assert_eq!(Ok("abc".to_string()), parse_string(&buf, ParseMode::Strict));
+
+        // bLength covers only the first character
+        let buf = [0x04, 0x03, b'a', 0, b'b', 0];
+        assert!(parse_string(&buf, ParseMode::Strict).is_err());
+        assert_eq!(Ok("a".to_string()), parse_string(&buf, ParseMode::Lenient));
+
+        // odd length and an unpaired surrogate
+        let buf = [0x07, 0x03, 0x00, 0xD8, b'a', 0, 0x00];
+        assert!(parse_string(&buf, ParseMode::Strict).is_err());
+        assert_eq!(
+            Ok("\u{FFFD}a".to_string()),
+            parse_string(&buf, ParseMode::Lenient)
+        );
+    }
+
+    #[test]
+    fn it_parses_lang_ids() {
+        let buf = [0x06, 0x03, 0x09, 0x04, 0x07, 0x04];
+        assert_eq!(
+            Ok(vec![0x0409, 0x0407]),
+            parse_lang_ids(&buf, ParseMode::Strict)
+        );
+        assert!(parse_lang_ids(&[0x03], ParseMode::Lenient).is_err());
+    }
+
+    /// Walks every accessor of a view, which must never panic.
+    fn exercise(config: ConfigDescriptorView) {
+        let _ = (config.number(), config.max_power(), config.extra());
+        for interface in config.interface_descriptors() {
+            let _ = (interface.class_code(), interface.description_string_index());
+            let _ = interface.extra();
+            for endpoint in interface.endpoint_descriptors() {
+                let _ = (
+                    endpoint.address(),
+                    endpoint.max_packet_size(),
+                    endpoint.interval(),
+                );
+                let _ = (
+                    endpoint.transfer_type(),
+                    endpoint.usage_type(),
+                    endpoint.extra(),
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn it_never_panics_on_corrupted_data() {
+        // a fixed xorshift sequence makes the corpus reproducible
+        let mut state = 0x2545_F491_4F6C_DD1Du64;
+        let mut next = move || {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state
+        };
+
+        for _ in 0..20_000 {
+            let mut raw = CONFIG.to_vec();
+            for _ in 0..(next() % 4 + 1) {
+                let at = next() as usize % raw.len();
+                raw[at] = next() as u8;
+            }
+            raw.truncate(next() as usize % (raw.len() + 1));
+
+            for mode in [ParseMode::Strict, ParseMode::Lenient] {
+                if let Ok(config) = ConfigDescriptorView::parse_with(&raw, mode) {
+                    exercise(config);
+                }
+                let _ = parse_string(&raw, mode);
+                let _ = parse_lang_ids(&raw, mode);
+            }
+        }
+    }
+}
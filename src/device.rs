@@ -1,21 +1,36 @@
 use std::{
     fmt::{self, Debug},
+    hash::{Hash, Hasher},
     mem,
+    path::Path,
     ptr::NonNull,
 };
 
 use libusb1_sys::*;
 
 use crate::{
-    config_descriptor::{self, ConfigDescriptor},
+    annotations::Annotations,
+    config_descriptor::{self, ConfigDescriptor, PowerDraw},
+    debug_bundle,
     device_descriptor::{self, DeviceDescriptor},
     device_handle::{self, DeviceHandle},
+    device_policy,
+    error::{self, Error},
+    event_log::{self, EventKind, Record},
     fields::{self, Speed},
+    managed_context::DeviceId,
+    open_options::{OpenLock, OpenOptions},
+    profile_string::{self, Serial},
     UsbContext,
 };
 
 /// A reference to a USB device.
-#[derive(Eq, PartialEq)]
+///
+/// Devices compare equal, and hash the same, when they sit at the same address on the same bus,
+/// even if they were listed from different contexts, so they can be used as map keys. An address
+/// only identifies a device while it stays attached: once it is unplugged, the address can be
+/// given to another device. Use [`same_physical_device`](#method.same_physical_device) to
+/// recognize a device re-enumerated on the same port.
 pub struct Device<T: UsbContext> {
     context: T,
     device: NonNull<libusb_device>,
@@ -30,6 +45,20 @@ impl<T: UsbContext> Drop for Device<T> {
     }
 }
 
+impl<T: UsbContext> PartialEq for Device<T> {
+    fn eq(&self, other: &Self) -> bool {
+        (self.bus_number(), self.address()) == (other.bus_number(), other.address())
+    }
+}
+
+impl<T: UsbContext> Eq for Device<T> {}
+
+impl<T: UsbContext> Hash for Device<T> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        (self.bus_number(), self.address()).hash(state);
+    }
+}
+
 unsafe impl<T: UsbContext> Send for Device<T> {}
 unsafe impl<T: UsbContext> Sync for Device<T> {}
 
@@ -58,6 +87,12 @@ impl<T: UsbContext> Device<T> {
         self.device.as_ptr()
     }
 
+    /// Returns the user data attached to the device, shared by every `Device` and `DeviceHandle`
+    /// for it. See [`Annotations`](struct.Annotations.html).
+    pub fn annotations(&self) -> Annotations<'_> {
+        unsafe { Annotations::new(self.context.as_raw(), self.device.as_ptr()) }
+    }
+
     /// Reads the device descriptor.
     pub fn device_descriptor(&self) -> crate::Result<DeviceDescriptor> {
         let mut descriptor = mem::MaybeUninit::<libusb_device_descriptor>::uninit();
@@ -108,24 +143,215 @@ impl<T: UsbContext> Device<T> {
         unsafe { libusb_get_device_address(self.device.as_ptr()) }
     }
 
+    /// Summarizes the power requirements of the device in its active configuration, with
+    /// `bMaxPower` converted to milliamps for the speed the device operates at.
+    pub fn power_draw(&self) -> crate::Result<PowerDraw> {
+        Ok(PowerDraw::new(
+            &self.active_config_descriptor()?,
+            self.speed(),
+        ))
+    }
+
     /// Returns the device's connection speed.
     pub fn speed(&self) -> Speed {
         fields::speed_from_libusb(unsafe { libusb_get_device_speed(self.device.as_ptr()) })
     }
 
+    /// Returns a single-line identity of the device for logs and support requests, e.g.
+    /// `1234:5678 bcd=1.00 serial=A1B2C3 speed=high port=1-2.3`.
+    ///
+    /// It gives the vendor and product IDs, `bcdDevice`, the serial number, the speed and the
+    /// port path starting with the bus number, always in this order and format. rusb identifies
+    /// devices in its [event log](event_log/index.html) with the same string, so a device can be
+    /// followed across applications by grepping for it.
+    ///
+    /// Reading the serial number needs an open handle, so unless the device has no serial
+    /// number, **this opens the device** for the time of the read, with everything opening
+    /// entails: the open is subject to the
+    /// [device policy](trait.UsbContext.html#method.set_device_policy), recorded in the event
+    /// log, and may fail or disturb another application on platforms where opening is
+    /// exclusive. If it can't be opened, e.g. for lack of permissions, the serial number is shown
+    /// as `?`, like anything else that can't be found out. A device without a serial number shows
+    /// `-`.
+    /// [`DeviceHandle::profile_string`](struct.DeviceHandle.html#method.profile_string) reuses an
+    /// open handle instead.
+    #[cfg_attr(feature = "async-only", deprecated(note = "blocks the calling thread"))]
+    #[cfg_attr(feature = "async-only", allow(deprecated))]
+    pub fn profile_string(&self) -> String {
+        let descriptor = self.device_descriptor().ok();
+        let serial = descriptor
+            .as_ref()
+            .filter(|d| d.serial_number_string_index().is_some())
+            .map(|d| {
+                self.open()
+                    .and_then(|handle| handle.read_serial_number_string_ascii(d))
+            });
+        self.profile(
+            descriptor.as_ref(),
+            Serial::of(descriptor.as_ref(), serial.as_ref()),
+        )
+    }
+
+    /// Returns the profile string of the device without reading its serial number, for logging
+    /// where the device mustn't be accessed.
+    pub(crate) fn quick_profile(&self) -> String {
+        let descriptor = self.device_descriptor().ok();
+        self.profile(descriptor.as_ref(), Serial::of(descriptor.as_ref(), None))
+    }
+
+    pub(crate) fn profile(
+        &self,
+        descriptor: Option<&DeviceDescriptor>,
+        serial: Serial<'_>,
+    ) -> String {
+        profile_string::render(
+            descriptor,
+            serial,
+            self.speed(),
+            self.bus_number(),
+            self.port_numbers().ok().as_deref(),
+        )
+    }
+
     /// Opens the device.
+    ///
+    /// The handle can be shared and can read and write, see [`open_with`](#method.open_with).
     pub fn open(&self) -> crate::Result<DeviceHandle<T>> {
+        self.open_with(OpenOptions::new())
+    }
+
+    /// Opens the device in the mode set by `options`, e.g. read-only or exclusively.
+    ///
+    /// ## Errors
+    ///
+    /// * `Access` if the device policy of the context refuses the device, see
+    ///   [`UsbContext::set_device_policy`](trait.UsbContext.html#method.set_device_policy).
+    /// * `Busy` if the device is opened exclusively, or if `options` asks for an exclusive handle
+    ///   and the device is already opened through rusb.
+    /// * Any error returned when opening the device.
+    pub fn open_with(&self, options: OpenOptions) -> crate::Result<DeviceHandle<T>> {
+        if !unsafe { device_policy::permits(self.context.as_raw(), self.device.as_ptr()) } {
+            return Err(Error::Access);
+        }
+
+        let lock = OpenLock::acquire(self.bus_number(), self.address(), options.is_exclusive())?;
         let mut handle = mem::MaybeUninit::<*mut libusb_device_handle>::uninit();
 
-        try_unsafe!(libusb_open(self.device.as_ptr(), handle.as_mut_ptr()));
+        let res = unsafe { libusb_open(self.device.as_ptr(), handle.as_mut_ptr()) };
+        if res != 0 {
+            let err = error::from_libusb(res);
+            if event_log::is_enabled() {
+                event_log::record(
+                    Record::new(EventKind::Error, self.bus_number(), self.address())
+                        .text("device", self.quick_profile())
+                        .str("operation", "open")
+                        .result::<()>(&Err(err)),
+                );
+            }
+            return Err(err);
+        }
+
+        let mut handle =
+            unsafe { device_handle::from_libusb(self.context.clone(), handle.assume_init()) };
+        handle.set_open_options(options, lock);
+        Ok(handle)
+    }
+
+    /// Writes everything that can be found out about the device to a text file at `path`, to be
+    /// attached to bug reports.
+    ///
+    /// The file holds the raw device, configuration and BOS descriptors, the string descriptors in
+    /// every supported language, the device's topology and speed, the versions of rusb and libusb
+    /// and the drivers bound to the device and its interfaces (from sysfs on Linux). Parts that
+    /// can't be read, e.g. because the device can't be opened, are noted in the file rather than
+    /// failing the export.
+    ///
+    /// ## Errors
+    ///
+    /// Only failures to write the file are returned.
+    pub fn export_debug_bundle<P: AsRef<Path>>(&self, path: P) -> crate::Result<()> {
+        debug_bundle::export(self, path.as_ref())
+    }
+
+    /// Returns the number of bytes an isochronous endpoint can transfer per (micro)frame in the
+    /// active configuration, i.e. the size of the packets of an isochronous transfer, accounting
+    /// for the additional transactions of high-bandwidth endpoints.
+    ///
+    /// ## Errors
+    ///
+    /// * `NotFound` if the endpoint doesn't exist in the active configuration.
+    pub fn max_iso_packet_size(&self, endpoint: u8) -> crate::Result<usize> {
+        let n = unsafe { libusb_get_max_iso_packet_size(self.device.as_ptr(), endpoint) };
 
-        Ok(unsafe { device_handle::from_libusb(self.context.clone(), handle.assume_init()) })
+        if n < 0 {
+            Err(error::from_libusb(n))
+        } else {
+            Ok(n as usize)
+        }
     }
 
     /// Returns the device's port number
     pub fn port_number(&self) -> u8 {
         unsafe { libusb_get_port_number(self.device.as_ptr()) }
     }
+
+    /// Returns the hub the device is attached to, or `None` for a root hub.
+    ///
+    /// Some platforms don't report hubs, in which case devices have no parent either.
+    pub fn parent(&self) -> Option<Device<T>> {
+        // libusb doesn't add a reference to the parent, which the device itself keeps alive
+        let parent = unsafe { libusb_get_parent(self.device.as_ptr()) };
+
+        if parent.is_null() {
+            None
+        } else {
+            Some(unsafe { from_libusb(self.context.clone(), parent) })
+        }
+    }
+
+    /// Indicates whether `other` is attached to the same port as this device, e.g. the same
+    /// device after a reset or re-plug made it re-enumerate under a new address.
+    ///
+    /// Devices whose port path can't be read are never considered the same.
+    pub fn same_physical_device<U: UsbContext>(&self, other: &Device<U>) -> bool {
+        if self.bus_number() != other.bus_number() {
+            return false;
+        }
+
+        match (self.port_numbers(), other.port_numbers()) {
+            (Ok(ports), Ok(other_ports)) => ports == other_ports,
+            _ => false,
+        }
+    }
+
+    /// Returns the id of the device, from which it can be found again after it was
+    /// re-enumerated, see [`DeviceId`](struct.DeviceId.html).
+    pub fn id(&self) -> crate::Result<DeviceId> {
+        DeviceId::of(self)
+    }
+
+    /// Returns the port numbers from the root hub down to the device, empty for a root hub.
+    ///
+    /// Together with the bus number, they identify the physical port the device is plugged into,
+    /// which stays the same across re-enumerations and reboots, unlike its address.
+    ///
+    /// ## Errors
+    ///
+    /// * `Overflow` if the device is deeper than the 7 tiers of hubs USB allows.
+    pub fn port_numbers(&self) -> crate::Result<Vec<u8>> {
+        // USB 3.0 limits the hub depth to 7
+        let mut ports = [0u8; 7];
+
+        let n = unsafe {
+            libusb_get_port_numbers(self.device.as_ptr(), ports.as_mut_ptr(), ports.len() as i32)
+        };
+
+        if n < 0 {
+            Err(error::from_libusb(n))
+        } else {
+            Ok(ports[..n as usize].to_vec())
+        }
+    }
 }
 
 #[doc(hidden)]
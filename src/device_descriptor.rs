@@ -2,7 +2,7 @@ use std::fmt;
 
 use libusb1_sys::*;
 
-use crate::fields::Version;
+use crate::fields::{UsbSpec, Version};
 
 /// Describes a device.
 pub struct DeviceDescriptor {
@@ -15,6 +15,17 @@ impl DeviceDescriptor {
         Version::from_bcd(self.descriptor.bcdUSB)
     }
 
+    /// Returns the USB specification level the device declares.
+    pub fn usb_spec(&self) -> UsbSpec {
+        UsbSpec::from_version(self.usb_version())
+    }
+
+    /// Indicates whether the device declares a USB version that requires a Binary Object Store
+    /// (BOS) descriptor, i.e. 2.01 or later.
+    pub fn supports_bos(&self) -> bool {
+        self.usb_spec().supports_bos()
+    }
+
     /// Returns the manufacturer's version of the device.
     pub fn device_version(&self) -> Version {
         Version::from_bcd(self.descriptor.bcdDevice)
@@ -78,6 +89,64 @@ impl DeviceDescriptor {
     pub fn num_configurations(&self) -> u8 {
         self.descriptor.bNumConfigurations
     }
+
+    /// Returns the descriptor in its wire format.
+    pub(crate) fn to_bytes(&self) -> [u8; 18] {
+        let d = &self.descriptor;
+        let usb = d.bcdUSB.to_le_bytes();
+        let vendor = d.idVendor.to_le_bytes();
+        let product = d.idProduct.to_le_bytes();
+        let device = d.bcdDevice.to_le_bytes();
+
+        [
+            d.bLength,
+            d.bDescriptorType,
+            usb[0],
+            usb[1],
+            d.bDeviceClass,
+            d.bDeviceSubClass,
+            d.bDeviceProtocol,
+            d.bMaxPacketSize0,
+            vendor[0],
+            vendor[1],
+            product[0],
+            product[1],
+            device[0],
+            device[1],
+            d.iManufacturer,
+            d.iProduct,
+            d.iSerialNumber,
+            d.bNumConfigurations,
+        ]
+    }
+
+    /// Decodes a descriptor from its wire format.
+    ///
+    /// Returns `None` if the data is not a device descriptor.
+    pub(crate) fn from_bytes(raw: &[u8]) -> Option<DeviceDescriptor> {
+        if raw.len() < 18 || raw[1] != constants::LIBUSB_DT_DEVICE {
+            return None;
+        }
+
+        let word = |i: usize| u16::from_le_bytes([raw[i], raw[i + 1]]);
+
+        Some(from_libusb(libusb_device_descriptor {
+            bLength: raw[0],
+            bDescriptorType: raw[1],
+            bcdUSB: word(2),
+            bDeviceClass: raw[4],
+            bDeviceSubClass: raw[5],
+            bDeviceProtocol: raw[6],
+            bMaxPacketSize0: raw[7],
+            idVendor: word(8),
+            idProduct: word(10),
+            bcdDevice: word(12),
+            iManufacturer: raw[14],
+            iProduct: raw[15],
+            iSerialNumber: raw[16],
+            bNumConfigurations: raw[17],
+        }))
+    }
 }
 
 impl fmt::Debug for DeviceDescriptor {
@@ -110,7 +179,7 @@ pub fn from_libusb(device: libusb_device_descriptor) -> DeviceDescriptor {
 
 #[cfg(test)]
 mod test {
-    use crate::fields::Version;
+    use crate::fields::{UsbSpec, Version};
 
     #[test]
     fn it_has_usb_version() {
@@ -120,6 +189,20 @@ mod test {
         );
     }
 
+    #[test]
+    fn it_has_usb_spec() {
+        assert_eq!(
+            UsbSpec::Usb2_1,
+            super::from_libusb(device_descriptor!(bcdUSB: 0x0201)).usb_spec()
+        );
+    }
+
+    #[test]
+    fn it_supports_bos_from_usb_2_1() {
+        assert!(!super::from_libusb(device_descriptor!(bcdUSB: 0x0200)).supports_bos());
+        assert!(super::from_libusb(device_descriptor!(bcdUSB: 0x0210)).supports_bos());
+    }
+
     #[test]
     fn it_has_device_version() {
         assert_eq!(
@@ -231,4 +314,38 @@ mod test {
             super::from_libusb(device_descriptor!(bNumConfigurations: 3)).num_configurations()
         );
     }
+
+    #[test]
+    fn it_serializes_to_wire_format() {
+        let bytes = super::from_libusb(device_descriptor!(
+            bLength: 18,
+            bDescriptorType: 1,
+            bcdUSB: 0x0200,
+            idVendor: 0x1234,
+            idProduct: 0x5678,
+            bNumConfigurations: 1
+        ))
+        .to_bytes();
+
+        assert_eq!(&[18, 1, 0x00, 0x02], &bytes[..4]);
+        assert_eq!(&[0x34, 0x12, 0x78, 0x56], &bytes[8..12]);
+        assert_eq!(1, bytes[17]);
+    }
+
+    #[test]
+    fn it_parses_the_wire_format() {
+        let bytes = super::from_libusb(device_descriptor!(
+            bLength: 18,
+            bDescriptorType: 1,
+            bcdUSB: 0x0200,
+            idVendor: 0x1234,
+            idProduct: 0x5678,
+            bNumConfigurations: 1
+        ))
+        .to_bytes();
+
+        let descriptor = super::DeviceDescriptor::from_bytes(&bytes).unwrap();
+        assert_eq!(bytes, descriptor.to_bytes());
+        assert!(super::DeviceDescriptor::from_bytes(&bytes[..17]).is_none());
+    }
 }
@@ -0,0 +1,229 @@
+use crate::{
+    context::{self, UsbContext},
+    device::Device,
+    device_descriptor::DeviceDescriptor,
+    device_handle::DeviceHandle,
+    error::Error,
+};
+
+/// Finds the devices matching a set of criteria.
+///
+/// Created by [`UsbContext::find_devices`](trait.UsbContext.html#method.find_devices). Every
+/// criterion left unset matches any device. Criteria are evaluated from the cheapest to the most
+/// expensive, so configuration descriptors are only read for devices whose device descriptor
+/// matches, and devices are only opened to read their serial number once everything else
+/// matches.
+///
+/// ```no_run
+/// use rusb::{ClassCode, Context, UsbContext};
+///
+/// # fn main() -> rusb::Result<()> {
+/// let context = Context::new()?;
+/// let handle = context
+///     .find_devices()
+///     .vendor_id(0x1234)
+///     .interface_class(ClassCode::Hid)
+///     .serial("ABC")
+///     .open()?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone)]
+pub struct DeviceFilter<T: UsbContext> {
+    context: T,
+    vendor_id: Option<u16>,
+    product_id: Option<u16>,
+    class: Option<u8>,
+    interface_class: Option<u8>,
+    serial: Option<String>,
+}
+
+impl<T: UsbContext> DeviceFilter<T> {
+    pub(crate) fn new(context: T) -> DeviceFilter<T> {
+        DeviceFilter {
+            context,
+            vendor_id: None,
+            product_id: None,
+            class: None,
+            interface_class: None,
+            serial: None,
+        }
+    }
+
+    /// Only matches devices with this vendor ID.
+    pub fn vendor_id(mut self, vendor_id: u16) -> Self {
+        self.vendor_id = Some(vendor_id);
+        self
+    }
+
+    /// Only matches devices with this product ID.
+    pub fn product_id(mut self, product_id: u16) -> Self {
+        self.product_id = Some(product_id);
+        self
+    }
+
+    /// Only matches devices with this device class, either a
+    /// [`ClassCode`](enum.ClassCode.html) or a raw class code.
+    ///
+    /// Most devices declare their class per interface, see
+    /// [`interface_class`](#method.interface_class).
+    pub fn class<C: Into<u8>>(mut self, class: C) -> Self {
+        self.class = Some(class.into());
+        self
+    }
+
+    /// Only matches devices with an interface of this class in any of their configurations,
+    /// either a [`ClassCode`](enum.ClassCode.html) or a raw class code.
+    pub fn interface_class<C: Into<u8>>(mut self, class: C) -> Self {
+        self.interface_class = Some(class.into());
+        self
+    }
+
+    /// Only matches devices with this serial number.
+    ///
+    /// Reading the serial number requires opening the device, so devices that can't be opened
+    /// never match, and matching blocks while the string descriptor is read.
+    pub fn serial(mut self, serial: &str) -> Self {
+        self.serial = Some(serial.to_owned());
+        self
+    }
+
+    /// Indicates whether `device` matches the criteria.
+    pub fn matches(&self, device: &Device<T>) -> bool {
+        let descriptor = match device.device_descriptor() {
+            Ok(d) => d,
+            Err(_) => return false,
+        };
+
+        if !self.matches_descriptor(&descriptor) || !self.matches_interfaces(device, &descriptor) {
+            return false;
+        }
+
+        match self.serial {
+            Some(ref serial) => match device.open() {
+                Ok(handle) => context::has_serial_number(&handle, &descriptor, serial),
+                Err(_) => false,
+            },
+            None => true,
+        }
+    }
+
+    /// Returns the matching devices.
+    pub fn devices(&self) -> crate::Result<Vec<Device<T>>> {
+        Ok(self
+            .context
+            .devices()?
+            .iter()
+            .filter(|device| self.matches(device))
+            .collect())
+    }
+
+    /// Returns the first matching device.
+    ///
+    /// ## Errors
+    ///
+    /// * `NotFound` if no device matches.
+    pub fn first(&self) -> crate::Result<Device<T>> {
+        self.context
+            .devices()?
+            .iter()
+            .find(|device| self.matches(device))
+            .ok_or(Error::NotFound)
+    }
+
+    /// Opens the first matching device that can be opened.
+    ///
+    /// ## Errors
+    ///
+    /// * `NotFound` if no device matches.
+    /// * The error opening a candidate, e.g. `Access`, if no device matches but some candidate
+    ///   could not be opened, since it might have been the one.
+    pub fn open(&self) -> crate::Result<DeviceHandle<T>> {
+        let mut open_error = None;
+
+        for device in self.context.devices()?.iter() {
+            let descriptor = match device.device_descriptor() {
+                Ok(d) => d,
+                Err(_) => continue,
+            };
+
+            if !self.matches_descriptor(&descriptor)
+                || !self.matches_interfaces(&device, &descriptor)
+            {
+                continue;
+            }
+
+            match device.open() {
+                Ok(handle) => {
+                    let matches = match self.serial {
+                        Some(ref serial) => {
+                            context::has_serial_number(&handle, &descriptor, serial)
+                        }
+                        None => true,
+                    };
+                    if matches {
+                        return Ok(handle);
+                    }
+                }
+                Err(e) => {
+                    open_error.get_or_insert(e);
+                }
+            }
+        }
+
+        Err(open_error.unwrap_or(Error::NotFound))
+    }
+
+    fn matches_descriptor(&self, descriptor: &DeviceDescriptor) -> bool {
+        self.vendor_id.map_or(true, |v| v == descriptor.vendor_id())
+            && self
+                .product_id
+                .map_or(true, |p| p == descriptor.product_id())
+            && self.class.map_or(true, |c| c == descriptor.class_code())
+    }
+
+    fn matches_interfaces(&self, device: &Device<T>, descriptor: &DeviceDescriptor) -> bool {
+        let class = match self.interface_class {
+            Some(class) => class,
+            None => return true,
+        };
+
+        (0..descriptor.num_configurations()).any(|index| match device.config_descriptor(index) {
+            Ok(config) => config
+                .interfaces()
+                .flat_map(|interface| interface.descriptors())
+                .any(|setting| setting.class_code() == class),
+            Err(_) => false,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{context::GlobalContext, fields::ClassCode};
+
+    fn descriptor(vendor_id: u16, product_id: u16, class: u8) -> DeviceDescriptor {
+        let [vl, vh] = vendor_id.to_le_bytes();
+        let [pl, ph] = product_id.to_le_bytes();
+        DeviceDescriptor::from_bytes(&[
+            18, 1, 0, 2, class, 0, 0, 64, vl, vh, pl, ph, 0, 1, 1, 2, 3, 1,
+        ])
+        .unwrap()
+    }
+
+    #[test]
+    fn it_matches_descriptors_on_the_criteria_set() {
+        let filter = GlobalContext::default().find_devices();
+        assert!(filter.matches_descriptor(&descriptor(0x1234, 0x5678, 0)));
+
+        let filter = filter.vendor_id(0x1234).class(ClassCode::Hid);
+        assert!(filter.matches_descriptor(&descriptor(0x1234, 0x5678, 3)));
+        assert!(!filter.matches_descriptor(&descriptor(0x1234, 0x5678, 0)));
+        assert!(!filter.matches_descriptor(&descriptor(0x4321, 0x5678, 3)));
+
+        let filter = filter.product_id(0x5678);
+        assert!(filter.matches_descriptor(&descriptor(0x1234, 0x5678, 3)));
+        assert!(!filter.matches_descriptor(&descriptor(0x1234, 0x8765, 3)));
+    }
+}
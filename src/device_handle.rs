@@ -1,31 +1,73 @@
-use std::{mem, ptr::NonNull, slice, time::Duration};
+use std::{
+    collections::BTreeMap,
+    convert::TryFrom,
+    mem,
+    ptr::NonNull,
+    sync::Arc,
+    time::{Duration, Instant},
+};
 
 use bit_set::BitSet;
-use libc::{c_int, c_uchar, c_uint};
+use libc::{c_int, c_uchar};
 use libusb1_sys::{constants::*, *};
 
 use crate::{
+    annotations::Annotations,
+    async_transfer::{ReadFuture, WriteFuture},
+    bos_descriptor::{self, BosDescriptor},
+    buffer_allocator,
+    class_descriptors::ClassDescriptor,
+    close_report::CloseReport,
     config_descriptor::ConfigDescriptor,
+    control_sequence::{self, ControlRequest, SequenceError},
+    descriptor_types::{self, DescriptorKind},
+    descriptor_view::{self, ParseMode, CONFIG_DESCRIPTOR_SIZE},
     device::{self, Device},
     device_descriptor::DeviceDescriptor,
+    device_strings::DeviceStrings,
     error::{self, Error},
-    fields::{request_type, Direction, Recipient, RequestType},
+    event_log::{self, EventKind, Record},
+    fields::{request_type, DescriptorType, Direction, Recipient, RequestType},
+    interface_claims::{ClaimedInterface, InterfaceClaims},
     interface_descriptor::InterfaceDescriptor,
+    interruptible::{self, OnInterrupt},
     language::Language,
+    open_options::{OpenLock, OpenOptions},
+    operation_trace::{self, OperationTrace, TraceEntry},
+    profile_string::Serial,
+    string_cache::{CachedStrings, StringCache, Strings},
+    transfer_outcome::TransferOutcome,
+    usb_memory::UsbMemory,
     UsbContext,
 };
 
 /// A handle to an open USB device.
-#[derive(Eq, PartialEq)]
 pub struct DeviceHandle<T: UsbContext> {
     context: T,
     handle: NonNull<libusb_device_handle>,
     interfaces: BitSet,
+    alt_settings: BTreeMap<u8, u8>,
+    detached: BitSet,
+    clear_halt_on_claim: bool,
+    on_interrupt: OnInterrupt,
+    parse_mode: ParseMode,
+    descriptor_timeout: Option<Duration>,
+    strings: StringCache,
+    trace: OperationTrace,
+    options: OpenOptions,
+    _lock: Option<OpenLock>,
 }
 
 impl<T: UsbContext> Drop for DeviceHandle<T> {
     /// Closes the device.
     fn drop(&mut self) {
+        #[cfg(feature = "leak-detection")]
+        crate::leak_detection::untrack(self.handle.as_ptr());
+
+        if event_log::is_enabled() {
+            event_log::record(self.event(EventKind::Closed));
+        }
+
         unsafe {
             for iface in self.interfaces.iter() {
                 libusb_release_interface(self.handle.as_ptr(), iface as c_int);
@@ -39,6 +81,14 @@ impl<T: UsbContext> Drop for DeviceHandle<T> {
 unsafe impl<T: UsbContext> Send for DeviceHandle<T> {}
 unsafe impl<T: UsbContext> Sync for DeviceHandle<T> {}
 
+impl<T: UsbContext> PartialEq for DeviceHandle<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.handle.as_ptr() == other.handle.as_ptr()
+    }
+}
+
+impl<T: UsbContext> Eq for DeviceHandle<T> {}
+
 impl<T: UsbContext> DeviceHandle<T> {
     /// Get the raw libusb_device_handle pointer, for advanced use in unsafe code.
     ///
@@ -48,6 +98,175 @@ impl<T: UsbContext> DeviceHandle<T> {
         self.handle.as_ptr()
     }
 
+    /// Returns the context this handle was opened from.
+    #[cfg(feature = "leak-detection")]
+    pub(crate) fn context(&self) -> &T {
+        &self.context
+    }
+
+    /// Starts an event log record about this handle's device.
+    fn event(&self, kind: EventKind) -> Record {
+        unsafe {
+            let device = libusb_get_device(self.handle.as_ptr());
+            Record::new(
+                kind,
+                libusb_get_bus_number(device),
+                libusb_get_device_address(device),
+            )
+        }
+    }
+
+    /// Returns the start time of an operation, if it is going to be logged or traced.
+    fn start(&self) -> Option<Instant> {
+        if event_log::is_enabled() || self.trace.is_enabled() {
+            Some(Instant::now())
+        } else {
+            None
+        }
+    }
+
+    /// Logs and traces an interface claim or release, or its failure.
+    fn log_interface(
+        &self,
+        kind: EventKind,
+        operation: &'static str,
+        iface: u8,
+        start: Option<Instant>,
+        res: &crate::Result<()>,
+    ) {
+        let start = match start {
+            Some(start) => start,
+            None => return,
+        };
+
+        self.trace.push(
+            TraceEntry::new(
+                if kind == EventKind::Claimed {
+                    "claim_interface"
+                } else {
+                    "release_interface"
+                },
+                start,
+            )
+            .arg("interface", iface.into())
+            .result(res, 0),
+        );
+
+        if !event_log::is_enabled() {
+            return;
+        }
+
+        let kind = if res.is_ok() { kind } else { EventKind::Error };
+        event_log::record(
+            self.event(kind)
+                .str("operation", operation)
+                .number("interface", iface.into())
+                .result(res),
+        );
+    }
+
+    /// Logs the summary of a synchronous transfer, and traces bulk and interrupt transfers.
+    fn log_transfer(
+        &self,
+        transfer_type: &'static str,
+        endpoint: u8,
+        requested: usize,
+        start: Option<Instant>,
+        res: &crate::Result<usize>,
+    ) {
+        let started = match start {
+            Some(started) => started,
+            None => return,
+        };
+
+        if transfer_type != "control" {
+            self.trace.push(
+                TraceEntry::new(transfer_operation(transfer_type, endpoint), started)
+                    .arg("endpoint", endpoint.into())
+                    .arg("requested", requested as u64)
+                    .result(res, *res.as_ref().unwrap_or(&0)),
+            );
+        }
+
+        if !event_log::is_enabled() {
+            return;
+        }
+
+        event_log::record(
+            self.event(EventKind::Transfer)
+                .str("type", transfer_type)
+                .number("endpoint", endpoint.into())
+                .number("requested", requested as u64)
+                .number("length", *res.as_ref().unwrap_or(&0) as u64)
+                .duration(start)
+                .result(res),
+        );
+    }
+
+    /// Traces a control transfer, with its setup packet.
+    #[allow(clippy::too_many_arguments)]
+    fn trace_control(
+        &self,
+        request_type: u8,
+        request: u8,
+        value: u16,
+        index: u16,
+        requested: usize,
+        start: Option<Instant>,
+        res: &crate::Result<usize>,
+    ) {
+        if let Some(started) = start {
+            self.trace.push(
+                TraceEntry::new(
+                    if request_type & LIBUSB_ENDPOINT_DIR_MASK == LIBUSB_ENDPOINT_IN {
+                        "read_control"
+                    } else {
+                        "write_control"
+                    },
+                    started,
+                )
+                .arg("request_type", request_type.into())
+                .arg("request", request.into())
+                .arg("value", value.into())
+                .arg("index", index.into())
+                .arg("requested", requested as u64)
+                .result(res, *res.as_ref().unwrap_or(&0)),
+            );
+        }
+    }
+
+    /// Sets how many of the latest operations this handle remembers, 32 by default.
+    ///
+    /// Claims, releases and synchronous transfers are recorded with a summary of their arguments,
+    /// their result and their duration, so that when an operation fails the sequence that led to
+    /// it can be inspected with [`trace`](#method.trace) or [`dump_trace`](#method.dump_trace)
+    /// without having enabled logging beforehand. A capacity of 0 disables the trace.
+    pub fn set_trace_capacity(&self, capacity: usize) {
+        self.trace.set_capacity(capacity);
+    }
+
+    /// Returns the latest operations performed through this handle, oldest first.
+    pub fn trace(&self) -> Vec<TraceEntry> {
+        self.trace.entries()
+    }
+
+    /// Formats the latest operations performed through this handle, one per line, oldest first.
+    ///
+    /// Each line starts with how long before the latest operation it started:
+    ///
+    /// ```text
+    /// -    1620us  write_bulk(endpoint=0x01, requested=64) -> Ok(64) in 95us
+    /// -       0us  read_bulk(endpoint=0x81, requested=512) -> Err(Operation timed out) in 1000230us
+    /// ```
+    pub fn dump_trace(&self) -> String {
+        operation_trace::format(&self.trace.entries())
+    }
+
+    /// Forgets the operations recorded so far.
+    pub fn clear_trace(&self) {
+        self.trace.clear();
+    }
+
     /// Get the device associated to this handle
     pub fn device(&self) -> Device<T> {
         unsafe {
@@ -58,6 +277,111 @@ impl<T: UsbContext> DeviceHandle<T> {
         }
     }
 
+    /// Returns the user data attached to the device, the same as through
+    /// [`Device::annotations`](struct.Device.html#method.annotations).
+    pub fn annotations(&self) -> Annotations<'_> {
+        unsafe {
+            Annotations::new(
+                self.context.as_raw(),
+                libusb_get_device(self.handle.as_ptr()),
+            )
+        }
+    }
+
+    /// Sets how string descriptors read through this handle cope with malformed data. Defaults
+    /// to [`ParseMode::Strict`](enum.ParseMode.html#variant.Strict).
+    ///
+    /// This applies to [`read_languages`](#method.read_languages),
+    /// [`read_string_descriptor`](#method.read_string_descriptor) and the methods built on them.
+    pub fn set_parse_mode(&mut self, mode: ParseMode) {
+        self.parse_mode = mode;
+    }
+
+    /// Returns how string descriptors read through this handle cope with malformed data.
+    pub fn parse_mode(&self) -> ParseMode {
+        self.parse_mode
+    }
+
+    /// Sets the timeout of the descriptor and string reads of this handle, or `None` (the
+    /// default) to use the timeout passed to each call.
+    ///
+    /// Some devices answer string requests slowly, e.g. while their firmware boots, but must time
+    /// out quickly on data transfers. Once set, this timeout replaces the one passed to
+    /// [`read_languages`](#method.read_languages),
+    /// [`read_string_descriptor`](#method.read_string_descriptor),
+    /// [`read_config_descriptor_raw`](#method.read_config_descriptor_raw),
+    /// [`read_bos_descriptor_raw`](#method.read_bos_descriptor_raw) and the methods built on
+    /// them, so the same timeout can be used for every call. The ASCII string reads use the
+    /// fixed timeout of `libusb`.
+    pub fn set_descriptor_timeout(&mut self, timeout: Option<Duration>) {
+        self.descriptor_timeout = timeout;
+    }
+
+    /// Returns the timeout of the descriptor and string reads of this handle, if one is set.
+    pub fn descriptor_timeout(&self) -> Option<Duration> {
+        self.descriptor_timeout
+    }
+
+    /// Sets how the blocking transfers of this handle react to signals. Defaults to
+    /// [`OnInterrupt::Retry`](enum.OnInterrupt.html#variant.Retry).
+    ///
+    /// This applies to the bulk, interrupt and control transfer methods, and to the methods built
+    /// on them. With [`OnInterrupt::Return`](enum.OnInterrupt.html#variant.Return), a signal
+    /// delivered to the thread handling events, such as `SIGINT` on Ctrl-C, ends the transfer
+    /// with `Error::Interrupted` on every platform.
+    pub fn set_on_interrupt(&mut self, policy: OnInterrupt) {
+        self.on_interrupt = policy;
+    }
+
+    /// Returns how the blocking transfers of this handle react to signals.
+    pub fn on_interrupt(&self) -> OnInterrupt {
+        self.on_interrupt
+    }
+
+    /// Returns the options the handle was opened with.
+    pub fn open_options(&self) -> OpenOptions {
+        self.options
+    }
+
+    /// Allocates a zeroed transfer buffer of `len` bytes in memory the device accesses directly,
+    /// see [`UsbMemory`](struct.UsbMemory.html).
+    ///
+    /// Falls back to heap memory where the platform or the kernel doesn't support it, e.g.
+    /// anywhere but Linux 4.6 or newer, so the buffer can be used either way.
+    pub fn alloc_transfer_buffer(&self, len: usize) -> UsbMemory<'_> {
+        unsafe { UsbMemory::alloc(self.handle.as_ptr(), len) }
+    }
+
+    /// Allocates a transfer buffer like [`alloc_transfer_buffer`](#method.alloc_transfer_buffer)
+    /// for sensitive data, which is zeroed when the buffer is dropped.
+    ///
+    /// Device memory is never swapped out, and the heap fallback is locked in memory, like a
+    /// [`SecureBuffer`](struct.SecureBuffer.html).
+    ///
+    /// ## Errors
+    ///
+    /// * `NoMem` if the heap fallback can't be locked, typically because the process exceeds its
+    ///   `RLIMIT_MEMLOCK` limit.
+    /// * `NotSupported` if the heap fallback is needed on a platform without `mlock`.
+    pub fn alloc_secure_transfer_buffer(&self, len: usize) -> crate::Result<UsbMemory<'_>> {
+        unsafe { UsbMemory::alloc_secure(self.handle.as_ptr(), len) }
+    }
+
+    /// Records how the handle was opened, see `Device::open_with`.
+    pub(crate) fn set_open_options(&mut self, options: OpenOptions, lock: OpenLock) {
+        self.options = options;
+        self._lock = Some(lock);
+    }
+
+    /// Fails with `Access` if the handle was opened read-only.
+    fn check_writable(&self) -> crate::Result<()> {
+        if self.options.is_read_only() {
+            Err(Error::Access)
+        } else {
+            Ok(())
+        }
+    }
+
     /// Returns the active configuration number.
     pub fn active_configuration(&self) -> crate::Result<u8> {
         let mut config = mem::MaybeUninit::<c_int>::uninit();
@@ -71,6 +395,7 @@ impl<T: UsbContext> DeviceHandle<T> {
 
     /// Sets the device's active configuration.
     pub fn set_active_configuration(&mut self, config: u8) -> crate::Result<()> {
+        self.check_writable()?;
         try_unsafe!(libusb_set_configuration(
             self.handle.as_ptr(),
             c_int::from(config)
@@ -80,22 +405,97 @@ impl<T: UsbContext> DeviceHandle<T> {
 
     /// Puts the device in an unconfigured state.
     pub fn unconfigure(&mut self) -> crate::Result<()> {
+        self.check_writable()?;
         try_unsafe!(libusb_set_configuration(self.handle.as_ptr(), -1));
         Ok(())
     }
 
     /// Resets the device.
+    ///
+    /// The [cached strings](#method.cached_strings) are forgotten, since the device may report
+    /// different ones after a reset, e.g. when it rebooted into new firmware.
     pub fn reset(&mut self) -> crate::Result<()> {
+        self.check_writable()?;
+        self.strings.clear();
         try_unsafe!(libusb_reset_device(self.handle.as_ptr()));
+
+        // the device is back to its default settings, with interfaces still claimed
+        self.alt_settings.clear();
         Ok(())
     }
 
     /// Clear the halt/stall condition for an endpoint.
     pub fn clear_halt(&mut self, endpoint: u8) -> crate::Result<()> {
+        self.check_writable()?;
         try_unsafe!(libusb_clear_halt(self.handle.as_ptr(), endpoint));
         Ok(())
     }
 
+    /// Allocates up to `num_streams` USB 3.0 bulk streams on each of `endpoints`, and returns the
+    /// number of streams allocated, which may be fewer.
+    ///
+    /// Streams let SuperSpeed protocols such as USB Attached SCSI (UAS) queue several transfers
+    /// on one bulk endpoint, each told apart by its stream ID. The streams are numbered from 1 to
+    /// the returned number, on every endpoint of the call, and are used with
+    /// [`Transfer::bulk_stream`](struct.Transfer.html#method.bulk_stream) or
+    /// [`OwnedTransfer::bulk_stream`](struct.OwnedTransfer.html#method.bulk_stream). The
+    /// endpoints' interface must be claimed. Free the streams with
+    /// [`free_streams`](#method.free_streams) once done.
+    ///
+    /// ## Errors
+    ///
+    /// * `Access` if the handle was opened read-only.
+    /// * `InvalidParam` if `num_streams` is zero or `endpoints` is empty.
+    /// * `NotSupported` if the platform, the host controller or the endpoints don't support
+    ///   streams.
+    /// * Any error returned when allocating the streams.
+    pub fn alloc_streams(&mut self, num_streams: u32, endpoints: &[u8]) -> crate::Result<u32> {
+        self.check_writable()?;
+        if num_streams == 0 || endpoints.is_empty() {
+            return Err(Error::InvalidParam);
+        }
+
+        let mut endpoints = endpoints.to_vec();
+        let len = c_int::try_from(endpoints.len()).map_err(|_| Error::InvalidParam)?;
+        let res = unsafe {
+            libusb_alloc_streams(
+                self.handle.as_ptr(),
+                num_streams,
+                endpoints.as_mut_ptr(),
+                len,
+            )
+        };
+        if res < 0 {
+            Err(error::from_libusb(res))
+        } else {
+            Ok(res as u32)
+        }
+    }
+
+    /// Frees the bulk streams allocated on `endpoints` with
+    /// [`alloc_streams`](#method.alloc_streams).
+    ///
+    /// ## Errors
+    ///
+    /// * `Access` if the handle was opened read-only.
+    /// * `InvalidParam` if `endpoints` is empty.
+    /// * Any error returned when freeing the streams.
+    pub fn free_streams(&mut self, endpoints: &[u8]) -> crate::Result<()> {
+        self.check_writable()?;
+        if endpoints.is_empty() {
+            return Err(Error::InvalidParam);
+        }
+
+        let mut endpoints = endpoints.to_vec();
+        let len = c_int::try_from(endpoints.len()).map_err(|_| Error::InvalidParam)?;
+        try_unsafe!(libusb_free_streams(
+            self.handle.as_ptr(),
+            endpoints.as_mut_ptr(),
+            len
+        ));
+        Ok(())
+    }
+
     /// Indicates whether the device has an attached kernel driver.
     ///
     /// This method is not supported on all platforms.
@@ -115,6 +515,7 @@ impl<T: UsbContext> DeviceHandle<T> {
             self.handle.as_ptr(),
             c_int::from(iface)
         ));
+        self.detached.insert(iface as usize);
         Ok(())
     }
 
@@ -126,6 +527,7 @@ impl<T: UsbContext> DeviceHandle<T> {
             self.handle.as_ptr(),
             c_int::from(iface)
         ));
+        self.detached.remove(iface as usize);
         Ok(())
     }
 
@@ -146,39 +548,232 @@ impl<T: UsbContext> DeviceHandle<T> {
         Ok(())
     }
 
+    /// Enable/disable clearing the halt condition of an interface's endpoints when claiming it.
+    ///
+    /// A process that crashed in the middle of a transfer can leave endpoints stalled, so that
+    /// the first transfer of the next process fails with `Pipe`. When enabled,
+    /// [`claim_interface`](#method.claim_interface) clears the halt on every endpoint of the
+    /// interface's current alternate setting. Disabled by default.
+    pub fn set_clear_halt_on_claim(&mut self, clear_halt: bool) {
+        self.clear_halt_on_claim = clear_halt;
+    }
+
+    /// Clears the halt condition of every endpoint of an interface's current alternate setting.
+    fn clear_interface_halts(&mut self, iface: u8) -> crate::Result<()> {
+        for endpoint in self.interface_endpoints(iface)? {
+            self.clear_halt(endpoint)?;
+        }
+        Ok(())
+    }
+
+    /// Returns the endpoint addresses of an interface's current alternate setting.
+    pub(crate) fn interface_endpoints(&self, iface: u8) -> crate::Result<Vec<u8>> {
+        let setting = self.current_alt_setting(iface).unwrap_or(0);
+        let config = self.device().active_config_descriptor()?;
+
+        let endpoints = config
+            .interfaces()
+            .filter(|i| i.number() == iface)
+            .flat_map(|i| i.descriptors())
+            .filter(|d| d.setting_number() == setting)
+            .flat_map(|d| {
+                d.endpoint_descriptors()
+                    .map(|e| e.address())
+                    .collect::<Vec<_>>()
+            })
+            .collect();
+        Ok(endpoints)
+    }
+
     /// Claims one of the device's interfaces.
     ///
     /// An interface must be claimed before operating on it. All claimed interfaces are released
     /// when the device handle goes out of scope.
+    ///
+    /// If [`set_clear_halt_on_claim`](#method.set_clear_halt_on_claim) is enabled, the halt
+    /// condition of the interface's endpoints is cleared as well. Should that fail, the error is
+    /// returned but the interface stays claimed.
     pub fn claim_interface(&mut self, iface: u8) -> crate::Result<()> {
-        try_unsafe!(libusb_claim_interface(
-            self.handle.as_ptr(),
-            c_int::from(iface)
-        ));
-        self.interfaces.insert(iface as usize);
+        let start = self.start();
+        let res = match unsafe { libusb_claim_interface(self.handle.as_ptr(), c_int::from(iface)) }
+        {
+            0 => Ok(()),
+            err => Err(error::from_libusb(err)),
+        };
+        self.log_interface(EventKind::Claimed, "claim", iface, start, &res);
+        res?;
+
+        if self.interfaces.insert(iface as usize) {
+            self.alt_settings.remove(&iface);
+        }
+
+        if self.clear_halt_on_claim {
+            self.clear_interface_halts(iface)?;
+        }
         Ok(())
     }
 
+    /// Claims one of the device's interfaces, and returns a guard releasing it when dropped.
+    ///
+    /// Unlike [`claim_interface`](#method.claim_interface), a kernel driver bound to the interface
+    /// is detached first, and attached again when the guard is dropped, so error paths can't leave
+    /// the interface claimed or without its driver. The guard scopes transfers to the endpoints
+    /// of the interface.
+    ///
+    /// ## Errors
+    ///
+    /// * `Busy` if another program or driver has claimed the interface.
+    /// * `NotFound` if the interface doesn't exist.
+    /// * Any error returned when detaching the kernel driver, e.g. `Access`.
+    pub fn claim(&mut self, iface: u8) -> crate::Result<ClaimedInterface<'_, T>> {
+        // platforms without kernel drivers report `NotSupported`, and have nothing to detach
+        let detached = if self.kernel_driver_active(iface).unwrap_or(false) {
+            self.detach_kernel_driver(iface)?;
+            true
+        } else {
+            false
+        };
+
+        let was_claimed = self.interfaces.contains(iface as usize);
+        if let Err(e) = self.claim_interface(iface) {
+            // clearing the halts may fail after the interface was claimed
+            if !was_claimed && self.interfaces.contains(iface as usize) {
+                self.release_interface(iface).ok();
+            }
+            if detached {
+                self.attach_kernel_driver(iface).ok();
+            }
+            return Err(e);
+        }
+
+        Ok(ClaimedInterface::new(self, iface, detached))
+    }
+
+    /// Claims every interface of the active configuration that this handle hasn't claimed yet.
+    ///
+    /// This is meant for tools that need the whole device to themselves, such as protocol
+    /// analyzers and flashing utilities. A failure to claim one interface doesn't stop the others
+    /// from being claimed; the returned guard reports the outcome for each interface, and
+    /// releases the interfaces it claimed when it is dropped.
+    ///
+    /// Interfaces bound to a kernel driver can only be claimed if the driver is detached first,
+    /// e.g. by enabling [`set_auto_detach_kernel_driver`](#method.set_auto_detach_kernel_driver).
+    ///
+    /// ## Errors
+    ///
+    /// Returns an error if the active configuration can't be read, in which case nothing is
+    /// claimed.
+    pub fn claim_all_unclaimed_interfaces(&mut self) -> crate::Result<InterfaceClaims<'_, T>> {
+        let config = self.device().active_config_descriptor()?;
+
+        let mut numbers: Vec<u8> = config.interfaces().map(|i| i.number()).collect();
+        numbers.sort_unstable();
+        numbers.dedup();
+
+        numbers.retain(|&iface| !self.interfaces.contains(iface as usize));
+
+        let mut results = Vec::with_capacity(numbers.len());
+        for iface in numbers {
+            results.push((iface, self.claim_interface(iface)));
+        }
+
+        Ok(InterfaceClaims::new(self, results))
+    }
+
     /// Releases a claimed interface.
     pub fn release_interface(&mut self, iface: u8) -> crate::Result<()> {
-        try_unsafe!(libusb_release_interface(
-            self.handle.as_ptr(),
-            c_int::from(iface)
-        ));
+        let start = self.start();
+        let res =
+            match unsafe { libusb_release_interface(self.handle.as_ptr(), c_int::from(iface)) } {
+                0 => Ok(()),
+                err => Err(error::from_libusb(err)),
+            };
+        self.log_interface(EventKind::Released, "release", iface, start, &res);
+        res?;
+
         self.interfaces.remove(iface as usize);
+        self.alt_settings.remove(&iface);
         Ok(())
     }
 
+    /// Closes the device, tearing it down in the correct order.
+    ///
+    /// Dropping a handle releases its claimed interfaces and closes it, but leaves interfaces
+    /// without their kernel driver if it was detached with
+    /// [`detach_kernel_driver`](#method.detach_kernel_driver). This method instead:
+    ///
+    /// 1. releases every claimed interface,
+    /// 2. reattaches the kernel driver of every interface it was detached from through this
+    ///    handle,
+    /// 3. processes any completion events still pending on the context,
+    /// 4. closes the handle.
+    ///
+    /// Taking the handle by value guarantees that no transfer borrowing it is still alive, and
+    /// the futures of the `*_async` methods hold the handle in an `Arc`, so step 3 only has to
+    /// deliver the completions of transfers that finished or were cancelled just before.
+    /// Failures are recorded in the returned report rather than aborting the teardown.
+    pub fn close_gracefully(mut self) -> CloseReport {
+        let mut report = CloseReport::default();
+
+        let claimed: Vec<u8> = self.interfaces.iter().map(|i| i as u8).collect();
+        for iface in claimed {
+            match self.release_interface(iface) {
+                Ok(()) => report.released(iface),
+                Err(e) => {
+                    // don't try again when the handle is dropped
+                    self.interfaces.remove(iface as usize);
+                    report.failed(iface, e);
+                }
+            }
+        }
+
+        let detached: Vec<u8> = self.detached.iter().map(|i| i as u8).collect();
+        for iface in detached {
+            match self.attach_kernel_driver(iface) {
+                Ok(()) => report.reattached(iface),
+                Err(e) => report.failed(iface, e),
+            }
+        }
+
+        self.context
+            .handle_events(Some(Duration::from_secs(0)))
+            .ok();
+
+        report
+    }
+
     /// Sets an interface's active setting.
     pub fn set_alternate_setting(&mut self, iface: u8, setting: u8) -> crate::Result<()> {
+        self.check_writable()?;
         try_unsafe!(libusb_set_interface_alt_setting(
             self.handle.as_ptr(),
             c_int::from(iface),
             c_int::from(setting)
         ));
+        self.alt_settings.insert(iface, setting);
         Ok(())
     }
 
+    /// Returns the interfaces claimed through this handle, in ascending order.
+    pub fn claimed_interfaces(&self) -> impl Iterator<Item = u8> + '_ {
+        self.interfaces.iter().map(|iface| iface as u8)
+    }
+
+    /// Returns the alternate setting selected for a claimed interface, or `None` if the interface
+    /// isn't claimed through this handle.
+    ///
+    /// The setting is the last one selected with
+    /// [`set_alternate_setting`](#method.set_alternate_setting) since the interface was claimed or
+    /// the device was reset, or 0 (the default setting) if none was. It is tracked by the handle
+    /// rather than read from the device, so it doesn't reflect changes made by other handles.
+    pub fn current_alt_setting(&self, iface: u8) -> Option<u8> {
+        if !self.interfaces.contains(iface as usize) {
+            return None;
+        }
+
+        Some(self.alt_settings.get(&iface).copied().unwrap_or(0))
+    }
+
     /// Reads from an interrupt endpoint.
     ///
     /// This function attempts to read from the interrupt endpoint with the address given by the
@@ -201,6 +796,7 @@ impl<T: UsbContext> DeviceHandle<T> {
     /// * `Overflow` if the device offered more data.
     /// * `NoDevice` if the device has been disconnected.
     /// * `Io` if the transfer encountered an I/O error.
+    #[cfg_attr(feature = "async-only", deprecated(note = "blocks the calling thread"))]
     pub fn read_interrupt(
         &self,
         endpoint: u8,
@@ -210,28 +806,29 @@ impl<T: UsbContext> DeviceHandle<T> {
         if endpoint & LIBUSB_ENDPOINT_DIR_MASK != LIBUSB_ENDPOINT_IN {
             return Err(Error::InvalidParam);
         }
-        let mut transferred = mem::MaybeUninit::<c_int>::uninit();
-        unsafe {
-            match libusb_interrupt_transfer(
-                self.handle.as_ptr(),
+        let start = self.start();
+        let res = unsafe {
+            match self.raw_transfer(
+                LIBUSB_TRANSFER_TYPE_INTERRUPT,
                 endpoint,
-                buf.as_mut_ptr() as *mut c_uchar,
-                buf.len() as c_int,
-                transferred.as_mut_ptr(),
-                timeout.as_millis() as c_uint,
+                buf.as_mut_ptr(),
+                buf.len(),
+                timeout,
             ) {
-                0 => Ok(transferred.assume_init() as usize),
-                err if err == LIBUSB_ERROR_INTERRUPTED => {
-                    let transferred = transferred.assume_init();
+                (0, transferred) => Ok(transferred),
+                (err, transferred) if err == LIBUSB_ERROR_INTERRUPTED => {
                     if transferred > 0 {
-                        Ok(transferred as usize)
+                        Ok(transferred)
                     } else {
                         Err(error::from_libusb(err))
                     }
                 }
-                err => Err(error::from_libusb(err)),
+                (err, _) => Err(error::from_libusb(err)),
             }
-        }
+        };
+
+        self.log_transfer("interrupt", endpoint, buf.len(), start, &res);
+        res
     }
 
     /// Writes to an interrupt endpoint.
@@ -255,6 +852,7 @@ impl<T: UsbContext> DeviceHandle<T> {
     /// * `NoDevice` if the device has been disconnected.
     /// * `Io` if the transfer encountered an I/O error.
     #[inline]
+    #[cfg_attr(feature = "async-only", deprecated(note = "blocks the calling thread"))]
     pub fn write_interrupt(
         &self,
         endpoint: u8,
@@ -264,28 +862,30 @@ impl<T: UsbContext> DeviceHandle<T> {
         if endpoint & LIBUSB_ENDPOINT_DIR_MASK != LIBUSB_ENDPOINT_OUT {
             return Err(Error::InvalidParam);
         }
-        let mut transferred = mem::MaybeUninit::<c_int>::uninit();
-        unsafe {
-            match libusb_interrupt_transfer(
-                self.handle.as_ptr(),
+        self.check_writable()?;
+        let start = self.start();
+        let res = unsafe {
+            match self.raw_transfer(
+                LIBUSB_TRANSFER_TYPE_INTERRUPT,
                 endpoint,
-                buf.as_ptr() as *mut c_uchar,
-                buf.len() as c_int,
-                transferred.as_mut_ptr(),
-                timeout.as_millis() as c_uint,
+                buf.as_ptr() as *mut u8,
+                buf.len(),
+                timeout,
             ) {
-                0 => Ok(transferred.assume_init() as usize),
-                err if err == LIBUSB_ERROR_INTERRUPTED => {
-                    let transferred = transferred.assume_init();
+                (0, transferred) => Ok(transferred),
+                (err, transferred) if err == LIBUSB_ERROR_INTERRUPTED => {
                     if transferred > 0 {
-                        Ok(transferred as usize)
+                        Ok(transferred)
                     } else {
                         Err(error::from_libusb(err))
                     }
                 }
-                err => Err(error::from_libusb(err)),
+                (err, _) => Err(error::from_libusb(err)),
             }
-        }
+        };
+
+        self.log_transfer("interrupt", endpoint, buf.len(), start, &res);
+        res
     }
 
     /// Reads from a bulk endpoint.
@@ -311,6 +911,7 @@ impl<T: UsbContext> DeviceHandle<T> {
     /// * `NoDevice` if the device has been disconnected.
     /// * `Io` if the transfer encountered an I/O error.
     #[inline]
+    #[cfg_attr(feature = "async-only", deprecated(note = "blocks the calling thread"))]
     pub fn read_bulk(
         &self,
         endpoint: u8,
@@ -320,28 +921,31 @@ impl<T: UsbContext> DeviceHandle<T> {
         if endpoint & LIBUSB_ENDPOINT_DIR_MASK != LIBUSB_ENDPOINT_IN {
             return Err(Error::InvalidParam);
         }
-        let mut transferred = mem::MaybeUninit::<c_int>::uninit();
-        unsafe {
-            match libusb_bulk_transfer(
-                self.handle.as_ptr(),
+        let start = self.start();
+        let res = unsafe {
+            match self.raw_transfer(
+                LIBUSB_TRANSFER_TYPE_BULK,
                 endpoint,
-                buf.as_mut_ptr() as *mut c_uchar,
-                buf.len() as c_int,
-                transferred.as_mut_ptr(),
-                timeout.as_millis() as c_uint,
+                buf.as_mut_ptr(),
+                buf.len(),
+                timeout,
             ) {
-                0 => Ok(transferred.assume_init() as usize),
-                err if err == LIBUSB_ERROR_INTERRUPTED || err == LIBUSB_ERROR_TIMEOUT => {
-                    let transferred = transferred.assume_init();
+                (0, transferred) => Ok(transferred),
+                (err, transferred)
+                    if err == LIBUSB_ERROR_INTERRUPTED || err == LIBUSB_ERROR_TIMEOUT =>
+                {
                     if transferred > 0 {
-                        Ok(transferred as usize)
+                        Ok(transferred)
                     } else {
                         Err(error::from_libusb(err))
                     }
                 }
-                err => Err(error::from_libusb(err)),
+                (err, _) => Err(error::from_libusb(err)),
             }
-        }
+        };
+
+        self.log_transfer("bulk", endpoint, buf.len(), start, &res);
+        res
     }
 
     /// Writes to a bulk endpoint.
@@ -364,39 +968,231 @@ impl<T: UsbContext> DeviceHandle<T> {
     /// * `Pipe` if the endpoint halted.
     /// * `NoDevice` if the device has been disconnected.
     /// * `Io` if the transfer encountered an I/O error.
+    #[cfg_attr(feature = "async-only", deprecated(note = "blocks the calling thread"))]
     pub fn write_bulk(&self, endpoint: u8, buf: &[u8], timeout: Duration) -> crate::Result<usize> {
         if endpoint & LIBUSB_ENDPOINT_DIR_MASK != LIBUSB_ENDPOINT_OUT {
             return Err(Error::InvalidParam);
         }
-        let mut transferred = mem::MaybeUninit::<c_int>::uninit();
-        unsafe {
-            match libusb_bulk_transfer(
-                self.handle.as_ptr(),
+        self.check_writable()?;
+        let start = self.start();
+        let res = unsafe {
+            match self.raw_transfer(
+                LIBUSB_TRANSFER_TYPE_BULK,
                 endpoint,
-                buf.as_ptr() as *mut c_uchar,
-                buf.len() as c_int,
-                transferred.as_mut_ptr(),
-                timeout.as_millis() as c_uint,
+                buf.as_ptr() as *mut u8,
+                buf.len(),
+                timeout,
             ) {
-                0 => Ok(transferred.assume_init() as usize),
-                err if err == LIBUSB_ERROR_INTERRUPTED || err == LIBUSB_ERROR_TIMEOUT => {
-                    let transferred = transferred.assume_init();
+                (0, transferred) => Ok(transferred),
+                (err, transferred)
+                    if err == LIBUSB_ERROR_INTERRUPTED || err == LIBUSB_ERROR_TIMEOUT =>
+                {
                     if transferred > 0 {
-                        Ok(transferred as usize)
+                        Ok(transferred)
                     } else {
                         Err(error::from_libusb(err))
                     }
                 }
-                err => Err(error::from_libusb(err)),
+                (err, _) => Err(error::from_libusb(err)),
             }
+        };
+
+        self.log_transfer("bulk", endpoint, buf.len(), start, &res);
+        res
+    }
+
+    /// Reads from a bulk endpoint, reporting partial data on failure.
+    ///
+    /// This behaves like [`read_bulk`](#method.read_bulk), but returns the number of bytes
+    /// received together with the error that ended the transfer, if any. On a timeout, `buf` holds
+    /// the data received before the timeout expired, so the read can be resumed.
+    #[cfg_attr(feature = "async-only", deprecated(note = "blocks the calling thread"))]
+    pub fn read_bulk_partial(
+        &self,
+        endpoint: u8,
+        buf: &mut [u8],
+        timeout: Duration,
+    ) -> TransferOutcome {
+        if endpoint & LIBUSB_ENDPOINT_DIR_MASK != LIBUSB_ENDPOINT_IN {
+            return TransferOutcome::new(0, Some(Error::InvalidParam));
+        }
+        unsafe {
+            self.sync_transfer(
+                LIBUSB_TRANSFER_TYPE_BULK,
+                endpoint,
+                buf.as_mut_ptr(),
+                buf.len(),
+                timeout,
+            )
         }
     }
 
-    /// Reads data using a control transfer.
+    /// Writes to a bulk endpoint, reporting partial data on failure.
     ///
-    /// This function attempts to read data from the device using a control transfer and fills
-    /// `buf` with any data received during the transfer. The function blocks up to the amount of
-    /// time specified by `timeout`.
+    /// This behaves like [`write_bulk`](#method.write_bulk), but returns the number of bytes
+    /// sent together with the error that ended the transfer, if any.
+    #[cfg_attr(feature = "async-only", deprecated(note = "blocks the calling thread"))]
+    pub fn write_bulk_partial(
+        &self,
+        endpoint: u8,
+        buf: &[u8],
+        timeout: Duration,
+    ) -> TransferOutcome {
+        if endpoint & LIBUSB_ENDPOINT_DIR_MASK != LIBUSB_ENDPOINT_OUT {
+            return TransferOutcome::new(0, Some(Error::InvalidParam));
+        }
+        if let Err(e) = self.check_writable() {
+            return TransferOutcome::new(0, Some(e));
+        }
+        unsafe {
+            self.sync_transfer(
+                LIBUSB_TRANSFER_TYPE_BULK,
+                endpoint,
+                buf.as_ptr() as *mut u8,
+                buf.len(),
+                timeout,
+            )
+        }
+    }
+
+    /// Reads from an interrupt endpoint, reporting partial data on failure.
+    ///
+    /// This behaves like [`read_interrupt`](#method.read_interrupt), but returns the number of
+    /// bytes received together with the error that ended the transfer, if any.
+    #[cfg_attr(feature = "async-only", deprecated(note = "blocks the calling thread"))]
+    pub fn read_interrupt_partial(
+        &self,
+        endpoint: u8,
+        buf: &mut [u8],
+        timeout: Duration,
+    ) -> TransferOutcome {
+        if endpoint & LIBUSB_ENDPOINT_DIR_MASK != LIBUSB_ENDPOINT_IN {
+            return TransferOutcome::new(0, Some(Error::InvalidParam));
+        }
+        unsafe {
+            self.sync_transfer(
+                LIBUSB_TRANSFER_TYPE_INTERRUPT,
+                endpoint,
+                buf.as_mut_ptr(),
+                buf.len(),
+                timeout,
+            )
+        }
+    }
+
+    /// Writes to an interrupt endpoint, reporting partial data on failure.
+    ///
+    /// This behaves like [`write_interrupt`](#method.write_interrupt), but returns the number of
+    /// bytes sent together with the error that ended the transfer, if any.
+    #[cfg_attr(feature = "async-only", deprecated(note = "blocks the calling thread"))]
+    pub fn write_interrupt_partial(
+        &self,
+        endpoint: u8,
+        buf: &[u8],
+        timeout: Duration,
+    ) -> TransferOutcome {
+        if endpoint & LIBUSB_ENDPOINT_DIR_MASK != LIBUSB_ENDPOINT_OUT {
+            return TransferOutcome::new(0, Some(Error::InvalidParam));
+        }
+        if let Err(e) = self.check_writable() {
+            return TransferOutcome::new(0, Some(e));
+        }
+        unsafe {
+            self.sync_transfer(
+                LIBUSB_TRANSFER_TYPE_INTERRUPT,
+                endpoint,
+                buf.as_ptr() as *mut u8,
+                buf.len(),
+                timeout,
+            )
+        }
+    }
+
+    /// Performs a blocking bulk or interrupt transfer according to the interruption policy,
+    /// returning the libusb error code and the number of bytes transferred.
+    unsafe fn raw_transfer(
+        &self,
+        transfer_type: u8,
+        endpoint: u8,
+        buf: *mut u8,
+        len: usize,
+        timeout: Duration,
+    ) -> (c_int, usize) {
+        interruptible::transfer(
+            self.context.as_raw(),
+            self.handle.as_ptr(),
+            transfer_type,
+            endpoint,
+            buf,
+            len,
+            timeout,
+            self.on_interrupt,
+        )
+    }
+
+    unsafe fn sync_transfer(
+        &self,
+        transfer_type: u8,
+        endpoint: u8,
+        buf: *mut u8,
+        len: usize,
+        timeout: Duration,
+    ) -> TransferOutcome {
+        let start = self.start();
+        let (res, transferred) = self.raw_transfer(transfer_type, endpoint, buf, len, timeout);
+
+        let error = match res {
+            0 => None,
+            err => Some(error::from_libusb(err)),
+        };
+        let res = error.map_or(Ok(()), Err);
+
+        if let Some(started) = start {
+            self.trace.push(
+                TraceEntry::new(
+                    transfer_operation(
+                        if transfer_type == LIBUSB_TRANSFER_TYPE_BULK {
+                            "bulk"
+                        } else {
+                            "interrupt"
+                        },
+                        endpoint,
+                    ),
+                    started,
+                )
+                .arg("endpoint", endpoint.into())
+                .arg("requested", len as u64)
+                .result(&res, transferred),
+            );
+        }
+
+        if start.is_some() && event_log::is_enabled() {
+            event_log::record(
+                self.event(EventKind::Transfer)
+                    .str(
+                        "type",
+                        if transfer_type == LIBUSB_TRANSFER_TYPE_BULK {
+                            "bulk"
+                        } else {
+                            "interrupt"
+                        },
+                    )
+                    .number("endpoint", endpoint.into())
+                    .number("requested", len as u64)
+                    .number("length", transferred as u64)
+                    .duration(start)
+                    .result(&res),
+            );
+        }
+
+        TransferOutcome::new(transferred, error)
+    }
+
+    /// Reads data using a control transfer.
+    ///
+    /// This function attempts to read data from the device using a control transfer and fills
+    /// `buf` with any data received during the transfer. The function blocks up to the amount of
+    /// time specified by `timeout`.
     ///
     /// The parameters `request_type`, `request`, `value`, and `index` specify the fields of the
     /// control transfer setup packet (`bmRequestType`, `bRequest`, `wValue`, and `wIndex`
@@ -419,6 +1215,7 @@ impl<T: UsbContext> DeviceHandle<T> {
     /// * `Pipe` if the control request was not supported by the device.
     /// * `NoDevice` if the device has been disconnected.
     /// * `Io` if the transfer encountered an I/O error.
+    #[cfg_attr(feature = "async-only", deprecated(note = "blocks the calling thread"))]
     pub fn read_control(
         &self,
         request_type: u8,
@@ -431,24 +1228,37 @@ impl<T: UsbContext> DeviceHandle<T> {
         if request_type & LIBUSB_ENDPOINT_DIR_MASK != LIBUSB_ENDPOINT_IN {
             return Err(Error::InvalidParam);
         }
+        let start = self.start();
         let res = unsafe {
-            libusb_control_transfer(
+            interruptible::control(
+                self.context.as_raw(),
                 self.handle.as_ptr(),
                 request_type,
                 request,
                 value,
                 index,
-                buf.as_mut_ptr() as *mut c_uchar,
-                buf.len() as u16,
-                timeout.as_millis() as c_uint,
+                buf.as_mut_ptr(),
+                buf.len(),
+                timeout,
+                self.on_interrupt,
             )
         };
 
-        if res < 0 {
+        let res = if res < 0 {
             Err(error::from_libusb(res))
         } else {
             Ok(res as usize)
-        }
+        };
+
+        self.trace_control(request_type, request, value, index, buf.len(), start, &res);
+        self.log_transfer(
+            "control",
+            request_type & LIBUSB_ENDPOINT_DIR_MASK,
+            buf.len(),
+            start,
+            &res,
+        );
+        res
     }
 
     /// Writes data using a control transfer.
@@ -477,6 +1287,7 @@ impl<T: UsbContext> DeviceHandle<T> {
     /// * `Pipe` if the control request was not supported by the device.
     /// * `NoDevice` if the device has been disconnected.
     /// * `Io` if the transfer encountered an I/O error.
+    #[cfg_attr(feature = "async-only", deprecated(note = "blocks the calling thread"))]
     pub fn write_control(
         &self,
         request_type: u8,
@@ -489,68 +1300,388 @@ impl<T: UsbContext> DeviceHandle<T> {
         if request_type & LIBUSB_ENDPOINT_DIR_MASK != LIBUSB_ENDPOINT_OUT {
             return Err(Error::InvalidParam);
         }
+        self.check_writable()?;
+        let start = self.start();
         let res = unsafe {
-            libusb_control_transfer(
+            interruptible::control(
+                self.context.as_raw(),
                 self.handle.as_ptr(),
                 request_type,
                 request,
                 value,
                 index,
-                buf.as_ptr() as *mut c_uchar,
-                buf.len() as u16,
-                timeout.as_millis() as c_uint,
+                buf.as_ptr() as *mut u8,
+                buf.len(),
+                timeout,
+                self.on_interrupt,
             )
         };
 
-        if res < 0 {
+        let res = if res < 0 {
             Err(error::from_libusb(res))
         } else {
             Ok(res as usize)
+        };
+
+        self.trace_control(request_type, request, value, index, buf.len(), start, &res);
+        self.log_transfer(
+            "control",
+            request_type & LIBUSB_ENDPOINT_DIR_MASK,
+            buf.len(),
+            start,
+            &res,
+        );
+        res
+    }
+
+    /// Runs a sequence of control requests in order, e.g. a device initialization sequence
+    /// from a datasheet, and returns the response of each step.
+    ///
+    /// Each request is sent only once the previous one succeeded and its response passed the
+    /// verification set with [`ControlRequest::verify`](struct.ControlRequest.html#method.verify).
+    /// `timeout` applies to each request.
+    ///
+    /// ## Errors
+    ///
+    /// Stops at the first step whose transfer fails or whose response is rejected, and returns a
+    /// [`SequenceError`](struct.SequenceError.html) telling which step it was. Steps that
+    /// completed before it are not undone.
+    #[cfg_attr(feature = "async-only", deprecated(note = "blocks the calling thread"))]
+    pub fn run_sequence(
+        &self,
+        steps: &[ControlRequest<'_>],
+        timeout: Duration,
+    ) -> std::result::Result<Vec<Vec<u8>>, SequenceError> {
+        control_sequence::run(self, steps, timeout)
+    }
+
+    /// Reads the raw configuration descriptor at `index` into `buf`.
+    ///
+    /// The descriptor is read with its interface, endpoint and class-specific descriptors, i.e.
+    /// `wTotalLength` bytes, replacing the previous contents of `buf`. Reusing the same buffer
+    /// avoids allocating when descriptors are read repeatedly; pass the result to
+    /// [`ConfigDescriptorView::parse`](struct.ConfigDescriptorView.html#method.parse) to access
+    /// the fields without further allocation.
+    ///
+    /// ## Errors
+    ///
+    /// * `Other` if the device returned less data than the descriptor's length.
+    /// * Any error returned by the underlying control transfers.
+    #[cfg_attr(feature = "async-only", deprecated(note = "blocks the calling thread"))]
+    #[cfg_attr(feature = "async-only", allow(deprecated))]
+    pub fn read_config_descriptor_raw(
+        &self,
+        index: u8,
+        buf: &mut Vec<u8>,
+        timeout: Duration,
+    ) -> crate::Result<()> {
+        let timeout = self.descriptor_timeout.unwrap_or(timeout);
+        let value = u16::from(LIBUSB_DT_CONFIG) << 8 | u16::from(index);
+        let request_type = request_type(Direction::In, RequestType::Standard, Recipient::Device);
+
+        let mut header = [0u8; CONFIG_DESCRIPTOR_SIZE];
+        let len = self.read_control(
+            request_type,
+            LIBUSB_REQUEST_GET_DESCRIPTOR,
+            value,
+            0,
+            &mut header,
+            timeout,
+        )?;
+        if len < 4 {
+            return Err(Error::Other);
+        }
+
+        let total = u16::from_le_bytes([header[2], header[3]]) as usize;
+        buf.clear();
+        buf.resize(total, 0);
+
+        let len = self.read_control(
+            request_type,
+            LIBUSB_REQUEST_GET_DESCRIPTOR,
+            value,
+            0,
+            buf,
+            timeout,
+        )?;
+        if len < total {
+            return Err(Error::Other);
         }
+
+        Ok(())
     }
 
-    /// Reads the languages supported by the device's string descriptors.
+    /// Reads and decodes the Binary Object Store (BOS) descriptor, with its device
+    /// capabilities.
     ///
-    /// This function returns a list of languages that can be used to read the device's string
-    /// descriptors.
-    pub fn read_languages(&self, timeout: Duration) -> crate::Result<Vec<Language>> {
-        let mut buf = [0u8; 255];
+    /// Devices declaring USB 2.01 or later provide one, see
+    /// [`DeviceDescriptor::supports_bos`](struct.DeviceDescriptor.html#method.supports_bos).
+    /// libusb reads it with its own timeout, so the
+    /// [descriptor timeout](#method.set_descriptor_timeout) doesn't apply.
+    ///
+    /// ## Errors
+    ///
+    /// * `Pipe` if the device has no BOS descriptor.
+    /// * `Io` if the descriptor is malformed.
+    /// * Any error returned by the underlying control transfers.
+    #[cfg_attr(feature = "async-only", deprecated(note = "blocks the calling thread"))]
+    pub fn bos_descriptor(&self) -> crate::Result<BosDescriptor> {
+        let mut bos = mem::MaybeUninit::<*const libusb_bos_descriptor>::uninit();
+
+        try_unsafe!(libusb_get_bos_descriptor(
+            self.handle.as_ptr(),
+            bos.as_mut_ptr()
+        ));
+
+        Ok(unsafe { bos_descriptor::from_libusb(bos.assume_init()) })
+    }
 
+    /// Reads the raw Binary Object Store (BOS) descriptor into `buf`.
+    ///
+    /// The descriptor is read with its device capability descriptors, i.e. `wTotalLength` bytes,
+    /// replacing the previous contents of `buf`. Devices declaring USB 2.01 or later provide one,
+    /// see [`DeviceDescriptor::supports_bos`](struct.DeviceDescriptor.html#method.supports_bos).
+    ///
+    /// ## Errors
+    ///
+    /// * `Pipe` if the device has no BOS descriptor.
+    /// * `Other` if the device returned less data than the descriptor's length.
+    /// * Any error returned by the underlying control transfers.
+    #[cfg_attr(feature = "async-only", deprecated(note = "blocks the calling thread"))]
+    #[cfg_attr(feature = "async-only", allow(deprecated))]
+    pub fn read_bos_descriptor_raw(
+        &self,
+        buf: &mut Vec<u8>,
+        timeout: Duration,
+    ) -> crate::Result<()> {
+        let timeout = self.descriptor_timeout.unwrap_or(timeout);
+        let value = u16::from(LIBUSB_DT_BOS) << 8;
+        let request_type = request_type(Direction::In, RequestType::Standard, Recipient::Device);
+
+        let mut header = [0u8; 5];
         let len = self.read_control(
-            request_type(Direction::In, RequestType::Standard, Recipient::Device),
+            request_type,
             LIBUSB_REQUEST_GET_DESCRIPTOR,
-            u16::from(LIBUSB_DT_STRING) << 8,
+            value,
             0,
-            &mut buf,
+            &mut header,
             timeout,
         )?;
+        if len < 4 {
+            return Err(Error::Other);
+        }
+
+        let total = u16::from_le_bytes([header[2], header[3]]) as usize;
+        buf.clear();
+        buf.resize(total, 0);
 
-        if len < 2 || buf[0] != len as u8 || len & 0x01 != 0 {
-            // Consider making this `Error::BadDescriptor` on next breaking change.
+        let len = self.read_control(
+            request_type,
+            LIBUSB_REQUEST_GET_DESCRIPTOR,
+            value,
+            0,
+            buf,
+            timeout,
+        )?;
+        if len < total {
             return Err(Error::Other);
         }
 
-        if len == 2 {
-            return Ok(Vec::new());
+        Ok(())
+    }
+
+    /// Reads a descriptor of the device with a standard `GET_DESCRIPTOR` request, and returns
+    /// the number of bytes read into `buf`.
+    ///
+    /// `language` is the `wIndex` of the request: the language ID for string descriptors, and
+    /// zero for everything else. For descriptors addressed to an interface, e.g. HID report
+    /// descriptors, see [`read_interface_descriptor`](#method.read_interface_descriptor).
+    ///
+    /// ## Errors
+    ///
+    /// * `Pipe` if the device doesn't have such a descriptor.
+    /// * Any error returned by the underlying control transfer.
+    #[cfg_attr(feature = "async-only", deprecated(note = "blocks the calling thread"))]
+    #[cfg_attr(feature = "async-only", allow(deprecated))]
+    pub fn read_descriptor(
+        &self,
+        descriptor_type: DescriptorType,
+        index: u8,
+        language: u16,
+        buf: &mut [u8],
+        timeout: Duration,
+    ) -> crate::Result<usize> {
+        self.read_control(
+            request_type(Direction::In, RequestType::Standard, Recipient::Device),
+            LIBUSB_REQUEST_GET_DESCRIPTOR,
+            u16::from(u8::from(descriptor_type)) << 8 | u16::from(index),
+            language,
+            buf,
+            self.descriptor_timeout.unwrap_or(timeout),
+        )
+    }
+
+    /// Reads a descriptor of kind `K` with a standard `GET_DESCRIPTOR` request, and returns the
+    /// number of bytes read into `buf`.
+    ///
+    /// Unlike [`read_descriptor`](#method.read_descriptor), the kind is checked against the reply:
+    /// it must start with the expected `bDescriptorType` and a valid `bLength`, and the device
+    /// must have returned the whole descriptor, unless it didn't fit in `buf`. The kinds are in
+    /// [`descriptor_types`](descriptor_types/index.html). `language` is the language ID for
+    /// string descriptors, and zero for everything else.
+    ///
+    /// ## Errors
+    ///
+    /// * `Pipe` if the device doesn't have such a descriptor.
+    /// * `Other` if the reply isn't a descriptor of kind `K`.
+    /// * Any error returned by the underlying control transfer.
+    #[cfg_attr(feature = "async-only", deprecated(note = "blocks the calling thread"))]
+    #[cfg_attr(feature = "async-only", allow(deprecated))]
+    pub fn get_descriptor<K: DescriptorKind>(
+        &self,
+        index: u8,
+        language: u16,
+        buf: &mut [u8],
+        timeout: Duration,
+    ) -> crate::Result<usize> {
+        let len = self.read_descriptor(K::TYPE.into(), index, language, buf, timeout)?;
+        descriptor_types::check::<K>(&buf[..len], buf.len())?;
+        Ok(len)
+    }
+
+    /// Reads a descriptor addressed to an interface with a standard `GET_DESCRIPTOR` request,
+    /// e.g. the HID report descriptor of `interface`, and returns the number of bytes read into
+    /// `buf`.
+    ///
+    /// ## Errors
+    ///
+    /// * `Pipe` if the interface doesn't have such a descriptor.
+    /// * Any error returned by the underlying control transfer.
+    #[cfg_attr(feature = "async-only", deprecated(note = "blocks the calling thread"))]
+    #[cfg_attr(feature = "async-only", allow(deprecated))]
+    pub fn read_interface_descriptor(
+        &self,
+        interface: u8,
+        descriptor_type: DescriptorType,
+        index: u8,
+        buf: &mut [u8],
+        timeout: Duration,
+    ) -> crate::Result<usize> {
+        self.read_control(
+            request_type(Direction::In, RequestType::Standard, Recipient::Interface),
+            LIBUSB_REQUEST_GET_DESCRIPTOR,
+            u16::from(u8::from(descriptor_type)) << 8 | u16::from(index),
+            u16::from(interface),
+            buf,
+            self.descriptor_timeout.unwrap_or(timeout),
+        )
+    }
+
+    /// Reads the HID report descriptor of `interface`, which [`hid::ReportDescriptor`] parses.
+    ///
+    /// The length of the report descriptor is taken from the interface's HID descriptor in the
+    /// active configuration, falling back to 4096 bytes, the largest report descriptor Linux
+    /// accepts, if the interface has none.
+    ///
+    /// [`hid::ReportDescriptor`]: hid/struct.ReportDescriptor.html
+    ///
+    /// ## Errors
+    ///
+    /// * `Pipe` if the interface isn't a HID interface.
+    /// * Any error returned by the underlying control transfer.
+    #[cfg_attr(feature = "async-only", deprecated(note = "blocks the calling thread"))]
+    #[cfg_attr(feature = "async-only", allow(deprecated))]
+    pub fn hid_report_descriptor(
+        &self,
+        interface: u8,
+        timeout: Duration,
+    ) -> crate::Result<Vec<u8>> {
+        let len = self
+            .device()
+            .active_config_descriptor()
+            .ok()
+            .and_then(|config| hid_report_descriptor_len(&config, interface))
+            .unwrap_or(MAX_HID_REPORT_DESCRIPTOR_LEN);
+
+        let allocator = buffer_allocator::get(self.context.as_raw());
+        let mut buf = buffer_allocator::allocate(allocator.as_ref(), usize::from(len));
+        match self.read_interface_descriptor(
+            interface,
+            DescriptorType::Report,
+            0,
+            &mut buf,
+            timeout,
+        ) {
+            Ok(len) => {
+                buf.truncate(len);
+                Ok(buf)
+            }
+            Err(e) => {
+                buffer_allocator::release(allocator.as_ref(), buf);
+                Err(e)
+            }
         }
+    }
+
+    /// Reads the languages supported by the device's string descriptors.
+    ///
+    /// This function returns a list of languages that can be used to read the device's string
+    /// descriptors.
+    #[cfg_attr(feature = "async-only", deprecated(note = "blocks the calling thread"))]
+    #[cfg_attr(feature = "async-only", allow(deprecated))]
+    pub fn read_languages(&self, timeout: Duration) -> crate::Result<Vec<Language>> {
+        let mut buf = [0u8; 255];
+
+        let len = self.read_descriptor(DescriptorType::String, 0, 0, &mut buf, timeout)?;
+
+        Ok(
+            descriptor_view::parse_lang_ids(&buf[..len], self.parse_mode)?
+                .into_iter()
+                .map(crate::language::from_lang_id)
+                .collect(),
+        )
+    }
+
+    /// Reads the manufacturer, product and serial number strings in every language supported by
+    /// the device, keyed by `LANGID`.
+    ///
+    /// This is meant for checking the localization of device firmware. A string that fails to
+    /// read in one language is reported in that language's entry instead of failing the whole
+    /// call; only a failure to read the list of languages is returned as an error.
+    #[cfg_attr(feature = "async-only", deprecated(note = "blocks the calling thread"))]
+    #[cfg_attr(feature = "async-only", allow(deprecated))]
+    pub fn read_strings_all_languages(
+        &self,
+        device: &DeviceDescriptor,
+        timeout: Duration,
+    ) -> crate::Result<BTreeMap<u16, DeviceStrings>> {
+        let read = |language, index: Option<u8>| {
+            index.map(|n| self.read_string_descriptor(language, n, timeout))
+        };
 
-        Ok(buf[0..len]
-            .chunks(2)
-            .skip(1)
-            .map(|chunk| {
-                let lang_id = u16::from(chunk[0]) | u16::from(chunk[1]) << 8;
-                crate::language::from_lang_id(lang_id)
+        Ok(self
+            .read_languages(timeout)?
+            .into_iter()
+            .map(|language| {
+                let strings = DeviceStrings::new(
+                    language,
+                    read(language, device.manufacturer_string_index()),
+                    read(language, device.product_string_index()),
+                    read(language, device.serial_number_string_index()),
+                );
+                (language.lang_id(), strings)
             })
             .collect())
     }
 
     /// Reads a ascii string descriptor from the device.
     ///
+    #[cfg_attr(feature = "async-only", deprecated(note = "blocks the calling thread"))]
     pub fn read_string_descriptor_ascii(&self, index: u8) -> crate::Result<String> {
         let mut buf = Vec::<u8>::with_capacity(255);
 
         let ptr = buf.as_mut_ptr() as *mut c_uchar;
-        let len = buf.len() as i32;
+        let len = buf.capacity() as i32;
 
         let res =
             unsafe { libusb_get_string_descriptor_ascii(self.handle.as_ptr(), index, ptr, len) };
@@ -569,6 +1700,8 @@ impl<T: UsbContext> DeviceHandle<T> {
     /// Reads a string descriptor from the device.
     ///
     /// `language` should be one of the languages returned from [`read_languages`](#method.read_languages).
+    #[cfg_attr(feature = "async-only", deprecated(note = "blocks the calling thread"))]
+    #[cfg_attr(feature = "async-only", allow(deprecated))]
     pub fn read_string_descriptor(
         &self,
         language: Language,
@@ -577,34 +1710,55 @@ impl<T: UsbContext> DeviceHandle<T> {
     ) -> crate::Result<String> {
         let mut buf = [0u8; 255];
 
-        let len = self.read_control(
-            request_type(Direction::In, RequestType::Standard, Recipient::Device),
-            LIBUSB_REQUEST_GET_DESCRIPTOR,
-            u16::from(LIBUSB_DT_STRING) << 8 | u16::from(index),
+        let len = self.read_descriptor(
+            DescriptorType::String,
+            index,
             language.lang_id(),
             &mut buf,
             timeout,
         )?;
 
-        if len < 2 || buf[0] != len as u8 || len & 0x01 != 0 {
-            // Consider making this `Error::BadDescriptor` on next breaking change.
-            return Err(Error::Other);
-        }
+        descriptor_view::parse_string(&buf[..len], self.parse_mode)
+    }
 
-        if len == 2 {
-            return Ok(String::new());
-        }
+    /// Returns a reader of string descriptors that caches what it reads in this handle.
+    ///
+    /// The cache is shared by every caller of this method, from any thread.
+    pub fn cached_strings(&self) -> CachedStrings<'_, T> {
+        CachedStrings::new(self, &self.strings)
+    }
 
-        let utf16: Vec<u16> = buf[..len]
-            .chunks(2)
-            .skip(1)
-            .map(|chunk| u16::from(chunk[0]) | u16::from(chunk[1]) << 8)
-            .collect();
+    /// Returns the single-line identity of the device, see
+    /// [`Device::profile_string`](struct.Device.html#method.profile_string).
+    ///
+    /// The serial number is read through the [string cache](#method.cached_strings), so only the
+    /// first call accesses the device.
+    #[cfg_attr(feature = "async-only", deprecated(note = "blocks the calling thread"))]
+    #[cfg_attr(feature = "async-only", allow(deprecated))]
+    pub fn profile_string(&self) -> String {
+        let device = self.device();
+        let descriptor = device.device_descriptor().ok();
+        let serial = descriptor
+            .as_ref()
+            .and_then(|d| d.serial_number_string_index())
+            .map(|index| self.cached_strings().read_string_descriptor_ascii(index));
+        device.profile(
+            descriptor.as_ref(),
+            Serial::of(descriptor.as_ref(), serial.as_ref()),
+        )
+    }
 
-        String::from_utf16(&utf16).map_err(|_| Error::Other)
+    /// Returns a reader of the device's manufacturer, product and serial number strings that
+    /// picks a language on its own, see [`Strings`](struct.Strings.html).
+    ///
+    /// It shares the cache of [`cached_strings`](#method.cached_strings).
+    pub fn strings(&self) -> Strings<'_, T> {
+        Strings::new(self, &self.strings)
     }
 
     /// Reads the device's manufacturer string descriptor (ascii).
+    #[cfg_attr(feature = "async-only", deprecated(note = "blocks the calling thread"))]
+    #[cfg_attr(feature = "async-only", allow(deprecated))]
     pub fn read_manufacturer_string_ascii(
         &self,
         device: &DeviceDescriptor,
@@ -616,6 +1770,8 @@ impl<T: UsbContext> DeviceHandle<T> {
     }
 
     /// Reads the device's manufacturer string descriptor.
+    #[cfg_attr(feature = "async-only", deprecated(note = "blocks the calling thread"))]
+    #[cfg_attr(feature = "async-only", allow(deprecated))]
     pub fn read_manufacturer_string(
         &self,
         language: Language,
@@ -629,6 +1785,8 @@ impl<T: UsbContext> DeviceHandle<T> {
     }
 
     /// Reads the device's product string descriptor (ascii).
+    #[cfg_attr(feature = "async-only", deprecated(note = "blocks the calling thread"))]
+    #[cfg_attr(feature = "async-only", allow(deprecated))]
     pub fn read_product_string_ascii(&self, device: &DeviceDescriptor) -> crate::Result<String> {
         match device.product_string_index() {
             None => Err(Error::InvalidParam),
@@ -637,6 +1795,8 @@ impl<T: UsbContext> DeviceHandle<T> {
     }
 
     /// Reads the device's product string descriptor.
+    #[cfg_attr(feature = "async-only", deprecated(note = "blocks the calling thread"))]
+    #[cfg_attr(feature = "async-only", allow(deprecated))]
     pub fn read_product_string(
         &self,
         language: Language,
@@ -650,6 +1810,8 @@ impl<T: UsbContext> DeviceHandle<T> {
     }
 
     /// Reads the device's serial number string descriptor (ascii).
+    #[cfg_attr(feature = "async-only", deprecated(note = "blocks the calling thread"))]
+    #[cfg_attr(feature = "async-only", allow(deprecated))]
     pub fn read_serial_number_string_ascii(
         &self,
         device: &DeviceDescriptor,
@@ -661,6 +1823,8 @@ impl<T: UsbContext> DeviceHandle<T> {
     }
 
     /// Reads the device's serial number string descriptor.
+    #[cfg_attr(feature = "async-only", deprecated(note = "blocks the calling thread"))]
+    #[cfg_attr(feature = "async-only", allow(deprecated))]
     pub fn read_serial_number_string(
         &self,
         language: Language,
@@ -674,6 +1838,8 @@ impl<T: UsbContext> DeviceHandle<T> {
     }
 
     /// Reads the string descriptor for a configuration's description.
+    #[cfg_attr(feature = "async-only", deprecated(note = "blocks the calling thread"))]
+    #[cfg_attr(feature = "async-only", allow(deprecated))]
     pub fn read_configuration_string(
         &self,
         language: Language,
@@ -687,6 +1853,8 @@ impl<T: UsbContext> DeviceHandle<T> {
     }
 
     /// Reads the string descriptor for a interface's description.
+    #[cfg_attr(feature = "async-only", deprecated(note = "blocks the calling thread"))]
+    #[cfg_attr(feature = "async-only", allow(deprecated))]
     pub fn read_interface_string(
         &self,
         language: Language,
@@ -700,14 +1868,224 @@ impl<T: UsbContext> DeviceHandle<T> {
     }
 }
 
-#[doc(hidden)]
+impl<T: UsbContext + 'static> DeviceHandle<T> {
+    /// Reads up to `len` bytes from a bulk endpoint, as a future resolving to the data read.
+    ///
+    /// The transfer is submitted when the future is first polled; see
+    /// [`ReadFuture`](struct.ReadFuture.html) for what drives it to completion. The future holds
+    /// a clone of the `Arc`, as do those of the other `*_async` methods, so the handle stays open
+    /// until the transfer completed. The errors are those of [`read_bulk`](#method.read_bulk),
+    /// reported by the future.
+    pub fn read_bulk_async(
+        self: &Arc<Self>,
+        endpoint: u8,
+        len: usize,
+        timeout: Duration,
+    ) -> ReadFuture {
+        self.read_async(LIBUSB_TRANSFER_TYPE_BULK, endpoint, len, timeout)
+    }
+
+    /// Writes `data` to a bulk endpoint, as a future resolving to the number of bytes written.
+    ///
+    /// The errors are those of [`write_bulk`](#method.write_bulk), reported by the future.
+    pub fn write_bulk_async(
+        self: &Arc<Self>,
+        endpoint: u8,
+        data: Vec<u8>,
+        timeout: Duration,
+    ) -> WriteFuture {
+        self.write_async(LIBUSB_TRANSFER_TYPE_BULK, endpoint, data, timeout)
+    }
+
+    /// Reads up to `len` bytes from an interrupt endpoint, as a future resolving to the data read.
+    ///
+    /// The errors are those of [`read_interrupt`](#method.read_interrupt), reported by the
+    /// future.
+    pub fn read_interrupt_async(
+        self: &Arc<Self>,
+        endpoint: u8,
+        len: usize,
+        timeout: Duration,
+    ) -> ReadFuture {
+        self.read_async(LIBUSB_TRANSFER_TYPE_INTERRUPT, endpoint, len, timeout)
+    }
+
+    /// Writes `data` to an interrupt endpoint, as a future resolving to the number of bytes
+    /// written.
+    ///
+    /// The errors are those of [`write_interrupt`](#method.write_interrupt), reported by the
+    /// future.
+    pub fn write_interrupt_async(
+        self: &Arc<Self>,
+        endpoint: u8,
+        data: Vec<u8>,
+        timeout: Duration,
+    ) -> WriteFuture {
+        self.write_async(LIBUSB_TRANSFER_TYPE_INTERRUPT, endpoint, data, timeout)
+    }
+
+    /// Reads up to `len` bytes using a control transfer, as a future resolving to the data read.
+    ///
+    /// The errors are those of [`read_control`](#method.read_control), reported by the future.
+    pub fn read_control_async(
+        self: &Arc<Self>,
+        request_type: u8,
+        request: u8,
+        value: u16,
+        index: u16,
+        len: usize,
+        timeout: Duration,
+    ) -> ReadFuture {
+        if request_type & LIBUSB_ENDPOINT_DIR_MASK != LIBUSB_ENDPOINT_IN || len > u16::MAX as usize
+        {
+            return ReadFuture::failed(Error::InvalidParam);
+        }
+        ReadFuture::control(
+            self,
+            request_type,
+            request,
+            value,
+            index,
+            len,
+            timeout,
+            buffer_allocator::get(self.context.as_raw()),
+        )
+    }
+
+    /// Writes `data` using a control transfer, as a future resolving to the number of bytes
+    /// written. `data` is copied into the transfer, after the setup packet.
+    ///
+    /// The errors are those of [`write_control`](#method.write_control), reported by the future.
+    pub fn write_control_async(
+        self: &Arc<Self>,
+        request_type: u8,
+        request: u8,
+        value: u16,
+        index: u16,
+        data: &[u8],
+        timeout: Duration,
+    ) -> WriteFuture {
+        if request_type & LIBUSB_ENDPOINT_DIR_MASK != LIBUSB_ENDPOINT_OUT
+            || data.len() > u16::MAX as usize
+        {
+            return WriteFuture::failed(Error::InvalidParam);
+        }
+        if let Err(e) = self.check_writable() {
+            return WriteFuture::failed(e);
+        }
+        WriteFuture::control(
+            self,
+            request_type,
+            request,
+            value,
+            index,
+            data,
+            timeout,
+            buffer_allocator::get(self.context.as_raw()),
+        )
+    }
+
+    fn read_async(
+        self: &Arc<Self>,
+        transfer_type: u8,
+        endpoint: u8,
+        len: usize,
+        timeout: Duration,
+    ) -> ReadFuture {
+        if endpoint & LIBUSB_ENDPOINT_DIR_MASK != LIBUSB_ENDPOINT_IN {
+            return ReadFuture::failed(Error::InvalidParam);
+        }
+        ReadFuture::new(
+            self,
+            transfer_type,
+            endpoint,
+            len,
+            timeout,
+            buffer_allocator::get(self.context.as_raw()),
+        )
+    }
+
+    fn write_async(
+        self: &Arc<Self>,
+        transfer_type: u8,
+        endpoint: u8,
+        data: Vec<u8>,
+        timeout: Duration,
+    ) -> WriteFuture {
+        if endpoint & LIBUSB_ENDPOINT_DIR_MASK != LIBUSB_ENDPOINT_OUT {
+            return WriteFuture::failed(Error::InvalidParam);
+        }
+        if let Err(e) = self.check_writable() {
+            return WriteFuture::failed(e);
+        }
+        WriteFuture::new(self, transfer_type, endpoint, data, timeout)
+    }
+}
+
+/// Names a bulk or interrupt transfer operation after its direction.
+fn transfer_operation(transfer_type: &'static str, endpoint: u8) -> &'static str {
+    let read = endpoint & LIBUSB_ENDPOINT_DIR_MASK == LIBUSB_ENDPOINT_IN;
+    match (transfer_type, read) {
+        ("bulk", true) => "read_bulk",
+        ("bulk", false) => "write_bulk",
+        (_, true) => "read_interrupt",
+        (_, false) => "write_interrupt",
+    }
+}
+
+/// The length of the report descriptor read when it is unknown, which is the largest Linux accepts
+/// (`HID_MAX_DESCRIPTOR_SIZE`). The HID specification itself only bounds it by its 16-bit length
+/// field.
+const MAX_HID_REPORT_DESCRIPTOR_LEN: u16 = 4096;
+
+/// Returns the report descriptor length from the HID descriptor of `interface` in `config`.
+fn hid_report_descriptor_len(config: &ConfigDescriptor, interface: u8) -> Option<u16> {
+    for candidate in config.interfaces().filter(|i| i.number() == interface) {
+        for setting in candidate.descriptors() {
+            for descriptor in setting.class_descriptors() {
+                if let ClassDescriptor::Hid(hid) = descriptor {
+                    return hid.report_descriptor_length();
+                }
+            }
+        }
+    }
+    None
+}
+
 pub(crate) unsafe fn from_libusb<T: UsbContext>(
     context: T,
     handle: *mut libusb_device_handle,
 ) -> DeviceHandle<T> {
-    DeviceHandle {
-        context: context,
+    #[cfg(feature = "leak-detection")]
+    crate::leak_detection::track(
+        crate::leak_detection::ResourceKind::DeviceHandle,
+        handle,
+        context.as_raw(),
+    );
+
+    let handle = DeviceHandle {
+        context,
         handle: NonNull::new_unchecked(handle),
-        interfaces: BitSet::with_capacity(u8::max_value() as usize + 1),
+        interfaces: BitSet::with_capacity(u8::MAX as usize + 1),
+        alt_settings: BTreeMap::new(),
+        detached: BitSet::with_capacity(u8::MAX as usize + 1),
+        clear_halt_on_claim: false,
+        on_interrupt: OnInterrupt::Retry,
+        parse_mode: ParseMode::Strict,
+        descriptor_timeout: None,
+        strings: StringCache::default(),
+        trace: OperationTrace::new(operation_trace::DEFAULT_CAPACITY),
+        options: OpenOptions::new(),
+        _lock: None,
+    };
+
+    if event_log::is_enabled() {
+        event_log::record(
+            handle
+                .event(EventKind::Opened)
+                .text("device", handle.device().quick_profile()),
+        );
     }
+
+    handle
 }
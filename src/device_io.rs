@@ -0,0 +1,111 @@
+use std::time::Duration;
+
+use crate::{DeviceHandle, UsbContext};
+
+/// Synchronous endpoint I/O on an open device.
+///
+/// This trait is implemented by [`DeviceHandle`](struct.DeviceHandle.html) and, with the `fake`
+/// feature, by the pure-Rust [`FakeDevice`](fake/struct.FakeDevice.html). Code that only needs to
+/// exchange data with a device can be written against `DeviceIo` and then tested without calling
+/// into `libusb`, e.g. under Miri or the sanitizers.
+///
+/// The methods have the same semantics and errors as the `DeviceHandle` methods of the same name.
+pub trait DeviceIo {
+    /// Reads from an interrupt endpoint.
+    #[cfg_attr(feature = "async-only", deprecated(note = "blocks the calling thread"))]
+    fn read_interrupt(
+        &self,
+        endpoint: u8,
+        buf: &mut [u8],
+        timeout: Duration,
+    ) -> crate::Result<usize>;
+
+    /// Writes to an interrupt endpoint.
+    #[cfg_attr(feature = "async-only", deprecated(note = "blocks the calling thread"))]
+    fn write_interrupt(&self, endpoint: u8, buf: &[u8], timeout: Duration) -> crate::Result<usize>;
+
+    /// Reads from a bulk endpoint.
+    #[cfg_attr(feature = "async-only", deprecated(note = "blocks the calling thread"))]
+    fn read_bulk(&self, endpoint: u8, buf: &mut [u8], timeout: Duration) -> crate::Result<usize>;
+
+    /// Writes to a bulk endpoint.
+    #[cfg_attr(feature = "async-only", deprecated(note = "blocks the calling thread"))]
+    fn write_bulk(&self, endpoint: u8, buf: &[u8], timeout: Duration) -> crate::Result<usize>;
+
+    /// Reads data using a control transfer.
+    #[cfg_attr(feature = "async-only", deprecated(note = "blocks the calling thread"))]
+    fn read_control(
+        &self,
+        request_type: u8,
+        request: u8,
+        value: u16,
+        index: u16,
+        buf: &mut [u8],
+        timeout: Duration,
+    ) -> crate::Result<usize>;
+
+    /// Writes data using a control transfer.
+    #[cfg_attr(feature = "async-only", deprecated(note = "blocks the calling thread"))]
+    fn write_control(
+        &self,
+        request_type: u8,
+        request: u8,
+        value: u16,
+        index: u16,
+        buf: &[u8],
+        timeout: Duration,
+    ) -> crate::Result<usize>;
+}
+
+impl<T: UsbContext> DeviceIo for DeviceHandle<T> {
+    #[cfg_attr(feature = "async-only", allow(deprecated))]
+    fn read_interrupt(
+        &self,
+        endpoint: u8,
+        buf: &mut [u8],
+        timeout: Duration,
+    ) -> crate::Result<usize> {
+        DeviceHandle::read_interrupt(self, endpoint, buf, timeout)
+    }
+
+    #[cfg_attr(feature = "async-only", allow(deprecated))]
+    fn write_interrupt(&self, endpoint: u8, buf: &[u8], timeout: Duration) -> crate::Result<usize> {
+        DeviceHandle::write_interrupt(self, endpoint, buf, timeout)
+    }
+
+    #[cfg_attr(feature = "async-only", allow(deprecated))]
+    fn read_bulk(&self, endpoint: u8, buf: &mut [u8], timeout: Duration) -> crate::Result<usize> {
+        DeviceHandle::read_bulk(self, endpoint, buf, timeout)
+    }
+
+    #[cfg_attr(feature = "async-only", allow(deprecated))]
+    fn write_bulk(&self, endpoint: u8, buf: &[u8], timeout: Duration) -> crate::Result<usize> {
+        DeviceHandle::write_bulk(self, endpoint, buf, timeout)
+    }
+
+    #[cfg_attr(feature = "async-only", allow(deprecated))]
+    fn read_control(
+        &self,
+        request_type: u8,
+        request: u8,
+        value: u16,
+        index: u16,
+        buf: &mut [u8],
+        timeout: Duration,
+    ) -> crate::Result<usize> {
+        DeviceHandle::read_control(self, request_type, request, value, index, buf, timeout)
+    }
+
+    #[cfg_attr(feature = "async-only", allow(deprecated))]
+    fn write_control(
+        &self,
+        request_type: u8,
+        request: u8,
+        value: u16,
+        index: u16,
+        buf: &[u8],
+        timeout: Duration,
+    ) -> crate::Result<usize> {
+        DeviceHandle::write_control(self, request_type, request, value, index, buf, timeout)
+    }
+}
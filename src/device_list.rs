@@ -5,7 +5,8 @@ use std::{mem, slice};
 use crate::{
     context::{GlobalContext, UsbContext},
     device::{self, Device},
-    error,
+    device_descriptor::{self, DeviceDescriptor},
+    device_policy, error,
 };
 use libusb1_sys::*;
 
@@ -14,8 +15,14 @@ pub struct DeviceList<T: UsbContext> {
     context: T,
     list: *const *mut libusb_device,
     len: usize,
+    /// The devices the policy of the context permits, if it has one.
+    permitted: Option<Vec<*mut libusb_device>>,
 }
 
+// The list is an array of device references, which libusb lets any thread use.
+unsafe impl<T: UsbContext + Send> Send for DeviceList<T> {}
+unsafe impl<T: UsbContext + Sync> Sync for DeviceList<T> {}
+
 impl<T: UsbContext> Drop for DeviceList<T> {
     /// Frees the device list.
     fn drop(&mut self) {
@@ -36,11 +43,7 @@ impl DeviceList<GlobalContext> {
             Err(error::from_libusb(n as c_int))
         } else {
             Ok(unsafe {
-                DeviceList {
-                    context: Default::default(),
-                    list: list.assume_init(),
-                    len: n as usize,
-                }
+                DeviceList::from_libusb(Default::default(), list.assume_init(), n as usize)
             })
         }
     }
@@ -55,36 +58,227 @@ impl<T: UsbContext> DeviceList<T> {
         if len < 0 {
             Err(error::from_libusb(len as c_int))
         } else {
-            Ok(unsafe {
-                DeviceList {
-                    context,
-                    list: list.assume_init(),
-                    len: len as usize,
-                }
-            })
+            Ok(unsafe { DeviceList::from_libusb(context, list.assume_init(), len as usize) })
+        }
+    }
+
+    unsafe fn from_libusb(
+        context: T,
+        list: *const *mut libusb_device,
+        len: usize,
+    ) -> DeviceList<T> {
+        let permitted = device_policy::get(context.as_raw()).map(|_| {
+            slice::from_raw_parts(list, len)
+                .iter()
+                .copied()
+                .filter(|&device| device_policy::permits(context.as_raw(), device))
+                .collect()
+        });
+
+        DeviceList {
+            context,
+            list,
+            len,
+            permitted,
         }
     }
 
     /// Returns the number of devices in the list.
     pub fn len(&self) -> usize {
-        self.len
+        self.raw().len()
     }
 
     /// Returns true if the list is empty, else returns false.
     pub fn is_empty(&self) -> bool {
-        self.len == 0
+        self.raw().is_empty()
     }
 
     /// Returns an iterator over the devices in the list.
     ///
     /// The iterator yields a sequence of `Device` objects.
-    pub fn iter(&self) -> Devices<T> {
+    pub fn iter(&self) -> Devices<'_, T> {
         Devices {
             context: self.context.clone(),
-            devices: unsafe { slice::from_raw_parts(self.list, self.len) },
+            devices: self.raw(),
             index: 0,
         }
     }
+
+    /// Reads the descriptor, bus number and address of every device in the list once.
+    ///
+    /// Iterating with [`iter`](#method.iter) creates a `Device` for every entry, taking and
+    /// releasing a libusb reference each time, and filtering by descriptor fields re-reads the
+    /// descriptors on every pass. Enumeration paths that filter the same list repeatedly can
+    /// instead build the cache once and only create `Device`s for the entries they select.
+    pub fn cache(&self) -> DeviceCache<'_, T> {
+        let entries = self
+            .raw()
+            .iter()
+            .filter_map(|&device| unsafe { CachedDevice::read(self, device) })
+            .collect();
+
+        DeviceCache { entries }
+    }
+
+    /// Returns the first non-`None` result of `f` applied to the cached information of each
+    /// device, without creating a `Device` for entries `f` rejects.
+    ///
+    /// Devices whose descriptor can't be read are skipped.
+    pub fn find_map_cached<R, F>(&self, mut f: F) -> Option<R>
+    where
+        F: FnMut(&CachedDevice<'_, T>) -> Option<R>,
+    {
+        self.raw()
+            .iter()
+            .filter_map(|&device| unsafe { CachedDevice::read(self, device) })
+            .find_map(|entry| f(&entry))
+    }
+
+    /// Returns the devices ordered by their position in the USB tree, which stays the same across
+    /// runs as long as the devices stay plugged into the same ports.
+    ///
+    /// Devices are ordered by bus number, then by the ports leading to them, so each hub comes
+    /// right before the devices behind it, and finally by address.
+    pub fn sorted_by_topology(&self) -> Vec<Device<T>> {
+        let mut devices: Vec<_> = self
+            .iter()
+            .map(|device| (topology_key(&device), device))
+            .collect();
+        devices.sort_by(|(a, _), (b, _)| a.cmp(b));
+        devices.into_iter().map(|(_, device)| device).collect()
+    }
+
+    /// Returns the devices ordered by vendor ID and product ID, identical devices being ordered
+    /// by their position in the USB tree, see [`sorted_by_topology`](#method.sorted_by_topology).
+    ///
+    /// Devices whose descriptor can't be read come last.
+    pub fn sorted_by_id(&self) -> Vec<Device<T>> {
+        let mut devices: Vec<_> = self
+            .iter()
+            .map(|device| {
+                let id = device
+                    .device_descriptor()
+                    .map(|descriptor| (descriptor.vendor_id(), descriptor.product_id()));
+                (
+                    (id.is_err(), id.unwrap_or_default(), topology_key(&device)),
+                    device,
+                )
+            })
+            .collect();
+        devices.sort_by(|(a, _), (b, _)| a.cmp(b));
+        devices.into_iter().map(|(_, device)| device).collect()
+    }
+
+    fn raw(&self) -> &[*mut libusb_device] {
+        match self.permitted {
+            Some(ref permitted) => permitted,
+            None => unsafe { slice::from_raw_parts(self.list, self.len) },
+        }
+    }
+}
+
+/// Returns the key ordering `device` by its position in the USB tree. Root hubs have no ports,
+/// so they come before the devices on their bus.
+fn topology_key<T: UsbContext>(device: &Device<T>) -> (u8, Vec<u8>, u8) {
+    (
+        device.bus_number(),
+        device.port_numbers().unwrap_or_default(),
+        device.address(),
+    )
+}
+
+/// Pre-fetched information about the devices of a [`DeviceList`](struct.DeviceList.html).
+///
+/// The cache borrows the list, which keeps the devices referenced for as long as the cache exists.
+pub struct DeviceCache<'a, T: UsbContext> {
+    entries: Vec<CachedDevice<'a, T>>,
+}
+
+impl<'a, T: UsbContext> DeviceCache<'a, T> {
+    /// Returns the number of cached devices.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Returns true if no devices are cached.
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Returns an iterator over the cached devices.
+    pub fn iter(&self) -> std::slice::Iter<'_, CachedDevice<'a, T>> {
+        self.entries.iter()
+    }
+
+    /// Returns the first non-`None` result of `f` applied to each cached device.
+    pub fn find_map<R, F>(&self, f: F) -> Option<R>
+    where
+        F: FnMut(&CachedDevice<'a, T>) -> Option<R>,
+    {
+        self.entries.iter().find_map(f)
+    }
+
+    /// Returns the devices whose cached information is accepted by `f`.
+    pub fn filter<F>(&self, mut f: F) -> Vec<Device<T>>
+    where
+        F: FnMut(&CachedDevice<'a, T>) -> bool,
+    {
+        self.entries
+            .iter()
+            .filter(|entry| f(entry))
+            .map(|entry| entry.device())
+            .collect()
+    }
+}
+
+/// Information about a device of a [`DeviceList`](struct.DeviceList.html), read once when the
+/// cache was built.
+pub struct CachedDevice<'a, T: UsbContext> {
+    list: &'a DeviceList<T>,
+    device: *mut libusb_device,
+    descriptor: DeviceDescriptor,
+    bus_number: u8,
+    address: u8,
+}
+
+impl<'a, T: UsbContext> CachedDevice<'a, T> {
+    /// # Safety
+    ///
+    /// `device` must be one of the devices of `list`.
+    unsafe fn read(list: &'a DeviceList<T>, device: *mut libusb_device) -> Option<Self> {
+        let mut descriptor = mem::MaybeUninit::<libusb_device_descriptor>::uninit();
+        if libusb_get_device_descriptor(device, descriptor.as_mut_ptr()) != 0 {
+            return None;
+        }
+
+        Some(CachedDevice {
+            list,
+            device,
+            descriptor: device_descriptor::from_libusb(descriptor.assume_init()),
+            bus_number: libusb_get_bus_number(device),
+            address: libusb_get_device_address(device),
+        })
+    }
+
+    /// Returns the device descriptor.
+    pub fn descriptor(&self) -> &DeviceDescriptor {
+        &self.descriptor
+    }
+
+    /// Returns the number of the bus that the device is connected to.
+    pub fn bus_number(&self) -> u8 {
+        self.bus_number
+    }
+
+    /// Returns the device's address on the bus that it's connected to.
+    pub fn address(&self) -> u8 {
+        self.address
+    }
+
+    /// Creates a `Device` for this entry.
+    pub fn device(&self) -> Device<T> {
+        unsafe { device::from_libusb(self.list.context.clone(), self.device) }
+    }
 }
 
 /// Iterator over detected USB devices.
@@ -0,0 +1,163 @@
+use std::{
+    mem,
+    sync::{Arc, Mutex, MutexGuard},
+};
+
+use libusb1_sys::{
+    libusb_context, libusb_device, libusb_device_descriptor, libusb_get_device_descriptor,
+};
+
+use crate::device_descriptor::{self, DeviceDescriptor};
+
+/// The policies installed on contexts, keyed by the address of their `libusb_context`.
+static POLICIES: Mutex<Vec<(usize, Arc<DevicePolicy>)>> = Mutex::new(Vec::new());
+
+/// Decides which devices a context may touch, see
+/// [`UsbContext::set_device_policy`](trait.UsbContext.html#method.set_device_policy).
+///
+/// A device is permitted if it matches an allow rule, or if the policy allows every device by
+/// default, and it doesn't match any block rule. Rules match a vendor ID, and optionally a product
+/// ID.
+///
+/// ```
+/// use rusb::DevicePolicy;
+///
+/// // only the vendor's devices, except one product
+/// let policy = DevicePolicy::deny_all()
+///     .allow(0x1234, None)
+///     .block(0x1234, Some(0x0001));
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct DevicePolicy {
+    allow_by_default: bool,
+    allow: Vec<(u16, Option<u16>)>,
+    block: Vec<(u16, Option<u16>)>,
+}
+
+impl DevicePolicy {
+    /// Creates a policy permitting every device, for building a blocklist.
+    pub fn allow_all() -> DevicePolicy {
+        DevicePolicy {
+            allow_by_default: true,
+            allow: Vec::new(),
+            block: Vec::new(),
+        }
+    }
+
+    /// Creates a policy refusing every device, for building an allowlist.
+    pub fn deny_all() -> DevicePolicy {
+        DevicePolicy {
+            allow_by_default: false,
+            ..DevicePolicy::allow_all()
+        }
+    }
+
+    /// Permits the devices with `vendor_id`, and `product_id` if given.
+    pub fn allow(mut self, vendor_id: u16, product_id: Option<u16>) -> Self {
+        self.allow.push((vendor_id, product_id));
+        self
+    }
+
+    /// Refuses the devices with `vendor_id`, and `product_id` if given, even if an allow rule
+    /// matches them.
+    pub fn block(mut self, vendor_id: u16, product_id: Option<u16>) -> Self {
+        self.block.push((vendor_id, product_id));
+        self
+    }
+
+    /// Indicates whether the policy permits the device with `descriptor`.
+    pub fn permits(&self, descriptor: &DeviceDescriptor) -> bool {
+        let matches = |&(vendor_id, product_id): &(u16, Option<u16>)| {
+            vendor_id == descriptor.vendor_id()
+                && product_id.map_or(true, |p| p == descriptor.product_id())
+        };
+
+        (self.allow_by_default || self.allow.iter().any(matches)) && !self.block.iter().any(matches)
+    }
+}
+
+fn policies() -> MutexGuard<'static, Vec<(usize, Arc<DevicePolicy>)>> {
+    POLICIES.lock().unwrap_or_else(|p| p.into_inner())
+}
+
+/// Installs `policy` on `context`, or removes the policy of `context` if `None`.
+pub(crate) fn set(context: *mut libusb_context, policy: Option<DevicePolicy>) {
+    let mut policies = policies();
+    policies.retain(|(c, _)| *c != context as usize);
+    if let Some(policy) = policy {
+        policies.push((context as usize, Arc::new(policy)));
+    }
+}
+
+/// Returns the policy installed on `context`, if any.
+pub(crate) fn get(context: *mut libusb_context) -> Option<Arc<DevicePolicy>> {
+    let policies = policies();
+    policies
+        .iter()
+        .find(|(c, _)| *c == context as usize)
+        .map(|(_, policy)| policy.clone())
+}
+
+/// Indicates whether the policy of `context` permits `device`. Devices whose descriptor can't be
+/// read are refused when a policy is installed.
+///
+/// # Safety
+///
+/// `device` must be a valid device of `context`.
+pub(crate) unsafe fn permits(context: *mut libusb_context, device: *mut libusb_device) -> bool {
+    match get(context) {
+        Some(policy) => {
+            let mut descriptor = mem::MaybeUninit::<libusb_device_descriptor>::uninit();
+            libusb_get_device_descriptor(device, descriptor.as_mut_ptr()) == 0
+                && policy.permits(&device_descriptor::from_libusb(descriptor.assume_init()))
+        }
+        None => true,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn descriptor(vendor_id: u16, product_id: u16) -> DeviceDescriptor {
+        let [vl, vh] = vendor_id.to_le_bytes();
+        let [pl, ph] = product_id.to_le_bytes();
+        DeviceDescriptor::from_bytes(&[18, 1, 0, 2, 0, 0, 0, 64, vl, vh, pl, ph, 0, 1, 1, 2, 3, 1])
+            .unwrap()
+    }
+
+    #[test]
+    fn it_applies_allowlists() {
+        let policy = DevicePolicy::deny_all()
+            .allow(0x1234, None)
+            .allow(0x4321, Some(0x0002));
+
+        assert!(policy.permits(&descriptor(0x1234, 0x0001)));
+        assert!(policy.permits(&descriptor(0x4321, 0x0002)));
+        assert!(!policy.permits(&descriptor(0x4321, 0x0001)));
+        assert!(!policy.permits(&descriptor(0x5555, 0x0001)));
+    }
+
+    #[test]
+    fn it_lets_block_rules_win() {
+        let policy = DevicePolicy::allow_all()
+            .allow(0x1234, None)
+            .block(0x1234, Some(0x0001));
+
+        assert!(policy.permits(&descriptor(0x5555, 0x0001)));
+        assert!(policy.permits(&descriptor(0x1234, 0x0002)));
+        assert!(!policy.permits(&descriptor(0x1234, 0x0001)));
+    }
+
+    #[test]
+    fn it_keeps_policies_per_context() {
+        let (a, b) = (0x1000 as *mut libusb_context, 0x2000 as *mut libusb_context);
+
+        set(a, Some(DevicePolicy::deny_all()));
+        assert_eq!(Some(DevicePolicy::deny_all()), get(a).as_deref().cloned());
+        assert_eq!(None, get(b));
+
+        set(a, None);
+        assert_eq!(None, get(a));
+    }
+}
@@ -0,0 +1,99 @@
+use crate::{error::Error, language::Language};
+
+/// The device-level string descriptors read in one language, as returned by
+/// [`DeviceHandle::read_strings_all_languages`](struct.DeviceHandle.html#method.read_strings_all_languages).
+///
+/// Each string is `None` if the device descriptor doesn't reference one, and holds the error that
+/// occurred if it couldn't be read, so that a translation missing from the firmware shows up
+/// rather than hiding the others.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DeviceStrings {
+    language: Language,
+    manufacturer: Option<Result<String, Error>>,
+    product: Option<Result<String, Error>>,
+    serial_number: Option<Result<String, Error>>,
+}
+
+impl DeviceStrings {
+    pub(crate) fn new(
+        language: Language,
+        manufacturer: Option<Result<String, Error>>,
+        product: Option<Result<String, Error>>,
+        serial_number: Option<Result<String, Error>>,
+    ) -> DeviceStrings {
+        DeviceStrings {
+            language,
+            manufacturer,
+            product,
+            serial_number,
+        }
+    }
+
+    /// Returns the language the strings were read in.
+    pub fn language(&self) -> Language {
+        self.language
+    }
+
+    /// Returns the manufacturer string.
+    pub fn manufacturer(&self) -> Option<Result<&str, Error>> {
+        as_str(&self.manufacturer)
+    }
+
+    /// Returns the product string.
+    pub fn product(&self) -> Option<Result<&str, Error>> {
+        as_str(&self.product)
+    }
+
+    /// Returns the serial number string.
+    pub fn serial_number(&self) -> Option<Result<&str, Error>> {
+        as_str(&self.serial_number)
+    }
+
+    /// Indicates whether every string referenced by the device descriptor could be read.
+    pub fn is_complete(&self) -> bool {
+        [&self.manufacturer, &self.product, &self.serial_number]
+            .iter()
+            .all(|s| !matches!(s, Some(Err(_))))
+    }
+}
+
+fn as_str(string: &Option<Result<String, Error>>) -> Option<Result<&str, Error>> {
+    string
+        .as_ref()
+        .map(|s| s.as_ref().map(String::as_str).map_err(|e| *e))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::language::from_lang_id;
+
+    #[test]
+    fn it_exposes_strings() {
+        let strings = DeviceStrings::new(
+            from_lang_id(0x0409),
+            Some(Ok("ACME".to_string())),
+            None,
+            Some(Ok("1234".to_string())),
+        );
+
+        assert_eq!(0x0409, strings.language().lang_id());
+        assert_eq!(Some(Ok("ACME")), strings.manufacturer());
+        assert_eq!(None, strings.product());
+        assert_eq!(Some(Ok("1234")), strings.serial_number());
+        assert!(strings.is_complete());
+    }
+
+    #[test]
+    fn it_is_incomplete_when_a_string_failed() {
+        let strings = DeviceStrings::new(
+            from_lang_id(0x0407),
+            Some(Ok("ACME".to_string())),
+            Some(Err(Error::Pipe)),
+            None,
+        );
+
+        assert_eq!(Some(Err(Error::Pipe)), strings.product());
+        assert!(!strings.is_complete());
+    }
+}
@@ -0,0 +1,537 @@
+use std::{
+    fmt,
+    future::Future,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::{self, Sender},
+        Arc, Condvar, Mutex, MutexGuard,
+    },
+    task::{Context, Poll, Waker},
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+use crate::{device_io::DeviceIo, error::Error};
+
+type Command<D> = Box<dyn FnOnce(&mut D) + Send>;
+
+/// Owns a device on a dedicated thread, and performs the commands sent to it in order.
+///
+/// Applications sharing a device between threads often end up serializing every access through
+/// one thread anyway, e.g. because a device only handles one request at a time, or because a
+/// read and a write must not be interleaved with the requests of another thread. The worker is
+/// that thread: each command returns a [`Reply`](struct.Reply.html) right away, which receives
+/// the result once the command has been performed.
+///
+/// The worker is generic over [`DeviceIo`](trait.DeviceIo.html), so it can own a
+/// `DeviceHandle` or, in tests, a `FakeDevice`. [`run`](#method.run) performs any other
+/// operation, with exclusive access to the device.
+///
+/// Multi-step flows, e.g. programming an EEPROM with the helpers of the `eeprom` module, can run
+/// as one command with [`run_cancellable`](#method.run_cancellable), so async code awaits their
+/// [`Reply`](struct.Reply.html) instead of wrapping the blocking calls in a blocking task, and
+/// can cancel them between steps.
+///
+/// Dropping the worker waits for the commands already sent to be performed.
+pub struct DeviceWorker<D: DeviceIo + Send + 'static> {
+    commands: Option<Sender<Command<D>>>,
+    thread: Option<JoinHandle<D>>,
+}
+
+impl<D: DeviceIo + Send + 'static> DeviceWorker<D> {
+    /// Starts a worker thread owning `device`.
+    pub fn new(device: D) -> DeviceWorker<D> {
+        let (commands, receiver) = mpsc::channel::<Command<D>>();
+
+        let thread = thread::spawn(move || {
+            let mut device = device;
+            for command in receiver {
+                command(&mut device);
+            }
+            device
+        });
+
+        DeviceWorker {
+            commands: Some(commands),
+            thread: Some(thread),
+        }
+    }
+
+    /// Performs `f` with exclusive access to the device.
+    ///
+    /// A panic in `f` stops the worker: the commands sent afterwards fail with `Other`.
+    pub fn run<F, R>(&self, f: F) -> Reply<R>
+    where
+        F: FnOnce(&mut D) -> crate::Result<R> + Send + 'static,
+        R: Send + 'static,
+    {
+        self.run_cancellable(move |device, _| f(device))
+    }
+
+    /// Performs `f` with exclusive access to the device, passing it a token that tells whether
+    /// the command was [`cancel`](struct.Reply.html#method.cancel)led.
+    ///
+    /// A multi-step flow checks the token between its steps, and stops with `Interrupted` once
+    /// cancelled, see [`CancelToken::check`](struct.CancelToken.html#method.check).
+    pub fn run_cancellable<F, R>(&self, f: F) -> Reply<R>
+    where
+        F: FnOnce(&mut D, &CancelToken) -> crate::Result<R> + Send + 'static,
+        R: Send + 'static,
+    {
+        let slot = Arc::new(Slot::default());
+        let completion = Completion {
+            slot: Some(slot.clone()),
+        };
+        let token = CancelToken {
+            cancelled: slot.cancelled.clone(),
+        };
+
+        if let Some(commands) = &self.commands {
+            // if the worker is gone, the command is dropped with its completion, which fails the
+            // reply
+            commands
+                .send(Box::new(move |device: &mut D| {
+                    let result = match token.check() {
+                        Ok(()) => f(device, &token),
+                        Err(e) => Err(e),
+                    };
+                    completion.complete(result);
+                }))
+                .ok();
+        }
+
+        Reply { slot }
+    }
+
+    /// Reads up to `len` bytes from a bulk endpoint.
+    #[cfg_attr(feature = "async-only", allow(deprecated))]
+    pub fn read_bulk(&self, endpoint: u8, len: usize, timeout: Duration) -> Reply<Vec<u8>> {
+        self.run(move |device| {
+            let mut buf = vec![0; len];
+            let len = device.read_bulk(endpoint, &mut buf, timeout)?;
+            buf.truncate(len);
+            Ok(buf)
+        })
+    }
+
+    /// Writes `data` to a bulk endpoint, and replies with the number of bytes written.
+    #[cfg_attr(feature = "async-only", allow(deprecated))]
+    pub fn write_bulk(&self, endpoint: u8, data: Vec<u8>, timeout: Duration) -> Reply<usize> {
+        self.run(move |device| device.write_bulk(endpoint, &data, timeout))
+    }
+
+    /// Reads up to `len` bytes from an interrupt endpoint.
+    #[cfg_attr(feature = "async-only", allow(deprecated))]
+    pub fn read_interrupt(&self, endpoint: u8, len: usize, timeout: Duration) -> Reply<Vec<u8>> {
+        self.run(move |device| {
+            let mut buf = vec![0; len];
+            let len = device.read_interrupt(endpoint, &mut buf, timeout)?;
+            buf.truncate(len);
+            Ok(buf)
+        })
+    }
+
+    /// Writes `data` to an interrupt endpoint, and replies with the number of bytes written.
+    #[cfg_attr(feature = "async-only", allow(deprecated))]
+    pub fn write_interrupt(&self, endpoint: u8, data: Vec<u8>, timeout: Duration) -> Reply<usize> {
+        self.run(move |device| device.write_interrupt(endpoint, &data, timeout))
+    }
+
+    /// Reads up to `len` bytes using a control transfer.
+    #[cfg_attr(feature = "async-only", allow(deprecated))]
+    pub fn read_control(
+        &self,
+        request_type: u8,
+        request: u8,
+        value: u16,
+        index: u16,
+        len: usize,
+        timeout: Duration,
+    ) -> Reply<Vec<u8>> {
+        self.run(move |device| {
+            let mut buf = vec![0; len];
+            let len =
+                device.read_control(request_type, request, value, index, &mut buf, timeout)?;
+            buf.truncate(len);
+            Ok(buf)
+        })
+    }
+
+    /// Writes `data` using a control transfer, and replies with the number of bytes written.
+    #[cfg_attr(feature = "async-only", allow(deprecated))]
+    pub fn write_control(
+        &self,
+        request_type: u8,
+        request: u8,
+        value: u16,
+        index: u16,
+        data: Vec<u8>,
+        timeout: Duration,
+    ) -> Reply<usize> {
+        self.run(move |device| {
+            device.write_control(request_type, request, value, index, &data, timeout)
+        })
+    }
+
+    /// Stops the worker once the commands already sent have been performed, and returns the
+    /// device.
+    ///
+    /// Returns `None` if a command panicked, in which case the device was dropped.
+    pub fn into_inner(mut self) -> Option<D> {
+        self.stop()
+    }
+
+    fn stop(&mut self) -> Option<D> {
+        // closing the channel ends the loop of the thread
+        self.commands.take();
+        self.thread.take()?.join().ok()
+    }
+}
+
+impl<D: DeviceIo + Send + 'static> Drop for DeviceWorker<D> {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+impl<D: DeviceIo + Send + 'static> fmt::Debug for DeviceWorker<D> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("DeviceWorker").finish()
+    }
+}
+
+/// The result of a command, shared by its reply and the worker.
+struct Slot<R> {
+    state: Mutex<SlotState<R>>,
+    completed: Condvar,
+    cancelled: Arc<AtomicBool>,
+}
+
+struct SlotState<R> {
+    result: Option<crate::Result<R>>,
+    /// The result was taken already, by `try_wait`.
+    delivered: bool,
+    waker: Option<Waker>,
+}
+
+impl<R> SlotState<R> {
+    /// Takes the result if the command has been performed, or `NotFound` if it was taken before.
+    fn take(&mut self) -> Option<crate::Result<R>> {
+        if self.delivered {
+            return Some(Err(Error::NotFound));
+        }
+        let result = self.result.take();
+        self.delivered = result.is_some();
+        result
+    }
+}
+
+impl<R> Default for Slot<R> {
+    fn default() -> Slot<R> {
+        Slot {
+            state: Mutex::new(SlotState {
+                result: None,
+                delivered: false,
+                waker: None,
+            }),
+            completed: Condvar::new(),
+            cancelled: Arc::new(AtomicBool::new(false)),
+        }
+    }
+}
+
+impl<R> Slot<R> {
+    fn lock(&self) -> MutexGuard<'_, SlotState<R>> {
+        self.state.lock().unwrap_or_else(|p| p.into_inner())
+    }
+}
+
+/// Completes a reply, with `Other` if it is dropped first, e.g. because the command panicked or
+/// the worker stopped before performing it.
+struct Completion<R> {
+    slot: Option<Arc<Slot<R>>>,
+}
+
+impl<R> Completion<R> {
+    fn complete(mut self, result: crate::Result<R>) {
+        if let Some(slot) = self.slot.take() {
+            let waker = {
+                let mut state = slot.lock();
+                state.result = Some(result);
+                state.waker.take()
+            };
+            slot.completed.notify_all();
+            if let Some(waker) = waker {
+                waker.wake();
+            }
+        }
+    }
+}
+
+impl<R> Drop for Completion<R> {
+    fn drop(&mut self) {
+        if self.slot.is_some() {
+            Completion {
+                slot: self.slot.take(),
+            }
+            .complete(Err(Error::Other));
+        }
+    }
+}
+
+/// Tells a command run by [`DeviceWorker::run_cancellable`](struct.DeviceWorker.html#method.run_cancellable)
+/// whether its reply was cancelled.
+pub struct CancelToken {
+    cancelled: Arc<AtomicBool>,
+}
+
+impl CancelToken {
+    /// Indicates whether the command was cancelled.
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::SeqCst)
+    }
+
+    /// Fails with `Interrupted` if the command was cancelled, so a flow can stop with `?`
+    /// between its steps.
+    pub fn check(&self) -> crate::Result<()> {
+        if self.is_cancelled() {
+            Err(Error::Interrupted)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+impl fmt::Debug for CancelToken {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CancelToken")
+            .field("cancelled", &self.is_cancelled())
+            .finish()
+    }
+}
+
+/// The result of a command sent to a [`DeviceWorker`](struct.DeviceWorker.html), received once
+/// the worker has performed it.
+///
+/// The reply can be waited for, or awaited as a future from async code. The future is woken by
+/// the worker thread, so it doesn't depend on any executor.
+///
+/// Dropping the reply doesn't cancel the command, see [`cancel`](#method.cancel).
+pub struct Reply<R> {
+    slot: Arc<Slot<R>>,
+}
+
+impl<R> Reply<R> {
+    /// Waits for the command to be performed, and returns its result.
+    ///
+    /// ## Errors
+    ///
+    /// * `Interrupted` if the command was cancelled before the worker started it.
+    /// * `Other` if the worker stopped before performing the command, because an earlier command
+    ///   panicked.
+    /// * `NotFound` if the result was already returned by [`try_wait`](#method.try_wait).
+    /// * Any error returned by the command.
+    pub fn wait(self) -> crate::Result<R> {
+        let mut state = self.slot.lock();
+        loop {
+            if let Some(result) = state.take() {
+                return result;
+            }
+            state = self
+                .slot
+                .completed
+                .wait(state)
+                .unwrap_or_else(|p| p.into_inner());
+        }
+    }
+
+    /// Returns the result of the command if it has been performed, without waiting.
+    ///
+    /// The result is only returned once; afterwards, this and [`wait`](#method.wait) return
+    /// `NotFound`.
+    pub fn try_wait(&self) -> Option<crate::Result<R>> {
+        self.slot.lock().take()
+    }
+
+    /// Cancels the command.
+    ///
+    /// A command the worker hasn't started yet is skipped, and replies `Interrupted`. A command
+    /// already running only stops if it was sent with
+    /// [`run_cancellable`](struct.DeviceWorker.html#method.run_cancellable) and checks its
+    /// token; the transfer in progress, if any, still runs to completion or timeout.
+    pub fn cancel(&self) {
+        self.slot.cancelled.store(true, Ordering::SeqCst);
+    }
+}
+
+impl<R> Future for Reply<R> {
+    type Output = crate::Result<R>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<crate::Result<R>> {
+        let mut state = self.slot.lock();
+        match state.take() {
+            Some(result) => Poll::Ready(result),
+            None => {
+                state.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+impl<R> fmt::Debug for Reply<R> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Reply")
+            .field("cancelled", &self.slot.cancelled.load(Ordering::SeqCst))
+            .finish()
+    }
+}
+
+#[cfg(test)]
+#[cfg_attr(feature = "async-only", allow(deprecated))]
+mod test {
+    use super::*;
+    use crate::fake::FakeDevice;
+
+    const TIMEOUT: Duration = Duration::from_secs(1);
+
+    #[test]
+    fn it_performs_commands_in_order() {
+        let device = FakeDevice::new();
+        device.push_in(0x81, &[1, 2, 3]);
+        let worker = DeviceWorker::new(device);
+
+        let written = worker.write_bulk(0x02, vec![4, 5], TIMEOUT);
+        let read = worker.read_bulk(0x81, 8, TIMEOUT);
+
+        assert_eq!(Ok(2), written.wait());
+        assert_eq!(Ok(vec![1, 2, 3]), read.wait());
+        let device = worker.into_inner().unwrap();
+        assert_eq!(vec![vec![4, 5]], device.take_out(0x02));
+    }
+
+    #[test]
+    fn it_replies_with_transfer_errors() {
+        let worker = DeviceWorker::new(FakeDevice::new());
+
+        assert_eq!(
+            Err(Error::Timeout),
+            worker.read_bulk(0x81, 8, TIMEOUT).wait()
+        );
+    }
+
+    #[test]
+    fn it_runs_custom_commands() {
+        let worker = DeviceWorker::new(FakeDevice::new());
+
+        let reply = worker.run(|device| {
+            device.push_in(0x81, &[7]);
+            let mut buf = [0; 4];
+            device.read_bulk(0x81, &mut buf, TIMEOUT)
+        });
+
+        assert_eq!(Ok(1), reply.wait());
+    }
+
+    #[test]
+    fn it_returns_results_only_once() {
+        let worker = DeviceWorker::new(FakeDevice::new());
+
+        let reply = worker.run(|_| Ok(3));
+        let result = loop {
+            if let Some(result) = reply.try_wait() {
+                break result;
+            }
+            thread::yield_now();
+        };
+
+        assert_eq!(Ok(3), result);
+        assert_eq!(Some(Err(Error::NotFound)), reply.try_wait());
+        assert_eq!(Err(Error::NotFound), reply.wait());
+    }
+
+    #[test]
+    fn it_skips_cancelled_commands() {
+        let worker = DeviceWorker::new(FakeDevice::new());
+        let (release, gate) = mpsc::channel::<()>();
+
+        let blocking = worker.run(move |_| {
+            gate.recv().ok();
+            Ok(())
+        });
+        let ran = Arc::new(AtomicBool::new(false));
+        let skipped = {
+            let ran = ran.clone();
+            worker.run(move |_| {
+                ran.store(true, Ordering::SeqCst);
+                Ok(())
+            })
+        };
+        skipped.cancel();
+        release.send(()).unwrap();
+
+        assert_eq!(Ok(()), blocking.wait());
+        assert_eq!(Err(Error::Interrupted), skipped.wait());
+        assert!(!ran.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn it_lets_running_commands_check_for_cancellation() {
+        let worker = DeviceWorker::new(FakeDevice::new());
+        let (started, running) = mpsc::channel::<()>();
+
+        let reply = worker.run_cancellable(move |_, token| {
+            started.send(()).unwrap();
+            while !token.is_cancelled() {
+                thread::yield_now();
+            }
+            token.check()
+        });
+        running.recv().unwrap();
+        reply.cancel();
+
+        assert_eq!(Err(Error::Interrupted), reply.wait());
+    }
+
+    #[test]
+    fn it_wakes_awaiting_tasks() {
+        struct Flag(AtomicBool);
+
+        impl std::task::Wake for Flag {
+            fn wake(self: Arc<Self>) {
+                self.0.store(true, Ordering::SeqCst);
+            }
+        }
+
+        let worker = DeviceWorker::new(FakeDevice::new());
+        let (release, gate) = mpsc::channel::<()>();
+        let mut reply = worker.run(move |_| {
+            gate.recv().ok();
+            Ok(5)
+        });
+
+        let flag = Arc::new(Flag(AtomicBool::new(false)));
+        let waker = Waker::from(flag.clone());
+        let mut cx = Context::from_waker(&waker);
+        assert!(Pin::new(&mut reply).poll(&mut cx).is_pending());
+
+        release.send(()).unwrap();
+        worker.into_inner().unwrap();
+
+        assert!(flag.0.load(Ordering::SeqCst));
+        assert_eq!(Poll::Ready(Ok(5)), Pin::new(&mut reply).poll(&mut cx));
+    }
+
+    #[test]
+    fn it_fails_commands_after_a_panic() {
+        let worker = DeviceWorker::new(FakeDevice::new());
+
+        let panicked = worker.run(|_| -> crate::Result<()> { panic!("command failed") });
+        let next = worker.run(|_| Ok(()));
+
+        assert_eq!(Err(Error::Other), panicked.wait());
+        assert_eq!(Err(Error::Other), next.wait());
+        assert!(worker.into_inner().is_none());
+    }
+}
@@ -0,0 +1,738 @@
+use std::{
+    io::{self, Read, Write},
+    ops::{Deref, DerefMut},
+    panic,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::{self, Receiver, SyncSender},
+        Mutex,
+    },
+    thread::{self, ScopedJoinHandle},
+    time::Duration,
+};
+
+use libusb1_sys::constants::*;
+
+use crate::{
+    async_io::{AsyncGroup, Transfer, TransferStatus},
+    device_io::DeviceIo,
+    error::{self, Error},
+    Context, DeviceHandle, UsbContext,
+};
+
+/// The size of each transfer, and of each read from the source, unless set otherwise.
+const DEFAULT_TRANSFER_SIZE: usize = 16 * 1024;
+
+/// Shuttles data between a byte stream and a pair of bulk endpoints, in both directions at once,
+/// e.g. to tunnel a socket or a pseudo-terminal through a USB-to-serial bridge.
+///
+/// Data read from the source is written to the OUT endpoint, and data read from the IN endpoint
+/// is written to the sink, each direction with up to [`buffers`](#method.buffers) transfers'
+/// worth of data queued, so a slow source or sink doesn't stall the other direction or the
+/// device. [`run`](#method.run) uses synchronous transfers on worker threads;
+/// [`run_async`](#method.run_async) keeps `buffers` asynchronous transfers in flight in each
+/// direction, for devices that need the endpoints queued without gaps.
+///
+/// The pump returns once the source reached its end, all its data was sent, and a read from the
+/// IN endpoint then timed out, i.e. the device has nothing more to send. It returns earlier with
+/// the first error of either direction, including those of the source and sink, which are
+/// converted as by [`Error`](enum.Error.html)'s mapping of `std::io::ErrorKind`s. A source
+/// blocked in `read` holds the pump up until the read returns.
+///
+/// ```no_run
+/// use std::net::TcpListener;
+///
+/// use rusb::DuplexPump;
+///
+/// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// let mut handle = rusb::open_device_with_vid_pid(0x1234, 0x5678).unwrap();
+/// handle.claim_interface(1)?;
+///
+/// let (socket, _) = TcpListener::bind("127.0.0.1:4000")?.accept()?;
+/// let stats = DuplexPump::new(0x82, 0x02)
+///     .buffers(8)
+///     .run(&handle, socket.try_clone()?, socket)?;
+/// println!("sent {}, received {}", stats.sent(), stats.received());
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone)]
+pub struct DuplexPump {
+    in_endpoint: u8,
+    out_endpoint: u8,
+    buffers: usize,
+    transfer_size: usize,
+    poll_timeout: Duration,
+    timeout: Duration,
+}
+
+/// The amount of data moved by a [`DuplexPump`](struct.DuplexPump.html).
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct PumpStats {
+    sent: u64,
+    received: u64,
+}
+
+impl PumpStats {
+    /// Returns the number of bytes read from the source and written to the device.
+    pub fn sent(&self) -> u64 {
+        self.sent
+    }
+
+    /// Returns the number of bytes read from the device and written to the sink.
+    pub fn received(&self) -> u64 {
+        self.received
+    }
+}
+
+/// Pumps data between `source` and `sink` and the bulk endpoints of `device` with the default
+/// settings, see [`DuplexPump`](struct.DuplexPump.html).
+#[cfg_attr(feature = "async-only", deprecated(note = "blocks the calling thread"))]
+#[cfg_attr(feature = "async-only", allow(deprecated))]
+pub fn duplex_pump<D, R, W>(
+    device: &D,
+    in_endpoint: u8,
+    out_endpoint: u8,
+    source: R,
+    sink: W,
+) -> crate::Result<PumpStats>
+where
+    D: DeviceIo + Sync + ?Sized,
+    R: Read + Send,
+    W: Write + Send,
+{
+    DuplexPump::new(in_endpoint, out_endpoint).run(device, source, sink)
+}
+
+/// Pumps data between `source` and `sink` and the bulk endpoints of `handle` with asynchronous
+/// transfers and the default settings, see [`DuplexPump`](struct.DuplexPump.html).
+pub fn duplex_pump_async<T, R, W>(
+    context: &Context,
+    handle: &DeviceHandle<T>,
+    in_endpoint: u8,
+    out_endpoint: u8,
+    source: R,
+    sink: W,
+) -> crate::Result<PumpStats>
+where
+    T: UsbContext,
+    R: Read + Send,
+    W: Write + Send,
+{
+    DuplexPump::new(in_endpoint, out_endpoint).run_async(context, handle, source, sink)
+}
+
+impl DuplexPump {
+    /// Creates a pump reading from the bulk IN `in_endpoint` and writing to the bulk OUT
+    /// `out_endpoint`.
+    pub fn new(in_endpoint: u8, out_endpoint: u8) -> DuplexPump {
+        DuplexPump {
+            in_endpoint,
+            out_endpoint,
+            buffers: 4,
+            transfer_size: DEFAULT_TRANSFER_SIZE,
+            poll_timeout: Duration::from_millis(100),
+            timeout: Duration::from_secs(1),
+        }
+    }
+
+    /// Sets the number of buffers of each direction, which bounds the data queued between the
+    /// device and the source or sink.
+    ///
+    /// Defaults to 4.
+    pub fn buffers(mut self, buffers: usize) -> DuplexPump {
+        self.buffers = buffers;
+        self
+    }
+
+    /// Sets the size of each buffer, which is the most data a transfer or a read from the source
+    /// moves. Make it a multiple of the IN endpoint's maximum packet size.
+    ///
+    /// Defaults to 16KiB.
+    pub fn transfer_size(mut self, size: usize) -> DuplexPump {
+        self.transfer_size = size;
+        self
+    }
+
+    /// Sets the timeout of the reads from the IN endpoint, which is how long the device must stay
+    /// silent after the source ended for the pump to return. A read that times out isn't an
+    /// error.
+    ///
+    /// Defaults to 100ms.
+    pub fn poll_timeout(mut self, timeout: Duration) -> DuplexPump {
+        self.poll_timeout = timeout;
+        self
+    }
+
+    /// Sets the timeout of the writes to the OUT endpoint. A write that times out ends the pump
+    /// with `Error::Timeout`.
+    ///
+    /// Defaults to one second.
+    pub fn timeout(mut self, timeout: Duration) -> DuplexPump {
+        self.timeout = timeout;
+        self
+    }
+
+    fn check(&self) -> crate::Result<()> {
+        if self.in_endpoint & LIBUSB_ENDPOINT_DIR_MASK != LIBUSB_ENDPOINT_IN
+            || self.out_endpoint & LIBUSB_ENDPOINT_DIR_MASK != LIBUSB_ENDPOINT_OUT
+            || self.buffers == 0
+            || self.transfer_size == 0
+        {
+            Err(Error::InvalidParam)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Pumps data with synchronous transfers, until the source ends or an error occurs.
+    ///
+    /// The device, the source and the sink are each served by their own thread, and buffers
+    /// are handed between them.
+    ///
+    /// ## Errors
+    ///
+    /// * `InvalidParam` if the endpoints don't have the expected directions, or the number or
+    ///   size of the buffers is zero.
+    /// * The first error of a transfer, or of the source or sink.
+    #[cfg_attr(feature = "async-only", deprecated(note = "blocks the calling thread"))]
+    pub fn run<D, R, W>(&self, device: &D, mut source: R, mut sink: W) -> crate::Result<PumpStats>
+    where
+        D: DeviceIo + Sync + ?Sized,
+        R: Read + Send,
+        W: Write + Send,
+    {
+        self.check()?;
+
+        let progress = State::default();
+        let state = &progress;
+        let (in_free_sender, in_free) = pool(self.buffers);
+        let (in_full_sender, in_full) = mpsc::sync_channel(self.buffers);
+        let (out_free_sender, out_free) = pool(self.buffers);
+        let (out_full_sender, out_full) = mpsc::sync_channel(self.buffers);
+
+        let (received, sent) = thread::scope(|scope| {
+            let reader =
+                scope.spawn(move || self.read_device(device, in_free, in_full_sender, state));
+            let writer = scope.spawn(move || write_sink(&mut sink, in_full, in_free_sender, state));
+            let source = scope.spawn(move || {
+                read_source(
+                    &mut source,
+                    self.transfer_size,
+                    out_free,
+                    out_full_sender,
+                    state,
+                )
+            });
+            let sent = self.write_device(device, out_full, out_free_sender, state);
+
+            join(reader);
+            join(source);
+            (join(writer), sent)
+        });
+
+        progress.result(PumpStats { sent, received })
+    }
+
+    /// Reads from the IN endpoint into buffers from `free`, and passes them on to `full`.
+    #[cfg_attr(feature = "async-only", allow(deprecated))]
+    fn read_device<D: DeviceIo + ?Sized>(
+        &self,
+        device: &D,
+        free: Receiver<Vec<u8>>,
+        full: SyncSender<Vec<u8>>,
+        state: &State,
+    ) {
+        let mut spare = None;
+        while !state.failed() {
+            let mut buffer = match spare.take().map_or_else(|| free.recv(), Ok) {
+                Ok(buffer) => buffer,
+                Err(_) => return,
+            };
+            buffer.resize(self.transfer_size, 0);
+
+            match device.read_bulk(self.in_endpoint, &mut buffer, self.poll_timeout) {
+                Ok(0) => spare = Some(buffer),
+                Ok(len) => {
+                    buffer.truncate(len);
+                    if full.send(buffer).is_err() {
+                        return;
+                    }
+                }
+                Err(Error::Timeout) if state.finished() => return,
+                Err(Error::Timeout) => spare = Some(buffer),
+                Err(e) => return state.fail(e),
+            }
+        }
+    }
+
+    /// Writes the buffers from `full` to the OUT endpoint, and gives them back to `free`.
+    /// Returns the number of bytes written.
+    #[cfg_attr(feature = "async-only", allow(deprecated))]
+    fn write_device<D: DeviceIo + ?Sized>(
+        &self,
+        device: &D,
+        full: Receiver<Vec<u8>>,
+        free: SyncSender<Vec<u8>>,
+        state: &State,
+    ) -> u64 {
+        let mut sent = 0;
+        for buffer in full {
+            let mut data = &buffer[..];
+            while !data.is_empty() {
+                if state.failed() {
+                    return sent;
+                }
+                match device.write_bulk(self.out_endpoint, data, self.timeout) {
+                    Ok(0) => {
+                        state.fail(Error::Io);
+                        return sent;
+                    }
+                    Ok(len) => {
+                        data = &data[len..];
+                        sent += len as u64;
+                    }
+                    Err(e) => {
+                        state.fail(e);
+                        return sent;
+                    }
+                }
+            }
+            free.send(buffer).ok();
+        }
+
+        state.finish();
+        sent
+    }
+
+    /// Pumps data with asynchronous transfers, until the source ends or an error occurs.
+    ///
+    /// Each direction runs on its own thread, with up to [`buffers`](#method.buffers) transfers
+    /// in flight. The threads handle the events of `context` while they wait for transfers, so
+    /// no other thread needs to.
+    ///
+    /// ## Errors
+    ///
+    /// * `InvalidParam` if the endpoints don't have the expected directions, or the number or
+    ///   size of the buffers is zero.
+    /// * The first error of a transfer, or of the source or sink.
+    pub fn run_async<T, R, W>(
+        &self,
+        context: &Context,
+        handle: &DeviceHandle<T>,
+        mut source: R,
+        mut sink: W,
+    ) -> crate::Result<PumpStats>
+    where
+        T: UsbContext,
+        R: Read + Send,
+        W: Write + Send,
+    {
+        self.check()?;
+
+        let progress = State::default();
+        let state = &progress;
+        let (received, sent) = thread::scope(|scope| {
+            let receiver =
+                scope.spawn(move || self.receive_async(context, handle, &mut sink, state));
+            let sent = self.send_async(context, handle, &mut source, state);
+            (join(receiver), sent)
+        });
+
+        progress.result(PumpStats { sent, received })
+    }
+
+    /// Keeps transfers queued on the IN endpoint and writes their data to `sink`. Returns the
+    /// number of bytes written.
+    fn receive_async<T: UsbContext, W: Write>(
+        &self,
+        context: &Context,
+        handle: &DeviceHandle<T>,
+        sink: &mut W,
+        state: &State,
+    ) -> u64 {
+        let mut storage = vec![0; self.buffers * self.transfer_size];
+        let mut group = Cancelling(AsyncGroup::new(context));
+
+        let mut in_flight = 0;
+        for buffer in storage.chunks_mut(self.transfer_size) {
+            let transfer = Transfer::bulk(handle, self.in_endpoint, buffer, self.poll_timeout);
+            if let Err(e) = group.submit(transfer) {
+                state.fail(e);
+                return 0;
+            }
+            in_flight += 1;
+        }
+
+        let mut received = 0;
+        let mut draining = false;
+        while in_flight > 0 {
+            let mut transfer = match group.wait_any() {
+                Ok(transfer) => transfer,
+                Err(e) => {
+                    state.fail(e);
+                    return received;
+                }
+            };
+            in_flight -= 1;
+
+            // a transfer that timed out may still have received part of its data
+            let data = transfer.actual();
+            if !data.is_empty() {
+                if let Err(e) = sink.write_all(data).and_then(|()| sink.flush()) {
+                    state.fail(error::from_io_error(&e));
+                    return received;
+                }
+                received += data.len() as u64;
+            }
+
+            match transfer.status() {
+                TransferStatus::Success => (),
+                TransferStatus::Timeout => draining |= state.finished(),
+                status => {
+                    state.fail(status_error(status));
+                    return received;
+                }
+            }
+            if state.failed() {
+                return received;
+            }
+
+            if !draining {
+                if let Err(e) = group.submit(transfer) {
+                    state.fail(e);
+                    return received;
+                }
+                in_flight += 1;
+            }
+        }
+
+        received
+    }
+
+    /// Reads `source` into transfers and submits them to the OUT endpoint. Returns the number of
+    /// bytes written.
+    fn send_async<T: UsbContext, R: Read>(
+        &self,
+        context: &Context,
+        handle: &DeviceHandle<T>,
+        source: &mut R,
+        state: &State,
+    ) -> u64 {
+        let mut storage = vec![0; self.buffers * self.transfer_size];
+        let mut group = Cancelling(AsyncGroup::new(context));
+        let mut free: Vec<_> = storage
+            .chunks_mut(self.transfer_size)
+            .map(|buffer| Transfer::bulk(handle, self.out_endpoint, buffer, self.timeout))
+            .collect();
+
+        let mut in_flight = 0;
+        let mut sent = 0;
+        loop {
+            if state.failed() {
+                return sent;
+            }
+
+            let mut transfer = match free.pop() {
+                Some(transfer) => transfer,
+                None => match reclaim(&mut group, &mut sent) {
+                    Ok(transfer) => {
+                        in_flight -= 1;
+                        transfer
+                    }
+                    Err(e) => {
+                        state.fail(e);
+                        return sent;
+                    }
+                },
+            };
+
+            // the buffers were all created `transfer_size` bytes long
+            unsafe { transfer.set_length(self.transfer_size) };
+            let len = match read_retrying(source, transfer.buffer()) {
+                Ok(0) => break,
+                Ok(len) => len,
+                Err(e) => {
+                    state.fail(error::from_io_error(&e));
+                    return sent;
+                }
+            };
+            unsafe { transfer.set_length(len) };
+
+            if let Err(e) = group.submit(transfer) {
+                state.fail(e);
+                return sent;
+            }
+            in_flight += 1;
+        }
+
+        while in_flight > 0 {
+            if let Err(e) = reclaim(&mut group, &mut sent) {
+                state.fail(e);
+                return sent;
+            }
+            in_flight -= 1;
+        }
+
+        state.finish();
+        sent
+    }
+}
+
+/// The progress of a pump, shared by its threads.
+#[derive(Default)]
+struct State {
+    /// The source ended and all its data was written to the device.
+    finished: AtomicBool,
+    failed: AtomicBool,
+    error: Mutex<Option<Error>>,
+}
+
+impl State {
+    fn finish(&self) {
+        self.finished.store(true, Ordering::SeqCst);
+    }
+
+    fn finished(&self) -> bool {
+        self.finished.load(Ordering::SeqCst)
+    }
+
+    /// Records the first error, which stops both directions.
+    fn fail(&self, error: Error) {
+        let mut first = self.error.lock().unwrap_or_else(|p| p.into_inner());
+        first.get_or_insert(error);
+        self.failed.store(true, Ordering::SeqCst);
+    }
+
+    fn failed(&self) -> bool {
+        self.failed.load(Ordering::SeqCst)
+    }
+
+    fn result(self, stats: PumpStats) -> crate::Result<PumpStats> {
+        match self.error.into_inner().unwrap_or_else(|p| p.into_inner()) {
+            Some(error) => Err(error),
+            None => Ok(stats),
+        }
+    }
+}
+
+/// Cancels the pending transfers of a group when dropped, and waits until libusb is done with
+/// every one of them, before their buffers are freed.
+struct Cancelling<'d, T: UsbContext>(AsyncGroup<'d, T>);
+
+impl<'d, T: UsbContext> Deref for Cancelling<'d, T> {
+    type Target = AsyncGroup<'d, T>;
+
+    fn deref(&self) -> &AsyncGroup<'d, T> {
+        &self.0
+    }
+}
+
+impl<'d, T: UsbContext> DerefMut for Cancelling<'d, T> {
+    fn deref_mut(&mut self) -> &mut AsyncGroup<'d, T> {
+        &mut self.0
+    }
+}
+
+impl<'d, T: UsbContext> Drop for Cancelling<'d, T> {
+    fn drop(&mut self) {
+        drain(
+            &mut self.0,
+            |group| group.cancel_all(),
+            |group| group.wait_any().map(drop),
+        );
+    }
+}
+
+/// Calls `cancel_all`, then `wait` until it fails with `NotFound`, i.e. until nothing is pending
+/// anymore. Other errors are ignored: a transfer whose cancellation failed still completes, and
+/// must be waited for all the same.
+fn drain<S>(
+    state: &mut S,
+    cancel_all: impl FnOnce(&mut S) -> crate::Result<()>,
+    mut wait: impl FnMut(&mut S) -> crate::Result<()>,
+) {
+    cancel_all(state).ok();
+    while wait(state) != Err(Error::NotFound) {}
+}
+
+/// Returns a channel holding `buffers` empty buffers.
+fn pool(buffers: usize) -> (SyncSender<Vec<u8>>, Receiver<Vec<u8>>) {
+    let (sender, receiver) = mpsc::sync_channel(buffers);
+    for _ in 0..buffers {
+        sender.send(Vec::new()).ok();
+    }
+    (sender, receiver)
+}
+
+/// Writes the buffers from `full` to `sink`, and gives them back to `free`. Returns the number
+/// of bytes written.
+fn write_sink<W: Write>(
+    sink: &mut W,
+    full: Receiver<Vec<u8>>,
+    free: SyncSender<Vec<u8>>,
+    state: &State,
+) -> u64 {
+    let mut received = 0;
+    for buffer in full {
+        if let Err(e) = sink.write_all(&buffer).and_then(|()| sink.flush()) {
+            state.fail(error::from_io_error(&e));
+            break;
+        }
+        received += buffer.len() as u64;
+        free.send(buffer).ok();
+    }
+    received
+}
+
+/// Reads `source` into buffers from `free`, and passes them on to `full` until the source ends.
+fn read_source<R: Read>(
+    source: &mut R,
+    size: usize,
+    free: Receiver<Vec<u8>>,
+    full: SyncSender<Vec<u8>>,
+    state: &State,
+) {
+    for mut buffer in free {
+        if state.failed() {
+            return;
+        }
+
+        buffer.resize(size, 0);
+        match read_retrying(source, &mut buffer) {
+            Ok(0) => return,
+            Ok(len) => {
+                buffer.truncate(len);
+                if full.send(buffer).is_err() {
+                    return;
+                }
+            }
+            Err(e) => return state.fail(error::from_io_error(&e)),
+        }
+    }
+}
+
+/// Joins a pump thread, resuming its panic on the calling thread.
+fn join<T>(thread: ScopedJoinHandle<'_, T>) -> T {
+    thread
+        .join()
+        .unwrap_or_else(|payload| panic::resume_unwind(payload))
+}
+
+/// Reads from `source`, retrying reads interrupted by a signal.
+fn read_retrying<R: Read>(source: &mut R, buf: &mut [u8]) -> io::Result<usize> {
+    loop {
+        match source.read(buf) {
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            res => return res,
+        }
+    }
+}
+
+/// Waits for an OUT transfer to complete, and returns it for reuse once it succeeded, counting its
+/// data in `sent`.
+fn reclaim<'d, T: UsbContext>(
+    group: &mut AsyncGroup<'d, T>,
+    sent: &mut u64,
+) -> crate::Result<Transfer<'d, T>> {
+    let mut transfer = group.wait_any()?;
+    match transfer.status() {
+        TransferStatus::Success => {
+            *sent += transfer.actual().len() as u64;
+            Ok(transfer)
+        }
+        status => Err(status_error(status)),
+    }
+}
+
+/// Returns the error a transfer that didn't succeed completed with.
+fn status_error(status: TransferStatus) -> Error {
+    match status {
+        TransferStatus::Timeout => Error::Timeout,
+        TransferStatus::Stall => Error::Pipe,
+        TransferStatus::NoDevice => Error::NoDevice,
+        TransferStatus::Overflow => Error::Overflow,
+        TransferStatus::Cancelled => Error::Interrupted,
+        TransferStatus::Success | TransferStatus::Error | TransferStatus::Unknown => Error::Io,
+    }
+}
+
+#[cfg(test)]
+#[cfg_attr(feature = "async-only", allow(deprecated))]
+mod test {
+    use super::*;
+    use crate::fake::FakeDevice;
+
+    #[test]
+    fn it_pumps_both_directions() {
+        let device = FakeDevice::new();
+        device.push_in(0x82, b"OK\r\n");
+        device.push_in(0x82, b"");
+        device.push_in(0x82, b"READY\r\n");
+
+        let mut sink = Vec::new();
+        let stats = DuplexPump::new(0x82, 0x02)
+            .transfer_size(8)
+            .run(&device, &b"AT+RST\r\nAT\r\n"[..], &mut sink)
+            .unwrap();
+
+        assert_eq!(b"OK\r\nREADY\r\n", &sink[..]);
+        assert_eq!(
+            vec![b"AT+RST\r\n".to_vec(), b"AT\r\n".to_vec()],
+            device.take_out(0x02)
+        );
+        assert_eq!(12, stats.sent());
+        assert_eq!(11, stats.received());
+    }
+
+    #[test]
+    fn it_stops_at_the_first_error() {
+        let device = FakeDevice::new();
+        device.push_in_error(0x81, Error::NoDevice);
+
+        let res = duplex_pump(&device, 0x81, 0x01, io::empty(), io::sink());
+        assert_eq!(Err(Error::NoDevice), res);
+    }
+
+    #[test]
+    fn it_waits_for_every_transfer_after_cancelling() {
+        // pending transfers, and the results of waiting for them
+        let mut state = (
+            3,
+            vec![Err(Error::Interrupted), Ok(()), Ok(()), Ok(())].into_iter(),
+        );
+
+        drain(
+            &mut state,
+            |_| Err(Error::Io),
+            |(pending, results)| match results.next() {
+                Some(_) if *pending == 0 => panic!("waited with nothing pending"),
+                Some(Ok(())) => {
+                    *pending -= 1;
+                    Ok(())
+                }
+                Some(Err(e)) => Err(e),
+                None => Err(Error::NotFound),
+            },
+        );
+
+        assert_eq!(0, state.0);
+    }
+
+    #[test]
+    fn it_checks_its_settings() {
+        let device = FakeDevice::new();
+        let run = |pump: DuplexPump| pump.run(&device, io::empty(), io::sink());
+
+        assert_eq!(Err(Error::InvalidParam), run(DuplexPump::new(0x02, 0x02)));
+        assert_eq!(Err(Error::InvalidParam), run(DuplexPump::new(0x81, 0x81)));
+        assert_eq!(
+            Err(Error::InvalidParam),
+            run(DuplexPump::new(0x81, 0x01).buffers(0))
+        );
+        assert_eq!(
+            Err(Error::InvalidParam),
+            run(DuplexPump::new(0x81, 0x01).transfer_size(0))
+        );
+    }
+}
@@ -0,0 +1,397 @@
+//! Access to the configuration EEPROM of common USB bridge chips through their vendor requests.
+//!
+//! Tools managing serial numbers or configuration blobs usually go through the chip vendor's
+//! library. The helpers of this module implement the same vendor requests on top of
+//! [`DeviceIo`](../trait.DeviceIo.html), so a `DeviceHandle` or a fake device can be used
+//! directly. Each chip family is behind its own feature flag:
+//!
+//! * `ftdi-eeprom`: [`FtdiEeprom`](struct.FtdiEeprom.html), for FTDI FT232/FT2232/FT4232 and
+//!   similar chips.
+//! * `cypress-eeprom`: [`CypressEeprom`](struct.CypressEeprom.html), for Cypress EZ-USB FX2
+//!   chips running the vendor-command firmware.
+//!
+//! Writing a wrong configuration can leave a chip enumerating with other IDs, or not at all.
+
+use std::time::Duration;
+
+use crate::{device_io::DeviceIo, error::Error};
+
+/// `bmRequestType` of vendor requests to the device.
+const VENDOR_OUT: u8 = 0x40;
+
+/// `bmRequestType` of vendor requests from the device.
+const VENDOR_IN: u8 = 0xc0;
+
+/// `SIO_READ_EEPROM` request of FTDI chips.
+#[cfg(feature = "ftdi-eeprom")]
+const FTDI_READ_EEPROM: u8 = 0x90;
+
+/// `SIO_WRITE_EEPROM` request of FTDI chips.
+#[cfg(feature = "ftdi-eeprom")]
+const FTDI_WRITE_EEPROM: u8 = 0x91;
+
+/// `SIO_ERASE_EEPROM` request of FTDI chips.
+#[cfg(feature = "ftdi-eeprom")]
+const FTDI_ERASE_EEPROM: u8 = 0x92;
+
+/// The EEPROM of an FTDI chip, accessed as 16-bit words.
+///
+/// The last word of the configuration holds a checksum of the others, which the chip verifies
+/// at power-up; a configuration whose checksum doesn't match is ignored in favour of the
+/// defaults. [`update_checksum`](#method.update_checksum) sets it before writing a modified
+/// image.
+#[cfg(feature = "ftdi-eeprom")]
+#[derive(Debug)]
+pub struct FtdiEeprom<'d, D: DeviceIo + ?Sized> {
+    io: &'d D,
+    timeout: Duration,
+}
+
+#[cfg(feature = "ftdi-eeprom")]
+impl<'d, D: DeviceIo + ?Sized> FtdiEeprom<'d, D> {
+    /// Accesses the EEPROM of `io`, waiting up to `timeout` for each request.
+    pub fn new(io: &'d D, timeout: Duration) -> FtdiEeprom<'d, D> {
+        FtdiEeprom { io, timeout }
+    }
+
+    /// Reads the word at `address`, counted in words.
+    ///
+    /// ## Errors
+    ///
+    /// * `Other` if the chip returned less than a word.
+    #[cfg_attr(feature = "async-only", deprecated(note = "blocks the calling thread"))]
+    #[cfg_attr(feature = "async-only", allow(deprecated))]
+    pub fn read_word(&self, address: u16) -> crate::Result<u16> {
+        let mut word = [0u8; 2];
+        let len = self.io.read_control(
+            VENDOR_IN,
+            FTDI_READ_EEPROM,
+            0,
+            address,
+            &mut word,
+            self.timeout,
+        )?;
+        if len != word.len() {
+            return Err(Error::Other);
+        }
+        Ok(u16::from_le_bytes(word))
+    }
+
+    /// Writes the word at `address`, counted in words.
+    #[cfg_attr(feature = "async-only", deprecated(note = "blocks the calling thread"))]
+    #[cfg_attr(feature = "async-only", allow(deprecated))]
+    pub fn write_word(&self, address: u16, word: u16) -> crate::Result<()> {
+        self.io.write_control(
+            VENDOR_OUT,
+            FTDI_WRITE_EEPROM,
+            word,
+            address,
+            &[],
+            self.timeout,
+        )?;
+        Ok(())
+    }
+
+    /// Reads the first `len` bytes of the EEPROM, e.g. 128 for a 93C46, as little-endian words.
+    ///
+    /// ## Errors
+    ///
+    /// * `InvalidParam` if `len` is odd.
+    #[cfg_attr(feature = "async-only", deprecated(note = "blocks the calling thread"))]
+    #[cfg_attr(feature = "async-only", allow(deprecated))]
+    pub fn read(&self, len: usize) -> crate::Result<Vec<u8>> {
+        if len % 2 != 0 || len / 2 > usize::from(u16::MAX) {
+            return Err(Error::InvalidParam);
+        }
+
+        let mut image = Vec::with_capacity(len);
+        for address in 0..len / 2 {
+            image.extend_from_slice(&self.read_word(address as u16)?.to_le_bytes());
+        }
+        Ok(image)
+    }
+
+    /// Writes `image` from the start of the EEPROM, as little-endian words.
+    ///
+    /// ## Errors
+    ///
+    /// * `InvalidParam` if `image` has an odd length.
+    #[cfg_attr(feature = "async-only", deprecated(note = "blocks the calling thread"))]
+    #[cfg_attr(feature = "async-only", allow(deprecated))]
+    pub fn write(&self, image: &[u8]) -> crate::Result<()> {
+        if image.len() % 2 != 0 || image.len() / 2 > usize::from(u16::MAX) {
+            return Err(Error::InvalidParam);
+        }
+
+        for (address, word) in image.chunks_exact(2).enumerate() {
+            self.write_word(address as u16, u16::from_le_bytes([word[0], word[1]]))?;
+        }
+        Ok(())
+    }
+
+    /// Erases the whole EEPROM, so the chip uses its default configuration at the next
+    /// power-up.
+    #[cfg_attr(feature = "async-only", deprecated(note = "blocks the calling thread"))]
+    #[cfg_attr(feature = "async-only", allow(deprecated))]
+    pub fn erase(&self) -> crate::Result<()> {
+        self.io
+            .write_control(VENDOR_OUT, FTDI_ERASE_EEPROM, 0, 0, &[], self.timeout)?;
+        Ok(())
+    }
+
+    /// Returns the checksum of `image`, computed over all of its words but the last one.
+    pub fn checksum(image: &[u8]) -> u16 {
+        let words = image.len() / 2;
+
+        image
+            .chunks_exact(2)
+            .take(words.saturating_sub(1))
+            .fold(0xaaaa, |checksum: u16, word| {
+                (checksum ^ u16::from_le_bytes([word[0], word[1]])).rotate_left(1)
+            })
+    }
+
+    /// Stores the checksum of `image` in its last word.
+    ///
+    /// ## Errors
+    ///
+    /// * `InvalidParam` if `image` has an odd length or is empty.
+    pub fn update_checksum(image: &mut [u8]) -> crate::Result<()> {
+        if image.is_empty() || image.len() % 2 != 0 {
+            return Err(Error::InvalidParam);
+        }
+
+        let checksum = Self::checksum(image).to_le_bytes();
+        let len = image.len();
+        image[len - 2..].copy_from_slice(&checksum);
+        Ok(())
+    }
+
+    /// Indicates whether the last word of `image` holds its checksum.
+    pub fn checksum_matches(image: &[u8]) -> bool {
+        let len = image.len();
+        len >= 2 && len % 2 == 0 && image[len - 2..] == Self::checksum(image).to_le_bytes()[..]
+    }
+}
+
+/// The largest amount of data moved by a single Cypress EEPROM request.
+#[cfg(feature = "cypress-eeprom")]
+const CYPRESS_CHUNK_SIZE: usize = 64;
+
+/// The addressing of a Cypress EZ-USB boot EEPROM.
+#[cfg(feature = "cypress-eeprom")]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum CypressAddressing {
+    /// A small EEPROM with one address byte, e.g. a 24LC00 to 24LC02B, accessed with vendor
+    /// request `0xA2`.
+    Small,
+
+    /// A large EEPROM with two address bytes, e.g. a 24LC64 or 24LC128, accessed with vendor
+    /// request `0xA9`.
+    Large,
+}
+
+#[cfg(feature = "cypress-eeprom")]
+impl CypressAddressing {
+    fn request(self) -> u8 {
+        match self {
+            CypressAddressing::Small => 0xa2,
+            CypressAddressing::Large => 0xa9,
+        }
+    }
+}
+
+/// The boot EEPROM of a Cypress EZ-USB FX2 chip, accessed as bytes.
+///
+/// The chip's boot ROM doesn't implement EEPROM access: the requests are handled by Cypress's
+/// vendor-command firmware (`Vend_Ax`), which has to be loaded into the chip's RAM first.
+/// Data is moved in chunks of 64 bytes, one request each.
+#[cfg(feature = "cypress-eeprom")]
+#[derive(Debug)]
+pub struct CypressEeprom<'d, D: DeviceIo + ?Sized> {
+    io: &'d D,
+    addressing: CypressAddressing,
+    timeout: Duration,
+}
+
+#[cfg(feature = "cypress-eeprom")]
+impl<'d, D: DeviceIo + ?Sized> CypressEeprom<'d, D> {
+    /// Accesses the EEPROM of `io`, waiting up to `timeout` for each request.
+    pub fn new(
+        io: &'d D,
+        addressing: CypressAddressing,
+        timeout: Duration,
+    ) -> CypressEeprom<'d, D> {
+        CypressEeprom {
+            io,
+            addressing,
+            timeout,
+        }
+    }
+
+    /// Fills `buf` with the EEPROM contents starting at `address`.
+    ///
+    /// ## Errors
+    ///
+    /// * `InvalidParam` if the range doesn't fit in the 16-bit address space.
+    /// * `Other` if the firmware returned less data than requested.
+    #[cfg_attr(feature = "async-only", deprecated(note = "blocks the calling thread"))]
+    #[cfg_attr(feature = "async-only", allow(deprecated))]
+    pub fn read(&self, address: u16, buf: &mut [u8]) -> crate::Result<()> {
+        check_range(address, buf.len())?;
+
+        for (i, chunk) in buf.chunks_mut(CYPRESS_CHUNK_SIZE).enumerate() {
+            let offset = address + (i * CYPRESS_CHUNK_SIZE) as u16;
+            let len = self.io.read_control(
+                VENDOR_IN,
+                self.addressing.request(),
+                offset,
+                0,
+                chunk,
+                self.timeout,
+            )?;
+            if len != chunk.len() {
+                return Err(Error::Other);
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes `data` to the EEPROM starting at `address`.
+    ///
+    /// ## Errors
+    ///
+    /// * `InvalidParam` if the range doesn't fit in the 16-bit address space.
+    /// * `Other` if the firmware accepted less data than written.
+    #[cfg_attr(feature = "async-only", deprecated(note = "blocks the calling thread"))]
+    #[cfg_attr(feature = "async-only", allow(deprecated))]
+    pub fn write(&self, address: u16, data: &[u8]) -> crate::Result<()> {
+        check_range(address, data.len())?;
+
+        for (i, chunk) in data.chunks(CYPRESS_CHUNK_SIZE).enumerate() {
+            let offset = address + (i * CYPRESS_CHUNK_SIZE) as u16;
+            let len = self.io.write_control(
+                VENDOR_OUT,
+                self.addressing.request(),
+                offset,
+                0,
+                chunk,
+                self.timeout,
+            )?;
+            if len != chunk.len() {
+                return Err(Error::Other);
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(feature = "cypress-eeprom")]
+fn check_range(address: u16, len: usize) -> crate::Result<()> {
+    if usize::from(address) + len > 0x1_0000 {
+        Err(Error::InvalidParam)
+    } else {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+#[cfg_attr(feature = "async-only", allow(deprecated))]
+mod test {
+    use super::*;
+    use crate::fake::FakeDevice;
+    use std::sync::{Arc, Mutex};
+
+    const TIMEOUT: Duration = Duration::from_secs(1);
+
+    /// A fake FTDI chip whose EEPROM holds the words of `memory`.
+    #[cfg(feature = "ftdi-eeprom")]
+    fn ftdi(memory: Arc<Mutex<Vec<u16>>>) -> FakeDevice {
+        let device = FakeDevice::new();
+        device.set_control_handler(move |setup, data| {
+            let mut memory = memory.lock().unwrap();
+            match setup.request {
+                FTDI_READ_EEPROM => {
+                    let word = memory.get(usize::from(setup.index)).ok_or(Error::Pipe)?;
+                    data.extend_from_slice(&word.to_le_bytes());
+                }
+                FTDI_WRITE_EEPROM => memory[usize::from(setup.index)] = setup.value,
+                FTDI_ERASE_EEPROM => memory.iter_mut().for_each(|w| *w = 0xffff),
+                _ => return Err(Error::Pipe),
+            }
+            Ok(())
+        });
+        device
+    }
+
+    #[cfg(feature = "ftdi-eeprom")]
+    #[test]
+    fn it_reads_and_writes_ftdi_words() {
+        let memory = Arc::new(Mutex::new(vec![0x0403, 0x6001, 0, 0]));
+        let device = ftdi(memory.clone());
+        let eeprom = FtdiEeprom::new(&device, TIMEOUT);
+
+        assert_eq!(vec![0x03, 0x04, 0x01, 0x60], eeprom.read(4).unwrap());
+
+        eeprom.write(&[0x34, 0x12, 0x78, 0x56]).unwrap();
+        assert_eq!(vec![0x1234, 0x5678, 0, 0], *memory.lock().unwrap());
+
+        eeprom.erase().unwrap();
+        assert_eq!(0xffff, eeprom.read_word(3).unwrap());
+        assert_eq!(Err(Error::InvalidParam), eeprom.read(3));
+    }
+
+    #[cfg(feature = "ftdi-eeprom")]
+    #[test]
+    fn it_computes_ftdi_checksums() {
+        let mut image = vec![0x00, 0x00, 0x03, 0x04, 0x01, 0x60, 0x00, 0x00];
+
+        // 0xaaaa, then xor and rotate with each word but the last
+        let mut expected: u16 = 0xaaaa;
+        for word in [0x0000, 0x0403, 0x6001] {
+            expected = (expected ^ word).rotate_left(1);
+        }
+        assert_eq!(expected, FtdiEeprom::<FakeDevice>::checksum(&image));
+
+        assert!(!FtdiEeprom::<FakeDevice>::checksum_matches(&image));
+        FtdiEeprom::<FakeDevice>::update_checksum(&mut image).unwrap();
+        assert!(FtdiEeprom::<FakeDevice>::checksum_matches(&image));
+        assert_eq!(expected.to_le_bytes(), image[6..]);
+    }
+
+    #[cfg(feature = "cypress-eeprom")]
+    #[test]
+    fn it_reads_and_writes_cypress_eeproms_in_chunks() {
+        let memory = Arc::new(Mutex::new(vec![0u8; 256]));
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let device = FakeDevice::new();
+        {
+            let memory = memory.clone();
+            let requests = requests.clone();
+            device.set_control_handler(move |setup, data| {
+                let mut memory = memory.lock().unwrap();
+                let start = usize::from(setup.value);
+                requests.lock().unwrap().push((setup.request, start));
+                if setup.request_type == VENDOR_OUT {
+                    memory[start..start + data.len()].copy_from_slice(data);
+                } else {
+                    data.extend_from_slice(&memory[start..]);
+                }
+                Ok(())
+            });
+        }
+        let eeprom = CypressEeprom::new(&device, CypressAddressing::Small, TIMEOUT);
+
+        let data: Vec<u8> = (0..100).collect();
+        eeprom.write(8, &data).unwrap();
+        assert_eq!(&data[..], &memory.lock().unwrap()[8..108]);
+
+        let mut buf = [0u8; 100];
+        eeprom.read(8, &mut buf).unwrap();
+        assert_eq!(&data[..], &buf[..]);
+
+        let expected = vec![(0xa2, 8), (0xa2, 72), (0xa2, 8), (0xa2, 72)];
+        assert_eq!(expected, *requests.lock().unwrap());
+        assert_eq!(Err(Error::InvalidParam), eeprom.read(0xfff0, &mut buf));
+    }
+}
@@ -1,8 +1,14 @@
-use std::{fmt, slice};
+use std::{fmt, ptr, slice};
 
-use libusb1_sys::{constants::*, libusb_endpoint_descriptor};
+use libusb1_sys::{
+    constants::*, libusb_endpoint_descriptor, libusb_free_ss_endpoint_companion_descriptor,
+    libusb_get_ss_endpoint_companion_descriptor, libusb_ss_endpoint_companion_descriptor,
+};
 
-use crate::fields::{Direction, SyncType, TransferType, UsageType};
+use crate::{
+    descriptor_view::ExtraDescriptors,
+    fields::{DescriptorType, Direction, SyncType, TransferType, UsageType},
+};
 
 /// Describes an endpoint.
 pub struct EndpointDescriptor<'a> {
@@ -22,44 +28,26 @@ impl<'a> EndpointDescriptor<'a> {
 
     /// Returns the endpoint's direction.
     pub fn direction(&self) -> Direction {
-        match self.descriptor.bEndpointAddress & LIBUSB_ENDPOINT_DIR_MASK {
-            LIBUSB_ENDPOINT_OUT => Direction::Out,
-            LIBUSB_ENDPOINT_IN | _ => Direction::In,
-        }
+        direction(self.descriptor.bEndpointAddress)
     }
 
     /// Returns the endpoint's transfer type.
     pub fn transfer_type(&self) -> TransferType {
-        match self.descriptor.bmAttributes & LIBUSB_TRANSFER_TYPE_MASK {
-            LIBUSB_TRANSFER_TYPE_CONTROL => TransferType::Control,
-            LIBUSB_TRANSFER_TYPE_ISOCHRONOUS => TransferType::Isochronous,
-            LIBUSB_TRANSFER_TYPE_BULK => TransferType::Bulk,
-            LIBUSB_TRANSFER_TYPE_INTERRUPT | _ => TransferType::Interrupt,
-        }
+        transfer_type(self.descriptor.bmAttributes)
     }
 
     /// Returns the endpoint's synchronisation mode.
     ///
     /// The return value of this method is only valid for isochronous endpoints.
     pub fn sync_type(&self) -> SyncType {
-        match (self.descriptor.bmAttributes & LIBUSB_ISO_SYNC_TYPE_MASK) >> 2 {
-            LIBUSB_ISO_SYNC_TYPE_NONE => SyncType::NoSync,
-            LIBUSB_ISO_SYNC_TYPE_ASYNC => SyncType::Asynchronous,
-            LIBUSB_ISO_SYNC_TYPE_ADAPTIVE => SyncType::Adaptive,
-            LIBUSB_ISO_SYNC_TYPE_SYNC | _ => SyncType::Synchronous,
-        }
+        sync_type(self.descriptor.bmAttributes)
     }
 
     /// Returns the endpoint's usage type.
     ///
     /// The return value of this method is only valid for isochronous endpoints.
     pub fn usage_type(&self) -> UsageType {
-        match (self.descriptor.bmAttributes & LIBUSB_ISO_USAGE_TYPE_MASK) >> 4 {
-            LIBUSB_ISO_USAGE_TYPE_DATA => UsageType::Data,
-            LIBUSB_ISO_USAGE_TYPE_FEEDBACK => UsageType::Feedback,
-            LIBUSB_ISO_USAGE_TYPE_IMPLICIT => UsageType::FeedbackData,
-            _ => UsageType::Reserved,
-        }
+        usage_type(self.descriptor.bmAttributes)
     }
 
     /// Returns the endpoint's maximum packet size.
@@ -72,14 +60,70 @@ impl<'a> EndpointDescriptor<'a> {
         self.descriptor.bInterval
     }
 
+    /// Returns the descriptors in the 'extra' bytes, e.g. a SuperSpeed endpoint companion
+    /// descriptor.
+    pub fn extra_descriptors(&self) -> ExtraDescriptors<'_> {
+        ExtraDescriptors::new(self.extra().unwrap_or(&[]))
+    }
+
+    /// Returns the SuperSpeed endpoint companion descriptor, which describes the bursts, streams
+    /// and bandwidth of the endpoint at SuperSpeed.
+    ///
+    /// Returns `None` if the endpoint has no companion descriptor, i.e. below SuperSpeed.
+    pub fn ss_companion(&self) -> Option<SuperSpeedEndpointCompanion> {
+        let mut companion: *const libusb_ss_endpoint_companion_descriptor = ptr::null();
+
+        // the context is only used for logging
+        let res = unsafe {
+            libusb_get_ss_endpoint_companion_descriptor(
+                ptr::null_mut(),
+                self.descriptor,
+                &mut companion,
+            )
+        };
+        if res != 0 || companion.is_null() {
+            return None;
+        }
+
+        let decoded = unsafe {
+            SuperSpeedEndpointCompanion {
+                max_burst: (*companion).bMaxBurst,
+                attributes: (*companion).bmAttributes,
+                bytes_per_interval: (*companion).wBytesPerInterval,
+            }
+        };
+        unsafe {
+            libusb_free_ss_endpoint_companion_descriptor(
+                companion as *mut libusb_ss_endpoint_companion_descriptor,
+            )
+        };
+
+        Some(decoded)
+    }
+
+    /// Returns the number of packets the endpoint moves per burst, minus one, as declared by its
+    /// SuperSpeed endpoint companion descriptor.
+    ///
+    /// Returns `None` if the endpoint has no companion descriptor, i.e. below SuperSpeed.
+    pub fn max_burst(&self) -> Option<u8> {
+        max_burst(self.extra())
+    }
+
+    /// Returns the number of bytes the endpoint moves per burst: the packet size times the
+    /// packets per burst at SuperSpeed, or times the transactions per microframe of high
+    /// bandwidth endpoints at high speed.
+    ///
+    /// Transfers should be a multiple of this size, and for bulk endpoints several times it, to
+    /// keep the endpoint busy.
+    pub fn burst_size(&self) -> usize {
+        burst_size(self.descriptor.wMaxPacketSize, self.max_burst())
+    }
+
     /// Returns the unknown 'extra' bytes that libusb does not understand.
     pub fn extra(&'a self) -> Option<&'a [u8]> {
         unsafe {
-            match (*self.descriptor).extra_length {
-                len if len > 0 => Some(slice::from_raw_parts(
-                    (*self.descriptor).extra,
-                    len as usize,
-                )),
+            match self.descriptor.extra_length {
+                len if len > 0 => Some(slice::from_raw_parts(self.descriptor.extra, len as usize)),
                 _ => None,
             }
         }
@@ -101,8 +145,104 @@ impl<'a> fmt::Debug for EndpointDescriptor<'a> {
     }
 }
 
+/// Describes the SuperSpeed behavior of an endpoint, see
+/// [`EndpointDescriptor::ss_companion`](struct.EndpointDescriptor.html#method.ss_companion).
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct SuperSpeedEndpointCompanion {
+    max_burst: u8,
+    attributes: u8,
+    bytes_per_interval: u16,
+}
+
+impl SuperSpeedEndpointCompanion {
+    /// Returns the number of packets the endpoint moves per burst, minus one.
+    pub fn max_burst(&self) -> u8 {
+        self.max_burst
+    }
+
+    /// Returns the raw `bmAttributes` field, whose meaning depends on the transfer type.
+    pub fn attributes(&self) -> u8 {
+        self.attributes
+    }
+
+    /// Returns the number of streams a bulk endpoint supports, zero if it doesn't use streams.
+    ///
+    /// The return value of this method is only valid for bulk endpoints.
+    pub fn max_streams(&self) -> u32 {
+        match self.attributes & 0x1F {
+            0 => 0,
+            n => 1 << n,
+        }
+    }
+
+    /// Returns the number of bursts an isochronous endpoint moves per service interval.
+    ///
+    /// The return value of this method is only valid for isochronous endpoints.
+    pub fn mult(&self) -> u8 {
+        (self.attributes & 0x03) + 1
+    }
+
+    /// Returns the number of bytes a periodic endpoint moves per service interval.
+    ///
+    /// The return value of this method is only valid for interrupt and isochronous endpoints.
+    pub fn bytes_per_interval(&self) -> u16 {
+        self.bytes_per_interval
+    }
+}
+
+pub(crate) fn direction(address: u8) -> Direction {
+    match address & LIBUSB_ENDPOINT_DIR_MASK {
+        LIBUSB_ENDPOINT_OUT => Direction::Out,
+        _ => Direction::In,
+    }
+}
+
+pub(crate) fn transfer_type(attributes: u8) -> TransferType {
+    match attributes & LIBUSB_TRANSFER_TYPE_MASK {
+        LIBUSB_TRANSFER_TYPE_CONTROL => TransferType::Control,
+        LIBUSB_TRANSFER_TYPE_ISOCHRONOUS => TransferType::Isochronous,
+        LIBUSB_TRANSFER_TYPE_BULK => TransferType::Bulk,
+        _ => TransferType::Interrupt,
+    }
+}
+
+pub(crate) fn sync_type(attributes: u8) -> SyncType {
+    match (attributes & LIBUSB_ISO_SYNC_TYPE_MASK) >> 2 {
+        LIBUSB_ISO_SYNC_TYPE_NONE => SyncType::NoSync,
+        LIBUSB_ISO_SYNC_TYPE_ASYNC => SyncType::Asynchronous,
+        LIBUSB_ISO_SYNC_TYPE_ADAPTIVE => SyncType::Adaptive,
+        _ => SyncType::Synchronous,
+    }
+}
+
+pub(crate) fn usage_type(attributes: u8) -> UsageType {
+    match (attributes & LIBUSB_ISO_USAGE_TYPE_MASK) >> 4 {
+        LIBUSB_ISO_USAGE_TYPE_DATA => UsageType::Data,
+        LIBUSB_ISO_USAGE_TYPE_FEEDBACK => UsageType::Feedback,
+        LIBUSB_ISO_USAGE_TYPE_IMPLICIT => UsageType::FeedbackData,
+        _ => UsageType::Reserved,
+    }
+}
+
+/// Finds `bMaxBurst` in the SuperSpeed endpoint companion descriptor following an endpoint.
+pub(crate) fn max_burst(extra: Option<&[u8]>) -> Option<u8> {
+    ExtraDescriptors::new(extra?).find_map(|(descriptor_type, raw)| match descriptor_type {
+        DescriptorType::SuperSpeedEndpointCompanion => raw.get(2).copied(),
+        _ => None,
+    })
+}
+
+pub(crate) fn burst_size(max_packet_size: u16, max_burst: Option<u8>) -> usize {
+    let packet_size = usize::from(max_packet_size & 0x07FF);
+
+    match max_burst {
+        Some(burst) => packet_size * (usize::from(burst) + 1),
+        None => packet_size * (usize::from(max_packet_size >> 11 & 0x03) + 1),
+    }
+}
+
 #[doc(hidden)]
-pub(crate) fn from_libusb(endpoint: &libusb_endpoint_descriptor) -> EndpointDescriptor {
+pub(crate) fn from_libusb(endpoint: &libusb_endpoint_descriptor) -> EndpointDescriptor<'_> {
     EndpointDescriptor {
         descriptor: endpoint,
     }
@@ -112,6 +252,52 @@ pub(crate) fn from_libusb(endpoint: &libusb_endpoint_descriptor) -> EndpointDesc
 mod test {
     use crate::fields::{Direction, SyncType, TransferType, UsageType};
 
+    #[test]
+    fn it_finds_max_burst_in_companion_descriptor() {
+        assert_eq!(None, super::max_burst(None));
+        assert_eq!(Some(15), super::max_burst(Some(&[6, 0x30, 15, 0, 0, 0])));
+        // a class-specific descriptor first, then the companion
+        assert_eq!(
+            Some(3),
+            super::max_burst(Some(&[3, 0x25, 1, 6, 0x30, 3, 0, 0, 0]))
+        );
+        assert_eq!(None, super::max_burst(Some(&[6, 0x30, 15])));
+    }
+
+    #[test]
+    fn it_decodes_companion_attributes() {
+        let bulk = super::SuperSpeedEndpointCompanion {
+            max_burst: 15,
+            attributes: 0x04,
+            bytes_per_interval: 0,
+        };
+        assert_eq!(15, bulk.max_burst());
+        assert_eq!(16, bulk.max_streams());
+
+        let iso = super::SuperSpeedEndpointCompanion {
+            max_burst: 3,
+            attributes: 0x02,
+            bytes_per_interval: 12288,
+        };
+        assert_eq!(3, iso.mult());
+        assert_eq!(12288, iso.bytes_per_interval());
+
+        let plain = super::SuperSpeedEndpointCompanion {
+            max_burst: 0,
+            attributes: 0,
+            bytes_per_interval: 0,
+        };
+        assert_eq!(0, plain.max_streams());
+        assert_eq!(1, plain.mult());
+    }
+
+    #[test]
+    fn it_computes_burst_size() {
+        assert_eq!(512, super::burst_size(512, None));
+        assert_eq!(3 * 1024, super::burst_size(0x1400, None));
+        assert_eq!(16 * 1024, super::burst_size(1024, Some(15)));
+    }
+
     #[test]
     fn it_interprets_number_for_output_endpoints() {
         assert_eq!(
@@ -0,0 +1,447 @@
+use std::{
+    fmt,
+    io::{self, BufRead, Read, Write},
+    time::{Duration, Instant},
+};
+
+use libusb1_sys::constants::*;
+
+use crate::{
+    device_io::DeviceIo, endpoint_descriptor::burst_size, error::Error, fields::TransferType,
+};
+
+/// The packet size used until one is set, a multiple of the maximum packet size of every bulk
+/// endpoint and of most interrupt endpoints.
+const DEFAULT_PACKET_SIZE: usize = 1024;
+
+/// The timeout of each transfer until one is set.
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Reads from a bulk or interrupt IN endpoint through `std::io::Read` and `std::io::BufRead`.
+///
+/// Each transfer reads a multiple of the endpoint's maximum packet size, so the device can never
+/// send more than a transfer holds: reads into buffers smaller than a packet go through an
+/// internal buffer, and the data left over is returned by the next reads. Zero-length packets are
+/// skipped, since `Ok(0)` means the end of the stream to `Read` users; a read that only receives
+/// zero-length packets until the timeout fails as if it timed out.
+///
+/// Errors are converted to `std::io::Error`, e.g. a transfer that times out without receiving
+/// data fails with `ErrorKind::TimedOut`. The original error is available through
+/// `std::io::Error::get_ref`.
+///
+/// ```no_run
+/// use std::io::BufRead;
+///
+/// use rusb::EndpointReader;
+///
+/// # fn main() -> std::io::Result<()> {
+/// let mut handle = rusb::open_device_with_vid_pid(0x1234, 0x5678).unwrap();
+/// handle.claim_interface(1)?;
+///
+/// let reader = EndpointReader::bulk(&handle, 0x82)?.max_packet_size(64);
+/// for line in reader.lines() {
+///     println!("{}", line?);
+/// }
+/// # Ok(())
+/// # }
+/// ```
+pub struct EndpointReader<'d, D: DeviceIo + ?Sized> {
+    device: &'d D,
+    endpoint: u8,
+    transfer_type: TransferType,
+    packet_size: usize,
+    timeout: Duration,
+    buffer: Vec<u8>,
+    start: usize,
+    end: usize,
+}
+
+impl<'d, D: DeviceIo + ?Sized> EndpointReader<'d, D> {
+    /// Creates a reader for the bulk IN `endpoint` of `device`.
+    ///
+    /// ## Errors
+    ///
+    /// * `InvalidParam` if `endpoint` is not an IN endpoint.
+    #[cfg_attr(feature = "async-only", deprecated(note = "blocks the calling thread"))]
+    pub fn bulk(device: &'d D, endpoint: u8) -> crate::Result<EndpointReader<'d, D>> {
+        EndpointReader::new(device, endpoint, TransferType::Bulk)
+    }
+
+    /// Creates a reader for the interrupt IN `endpoint` of `device`.
+    ///
+    /// ## Errors
+    ///
+    /// * `InvalidParam` if `endpoint` is not an IN endpoint.
+    #[cfg_attr(feature = "async-only", deprecated(note = "blocks the calling thread"))]
+    pub fn interrupt(device: &'d D, endpoint: u8) -> crate::Result<EndpointReader<'d, D>> {
+        EndpointReader::new(device, endpoint, TransferType::Interrupt)
+    }
+
+    fn new(
+        device: &'d D,
+        endpoint: u8,
+        transfer_type: TransferType,
+    ) -> crate::Result<EndpointReader<'d, D>> {
+        if endpoint & LIBUSB_ENDPOINT_DIR_MASK != LIBUSB_ENDPOINT_IN {
+            return Err(Error::InvalidParam);
+        }
+
+        Ok(EndpointReader {
+            device,
+            endpoint,
+            transfer_type,
+            packet_size: DEFAULT_PACKET_SIZE,
+            timeout: DEFAULT_TIMEOUT,
+            buffer: Vec::new(),
+            start: 0,
+            end: 0,
+        })
+    }
+
+    /// Sets the endpoint's maximum packet size, as returned by
+    /// [`EndpointDescriptor::max_packet_size`](struct.EndpointDescriptor.html#method.max_packet_size).
+    ///
+    /// Defaults to 1024 bytes, which suits every endpoint except high-bandwidth interrupt
+    /// endpoints, but makes reads into small buffers go through a larger internal buffer than
+    /// needed.
+    pub fn max_packet_size(mut self, max_packet_size: u16) -> EndpointReader<'d, D> {
+        self.packet_size = burst_size(max_packet_size, None).max(1);
+        self
+    }
+
+    /// Sets the timeout of each transfer.
+    ///
+    /// Defaults to one second.
+    pub fn timeout(mut self, timeout: Duration) -> EndpointReader<'d, D> {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Returns the endpoint being read.
+    pub fn endpoint(&self) -> u8 {
+        self.endpoint
+    }
+
+    /// Returns the data received but not read yet.
+    pub fn buffered(&self) -> &[u8] {
+        &self.buffer[self.start..self.end]
+    }
+
+    /// Reads the next non-empty transfer into `buf`, whose length is a multiple of the packet
+    /// size, within the timeout.
+    #[cfg_attr(feature = "async-only", allow(deprecated))]
+    fn transfer(&self, buf: &mut [u8]) -> crate::Result<usize> {
+        let deadline = Instant::now() + self.timeout;
+        let mut timeout = self.timeout;
+        loop {
+            let len = match self.transfer_type {
+                TransferType::Interrupt => {
+                    self.device.read_interrupt(self.endpoint, buf, timeout)?
+                }
+                _ => self.device.read_bulk(self.endpoint, buf, timeout)?,
+            };
+            if len > 0 {
+                return Ok(len);
+            }
+
+            // libusb rounds the timeout down to milliseconds, and 0 means none
+            timeout = deadline.saturating_duration_since(Instant::now());
+            if timeout < Duration::from_millis(1) {
+                return Err(Error::Timeout);
+            }
+        }
+    }
+}
+
+impl<'d, D: DeviceIo + ?Sized> Read for EndpointReader<'d, D> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        // large reads bypass the internal buffer
+        if self.start == self.end && buf.len() >= self.packet_size {
+            let len = buf.len() - buf.len() % self.packet_size;
+            return Ok(self.transfer(&mut buf[..len])?);
+        }
+
+        let available = self.fill_buf()?;
+        let len = available.len().min(buf.len());
+        buf[..len].copy_from_slice(&available[..len]);
+        self.consume(len);
+        Ok(len)
+    }
+}
+
+impl<'d, D: DeviceIo + ?Sized> BufRead for EndpointReader<'d, D> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        if self.start == self.end {
+            let mut buffer = std::mem::take(&mut self.buffer);
+            buffer.resize(self.packet_size, 0);
+            let len = self.transfer(&mut buffer);
+            self.buffer = buffer;
+            self.start = 0;
+            self.end = len?;
+        }
+        Ok(self.buffered())
+    }
+
+    fn consume(&mut self, amount: usize) {
+        self.start = (self.start + amount).min(self.end);
+    }
+}
+
+impl<'d, D: DeviceIo + ?Sized> fmt::Debug for EndpointReader<'d, D> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("EndpointReader")
+            .field("endpoint", &self.endpoint)
+            .field("transfer_type", &self.transfer_type)
+            .field("packet_size", &self.packet_size)
+            .field("timeout", &self.timeout)
+            .field("buffered", &(self.end - self.start))
+            .finish()
+    }
+}
+
+/// Writes to a bulk or interrupt OUT endpoint through `std::io::Write`.
+///
+/// Each call to `write` sends at most one maximum-size packet, so `write_all` splits longer data
+/// into packets. Nothing is buffered: `flush` has nothing to do.
+///
+/// No zero-length packet is ever sent, so data whose length is a multiple of the packet size
+/// doesn't end with a short packet. Protocols that end their transfers with one need to send it
+/// themselves, with an empty `DeviceIo::write_bulk`.
+///
+/// Errors are converted to `std::io::Error` as for [`EndpointReader`](struct.EndpointReader.html).
+pub struct EndpointWriter<'d, D: DeviceIo + ?Sized> {
+    device: &'d D,
+    endpoint: u8,
+    transfer_type: TransferType,
+    packet_size: usize,
+    timeout: Duration,
+}
+
+impl<'d, D: DeviceIo + ?Sized> EndpointWriter<'d, D> {
+    /// Creates a writer for the bulk OUT `endpoint` of `device`.
+    ///
+    /// ## Errors
+    ///
+    /// * `InvalidParam` if `endpoint` is not an OUT endpoint.
+    #[cfg_attr(feature = "async-only", deprecated(note = "blocks the calling thread"))]
+    pub fn bulk(device: &'d D, endpoint: u8) -> crate::Result<EndpointWriter<'d, D>> {
+        EndpointWriter::new(device, endpoint, TransferType::Bulk)
+    }
+
+    /// Creates a writer for the interrupt OUT `endpoint` of `device`.
+    ///
+    /// ## Errors
+    ///
+    /// * `InvalidParam` if `endpoint` is not an OUT endpoint.
+    #[cfg_attr(feature = "async-only", deprecated(note = "blocks the calling thread"))]
+    pub fn interrupt(device: &'d D, endpoint: u8) -> crate::Result<EndpointWriter<'d, D>> {
+        EndpointWriter::new(device, endpoint, TransferType::Interrupt)
+    }
+
+    fn new(
+        device: &'d D,
+        endpoint: u8,
+        transfer_type: TransferType,
+    ) -> crate::Result<EndpointWriter<'d, D>> {
+        if endpoint & LIBUSB_ENDPOINT_DIR_MASK != LIBUSB_ENDPOINT_OUT {
+            return Err(Error::InvalidParam);
+        }
+
+        Ok(EndpointWriter {
+            device,
+            endpoint,
+            transfer_type,
+            packet_size: DEFAULT_PACKET_SIZE,
+            timeout: DEFAULT_TIMEOUT,
+        })
+    }
+
+    /// Sets the endpoint's maximum packet size, as returned by
+    /// [`EndpointDescriptor::max_packet_size`](struct.EndpointDescriptor.html#method.max_packet_size).
+    ///
+    /// Defaults to 1024 bytes.
+    pub fn max_packet_size(mut self, max_packet_size: u16) -> EndpointWriter<'d, D> {
+        self.packet_size = burst_size(max_packet_size, None).max(1);
+        self
+    }
+
+    /// Sets the timeout of each transfer.
+    ///
+    /// Defaults to one second.
+    pub fn timeout(mut self, timeout: Duration) -> EndpointWriter<'d, D> {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Returns the endpoint being written.
+    pub fn endpoint(&self) -> u8 {
+        self.endpoint
+    }
+}
+
+impl<'d, D: DeviceIo + ?Sized> Write for EndpointWriter<'d, D> {
+    #[cfg_attr(feature = "async-only", allow(deprecated))]
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
+        let packet = &buf[..buf.len().min(self.packet_size)];
+        let len = match self.transfer_type {
+            TransferType::Interrupt => {
+                self.device
+                    .write_interrupt(self.endpoint, packet, self.timeout)?
+            }
+            _ => self
+                .device
+                .write_bulk(self.endpoint, packet, self.timeout)?,
+        };
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl<'d, D: DeviceIo + ?Sized> fmt::Debug for EndpointWriter<'d, D> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("EndpointWriter")
+            .field("endpoint", &self.endpoint)
+            .field("transfer_type", &self.transfer_type)
+            .field("packet_size", &self.packet_size)
+            .field("timeout", &self.timeout)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+#[cfg_attr(feature = "async-only", allow(deprecated))]
+mod test {
+    use super::*;
+    use crate::fake::FakeDevice;
+
+    #[test]
+    fn it_reads_a_stream_across_packets() {
+        let device = FakeDevice::new();
+        device.push_in(0x81, &[1, 2, 3]);
+        device.push_in(0x81, &[]);
+        device.push_in(0x81, &[4, 5]);
+
+        let mut reader = EndpointReader::bulk(&device, 0x81)
+            .unwrap()
+            .max_packet_size(4);
+
+        let mut buf = [0; 2];
+        assert_eq!(2, reader.read(&mut buf).unwrap());
+        assert_eq!([1, 2], buf);
+        assert_eq!([3], reader.buffered());
+
+        let mut buf = [0; 3];
+        reader.read_exact(&mut buf).unwrap();
+        assert_eq!([3, 4, 5], buf);
+
+        let error = reader.read(&mut buf).unwrap_err();
+        assert_eq!(io::ErrorKind::TimedOut, error.kind());
+    }
+
+    /// A device whose IN endpoints only ever send zero-length packets.
+    struct Zlps;
+
+    impl DeviceIo for Zlps {
+        fn read_interrupt(&self, _: u8, _: &mut [u8], _: Duration) -> crate::Result<usize> {
+            Ok(0)
+        }
+
+        fn write_interrupt(&self, _: u8, _: &[u8], _: Duration) -> crate::Result<usize> {
+            Err(Error::NotSupported)
+        }
+
+        fn read_bulk(&self, _: u8, _: &mut [u8], _: Duration) -> crate::Result<usize> {
+            Ok(0)
+        }
+
+        fn write_bulk(&self, _: u8, _: &[u8], _: Duration) -> crate::Result<usize> {
+            Err(Error::NotSupported)
+        }
+
+        fn read_control(
+            &self,
+            _: u8,
+            _: u8,
+            _: u16,
+            _: u16,
+            _: &mut [u8],
+            _: Duration,
+        ) -> crate::Result<usize> {
+            Err(Error::NotSupported)
+        }
+
+        fn write_control(
+            &self,
+            _: u8,
+            _: u8,
+            _: u16,
+            _: u16,
+            _: &[u8],
+            _: Duration,
+        ) -> crate::Result<usize> {
+            Err(Error::NotSupported)
+        }
+    }
+
+    #[test]
+    fn it_times_out_on_zero_length_packets() {
+        let mut reader = EndpointReader::interrupt(&Zlps, 0x81)
+            .unwrap()
+            .timeout(Duration::from_millis(20));
+
+        let error = reader.read(&mut [0; 8]).unwrap_err();
+        assert_eq!(io::ErrorKind::TimedOut, error.kind());
+    }
+
+    #[test]
+    fn it_reads_lines() {
+        let device = FakeDevice::new();
+        device.push_in(0x82, b"ok\r\nre");
+        device.push_in(0x82, b"ady\r\n");
+
+        let mut reader = EndpointReader::interrupt(&device, 0x82)
+            .unwrap()
+            .max_packet_size(8);
+
+        let mut line = String::new();
+        reader.read_line(&mut line).unwrap();
+        assert_eq!("ok\r\n", line);
+        line.clear();
+        reader.read_line(&mut line).unwrap();
+        assert_eq!("ready\r\n", line);
+    }
+
+    #[test]
+    fn it_splits_writes_into_packets() {
+        let device = FakeDevice::new();
+        let mut writer = EndpointWriter::bulk(&device, 0x02)
+            .unwrap()
+            .max_packet_size(4);
+
+        writer.write_all(&[0, 1, 2, 3, 4, 5, 6, 7, 8, 9]).unwrap();
+        assert_eq!(
+            vec![vec![0, 1, 2, 3], vec![4, 5, 6, 7], vec![8, 9]],
+            device.take_out(0x02)
+        );
+    }
+
+    #[test]
+    fn it_checks_the_endpoint_direction() {
+        let device = FakeDevice::new();
+        assert_eq!(
+            Error::InvalidParam,
+            EndpointReader::bulk(&device, 0x01).unwrap_err()
+        );
+        assert_eq!(
+            Error::InvalidParam,
+            EndpointWriter::interrupt(&device, 0x81).unwrap_err()
+        );
+    }
+}
@@ -32,7 +32,11 @@ pub enum Error {
     /// Operation timed out.
     Timeout,
 
-    /// Overflow.
+    /// Overflow: the device sent more data than the buffer could hold (babble).
+    ///
+    /// The data of the transfer is lost, but the endpoint usually keeps working: make the buffer
+    /// a multiple of the endpoint's maximum packet size and read again. If the next transfers
+    /// fail with `Pipe`, clear the halt condition with `DeviceHandle::clear_halt` first.
     Overflow,
 
     /// Pipe error.
@@ -51,7 +55,6 @@ pub enum Error {
     /// The device returned a malformed descriptor
     BadDescriptor,
     */
-
     /// Other error.
     Other,
 }
@@ -91,6 +94,35 @@ impl StdError for Error {
     }
 }
 
+/// Converts to the closest `std::io::ErrorKind`, keeping the `Error` as the inner error.
+impl From<Error> for std::io::Error {
+    fn from(err: Error) -> std::io::Error {
+        let kind = match err {
+            Error::InvalidParam => std::io::ErrorKind::InvalidInput,
+            Error::Access => std::io::ErrorKind::PermissionDenied,
+            Error::NoDevice => std::io::ErrorKind::NotConnected,
+            Error::NotFound => std::io::ErrorKind::NotFound,
+            Error::Timeout => std::io::ErrorKind::TimedOut,
+            Error::Pipe => std::io::ErrorKind::BrokenPipe,
+            Error::Interrupted => std::io::ErrorKind::Interrupted,
+            _ => std::io::ErrorKind::Other,
+        };
+        std::io::Error::new(kind, err)
+    }
+}
+
+#[doc(hidden)]
+pub(crate) fn from_io_error(err: &std::io::Error) -> Error {
+    match err.kind() {
+        std::io::ErrorKind::PermissionDenied => Error::Access,
+        std::io::ErrorKind::NotFound => Error::NotFound,
+        std::io::ErrorKind::InvalidInput => Error::InvalidParam,
+        std::io::ErrorKind::TimedOut => Error::Timeout,
+        std::io::ErrorKind::Interrupted => Error::Interrupted,
+        _ => Error::Io,
+    }
+}
+
 #[doc(hidden)]
 pub(crate) fn from_libusb(err: i32) -> Error {
     match err {
@@ -107,7 +139,7 @@ pub(crate) fn from_libusb(err: i32) -> Error {
         LIBUSB_ERROR_INTERRUPTED => Error::Interrupted,
         LIBUSB_ERROR_NO_MEM => Error::NoMem,
         LIBUSB_ERROR_NOT_SUPPORTED => Error::NotSupported,
-        LIBUSB_ERROR_OTHER | _ => Error::Other,
+        _ => Error::Other,
     }
 }
 
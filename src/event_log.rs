@@ -0,0 +1,409 @@
+//! A structured log of USB activity, written as JSON lines.
+//!
+//! Deployed devices are hard to debug remotely. Once an [`EventLog`](struct.EventLog.html) is
+//! installed, rusb records device arrivals and departures, opens and closes, interface claims,
+//! synchronous transfers and failed operations, one JSON object per line, to a writer chosen by
+//! the application (a file, a rotating log, a socket...). For example:
+//!
+//! ```text
+//! {"ts_ms":1700000000123,"event":"opened","bus":1,"address":7,"device":"1234:5678 bcd=1.00 serial=? speed=high port=1-2.3"}
+//! {"ts_ms":1700000000125,"event":"claimed","bus":1,"address":7,"interface":0}
+//! {"ts_ms":1700000000140,"event":"transfer","bus":1,"address":7,"type":"bulk","endpoint":129,"requested":512,"length":64,"duration_us":812,"status":"ok"}
+//! {"ts_ms":1700000000950,"event":"transfer","bus":1,"address":7,"type":"bulk","endpoint":129,"requested":512,"length":0,"duration_us":500114,"status":"error","error":"Operation timed out"}
+//! ```
+//!
+//! Arrivals, departures and opens carry the device's
+//! [profile string](../struct.Device.html#method.profile_string) with the serial number left as
+//! `?`, since reading it takes a transfer; later events refer to the device by bus and address.
+//!
+//! Successful transfers can be sampled to keep the volume down; failed transfers and other
+//! events are always written. Nothing is recorded, and the hooks cost a single atomic load, while
+//! no log is installed. Write errors are ignored so that logging never interferes with I/O.
+
+use std::{
+    fmt::Write as _,
+    io::Write,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex,
+    },
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use crate::error::Error;
+
+/// The kinds of events recorded in the log.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum EventKind {
+    /// A device was attached (reported through hotplug callbacks).
+    Arrived,
+
+    /// A device was detached (reported through hotplug callbacks).
+    Left,
+
+    /// A device was opened.
+    Opened,
+
+    /// A device handle was closed.
+    Closed,
+
+    /// An interface was claimed.
+    Claimed,
+
+    /// An interface was released.
+    Released,
+
+    /// A synchronous transfer completed or failed.
+    Transfer,
+
+    /// Opening a device or claiming or releasing an interface failed.
+    Error,
+}
+
+impl EventKind {
+    fn name(self) -> &'static str {
+        match self {
+            EventKind::Arrived => "arrived",
+            EventKind::Left => "left",
+            EventKind::Opened => "opened",
+            EventKind::Closed => "closed",
+            EventKind::Claimed => "claimed",
+            EventKind::Released => "released",
+            EventKind::Transfer => "transfer",
+            EventKind::Error => "error",
+        }
+    }
+
+    fn bit(self) -> u16 {
+        1 << self as u16
+    }
+}
+
+/// A sink for the event log, with its filtering and sampling settings.
+pub struct EventLog {
+    writer: Box<dyn Write + Send>,
+    enabled: u16,
+    sample_every: u32,
+    skipped: u32,
+}
+
+impl EventLog {
+    /// Creates a log writing every event to `writer`.
+    pub fn new<W: Write + Send + 'static>(writer: W) -> EventLog {
+        EventLog {
+            writer: Box::new(writer),
+            enabled: !0,
+            sample_every: 1,
+            skipped: 0,
+        }
+    }
+
+    /// Only writes one in `every` successful transfers. Failed transfers are always written.
+    pub fn sample_transfers(mut self, every: u32) -> EventLog {
+        self.sample_every = every.max(1);
+        self
+    }
+
+    /// Stops writing events of the given kind.
+    pub fn disable(mut self, kind: EventKind) -> EventLog {
+        self.enabled &= !kind.bit();
+        self
+    }
+
+    /// Resumes writing events of the given kind.
+    pub fn enable(mut self, kind: EventKind) -> EventLog {
+        self.enabled |= kind.bit();
+        self
+    }
+
+    fn write(&mut self, record: &Record) {
+        if self.enabled & record.kind.bit() == 0 {
+            return;
+        }
+
+        if record.kind == EventKind::Transfer && record.error.is_none() {
+            self.skipped += 1;
+            if self.skipped < self.sample_every {
+                return;
+            }
+            self.skipped = 0;
+        }
+
+        let mut line = record.to_json();
+        line.push('\n');
+        self.writer.write_all(line.as_bytes()).ok();
+    }
+}
+
+static INSTALLED: AtomicBool = AtomicBool::new(false);
+static LOG: Mutex<Option<EventLog>> = Mutex::new(None);
+
+/// Installs the process-wide event log, replacing any previous one.
+pub fn install(log: EventLog) {
+    let mut current = lock();
+    *current = Some(log);
+    INSTALLED.store(true, Ordering::SeqCst);
+}
+
+/// Removes the event log, flushing and returning it.
+pub fn uninstall() -> Option<EventLog> {
+    let mut current = lock();
+    INSTALLED.store(false, Ordering::SeqCst);
+
+    let mut log = current.take();
+    if let Some(log) = log.as_mut() {
+        log.writer.flush().ok();
+    }
+    log
+}
+
+/// Flushes the installed event log's writer.
+pub fn flush() {
+    if let Some(log) = lock().as_mut() {
+        log.writer.flush().ok();
+    }
+}
+
+fn lock() -> std::sync::MutexGuard<'static, Option<EventLog>> {
+    LOG.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+/// Indicates whether a log is installed, so hooks can skip gathering data for nothing.
+pub(crate) fn is_enabled() -> bool {
+    INSTALLED.load(Ordering::Relaxed)
+}
+
+/// Writes a record to the installed log.
+pub(crate) fn record(record: Record) {
+    if let Some(log) = lock().as_mut() {
+        log.write(&record);
+    }
+}
+
+/// An event about to be written to the log.
+pub(crate) struct Record {
+    kind: EventKind,
+    bus: u8,
+    address: u8,
+    fields: Vec<(&'static str, Value)>,
+    error: Option<Error>,
+}
+
+pub(crate) enum Value {
+    Number(u64),
+    Str(&'static str),
+    Text(String),
+}
+
+impl Record {
+    pub(crate) fn new(kind: EventKind, bus: u8, address: u8) -> Record {
+        Record {
+            kind,
+            bus,
+            address,
+            fields: Vec::new(),
+            error: None,
+        }
+    }
+
+    pub(crate) fn number(mut self, name: &'static str, value: u64) -> Record {
+        self.fields.push((name, Value::Number(value)));
+        self
+    }
+
+    pub(crate) fn str(mut self, name: &'static str, value: &'static str) -> Record {
+        self.fields.push((name, Value::Str(value)));
+        self
+    }
+
+    pub(crate) fn text(mut self, name: &'static str, value: String) -> Record {
+        self.fields.push((name, Value::Text(value)));
+        self
+    }
+
+    pub(crate) fn duration(self, start: Option<Instant>) -> Record {
+        let elapsed = start.map(|s| s.elapsed()).unwrap_or(Duration::ZERO);
+        self.number("duration_us", elapsed.as_micros() as u64)
+    }
+
+    pub(crate) fn result<T>(mut self, res: &crate::Result<T>) -> Record {
+        self.error = res.as_ref().err().copied();
+        self
+    }
+
+    fn to_json(&self) -> String {
+        let ts_ms = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis())
+            .unwrap_or(0);
+
+        let mut json = format!(
+            "{{\"ts_ms\":{},\"event\":\"{}\",\"bus\":{},\"address\":{}",
+            ts_ms,
+            self.kind.name(),
+            self.bus,
+            self.address
+        );
+
+        for (name, value) in &self.fields {
+            match value {
+                Value::Number(n) => write!(json, ",\"{}\":{}", name, n),
+                Value::Str(s) => write!(json, ",\"{}\":\"{}\"", name, s),
+                Value::Text(s) => write!(json, ",\"{}\":\"{}\"", name, escape(s)),
+            }
+            .ok();
+        }
+
+        if self.kind == EventKind::Transfer || self.error.is_some() {
+            match self.error {
+                None => json.push_str(",\"status\":\"ok\""),
+                Some(e) => {
+                    write!(json, ",\"status\":\"error\",\"error\":\"{}\"", e.strerror()).ok();
+                }
+            }
+        }
+
+        json.push('}');
+        json
+    }
+}
+
+/// Escapes `s` for a JSON string.
+fn escape(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            c if c.is_control() => {
+                write!(escaped, "\\u{:04x}", c as u32).ok();
+            }
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        fields::Speed,
+        profile_string::{self, Serial},
+    };
+    use std::sync::Arc;
+
+    #[derive(Clone, Default)]
+    struct Buffer(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Buffer {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Buffer {
+        fn lines(&self) -> Vec<String> {
+            String::from_utf8(self.0.lock().unwrap().clone())
+                .unwrap()
+                .lines()
+                .map(String::from)
+                .collect()
+        }
+    }
+
+    fn transfer(error: Option<Error>) -> Record {
+        let res = match error {
+            Some(e) => Err(e),
+            None => Ok(64),
+        };
+
+        Record::new(EventKind::Transfer, 1, 7)
+            .str("type", "bulk")
+            .number("endpoint", 0x81)
+            .number("length", 64)
+            .result(&res)
+    }
+
+    #[test]
+    fn it_formats_records_as_json() {
+        let json = Record::new(EventKind::Claimed, 1, 7)
+            .number("interface", 2)
+            .to_json();
+
+        assert!(json.starts_with("{\"ts_ms\":"));
+        assert!(json.ends_with(",\"event\":\"claimed\",\"bus\":1,\"address\":7,\"interface\":2}"));
+    }
+
+    #[test]
+    fn it_escapes_text() {
+        let json = Record::new(EventKind::Opened, 1, 7)
+            .text("device", "a\"b\\c\n".to_string())
+            .to_json();
+
+        assert!(json.ends_with(",\"device\":\"a\\\"b\\\\c\\u000a\"}"));
+    }
+
+    #[test]
+    fn it_identifies_devices_by_their_profile() {
+        let descriptor = crate::device_descriptor::from_libusb(device_descriptor!(
+            idVendor: 0x1234,
+            idProduct: 0x5678,
+            bcdDevice: 0x0100,
+            iSerialNumber: 3
+        ));
+        let profile = profile_string::render(
+            Some(&descriptor),
+            Serial::of(Some(&descriptor), None),
+            Speed::High,
+            1,
+            Some(&[2, 3]),
+        );
+        let json = Record::new(EventKind::Opened, 1, 7)
+            .text("device", profile)
+            .to_json();
+
+        assert!(json.ends_with(
+            ",\"event\":\"opened\",\"bus\":1,\"address\":7,\
+             \"device\":\"1234:5678 bcd=1.00 serial=? speed=high port=1-2.3\"}"
+        ));
+    }
+
+    #[test]
+    fn it_reports_errors() {
+        let json = transfer(Some(Error::Timeout)).to_json();
+
+        assert!(json.ends_with(",\"status\":\"error\",\"error\":\"Operation timed out\"}"));
+    }
+
+    #[test]
+    fn it_samples_successful_transfers_only() {
+        let buffer = Buffer::default();
+        let mut log = EventLog::new(buffer.clone()).sample_transfers(3);
+
+        for _ in 0..6 {
+            log.write(&transfer(None));
+        }
+        log.write(&transfer(Some(Error::Pipe)));
+
+        assert_eq!(3, buffer.lines().len());
+    }
+
+    #[test]
+    fn it_filters_disabled_kinds() {
+        let buffer = Buffer::default();
+        let mut log = EventLog::new(buffer.clone()).disable(EventKind::Transfer);
+
+        log.write(&transfer(None));
+        log.write(&Record::new(EventKind::Opened, 1, 7));
+
+        let lines = buffer.lines();
+        assert_eq!(1, lines.len());
+        assert!(lines[0].contains("\"event\":\"opened\""));
+    }
+}
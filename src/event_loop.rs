@@ -0,0 +1,273 @@
+use std::{
+    any::Any,
+    cell::RefCell,
+    fmt,
+    panic::{self, AssertUnwindSafe},
+    sync::{
+        atomic::{AtomicBool, AtomicI32, AtomicU32, Ordering},
+        Arc, Mutex,
+    },
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+use libc::c_int;
+use libusb1_sys::{constants::*, libusb_context, libusb_handle_events_completed};
+
+use crate::context::UsbContext;
+
+// available since libusb 1.0.21, but not bound by libusb1-sys
+extern "system" {
+    pub(crate) fn libusb_interrupt_event_handler(context: *mut libusb_context);
+}
+
+/// How long the thread waits after an error before handling events again, so a persistent error
+/// doesn't turn into a busy loop.
+const ERROR_BACKOFF: Duration = Duration::from_millis(100);
+
+/// A background thread handling the events of a context, until it is dropped.
+///
+/// Asynchronous transfers, their futures and hotplug callbacks only make progress while some
+/// thread handles the events of their context. Created by
+/// [`UsbContext::spawn_event_loop`](trait.UsbContext.html#method.spawn_event_loop), the loop
+/// does that for as long as the guard lives. Dropping the guard interrupts the thread, even
+/// while it waits for events, and joins it.
+///
+/// Errors while handling events, e.g. `Io` after a hub disappeared, don't stop the loop. A panic,
+/// e.g. in a hotplug callback, stops it, unless
+/// [`set_restart_on_panic`](#method.set_restart_on_panic) is enabled: check
+/// [`is_running`](#method.is_running) before relying on the loop to complete transfers.
+pub struct EventLoop<T: UsbContext + Send + 'static> {
+    context: T,
+    stop: Arc<AtomicI32>,
+    state: Arc<LoopState>,
+    thread: Option<JoinHandle<()>>,
+}
+
+#[derive(Default)]
+struct LoopState {
+    stopped: AtomicBool,
+    restart: AtomicBool,
+    panics: AtomicU32,
+    last_panic: Mutex<Option<String>>,
+}
+
+impl<T: UsbContext + Send + 'static> EventLoop<T> {
+    /// Starts a thread handling the events of `context`.
+    pub(crate) fn spawn(context: T) -> EventLoop<T> {
+        let stop = Arc::new(AtomicI32::new(0));
+        let state = Arc::new(LoopState::default());
+
+        let thread = {
+            let context = context.clone();
+            let stop = stop.clone();
+            let state = state.clone();
+            thread::spawn(move || {
+                // libusb reads the flag once it holds the event lock, so a stop requested while
+                // another thread handles events is noticed too
+                let completed = &*stop as *const AtomicI32 as *mut c_int;
+                supervise(
+                    || {
+                        while stop.load(Ordering::SeqCst) == 0 {
+                            let n = unsafe {
+                                libusb_handle_events_completed(context.as_raw(), completed)
+                            };
+                            resume_callback_panic();
+                            match n {
+                                0 | LIBUSB_ERROR_INTERRUPTED => (),
+                                _ => thread::sleep(ERROR_BACKOFF),
+                            }
+                        }
+                    },
+                    |message| {
+                        state.panics.fetch_add(1, Ordering::SeqCst);
+                        *state.last_panic.lock().unwrap_or_else(|p| p.into_inner()) = Some(message);
+                        state.restart.load(Ordering::SeqCst)
+                    },
+                );
+                state.stopped.store(true, Ordering::SeqCst);
+            })
+        };
+
+        EventLoop {
+            context,
+            stop,
+            state,
+            thread: Some(thread),
+        }
+    }
+
+    /// Returns the context whose events the thread handles.
+    pub fn context(&self) -> &T {
+        &self.context
+    }
+
+    /// Indicates whether the thread still handles events, i.e. it didn't stop after a panic.
+    ///
+    /// Transfers submitted while the loop isn't running never complete, unless another thread
+    /// handles the events of the context.
+    pub fn is_running(&self) -> bool {
+        !self.state.stopped.load(Ordering::SeqCst)
+    }
+
+    /// Sets whether the thread handles events again after a panic, rather than stopping.
+    ///
+    /// Disabled by default, since a callback panicking on every event would otherwise panic over
+    /// and over; enabling it doesn't restart a loop that already stopped.
+    pub fn set_restart_on_panic(&self, restart: bool) {
+        self.state.restart.store(restart, Ordering::SeqCst);
+    }
+
+    /// Returns how many times the thread panicked.
+    pub fn panics(&self) -> u32 {
+        self.state.panics.load(Ordering::SeqCst)
+    }
+
+    /// Returns the message of the last panic of the thread, if it panicked.
+    pub fn last_panic(&self) -> Option<String> {
+        self.state
+            .last_panic
+            .lock()
+            .unwrap_or_else(|p| p.into_inner())
+            .clone()
+    }
+}
+
+impl<T: UsbContext + Send + 'static> Drop for EventLoop<T> {
+    fn drop(&mut self) {
+        self.stop.store(1, Ordering::SeqCst);
+        // wakes the thread up if it is waiting for events
+        unsafe { libusb_interrupt_event_handler(self.context.as_raw()) };
+
+        if let Some(thread) = self.thread.take() {
+            thread.join().ok();
+        }
+    }
+}
+
+impl<T: UsbContext + Send + 'static> fmt::Debug for EventLoop<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("EventLoop")
+            .field("running", &self.is_running())
+            .field("panics", &self.panics())
+            .finish()
+    }
+}
+
+thread_local! {
+    static CALLBACK_PANIC: RefCell<Option<Box<dyn Any + Send>>> = RefCell::new(None);
+}
+
+/// Runs a callback invoked by `libusb`, deferring its panic until `libusb` returns.
+///
+/// A panic can't unwind through `libusb`, it would abort the process. The first panic is kept
+/// for [`resume_callback_panic`](fn.resume_callback_panic.html) to raise again once the thread is
+/// back in Rust code.
+pub(crate) fn catch_callback_panic<F: FnOnce()>(f: F) {
+    if let Err(payload) = panic::catch_unwind(AssertUnwindSafe(f)) {
+        CALLBACK_PANIC.with(|slot| {
+            slot.borrow_mut().get_or_insert(payload);
+        });
+    }
+}
+
+/// Runs a callback that `libusb` may invoke from any of its functions, e.g. while opening a
+/// device, discarding its panic.
+///
+/// Such a panic can't be raised again once `libusb` returns without checking after every call,
+/// and raising it at the next unrelated event handling would be misleading. The panic hook
+/// already reported it, like the panic of a thread.
+pub(crate) fn discard_callback_panic<F: FnOnce()>(f: F) {
+    let _ = panic::catch_unwind(AssertUnwindSafe(f));
+}
+
+/// Raises again the panic of a callback that ran while this thread handled events, if any.
+pub(crate) fn resume_callback_panic() {
+    if let Some(payload) = CALLBACK_PANIC.with(|slot| slot.borrow_mut().take()) {
+        panic::resume_unwind(payload);
+    }
+}
+
+/// Runs the loop of an event-handling thread, catching its panics.
+///
+/// `on_panic` gets the message of each panic, and returns whether to run the loop again.
+pub(crate) fn supervise(mut run: impl FnMut(), mut on_panic: impl FnMut(String) -> bool) {
+    loop {
+        match panic::catch_unwind(AssertUnwindSafe(&mut run)) {
+            Ok(()) => return,
+            Err(payload) => {
+                if !on_panic(panic_message(&*payload)) {
+                    return;
+                }
+            }
+        }
+    }
+}
+
+fn panic_message(payload: &(dyn Any + Send)) -> String {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        message.to_string()
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message.clone()
+    } else {
+        "Box<dyn Any>".to_string()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn it_defers_callback_panics() {
+        catch_callback_panic(|| panic!("first"));
+        catch_callback_panic(|| panic!("second"));
+
+        let payload = panic::catch_unwind(resume_callback_panic).unwrap_err();
+        assert_eq!("first", panic_message(&*payload));
+        resume_callback_panic();
+    }
+
+    #[test]
+    fn it_discards_the_panics_of_callbacks_outside_of_event_handling() {
+        discard_callback_panic(|| panic!("discarded"));
+        resume_callback_panic();
+    }
+
+    #[test]
+    fn it_restarts_a_panicking_loop_when_asked() {
+        let mut runs = 0;
+        let mut messages = Vec::new();
+
+        supervise(
+            || {
+                runs += 1;
+                if runs < 3 {
+                    panic!("run {}", runs);
+                }
+            },
+            |message| {
+                messages.push(message);
+                true
+            },
+        );
+
+        assert_eq!(3, runs);
+        assert_eq!(vec!["run 1", "run 2"], messages);
+    }
+
+    #[test]
+    fn it_stops_a_panicking_loop() {
+        let mut runs = 0;
+
+        supervise(
+            || {
+                runs += 1;
+                panic!("boom");
+            },
+            |_| false,
+        );
+
+        assert_eq!(1, runs);
+    }
+}
@@ -0,0 +1,413 @@
+//! A pure-Rust fake device for testing code written against [`DeviceIo`](../trait.DeviceIo.html).
+//!
+//! Nothing in this module calls into `libusb`, so tests using it can run under Miri and the
+//! sanitizers, and on machines without USB access. Data is queued on IN endpoints ahead of time,
+//! data written to OUT endpoints is recorded, and control requests are answered from a table of
+//! descriptors or by a user supplied handler.
+
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::Mutex,
+    time::Duration,
+};
+
+use libusb1_sys::constants::*;
+
+use crate::{device_io::DeviceIo, Error, Result};
+
+/// The setup stage of a control request received by a [`FakeDevice`](struct.FakeDevice.html).
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct ControlSetup {
+    /// The `bmRequestType` field.
+    pub request_type: u8,
+
+    /// The `bRequest` field.
+    pub request: u8,
+
+    /// The `wValue` field.
+    pub value: u16,
+
+    /// The `wIndex` field.
+    pub index: u16,
+}
+
+type ControlHandler = Box<dyn FnMut(&ControlSetup, &mut Vec<u8>) -> Result<()> + Send>;
+
+#[derive(Default)]
+struct State {
+    in_queues: HashMap<u8, VecDeque<Result<Vec<u8>>>>,
+    out_data: HashMap<u8, Vec<Vec<u8>>>,
+    control_writes: Vec<(ControlSetup, Vec<u8>)>,
+    descriptors: HashMap<(u8, u8, u16), Vec<u8>>,
+    halted: Vec<u8>,
+    disconnected: bool,
+}
+
+/// An in-memory USB device that implements [`DeviceIo`](../trait.DeviceIo.html).
+///
+/// Reads never block: reading from an endpoint with nothing queued fails immediately with
+/// `Error::Timeout`, which keeps tests deterministic.
+#[derive(Default)]
+pub struct FakeDevice {
+    state: Mutex<State>,
+    handler: Mutex<Option<ControlHandler>>,
+}
+
+impl FakeDevice {
+    /// Creates a fake device with no queued data and no descriptors.
+    pub fn new() -> FakeDevice {
+        FakeDevice::default()
+    }
+
+    /// Queues a packet to be returned by the next read from the IN `endpoint`.
+    pub fn push_in(&self, endpoint: u8, data: &[u8]) {
+        self.lock()
+            .in_queues
+            .entry(endpoint)
+            .or_default()
+            .push_back(Ok(data.to_vec()));
+    }
+
+    /// Queues an error to be returned by a read from the IN `endpoint`, in order with the data
+    /// queued by [`push_in`](#method.push_in).
+    pub fn push_in_error(&self, endpoint: u8, error: Error) {
+        self.lock()
+            .in_queues
+            .entry(endpoint)
+            .or_default()
+            .push_back(Err(error));
+    }
+
+    /// Returns and clears the packets written to the OUT `endpoint`.
+    pub fn take_out(&self, endpoint: u8) -> Vec<Vec<u8>> {
+        self.lock().out_data.remove(&endpoint).unwrap_or_default()
+    }
+
+    /// Returns and clears the data stages of all control writes received so far.
+    pub fn take_control_writes(&self) -> Vec<(ControlSetup, Vec<u8>)> {
+        std::mem::take(&mut self.lock().control_writes)
+    }
+
+    /// Registers a descriptor to be returned by standard `GET_DESCRIPTOR` requests.
+    ///
+    /// `language` is the `wIndex` of the request, which is the language ID for string
+    /// descriptors and zero for everything else.
+    ///
+    /// `descriptor_type` is either a [`DescriptorType`](../enum.DescriptorType.html) or a raw
+    /// descriptor type.
+    pub fn add_descriptor<D: Into<u8>>(
+        &self,
+        descriptor_type: D,
+        index: u8,
+        language: u16,
+        data: &[u8],
+    ) {
+        self.lock()
+            .descriptors
+            .insert((descriptor_type.into(), index, language), data.to_vec());
+    }
+
+    /// Installs a handler for control requests that are not answered from the descriptor table.
+    ///
+    /// For IN requests the handler fills the data stage, which is truncated to the caller's
+    /// buffer. For OUT requests it receives the data stage written by the caller. Without a
+    /// handler, such requests fail with `Error::Pipe` like an unsupported request on real
+    /// hardware.
+    pub fn set_control_handler<F>(&self, handler: F)
+    where
+        F: FnMut(&ControlSetup, &mut Vec<u8>) -> Result<()> + Send + 'static,
+    {
+        *self.handler.lock().unwrap() = Some(Box::new(handler));
+    }
+
+    /// Sets or clears the halt condition on an endpoint. Transfers on a halted endpoint fail with
+    /// `Error::Pipe`.
+    pub fn set_halted(&self, endpoint: u8, halted: bool) {
+        let mut state = self.lock();
+        state.halted.retain(|&e| e != endpoint);
+        if halted {
+            state.halted.push(endpoint);
+        }
+    }
+
+    /// Simulates unplugging the device. All further operations fail with `Error::NoDevice`.
+    pub fn disconnect(&self) {
+        self.lock().disconnected = true;
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn read(&self, endpoint: u8, buf: &mut [u8]) -> Result<usize> {
+        if endpoint & LIBUSB_ENDPOINT_DIR_MASK != LIBUSB_ENDPOINT_IN {
+            return Err(Error::InvalidParam);
+        }
+        let mut state = self.lock();
+        state.check(endpoint)?;
+
+        let data = match state
+            .in_queues
+            .get_mut(&endpoint)
+            .and_then(|q| q.pop_front())
+        {
+            Some(data) => data?,
+            None => return Err(Error::Timeout),
+        };
+        if data.len() > buf.len() {
+            return Err(Error::Overflow);
+        }
+        buf[..data.len()].copy_from_slice(&data);
+        Ok(data.len())
+    }
+
+    fn write(&self, endpoint: u8, buf: &[u8]) -> Result<usize> {
+        if endpoint & LIBUSB_ENDPOINT_DIR_MASK != LIBUSB_ENDPOINT_OUT {
+            return Err(Error::InvalidParam);
+        }
+        let mut state = self.lock();
+        state.check(endpoint)?;
+
+        state
+            .out_data
+            .entry(endpoint)
+            .or_default()
+            .push(buf.to_vec());
+        Ok(buf.len())
+    }
+
+    fn control(&self, setup: ControlSetup, data: &mut Vec<u8>) -> Result<()> {
+        self.lock().check(0)?;
+
+        if setup.request_type == LIBUSB_ENDPOINT_IN
+            && setup.request == LIBUSB_REQUEST_GET_DESCRIPTOR
+        {
+            let key = ((setup.value >> 8) as u8, setup.value as u8, setup.index);
+            if let Some(descriptor) = self.lock().descriptors.get(&key) {
+                *data = descriptor.clone();
+                return Ok(());
+            }
+        }
+
+        match self.handler.lock().unwrap().as_mut() {
+            Some(handler) => handler(&setup, data),
+            None => Err(Error::Pipe),
+        }
+    }
+}
+
+impl State {
+    fn check(&self, endpoint: u8) -> Result<()> {
+        if self.disconnected {
+            Err(Error::NoDevice)
+        } else if self.halted.contains(&endpoint) {
+            Err(Error::Pipe)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+impl DeviceIo for FakeDevice {
+    fn read_interrupt(&self, endpoint: u8, buf: &mut [u8], _timeout: Duration) -> Result<usize> {
+        self.read(endpoint, buf)
+    }
+
+    fn write_interrupt(&self, endpoint: u8, buf: &[u8], _timeout: Duration) -> Result<usize> {
+        self.write(endpoint, buf)
+    }
+
+    fn read_bulk(&self, endpoint: u8, buf: &mut [u8], _timeout: Duration) -> Result<usize> {
+        self.read(endpoint, buf)
+    }
+
+    fn write_bulk(&self, endpoint: u8, buf: &[u8], _timeout: Duration) -> Result<usize> {
+        self.write(endpoint, buf)
+    }
+
+    fn read_control(
+        &self,
+        request_type: u8,
+        request: u8,
+        value: u16,
+        index: u16,
+        buf: &mut [u8],
+        _timeout: Duration,
+    ) -> Result<usize> {
+        if request_type & LIBUSB_ENDPOINT_DIR_MASK != LIBUSB_ENDPOINT_IN {
+            return Err(Error::InvalidParam);
+        }
+        let setup = ControlSetup {
+            request_type,
+            request,
+            value,
+            index,
+        };
+
+        let mut data = Vec::new();
+        self.control(setup, &mut data)?;
+
+        let len = data.len().min(buf.len());
+        buf[..len].copy_from_slice(&data[..len]);
+        Ok(len)
+    }
+
+    fn write_control(
+        &self,
+        request_type: u8,
+        request: u8,
+        value: u16,
+        index: u16,
+        buf: &[u8],
+        _timeout: Duration,
+    ) -> Result<usize> {
+        if request_type & LIBUSB_ENDPOINT_DIR_MASK != LIBUSB_ENDPOINT_OUT {
+            return Err(Error::InvalidParam);
+        }
+        let setup = ControlSetup {
+            request_type,
+            request,
+            value,
+            index,
+        };
+
+        let mut data = buf.to_vec();
+        self.control(setup, &mut data)?;
+
+        self.lock().control_writes.push((setup, buf.to_vec()));
+        Ok(buf.len())
+    }
+}
+
+#[cfg(test)]
+#[cfg_attr(feature = "async-only", allow(deprecated))]
+mod test {
+    use super::*;
+
+    const TIMEOUT: Duration = Duration::from_secs(1);
+
+    #[test]
+    fn it_returns_queued_data_in_order() {
+        let device = FakeDevice::new();
+        device.push_in(0x81, &[1, 2, 3]);
+        device.push_in(0x81, &[4]);
+
+        let mut buf = [0u8; 8];
+        assert_eq!(Ok(3), device.read_bulk(0x81, &mut buf, TIMEOUT));
+        assert_eq!([1, 2, 3], buf[..3]);
+        assert_eq!(Ok(1), device.read_interrupt(0x81, &mut buf, TIMEOUT));
+        assert_eq!(4, buf[0]);
+        assert_eq!(
+            Err(Error::Timeout),
+            device.read_bulk(0x81, &mut buf, TIMEOUT)
+        );
+    }
+
+    #[test]
+    fn it_reports_overflow_for_short_buffers() {
+        let device = FakeDevice::new();
+        device.push_in(0x81, &[0; 16]);
+
+        let mut buf = [0u8; 8];
+        assert_eq!(
+            Err(Error::Overflow),
+            device.read_bulk(0x81, &mut buf, TIMEOUT)
+        );
+    }
+
+    #[test]
+    fn it_records_written_data() {
+        let device = FakeDevice::new();
+        assert_eq!(Ok(2), device.write_bulk(0x02, &[5, 6], TIMEOUT));
+        assert_eq!(Ok(1), device.write_interrupt(0x02, &[7], TIMEOUT));
+
+        assert_eq!(vec![vec![5, 6], vec![7]], device.take_out(0x02));
+        assert!(device.take_out(0x02).is_empty());
+    }
+
+    #[test]
+    fn it_checks_endpoint_direction() {
+        let device = FakeDevice::new();
+        let mut buf = [0u8; 8];
+
+        assert_eq!(
+            Err(Error::InvalidParam),
+            device.read_bulk(0x01, &mut buf, TIMEOUT)
+        );
+        assert_eq!(
+            Err(Error::InvalidParam),
+            device.write_bulk(0x81, &buf, TIMEOUT)
+        );
+    }
+
+    #[test]
+    fn it_serves_descriptors() {
+        let device = FakeDevice::new();
+        device.add_descriptor(LIBUSB_DT_STRING, 0, 0, &[4, 3, 0x09, 0x04]);
+
+        let mut buf = [0u8; 255];
+        let len = device
+            .read_control(
+                0x80,
+                LIBUSB_REQUEST_GET_DESCRIPTOR,
+                0x0300,
+                0,
+                &mut buf,
+                TIMEOUT,
+            )
+            .unwrap();
+        assert_eq!([4, 3, 0x09, 0x04], buf[..len]);
+    }
+
+    #[test]
+    fn it_stalls_unhandled_control_requests() {
+        let device = FakeDevice::new();
+        let mut buf = [0u8; 8];
+
+        assert_eq!(
+            Err(Error::Pipe),
+            device.read_control(0xC0, 0x01, 0, 0, &mut buf, TIMEOUT)
+        );
+    }
+
+    #[test]
+    fn it_passes_control_requests_to_handler() {
+        let device = FakeDevice::new();
+        device.set_control_handler(|setup, data| {
+            if setup.request_type & 0x80 != 0 {
+                *data = vec![setup.request, setup.value as u8];
+            }
+            Ok(())
+        });
+
+        let mut buf = [0u8; 8];
+        assert_eq!(
+            Ok(2),
+            device.read_control(0xC0, 0x10, 0x20, 0, &mut buf, TIMEOUT)
+        );
+        assert_eq!([0x10, 0x20], buf[..2]);
+
+        assert_eq!(Ok(1), device.write_control(0x40, 0x11, 0, 0, &[9], TIMEOUT));
+        let writes = device.take_control_writes();
+        assert_eq!(1, writes.len());
+        assert_eq!(0x11, writes[0].0.request);
+        assert_eq!(vec![9], writes[0].1);
+    }
+
+    #[test]
+    fn it_simulates_halt_and_disconnect() {
+        let device = FakeDevice::new();
+        let mut buf = [0u8; 8];
+
+        device.set_halted(0x81, true);
+        assert_eq!(Err(Error::Pipe), device.read_bulk(0x81, &mut buf, TIMEOUT));
+        device.set_halted(0x81, false);
+        assert_eq!(
+            Err(Error::Timeout),
+            device.read_bulk(0x81, &mut buf, TIMEOUT)
+        );
+
+        device.disconnect();
+        assert_eq!(Err(Error::NoDevice), device.write_bulk(0x01, &buf, TIMEOUT));
+    }
+}
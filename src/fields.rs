@@ -28,7 +28,7 @@ pub(crate) fn speed_from_libusb(n: c_int) -> Speed {
         LIBUSB_SPEED_FULL => Speed::Full,
         LIBUSB_SPEED_LOW => Speed::Low,
 
-        LIBUSB_SPEED_UNKNOWN | _ => Speed::Unknown,
+        _ => Speed::Unknown,
     }
 }
 
@@ -80,10 +80,11 @@ pub enum UsageType {
     /// Data endpoint.
     Data,
 
-    /// Feedback endpoint.
+    /// Explicit feedback endpoint.
     Feedback,
 
-    /// Explicit feedback data endpoint.
+    /// Implicit feedback data endpoint, i.e. a data endpoint whose packets also serve as
+    /// feedback for another endpoint.
     FeedbackData,
 
     /// Reserved.
@@ -122,6 +123,215 @@ pub enum Recipient {
     Other,
 }
 
+/// Device and interface class codes, as assigned by the USB-IF.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Hash)]
+pub enum ClassCode {
+    /// The class is defined by each interface, only valid for devices.
+    PerInterface,
+
+    /// Audio.
+    Audio,
+
+    /// Communications and CDC control.
+    Comm,
+
+    /// Human interface device.
+    Hid,
+
+    /// Physical.
+    Physical,
+
+    /// Still imaging.
+    Image,
+
+    /// Printer.
+    Printer,
+
+    /// Mass storage.
+    MassStorage,
+
+    /// Hub, only valid for devices.
+    Hub,
+
+    /// CDC data, only valid for interfaces.
+    Data,
+
+    /// Smart card.
+    SmartCard,
+
+    /// Content security.
+    ContentSecurity,
+
+    /// Video.
+    Video,
+
+    /// Personal healthcare.
+    PersonalHealthcare,
+
+    /// Diagnostic device.
+    Diagnostic,
+
+    /// Wireless controller.
+    Wireless,
+
+    /// Application specific, e.g. DFU.
+    Application,
+
+    /// Vendor specific.
+    VendorSpecific,
+
+    /// Any other class code.
+    Other(u8),
+}
+
+impl From<u8> for ClassCode {
+    fn from(code: u8) -> ClassCode {
+        match code {
+            LIBUSB_CLASS_PER_INTERFACE => ClassCode::PerInterface,
+            LIBUSB_CLASS_AUDIO => ClassCode::Audio,
+            LIBUSB_CLASS_COMM => ClassCode::Comm,
+            LIBUSB_CLASS_HID => ClassCode::Hid,
+            LIBUSB_CLASS_PHYSICAL => ClassCode::Physical,
+            LIBUSB_CLASS_IMAGE => ClassCode::Image,
+            LIBUSB_CLASS_PRINTER => ClassCode::Printer,
+            LIBUSB_CLASS_MASS_STORAGE => ClassCode::MassStorage,
+            LIBUSB_CLASS_HUB => ClassCode::Hub,
+            LIBUSB_CLASS_DATA => ClassCode::Data,
+            LIBUSB_CLASS_SMART_CARD => ClassCode::SmartCard,
+            LIBUSB_CLASS_CONTENT_SECURITY => ClassCode::ContentSecurity,
+            LIBUSB_CLASS_VIDEO => ClassCode::Video,
+            LIBUSB_CLASS_PERSONAL_HEALTHCARE => ClassCode::PersonalHealthcare,
+            LIBUSB_CLASS_DIAGNOSTIC_DEVICE => ClassCode::Diagnostic,
+            LIBUSB_CLASS_WIRELESS => ClassCode::Wireless,
+            LIBUSB_CLASS_APPLICATION => ClassCode::Application,
+            LIBUSB_CLASS_VENDOR_SPEC => ClassCode::VendorSpecific,
+            code => ClassCode::Other(code),
+        }
+    }
+}
+
+impl From<ClassCode> for u8 {
+    fn from(class: ClassCode) -> u8 {
+        match class {
+            ClassCode::PerInterface => LIBUSB_CLASS_PER_INTERFACE,
+            ClassCode::Audio => LIBUSB_CLASS_AUDIO,
+            ClassCode::Comm => LIBUSB_CLASS_COMM,
+            ClassCode::Hid => LIBUSB_CLASS_HID,
+            ClassCode::Physical => LIBUSB_CLASS_PHYSICAL,
+            ClassCode::Image => LIBUSB_CLASS_IMAGE,
+            ClassCode::Printer => LIBUSB_CLASS_PRINTER,
+            ClassCode::MassStorage => LIBUSB_CLASS_MASS_STORAGE,
+            ClassCode::Hub => LIBUSB_CLASS_HUB,
+            ClassCode::Data => LIBUSB_CLASS_DATA,
+            ClassCode::SmartCard => LIBUSB_CLASS_SMART_CARD,
+            ClassCode::ContentSecurity => LIBUSB_CLASS_CONTENT_SECURITY,
+            ClassCode::Video => LIBUSB_CLASS_VIDEO,
+            ClassCode::PersonalHealthcare => LIBUSB_CLASS_PERSONAL_HEALTHCARE,
+            ClassCode::Diagnostic => LIBUSB_CLASS_DIAGNOSTIC_DEVICE,
+            ClassCode::Wireless => LIBUSB_CLASS_WIRELESS,
+            ClassCode::Application => LIBUSB_CLASS_APPLICATION,
+            ClassCode::VendorSpecific => LIBUSB_CLASS_VENDOR_SPEC,
+            ClassCode::Other(code) => code,
+        }
+    }
+}
+
+/// Standard and class descriptor types, as found in `bDescriptorType` and in the high byte of
+/// the `wValue` of `GET_DESCRIPTOR` requests.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Hash)]
+pub enum DescriptorType {
+    /// Device descriptor.
+    Device,
+
+    /// Configuration descriptor.
+    Config,
+
+    /// String descriptor.
+    String,
+
+    /// Interface descriptor.
+    Interface,
+
+    /// Endpoint descriptor.
+    Endpoint,
+
+    /// Interface association descriptor.
+    InterfaceAssociation,
+
+    /// Binary Object Store (BOS) descriptor.
+    Bos,
+
+    /// Device capability descriptor, part of the BOS descriptor.
+    DeviceCapability,
+
+    /// HID descriptor.
+    Hid,
+
+    /// HID report descriptor.
+    Report,
+
+    /// HID physical descriptor.
+    Physical,
+
+    /// Hub descriptor.
+    Hub,
+
+    /// SuperSpeed hub descriptor.
+    SuperSpeedHub,
+
+    /// SuperSpeed endpoint companion descriptor.
+    SuperSpeedEndpointCompanion,
+
+    /// Any other descriptor type, e.g. a class-specific one.
+    Other(u8),
+}
+
+const DT_INTERFACE_ASSOCIATION: u8 = 0x0B;
+
+impl From<u8> for DescriptorType {
+    fn from(descriptor_type: u8) -> DescriptorType {
+        match descriptor_type {
+            LIBUSB_DT_DEVICE => DescriptorType::Device,
+            LIBUSB_DT_CONFIG => DescriptorType::Config,
+            LIBUSB_DT_STRING => DescriptorType::String,
+            LIBUSB_DT_INTERFACE => DescriptorType::Interface,
+            LIBUSB_DT_ENDPOINT => DescriptorType::Endpoint,
+            DT_INTERFACE_ASSOCIATION => DescriptorType::InterfaceAssociation,
+            LIBUSB_DT_BOS => DescriptorType::Bos,
+            LIBUSB_DT_DEVICE_CAPABILITY => DescriptorType::DeviceCapability,
+            LIBUSB_DT_HID => DescriptorType::Hid,
+            LIBUSB_DT_REPORT => DescriptorType::Report,
+            LIBUSB_DT_PHYSICAL => DescriptorType::Physical,
+            LIBUSB_DT_HUB => DescriptorType::Hub,
+            LIBUSB_DT_SUPERSPEED_HUB => DescriptorType::SuperSpeedHub,
+            LIBUSB_DT_SS_ENDPOINT_COMPANION => DescriptorType::SuperSpeedEndpointCompanion,
+            descriptor_type => DescriptorType::Other(descriptor_type),
+        }
+    }
+}
+
+impl From<DescriptorType> for u8 {
+    fn from(descriptor_type: DescriptorType) -> u8 {
+        match descriptor_type {
+            DescriptorType::Device => LIBUSB_DT_DEVICE,
+            DescriptorType::Config => LIBUSB_DT_CONFIG,
+            DescriptorType::String => LIBUSB_DT_STRING,
+            DescriptorType::Interface => LIBUSB_DT_INTERFACE,
+            DescriptorType::Endpoint => LIBUSB_DT_ENDPOINT,
+            DescriptorType::InterfaceAssociation => DT_INTERFACE_ASSOCIATION,
+            DescriptorType::Bos => LIBUSB_DT_BOS,
+            DescriptorType::DeviceCapability => LIBUSB_DT_DEVICE_CAPABILITY,
+            DescriptorType::Hid => LIBUSB_DT_HID,
+            DescriptorType::Report => LIBUSB_DT_REPORT,
+            DescriptorType::Physical => LIBUSB_DT_PHYSICAL,
+            DescriptorType::Hub => LIBUSB_DT_HUB,
+            DescriptorType::SuperSpeedHub => LIBUSB_DT_SUPERSPEED_HUB,
+            DescriptorType::SuperSpeedEndpointCompanion => LIBUSB_DT_SS_ENDPOINT_COMPANION,
+            DescriptorType::Other(descriptor_type) => descriptor_type,
+        }
+    }
+}
+
 /// A three-part version consisting of major, minor, and sub minor components.
 ///
 /// This can be used to represent versions of the format `J.M.N`, where `J` is the major version,
@@ -136,7 +346,16 @@ pub enum Recipient {
 ///
 /// The intended use case of `Version` is to extract meaning from the version fields in USB
 /// descriptors, such as `bcdUSB` and `bcdDevice` in device descriptors.
-#[derive(Debug, PartialEq, Eq, Clone, Copy, Hash)]
+///
+/// Versions are ordered by major, then minor, then sub minor version, so they can be compared
+/// directly:
+///
+/// ```
+/// use rusb::Version;
+///
+/// assert!(Version::from_bcd(0x0210) > Version(2, 0, 1));
+/// ```
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Hash)]
 pub struct Version(pub u8, pub u8, pub u8);
 
 impl Version {
@@ -159,6 +378,17 @@ impl Version {
         Version(major, minor, sub_minor)
     }
 
+    /// Encodes the version as a binary coded decimal (BCD) field, the reverse of
+    /// [`from_bcd`](#method.from_bcd). Minor and sub minor versions above 9 are truncated.
+    pub fn to_bcd(self) -> u16 {
+        let Version(major, minor, sub_minor) = self;
+
+        (u16::from(major / 10 % 10) << 12)
+            | (u16::from(major % 10) << 8)
+            | (u16::from(minor & 0x0F) << 4)
+            | u16::from(sub_minor & 0x0F)
+    }
+
     /// Returns the major version.
     pub fn major(self) -> u8 {
         let Version(major, _, _) = self;
@@ -178,6 +408,65 @@ impl Version {
     }
 }
 
+/// USB specification levels, as declared in the `bcdUSB` field of device descriptors.
+///
+/// Levels are ordered from oldest to newest.
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Hash)]
+pub enum UsbSpec {
+    /// USB 1.0, or an invalid version below it.
+    Usb1_0,
+
+    /// USB 1.1.
+    Usb1_1,
+
+    /// USB 2.0.
+    Usb2_0,
+
+    /// USB 2.0 with the LPM ECN, declared as 2.01 or 2.1. Such devices have a BOS descriptor.
+    Usb2_1,
+
+    /// USB 3.0.
+    Usb3_0,
+
+    /// USB 3.1.
+    Usb3_1,
+
+    /// USB 3.2.
+    Usb3_2,
+
+    /// USB4, or a later version.
+    Usb4,
+}
+
+impl UsbSpec {
+    /// Returns the specification level of a `bcdUSB` version.
+    ///
+    /// Versions that aren't defined by the USB-IF map to the closest level below them, e.g. 2.5
+    /// maps to `Usb2_1`.
+    pub fn from_version(version: Version) -> UsbSpec {
+        match version {
+            Version(0, _, _) | Version(1, 0, _) => UsbSpec::Usb1_0,
+            Version(1, _, _) => UsbSpec::Usb1_1,
+            Version(2, 0, 0) => UsbSpec::Usb2_0,
+            Version(2, _, _) => UsbSpec::Usb2_1,
+            Version(3, 0, _) => UsbSpec::Usb3_0,
+            Version(3, 1, _) => UsbSpec::Usb3_1,
+            Version(3, _, _) => UsbSpec::Usb3_2,
+            _ => UsbSpec::Usb4,
+        }
+    }
+
+    /// Indicates whether devices of this level provide a Binary Object Store (BOS) descriptor.
+    pub fn supports_bos(self) -> bool {
+        self >= UsbSpec::Usb2_1
+    }
+
+    /// Indicates whether this level defines SuperSpeed operation.
+    pub fn supports_super_speed(self) -> bool {
+        self >= UsbSpec::Usb3_0
+    }
+}
+
 /// Builds a value for the `bmRequestType` field of a control transfer setup packet.
 ///
 /// The `bmRequestType` field of a USB control transfer setup packet is a bit field specifying
@@ -193,7 +482,14 @@ impl Version {
 ///
 /// rusb::request_type(Direction::In, RequestType::Standard, Recipient::Device);
 /// ```
-pub fn request_type(direction: Direction, request_type: RequestType, recipient: Recipient) -> u8 {
+///
+/// The function can be evaluated at compile time, e.g. to define protocol tables as constants.
+/// Common values are predefined in [`request_types`](request_types/index.html).
+pub const fn request_type(
+    direction: Direction,
+    request_type: RequestType,
+    recipient: Recipient,
+) -> u8 {
     let mut value: u8 = match direction {
         Direction::Out => LIBUSB_ENDPOINT_OUT,
         Direction::In => LIBUSB_ENDPOINT_IN,
@@ -220,6 +516,35 @@ pub fn request_type(direction: Direction, request_type: RequestType, recipient:
 mod test {
     use super::*;
 
+    // ClassCode
+
+    #[test]
+    fn class_code_round_trips_through_u8() {
+        assert_eq!(ClassCode::Hid, ClassCode::from(3));
+        assert_eq!(ClassCode::VendorSpecific, ClassCode::from(0xff));
+        assert_eq!(ClassCode::Other(0xef), ClassCode::from(0xef));
+
+        for code in 0..=255u8 {
+            assert_eq!(code, u8::from(ClassCode::from(code)));
+        }
+    }
+
+    // DescriptorType
+
+    #[test]
+    fn descriptor_type_round_trips_through_u8() {
+        assert_eq!(DescriptorType::Bos, DescriptorType::from(0x0f));
+        assert_eq!(DescriptorType::Report, DescriptorType::from(0x22));
+        assert_eq!(DescriptorType::Other(0x24), DescriptorType::from(0x24));
+
+        for descriptor_type in 0..=255u8 {
+            assert_eq!(
+                descriptor_type,
+                u8::from(DescriptorType::from(descriptor_type))
+            );
+        }
+    }
+
     // Version
 
     #[test]
@@ -240,6 +565,86 @@ mod test {
         assert_eq!(2, Version(0, 0, 2).sub_minor());
     }
 
+    #[test]
+    fn version_is_ordered_by_components() {
+        assert!(Version(2, 0, 0) < Version(2, 0, 1));
+        assert!(Version(2, 1, 0) > Version(2, 0, 9));
+        assert!(Version(3, 0, 0) > Version(2, 9, 9));
+        assert!(Version::from_bcd(0x0110) < Version::from_bcd(0x0200));
+    }
+
+    #[test]
+    fn version_round_trips_through_bcd() {
+        for &raw in &[0x0100, 0x0110, 0x0200, 0x0210, 0x0320, 0x1234] {
+            assert_eq!(raw, Version::from_bcd(raw).to_bcd());
+        }
+    }
+
+    // UsbSpec
+
+    #[test]
+    fn usb_spec_maps_declared_versions() {
+        assert_eq!(
+            UsbSpec::Usb1_0,
+            UsbSpec::from_version(Version::from_bcd(0x0100))
+        );
+        assert_eq!(
+            UsbSpec::Usb1_1,
+            UsbSpec::from_version(Version::from_bcd(0x0110))
+        );
+        assert_eq!(
+            UsbSpec::Usb2_0,
+            UsbSpec::from_version(Version::from_bcd(0x0200))
+        );
+        assert_eq!(
+            UsbSpec::Usb2_1,
+            UsbSpec::from_version(Version::from_bcd(0x0201))
+        );
+        assert_eq!(
+            UsbSpec::Usb2_1,
+            UsbSpec::from_version(Version::from_bcd(0x0210))
+        );
+        assert_eq!(
+            UsbSpec::Usb3_0,
+            UsbSpec::from_version(Version::from_bcd(0x0300))
+        );
+        assert_eq!(
+            UsbSpec::Usb3_1,
+            UsbSpec::from_version(Version::from_bcd(0x0310))
+        );
+        assert_eq!(
+            UsbSpec::Usb3_2,
+            UsbSpec::from_version(Version::from_bcd(0x0320))
+        );
+        assert_eq!(
+            UsbSpec::Usb4,
+            UsbSpec::from_version(Version::from_bcd(0x0400))
+        );
+    }
+
+    #[test]
+    fn usb_spec_maps_undefined_versions_down() {
+        assert_eq!(
+            UsbSpec::Usb1_0,
+            UsbSpec::from_version(Version::from_bcd(0x0000))
+        );
+        assert_eq!(
+            UsbSpec::Usb2_1,
+            UsbSpec::from_version(Version::from_bcd(0x0250))
+        );
+        assert_eq!(
+            UsbSpec::Usb3_2,
+            UsbSpec::from_version(Version::from_bcd(0x0350))
+        );
+    }
+
+    #[test]
+    fn usb_spec_supports_bos_from_2_1() {
+        assert!(!UsbSpec::Usb2_0.supports_bos());
+        assert!(UsbSpec::Usb2_1.supports_bos());
+        assert!(UsbSpec::Usb3_0.supports_bos());
+    }
+
     #[test]
     fn version_parses_major_version() {
         assert_eq!(3, Version::from_bcd(0x0300).major());
@@ -0,0 +1,620 @@
+//! Builders for the descriptors a device sends to the host.
+//!
+//! They turn typed settings into the byte blobs found on the wire, with the lengths, counts and
+//! totals filled in, e.g. to configure a Linux raw-gadget or FunctionFS test device, or to feed a
+//! [`FakeDevice`](../fake/struct.FakeDevice.html) realistic descriptors:
+//!
+//! ```
+//! use rusb::gadget::*;
+//! use rusb::TransferType;
+//!
+//! let device = DeviceDescriptorBuilder::new(0x1209, 0x0001)
+//!     .strings(1, 2, 0)
+//!     .build()
+//!     .unwrap();
+//!
+//! let config = ConfigDescriptorBuilder::new(1)
+//!     .max_power_ma(100)
+//!     .interface(
+//!         InterfaceDescriptorBuilder::new(0, 0)
+//!             .class(0xff, 0, 0)
+//!             .endpoint(EndpointDescriptorBuilder::new(0x81, TransferType::Bulk).max_packet_size(512))
+//!             .endpoint(EndpointDescriptorBuilder::new(0x01, TransferType::Bulk).max_packet_size(512)),
+//!     )
+//!     .build()
+//!     .unwrap();
+//!
+//! assert_eq!(18, device.len());
+//! assert_eq!(9 + 9 + 7 + 7, config.len());
+//! ```
+
+use libusb1_sys::constants::*;
+
+use crate::{
+    error::Error,
+    fields::{TransferType, Version},
+};
+
+/// Builds a device descriptor.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DeviceDescriptorBuilder {
+    usb_version: Version,
+    class: (u8, u8, u8),
+    max_packet_size0: u8,
+    vendor_id: u16,
+    product_id: u16,
+    device_version: Version,
+    strings: (u8, u8, u8),
+    num_configurations: u8,
+}
+
+impl DeviceDescriptorBuilder {
+    /// Starts a USB 2.0 device descriptor with the given IDs, a 64 byte control endpoint, one
+    /// configuration and no strings.
+    pub fn new(vendor_id: u16, product_id: u16) -> DeviceDescriptorBuilder {
+        DeviceDescriptorBuilder {
+            usb_version: Version(2, 0, 0),
+            class: (0, 0, 0),
+            max_packet_size0: 64,
+            vendor_id,
+            product_id,
+            device_version: Version(1, 0, 0),
+            strings: (0, 0, 0),
+            num_configurations: 1,
+        }
+    }
+
+    /// Sets the USB specification version (`bcdUSB`).
+    pub fn usb_version(mut self, version: Version) -> DeviceDescriptorBuilder {
+        self.usb_version = version;
+        self
+    }
+
+    /// Sets the device's class, sub class and protocol codes.
+    pub fn class(mut self, class: u8, sub_class: u8, protocol: u8) -> DeviceDescriptorBuilder {
+        self.class = (class, sub_class, protocol);
+        self
+    }
+
+    /// Sets the maximum packet size of the control endpoint: 8, 16, 32 or 64 bytes, or 512 for
+    /// USB 3 devices.
+    pub fn max_packet_size0(mut self, size: u16) -> DeviceDescriptorBuilder {
+        // USB 3 devices declare the exponent of a power of two instead
+        self.max_packet_size0 = if size == 512 { 9 } else { size as u8 };
+        self
+    }
+
+    /// Sets the device's release number (`bcdDevice`).
+    pub fn device_version(mut self, version: Version) -> DeviceDescriptorBuilder {
+        self.device_version = version;
+        self
+    }
+
+    /// Sets the indexes of the manufacturer, product and serial number strings, 0 for none.
+    pub fn strings(mut self, manufacturer: u8, product: u8, serial: u8) -> DeviceDescriptorBuilder {
+        self.strings = (manufacturer, product, serial);
+        self
+    }
+
+    /// Sets the number of configurations.
+    pub fn num_configurations(mut self, count: u8) -> DeviceDescriptorBuilder {
+        self.num_configurations = count;
+        self
+    }
+
+    /// Returns the descriptor's bytes.
+    ///
+    /// ## Errors
+    ///
+    /// * `InvalidParam` if the control endpoint's packet size or the number of configurations
+    ///   isn't valid.
+    pub fn build(&self) -> crate::Result<[u8; 18]> {
+        let valid_size = match self.max_packet_size0 {
+            8 | 16 | 32 | 64 => true,
+            9 => self.usb_version >= Version(3, 0, 0),
+            _ => false,
+        };
+        if !valid_size || self.num_configurations == 0 {
+            return Err(Error::InvalidParam);
+        }
+
+        let usb = self.usb_version.to_bcd().to_le_bytes();
+        let vendor = self.vendor_id.to_le_bytes();
+        let product = self.product_id.to_le_bytes();
+        let device = self.device_version.to_bcd().to_le_bytes();
+
+        Ok([
+            18,
+            LIBUSB_DT_DEVICE,
+            usb[0],
+            usb[1],
+            self.class.0,
+            self.class.1,
+            self.class.2,
+            self.max_packet_size0,
+            vendor[0],
+            vendor[1],
+            product[0],
+            product[1],
+            device[0],
+            device[1],
+            self.strings.0,
+            self.strings.1,
+            self.strings.2,
+            self.num_configurations,
+        ])
+    }
+}
+
+/// Builds a configuration descriptor, followed by its interface, endpoint and class-specific
+/// descriptors.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConfigDescriptorBuilder {
+    value: u8,
+    string: u8,
+    self_powered: bool,
+    remote_wakeup: bool,
+    max_power: u8,
+    extra: Vec<u8>,
+    interfaces: Vec<InterfaceDescriptorBuilder>,
+}
+
+impl ConfigDescriptorBuilder {
+    /// Starts a bus-powered configuration with the given `bConfigurationValue` and no
+    /// interfaces.
+    pub fn new(value: u8) -> ConfigDescriptorBuilder {
+        ConfigDescriptorBuilder {
+            value,
+            string: 0,
+            self_powered: false,
+            remote_wakeup: false,
+            max_power: 0,
+            extra: Vec::new(),
+            interfaces: Vec::new(),
+        }
+    }
+
+    /// Sets the index of the configuration's description string, 0 for none.
+    pub fn string(mut self, index: u8) -> ConfigDescriptorBuilder {
+        self.string = index;
+        self
+    }
+
+    /// Declares the configuration self-powered.
+    pub fn self_powered(mut self, self_powered: bool) -> ConfigDescriptorBuilder {
+        self.self_powered = self_powered;
+        self
+    }
+
+    /// Declares support for remote wakeup.
+    pub fn remote_wakeup(mut self, remote_wakeup: bool) -> ConfigDescriptorBuilder {
+        self.remote_wakeup = remote_wakeup;
+        self
+    }
+
+    /// Sets the maximum power drawn from the bus, in milliamps, for a device that isn't operating
+    /// at SuperSpeed (2 mA units, up to 510 mA). Use [`max_power`](#method.max_power) to set the
+    /// raw value.
+    pub fn max_power_ma(mut self, milliamps: u16) -> ConfigDescriptorBuilder {
+        self.max_power = ((u32::from(milliamps) + 1) / 2).min(u8::MAX.into()) as u8;
+        self
+    }
+
+    /// Sets the raw `bMaxPower` field.
+    pub fn max_power(mut self, max_power: u8) -> ConfigDescriptorBuilder {
+        self.max_power = max_power;
+        self
+    }
+
+    /// Appends class-specific descriptors following the configuration descriptor.
+    pub fn extra(mut self, descriptors: &[u8]) -> ConfigDescriptorBuilder {
+        self.extra.extend_from_slice(descriptors);
+        self
+    }
+
+    /// Appends an interface, or an alternate setting of an interface.
+    pub fn interface(mut self, interface: InterfaceDescriptorBuilder) -> ConfigDescriptorBuilder {
+        self.interfaces.push(interface);
+        self
+    }
+
+    /// Returns the bytes of the configuration descriptor and of all the descriptors following it,
+    /// as returned by a `GET_DESCRIPTOR` request for the whole configuration.
+    ///
+    /// ## Errors
+    ///
+    /// * `InvalidParam` if the descriptors don't fit in the 64 KiB allowed by `wTotalLength`, if
+    ///   class-specific descriptors aren't a sequence of complete descriptors, or if an
+    ///   interface's endpoints are invalid.
+    pub fn build(&self) -> crate::Result<Vec<u8>> {
+        check_extra(&self.extra)?;
+
+        let mut numbers: Vec<u8> = self.interfaces.iter().map(|i| i.number).collect();
+        numbers.sort_unstable();
+        numbers.dedup();
+
+        let mut attributes = 0x80;
+        if self.self_powered {
+            attributes |= 0x40;
+        }
+        if self.remote_wakeup {
+            attributes |= 0x20;
+        }
+
+        let mut bytes = vec![
+            9,
+            LIBUSB_DT_CONFIG,
+            0,
+            0,
+            numbers.len() as u8,
+            self.value,
+            self.string,
+            attributes,
+            self.max_power,
+        ];
+        bytes.extend_from_slice(&self.extra);
+        for interface in &self.interfaces {
+            interface.append_to(&mut bytes)?;
+        }
+
+        if bytes.len() > u16::MAX as usize {
+            return Err(Error::InvalidParam);
+        }
+        let total = (bytes.len() as u16).to_le_bytes();
+        bytes[2] = total[0];
+        bytes[3] = total[1];
+        Ok(bytes)
+    }
+}
+
+/// Builds an interface descriptor, followed by its class-specific and endpoint descriptors.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InterfaceDescriptorBuilder {
+    number: u8,
+    setting: u8,
+    class: (u8, u8, u8),
+    string: u8,
+    extra: Vec<u8>,
+    endpoints: Vec<EndpointDescriptorBuilder>,
+}
+
+impl InterfaceDescriptorBuilder {
+    /// Starts an interface descriptor for the given interface and alternate setting numbers.
+    pub fn new(number: u8, setting: u8) -> InterfaceDescriptorBuilder {
+        InterfaceDescriptorBuilder {
+            number,
+            setting,
+            class: (0, 0, 0),
+            string: 0,
+            extra: Vec::new(),
+            endpoints: Vec::new(),
+        }
+    }
+
+    /// Sets the interface's class, sub class and protocol codes.
+    pub fn class(mut self, class: u8, sub_class: u8, protocol: u8) -> InterfaceDescriptorBuilder {
+        self.class = (class, sub_class, protocol);
+        self
+    }
+
+    /// Sets the index of the interface's description string, 0 for none.
+    pub fn string(mut self, index: u8) -> InterfaceDescriptorBuilder {
+        self.string = index;
+        self
+    }
+
+    /// Appends class-specific descriptors, e.g. CDC functional descriptors, which are placed
+    /// between the interface descriptor and its endpoint descriptors.
+    pub fn extra(mut self, descriptors: &[u8]) -> InterfaceDescriptorBuilder {
+        self.extra.extend_from_slice(descriptors);
+        self
+    }
+
+    /// Appends an endpoint.
+    pub fn endpoint(mut self, endpoint: EndpointDescriptorBuilder) -> InterfaceDescriptorBuilder {
+        self.endpoints.push(endpoint);
+        self
+    }
+
+    /// Returns the bytes of the interface descriptor and of the descriptors following it, e.g.
+    /// to describe a FunctionFS function.
+    ///
+    /// ## Errors
+    ///
+    /// * `InvalidParam` if class-specific descriptors aren't a sequence of complete descriptors,
+    ///   or if the endpoints are invalid.
+    pub fn build(&self) -> crate::Result<Vec<u8>> {
+        let mut bytes = Vec::new();
+        self.append_to(&mut bytes)?;
+        Ok(bytes)
+    }
+
+    fn append_to(&self, bytes: &mut Vec<u8>) -> crate::Result<()> {
+        check_extra(&self.extra)?;
+
+        let mut addresses: Vec<u8> = self.endpoints.iter().map(|e| e.address).collect();
+        addresses.sort_unstable();
+        addresses.dedup();
+        if self.endpoints.len() > 30 || addresses.len() != self.endpoints.len() {
+            return Err(Error::InvalidParam);
+        }
+
+        bytes.extend_from_slice(&[
+            9,
+            LIBUSB_DT_INTERFACE,
+            self.number,
+            self.setting,
+            self.endpoints.len() as u8,
+            self.class.0,
+            self.class.1,
+            self.class.2,
+            self.string,
+        ]);
+        bytes.extend_from_slice(&self.extra);
+        for endpoint in &self.endpoints {
+            endpoint.append_to(bytes)?;
+        }
+        Ok(())
+    }
+}
+
+/// Builds an endpoint descriptor, followed by its class-specific descriptors.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EndpointDescriptorBuilder {
+    address: u8,
+    transfer_type: TransferType,
+    max_packet_size: u16,
+    interval: u8,
+    extra: Vec<u8>,
+}
+
+impl EndpointDescriptorBuilder {
+    /// Starts an endpoint descriptor for the given address, e.g. `0x81` for endpoint 1 IN, with
+    /// a 64 byte maximum packet size.
+    ///
+    /// Interrupt and isochronous endpoints are polled every frame, bulk endpoints don't NAK.
+    pub fn new(address: u8, transfer_type: TransferType) -> EndpointDescriptorBuilder {
+        let interval = match transfer_type {
+            TransferType::Interrupt | TransferType::Isochronous => 1,
+            TransferType::Control | TransferType::Bulk => 0,
+        };
+
+        EndpointDescriptorBuilder {
+            address,
+            transfer_type,
+            max_packet_size: 64,
+            interval,
+            extra: Vec::new(),
+        }
+    }
+
+    /// Sets the `wMaxPacketSize` field, including the additional transactions bits for high
+    /// bandwidth endpoints.
+    pub fn max_packet_size(mut self, size: u16) -> EndpointDescriptorBuilder {
+        self.max_packet_size = size;
+        self
+    }
+
+    /// Sets the `bInterval` field.
+    pub fn interval(mut self, interval: u8) -> EndpointDescriptorBuilder {
+        self.interval = interval;
+        self
+    }
+
+    /// Appends class-specific descriptors, e.g. a SuperSpeed endpoint companion descriptor.
+    pub fn extra(mut self, descriptors: &[u8]) -> EndpointDescriptorBuilder {
+        self.extra.extend_from_slice(descriptors);
+        self
+    }
+
+    fn append_to(&self, bytes: &mut Vec<u8>) -> crate::Result<()> {
+        check_extra(&self.extra)?;
+        if self.address & 0x0f == 0 || self.address & 0x70 != 0 {
+            return Err(Error::InvalidParam);
+        }
+
+        let attributes = match self.transfer_type {
+            TransferType::Control => LIBUSB_TRANSFER_TYPE_CONTROL,
+            TransferType::Isochronous => LIBUSB_TRANSFER_TYPE_ISOCHRONOUS,
+            TransferType::Bulk => LIBUSB_TRANSFER_TYPE_BULK,
+            TransferType::Interrupt => LIBUSB_TRANSFER_TYPE_INTERRUPT,
+        };
+        let size = self.max_packet_size.to_le_bytes();
+
+        bytes.extend_from_slice(&[
+            7,
+            LIBUSB_DT_ENDPOINT,
+            self.address,
+            attributes,
+            size[0],
+            size[1],
+            self.interval,
+        ]);
+        bytes.extend_from_slice(&self.extra);
+        Ok(())
+    }
+}
+
+/// Returns a string descriptor holding `string`.
+///
+/// ## Errors
+///
+/// * `InvalidParam` if the string is longer than the 126 UTF-16 code units a descriptor holds.
+pub fn string_descriptor(string: &str) -> crate::Result<Vec<u8>> {
+    let units: Vec<u16> = string.encode_utf16().collect();
+    if units.len() > 126 {
+        return Err(Error::InvalidParam);
+    }
+
+    let mut bytes = vec![(2 + units.len() * 2) as u8, LIBUSB_DT_STRING];
+    for unit in units {
+        bytes.extend_from_slice(&unit.to_le_bytes());
+    }
+    Ok(bytes)
+}
+
+/// Returns string descriptor zero, listing the `LANGID`s of the languages the device supports.
+///
+/// ## Errors
+///
+/// * `InvalidParam` if there are more than the 126 languages a descriptor holds.
+pub fn language_descriptor(lang_ids: &[u16]) -> crate::Result<Vec<u8>> {
+    if lang_ids.len() > 126 {
+        return Err(Error::InvalidParam);
+    }
+
+    let mut bytes = vec![(2 + lang_ids.len() * 2) as u8, LIBUSB_DT_STRING];
+    for lang_id in lang_ids {
+        bytes.extend_from_slice(&lang_id.to_le_bytes());
+    }
+    Ok(bytes)
+}
+
+/// Checks that class-specific descriptors are a sequence of complete descriptors.
+fn check_extra(mut extra: &[u8]) -> crate::Result<()> {
+    while !extra.is_empty() {
+        let len = extra[0] as usize;
+        if len < 2 || len > extra.len() {
+            return Err(Error::InvalidParam);
+        }
+        extra = &extra[len..];
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        descriptor_view::{self, ConfigDescriptorView, ParseMode},
+        fields::Direction,
+    };
+
+    #[test]
+    fn it_builds_device_descriptors() {
+        let bytes = DeviceDescriptorBuilder::new(0x1209, 0x0001)
+            .usb_version(Version(2, 1, 0))
+            .device_version(Version(1, 2, 3))
+            .strings(1, 2, 3)
+            .build()
+            .unwrap();
+
+        assert_eq!(
+            [18, 1, 0x10, 0x02, 0, 0, 0, 64, 0x09, 0x12, 0x01, 0x00, 0x23, 0x01, 1, 2, 3, 1],
+            bytes
+        );
+    }
+
+    #[test]
+    fn it_rejects_invalid_control_packet_sizes() {
+        let device = DeviceDescriptorBuilder::new(0x1209, 0x0001);
+
+        assert_eq!(
+            Err(Error::InvalidParam),
+            device.clone().max_packet_size0(48).build()
+        );
+        assert_eq!(
+            Err(Error::InvalidParam),
+            device.clone().max_packet_size0(512).build()
+        );
+        assert_eq!(
+            9,
+            device
+                .usb_version(Version(3, 2, 0))
+                .max_packet_size0(512)
+                .build()
+                .unwrap()[7]
+        );
+    }
+
+    #[test]
+    fn it_builds_configurations_that_parse_back() {
+        let bytes = ConfigDescriptorBuilder::new(1)
+            .self_powered(true)
+            .max_power_ma(100)
+            .interface(
+                InterfaceDescriptorBuilder::new(0, 0)
+                    .class(0x02, 0x02, 0x01)
+                    .extra(&[5, 0x24, 0x00, 0x10, 0x01])
+                    .endpoint(
+                        EndpointDescriptorBuilder::new(0x82, TransferType::Interrupt)
+                            .max_packet_size(8)
+                            .interval(16),
+                    ),
+            )
+            .interface(InterfaceDescriptorBuilder::new(1, 0).class(0x0a, 0, 0))
+            .interface(
+                InterfaceDescriptorBuilder::new(1, 1)
+                    .class(0x0a, 0, 0)
+                    .endpoint(EndpointDescriptorBuilder::new(0x81, TransferType::Bulk))
+                    .endpoint(EndpointDescriptorBuilder::new(0x01, TransferType::Bulk)),
+            )
+            .build()
+            .unwrap();
+
+        let view = ConfigDescriptorView::parse(&bytes).unwrap();
+        assert_eq!(
+            bytes.len(),
+            u16::from_le_bytes([bytes[2], bytes[3]]) as usize
+        );
+        assert_eq!(1, view.number());
+        assert_eq!(2, view.num_interfaces());
+        assert_eq!(100, view.max_power());
+        assert!(view.self_powered());
+
+        let interfaces: Vec<_> = view.interface_descriptors().collect();
+        assert_eq!(3, interfaces.len());
+        assert_eq!(
+            Some(&[5, 0x24, 0x00, 0x10, 0x01][..]),
+            interfaces[0].extra()
+        );
+
+        let endpoint = interfaces[0].endpoint_descriptors().next().unwrap();
+        assert_eq!(TransferType::Interrupt, endpoint.transfer_type());
+        assert_eq!(Direction::In, endpoint.direction());
+        assert_eq!(16, endpoint.interval());
+
+        assert_eq!(2, interfaces[2].endpoint_descriptors().count());
+    }
+
+    #[test]
+    fn it_rejects_invalid_endpoints() {
+        let build = |endpoints: &[u8]| {
+            let mut interface = InterfaceDescriptorBuilder::new(0, 0);
+            for &address in endpoints {
+                interface =
+                    interface.endpoint(EndpointDescriptorBuilder::new(address, TransferType::Bulk));
+            }
+            ConfigDescriptorBuilder::new(1).interface(interface).build()
+        };
+
+        assert_eq!(Err(Error::InvalidParam), build(&[0x80]).map(|_| ()));
+        assert_eq!(Err(Error::InvalidParam), build(&[0x81, 0x81]).map(|_| ()));
+        assert!(build(&[0x81, 0x01]).is_ok());
+    }
+
+    #[test]
+    fn it_rejects_truncated_extra_descriptors() {
+        let config = ConfigDescriptorBuilder::new(1).extra(&[4, 0x24, 0x00]);
+
+        assert_eq!(Err(Error::InvalidParam), config.build().map(|_| ()));
+    }
+
+    #[test]
+    fn it_builds_string_descriptors() {
+        let bytes = string_descriptor("Zé").unwrap();
+        assert_eq!(vec![6, 3, b'Z', 0, 0xe9, 0], bytes);
+        assert_eq!(
+            "Zé",
+            descriptor_view::parse_string(&bytes, ParseMode::Strict).unwrap()
+        );
+
+        let languages = language_descriptor(&[0x0409, 0x0407]).unwrap();
+        assert_eq!(
+            vec![0x0409, 0x0407],
+            descriptor_view::parse_lang_ids(&languages, ParseMode::Strict).unwrap()
+        );
+
+        assert!(string_descriptor(&"x".repeat(127)).is_err());
+    }
+}
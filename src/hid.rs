@@ -0,0 +1,570 @@
+//! Parsing of HID report descriptors.
+//!
+//! A HID device describes the layout of its reports in a report descriptor, read with
+//! [`DeviceHandle::hid_report_descriptor`](../struct.DeviceHandle.html#method.hid_report_descriptor).
+//! [`ReportDescriptor::parse`] turns it into the fields of each report, which locate the values
+//! in the reports read from the interrupt IN endpoint or with `GET_REPORT`:
+//!
+//! ```
+//! use rusb::hid::{ReportDescriptor, ReportKind, Usage};
+//!
+//! // a mouse with three buttons and relative X and Y axes
+//! let descriptor = ReportDescriptor::parse(&[
+//!     0x05, 0x01, 0x09, 0x02, 0xA1, 0x01, 0x09, 0x01, 0xA1, 0x00, 0x05, 0x09, 0x19, 0x01, 0x29,
+//!     0x03, 0x15, 0x00, 0x25, 0x01, 0x95, 0x03, 0x75, 0x01, 0x81, 0x02, 0x95, 0x01, 0x75, 0x05,
+//!     0x81, 0x01, 0x05, 0x01, 0x09, 0x30, 0x09, 0x31, 0x15, 0x81, 0x25, 0x7F, 0x75, 0x08, 0x95,
+//!     0x02, 0x81, 0x06, 0xC0, 0xC0,
+//! ])?;
+//!
+//! let report = [0b001, 0xFE, 0x03];
+//! let axes = descriptor
+//!     .fields(ReportKind::Input, 0)
+//!     .find(|field| field.usage(0) == Some(Usage::new(0x01, 0x30)))
+//!     .unwrap();
+//! assert_eq!(Some(-2), axes.value(&report, 0));
+//! assert_eq!(Some(3), axes.value(&report, 1));
+//! # Ok::<(), rusb::Error>(())
+//! ```
+
+use crate::error::Error;
+
+const MAIN: u8 = 0;
+const GLOBAL: u8 = 1;
+const LOCAL: u8 = 2;
+
+const INPUT: u8 = 0x8;
+const OUTPUT: u8 = 0x9;
+const COLLECTION: u8 = 0xA;
+const FEATURE: u8 = 0xB;
+const END_COLLECTION: u8 = 0xC;
+
+const USAGE_PAGE: u8 = 0x0;
+const LOGICAL_MINIMUM: u8 = 0x1;
+const LOGICAL_MAXIMUM: u8 = 0x2;
+const REPORT_SIZE: u8 = 0x7;
+const REPORT_ID: u8 = 0x8;
+const REPORT_COUNT: u8 = 0x9;
+const PUSH: u8 = 0xA;
+const POP: u8 = 0xB;
+
+const USAGE: u8 = 0x0;
+const USAGE_MINIMUM: u8 = 0x1;
+const USAGE_MAXIMUM: u8 = 0x2;
+
+/// The prefix of long items, which no HID specification defines but which must be skipped.
+const LONG_ITEM: u8 = 0xFE;
+
+/// The `Application` collection type.
+const APPLICATION: u8 = 0x01;
+
+/// The largest report size, in bits, the parser accepts, as Linux does.
+const MAX_REPORT_SIZE: u32 = 256;
+/// The largest report count the parser accepts, which covers a report of bytes as long as a
+/// control transfer can be.
+const MAX_REPORT_COUNT: u32 = 0xFFFF;
+
+/// A usage, identifying what a control or collection is, e.g. the X axis of a generic desktop
+/// device.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Usage {
+    page: u16,
+    id: u16,
+}
+
+impl Usage {
+    /// Creates a usage from its page and ID.
+    pub fn new(page: u16, id: u16) -> Usage {
+        Usage { page, id }
+    }
+
+    /// Returns the usage page, e.g. `0x01` for generic desktop controls.
+    pub fn page(&self) -> u16 {
+        self.page
+    }
+
+    /// Returns the usage ID within the page.
+    pub fn id(&self) -> u16 {
+        self.id
+    }
+
+    /// Decodes a local usage item, which holds the page itself when it is 4 bytes long.
+    fn from_item(page: u16, data: u32, size: usize) -> Usage {
+        if size == 4 {
+            Usage::new((data >> 16) as u16, data as u16)
+        } else {
+            Usage::new(page, data as u16)
+        }
+    }
+}
+
+/// The kind of report a field belongs to.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum ReportKind {
+    /// Reports sent by the device, on the interrupt IN endpoint or in reply to `GET_REPORT`.
+    Input,
+
+    /// Reports sent to the device, on the interrupt OUT endpoint or with `SET_REPORT`.
+    Output,
+
+    /// Configuration reports, read and written with `GET_REPORT` and `SET_REPORT`.
+    Feature,
+}
+
+/// A field of a report: `count` values of `size` bits each, placed back to back.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReportField {
+    kind: ReportKind,
+    report_id: u8,
+    application: Option<Usage>,
+    usages: Vec<Usage>,
+    usage_range: Option<(Usage, Usage)>,
+    bit_offset: usize,
+    size: usize,
+    count: usize,
+    logical_minimum: i32,
+    logical_maximum: i32,
+    flags: u32,
+}
+
+impl ReportField {
+    /// Returns the kind of report the field belongs to.
+    pub fn kind(&self) -> ReportKind {
+        self.kind
+    }
+
+    /// Returns the ID of the report the field belongs to, 0 if the device doesn't use report
+    /// IDs.
+    pub fn report_id(&self) -> u8 {
+        self.report_id
+    }
+
+    /// Returns the usage of the application collection the field belongs to, e.g. mouse or
+    /// keyboard.
+    pub fn application(&self) -> Option<Usage> {
+        self.application
+    }
+
+    /// Returns the usage of the value at `index`.
+    ///
+    /// For variable fields, each value has its own usage, the last one being repeated when the
+    /// descriptor lists fewer usages than values. For array fields, the values are indices into
+    /// the usages instead, see [`is_array`](#method.is_array).
+    pub fn usage(&self, index: usize) -> Option<Usage> {
+        if let Some((min, max)) = self.usage_range {
+            let id = u32::from(min.id) + index as u32;
+            return Some(Usage::new(min.page, id.min(u32::from(max.id)) as u16));
+        }
+        self.usages
+            .get(index)
+            .or_else(|| self.usages.last())
+            .copied()
+    }
+
+    /// Returns the usages listed for the field, without the ones of a usage range.
+    pub fn usages(&self) -> &[Usage] {
+        &self.usages
+    }
+
+    /// Returns the first and last usage of the usage range of the field, if it has one.
+    pub fn usage_range(&self) -> Option<(Usage, Usage)> {
+        self.usage_range
+    }
+
+    /// Returns the position of the field's first bit in the report, not counting the report ID.
+    pub fn bit_offset(&self) -> usize {
+        self.bit_offset
+    }
+
+    /// Returns the size in bits of each value.
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// Returns the number of values.
+    pub fn count(&self) -> usize {
+        self.count
+    }
+
+    /// Returns the smallest value the field reports.
+    pub fn logical_minimum(&self) -> i32 {
+        self.logical_minimum
+    }
+
+    /// Returns the largest value the field reports.
+    pub fn logical_maximum(&self) -> i32 {
+        self.logical_maximum
+    }
+
+    /// Returns the flags of the main item, e.g. `0x02` for a variable absolute field.
+    pub fn flags(&self) -> u32 {
+        self.flags
+    }
+
+    /// Indicates whether the field is padding, whose values don't mean anything.
+    pub fn is_constant(&self) -> bool {
+        self.flags & 0x01 != 0
+    }
+
+    /// Indicates whether the values are indices into the usages, e.g. the keys pressed on a
+    /// keyboard, rather than one value per usage.
+    pub fn is_array(&self) -> bool {
+        self.flags & 0x02 == 0
+    }
+
+    /// Indicates whether the values are changes since the last report rather than absolute
+    /// values, e.g. for mouse movements.
+    pub fn is_relative(&self) -> bool {
+        self.flags & 0x04 != 0
+    }
+
+    /// Returns the value at `index` in `report`, which doesn't start with the report ID.
+    ///
+    /// The value is sign-extended when the logical minimum is negative. Returns `None` if `index`
+    /// is out of range or `report` is too short.
+    pub fn value(&self, report: &[u8], index: usize) -> Option<i32> {
+        if index >= self.count || self.size == 0 || self.size > 32 {
+            return None;
+        }
+
+        let start = self.bit_offset + index * self.size;
+        let mut value: u32 = 0;
+        for bit in 0..self.size {
+            let position = start + bit;
+            let byte = *report.get(position / 8)?;
+            value |= u32::from((byte >> (position % 8)) & 1) << bit;
+        }
+
+        if self.logical_minimum < 0 && self.size < 32 && value & (1 << (self.size - 1)) != 0 {
+            value |= !0 << self.size;
+        }
+        Some(value as i32)
+    }
+}
+
+/// A parsed report descriptor, listing the fields of every report.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReportDescriptor {
+    fields: Vec<ReportField>,
+}
+
+#[derive(Debug, Copy, Clone, Default)]
+struct Globals {
+    usage_page: u16,
+    logical_minimum: i32,
+    logical_maximum: i32,
+    report_size: usize,
+    report_id: u8,
+    report_count: usize,
+}
+
+#[derive(Debug, Clone, Default)]
+struct Locals {
+    usages: Vec<Usage>,
+    usage_minimum: Option<Usage>,
+    usage_maximum: Option<Usage>,
+}
+
+impl ReportDescriptor {
+    /// Parses a report descriptor.
+    ///
+    /// Items the parser doesn't need, e.g. units and designators, are skipped.
+    ///
+    /// ## Errors
+    ///
+    /// * `InvalidParam` if an item is truncated, a `Pop` or `End Collection` item has no
+    ///   matching `Push` or `Collection` item, a report size is over 256 bits or a report count
+    ///   over 65535, or a report is too long to be addressed in bits.
+    pub fn parse(raw: &[u8]) -> crate::Result<ReportDescriptor> {
+        let mut fields = Vec::new();
+        let mut globals = Globals::default();
+        let mut stack = Vec::new();
+        let mut locals = Locals::default();
+        let mut collections: Vec<Option<Usage>> = Vec::new();
+        let mut application = None;
+        let mut offsets: Vec<((ReportKind, u8), usize)> = Vec::new();
+
+        let mut rest = raw;
+        while let Some(&prefix) = rest.first() {
+            if prefix == LONG_ITEM {
+                let len = *rest.get(1).ok_or(Error::InvalidParam)? as usize;
+                rest = rest.get(3 + len..).ok_or(Error::InvalidParam)?;
+                continue;
+            }
+
+            let size = match prefix & 0x03 {
+                3 => 4,
+                n => n as usize,
+            };
+            let data = rest.get(1..1 + size).ok_or(Error::InvalidParam)?;
+            rest = &rest[1 + size..];
+
+            let unsigned = data
+                .iter()
+                .rev()
+                .fold(0u32, |value, &byte| value << 8 | u32::from(byte));
+            let signed = match size {
+                1 => i32::from(unsigned as u8 as i8),
+                2 => i32::from(unsigned as u16 as i16),
+                _ => unsigned as i32,
+            };
+
+            match ((prefix >> 2) & 0x03, prefix >> 4) {
+                (MAIN, tag @ INPUT) | (MAIN, tag @ OUTPUT) | (MAIN, tag @ FEATURE) => {
+                    let kind = match tag {
+                        INPUT => ReportKind::Input,
+                        OUTPUT => ReportKind::Output,
+                        _ => ReportKind::Feature,
+                    };
+                    let key = (kind, globals.report_id);
+                    let offset = match offsets.iter_mut().find(|(k, _)| *k == key) {
+                        Some((_, offset)) => offset,
+                        None => {
+                            offsets.push((key, 0));
+                            &mut offsets.last_mut().unwrap().1
+                        }
+                    };
+
+                    let usage_range = match (locals.usage_minimum, locals.usage_maximum) {
+                        (Some(min), Some(max)) => Some((min, max)),
+                        _ => None,
+                    };
+                    fields.push(ReportField {
+                        kind,
+                        report_id: globals.report_id,
+                        application,
+                        usages: std::mem::take(&mut locals.usages),
+                        usage_range,
+                        bit_offset: *offset,
+                        size: globals.report_size,
+                        count: globals.report_count,
+                        logical_minimum: globals.logical_minimum,
+                        logical_maximum: globals.logical_maximum,
+                        flags: unsigned,
+                    });
+                    *offset = globals
+                        .report_size
+                        .checked_mul(globals.report_count)
+                        .and_then(|bits| offset.checked_add(bits))
+                        .ok_or(Error::InvalidParam)?;
+                    locals = Locals::default();
+                }
+                (MAIN, COLLECTION) => {
+                    let usage = locals.usages.first().copied();
+                    if unsigned == u32::from(APPLICATION) && application.is_none() {
+                        application = usage;
+                    }
+                    collections.push(usage);
+                    locals = Locals::default();
+                }
+                (MAIN, END_COLLECTION) => {
+                    collections.pop().ok_or(Error::InvalidParam)?;
+                    if collections.is_empty() {
+                        application = None;
+                    }
+                    locals = Locals::default();
+                }
+                (MAIN, _) => locals = Locals::default(),
+                (GLOBAL, USAGE_PAGE) => globals.usage_page = unsigned as u16,
+                (GLOBAL, LOGICAL_MINIMUM) => globals.logical_minimum = signed,
+                (GLOBAL, LOGICAL_MAXIMUM) => {
+                    // a maximum that only fits unsigned, e.g. 255 in one byte after a minimum of 0
+                    globals.logical_maximum = if signed < globals.logical_minimum {
+                        unsigned as i32
+                    } else {
+                        signed
+                    };
+                }
+                (GLOBAL, REPORT_SIZE) if unsigned > MAX_REPORT_SIZE => {
+                    return Err(Error::InvalidParam)
+                }
+                (GLOBAL, REPORT_SIZE) => globals.report_size = unsigned as usize,
+                (GLOBAL, REPORT_ID) => globals.report_id = unsigned as u8,
+                (GLOBAL, REPORT_COUNT) if unsigned > MAX_REPORT_COUNT => {
+                    return Err(Error::InvalidParam)
+                }
+                (GLOBAL, REPORT_COUNT) => globals.report_count = unsigned as usize,
+                (GLOBAL, PUSH) => stack.push(globals),
+                (GLOBAL, POP) => globals = stack.pop().ok_or(Error::InvalidParam)?,
+                (LOCAL, USAGE) => {
+                    locals
+                        .usages
+                        .push(Usage::from_item(globals.usage_page, unsigned, size));
+                }
+                (LOCAL, USAGE_MINIMUM) => {
+                    locals.usage_minimum =
+                        Some(Usage::from_item(globals.usage_page, unsigned, size));
+                }
+                (LOCAL, USAGE_MAXIMUM) => {
+                    locals.usage_maximum =
+                        Some(Usage::from_item(globals.usage_page, unsigned, size));
+                }
+                _ => (),
+            }
+        }
+
+        Ok(ReportDescriptor { fields })
+    }
+
+    /// Returns every field, in the order of the descriptor.
+    pub fn all_fields(&self) -> &[ReportField] {
+        &self.fields
+    }
+
+    /// Returns the fields of the reports of `kind` with ID `report_id`, 0 if the device doesn't
+    /// use report IDs.
+    pub fn fields(
+        &self,
+        kind: ReportKind,
+        report_id: u8,
+    ) -> impl Iterator<Item = &ReportField> + '_ {
+        self.fields
+            .iter()
+            .filter(move |field| field.kind == kind && field.report_id == report_id)
+    }
+
+    /// Returns the IDs of the reports, in the order they first appear. Devices that don't use
+    /// report IDs have the single ID 0.
+    pub fn report_ids(&self) -> Vec<u8> {
+        let mut ids = Vec::new();
+        for field in &self.fields {
+            if !ids.contains(&field.report_id) {
+                ids.push(field.report_id);
+            }
+        }
+        ids
+    }
+
+    /// Indicates whether reports start with their report ID.
+    pub fn uses_report_ids(&self) -> bool {
+        self.fields.iter().any(|field| field.report_id != 0)
+    }
+
+    /// Returns the length in bytes of the report of `kind` with ID `report_id`, not counting the
+    /// report ID.
+    pub fn report_len(&self, kind: ReportKind, report_id: u8) -> usize {
+        // the fields were laid out without overflowing their offsets, so neither can this
+        let bits: usize = self
+            .fields(kind, report_id)
+            .map(|field| field.size * field.count)
+            .sum();
+        bits / 8 + usize::from(bits & 7 != 0)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[rustfmt::skip]
+    const KEYBOARD: [u8; 63] = [
+        0x05, 0x01, 0x09, 0x06, 0xA1, 0x01,
+        // modifiers
+        0x05, 0x07, 0x19, 0xE0, 0x29, 0xE7, 0x15, 0x00, 0x25, 0x01, 0x75, 0x01, 0x95, 0x08, 0x81, 0x02,
+        // reserved byte
+        0x95, 0x01, 0x75, 0x08, 0x81, 0x01,
+        // LEDs
+        0x95, 0x05, 0x75, 0x01, 0x05, 0x08, 0x19, 0x01, 0x29, 0x05, 0x91, 0x02,
+        0x95, 0x01, 0x75, 0x03, 0x91, 0x01,
+        // keys
+        0x95, 0x06, 0x75, 0x08, 0x15, 0x00, 0x25, 0x65, 0x05, 0x07, 0x19, 0x00, 0x29, 0x65, 0x81, 0x00,
+        0xC0,
+    ];
+
+    #[test]
+    fn it_lays_out_keyboard_reports() {
+        let descriptor = ReportDescriptor::parse(&KEYBOARD).unwrap();
+
+        assert_eq!(vec![0], descriptor.report_ids());
+        assert!(!descriptor.uses_report_ids());
+        assert_eq!(8, descriptor.report_len(ReportKind::Input, 0));
+        assert_eq!(1, descriptor.report_len(ReportKind::Output, 0));
+
+        let fields: Vec<_> = descriptor.fields(ReportKind::Input, 0).collect();
+        assert_eq!(3, fields.len());
+        assert_eq!(Some(Usage::new(0x01, 0x06)), fields[0].application());
+        assert_eq!(Some(Usage::new(0x07, 0xE1)), fields[0].usage(1));
+        assert!(fields[1].is_constant());
+        assert_eq!(
+            (16, 8, 6),
+            (fields[2].bit_offset(), fields[2].size(), fields[2].count())
+        );
+        assert!(fields[2].is_array());
+
+        // left shift and the A key
+        let report = [0x02, 0x00, 0x04, 0, 0, 0, 0, 0];
+        assert_eq!(Some(1), fields[0].value(&report, 1));
+        assert_eq!(Some(0x04), fields[2].value(&report, 0));
+        assert_eq!(None, fields[2].value(&report, 6));
+    }
+
+    #[test]
+    fn it_tracks_report_ids_and_global_state() {
+        #[rustfmt::skip]
+        let raw = [
+            0x06, 0x00, 0xFF, 0x09, 0x01, 0xA1, 0x01,
+            0x85, 0x01, 0x75, 0x08, 0x95, 0x02, 0x09, 0x02, 0x81, 0x02,
+            0xA4, 0x85, 0x02, 0x75, 0x10, 0x95, 0x01, 0x09, 0x03, 0xB1, 0x02, 0xB4,
+            0x09, 0x04, 0x81, 0x02,
+            0xC0,
+        ];
+        let descriptor = ReportDescriptor::parse(&raw).unwrap();
+
+        assert_eq!(vec![1, 2], descriptor.report_ids());
+        assert!(descriptor.uses_report_ids());
+        assert_eq!(4, descriptor.report_len(ReportKind::Input, 1));
+        assert_eq!(2, descriptor.report_len(ReportKind::Feature, 2));
+
+        // the pop restored report ID 1 and 8-bit values
+        let last = descriptor.fields(ReportKind::Input, 1).last().unwrap();
+        assert_eq!(Some(Usage::new(0xFF00, 0x04)), last.usage(0));
+        assert_eq!((16, 8), (last.bit_offset(), last.size()));
+    }
+
+    #[test]
+    fn it_sign_extends_values_and_reads_unsigned_maximums() {
+        let raw = [0x15, 0x00, 0x25, 0xFF, 0x75, 0x08, 0x95, 0x01, 0x81, 0x02];
+        let field = ReportDescriptor::parse(&raw).unwrap().all_fields()[0].clone();
+        assert_eq!(255, field.logical_maximum());
+        assert_eq!(Some(200), field.value(&[200], 0));
+
+        let raw = [0x15, 0xF8, 0x25, 0x07, 0x75, 0x04, 0x95, 0x02, 0x81, 0x06];
+        let field = ReportDescriptor::parse(&raw).unwrap().all_fields()[0].clone();
+        assert!(field.is_relative());
+        assert_eq!(Some(-1), field.value(&[0x3F], 0));
+        assert_eq!(Some(3), field.value(&[0x3F], 1));
+    }
+
+    #[test]
+    fn it_refuses_malformed_descriptors() {
+        assert_eq!(Err(Error::InvalidParam), ReportDescriptor::parse(&[0x05]));
+        assert_eq!(Err(Error::InvalidParam), ReportDescriptor::parse(&[0xC0]));
+        assert_eq!(Err(Error::InvalidParam), ReportDescriptor::parse(&[0xB4]));
+    }
+
+    #[test]
+    fn it_refuses_oversized_fields() {
+        // report size and count of 0xFFFFFFFF, then two inputs
+        let raw = [
+            0x77, 0xFF, 0xFF, 0xFF, 0xFF, 0x97, 0xFF, 0xFF, 0xFF, 0xFF, 0x81, 0x02, 0x81, 0x02,
+        ];
+        assert_eq!(Err(Error::InvalidParam), ReportDescriptor::parse(&raw));
+
+        assert_eq!(
+            Err(Error::InvalidParam),
+            ReportDescriptor::parse(&[0x76, 0x01, 0x01])
+        );
+        assert_eq!(
+            Err(Error::InvalidParam),
+            ReportDescriptor::parse(&[0x97, 0x00, 0x00, 0x01, 0x00])
+        );
+    }
+
+    #[test]
+    fn it_accepts_the_largest_fields() {
+        // 256-bit values, 65535 of them, in two inputs
+        let raw = [0x76, 0x00, 0x01, 0x96, 0xFF, 0xFF, 0x81, 0x02, 0x81, 0x02];
+        let descriptor = ReportDescriptor::parse(&raw).unwrap();
+
+        assert_eq!(2 * 32 * 0xFFFF, descriptor.report_len(ReportKind::Input, 0));
+    }
+}
@@ -0,0 +1,803 @@
+use libc::{c_int, c_void};
+use libusb1_sys::{constants::*, *};
+
+use std::{
+    collections::VecDeque,
+    fmt,
+    future::Future,
+    pin::Pin,
+    ptr::NonNull,
+    sync::{Arc, Mutex, MutexGuard},
+    task::{Context as TaskContext, Poll, Waker},
+    time::{Duration, Instant},
+};
+
+use crate::{
+    context::UsbContext,
+    device::{self, Device},
+    device_policy, error,
+    event_log::{self, EventKind, Record},
+    event_loop,
+};
+
+/// Receives the devices arriving and leaving, see [`HotplugBuilder`](struct.HotplugBuilder.html).
+///
+/// The methods are called from the thread handling the events of the context. They must not
+/// perform synchronous transfers, which `libusb` forbids while it is delivering hotplug events.
+pub trait Hotplug<T: UsbContext> {
+    fn device_arrived(&mut self, device: Device<T>);
+    fn device_left(&mut self, device: Device<T>);
+}
+
+/// Registers hotplug callbacks on a context, optionally only for some devices.
+///
+/// Created by [`UsbContext::hotplug`](trait.UsbContext.html#method.hotplug).
+///
+/// ```no_run
+/// use rusb::{Context, Device, Hotplug, UsbContext};
+///
+/// struct Printer;
+///
+/// impl<T: UsbContext> Hotplug<T> for Printer {
+///     fn device_arrived(&mut self, device: Device<T>) {
+///         println!("arrived: {:?}", device);
+///     }
+///
+///     fn device_left(&mut self, device: Device<T>) {
+///         println!("left: {:?}", device);
+///     }
+/// }
+///
+/// # fn main() -> rusb::Result<()> {
+/// let context = Context::new()?;
+/// let _registration = context
+///     .hotplug()
+///     .vendor_id(0x1d6b)
+///     .enumerate(true)
+///     .register(Box::new(Printer))?;
+///
+/// loop {
+///     context.handle_events(None)?;
+/// }
+/// # }
+/// ```
+#[derive(Clone)]
+pub struct HotplugBuilder<T: UsbContext> {
+    context: T,
+    vendor_id: Option<u16>,
+    product_id: Option<u16>,
+    class: Option<u8>,
+    enumerate: bool,
+}
+
+impl<T: UsbContext> HotplugBuilder<T> {
+    /// Creates a builder matching every device of `context`.
+    pub(crate) fn new(context: T) -> HotplugBuilder<T> {
+        HotplugBuilder {
+            context,
+            vendor_id: None,
+            product_id: None,
+            class: None,
+            enumerate: false,
+        }
+    }
+
+    /// Sets the filters that are given, and matches any value for those that aren't.
+    pub(crate) fn matching(
+        mut self,
+        vendor_id: Option<u16>,
+        product_id: Option<u16>,
+        class: Option<u8>,
+    ) -> Self {
+        self.vendor_id = vendor_id;
+        self.product_id = product_id;
+        self.class = class;
+        self
+    }
+
+    /// Only reports devices with this vendor ID.
+    pub fn vendor_id(mut self, vendor_id: u16) -> Self {
+        self.vendor_id = Some(vendor_id);
+        self
+    }
+
+    /// Only reports devices with this product ID.
+    pub fn product_id(mut self, product_id: u16) -> Self {
+        self.product_id = Some(product_id);
+        self
+    }
+
+    /// Only reports devices with this device class.
+    pub fn class(mut self, class: u8) -> Self {
+        self.class = Some(class);
+        self
+    }
+
+    /// Reports the matching devices already attached as arrived, before `register` returns.
+    ///
+    /// Without it, only the changes happening after the registration are reported. Listing the
+    /// devices separately instead could miss a device plugged in between the listing and the
+    /// registration.
+    pub fn enumerate(mut self, enumerate: bool) -> Self {
+        self.enumerate = enumerate;
+        self
+    }
+
+    /// Registers `callback` on the context.
+    ///
+    /// Events are only delivered while the events of the context are being handled. The
+    /// callback stays registered until the returned registration is dropped.
+    ///
+    /// ## Errors
+    ///
+    /// * `NotSupported` if the platform doesn't support hotplug, see
+    ///   [`has_hotplug`](fn.has_hotplug.html).
+    #[must_use = "the callback is deregistered when the registration is dropped"]
+    pub fn register(self, callback: Box<dyn Hotplug<T>>) -> crate::Result<Registration<T>> {
+        let context = self.context;
+        let data = Box::into_raw(Box::new(CallbackData {
+            slot: Mutex::new(Slot::new((context.clone(), callback))),
+        }));
+        let flags = if self.enumerate {
+            LIBUSB_HOTPLUG_ENUMERATE
+        } else {
+            LIBUSB_HOTPLUG_NO_FLAGS
+        };
+
+        let mut handle: libusb_hotplug_callback_handle = 0;
+        let n = unsafe {
+            libusb_hotplug_register_callback(
+                context.as_raw(),
+                LIBUSB_HOTPLUG_EVENT_DEVICE_ARRIVED | LIBUSB_HOTPLUG_EVENT_DEVICE_LEFT,
+                flags,
+                filter(self.vendor_id.map(c_int::from)),
+                filter(self.product_id.map(c_int::from)),
+                filter(self.class.map(c_int::from)),
+                hotplug_callback::<T>,
+                data as *mut c_void,
+                &mut handle,
+            )
+        };
+        if n < 0 {
+            drop(unsafe { Box::from_raw(data) });
+            return Err(error::from_libusb(n));
+        }
+
+        let registration = Registration {
+            context,
+            handle,
+            data: unsafe { NonNull::new_unchecked(data) },
+            deregister_on_drop: true,
+        };
+        // the enumerated devices were reported on this thread, the callback may have panicked
+        event_loop::resume_callback_panic();
+        Ok(registration)
+    }
+
+    /// Registers a callback queueing the events of the context, and returns them as an
+    /// iterator.
+    ///
+    /// See [`HotplugEvents`](struct.HotplugEvents.html).
+    pub fn events(self) -> crate::Result<HotplugEvents<T>>
+    where
+        T: 'static,
+    {
+        let context = self.context.clone();
+        let queue = Arc::new(Mutex::new(Queue {
+            events: VecDeque::new(),
+            waker: None,
+        }));
+        let registration = self.register(Box::new(Enqueue {
+            queue: queue.clone(),
+        }))?;
+
+        Ok(HotplugEvents {
+            context,
+            queue,
+            _registration: registration,
+        })
+    }
+}
+
+impl<T: UsbContext> fmt::Debug for HotplugBuilder<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("HotplugBuilder")
+            .field("vendor_id", &self.vendor_id)
+            .field("product_id", &self.product_id)
+            .field("class", &self.class)
+            .field("enumerate", &self.enumerate)
+            .finish()
+    }
+}
+
+fn filter(value: Option<c_int>) -> c_int {
+    value.unwrap_or(LIBUSB_HOTPLUG_MATCH_ANY)
+}
+
+/// A registered hotplug callback.
+///
+/// The callback of a registration returned by
+/// [`HotplugBuilder::register`](struct.HotplugBuilder.html#method.register) is deregistered when
+/// the registration is dropped. That of a registration returned by
+/// [`UsbContext::register_callback`](trait.UsbContext.html#method.register_callback) stays
+/// registered until the registration is passed to
+/// [`UsbContext::unregister_callback`](trait.UsbContext.html#method.unregister_callback).
+pub struct Registration<T: UsbContext> {
+    context: T,
+    handle: libusb_hotplug_callback_handle,
+    data: NonNull<CallbackData<T>>,
+    deregister_on_drop: bool,
+}
+
+// The callback is already called from whichever thread handles the events of the context.
+unsafe impl<T: UsbContext + Send> Send for Registration<T> {}
+// A shared registration only gives access to the callback handle.
+unsafe impl<T: UsbContext + Sync> Sync for Registration<T> {}
+
+impl<T: UsbContext> Registration<T> {
+    /// Returns the `libusb` handle of the callback.
+    pub fn handle(&self) -> libusb_hotplug_callback_handle {
+        self.handle
+    }
+
+    /// Keeps the callback registered when the registration is dropped, leaking it unless the
+    /// registration is passed to [`deregister`](#method.deregister).
+    pub(crate) fn keep_registered(mut self) -> Registration<T> {
+        self.deregister_on_drop = false;
+        self
+    }
+
+    /// Deregisters the callback, even if it was kept registered.
+    pub(crate) fn deregister(mut self) {
+        self.deregister_on_drop = true;
+    }
+}
+
+impl<T: UsbContext> Drop for Registration<T> {
+    fn drop(&mut self) {
+        if !self.deregister_on_drop {
+            return;
+        }
+
+        unsafe { libusb_hotplug_deregister_callback(self.context.as_raw(), self.handle) };
+
+        // libusb releases its callback lock while calling back, so the callback may still be
+        // running on the thread handling events, or about to be called with the data: the
+        // callback is dropped once it returns, and the data once the context is exited
+        let callback = slot(unsafe { self.data.as_ref() }).deregister();
+        drop(callback);
+        retire(self.context.as_raw(), self.data);
+    }
+}
+
+impl<T: UsbContext> fmt::Debug for Registration<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Registration")
+            .field("handle", &self.handle)
+            .finish()
+    }
+}
+
+/// A device arriving or leaving, see [`HotplugEvents`](struct.HotplugEvents.html).
+#[derive(Debug)]
+pub enum HotplugEvent<T: UsbContext> {
+    /// The device was attached.
+    Arrived(Device<T>),
+
+    /// The device was detached.
+    Left(Device<T>),
+}
+
+impl<T: UsbContext> HotplugEvent<T> {
+    /// Returns the device that arrived or left.
+    pub fn device(&self) -> &Device<T> {
+        match self {
+            HotplugEvent::Arrived(device) | HotplugEvent::Left(device) => device,
+        }
+    }
+}
+
+/// The hotplug events of a context, in the order they happened.
+///
+/// Unlike a [`Hotplug`](trait.Hotplug.html) callback, which `libusb` calls while it is handling
+/// events, the events are queued and consumed from the application's own loop, where there are
+/// no restrictions on what can be done with the devices.
+///
+/// Iterating handles the events of the context until the next hotplug event is queued, and yields
+/// the errors of the event handling. To handle events on another thread instead, wait for the
+/// next event with [`next_event`](#method.next_event), or poll with
+/// [`try_next`](#method.try_next). With the `async` feature, this is also a `Stream` of the
+/// events.
+///
+/// The callback is deregistered when this is dropped.
+pub struct HotplugEvents<T: UsbContext> {
+    context: T,
+    queue: Arc<Mutex<Queue<T>>>,
+    _registration: Registration<T>,
+}
+
+struct Queue<T: UsbContext> {
+    events: VecDeque<HotplugEvent<T>>,
+    waker: Option<Waker>,
+}
+
+impl<T: UsbContext> HotplugEvents<T> {
+    /// Returns the next event that was queued, without handling events.
+    pub fn try_next(&self) -> Option<HotplugEvent<T>> {
+        lock(&self.queue).events.pop_front()
+    }
+
+    /// Handles events until the next hotplug event is queued, waiting up to `timeout`.
+    ///
+    /// Returns `None` if no event was queued in time.
+    pub fn next_timeout(&self, timeout: Duration) -> crate::Result<Option<HotplugEvent<T>>> {
+        if let Some(event) = self.try_next() {
+            return Ok(Some(event));
+        }
+
+        // unrelated events, e.g. completed transfers, end `handle_events` early, so it's called
+        // again for the remaining time rather than returning before the deadline
+        let deadline = Instant::now() + timeout;
+        loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            self.context.handle_events(Some(remaining))?;
+
+            if let Some(event) = self.try_next() {
+                return Ok(Some(event));
+            }
+            if Instant::now() >= deadline {
+                return Ok(None);
+            }
+        }
+    }
+
+    /// Returns a future resolving to the next event.
+    ///
+    /// The future doesn't handle events: another thread must be handling the events of the
+    /// context, otherwise it never resolves.
+    pub fn next_event(&self) -> NextEvent<'_, T> {
+        NextEvent { events: self }
+    }
+}
+
+impl<T: UsbContext> Iterator for HotplugEvents<T> {
+    type Item = crate::Result<HotplugEvent<T>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(event) = self.try_next() {
+                return Some(Ok(event));
+            }
+
+            if let Err(e) = self.context.handle_events(None) {
+                return Some(Err(e));
+            }
+        }
+    }
+}
+
+impl<T: UsbContext> fmt::Debug for HotplugEvents<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("HotplugEvents")
+            .field("queued", &lock(&self.queue).events.len())
+            .finish()
+    }
+}
+
+/// The future returned by [`HotplugEvents::next_event`](struct.HotplugEvents.html#method.next_event).
+#[must_use = "futures do nothing unless polled"]
+#[derive(Debug)]
+pub struct NextEvent<'a, T: UsbContext> {
+    events: &'a HotplugEvents<T>,
+}
+
+impl<'a, T: UsbContext> Future for NextEvent<'a, T> {
+    type Output = HotplugEvent<T>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut TaskContext<'_>) -> Poll<Self::Output> {
+        lock(&self.events.queue).poll_next(cx)
+    }
+}
+
+/// With the `async` feature, the events are also a stream, which never ends.
+///
+/// Like [`next_event`](struct.HotplugEvents.html#method.next_event), the stream doesn't handle
+/// events: another thread must be handling the events of the context.
+#[cfg(feature = "async")]
+impl<T: UsbContext> futures_core::Stream for HotplugEvents<T> {
+    type Item = HotplugEvent<T>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut TaskContext<'_>) -> Poll<Option<Self::Item>> {
+        lock(&self.queue).poll_next(cx).map(Some)
+    }
+}
+
+impl<T: UsbContext> Queue<T> {
+    /// Pops the next event, or registers the task to wake when one is queued.
+    fn poll_next(&mut self, cx: &mut TaskContext<'_>) -> Poll<HotplugEvent<T>> {
+        match self.events.pop_front() {
+            Some(event) => Poll::Ready(event),
+            None => {
+                self.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+/// Queues the events for a [`HotplugEvents`](struct.HotplugEvents.html).
+struct Enqueue<T: UsbContext> {
+    queue: Arc<Mutex<Queue<T>>>,
+}
+
+impl<T: UsbContext> Enqueue<T> {
+    fn push(&self, event: HotplugEvent<T>) {
+        let mut queue = lock(&self.queue);
+        queue.events.push_back(event);
+        if let Some(waker) = queue.waker.take() {
+            waker.wake();
+        }
+    }
+}
+
+impl<T: UsbContext> Hotplug<T> for Enqueue<T> {
+    fn device_arrived(&mut self, device: Device<T>) {
+        self.push(HotplugEvent::Arrived(device));
+    }
+
+    fn device_left(&mut self, device: Device<T>) {
+        self.push(HotplugEvent::Left(device));
+    }
+}
+
+fn lock<T: UsbContext>(queue: &Mutex<Queue<T>>) -> MutexGuard<'_, Queue<T>> {
+    queue.lock().unwrap_or_else(|p| p.into_inner())
+}
+
+/// A callback with the context it reports the devices of.
+type Callback<T> = (T, Box<dyn Hotplug<T>>);
+
+/// The data of a registered callback, passed to `libusb`.
+struct CallbackData<T: UsbContext> {
+    slot: Mutex<Slot<Callback<T>, QueuedEvent>>,
+}
+
+fn slot<T: UsbContext>(data: &CallbackData<T>) -> MutexGuard<'_, Slot<Callback<T>, QueuedEvent>> {
+    data.slot.lock().unwrap_or_else(|p| p.into_inner())
+}
+
+/// Holds a callback, which is taken out while it runs so it isn't called with the lock held, and
+/// the events reported meanwhile, which it is called with before it is put back.
+struct Slot<C, E> {
+    callback: Option<C>,
+    queued: VecDeque<E>,
+    deregistered: bool,
+}
+
+impl<C, E> Slot<C, E> {
+    fn new(callback: C) -> Slot<C, E> {
+        Slot {
+            callback: Some(callback),
+            queued: VecDeque::new(),
+            deregistered: false,
+        }
+    }
+
+    /// Takes the callback out to call it with `event`, or queues the event if the callback is
+    /// already running. The event is dropped if the callback was deregistered.
+    fn take(&mut self, event: E) -> Option<(C, E)> {
+        match self.callback.take() {
+            Some(callback) => Some((callback, event)),
+            None => {
+                if !self.deregistered {
+                    self.queued.push_back(event);
+                }
+                None
+            }
+        }
+    }
+
+    /// Returns the next event queued while the callback ran, to call it again before putting it
+    /// back.
+    fn next_queued(&mut self) -> Option<E> {
+        self.queued.pop_front()
+    }
+
+    /// Puts the callback back once it returned, or gives it back to be dropped if it was
+    /// deregistered meanwhile.
+    fn put_back(&mut self, callback: C) -> Option<C> {
+        if self.deregistered {
+            return Some(callback);
+        }
+        self.callback = Some(callback);
+        None
+    }
+
+    /// Marks the callback as deregistered and drops the queued events, and returns the callback
+    /// unless it is running, in which case it is given back by [`put_back`](#method.put_back)
+    /// instead.
+    fn deregister(&mut self) -> Option<C> {
+        self.deregistered = true;
+        self.queued.clear();
+        self.callback.take()
+    }
+}
+
+/// An event reported while the callback was running, holding a reference to its device.
+struct QueuedEvent {
+    event: libusb_hotplug_event,
+    device: NonNull<libusb_device>,
+}
+
+impl QueuedEvent {
+    unsafe fn new(event: libusb_hotplug_event, device: *mut libusb_device) -> QueuedEvent {
+        libusb_ref_device(device);
+
+        QueuedEvent {
+            event,
+            device: NonNull::new_unchecked(device),
+        }
+    }
+}
+
+impl Drop for QueuedEvent {
+    fn drop(&mut self) {
+        unsafe { libusb_unref_device(self.device.as_ptr()) };
+    }
+}
+
+/// The data of deregistered callbacks, with the address of their context and the function
+/// freeing them.
+struct Retired(usize, *mut c_void, unsafe fn(*mut c_void));
+
+// the data is only freed, once no callback can use it anymore
+unsafe impl Send for Retired {}
+
+static RETIRED: Mutex<Vec<Retired>> = Mutex::new(Vec::new());
+
+/// Keeps the data of a deregistered callback until its context is exited.
+fn retire<T: UsbContext>(context: *mut libusb_context, data: NonNull<CallbackData<T>>) {
+    unsafe fn free<T: UsbContext>(data: *mut c_void) {
+        drop(Box::from_raw(data as *mut CallbackData<T>));
+    }
+
+    let mut retired = RETIRED.lock().unwrap_or_else(|p| p.into_inner());
+    retired.push(Retired(
+        context as usize,
+        data.as_ptr() as *mut c_void,
+        free::<T>,
+    ));
+}
+
+/// Frees the data of the callbacks deregistered from `context`, once it was `exited`.
+///
+/// A context borrowed from foreign code isn't exited, and its events may still be handled, so the
+/// data is leaked instead, a few bytes per callback, like that of contexts that are never
+/// dropped, e.g. the global context. The callbacks themselves are dropped on deregistration.
+pub(crate) fn free_retired(context: *mut libusb_context, exited: bool) {
+    let freed: Vec<Retired> = {
+        let mut retired = RETIRED.lock().unwrap_or_else(|p| p.into_inner());
+        let (freed, kept) = retired
+            .drain(..)
+            .partition(|Retired(c, _, _)| *c == context as usize);
+        *retired = kept;
+        freed
+    };
+
+    if !exited {
+        return;
+    }
+    for Retired(_, data, free) in freed {
+        unsafe { free(data) };
+    }
+}
+
+extern "system" fn hotplug_callback<T: UsbContext>(
+    ctx: *mut libusb_context,
+    device: *mut libusb_device,
+    event: libusb_hotplug_event,
+    data: *mut c_void,
+) -> c_int {
+    unsafe {
+        if !device_policy::permits(ctx, device) {
+            return 0;
+        }
+        let data = &*(data as *const CallbackData<T>);
+        // the callback may be running on another thread, e.g. one registering with `enumerate`,
+        // or on this one, if it handles events itself: the event is then queued for it, rather
+        // than lost
+        let ((context, mut hotplug), mut event) =
+            match slot(data).take(QueuedEvent::new(event, device)) {
+                Some(taken) => taken,
+                None => return 0,
+            };
+
+        loop {
+            deliver(&context, &mut hotplug, &event);
+
+            let mut slot = slot(data);
+            match slot.next_queued() {
+                Some(next) => {
+                    drop(slot);
+                    event = next;
+                }
+                None => {
+                    // dropped outside of the lock, in case it drops its own registration
+                    let deregistered = slot.put_back((context, hotplug));
+                    drop(slot);
+                    drop(deregistered);
+                    break;
+                }
+            }
+        }
+    }
+    0
+}
+
+/// Calls the callback with an event.
+unsafe fn deliver<T: UsbContext>(
+    context: &T,
+    hotplug: &mut Box<dyn Hotplug<T>>,
+    event: &QueuedEvent,
+) {
+    let device = device::from_libusb(context.clone(), event.device.as_ptr());
+    if event_log::is_enabled() {
+        let kind = if event.event == LIBUSB_HOTPLUG_EVENT_DEVICE_ARRIVED {
+            EventKind::Arrived
+        } else {
+            EventKind::Left
+        };
+        event_log::record(
+            Record::new(kind, device.bus_number(), device.address())
+                .text("device", device.quick_profile()),
+        );
+    }
+    event_loop::catch_callback_panic(|| match event.event {
+        LIBUSB_HOTPLUG_EVENT_DEVICE_ARRIVED => hotplug.device_arrived(device),
+        LIBUSB_HOTPLUG_EVENT_DEVICE_LEFT => hotplug.device_left(device),
+        _ => (),
+    });
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn it_matches_any_device_by_default() {
+        let builder = HotplugBuilder::new(crate::GlobalContext::default());
+
+        assert_eq!(
+            (None, None, None, false),
+            (
+                builder.vendor_id,
+                builder.product_id,
+                builder.class,
+                builder.enumerate
+            )
+        );
+        assert_eq!(
+            LIBUSB_HOTPLUG_MATCH_ANY,
+            filter(builder.vendor_id.map(c_int::from))
+        );
+    }
+
+    #[test]
+    fn it_sets_filters() {
+        let builder = HotplugBuilder::new(crate::GlobalContext::default())
+            .vendor_id(0x1d6b)
+            .product_id(0x0002)
+            .class(9)
+            .enumerate(true);
+
+        assert_eq!(
+            (Some(0x1d6b), Some(0x0002), Some(9), true),
+            (
+                builder.vendor_id,
+                builder.product_id,
+                builder.class,
+                builder.enumerate
+            )
+        );
+        assert_eq!(0x1d6b, filter(builder.vendor_id.map(c_int::from)));
+    }
+
+    #[test]
+    fn it_waits_for_the_next_event() {
+        struct Noop;
+
+        impl std::task::Wake for Noop {
+            fn wake(self: Arc<Self>) {}
+        }
+
+        let mut queue = Queue::<crate::GlobalContext> {
+            events: VecDeque::new(),
+            waker: None,
+        };
+        let waker = Waker::from(Arc::new(Noop));
+
+        assert!(queue
+            .poll_next(&mut TaskContext::from_waker(&waker))
+            .is_pending());
+        assert!(queue.waker.is_some());
+    }
+
+    #[test]
+    fn it_drops_deregistered_callbacks() {
+        let callback = Arc::new(());
+        let mut slot = Slot::new(callback.clone());
+
+        assert!(slot.deregister().is_some());
+        assert!(slot.take(1).is_none());
+        assert!(slot.next_queued().is_none());
+        assert_eq!(1, Arc::strong_count(&callback));
+    }
+
+    #[test]
+    fn it_drops_running_callbacks_once_they_return() {
+        let callback = Arc::new(());
+        let mut slot = Slot::new(callback.clone());
+
+        let (running, _) = slot.take(1).unwrap();
+        assert!(slot.take(2).is_none());
+        // the registration is dropped while the callback runs
+        assert!(slot.deregister().is_none());
+        assert_eq!(2, Arc::strong_count(&callback));
+
+        assert!(slot.next_queued().is_none());
+        assert!(slot.put_back(running).is_some());
+        assert!(slot.take(3).is_none());
+        assert_eq!(1, Arc::strong_count(&callback));
+    }
+
+    #[test]
+    fn it_keeps_registered_callbacks() {
+        let mut slot = Slot::new(1);
+
+        let (running, _) = slot.take(1).unwrap();
+        assert!(slot.put_back(running).is_none());
+        assert_eq!(Some((1, 2)), slot.take(2));
+    }
+
+    #[test]
+    fn it_queues_the_events_reported_while_the_callback_runs() {
+        let mut slot = Slot::new(());
+
+        assert_eq!(Some(((), 1)), slot.take(1));
+        // a callback isn't called again while it runs
+        assert!(slot.take(2).is_none());
+        assert!(slot.take(3).is_none());
+
+        assert_eq!(Some(2), slot.next_queued());
+        assert_eq!(Some(3), slot.next_queued());
+        assert!(slot.next_queued().is_none());
+    }
+
+    #[test]
+    fn it_frees_retired_data_with_the_context() {
+        let (a, b) = (0x1000 as *mut libusb_context, 0x2000 as *mut libusb_context);
+        let data = NonNull::new(Box::into_raw(Box::new(CallbackData::<crate::Context> {
+            slot: Mutex::new(Slot {
+                callback: None,
+                queued: VecDeque::new(),
+                deregistered: true,
+            }),
+        })))
+        .unwrap();
+
+        retire(a, data);
+        free_retired(b, true);
+        let retired = |context: *mut libusb_context| {
+            RETIRED
+                .lock()
+                .unwrap()
+                .iter()
+                .filter(|Retired(c, _, _)| *c == context as usize)
+                .count()
+        };
+        assert_eq!(1, retired(a));
+
+        free_retired(a, true);
+        assert_eq!(0, retired(a));
+    }
+}
@@ -0,0 +1,318 @@
+use std::{
+    fmt,
+    sync::{Arc, Condvar, Mutex, MutexGuard},
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+use crate::{device::Device, device_handle::DeviceHandle, UsbContext};
+
+/// Configures and opens an [`IdleSession`](struct.IdleSession.html).
+#[derive(Debug, Clone)]
+pub struct IdleSessionBuilder {
+    interfaces: Vec<u8>,
+    idle_after: Duration,
+    allow_autosuspend: bool,
+}
+
+impl IdleSessionBuilder {
+    /// Creates a builder for a session parking after 5 seconds without requests.
+    pub fn new() -> IdleSessionBuilder {
+        IdleSessionBuilder {
+            interfaces: Vec::new(),
+            idle_after: Duration::from_secs(5),
+            allow_autosuspend: false,
+        }
+    }
+
+    /// Adds an interface that the session claims while it is active.
+    pub fn interface(mut self, iface: u8) -> IdleSessionBuilder {
+        self.interfaces.push(iface);
+        self
+    }
+
+    /// Sets how long the session waits after the last request before parking.
+    pub fn idle_after(mut self, idle_after: Duration) -> IdleSessionBuilder {
+        self.idle_after = idle_after;
+        self
+    }
+
+    /// Closes the device while the session is parked, and opens it again on the next request.
+    ///
+    /// Operating systems usually keep a device awake while it is open, e.g. Linux for as long as
+    /// its usbfs node is, so releasing the interfaces isn't enough to let it suspend. Whether the
+    /// device then suspends is up to the system, e.g. its `power/control` attribute in sysfs
+    /// must be `auto` on Linux.
+    pub fn allow_autosuspend(mut self, allow: bool) -> IdleSessionBuilder {
+        self.allow_autosuspend = allow;
+        self
+    }
+
+    /// Opens `device`, claims the interfaces, and starts monitoring the session.
+    ///
+    /// ## Errors
+    ///
+    /// * Any error opening the device or claiming an interface.
+    pub fn open<T>(self, device: Device<T>) -> crate::Result<IdleSession<T>>
+    where
+        T: UsbContext + Send + Sync + 'static,
+    {
+        let mut handle = device.open()?;
+        for &iface in &self.interfaces {
+            handle.claim_interface(iface)?;
+        }
+
+        let shared = Arc::new(Shared {
+            device,
+            interfaces: self.interfaces,
+            idle_after: self.idle_after,
+            allow_autosuspend: self.allow_autosuspend,
+            state: Mutex::new(State {
+                handle: Some(handle),
+                parked: false,
+                last_use: Instant::now(),
+                parks: 0,
+                stop: false,
+            }),
+            wake: Condvar::new(),
+        });
+
+        let monitor = {
+            let shared = shared.clone();
+            thread::spawn(move || monitor(&shared))
+        };
+
+        Ok(IdleSession {
+            shared,
+            monitor: Some(monitor),
+        })
+    }
+}
+
+impl Default for IdleSessionBuilder {
+    fn default() -> Self {
+        IdleSessionBuilder::new()
+    }
+}
+
+/// An open device that releases its interfaces when it goes unused, to save power on
+/// battery-powered hosts, and takes them back on the next request.
+///
+/// Every request goes through [`with`](#method.with). Once no request was made for the
+/// [idle period](struct.IdleSessionBuilder.html#method.idle_after), a monitor thread parks the
+/// session: it releases the interfaces, and closes the device if
+/// [`allow_autosuspend`](struct.IdleSessionBuilder.html#method.allow_autosuspend) is set. The
+/// next request resumes the session before it runs, so parking is transparent to the caller,
+/// apart from the time it takes to claim the interfaces again.
+///
+/// Requests are serialized, and the session can't park while one runs. State the device forgets
+/// when an interface is released, e.g. an alternate setting, has to be restored by the caller.
+pub struct IdleSession<T: UsbContext + Send + Sync + 'static> {
+    shared: Arc<Shared<T>>,
+    monitor: Option<JoinHandle<()>>,
+}
+
+struct Shared<T: UsbContext> {
+    device: Device<T>,
+    interfaces: Vec<u8>,
+    idle_after: Duration,
+    allow_autosuspend: bool,
+    state: Mutex<State<T>>,
+    wake: Condvar,
+}
+
+struct State<T: UsbContext> {
+    /// `None` while parked with the device closed.
+    handle: Option<DeviceHandle<T>>,
+    parked: bool,
+    last_use: Instant,
+    parks: u32,
+    stop: bool,
+}
+
+impl<T: UsbContext + Send + Sync + 'static> IdleSession<T> {
+    /// Performs `f` with the device handle, resuming the session first if it is parked.
+    ///
+    /// ## Errors
+    ///
+    /// * Any error opening the device again or claiming an interface while resuming, in which
+    ///   case the session stays parked.
+    /// * The error returned by `f`.
+    pub fn with<R, F>(&self, f: F) -> crate::Result<R>
+    where
+        F: FnOnce(&mut DeviceHandle<T>) -> crate::Result<R>,
+    {
+        let mut state = self.shared.lock();
+        if state.parked {
+            self.shared.resume(&mut state)?;
+            self.shared.wake.notify_all();
+        }
+
+        let handle = state
+            .handle
+            .as_mut()
+            .expect("an active session has a handle");
+        let result = f(handle);
+        state.last_use = Instant::now();
+        result
+    }
+
+    /// Parks the session now, without waiting for the idle period.
+    ///
+    /// ## Errors
+    ///
+    /// * The first error releasing an interface. The session is parked anyway.
+    pub fn park(&self) -> crate::Result<()> {
+        let mut state = self.shared.lock();
+        if state.parked {
+            return Ok(());
+        }
+        self.shared.park(&mut state)
+    }
+
+    /// Indicates whether the session is parked, i.e. its interfaces are released.
+    pub fn is_parked(&self) -> bool {
+        self.shared.lock().parked
+    }
+
+    /// Returns how many times the session was parked.
+    pub fn parks(&self) -> u32 {
+        self.shared.lock().parks
+    }
+
+    /// Returns the device of the session.
+    pub fn device(&self) -> &Device<T> {
+        &self.shared.device
+    }
+}
+
+impl<T: UsbContext + Send + Sync + 'static> Drop for IdleSession<T> {
+    fn drop(&mut self) {
+        self.shared.lock().stop = true;
+        self.shared.wake.notify_all();
+
+        if let Some(monitor) = self.monitor.take() {
+            monitor.join().ok();
+        }
+    }
+}
+
+impl<T: UsbContext + Send + Sync + 'static> fmt::Debug for IdleSession<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let state = self.shared.lock();
+        f.debug_struct("IdleSession")
+            .field("interfaces", &self.shared.interfaces)
+            .field("idle_after", &self.shared.idle_after)
+            .field("parked", &state.parked)
+            .field("parks", &state.parks)
+            .finish()
+    }
+}
+
+impl<T: UsbContext> Shared<T> {
+    fn lock(&self) -> MutexGuard<'_, State<T>> {
+        self.state.lock().unwrap_or_else(|p| p.into_inner())
+    }
+
+    fn park(&self, state: &mut State<T>) -> crate::Result<()> {
+        let mut result = Ok(());
+        if let Some(handle) = state.handle.as_mut() {
+            for &iface in &self.interfaces {
+                if let Err(e) = handle.release_interface(iface) {
+                    result = result.and(Err(e));
+                }
+            }
+        }
+        if self.allow_autosuspend {
+            state.handle = None;
+        }
+
+        state.parked = true;
+        state.parks += 1;
+        result
+    }
+
+    fn resume(&self, state: &mut State<T>) -> crate::Result<()> {
+        let handle = match state.handle.take() {
+            Some(handle) => handle,
+            None => self.device.open()?,
+        };
+        let handle = state.handle.insert(handle);
+
+        for (i, &iface) in self.interfaces.iter().enumerate() {
+            if let Err(e) = handle.claim_interface(iface) {
+                for &claimed in &self.interfaces[..i] {
+                    handle.release_interface(claimed).ok();
+                }
+                return Err(e);
+            }
+        }
+
+        state.parked = false;
+        Ok(())
+    }
+}
+
+/// The loop of the monitor thread, parking the session once it is idle.
+fn monitor<T: UsbContext>(shared: &Shared<T>) {
+    let mut state = shared.lock();
+
+    while !state.stop {
+        if state.parked {
+            state = shared.wake.wait(state).unwrap_or_else(|p| p.into_inner());
+            continue;
+        }
+
+        match idle_remaining(state.last_use, shared.idle_after, Instant::now()) {
+            Some(remaining) => {
+                state = shared
+                    .wake
+                    .wait_timeout(state, remaining)
+                    .unwrap_or_else(|p| p.into_inner())
+                    .0;
+            }
+            None => {
+                shared.park(&mut state).ok();
+            }
+        }
+    }
+}
+
+/// Returns how long remains until a session last used at `last_use` is idle, or `None` if it
+/// already is.
+fn idle_remaining(last_use: Instant, idle_after: Duration, now: Instant) -> Option<Duration> {
+    (last_use + idle_after)
+        .checked_duration_since(now)
+        .filter(|remaining| !remaining.is_zero())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn it_waits_for_the_rest_of_the_idle_period() {
+        let last_use = Instant::now();
+        let idle_after = Duration::from_secs(5);
+
+        assert_eq!(
+            Some(Duration::from_secs(3)),
+            idle_remaining(last_use, idle_after, last_use + Duration::from_secs(2))
+        );
+    }
+
+    #[test]
+    fn it_is_idle_once_the_period_elapsed() {
+        let last_use = Instant::now();
+        let idle_after = Duration::from_secs(5);
+
+        assert_eq!(
+            None,
+            idle_remaining(last_use, idle_after, last_use + idle_after)
+        );
+        assert_eq!(
+            None,
+            idle_remaining(last_use, idle_after, last_use + Duration::from_secs(6))
+        );
+    }
+}
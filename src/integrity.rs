@@ -0,0 +1,283 @@
+use std::sync::{
+    atomic::{AtomicU64, Ordering},
+    Arc,
+};
+
+use crate::{error::Error, pipe::Transform};
+
+/// What an [`IntegrityCheck`](struct.IntegrityCheck.html) does with frames that fail validation.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum OnCorruption {
+    /// Count the frame and discard it.
+    Drop,
+
+    /// Count the frame and pass it on unchanged, e.g. to inspect it.
+    Deliver,
+
+    /// Count the frame and deliver `Error::Other` in its place.
+    Fail,
+}
+
+/// Counters maintained by an [`IntegrityCheck`](struct.IntegrityCheck.html).
+///
+/// The counters are updated as frames pass through the check and can be read at any time from
+/// another thread.
+#[derive(Debug, Default)]
+pub struct IntegrityStats {
+    frames: AtomicU64,
+    corrupt: AtomicU64,
+    gaps: AtomicU64,
+    lost: AtomicU64,
+}
+
+impl IntegrityStats {
+    /// Returns the number of frames checked.
+    pub fn frames(&self) -> u64 {
+        self.frames.load(Ordering::Relaxed)
+    }
+
+    /// Returns the number of frames that failed validation.
+    pub fn corrupt(&self) -> u64 {
+        self.corrupt.load(Ordering::Relaxed)
+    }
+
+    /// Returns the number of times a frame's sequence number was not the expected one.
+    pub fn sequence_gaps(&self) -> u64 {
+        self.gaps.load(Ordering::Relaxed)
+    }
+
+    /// Returns the number of frames missing according to their sequence numbers.
+    pub fn lost(&self) -> u64 {
+        self.lost.load(Ordering::Relaxed)
+    }
+}
+
+type Validator = Box<dyn FnMut(&[u8]) -> bool + Send>;
+type Sequencer = Box<dyn FnMut(&[u8]) -> Option<u64> + Send>;
+
+/// A [`Transform`](trait.Transform.html) that validates received frames and counts corruption.
+///
+/// Meant for qualifying hardware, cables and hubs: add it to an
+/// [`InPipeBuilder`](struct.InPipeBuilder.html) and watch its
+/// [`IntegrityStats`](struct.IntegrityStats.html) while traffic flows. Both checks are
+/// configured with functions, so any framing can be supported:
+///
+/// * a validator, e.g. comparing a CRC carried in the frame with one computed over its payload,
+/// * a sequence number extractor, whose results are expected to increase by one per frame.
+///
+/// A frame whose sequence number is not the expected one is counted as a gap, and the frames
+/// skipped over as lost; the check then resynchronizes on the new number. Sequence numbers are
+/// only taken from frames that pass validation.
+pub struct IntegrityCheck {
+    validate: Option<Validator>,
+    sequence: Option<Sequencer>,
+    sequence_modulus: Option<u64>,
+    trailer: usize,
+    on_corruption: OnCorruption,
+    expected: Option<u64>,
+    stats: Arc<IntegrityStats>,
+}
+
+impl IntegrityCheck {
+    /// Creates a check that accepts every frame. Corrupt frames are dropped by default.
+    pub fn new() -> IntegrityCheck {
+        IntegrityCheck {
+            validate: None,
+            sequence: None,
+            sequence_modulus: None,
+            trailer: 0,
+            on_corruption: OnCorruption::Drop,
+            expected: None,
+            stats: Arc::new(IntegrityStats::default()),
+        }
+    }
+
+    /// Creates a check for frames ending in a little-endian CRC-32 (IEEE 802.3) of the preceding
+    /// bytes. The CRC is removed from frames that pass.
+    pub fn crc32_le_trailer() -> IntegrityCheck {
+        IntegrityCheck::new()
+            .validate(|frame: &[u8]| {
+                if frame.len() < 4 {
+                    return false;
+                }
+
+                let (payload, trailer) = frame.split_at(frame.len() - 4);
+                crc32(payload)
+                    == u32::from_le_bytes([trailer[0], trailer[1], trailer[2], trailer[3]])
+            })
+            .strip_trailer(4)
+    }
+
+    /// Sets the function deciding whether a frame is intact.
+    pub fn validate<F>(mut self, validate: F) -> IntegrityCheck
+    where
+        F: FnMut(&[u8]) -> bool + Send + 'static,
+    {
+        self.validate = Some(Box::new(validate));
+        self
+    }
+
+    /// Sets the function extracting a frame's sequence number.
+    ///
+    /// Frames for which it returns `None` are not sequence checked.
+    pub fn sequence<F>(mut self, sequence: F) -> IntegrityCheck
+    where
+        F: FnMut(&[u8]) -> Option<u64> + Send + 'static,
+    {
+        self.sequence = Some(Box::new(sequence));
+        self
+    }
+
+    /// Sets the value at which sequence numbers wrap around to zero, e.g. 256 for an 8-bit
+    /// counter. Sequence numbers are assumed not to wrap by default.
+    pub fn sequence_modulus(mut self, modulus: u64) -> IntegrityCheck {
+        self.sequence_modulus = Some(modulus).filter(|&m| m > 0);
+        self
+    }
+
+    /// Removes `len` bytes, e.g. a checksum, from the end of frames that pass validation.
+    pub fn strip_trailer(mut self, len: usize) -> IntegrityCheck {
+        self.trailer = len;
+        self
+    }
+
+    /// Sets what happens to frames that fail validation.
+    pub fn on_corruption(mut self, policy: OnCorruption) -> IntegrityCheck {
+        self.on_corruption = policy;
+        self
+    }
+
+    /// Returns the counters of this check.
+    pub fn stats(&self) -> Arc<IntegrityStats> {
+        self.stats.clone()
+    }
+
+    fn check_sequence(&mut self, frame: &[u8]) {
+        let number = match self.sequence.as_mut().and_then(|f| f(frame)) {
+            Some(n) => n,
+            None => return,
+        };
+
+        if let Some(expected) = self.expected {
+            if number != expected {
+                let lost = match self.sequence_modulus {
+                    Some(m) => (number % m + m - expected % m) % m,
+                    None => number.saturating_sub(expected),
+                };
+
+                self.stats.gaps.fetch_add(1, Ordering::Relaxed);
+                self.stats.lost.fetch_add(lost, Ordering::Relaxed);
+            }
+        }
+
+        let next = number.wrapping_add(1);
+        self.expected = Some(match self.sequence_modulus {
+            Some(m) => next % m,
+            None => next,
+        });
+    }
+}
+
+impl Default for IntegrityCheck {
+    fn default() -> Self {
+        IntegrityCheck::new()
+    }
+}
+
+impl Transform for IntegrityCheck {
+    fn apply(&mut self, mut data: Vec<u8>) -> crate::Result<Option<Vec<u8>>> {
+        self.stats.frames.fetch_add(1, Ordering::Relaxed);
+
+        let intact = self.validate.as_mut().map_or(true, |f| f(&data));
+        if !intact {
+            self.stats.corrupt.fetch_add(1, Ordering::Relaxed);
+
+            return match self.on_corruption {
+                OnCorruption::Drop => Ok(None),
+                OnCorruption::Deliver => Ok(Some(data)),
+                OnCorruption::Fail => Err(Error::Other),
+            };
+        }
+
+        self.check_sequence(&data);
+
+        let len = data.len().saturating_sub(self.trailer);
+        data.truncate(len);
+        Ok(Some(data))
+    }
+}
+
+/// Computes the CRC-32 (IEEE 802.3) of `data`, as used by Ethernet, zlib and PNG.
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+
+    for &byte in data {
+        crc ^= u32::from(byte);
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xEDB8_8320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+
+    !crc
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn it_computes_crc32() {
+        assert_eq!(0xCBF4_3926, crc32(b"123456789"));
+        assert_eq!(0, crc32(&[]));
+    }
+
+    #[test]
+    fn it_validates_and_strips_crc_trailers() {
+        let mut check = IntegrityCheck::crc32_le_trailer();
+        let stats = check.stats();
+
+        let mut frame = b"hello".to_vec();
+        frame.extend_from_slice(&crc32(b"hello").to_le_bytes());
+
+        assert_eq!(Ok(Some(b"hello".to_vec())), check.apply(frame.clone()));
+
+        frame[0] ^= 0x01;
+        assert_eq!(Ok(None), check.apply(frame));
+
+        assert_eq!(2, stats.frames());
+        assert_eq!(1, stats.corrupt());
+    }
+
+    #[test]
+    fn it_applies_the_corruption_policy() {
+        let mut check = IntegrityCheck::new()
+            .validate(|_: &[u8]| false)
+            .on_corruption(OnCorruption::Fail);
+        assert_eq!(Err(Error::Other), check.apply(vec![1]));
+
+        let mut check = IntegrityCheck::new()
+            .validate(|_: &[u8]| false)
+            .on_corruption(OnCorruption::Deliver);
+        assert_eq!(Ok(Some(vec![1])), check.apply(vec![1]));
+    }
+
+    #[test]
+    fn it_counts_sequence_gaps() {
+        let mut check = IntegrityCheck::new()
+            .sequence(|frame: &[u8]| frame.first().map(|&n| u64::from(n)))
+            .sequence_modulus(256);
+        let stats = check.stats();
+
+        for n in [254u8, 255, 0, 3, 4] {
+            check.apply(vec![n]).unwrap();
+        }
+
+        assert_eq!(5, stats.frames());
+        assert_eq!(1, stats.sequence_gaps());
+        assert_eq!(2, stats.lost());
+    }
+}
@@ -0,0 +1,294 @@
+use std::{
+    fmt,
+    ops::{Deref, DerefMut},
+    time::Duration,
+};
+
+use crate::{device_handle::DeviceHandle, error::Error, UsbContext};
+
+/// The interfaces claimed by
+/// [`DeviceHandle::claim_all_unclaimed_interfaces`](struct.DeviceHandle.html#method.claim_all_unclaimed_interfaces).
+///
+/// The guard gives access to the handle through `Deref` and releases the interfaces it claimed
+/// when it is dropped. Interfaces that were already claimed beforehand are left alone.
+pub struct InterfaceClaims<'h, T: UsbContext> {
+    handle: &'h mut DeviceHandle<T>,
+    results: Vec<(u8, crate::Result<()>)>,
+}
+
+impl<'h, T: UsbContext> InterfaceClaims<'h, T> {
+    pub(crate) fn new(
+        handle: &'h mut DeviceHandle<T>,
+        results: Vec<(u8, crate::Result<()>)>,
+    ) -> InterfaceClaims<'h, T> {
+        InterfaceClaims { handle, results }
+    }
+
+    /// Returns the outcome of claiming each interface that wasn't claimed yet, in interface
+    /// number order.
+    pub fn results(&self) -> &[(u8, crate::Result<()>)] {
+        &self.results
+    }
+
+    /// Returns the interfaces that were claimed.
+    pub fn claimed(&self) -> Vec<u8> {
+        self.results
+            .iter()
+            .filter(|(_, res)| res.is_ok())
+            .map(|&(iface, _)| iface)
+            .collect()
+    }
+
+    /// Indicates whether every interface was claimed.
+    pub fn is_complete(&self) -> bool {
+        self.results.iter().all(|(_, res)| res.is_ok())
+    }
+
+    /// Keeps the interfaces claimed instead of releasing them when the guard is dropped.
+    ///
+    /// They are then released when the handle is closed, as if they had been claimed one by one.
+    pub fn keep(mut self) {
+        self.results.clear();
+    }
+}
+
+impl<'h, T: UsbContext> Deref for InterfaceClaims<'h, T> {
+    type Target = DeviceHandle<T>;
+
+    fn deref(&self) -> &DeviceHandle<T> {
+        self.handle
+    }
+}
+
+impl<'h, T: UsbContext> DerefMut for InterfaceClaims<'h, T> {
+    fn deref_mut(&mut self) -> &mut DeviceHandle<T> {
+        self.handle
+    }
+}
+
+impl<'h, T: UsbContext> Drop for InterfaceClaims<'h, T> {
+    /// Releases the claimed interfaces.
+    fn drop(&mut self) {
+        for iface in self.claimed() {
+            self.handle.release_interface(iface).ok();
+        }
+    }
+}
+
+/// An interface claimed by [`DeviceHandle::claim`](struct.DeviceHandle.html#method.claim).
+///
+/// The guard releases the interface when it is dropped, and attaches again the kernel driver
+/// that claiming it detached. Its transfer methods only accept the endpoints of the interface's
+/// current alternate setting, and the handle stays reachable through `Deref` for everything
+/// else.
+pub struct ClaimedInterface<'h, T: UsbContext> {
+    handle: &'h mut DeviceHandle<T>,
+    iface: u8,
+    detached: bool,
+    /// The endpoints of the current alternate setting, `None` if they couldn't be read.
+    endpoints: Option<Vec<u8>>,
+    /// Whether dropping the guard releases the interface.
+    release_on_drop: bool,
+}
+
+impl<'h, T: UsbContext> ClaimedInterface<'h, T> {
+    pub(crate) fn new(
+        handle: &'h mut DeviceHandle<T>,
+        iface: u8,
+        detached: bool,
+    ) -> ClaimedInterface<'h, T> {
+        let endpoints = handle.interface_endpoints(iface).ok();
+        ClaimedInterface {
+            handle,
+            iface,
+            detached,
+            endpoints,
+            release_on_drop: true,
+        }
+    }
+
+    /// Returns the interface number.
+    pub fn number(&self) -> u8 {
+        self.iface
+    }
+
+    /// Indicates whether a kernel driver was detached to claim the interface, and will be
+    /// attached again when the guard is dropped.
+    pub fn detached_kernel_driver(&self) -> bool {
+        self.detached
+    }
+
+    /// Returns the interface's current alternate setting.
+    pub fn alternate_setting(&self) -> u8 {
+        self.handle.current_alt_setting(self.iface).unwrap_or(0)
+    }
+
+    /// Selects an alternate setting of the interface, whose endpoints the transfer methods then
+    /// accept.
+    pub fn set_alternate_setting(&mut self, setting: u8) -> crate::Result<()> {
+        self.handle.set_alternate_setting(self.iface, setting)?;
+        self.endpoints = self.handle.interface_endpoints(self.iface).ok();
+        Ok(())
+    }
+
+    /// Reads from a bulk endpoint of the interface, see
+    /// [`DeviceHandle::read_bulk`](struct.DeviceHandle.html#method.read_bulk).
+    ///
+    /// ## Errors
+    ///
+    /// * `InvalidParam` if the endpoint doesn't belong to the interface's current alternate
+    ///   setting.
+    /// * Any error returned by the transfer.
+    #[cfg_attr(feature = "async-only", deprecated(note = "blocks the calling thread"))]
+    #[cfg_attr(feature = "async-only", allow(deprecated))]
+    pub fn read_bulk(
+        &self,
+        endpoint: u8,
+        buf: &mut [u8],
+        timeout: Duration,
+    ) -> crate::Result<usize> {
+        self.check_endpoint(endpoint)?;
+        self.handle.read_bulk(endpoint, buf, timeout)
+    }
+
+    /// Writes to a bulk endpoint of the interface, see
+    /// [`DeviceHandle::write_bulk`](struct.DeviceHandle.html#method.write_bulk).
+    ///
+    /// ## Errors
+    ///
+    /// * `InvalidParam` if the endpoint doesn't belong to the interface's current alternate
+    ///   setting.
+    /// * Any error returned by the transfer.
+    #[cfg_attr(feature = "async-only", deprecated(note = "blocks the calling thread"))]
+    #[cfg_attr(feature = "async-only", allow(deprecated))]
+    pub fn write_bulk(&self, endpoint: u8, buf: &[u8], timeout: Duration) -> crate::Result<usize> {
+        self.check_endpoint(endpoint)?;
+        self.handle.write_bulk(endpoint, buf, timeout)
+    }
+
+    /// Reads from an interrupt endpoint of the interface, see
+    /// [`DeviceHandle::read_interrupt`](struct.DeviceHandle.html#method.read_interrupt).
+    ///
+    /// ## Errors
+    ///
+    /// * `InvalidParam` if the endpoint doesn't belong to the interface's current alternate
+    ///   setting.
+    /// * Any error returned by the transfer.
+    #[cfg_attr(feature = "async-only", deprecated(note = "blocks the calling thread"))]
+    #[cfg_attr(feature = "async-only", allow(deprecated))]
+    pub fn read_interrupt(
+        &self,
+        endpoint: u8,
+        buf: &mut [u8],
+        timeout: Duration,
+    ) -> crate::Result<usize> {
+        self.check_endpoint(endpoint)?;
+        self.handle.read_interrupt(endpoint, buf, timeout)
+    }
+
+    /// Writes to an interrupt endpoint of the interface, see
+    /// [`DeviceHandle::write_interrupt`](struct.DeviceHandle.html#method.write_interrupt).
+    ///
+    /// ## Errors
+    ///
+    /// * `InvalidParam` if the endpoint doesn't belong to the interface's current alternate
+    ///   setting.
+    /// * Any error returned by the transfer.
+    #[cfg_attr(feature = "async-only", deprecated(note = "blocks the calling thread"))]
+    #[cfg_attr(feature = "async-only", allow(deprecated))]
+    pub fn write_interrupt(
+        &self,
+        endpoint: u8,
+        buf: &[u8],
+        timeout: Duration,
+    ) -> crate::Result<usize> {
+        self.check_endpoint(endpoint)?;
+        self.handle.write_interrupt(endpoint, buf, timeout)
+    }
+
+    /// Clears the halt condition of an endpoint of the interface.
+    pub fn clear_halt(&mut self, endpoint: u8) -> crate::Result<()> {
+        self.check_endpoint(endpoint)?;
+        self.handle.clear_halt(endpoint)
+    }
+
+    /// Releases the interface now, reporting the error the guard would ignore when dropped.
+    pub fn release(mut self) -> crate::Result<()> {
+        self.release_on_drop = false;
+        let res = self.handle.release_interface(self.iface);
+        if self.detached {
+            self.handle.attach_kernel_driver(self.iface).ok();
+        }
+        res
+    }
+
+    /// Keeps the interface claimed instead of releasing it when the guard is dropped.
+    ///
+    /// It is then released when the handle is closed, and a detached kernel driver is not
+    /// attached again.
+    pub fn keep(mut self) {
+        self.release_on_drop = false;
+    }
+
+    fn check_endpoint(&self, endpoint: u8) -> crate::Result<()> {
+        check_endpoint(self.endpoints.as_deref(), endpoint)
+    }
+}
+
+/// Checks that `endpoint` is one of `endpoints`, if they are known.
+fn check_endpoint(endpoints: Option<&[u8]>, endpoint: u8) -> crate::Result<()> {
+    match endpoints {
+        Some(endpoints) if !endpoints.contains(&endpoint) => Err(Error::InvalidParam),
+        _ => Ok(()),
+    }
+}
+
+impl<'h, T: UsbContext> Deref for ClaimedInterface<'h, T> {
+    type Target = DeviceHandle<T>;
+
+    fn deref(&self) -> &DeviceHandle<T> {
+        self.handle
+    }
+}
+
+impl<'h, T: UsbContext> Drop for ClaimedInterface<'h, T> {
+    /// Releases the interface, and attaches the kernel driver again if it was detached.
+    fn drop(&mut self) {
+        if !self.release_on_drop {
+            return;
+        }
+        self.handle.release_interface(self.iface).ok();
+        if self.detached {
+            self.handle.attach_kernel_driver(self.iface).ok();
+        }
+    }
+}
+
+impl<'h, T: UsbContext> fmt::Debug for ClaimedInterface<'h, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ClaimedInterface")
+            .field("iface", &self.iface)
+            .field("detached", &self.detached)
+            .field("endpoints", &self.endpoints)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn it_only_accepts_the_interface_endpoints() {
+        let endpoints = [0x81, 0x02];
+
+        assert_eq!(Ok(()), check_endpoint(Some(&endpoints), 0x81));
+        assert_eq!(
+            Err(Error::InvalidParam),
+            check_endpoint(Some(&endpoints), 0x82)
+        );
+        assert_eq!(Err(Error::InvalidParam), check_endpoint(Some(&[]), 0x81));
+        // unknown endpoints don't get in the way
+        assert_eq!(Ok(()), check_endpoint(None, 0x82));
+    }
+}
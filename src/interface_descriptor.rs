@@ -2,7 +2,11 @@ use std::{fmt, slice};
 
 use libusb1_sys::{libusb_endpoint_descriptor, libusb_interface, libusb_interface_descriptor};
 
-use crate::endpoint_descriptor::{self, EndpointDescriptor};
+use crate::{
+    class_descriptors::ClassDescriptors,
+    descriptor_view::ExtraDescriptors,
+    endpoint_descriptor::{self, EndpointDescriptor},
+};
 
 /// A device interface.
 ///
@@ -90,7 +94,7 @@ impl<'a> InterfaceDescriptor<'a> {
     }
 
     /// Returns an iterator over the interface's endpoint descriptors.
-    pub fn endpoint_descriptors(&self) -> EndpointDescriptors {
+    pub fn endpoint_descriptors(&self) -> EndpointDescriptors<'_> {
         let endpoints = unsafe {
             slice::from_raw_parts(
                 self.descriptor.endpoint,
@@ -106,15 +110,27 @@ impl<'a> InterfaceDescriptor<'a> {
     /// Returns the unknown 'extra' bytes that libusb does not understand.
     pub fn extra(&self) -> Option<&[u8]> {
         unsafe {
-            match (*self.descriptor).extra_length {
-                len if len > 0 => Some(slice::from_raw_parts(
-                    (*self.descriptor).extra,
-                    len as usize,
-                )),
+            match self.descriptor.extra_length {
+                len if len > 0 => Some(slice::from_raw_parts(self.descriptor.extra, len as usize)),
                 _ => None,
             }
         }
     }
+
+    /// Returns the descriptors in the 'extra' bytes, e.g. class-specific descriptors.
+    pub fn extra_descriptors(&self) -> ExtraDescriptors<'_> {
+        ExtraDescriptors::new(self.extra().unwrap_or(&[]))
+    }
+
+    /// Returns the class-specific descriptors in the 'extra' bytes, decoded according to the
+    /// interface's class, e.g. CDC functional descriptors or the HID descriptor.
+    pub fn class_descriptors(&self) -> ClassDescriptors<'_> {
+        ClassDescriptors::new(
+            self.class_code(),
+            self.sub_class_code(),
+            self.extra().unwrap_or(&[]),
+        )
+    }
 }
 
 impl<'a> fmt::Debug for InterfaceDescriptor<'a> {
@@ -155,7 +171,7 @@ impl<'a> Iterator for EndpointDescriptors<'a> {
 }
 
 #[doc(hidden)]
-pub(crate) unsafe fn from_libusb(interface: &libusb_interface) -> Interface {
+pub(crate) unsafe fn from_libusb(interface: &libusb_interface) -> Interface<'_> {
     let descriptors =
         slice::from_raw_parts(interface.altsetting, interface.num_altsetting as usize);
     debug_assert!(!descriptors.is_empty());
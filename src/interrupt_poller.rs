@@ -0,0 +1,313 @@
+use std::time::Duration;
+
+use libusb1_sys::constants::*;
+
+use crate::{
+    async_io::{AsyncGroup, Transfer, TransferStatus},
+    endpoint_descriptor::EndpointDescriptor,
+    fields::{Direction, TransferType},
+    Context, DeviceHandle, Error, Result, UsbContext,
+};
+
+/// Keeps several interrupt IN transfers queued on one endpoint so that no report is missed.
+///
+/// A single `read_interrupt()` loop leaves the endpoint without a pending request between the
+/// moment one transfer completes and the moment the next one is submitted. The host controller
+/// does not poll the endpoint during that gap, so devices reporting at high rates (e.g. 8kHz
+/// gaming peripherals) lose reports. The poller instead keeps `N` transfers in flight, each sized
+/// to the endpoint's `wMaxPacketSize`, and resubmits every transfer as soon as its report has been
+/// handed out.
+///
+/// Choosing `N` is a tradeoff:
+///
+/// * Sizing each transfer to one packet means every report completes its own transfer, so
+///   latency is never increased by waiting for a buffer to fill.
+/// * A larger `N` tolerates longer delays in the consumer before reports are dropped, at the cost
+///   of memory and of more transfers to cancel when the poller is dropped. Reports are always
+///   delivered in the order the device sent them, so a large `N` never reorders data, but it
+///   lets a slow consumer fall further behind the device.
+///
+/// Two to four transfers are usually enough at full speed; high speed devices with a 125µs
+/// interval typically need eight or more.
+///
+/// A consumer that processes reports in place can hold them with
+/// [`hold_report`](#method.hold_report) instead of copying them, and
+/// [`release`](#method.release) each one once done. A held transfer isn't
+/// queued on the endpoint, so a slow consumer naturally makes the device wait, down to a floor
+/// of transfers that stay queued, see [`set_min_in_flight`](#method.set_min_in_flight).
+pub struct InterruptPoller<'d, T: UsbContext> {
+    group: AsyncGroup<'d, T>,
+    in_flight: usize,
+    held: usize,
+    min_in_flight: usize,
+}
+
+/// A report held by the consumer of an [`InterruptPoller`](struct.InterruptPoller.html), whose
+/// transfer is resubmitted once it is [`release`](struct.InterruptPoller.html#method.release)d.
+///
+/// Dropping a held report without releasing it frees its transfer, so the poller keeps one
+/// transfer less in flight for good.
+pub struct HeldReport<'d, T: UsbContext> {
+    transfer: Transfer<'d, T>,
+}
+
+impl<'d, T: UsbContext> HeldReport<'d, T> {
+    /// Returns the data of the report.
+    pub fn data(&mut self) -> &[u8] {
+        self.transfer.actual()
+    }
+}
+
+impl<'d, T: UsbContext> InterruptPoller<'d, T> {
+    /// Starts polling `endpoint` with one transfer per `packet_size` chunk of `buffer`.
+    ///
+    /// The number of transfers kept in flight is `buffer.len() / packet_size`; any remainder of
+    /// `buffer` is left unused. `packet_size` should normally be the endpoint's
+    /// `wMaxPacketSize`, see [`packet_size`](#method.packet_size).
+    ///
+    /// ## Errors
+    ///
+    /// * `InvalidParam` if `endpoint` is not an IN endpoint, `packet_size` is zero, or `buffer`
+    ///   is too small to hold a single packet.
+    /// * Any error returned when submitting the initial transfers.
+    pub fn new(
+        context: &'d Context,
+        handle: &'d DeviceHandle<T>,
+        endpoint: u8,
+        buffer: &'d mut [u8],
+        packet_size: usize,
+        timeout: Duration,
+    ) -> Result<InterruptPoller<'d, T>> {
+        if endpoint & LIBUSB_ENDPOINT_DIR_MASK != LIBUSB_ENDPOINT_IN
+            || packet_size == 0
+            || buffer.len() < packet_size
+        {
+            return Err(Error::InvalidParam);
+        }
+
+        let mut poller = InterruptPoller {
+            group: AsyncGroup::new(context),
+            in_flight: 0,
+            held: 0,
+            min_in_flight: 1,
+        };
+
+        for chunk in buffer.chunks_exact_mut(packet_size) {
+            poller
+                .group
+                .submit(Transfer::interrupt(handle, endpoint, chunk, timeout))?;
+            poller.in_flight += 1;
+        }
+
+        Ok(poller)
+    }
+
+    /// Returns the transfer size to use for an interrupt IN endpoint.
+    ///
+    /// Only the low 11 bits of `wMaxPacketSize` hold the packet size, the remaining bits encode
+    /// additional transactions per microframe for high bandwidth endpoints, which are accounted
+    /// for here, like the packets per burst of SuperSpeed endpoints, see
+    /// [`EndpointDescriptor::burst_size`](struct.EndpointDescriptor.html#method.burst_size).
+    ///
+    /// Returns `None` if the endpoint is not an interrupt IN endpoint.
+    pub fn packet_size(endpoint: &EndpointDescriptor) -> Option<usize> {
+        if endpoint.direction() != Direction::In
+            || endpoint.transfer_type() != TransferType::Interrupt
+        {
+            return None;
+        }
+
+        Some(endpoint.burst_size())
+    }
+
+    /// Returns the number of transfers currently queued on the endpoint.
+    pub fn in_flight(&self) -> usize {
+        self.in_flight
+    }
+
+    /// Waits for the next report and copies it into `buf`.
+    ///
+    /// The transfer that carried the report is resubmitted before returning, including when it
+    /// timed out, so the endpoint stays covered. Reports longer than `buf` are truncated.
+    ///
+    /// ## Errors
+    ///
+    /// * `NotFound` if no transfers are left in flight.
+    /// * `Timeout` if the oldest transfer timed out without data.
+    /// * `Pipe` if the endpoint halted.
+    /// * `NoDevice` if the device has been disconnected.
+    /// * `Io` if the transfer encountered an I/O error.
+    ///
+    /// Transfers that fail with anything other than a timeout are not resubmitted.
+    pub fn read_report(&mut self, buf: &mut [u8]) -> Result<usize> {
+        let mut transfer = self.group.wait_any()?;
+        self.in_flight -= 1;
+
+        let status = transfer.status();
+        let len = match status_error(status) {
+            None => {
+                let data = transfer.actual();
+                let len = data.len().min(buf.len());
+                buf[..len].copy_from_slice(&data[..len]);
+                Ok(len)
+            }
+            Some(e) => Err(e),
+        };
+
+        if let TransferStatus::Success | TransferStatus::Timeout = status {
+            self.group.submit(transfer)?;
+            self.in_flight += 1;
+        }
+
+        len
+    }
+
+    /// Sets how many transfers stay queued on the endpoint however many reports are held.
+    /// Defaults to one, so the endpoint is never left without a pending request.
+    pub fn set_min_in_flight(&mut self, min_in_flight: usize) {
+        self.min_in_flight = min_in_flight;
+    }
+
+    /// Returns how many transfers stay queued on the endpoint however many reports are held.
+    pub fn min_in_flight(&self) -> usize {
+        self.min_in_flight
+    }
+
+    /// Returns the number of reports held and not released yet.
+    pub fn held(&self) -> usize {
+        self.held
+    }
+
+    /// Waits for the next report, and hands out its transfer without resubmitting it.
+    ///
+    /// The transfer is resubmitted by [`release`](#method.release), once the consumer is done
+    /// with the report. Transfers that time out are resubmitted right away, like with
+    /// [`read_report`](#method.read_report).
+    ///
+    /// ## Errors
+    ///
+    /// * `Busy` if holding another report would leave fewer transfers in flight than
+    ///   [`min_in_flight`](#method.min_in_flight); release a report first.
+    /// * The errors of [`read_report`](#method.read_report).
+    pub fn hold_report(&mut self) -> Result<HeldReport<'d, T>> {
+        if !can_hold(self.in_flight, self.min_in_flight) {
+            return Err(Error::Busy);
+        }
+
+        let transfer = self.group.wait_any()?;
+        self.in_flight -= 1;
+
+        let status = transfer.status();
+        match status_error(status) {
+            None => {
+                self.held += 1;
+                Ok(HeldReport { transfer })
+            }
+            Some(e) => {
+                if status == TransferStatus::Timeout {
+                    self.group.submit(transfer)?;
+                    self.in_flight += 1;
+                }
+                Err(e)
+            }
+        }
+    }
+
+    /// Resubmits the transfer of a held report.
+    ///
+    /// ## Errors
+    ///
+    /// * Any error returned when submitting the transfer, which is then freed.
+    pub fn release(&mut self, report: HeldReport<'d, T>) -> Result<()> {
+        self.held -= 1;
+        self.group.submit(report.transfer)?;
+        self.in_flight += 1;
+        Ok(())
+    }
+
+    /// Cancels all queued transfers.
+    ///
+    /// This is done automatically when the poller is dropped.
+    pub fn cancel(&mut self) -> Result<()> {
+        // the transfers completed even if the cancellation failed
+        let res = self.group.cancel_all();
+        self.in_flight = 0;
+        res
+    }
+}
+
+/// Returns the error a transfer completed with, if any.
+fn status_error(status: TransferStatus) -> Option<Error> {
+    match status {
+        TransferStatus::Success => None,
+        TransferStatus::Timeout => Some(Error::Timeout),
+        TransferStatus::Stall => Some(Error::Pipe),
+        TransferStatus::NoDevice => Some(Error::NoDevice),
+        TransferStatus::Overflow => Some(Error::Overflow),
+        TransferStatus::Cancelled => Some(Error::Interrupted),
+        TransferStatus::Error | TransferStatus::Unknown => Some(Error::Io),
+    }
+}
+
+/// Indicates whether a report can be held without leaving fewer than `min_in_flight` transfers
+/// in flight.
+fn can_hold(in_flight: usize, min_in_flight: usize) -> bool {
+    in_flight > min_in_flight
+}
+
+impl<'d, T: UsbContext> Drop for InterruptPoller<'d, T> {
+    fn drop(&mut self) {
+        self.cancel().ok();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{can_hold, InterruptPoller};
+    use crate::{endpoint_descriptor, Context};
+
+    fn packet_size(descriptor: libusb1_sys::libusb_endpoint_descriptor) -> Option<usize> {
+        InterruptPoller::<Context>::packet_size(&endpoint_descriptor::from_libusb(&descriptor))
+    }
+
+    #[test]
+    fn it_uses_max_packet_size_for_interrupt_in_endpoints() {
+        assert_eq!(
+            Some(64),
+            packet_size(
+                endpoint_descriptor!(bEndpointAddress: 0x81, bmAttributes: 0x03, wMaxPacketSize: 64)
+            )
+        );
+    }
+
+    #[test]
+    fn it_accounts_for_additional_transactions() {
+        assert_eq!(
+            Some(3 * 1024),
+            packet_size(
+                endpoint_descriptor!(bEndpointAddress: 0x81, bmAttributes: 0x03, wMaxPacketSize: 0x1400)
+            )
+        );
+    }
+
+    #[test]
+    fn it_keeps_the_floor_of_transfers_in_flight() {
+        assert!(can_hold(4, 1));
+        assert!(can_hold(2, 1));
+        assert!(!can_hold(1, 1));
+        assert!(can_hold(1, 0));
+        assert!(!can_hold(0, 0));
+    }
+
+    #[test]
+    fn it_rejects_other_endpoints() {
+        assert_eq!(
+            None,
+            packet_size(endpoint_descriptor!(bEndpointAddress: 0x01, bmAttributes: 0x03))
+        );
+        assert_eq!(
+            None,
+            packet_size(endpoint_descriptor!(bEndpointAddress: 0x81, bmAttributes: 0x02))
+        );
+    }
+}
@@ -0,0 +1,304 @@
+//! Blocking transfers with a well-defined response to signals.
+//!
+//! When a signal arrives while libusb waits for events, some platforms and versions of libusb
+//! restart the wait, others return `LIBUSB_ERROR_INTERRUPTED`, so a Ctrl-C handler may or may
+//! not unblock a synchronous transfer. The functions of this module reproduce libusb's
+//! synchronous transfers with the policy chosen by the caller instead.
+
+use std::time::{Duration, Instant};
+
+use libc::{c_int, c_uint, c_void};
+use libusb1_sys::{constants::*, *};
+
+use crate::{buffer_allocator, setup_packet::SetupPacket};
+
+/// How blocking calls react when a signal interrupts them.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum OnInterrupt {
+    /// Keep waiting, for what remains of the timeout, as if nothing happened. This is the
+    /// default.
+    Retry,
+
+    /// Cancel the transfer and fail with `Error::Interrupted`, e.g. so that a CLI tool can exit
+    /// promptly on Ctrl-C. Bulk and interrupt data transferred before the cancellation is still
+    /// reported, as a successful short transfer.
+    Return,
+}
+
+/// Performs a bulk or interrupt transfer, returning the libusb error code and the number of
+/// bytes transferred, like `libusb_bulk_transfer` and `libusb_interrupt_transfer`.
+///
+/// # Safety
+///
+/// `buf` must be valid for `len` bytes, and writable for IN transfers.
+#[allow(clippy::too_many_arguments)]
+pub(crate) unsafe fn transfer(
+    context: *mut libusb_context,
+    handle: *mut libusb_device_handle,
+    transfer_type: u8,
+    endpoint: u8,
+    buf: *mut u8,
+    len: usize,
+    timeout: Duration,
+    policy: OnInterrupt,
+) -> (c_int, usize) {
+    match policy {
+        OnInterrupt::Retry => retry(timeout, |remaining| {
+            let sync = if transfer_type == LIBUSB_TRANSFER_TYPE_BULK {
+                libusb_bulk_transfer
+            } else {
+                libusb_interrupt_transfer
+            };
+
+            let mut transferred: c_int = 0;
+            let res = sync(
+                handle,
+                endpoint,
+                buf,
+                len as c_int,
+                &mut transferred,
+                millis(remaining),
+            );
+            (res, transferred.max(0) as usize)
+        }),
+        OnInterrupt::Return => {
+            interruptible(context, handle, transfer_type, endpoint, buf, len, timeout)
+        }
+    }
+}
+
+/// Performs a control transfer, returning the number of bytes transferred or a libusb error
+/// code, like `libusb_control_transfer`.
+///
+/// # Safety
+///
+/// `buf` must be valid for `len` bytes, and writable for IN transfers.
+#[allow(clippy::too_many_arguments)]
+pub(crate) unsafe fn control(
+    context: *mut libusb_context,
+    handle: *mut libusb_device_handle,
+    request_type: u8,
+    request: u8,
+    value: u16,
+    index: u16,
+    buf: *mut u8,
+    len: usize,
+    timeout: Duration,
+    policy: OnInterrupt,
+) -> c_int {
+    match policy {
+        OnInterrupt::Retry => {
+            let (res, transferred) = retry(timeout, |remaining| {
+                let res = libusb_control_transfer(
+                    handle,
+                    request_type,
+                    request,
+                    value,
+                    index,
+                    buf,
+                    len as u16,
+                    millis(remaining),
+                );
+                // a control transfer that failed transferred nothing usable
+                (res.min(0), res.max(0) as usize)
+            });
+            if res < 0 {
+                res
+            } else {
+                transferred as c_int
+            }
+        }
+        OnInterrupt::Return => {
+            let setup_len = SetupPacket::SIZE;
+            let allocator = buffer_allocator::get(context);
+            let mut packet = buffer_allocator::allocate(allocator.as_ref(), setup_len + len);
+            SetupPacket::new(request_type, request, value, index, len as u16).write_to(&mut packet);
+
+            let read = request_type & LIBUSB_ENDPOINT_DIR_MASK == LIBUSB_ENDPOINT_IN;
+            if !read && len > 0 {
+                std::ptr::copy_nonoverlapping(buf, packet[setup_len..].as_mut_ptr(), len);
+            }
+
+            let (res, transferred) = interruptible(
+                context,
+                handle,
+                LIBUSB_TRANSFER_TYPE_CONTROL,
+                0,
+                packet.as_mut_ptr(),
+                packet.len(),
+                timeout,
+            );
+
+            if read && transferred > 0 {
+                std::ptr::copy_nonoverlapping(packet[setup_len..].as_ptr(), buf, transferred);
+            }
+            buffer_allocator::release(allocator.as_ref(), packet);
+            match res {
+                0 => transferred as c_int,
+                // control transfers are all or nothing in libusb's API
+                err => err,
+            }
+        }
+    }
+}
+
+/// Repeats an interrupted transfer that transferred nothing, for what remains of `timeout`.
+fn retry<F>(timeout: Duration, mut attempt: F) -> (c_int, usize)
+where
+    F: FnMut(Duration) -> (c_int, usize),
+{
+    let deadline = Instant::now() + timeout;
+
+    loop {
+        let remaining = if timeout == Duration::ZERO {
+            // no timeout
+            Duration::ZERO
+        } else {
+            match deadline.checked_duration_since(Instant::now()) {
+                Some(remaining) if remaining > Duration::ZERO => remaining,
+                _ => return (LIBUSB_ERROR_TIMEOUT, 0),
+            }
+        };
+
+        let (res, transferred) = attempt(remaining);
+        if res != LIBUSB_ERROR_INTERRUPTED || transferred > 0 {
+            return (res, transferred);
+        }
+    }
+}
+
+extern "system" fn completed_callback(transfer: *mut libusb_transfer) {
+    unsafe {
+        *((*transfer).user_data as *mut c_int) = 1;
+    }
+}
+
+/// libusb's synchronous transfer, except that an interruption cancels the transfer.
+unsafe fn interruptible(
+    context: *mut libusb_context,
+    handle: *mut libusb_device_handle,
+    transfer_type: u8,
+    endpoint: u8,
+    buf: *mut u8,
+    len: usize,
+    timeout: Duration,
+) -> (c_int, usize) {
+    let transfer = libusb_alloc_transfer(0);
+    if transfer.is_null() {
+        return (LIBUSB_ERROR_NO_MEM, 0);
+    }
+
+    let mut completed: c_int = 0;
+    (*transfer).dev_handle = handle;
+    (*transfer).endpoint = endpoint;
+    (*transfer).transfer_type = transfer_type;
+    (*transfer).timeout = millis(timeout);
+    (*transfer).buffer = buf;
+    (*transfer).length = len as c_int;
+    (*transfer).user_data = &mut completed as *mut c_int as *mut c_void;
+    (*transfer).callback = completed_callback;
+
+    let res = libusb_submit_transfer(transfer);
+    if res < 0 {
+        libusb_free_transfer(transfer);
+        return (res, 0);
+    }
+
+    let mut interrupted = false;
+    while completed == 0 {
+        match libusb_handle_events_completed(context, &mut completed) {
+            0 => (),
+            LIBUSB_ERROR_INTERRUPTED => {
+                if !interrupted {
+                    interrupted = true;
+                    libusb_cancel_transfer(transfer);
+                }
+            }
+            _ => {
+                // like libusb, give up on the transfer but wait for it to be released
+                libusb_cancel_transfer(transfer);
+            }
+        }
+    }
+
+    let mut transferred = (*transfer).actual_length.max(0) as usize;
+    if transfer_type == LIBUSB_TRANSFER_TYPE_CONTROL {
+        // the setup packet is not part of the transferred data
+        transferred = transferred.min(len.saturating_sub(SetupPacket::SIZE));
+    }
+
+    let res = match (*transfer).status {
+        LIBUSB_TRANSFER_COMPLETED => 0,
+        LIBUSB_TRANSFER_CANCELLED if interrupted => LIBUSB_ERROR_INTERRUPTED,
+        LIBUSB_TRANSFER_TIMED_OUT => LIBUSB_ERROR_TIMEOUT,
+        LIBUSB_TRANSFER_STALL => LIBUSB_ERROR_PIPE,
+        LIBUSB_TRANSFER_NO_DEVICE => LIBUSB_ERROR_NO_DEVICE,
+        LIBUSB_TRANSFER_OVERFLOW => LIBUSB_ERROR_OVERFLOW,
+        _ => LIBUSB_ERROR_IO,
+    };
+    libusb_free_transfer(transfer);
+    // the panic of a callback of another transfer, raised once libusb is done with this one
+    crate::event_loop::resume_callback_panic();
+
+    (res, transferred)
+}
+
+fn millis(timeout: Duration) -> c_uint {
+    timeout.as_millis().min(c_uint::MAX as u128) as c_uint
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn it_retries_interrupted_transfers_without_data() {
+        let mut attempts = 0;
+        let res = retry(Duration::from_secs(5), |_| {
+            attempts += 1;
+            if attempts < 3 {
+                (LIBUSB_ERROR_INTERRUPTED, 0)
+            } else {
+                (0, 8)
+            }
+        });
+
+        assert_eq!((0, 8), res);
+        assert_eq!(3, attempts);
+    }
+
+    #[test]
+    fn it_keeps_data_of_interrupted_transfers() {
+        let res = retry(Duration::from_secs(5), |_| (LIBUSB_ERROR_INTERRUPTED, 4));
+
+        assert_eq!((LIBUSB_ERROR_INTERRUPTED, 4), res);
+    }
+
+    #[test]
+    fn it_retries_within_the_timeout() {
+        let timeout = Duration::from_millis(20);
+        let res = retry(timeout, |remaining| {
+            assert!(remaining <= timeout);
+            std::thread::sleep(Duration::from_millis(5));
+            (LIBUSB_ERROR_INTERRUPTED, 0)
+        });
+
+        assert_eq!((LIBUSB_ERROR_TIMEOUT, 0), res);
+    }
+
+    #[test]
+    fn it_passes_no_timeout_through() {
+        let mut attempts = 0;
+        let res = retry(Duration::ZERO, |remaining| {
+            assert_eq!(Duration::ZERO, remaining);
+            attempts += 1;
+            if attempts < 2 {
+                (LIBUSB_ERROR_INTERRUPTED, 0)
+            } else {
+                (LIBUSB_ERROR_PIPE, 0)
+            }
+        });
+
+        assert_eq!((LIBUSB_ERROR_PIPE, 0), res);
+    }
+}
@@ -0,0 +1,130 @@
+use std::{collections::HashMap, sync::Arc};
+
+use libusb1_sys::{libusb_device, libusb_ref_device, libusb_unref_device};
+
+use crate::{device_handle::DeviceHandle, Context};
+
+/// An opaque integer identifying a device registered with a [`Context`](struct.Context.html).
+///
+/// Keys let bindings to other languages refer to devices without exposing Rust lifetimes or
+/// pointers across the boundary: pass the integer from [`as_u64`](#method.as_u64) out, and look
+/// the device up again with [`Context::device_by_key`](struct.Context.html#method.device_by_key)
+/// when it comes back. Keys are never reused by a context.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct DeviceKey(u64);
+
+/// An opaque integer identifying a device handle registered with a
+/// [`Context`](struct.Context.html).
+///
+/// See [`DeviceKey`](struct.DeviceKey.html).
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct HandleKey(u64);
+
+macro_rules! key_conversions {
+    ($key:ident) => {
+        impl $key {
+            /// Returns the integer value of the key.
+            pub fn as_u64(self) -> u64 {
+                self.0
+            }
+
+            /// Recreates a key from its integer value.
+            ///
+            /// Keys that were never issued simply aren't found when looked up.
+            pub fn from_u64(value: u64) -> $key {
+                $key(value)
+            }
+        }
+    };
+}
+
+key_conversions!(DeviceKey);
+key_conversions!(HandleKey);
+
+/// The devices and handles registered with a context.
+///
+/// Devices are kept as referenced raw pointers rather than `Device`s, which would hold the
+/// context and keep it alive forever.
+#[derive(Default)]
+pub(crate) struct KeyRegistry {
+    next: u64,
+    device_keys: HashMap<usize, DeviceKey>,
+    devices: HashMap<DeviceKey, usize>,
+    handles: HashMap<HandleKey, Arc<DeviceHandle<Context>>>,
+}
+
+impl KeyRegistry {
+    fn next_key(&mut self) -> u64 {
+        self.next += 1;
+        self.next
+    }
+
+    pub(crate) fn device_key(&mut self, device: *mut libusb_device) -> DeviceKey {
+        if let Some(&key) = self.device_keys.get(&(device as usize)) {
+            return key;
+        }
+
+        let key = DeviceKey(self.next_key());
+        unsafe { libusb_ref_device(device) };
+        self.device_keys.insert(device as usize, key);
+        self.devices.insert(key, device as usize);
+        key
+    }
+
+    pub(crate) fn device(&self, key: DeviceKey) -> Option<*mut libusb_device> {
+        self.devices.get(&key).map(|&device| device as *mut _)
+    }
+
+    pub(crate) fn forget_device(&mut self, key: DeviceKey) -> bool {
+        match self.devices.remove(&key) {
+            Some(device) => {
+                self.device_keys.remove(&device);
+                unsafe { libusb_unref_device(device as *mut _) };
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub(crate) fn insert_handle(&mut self, handle: Arc<DeviceHandle<Context>>) -> HandleKey {
+        let key = HandleKey(self.next_key());
+        self.handles.insert(key, handle);
+        key
+    }
+
+    pub(crate) fn handle(&self, key: HandleKey) -> Option<Arc<DeviceHandle<Context>>> {
+        self.handles.get(&key).cloned()
+    }
+
+    pub(crate) fn remove_handle(&mut self, key: HandleKey) -> Option<Arc<DeviceHandle<Context>>> {
+        self.handles.remove(&key)
+    }
+
+    /// Releases the registered devices. Must be called before the context is exited.
+    pub(crate) fn clear(&mut self) {
+        for (_, device) in self.devices.drain() {
+            unsafe { libusb_unref_device(device as *mut _) };
+        }
+        self.device_keys.clear();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn it_round_trips_keys_through_integers() {
+        assert_eq!(DeviceKey(7), DeviceKey::from_u64(DeviceKey(7).as_u64()));
+        assert_eq!(HandleKey(9), HandleKey::from_u64(9));
+    }
+
+    #[test]
+    fn it_does_not_find_unknown_keys() {
+        let mut registry = KeyRegistry::default();
+
+        assert!(registry.device(DeviceKey(1)).is_none());
+        assert!(registry.handle(HandleKey(1)).is_none());
+        assert!(!registry.forget_device(DeviceKey(1)));
+    }
+}
@@ -0,0 +1,157 @@
+//! Tracking of libusb resources to find objects that are never freed.
+//!
+//! Available with the `leak-detection` feature. Every context, device handle and transfer created
+//! through rusb is recorded together with a backtrace of where it was created. When a `Context` is
+//! dropped, any handles and transfers created from it that are still alive are reported on
+//! `stderr`. The same information is available at any time from [`outstanding`].
+
+use std::{
+    backtrace::Backtrace,
+    collections::HashMap,
+    fmt,
+    sync::{Arc, Mutex, OnceLock},
+};
+
+/// The kind of resource being tracked.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum ResourceKind {
+    /// A `libusb` context.
+    Context,
+
+    /// An open device handle.
+    DeviceHandle,
+
+    /// An asynchronous transfer.
+    Transfer,
+}
+
+/// A tracked resource that has not been freed yet.
+#[derive(Clone)]
+pub struct Allocation {
+    kind: ResourceKind,
+    address: usize,
+    context: usize,
+    backtrace: Arc<Backtrace>,
+}
+
+impl Allocation {
+    /// Returns the kind of resource.
+    pub fn kind(&self) -> ResourceKind {
+        self.kind
+    }
+
+    /// Returns the address of the underlying libusb object.
+    pub fn address(&self) -> usize {
+        self.address
+    }
+
+    /// Returns the address of the libusb context the resource belongs to.
+    pub fn context(&self) -> usize {
+        self.context
+    }
+
+    /// Returns the backtrace captured when the resource was created.
+    pub fn backtrace(&self) -> &Backtrace {
+        &self.backtrace
+    }
+}
+
+impl fmt::Debug for Allocation {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        let mut debug = fmt.debug_struct("Allocation");
+
+        debug.field("kind", &self.kind);
+        debug.field("address", &format_args!("{:#x}", self.address));
+        debug.field("context", &format_args!("{:#x}", self.context));
+
+        debug.finish()
+    }
+}
+
+impl fmt::Display for Allocation {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(
+            fmt,
+            "{:?} {:#x} (context {:#x}) created at:",
+            self.kind, self.address, self.context
+        )?;
+        write!(fmt, "{}", self.backtrace)
+    }
+}
+
+fn registry() -> &'static Mutex<HashMap<usize, Allocation>> {
+    static REGISTRY: OnceLock<Mutex<HashMap<usize, Allocation>>> = OnceLock::new();
+    REGISTRY.get_or_init(Default::default)
+}
+
+/// Returns all tracked resources that have not been freed yet.
+pub fn outstanding() -> Vec<Allocation> {
+    let registry = registry().lock().unwrap_or_else(|e| e.into_inner());
+    registry.values().cloned().collect()
+}
+
+pub(crate) fn track<P, C>(kind: ResourceKind, address: *const P, context: *const C) {
+    let allocation = Allocation {
+        kind,
+        address: address as usize,
+        context: context as usize,
+        backtrace: Arc::new(Backtrace::force_capture()),
+    };
+
+    let mut registry = registry().lock().unwrap_or_else(|e| e.into_inner());
+    registry.insert(address as usize, allocation);
+}
+
+pub(crate) fn untrack<P>(address: *const P) {
+    let mut registry = registry().lock().unwrap_or_else(|e| e.into_inner());
+    registry.remove(&(address as usize));
+}
+
+/// Stops tracking a context and reports every resource created from it that is still alive.
+pub(crate) fn report_context<C>(context: *const C) {
+    untrack(context);
+
+    let leaked: Vec<Allocation> = outstanding()
+        .into_iter()
+        .filter(|allocation| allocation.context == context as usize)
+        .collect();
+
+    if leaked.is_empty() {
+        return;
+    }
+
+    eprintln!(
+        "rusb: context {:#x} dropped with {} resource(s) still alive:",
+        context as usize,
+        leaked.len()
+    );
+    for allocation in leaked {
+        eprintln!("{}", allocation);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn it_tracks_and_untracks_resources() {
+        let context = 0x1000 as *const u8;
+        let handle = 0x2000 as *const u8;
+
+        track(ResourceKind::DeviceHandle, handle, context);
+
+        let found = outstanding()
+            .into_iter()
+            .find(|allocation| allocation.address() == 0x2000)
+            .unwrap();
+        assert_eq!(ResourceKind::DeviceHandle, found.kind());
+        assert_eq!(0x1000, found.context());
+
+        untrack(handle);
+
+        assert!(outstanding()
+            .into_iter()
+            .all(|allocation| allocation.address() != 0x2000));
+    }
+}
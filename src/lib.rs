@@ -1,50 +1,172 @@
 //! This crate provides a safe wrapper around the native `libusb` library.
+//!
+//! With the `async-only` feature, the methods performing synchronous transfers, such as
+//! `DeviceHandle::read_bulk` or the string descriptor helpers, are deprecated. Code that must
+//! never block, e.g. because it runs on the thread handling events, can then enforce it at
+//! compile time with `#![deny(deprecated)]`.
 
 pub use libusb1_sys::constants;
 
 pub use crate::{
-    async_io::{AsyncGroup, Transfer, TransferStatus},
-    config_descriptor::{ConfigDescriptor, Interfaces},
-    context::{Context, GlobalContext, Hotplug, LogLevel, Registration, UsbContext},
+    annotations::Annotations,
+    async_io::{AsyncGroup, Completion, IsoPacket, Priority, Transfer, TransferId, TransferStatus},
+    async_transfer::{OwnedTransfer, ReadFuture, WriteFuture},
+    bos_descriptor::{
+        BosDescriptor, Capabilities, ContainerId, DeviceCapability, SuperSpeedCapability,
+        Usb2Extension,
+    },
+    buffer_allocator::BufferAllocator,
+    close_report::CloseReport,
+    config_descriptor::{ConfigDescriptor, InterfaceAssociation, Interfaces, PowerDraw},
+    context::{Context, GlobalContext, LogLevel, UsbContext},
+    context_pool::{ContextPool, EventError, PoolRegistration},
+    control_sequence::{ControlRequest, SequenceError},
+    demux::Demux,
+    descriptor_view::{
+        ConfigDescriptorView, EndpointDescriptorView, EndpointDescriptorViews, ExtraDescriptors,
+        InterfaceDescriptorView, InterfaceDescriptorViews, ParseMode,
+    },
     device::Device,
     device_descriptor::DeviceDescriptor,
+    device_filter::DeviceFilter,
     device_handle::DeviceHandle,
-    device_list::{DeviceList, Devices},
-    endpoint_descriptor::EndpointDescriptor,
+    device_io::DeviceIo,
+    device_list::{CachedDevice, DeviceCache, DeviceList, Devices},
+    device_policy::DevicePolicy,
+    device_strings::DeviceStrings,
+    device_worker::{CancelToken, DeviceWorker, Reply},
+    duplex::{duplex_pump_async, DuplexPump, PumpStats},
+    endpoint_descriptor::{EndpointDescriptor, SuperSpeedEndpointCompanion},
+    endpoint_io::{EndpointReader, EndpointWriter},
     error::{Error, Result},
+    event_log::{EventKind, EventLog},
+    event_loop::EventLoop,
     fields::{
-        request_type, Direction, Recipient, RequestType, Speed, SyncType, TransferType, UsageType,
-        Version,
+        request_type, ClassCode, DescriptorType, Direction, Recipient, RequestType, Speed,
+        SyncType, TransferType, UsageType, UsbSpec, Version,
     },
+    hotplug::{Hotplug, HotplugBuilder, HotplugEvent, HotplugEvents, NextEvent, Registration},
+    idle_session::{IdleSession, IdleSessionBuilder},
+    integrity::{crc32, IntegrityCheck, IntegrityStats, OnCorruption},
+    interface_claims::{ClaimedInterface, InterfaceClaims},
     interface_descriptor::{
         EndpointDescriptors, Interface, InterfaceDescriptor, InterfaceDescriptors,
     },
+    interrupt_poller::{HeldReport, InterruptPoller},
+    interruptible::OnInterrupt,
+    keys::{DeviceKey, HandleKey},
     language::{Language, PrimaryLanguage, SubLanguage},
+    managed_context::{DeviceId, ManagedContext, ManagedRegistration},
+    open_options::OpenOptions,
+    operation_trace::TraceEntry,
     options::UsbOption,
+    pacer::{PacedWriter, Pacer, Tick},
+    pipe::{InPipe, InPipeBuilder, OnOverflow, OnRepeat, Transform},
+    pollfd::{PollFd, PollFdNotifier, PollFdRegistration},
+    reconnecting_handle::{ReconnectingHandle, ReconnectingHandleBuilder},
+    secure_buffer::SecureBuffer,
+    setup_packet::SetupPacket,
+    simple_vendor::SimpleVendorDevice,
+    string_cache::{CachedStrings, Strings},
+    topology::TopologyNode,
+    transfer_outcome::TransferOutcome,
+    usb_memory::UsbMemory,
     version::{version, LibraryVersion},
 };
 
+#[cfg_attr(feature = "async-only", allow(deprecated))]
+pub use crate::duplex::duplex_pump;
+
+#[cfg(feature = "log")]
+pub use crate::log_forward::forward_log_messages;
+
 #[cfg(test)]
 #[macro_use]
 mod test_helpers;
 
 #[macro_use]
 mod error;
+mod annotations;
 mod async_io;
+mod async_transfer;
+#[cfg(feature = "capi")]
+pub mod capi;
+pub mod cdc;
+pub mod class_descriptors;
+pub mod descriptor_types;
+#[cfg(any(feature = "ftdi-eeprom", feature = "cypress-eeprom"))]
+pub mod eeprom;
+#[cfg(any(test, feature = "fake"))]
+pub mod fake;
+
+#[cfg(target_os = "linux")]
+pub mod authorization;
+pub mod event_log;
+pub mod gadget;
+pub mod hid;
+mod integrity;
+mod interrupt_poller;
+mod keys;
+#[cfg(feature = "leak-detection")]
+pub mod leak_detection;
+#[cfg(all(feature = "loopback", target_os = "linux"))]
+pub mod loopback;
+pub mod lpm;
+pub mod profiles;
+pub mod request_types;
+#[cfg(target_os = "linux")]
+pub mod system_devices;
 mod version;
 
 mod context;
+mod context_pool;
+mod debug_bundle;
+mod demux;
+mod descriptor_view;
 mod device;
 mod device_handle;
+mod device_io;
 mod device_list;
+mod device_strings;
+mod device_worker;
+mod duplex;
+mod event_loop;
+mod hotplug;
+mod idle_session;
 
+mod bos_descriptor;
+mod buffer_allocator;
+mod close_report;
 mod config_descriptor;
+mod control_sequence;
 mod device_descriptor;
+mod device_filter;
+mod device_policy;
 mod endpoint_descriptor;
+mod endpoint_io;
 mod fields;
+mod interface_claims;
 mod interface_descriptor;
+mod interruptible;
 mod language;
+#[cfg(feature = "log")]
+mod log_forward;
+mod managed_context;
+mod open_options;
+mod operation_trace;
 mod options;
+mod pacer;
+mod pipe;
+mod pollfd;
+mod profile_string;
+mod reconnecting_handle;
+mod secure_buffer;
+mod setup_packet;
+mod simple_vendor;
+mod string_cache;
+mod topology;
+mod transfer_outcome;
+mod usb_memory;
 
 /// Tests whether the running `libusb` library supports capability API.
 pub fn has_capability() -> bool {
@@ -0,0 +1,74 @@
+use std::{ffi::CStr, ptr};
+
+use libc::{c_char, c_int};
+use libusb1_sys::{constants::*, libusb_context};
+
+use crate::event_loop;
+
+type LogCallback = extern "system" fn(*mut libusb_context, c_int, *const c_char);
+
+const LIBUSB_LOG_CB_GLOBAL: c_int = 1 << 0;
+
+// available since libusb 1.0.23, but not bound by libusb1-sys
+extern "system" {
+    fn libusb_set_log_cb(ctx: *mut libusb_context, cb: Option<LogCallback>, mode: c_int);
+}
+
+/// Routes the messages of `libusb` to the [`log`](https://docs.rs/log) crate, instead of
+/// `stderr`.
+///
+/// Messages of every context, including the global one, are logged with the `libusb` target,
+/// prefixed by the address of their context so messages of different contexts can be told apart.
+/// `libusb` still filters messages by the log level of their context, which is
+/// [`LogLevel::None`](enum.LogLevel.html) by default: raise it with
+/// [`UsbContext::set_log_level`](trait.UsbContext.html#method.set_log_level),
+/// [`UsbOption::log_level`](struct.UsbOption.html#method.log_level) or
+/// [`set_log_level`](fn.set_log_level.html) for the global context.
+///
+/// Messages are logged from within `libusb`, so a logger that panics doesn't unwind into the
+/// caller: the panic is reported by the panic hook, and the message is dropped.
+///
+/// **Note**: This function is available with the `log` feature only!
+pub fn forward_log_messages() {
+    unsafe { libusb_set_log_cb(ptr::null_mut(), Some(log_callback), LIBUSB_LOG_CB_GLOBAL) };
+}
+
+extern "system" fn log_callback(ctx: *mut libusb_context, level: c_int, message: *const c_char) {
+    if message.is_null() {
+        return;
+    }
+    let message = unsafe { CStr::from_ptr(message) }.to_string_lossy();
+
+    // the logger is the application's code, called from any libusb function
+    event_loop::discard_callback_panic(|| {
+        log::log!(
+            target: "libusb",
+            log_level(level),
+            "[context {:p}] {}",
+            ctx,
+            message.trim_end()
+        )
+    });
+}
+
+fn log_level(level: c_int) -> log::Level {
+    match level {
+        LIBUSB_LOG_LEVEL_ERROR => log::Level::Error,
+        LIBUSB_LOG_LEVEL_WARNING => log::Level::Warn,
+        LIBUSB_LOG_LEVEL_INFO => log::Level::Info,
+        _ => log::Level::Debug,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn it_maps_libusb_levels() {
+        assert_eq!(log::Level::Error, log_level(LIBUSB_LOG_LEVEL_ERROR));
+        assert_eq!(log::Level::Warn, log_level(LIBUSB_LOG_LEVEL_WARNING));
+        assert_eq!(log::Level::Info, log_level(LIBUSB_LOG_LEVEL_INFO));
+        assert_eq!(log::Level::Debug, log_level(LIBUSB_LOG_LEVEL_DEBUG));
+    }
+}
@@ -0,0 +1,506 @@
+//! A FunctionFS loopback gadget for testing the transfer paths against a real USB stack.
+//!
+//! [`Loopback::setup`](struct.Loopback.html#method.setup) creates a gadget through configfs with
+//! a single vendor-specific interface holding a bulk IN and a bulk OUT endpoint, implemented with
+//! FunctionFS, and binds it to a USB device controller. Everything received on the OUT endpoint
+//! is sent back on the IN endpoint. With the `dummy_hcd` module loaded, the gadget shows up on
+//! the same machine, so tests can drive it through rusb:
+//!
+//! ```no_run
+//! use std::time::Duration;
+//! use rusb::{loopback::Loopback, Context};
+//!
+//! let loopback = Loopback::setup("rusb_loopback", None).unwrap();
+//! let context = Context::new().unwrap();
+//! let device = loopback.open(&context, Duration::from_secs(5)).unwrap();
+//!
+//! let data: Vec<u8> = (0..1000).map(|i| i as u8).collect();
+//! assert_eq!(data, device.round_trip(&data, Duration::from_secs(1)).unwrap());
+//! ```
+//!
+//! Setting up the gadget requires root privileges, a kernel with `CONFIG_USB_CONFIGFS_F_FS`,
+//! configfs mounted on `/sys/kernel/config`, and a device controller (`dummy_hcd` or real
+//! hardware). Tests can treat a failing `setup` as a reason to skip.
+
+use std::{
+    ffi::CString,
+    fs::{self, File, OpenOptions},
+    io::{Read, Write},
+    os::unix::{ffi::OsStrExt, fs::symlink},
+    path::{Path, PathBuf},
+    ptr,
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+use crate::{
+    device_handle::DeviceHandle,
+    error::{self, Error},
+    fields::{Direction, TransferType},
+    gadget::{EndpointDescriptorBuilder, InterfaceDescriptorBuilder},
+    UsbContext,
+};
+
+/// The vendor ID of the loopback gadget (Linux Foundation).
+pub const VENDOR_ID: u16 = 0x1d6b;
+
+/// The product ID of the loopback gadget (Multifunction Composite Gadget).
+pub const PRODUCT_ID: u16 = 0x0104;
+
+const CONFIGFS: &str = "/sys/kernel/config/usb_gadget";
+const UDC_CLASS: &str = "/sys/class/udc";
+
+const DESCRIPTORS_MAGIC_V2: u32 = 3;
+const STRINGS_MAGIC: u32 = 2;
+const HAS_FS_DESC: u32 = 1;
+const HAS_HS_DESC: u32 = 2;
+
+const EVENT_SIZE: usize = 12;
+const EVENT_UNBIND: u8 = 1;
+const EVENT_ENABLE: u8 = 2;
+
+/// The size of the reads on the OUT endpoint. Reading no more than the smallest bulk packet
+/// size makes every read complete as soon as a packet arrives, whatever the speed; FunctionFS
+/// keeps the rest of larger packets for the next read.
+const ECHO_CHUNK: usize = 64;
+
+/// A FunctionFS loopback gadget, removed when dropped.
+pub struct Loopback {
+    name: String,
+    gadget: PathBuf,
+    mount: Option<PathBuf>,
+    control: Option<JoinHandle<()>>,
+}
+
+impl Loopback {
+    /// Creates the gadget `name` and binds it to the device controller `udc`, or to the first
+    /// one found in `/sys/class/udc`.
+    ///
+    /// ## Errors
+    ///
+    /// * `NotSupported` if configfs gadgets aren't available.
+    /// * `NotFound` if there is no device controller.
+    /// * `Busy` if a gadget with this name already exists.
+    /// * `Access` without the privileges to create gadgets and mount FunctionFS.
+    /// * `Io` or another error if a step of the setup fails.
+    pub fn setup(name: &str, udc: Option<&str>) -> crate::Result<Loopback> {
+        if !Path::new(CONFIGFS).is_dir() {
+            return Err(Error::NotSupported);
+        }
+
+        let udc = match udc {
+            Some(udc) => udc.to_string(),
+            None => first_udc()?,
+        };
+
+        let gadget = Path::new(CONFIGFS).join(name);
+        if gadget.exists() {
+            return Err(Error::Busy);
+        }
+
+        let mut loopback = Loopback {
+            name: name.to_string(),
+            gadget,
+            mount: None,
+            control: None,
+        };
+
+        // on failure, dropping the partial gadget removes what was created so far
+        loopback.create()?;
+        loopback.start(&udc)?;
+        Ok(loopback)
+    }
+
+    /// Waits up to `timeout` for the gadget to show up on the host side, then opens it and
+    /// claims its interface.
+    ///
+    /// ## Errors
+    ///
+    /// * `Timeout` if the gadget didn't show up in time.
+    /// * Any error returned while opening the device or claiming the interface.
+    pub fn open<T: UsbContext>(
+        &self,
+        context: &T,
+        timeout: Duration,
+    ) -> crate::Result<LoopbackDevice<T>> {
+        let deadline = Instant::now() + timeout;
+
+        let mut handle = loop {
+            if let Some(handle) = context.open_device_with_vid_pid(VENDOR_ID, PRODUCT_ID) {
+                break handle;
+            }
+            if Instant::now() >= deadline {
+                return Err(Error::Timeout);
+            }
+            thread::sleep(Duration::from_millis(50));
+        };
+
+        let config = handle.device().active_config_descriptor()?;
+        let interface = config
+            .interfaces()
+            .flat_map(|i| i.descriptors())
+            .find(|d| d.class_code() == 0xff)
+            .ok_or(Error::NotFound)?;
+
+        let mut endpoint_in = None;
+        let mut endpoint_out = None;
+        for endpoint in interface.endpoint_descriptors() {
+            if endpoint.transfer_type() != TransferType::Bulk {
+                continue;
+            }
+            match endpoint.direction() {
+                Direction::In => endpoint_in = Some(endpoint.address()),
+                Direction::Out => endpoint_out = Some(endpoint.address()),
+            }
+        }
+
+        handle.set_auto_detach_kernel_driver(true).ok();
+        handle.claim_interface(interface.interface_number())?;
+
+        Ok(LoopbackDevice {
+            handle,
+            endpoint_in: endpoint_in.ok_or(Error::NotFound)?,
+            endpoint_out: endpoint_out.ok_or(Error::NotFound)?,
+        })
+    }
+
+    /// Creates the configfs gadget and mounts its FunctionFS instance.
+    fn create(&mut self) -> crate::Result<()> {
+        let function = format!("ffs.{}", self.name);
+
+        mkdir(&self.gadget)?;
+        write(
+            &self.gadget.join("idVendor"),
+            &format!("{:#06x}", VENDOR_ID),
+        )?;
+        write(
+            &self.gadget.join("idProduct"),
+            &format!("{:#06x}", PRODUCT_ID),
+        )?;
+        write(&self.gadget.join("bcdUSB"), "0x0200")?;
+
+        let strings = self.gadget.join("strings/0x409");
+        mkdir(&strings)?;
+        write(&strings.join("manufacturer"), "rusb")?;
+        write(&strings.join("product"), "rusb loopback")?;
+        write(&strings.join("serialnumber"), &self.name)?;
+
+        let config = self.gadget.join("configs/c.1");
+        mkdir(&config)?;
+        mkdir(&config.join("strings/0x409"))?;
+        write(&config.join("strings/0x409/configuration"), "loopback")?;
+        write(&config.join("MaxPower"), "100")?;
+
+        let function_dir = self.gadget.join("functions").join(&function);
+        mkdir(&function_dir)?;
+        symlink(&function_dir, config.join(&function)).map_err(|e| error::from_io_error(&e))?;
+
+        let mount = std::env::temp_dir().join(format!("rusb-{}", function));
+        fs::create_dir_all(&mount).map_err(|e| error::from_io_error(&e))?;
+        mount_functionfs(&self.name, &mount)?;
+        self.mount = Some(mount);
+        Ok(())
+    }
+
+    /// Describes the function, starts answering on it, and binds the gadget to `udc`.
+    fn start(&mut self, udc: &str) -> crate::Result<()> {
+        let mount = self.mount.clone().ok_or(Error::Other)?;
+
+        let mut ep0 = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(mount.join("ep0"))
+            .map_err(|e| error::from_io_error(&e))?;
+        ep0.write_all(&functionfs_descriptors()?)
+            .map_err(|e| error::from_io_error(&e))?;
+        ep0.write_all(&functionfs_strings())
+            .map_err(|e| error::from_io_error(&e))?;
+
+        self.control = Some(thread::spawn(move || control(ep0, mount)));
+
+        write(&self.gadget.join("UDC"), udc)
+    }
+}
+
+impl Drop for Loopback {
+    fn drop(&mut self) {
+        // unbinding ends the control thread, which stops the echo once the endpoints are disabled
+        if self.gadget.join("UDC").exists() {
+            fs::write(self.gadget.join("UDC"), "\n").ok();
+        }
+        if let Some(control) = self.control.take() {
+            control.join().ok();
+        }
+
+        if let Some(mount) = self.mount.take() {
+            if let Ok(path) = CString::new(mount.as_os_str().as_bytes()) {
+                unsafe { libc::umount2(path.as_ptr(), libc::MNT_DETACH) };
+            }
+            fs::remove_dir(&mount).ok();
+        }
+
+        let function = format!("ffs.{}", self.name);
+        let config = self.gadget.join("configs/c.1");
+        fs::remove_file(config.join(&function)).ok();
+        fs::remove_dir(config.join("strings/0x409")).ok();
+        fs::remove_dir(&config).ok();
+        fs::remove_dir(self.gadget.join("functions").join(&function)).ok();
+        fs::remove_dir(self.gadget.join("strings/0x409")).ok();
+        fs::remove_dir(&self.gadget).ok();
+    }
+}
+
+/// The host side of a [`Loopback`](struct.Loopback.html) gadget, with its interface claimed.
+pub struct LoopbackDevice<T: UsbContext> {
+    handle: DeviceHandle<T>,
+    endpoint_in: u8,
+    endpoint_out: u8,
+}
+
+impl<T: UsbContext> LoopbackDevice<T> {
+    /// Returns the handle of the gadget.
+    pub fn handle(&self) -> &DeviceHandle<T> {
+        &self.handle
+    }
+
+    /// Returns the address of the bulk IN endpoint.
+    pub fn endpoint_in(&self) -> u8 {
+        self.endpoint_in
+    }
+
+    /// Returns the address of the bulk OUT endpoint.
+    pub fn endpoint_out(&self) -> u8 {
+        self.endpoint_out
+    }
+
+    /// Sends `data` to the gadget and returns what it echoed.
+    ///
+    /// Writing and reading happen concurrently, so the gadget never blocks on a full IN endpoint
+    /// whatever the size of `data`. Each transfer waits up to `timeout`.
+    ///
+    /// ## Errors
+    ///
+    /// Returns the first error of the write or of the reads, e.g. `Timeout` if the gadget echoed
+    /// less data than it was sent.
+    #[cfg_attr(feature = "async-only", deprecated(note = "blocks the calling thread"))]
+    #[cfg_attr(feature = "async-only", allow(deprecated))]
+    pub fn round_trip(&self, data: &[u8], timeout: Duration) -> crate::Result<Vec<u8>> {
+        thread::scope(|scope| {
+            let writer = scope.spawn(|| self.handle.write_bulk(self.endpoint_out, data, timeout));
+
+            let mut echoed = vec![0; data.len()];
+            let mut received = 0;
+            let mut res = Ok(());
+            while received < data.len() {
+                match self
+                    .handle
+                    .read_bulk(self.endpoint_in, &mut echoed[received..], timeout)
+                {
+                    Ok(n) => received += n,
+                    Err(e) => {
+                        res = Err(e);
+                        break;
+                    }
+                }
+            }
+
+            let written = writer.join().map_err(|_| Error::Other)??;
+            res?;
+            if written != data.len() {
+                return Err(Error::Io);
+            }
+            Ok(echoed)
+        })
+    }
+}
+
+/// Handles the events of the function until it is unbound, running the echo while the
+/// endpoints are enabled.
+fn control(mut ep0: File, mount: PathBuf) {
+    let mut echo: Option<JoinHandle<()>> = None;
+    let mut events = [0u8; EVENT_SIZE * 4];
+
+    while let Ok(n) = ep0.read(&mut events) {
+        for event in events[..n].chunks_exact(EVENT_SIZE) {
+            match event[8] {
+                EVENT_ENABLE if echo.as_ref().map_or(true, |e| e.is_finished()) => {
+                    let mount = mount.clone();
+                    echo = Some(thread::spawn(move || run_echo(&mount)));
+                }
+                EVENT_UNBIND => return finish(echo),
+                _ => (),
+            }
+        }
+    }
+
+    finish(echo);
+}
+
+/// Waits briefly for the echo thread to notice that its endpoints were disabled.
+///
+/// A thread that was between two transfers when that happened waits for the endpoints to be
+/// enabled again, so it is left behind rather than blocking the teardown; the mount point is
+/// detached lazily for that reason.
+fn finish(echo: Option<JoinHandle<()>>) {
+    if let Some(echo) = echo {
+        let deadline = Instant::now() + Duration::from_secs(1);
+        while !echo.is_finished() && Instant::now() < deadline {
+            thread::sleep(Duration::from_millis(10));
+        }
+        if echo.is_finished() {
+            echo.join().ok();
+        }
+    }
+}
+
+/// Sends back everything received on the OUT endpoint, until either endpoint fails.
+fn run_echo(mount: &Path) {
+    // ep1 is the first endpoint of the descriptors (IN), ep2 the second (OUT)
+    let endpoints = (
+        OpenOptions::new().write(true).open(mount.join("ep1")),
+        OpenOptions::new().read(true).open(mount.join("ep2")),
+    );
+    let (mut ep_in, mut ep_out) = match endpoints {
+        (Ok(ep_in), Ok(ep_out)) => (ep_in, ep_out),
+        _ => return,
+    };
+
+    let mut buf = [0u8; ECHO_CHUNK];
+    loop {
+        let n = match ep_out.read(&mut buf) {
+            Ok(n) => n,
+            Err(_) => return,
+        };
+        if ep_in.write_all(&buf[..n]).is_err() {
+            return;
+        }
+    }
+}
+
+/// Returns the FunctionFS descriptors of the function, for full and high speed.
+fn functionfs_descriptors() -> crate::Result<Vec<u8>> {
+    let interface = |packet_size| {
+        InterfaceDescriptorBuilder::new(0, 0)
+            .class(0xff, 0, 0)
+            .string(1)
+            .endpoint(
+                EndpointDescriptorBuilder::new(0x81, TransferType::Bulk)
+                    .max_packet_size(packet_size),
+            )
+            .endpoint(
+                EndpointDescriptorBuilder::new(0x02, TransferType::Bulk)
+                    .max_packet_size(packet_size),
+            )
+            .build()
+    };
+    let full_speed = interface(64)?;
+    let high_speed = interface(512)?;
+
+    let mut blob = Vec::new();
+    for word in &[
+        DESCRIPTORS_MAGIC_V2,
+        0,
+        HAS_FS_DESC | HAS_HS_DESC,
+        count_descriptors(&full_speed),
+        count_descriptors(&high_speed),
+    ] {
+        blob.extend_from_slice(&word.to_le_bytes());
+    }
+    blob.extend_from_slice(&full_speed);
+    blob.extend_from_slice(&high_speed);
+
+    let len = (blob.len() as u32).to_le_bytes();
+    blob[4..8].copy_from_slice(&len);
+    Ok(blob)
+}
+
+/// Returns the FunctionFS strings of the function: the interface's name, in English.
+fn functionfs_strings() -> Vec<u8> {
+    let name = b"rusb loopback\0";
+
+    let mut blob = Vec::new();
+    for word in &[STRINGS_MAGIC, (16 + 2 + name.len()) as u32, 1, 1] {
+        blob.extend_from_slice(&word.to_le_bytes());
+    }
+    blob.extend_from_slice(&0x0409u16.to_le_bytes());
+    blob.extend_from_slice(name);
+    blob
+}
+
+fn count_descriptors(mut bytes: &[u8]) -> u32 {
+    let mut count = 0;
+    while !bytes.is_empty() {
+        count += 1;
+        bytes = &bytes[(bytes[0] as usize).max(1).min(bytes.len())..];
+    }
+    count
+}
+
+fn first_udc() -> crate::Result<String> {
+    let mut names: Vec<String> = fs::read_dir(UDC_CLASS)
+        .map_err(|e| error::from_io_error(&e))?
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.file_name().to_string_lossy().into_owned())
+        .collect();
+    names.sort();
+    names.into_iter().next().ok_or(Error::NotFound)
+}
+
+fn mount_functionfs(name: &str, mount: &Path) -> crate::Result<()> {
+    let source = CString::new(name).map_err(|_| Error::InvalidParam)?;
+    let target = CString::new(mount.as_os_str().as_bytes()).map_err(|_| Error::InvalidParam)?;
+    let fs_type = CString::new("functionfs").unwrap();
+
+    let res = unsafe {
+        libc::mount(
+            source.as_ptr(),
+            target.as_ptr(),
+            fs_type.as_ptr(),
+            0,
+            ptr::null(),
+        )
+    };
+    if res != 0 {
+        return Err(error::from_io_error(&std::io::Error::last_os_error()));
+    }
+    Ok(())
+}
+
+fn mkdir(path: &Path) -> crate::Result<()> {
+    fs::create_dir(path).map_err(|e| error::from_io_error(&e))
+}
+
+fn write(path: &Path, value: &str) -> crate::Result<()> {
+    fs::write(path, value).map_err(|e| error::from_io_error(&e))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn it_describes_the_function_for_both_speeds() {
+        let blob = functionfs_descriptors().unwrap();
+        let word = |i: usize| u32::from_le_bytes([blob[i], blob[i + 1], blob[i + 2], blob[i + 3]]);
+
+        assert_eq!(DESCRIPTORS_MAGIC_V2, word(0));
+        assert_eq!(blob.len() as u32, word(4));
+        assert_eq!(HAS_FS_DESC | HAS_HS_DESC, word(8));
+        assert_eq!((3, 3), (word(12), word(16)));
+        assert_eq!(20 + 2 * (9 + 7 + 7), blob.len());
+
+        // wMaxPacketSize of the first high speed endpoint
+        assert_eq!([0x00, 0x02], blob[20 + 23 + 9 + 4..20 + 23 + 9 + 6]);
+    }
+
+    #[test]
+    fn it_names_the_interface() {
+        let blob = functionfs_strings();
+
+        assert_eq!(
+            blob.len(),
+            u32::from_le_bytes([blob[4], blob[5], blob[6], blob[7]]) as usize
+        );
+        assert!(blob.ends_with(b"\x09\x04rusb loopback\0"));
+    }
+}
@@ -0,0 +1,309 @@
+//! Link power management (LPM) capabilities and settings.
+//!
+//! To save power, USB links enter low-power states between transfers: L1 on USB 2.0 links that
+//! support LPM, and U1 or U2 on SuperSpeed links. Leaving these states takes time, so they show
+//! up as latency spikes on interrupt and isochronous endpoints. [`LinkPowerCapabilities`]
+//! decodes what a device declares in its BOS descriptor, including its exit latencies. On Linux,
+//! the functions of this module also query and adjust what the kernel actually enables for a
+//! device, through sysfs.
+//!
+//! Changing these settings requires write access to sysfs, which normally means running as root.
+//! Failures to read or write the attributes are reported as `Error::Access` (permission denied),
+//! `Error::NotFound` (no such sysfs entry, e.g. the device doesn't support LPM or the kernel
+//! doesn't expose it) or `Error::Io`.
+
+use std::time::Duration;
+
+use crate::{device_handle::DeviceHandle, error::Error, UsbContext};
+
+#[cfg(target_os = "linux")]
+use std::{fs, path::PathBuf};
+
+#[cfg(target_os = "linux")]
+use crate::{authorization::sysfs_path, device::Device, error};
+
+const DT_DEVICE_CAPABILITY: u8 = 0x10;
+const CAP_USB_2_0_EXTENSION: u8 = 0x02;
+const CAP_SUPERSPEED_USB: u8 = 0x03;
+
+/// The exit latencies, in microseconds, of the 16 Best Effort Service Latency (BESL) values.
+const BESL_LATENCY_US: [u16; 16] = [
+    125, 150, 200, 300, 400, 500, 1000, 2000, 3000, 4000, 5000, 6000, 7000, 8000, 9000, 10000,
+];
+
+/// The link power management capabilities a device declares in its BOS descriptor.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, Hash)]
+pub struct LinkPowerCapabilities {
+    lpm: bool,
+    besl: bool,
+    baseline_besl: Option<u8>,
+    deep_besl: Option<u8>,
+    u1_exit_latency: Option<u8>,
+    u2_exit_latency: Option<u16>,
+}
+
+impl LinkPowerCapabilities {
+    /// Reads the capabilities of the device from its BOS descriptor.
+    ///
+    /// ## Errors
+    ///
+    /// * `Pipe` if the device has no BOS descriptor, e.g. because it declares USB 2.0 or earlier.
+    /// * `Other` if the BOS descriptor is malformed.
+    #[cfg_attr(feature = "async-only", deprecated(note = "blocks the calling thread"))]
+    #[cfg_attr(feature = "async-only", allow(deprecated))]
+    pub fn read<T: UsbContext>(
+        handle: &DeviceHandle<T>,
+        timeout: Duration,
+    ) -> crate::Result<LinkPowerCapabilities> {
+        let mut bos = Vec::new();
+        handle.read_bos_descriptor_raw(&mut bos, timeout)?;
+        LinkPowerCapabilities::parse(&bos)
+    }
+
+    /// Decodes the capabilities from a raw BOS descriptor.
+    ///
+    /// ## Errors
+    ///
+    /// * `Other` if the data is not a BOS descriptor or contains a descriptor whose length runs
+    ///   past the end.
+    pub fn parse(bos: &[u8]) -> crate::Result<LinkPowerCapabilities> {
+        if bos.len() < 5 || (bos[0] as usize) < 5 || bos[1] != crate::constants::LIBUSB_DT_BOS {
+            return Err(Error::Other);
+        }
+
+        let mut caps = LinkPowerCapabilities::default();
+
+        let mut rest = &bos[bos[0] as usize..];
+        while rest.len() >= 2 {
+            let len = rest[0] as usize;
+            if len < 2 || len > rest.len() {
+                return Err(Error::Other);
+            }
+            let (record, next) = rest.split_at(len);
+            rest = next;
+
+            if record[1] != DT_DEVICE_CAPABILITY || len < 3 {
+                continue;
+            }
+
+            match (record[2], record) {
+                (CAP_USB_2_0_EXTENSION, [_, _, _, a0, a1, ..]) => {
+                    let attributes = u16::from_le_bytes([*a0, *a1]);
+                    caps.lpm = attributes & 0x02 != 0;
+                    caps.besl = attributes & 0x04 != 0;
+                    if attributes & 0x08 != 0 {
+                        caps.baseline_besl = Some((attributes >> 8 & 0x0F) as u8);
+                    }
+                    if attributes & 0x10 != 0 {
+                        caps.deep_besl = Some((attributes >> 12 & 0x0F) as u8);
+                    }
+                }
+                (CAP_SUPERSPEED_USB, [_, _, _, _, _, _, _, u1, u2_0, u2_1, ..]) => {
+                    caps.u1_exit_latency = Some(*u1);
+                    caps.u2_exit_latency = Some(u16::from_le_bytes([*u2_0, *u2_1]));
+                }
+                _ => {}
+            }
+        }
+
+        Ok(caps)
+    }
+
+    /// Indicates whether the device supports USB 2.0 link power management (the L1 state).
+    pub fn supports_lpm(&self) -> bool {
+        self.lpm
+    }
+
+    /// Indicates whether the device uses Best Effort Service Latency (BESL) values rather than
+    /// the older HIRD values.
+    pub fn supports_besl(&self) -> bool {
+        self.besl
+    }
+
+    /// Returns the recommended baseline BESL value, if the device declares one.
+    pub fn baseline_besl(&self) -> Option<u8> {
+        self.baseline_besl
+    }
+
+    /// Returns the recommended deep BESL value, if the device declares one.
+    pub fn deep_besl(&self) -> Option<u8> {
+        self.deep_besl
+    }
+
+    /// Returns the time the device takes to leave U1, if it is SuperSpeed capable.
+    pub fn u1_exit_latency(&self) -> Option<Duration> {
+        self.u1_exit_latency
+            .map(|us| Duration::from_micros(u64::from(us)))
+    }
+
+    /// Returns the time the device takes to leave U2, if it is SuperSpeed capable.
+    pub fn u2_exit_latency(&self) -> Option<Duration> {
+        self.u2_exit_latency
+            .map(|us| Duration::from_micros(u64::from(us)))
+    }
+}
+
+/// Returns the exit latency a Best Effort Service Latency (BESL) value stands for.
+///
+/// Only the low 4 bits of `besl` are used.
+pub fn besl_latency(besl: u8) -> Duration {
+    Duration::from_micros(u64::from(BESL_LATENCY_US[(besl & 0x0F) as usize]))
+}
+
+/// Indicates whether the kernel enables USB 2.0 hardware LPM for a device.
+#[cfg(target_os = "linux")]
+pub fn usb2_hardware_lpm<T: UsbContext>(device: &Device<T>) -> crate::Result<bool> {
+    read_enabled(power_path(device)?.join("usb2_hardware_lpm"))
+}
+
+/// Enables or disables USB 2.0 hardware LPM for a device.
+///
+/// Disabling it removes the L1 exit latency from every transfer, at the cost of power.
+#[cfg(target_os = "linux")]
+pub fn set_usb2_hardware_lpm<T: UsbContext>(
+    device: &Device<T>,
+    enabled: bool,
+) -> crate::Result<()> {
+    write_value(
+        power_path(device)?.join("usb2_hardware_lpm"),
+        if enabled { "y" } else { "n" },
+    )
+}
+
+/// Returns the BESL value the kernel uses for a device's L1 state.
+#[cfg(target_os = "linux")]
+pub fn usb2_lpm_besl<T: UsbContext>(device: &Device<T>) -> crate::Result<u8> {
+    read_number(power_path(device)?.join("usb2_lpm_besl"))
+}
+
+/// Sets the BESL value the kernel uses for a device's L1 state.
+#[cfg(target_os = "linux")]
+pub fn set_usb2_lpm_besl<T: UsbContext>(device: &Device<T>, besl: u8) -> crate::Result<()> {
+    write_value(power_path(device)?.join("usb2_lpm_besl"), &besl.to_string())
+}
+
+/// Returns how long a device's link stays idle before the host puts it in L1.
+#[cfg(target_os = "linux")]
+pub fn usb2_lpm_l1_timeout<T: UsbContext>(device: &Device<T>) -> crate::Result<Duration> {
+    let us: u64 = read_number(power_path(device)?.join("usb2_lpm_l1_timeout"))?;
+    Ok(Duration::from_micros(us))
+}
+
+/// Sets how long a device's link stays idle before the host puts it in L1.
+#[cfg(target_os = "linux")]
+pub fn set_usb2_lpm_l1_timeout<T: UsbContext>(
+    device: &Device<T>,
+    timeout: Duration,
+) -> crate::Result<()> {
+    write_value(
+        power_path(device)?.join("usb2_lpm_l1_timeout"),
+        &timeout.as_micros().to_string(),
+    )
+}
+
+/// Indicates whether the kernel enables the U1 and U2 states for a SuperSpeed device.
+///
+/// These attributes are read-only; U1 and U2 can be forbidden per port with the hub's
+/// `usb3_lpm_permit` attribute.
+#[cfg(target_os = "linux")]
+pub fn usb3_hardware_lpm<T: UsbContext>(device: &Device<T>) -> crate::Result<(bool, bool)> {
+    let power = power_path(device)?;
+    Ok((
+        read_enabled(power.join("usb3_hardware_lpm_u1"))?,
+        read_enabled(power.join("usb3_hardware_lpm_u2"))?,
+    ))
+}
+
+#[cfg(target_os = "linux")]
+fn power_path<T: UsbContext>(device: &Device<T>) -> crate::Result<PathBuf> {
+    Ok(sysfs_path(device)?.join("power"))
+}
+
+#[cfg(target_os = "linux")]
+fn read_enabled(path: PathBuf) -> crate::Result<bool> {
+    let value = fs::read_to_string(path).map_err(|e| error::from_io_error(&e))?;
+
+    parse_enabled(&value)
+}
+
+#[cfg(target_os = "linux")]
+fn read_number<N: std::str::FromStr>(path: PathBuf) -> crate::Result<N> {
+    let value = fs::read_to_string(path).map_err(|e| error::from_io_error(&e))?;
+
+    value.trim().parse().map_err(|_| Error::Other)
+}
+
+#[cfg(target_os = "linux")]
+fn write_value(path: PathBuf, value: &str) -> crate::Result<()> {
+    fs::write(path, value).map_err(|e| error::from_io_error(&e))
+}
+
+#[cfg(any(test, target_os = "linux"))]
+fn parse_enabled(value: &str) -> crate::Result<bool> {
+    match value.trim() {
+        "enabled" | "y" | "1" => Ok(true),
+        "disabled" | "n" | "0" => Ok(false),
+        _ => Err(Error::Other),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[rustfmt::skip]
+    const BOS: &[u8] = &[
+        // BOS, 22 bytes, 2 capabilities
+        0x05, 0x0F, 0x16, 0x00, 0x02,
+        // USB 2.0 extension: LPM, BESL, baseline BESL 4, deep BESL 10
+        0x07, 0x10, 0x02, 0x1E, 0xA4, 0x00, 0x00,
+        // SuperSpeed USB: U1 exit 10us, U2 exit 2047us
+        0x0A, 0x10, 0x03, 0x00, 0x0E, 0x00, 0x01, 0x0A, 0xFF, 0x07,
+    ];
+
+    #[test]
+    fn it_decodes_usb2_extension() {
+        let caps = LinkPowerCapabilities::parse(BOS).unwrap();
+
+        assert!(caps.supports_lpm());
+        assert!(caps.supports_besl());
+        assert_eq!(Some(4), caps.baseline_besl());
+        assert_eq!(Some(10), caps.deep_besl());
+    }
+
+    #[test]
+    fn it_decodes_superspeed_exit_latencies() {
+        let caps = LinkPowerCapabilities::parse(BOS).unwrap();
+
+        assert_eq!(Some(Duration::from_micros(10)), caps.u1_exit_latency());
+        assert_eq!(Some(Duration::from_micros(2047)), caps.u2_exit_latency());
+    }
+
+    #[test]
+    fn it_has_no_capabilities_without_capability_descriptors() {
+        let caps = LinkPowerCapabilities::parse(&BOS[..5]).unwrap();
+
+        assert_eq!(LinkPowerCapabilities::default(), caps);
+        assert_eq!(None, caps.u1_exit_latency());
+    }
+
+    #[test]
+    fn it_rejects_malformed_bos() {
+        assert!(LinkPowerCapabilities::parse(&BOS[..10]).is_err());
+        assert!(LinkPowerCapabilities::parse(&BOS[5..]).is_err());
+    }
+
+    #[test]
+    fn it_converts_besl_to_latency() {
+        assert_eq!(Duration::from_micros(125), besl_latency(0));
+        assert_eq!(Duration::from_micros(400), besl_latency(4));
+        assert_eq!(Duration::from_millis(10), besl_latency(15));
+    }
+
+    #[test]
+    fn it_parses_enabled_attributes() {
+        assert_eq!(Ok(true), parse_enabled("enabled\n"));
+        assert_eq!(Ok(false), parse_enabled("disabled\n"));
+        assert_eq!(Err(Error::Other), parse_enabled("maybe"));
+    }
+}
@@ -0,0 +1,551 @@
+use std::{
+    collections::BTreeMap,
+    fmt,
+    sync::{Arc, Mutex, MutexGuard},
+};
+
+use crate::{
+    context::{Context, UsbContext},
+    device::Device,
+    device_handle::DeviceHandle,
+    error::Error,
+    hotplug::{Hotplug, Registration},
+    options::UsbOption,
+};
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+/// Identifies a device by where it is plugged in and what it is, rather than by a `libusb`
+/// device, so that it can be found again in another context.
+///
+/// A device matches its id as long as it stays on the same port and keeps its vendor and product
+/// IDs, even if it was re-enumerated, the context was recreated or the program restarted. An id
+/// taken from an open device with [`of_handle`](#method.of_handle) also holds its serial number,
+/// so that another unit of the same product plugged into that port doesn't match.
+///
+/// With the `serde` feature, ids can be serialized, e.g. to remember a device in a configuration
+/// file.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct DeviceId {
+    bus_number: u8,
+    port_numbers: Vec<u8>,
+    vendor_id: u16,
+    product_id: u16,
+    #[cfg_attr(feature = "serde", serde(default))]
+    serial_number: Option<String>,
+}
+
+impl DeviceId {
+    /// Returns the id of `device`, without a serial number.
+    pub fn of<T: UsbContext>(device: &Device<T>) -> crate::Result<DeviceId> {
+        let descriptor = device.device_descriptor()?;
+
+        Ok(DeviceId {
+            bus_number: device.bus_number(),
+            port_numbers: device.port_numbers()?,
+            vendor_id: descriptor.vendor_id(),
+            product_id: descriptor.product_id(),
+            serial_number: None,
+        })
+    }
+
+    /// Returns the id of the device behind `handle`, including its serial number if it has one,
+    /// read in the first language the device lists.
+    ///
+    /// ## Errors
+    ///
+    /// * Any error reading the serial number of a device reporting one.
+    #[cfg_attr(feature = "async-only", deprecated(note = "blocks the calling thread"))]
+    #[cfg_attr(feature = "async-only", allow(deprecated))]
+    pub fn of_handle<T: UsbContext>(handle: &DeviceHandle<T>) -> crate::Result<DeviceId> {
+        let device = handle.device();
+        let descriptor = device.device_descriptor()?;
+
+        let serial_number = match descriptor.serial_number_string_index() {
+            Some(index) => Some(handle.strings().read(index)?),
+            None => None,
+        };
+
+        Ok(DeviceId {
+            serial_number,
+            ..DeviceId::of(&device)?
+        })
+    }
+
+    /// Returns the number of the bus the device is connected to.
+    pub fn bus_number(&self) -> u8 {
+        self.bus_number
+    }
+
+    /// Returns the port numbers from the root hub to the device.
+    pub fn port_numbers(&self) -> &[u8] {
+        &self.port_numbers
+    }
+
+    /// Returns the vendor ID of the device.
+    pub fn vendor_id(&self) -> u16 {
+        self.vendor_id
+    }
+
+    /// Returns the product ID of the device.
+    pub fn product_id(&self) -> u16 {
+        self.product_id
+    }
+
+    /// Returns the serial number of the device, if the id holds one.
+    pub fn serial_number(&self) -> Option<&str> {
+        self.serial_number.as_deref()
+    }
+
+    /// Looks for the device with this id in `context`.
+    ///
+    /// If the id holds a serial number, the device on its port is opened to read it, and closed
+    /// again. A device that can't be opened or whose serial number can't be read is skipped;
+    /// if no device matches, the first such error is returned rather than `None`, since the
+    /// device may well be the one that couldn't be checked.
+    #[cfg_attr(feature = "async-only", deprecated(note = "blocks the calling thread"))]
+    pub fn find<T: UsbContext>(&self, context: &T) -> crate::Result<Option<Device<T>>> {
+        Ok(self.locate(context)?.map(|(device, _)| device))
+    }
+
+    /// Looks for the device with this id in `context`, and opens it.
+    pub(crate) fn open<T: UsbContext>(&self, context: &T) -> crate::Result<DeviceHandle<T>> {
+        match self.locate(context)? {
+            Some((_, Some(handle))) => Ok(handle),
+            Some((device, None)) => device.open(),
+            None => Err(Error::NoDevice),
+        }
+    }
+
+    /// Returns the device matching the id, along with the handle opened to check its serial
+    /// number, if any.
+    #[cfg_attr(feature = "async-only", allow(deprecated))]
+    fn locate<T: UsbContext>(&self, context: &T) -> crate::Result<Option<Located<T>>> {
+        let devices = context.devices()?;
+        let candidates = devices.iter().filter(
+            |device| matches!(DeviceId::of(device), Ok(id) if self.same_port_and_product(&id)),
+        );
+
+        first_accepted(candidates, |device| {
+            let serial_number = match &self.serial_number {
+                Some(serial_number) => serial_number,
+                None => return Ok(Some(None)),
+            };
+            let handle = device.open()?;
+            if DeviceId::of_handle(&handle)?.serial_number() == Some(serial_number.as_str()) {
+                Ok(Some(Some(handle)))
+            } else {
+                Ok(None)
+            }
+        })
+    }
+
+    fn same_port_and_product(&self, other: &DeviceId) -> bool {
+        self.bus_number == other.bus_number
+            && self.port_numbers == other.port_numbers
+            && self.vendor_id == other.vendor_id
+            && self.product_id == other.product_id
+    }
+}
+
+/// A device matching an id, and the handle opened to check its serial number.
+type Located<T> = (Device<T>, Option<DeviceHandle<T>>);
+
+/// Returns the first of `candidates` that `check` accepts, with what `check` returned for it.
+///
+/// A candidate `check` fails for is skipped, and the first failure is returned if no candidate is
+/// accepted.
+fn first_accepted<C, A>(
+    candidates: impl IntoIterator<Item = C>,
+    mut check: impl FnMut(&C) -> crate::Result<Option<A>>,
+) -> crate::Result<Option<(C, A)>> {
+    let mut failure = None;
+    for candidate in candidates {
+        match check(&candidate) {
+            Ok(Some(accepted)) => return Ok(Some((candidate, accepted))),
+            Ok(None) => (),
+            Err(e) => {
+                failure.get_or_insert(e);
+            }
+        }
+    }
+
+    match failure {
+        Some(e) => Err(e),
+        None => Ok(None),
+    }
+}
+
+impl fmt::Display for DeviceId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.bus_number)?;
+        for (i, port) in self.port_numbers.iter().enumerate() {
+            write!(f, "{}{}", if i == 0 { '-' } else { '.' }, port)?;
+        }
+        write!(f, " {:04x}:{:04x}", self.vendor_id, self.product_id)?;
+        if let Some(serial_number) = &self.serial_number {
+            write!(f, " {}", serial_number)?;
+        }
+        Ok(())
+    }
+}
+
+/// A `libusb` context that can be torn down and rebuilt, for long-running daemons that must
+/// survive USB stack hiccups.
+///
+/// Once a context is broken, e.g. its event handling keeps failing with `Io`, every device and
+/// handle obtained from it is useless. [`recreate`](#method.recreate) replaces it with a new
+/// context created with the same options, moves the hotplug callbacks registered through the
+/// managed context over to it, and reopens the devices opened with [`open`](#method.open).
+///
+/// Devices and handles obtained before a recreation keep the old context alive until they are
+/// dropped; they should be replaced by the ones from the new context, whose
+/// [`generation`](#method.generation) tells them apart.
+pub struct ManagedContext {
+    options: Vec<UsbOption>,
+    state: Mutex<State>,
+}
+
+struct State {
+    context: Context,
+    generation: u64,
+    callbacks: BTreeMap<u64, Callback>,
+    next_callback: u64,
+    sessions: Vec<DeviceId>,
+}
+
+struct Callback {
+    vendor_id: Option<u16>,
+    product_id: Option<u16>,
+    class: Option<u8>,
+    hotplug: Arc<Mutex<Box<dyn Hotplug<Context> + Send>>>,
+    registration: Registration<Context>,
+}
+
+/// Identifies a hotplug callback registered on a [`ManagedContext`](struct.ManagedContext.html).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ManagedRegistration(u64);
+
+impl ManagedContext {
+    /// Creates a managed context.
+    pub fn new() -> crate::Result<ManagedContext> {
+        ManagedContext::with_options(Vec::new())
+    }
+
+    /// Creates a managed context whose contexts are created with `options`.
+    pub fn with_options(options: Vec<UsbOption>) -> crate::Result<ManagedContext> {
+        let context = Context::with_options(&options)?;
+
+        Ok(ManagedContext {
+            options,
+            state: Mutex::new(State {
+                context,
+                generation: 0,
+                callbacks: BTreeMap::new(),
+                next_callback: 0,
+                sessions: Vec::new(),
+            }),
+        })
+    }
+
+    /// Returns the current context.
+    pub fn context(&self) -> Context {
+        self.state().context.clone()
+    }
+
+    /// Returns the number of times the context was recreated.
+    pub fn generation(&self) -> u64 {
+        self.state().generation
+    }
+
+    /// Registers a hotplug callback, which keeps receiving events after the context is
+    /// recreated.
+    ///
+    /// Calls to the callback are serialized, but may come from contexts of different
+    /// generations, so it must be `Send`.
+    pub fn register_callback(
+        &self,
+        vendor_id: Option<u16>,
+        product_id: Option<u16>,
+        class: Option<u8>,
+        callback: Box<dyn Hotplug<Context> + Send>,
+    ) -> crate::Result<ManagedRegistration> {
+        let mut state = self.state();
+        let hotplug = Arc::new(Mutex::new(callback));
+        let registration = register(&state.context, vendor_id, product_id, class, &hotplug)?;
+
+        let key = state.next_callback;
+        state.next_callback += 1;
+        state.callbacks.insert(
+            key,
+            Callback {
+                vendor_id,
+                product_id,
+                class,
+                hotplug,
+                registration,
+            },
+        );
+
+        Ok(ManagedRegistration(key))
+    }
+
+    /// Deregisters a hotplug callback.
+    pub fn unregister_callback(&self, registration: ManagedRegistration) {
+        let mut state = self.state();
+        if let Some(callback) = state.callbacks.remove(&registration.0) {
+            state.context.unregister_callback(callback.registration);
+        }
+    }
+
+    /// Opens the device identified by `id`, and remembers to reopen it when the context is
+    /// recreated.
+    ///
+    /// ## Errors
+    ///
+    /// * `NoDevice` if no such device is attached; it is not remembered in that case.
+    /// * Any error returned when opening the device.
+    #[cfg_attr(feature = "async-only", deprecated(note = "blocks the calling thread"))]
+    #[cfg_attr(feature = "async-only", allow(deprecated))]
+    pub fn open(&self, id: &DeviceId) -> crate::Result<DeviceHandle<Context>> {
+        let mut state = self.state();
+        let handle = open(&state.context, id)?;
+
+        if !state.sessions.contains(id) {
+            state.sessions.push(id.clone());
+        }
+        Ok(handle)
+    }
+
+    /// Forgets a device opened with [`open`](#method.open), so it isn't reopened anymore.
+    /// Returns false if it wasn't opened.
+    pub fn close(&self, id: &DeviceId) -> bool {
+        let mut state = self.state();
+        let len = state.sessions.len();
+        state.sessions.retain(|session| session != id);
+        state.sessions.len() != len
+    }
+
+    /// Returns the ids of the devices reopened on recreation.
+    pub fn sessions(&self) -> Vec<DeviceId> {
+        self.state().sessions.clone()
+    }
+
+    /// Replaces the context with a new one, and returns the reopened devices.
+    ///
+    /// Hotplug callbacks are moved to the new context. Every device opened with
+    /// [`open`](#method.open) is reopened; the claims, alternate settings and other state of the
+    /// old handles are not restored. A device that can't be reopened, e.g. because it is gone,
+    /// is reported with its error and stays remembered, so the next recreation tries again.
+    ///
+    /// ## Errors
+    ///
+    /// Returns an error if the new context can't be created or a callback can't be registered
+    /// on it; the current context is kept in that case.
+    #[cfg_attr(feature = "async-only", deprecated(note = "blocks the calling thread"))]
+    #[cfg_attr(feature = "async-only", allow(deprecated))]
+    pub fn recreate(&self) -> crate::Result<Vec<(DeviceId, crate::Result<DeviceHandle<Context>>)>> {
+        let mut state = self.state();
+        let context = Context::with_options(&self.options)?;
+
+        let mut registrations = Vec::with_capacity(state.callbacks.len());
+        for callback in state.callbacks.values() {
+            match register(
+                &context,
+                callback.vendor_id,
+                callback.product_id,
+                callback.class,
+                &callback.hotplug,
+            ) {
+                Ok(registration) => registrations.push(registration),
+                Err(e) => {
+                    for registration in registrations {
+                        context.unregister_callback(registration);
+                    }
+                    return Err(e);
+                }
+            }
+        }
+
+        state.context = context;
+        for (callback, registration) in state.callbacks.values_mut().zip(registrations) {
+            // deregisters the callback from the old context
+            callback.registration = registration;
+        }
+        state.generation += 1;
+
+        let reopened = state
+            .sessions
+            .iter()
+            .map(|id| (id.clone(), open(&state.context, id)))
+            .collect();
+
+        Ok(reopened)
+    }
+
+    fn state(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(|p| p.into_inner())
+    }
+}
+
+impl fmt::Debug for ManagedContext {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let state = self.state();
+        f.debug_struct("ManagedContext")
+            .field("generation", &state.generation)
+            .field("callbacks", &state.callbacks.len())
+            .field("sessions", &state.sessions)
+            .finish()
+    }
+}
+
+fn register(
+    context: &Context,
+    vendor_id: Option<u16>,
+    product_id: Option<u16>,
+    class: Option<u8>,
+    hotplug: &Arc<Mutex<Box<dyn Hotplug<Context> + Send>>>,
+) -> crate::Result<Registration<Context>> {
+    let forward = Forward {
+        hotplug: hotplug.clone(),
+    };
+    context
+        .hotplug()
+        .matching(vendor_id, product_id, class)
+        .register(Box::new(forward))
+}
+
+#[cfg_attr(feature = "async-only", allow(deprecated))]
+fn open(context: &Context, id: &DeviceId) -> crate::Result<DeviceHandle<Context>> {
+    context.open_by_id(id)
+}
+
+/// Forwards the events of one context to a callback shared by all generations.
+struct Forward {
+    hotplug: Arc<Mutex<Box<dyn Hotplug<Context> + Send>>>,
+}
+
+impl Hotplug<Context> for Forward {
+    fn device_arrived(&mut self, device: Device<Context>) {
+        if let Ok(mut hotplug) = self.hotplug.lock() {
+            hotplug.device_arrived(device);
+        }
+    }
+
+    fn device_left(&mut self, device: Device<Context>) {
+        if let Ok(mut hotplug) = self.hotplug.lock() {
+            hotplug.device_left(device);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn it_formats_device_ids_like_sysfs() {
+        let id = DeviceId {
+            bus_number: 1,
+            port_numbers: vec![2, 4],
+            vendor_id: 0x1d6b,
+            product_id: 0x0104,
+            serial_number: None,
+        };
+
+        assert_eq!("1-2.4 1d6b:0104", id.to_string());
+    }
+
+    #[test]
+    fn it_formats_root_hub_ids() {
+        let id = DeviceId {
+            bus_number: 3,
+            port_numbers: vec![],
+            vendor_id: 0x1d6b,
+            product_id: 0x0002,
+            serial_number: None,
+        };
+
+        assert_eq!("3 1d6b:0002", id.to_string());
+    }
+
+    #[test]
+    fn it_formats_serial_numbers() {
+        let id = DeviceId {
+            bus_number: 1,
+            port_numbers: vec![3],
+            vendor_id: 0x0483,
+            product_id: 0x5740,
+            serial_number: Some("205A3592".to_owned()),
+        };
+
+        assert_eq!("1-3 0483:5740 205A3592", id.to_string());
+    }
+
+    #[test]
+    fn it_matches_ids_of_other_units_by_port_and_product() {
+        let id = DeviceId {
+            bus_number: 1,
+            port_numbers: vec![3],
+            vendor_id: 0x0483,
+            product_id: 0x5740,
+            serial_number: Some("205A3592".to_owned()),
+        };
+        let other = DeviceId {
+            serial_number: None,
+            ..id.clone()
+        };
+
+        assert!(id.same_port_and_product(&other));
+        assert!(!id.same_port_and_product(&DeviceId {
+            port_numbers: vec![4],
+            ..other
+        }));
+    }
+
+    #[test]
+    fn it_skips_candidates_that_cannot_be_checked() {
+        let located = first_accepted(vec![1, 2, 3], |&candidate| match candidate {
+            1 => Err(Error::Access),
+            2 => Ok(None),
+            _ => Ok(Some("serial")),
+        });
+
+        assert_eq!(Ok(Some((3, "serial"))), located);
+    }
+
+    #[test]
+    fn it_reports_why_candidates_could_not_be_checked() {
+        let located = first_accepted(vec![1, 2, 3], |&candidate| match candidate {
+            2 => Err(Error::Busy),
+            3 => Err(Error::Access),
+            _ => Ok(None::<()>),
+        });
+        assert_eq!(Err(Error::Busy), located);
+
+        assert_eq!(Ok(None), first_accepted(vec![1], |_| Ok(None::<()>)));
+    }
+
+    #[cfg(all(feature = "serde", feature = "toml"))]
+    #[test]
+    fn it_round_trips_through_serde() {
+        let id = DeviceId {
+            bus_number: 1,
+            port_numbers: vec![2, 4],
+            vendor_id: 0x0483,
+            product_id: 0x5740,
+            serial_number: Some("205A3592".to_owned()),
+        };
+        let without_serial = DeviceId {
+            serial_number: None,
+            ..id.clone()
+        };
+
+        for id in &[id, without_serial] {
+            let serialized = toml::to_string(id).unwrap();
+            assert_eq!(*id, toml::from_str::<DeviceId>(&serialized).unwrap());
+        }
+    }
+}
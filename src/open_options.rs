@@ -0,0 +1,155 @@
+use std::{collections::BTreeMap, sync::Mutex};
+
+use crate::error::Error;
+
+/// How a device is opened by [`Device::open_with`](struct.Device.html#method.open_with).
+///
+/// The modes are enforced by rusb, for the handles opened through it in this process; they don't
+/// stop other processes or code using `libusb` directly.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct OpenOptions {
+    read_only: bool,
+    exclusive: bool,
+}
+
+impl OpenOptions {
+    /// Creates options for a shareable handle that can read and write, like
+    /// [`Device::open`](struct.Device.html#method.open).
+    pub fn new() -> OpenOptions {
+        OpenOptions::default()
+    }
+
+    /// Refuses every operation that could change the state of the device, for diagnostic tools
+    /// that must guarantee they don't.
+    ///
+    /// The handle can still read from IN endpoints, perform IN control requests, claim and
+    /// release interfaces and read descriptors. Writing to OUT endpoints, OUT control requests,
+    /// changing the configuration or alternate settings, clearing halts and resetting the device
+    /// fail with `Access`.
+    ///
+    /// IN control requests are sent as is: a vendor request that changes the state of the device
+    /// despite reading data can't be told apart.
+    pub fn read_only(mut self, read_only: bool) -> Self {
+        self.read_only = read_only;
+        self
+    }
+
+    /// Makes the handle the only one opened on the device: opening fails with `Busy` while
+    /// another handle is open, and opening another handle fails with `Busy` while this one is.
+    ///
+    /// Otherwise the handle can be shared, like every handle opened with
+    /// [`Device::open`](struct.Device.html#method.open).
+    pub fn exclusive(mut self, exclusive: bool) -> Self {
+        self.exclusive = exclusive;
+        self
+    }
+
+    /// Returns whether the handle refuses operations changing the state of the device.
+    pub fn is_read_only(&self) -> bool {
+        self.read_only
+    }
+
+    /// Returns whether the handle is the only one opened on the device.
+    pub fn is_exclusive(&self) -> bool {
+        self.exclusive
+    }
+}
+
+/// Identifies an attached device across contexts: its bus number and address.
+type Key = (u8, u8);
+
+#[derive(Default)]
+struct Opened {
+    shared: usize,
+    exclusive: bool,
+}
+
+static OPENED: Mutex<BTreeMap<Key, Opened>> = Mutex::new(BTreeMap::new());
+
+/// Registers a handle opened on a device, until it is dropped.
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) struct OpenLock {
+    key: Key,
+    exclusive: bool,
+}
+
+impl OpenLock {
+    /// Registers a handle opened on the device at `address` on `bus`.
+    ///
+    /// Returns `Busy` if the device is opened exclusively, or if `exclusive` and the device is
+    /// already opened.
+    pub(crate) fn acquire(bus: u8, address: u8, exclusive: bool) -> crate::Result<OpenLock> {
+        let key = (bus, address);
+        let mut opened = OPENED.lock().unwrap_or_else(|p| p.into_inner());
+        let entry = opened.entry(key).or_default();
+
+        if entry.exclusive || (exclusive && entry.shared > 0) {
+            return Err(Error::Busy);
+        }
+        if exclusive {
+            entry.exclusive = true;
+        } else {
+            entry.shared += 1;
+        }
+
+        Ok(OpenLock { key, exclusive })
+    }
+}
+
+impl Drop for OpenLock {
+    fn drop(&mut self) {
+        let mut opened = OPENED.lock().unwrap_or_else(|p| p.into_inner());
+        if let Some(entry) = opened.get_mut(&self.key) {
+            if self.exclusive {
+                entry.exclusive = false;
+            } else {
+                entry.shared -= 1;
+            }
+            if !entry.exclusive && entry.shared == 0 {
+                opened.remove(&self.key);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    // every test uses its own bus, since the registry is shared by the tests running in parallel
+
+    #[test]
+    fn it_shares_devices_by_default() {
+        let first = OpenLock::acquire(201, 1, false).unwrap();
+        let second = OpenLock::acquire(201, 1, false).unwrap();
+
+        drop((first, second));
+        assert!(OpenLock::acquire(201, 1, true).is_ok());
+    }
+
+    #[test]
+    fn it_refuses_exclusive_opens_of_open_devices() {
+        let _shared = OpenLock::acquire(202, 1, false).unwrap();
+
+        assert_eq!(Error::Busy, OpenLock::acquire(202, 1, true).unwrap_err());
+    }
+
+    #[test]
+    fn it_refuses_opens_of_exclusively_open_devices() {
+        let exclusive = OpenLock::acquire(203, 1, true).unwrap();
+
+        assert_eq!(Error::Busy, OpenLock::acquire(203, 1, false).unwrap_err());
+        assert!(OpenLock::acquire(203, 2, true).is_ok());
+
+        drop(exclusive);
+        assert!(OpenLock::acquire(203, 1, false).is_ok());
+    }
+
+    #[test]
+    fn it_builds_options() {
+        let options = OpenOptions::new().read_only(true).exclusive(true);
+
+        assert!(options.is_read_only() && options.is_exclusive());
+        assert_eq!(OpenOptions::default(), OpenOptions::new());
+    }
+}
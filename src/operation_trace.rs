@@ -0,0 +1,237 @@
+//! A short in-memory history of the operations performed through a device handle.
+
+use std::{
+    collections::VecDeque,
+    fmt,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use crate::error::Error;
+
+/// The number of operations a handle remembers unless configured otherwise.
+pub(crate) const DEFAULT_CAPACITY: usize = 32;
+
+const MAX_ARGS: usize = 5;
+
+/// An operation recorded in a handle's trace.
+///
+/// Returned by [`DeviceHandle::trace`](struct.DeviceHandle.html#method.trace).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TraceEntry {
+    operation: &'static str,
+    args: [Option<(&'static str, u64)>; MAX_ARGS],
+    result: Result<usize, Error>,
+    started: Instant,
+    duration: Duration,
+}
+
+impl TraceEntry {
+    pub(crate) fn new(operation: &'static str, started: Instant) -> TraceEntry {
+        TraceEntry {
+            operation,
+            args: [None; MAX_ARGS],
+            result: Ok(0),
+            started,
+            duration: started.elapsed(),
+        }
+    }
+
+    /// Adds an argument to the summary. Arguments past the fifth are ignored.
+    pub(crate) fn arg(mut self, name: &'static str, value: u64) -> TraceEntry {
+        if let Some(slot) = self.args.iter_mut().find(|a| a.is_none()) {
+            *slot = Some((name, value));
+        }
+        self
+    }
+
+    pub(crate) fn result<T>(mut self, res: &crate::Result<T>, length: usize) -> TraceEntry {
+        self.result = match res {
+            Ok(_) => Ok(length),
+            Err(e) => Err(*e),
+        };
+        self
+    }
+
+    /// Returns the name of the operation, e.g. `"bulk_read"` or `"claim_interface"`.
+    pub fn operation(&self) -> &'static str {
+        self.operation
+    }
+
+    /// Returns the arguments of the operation, as name and value pairs.
+    pub fn args(&self) -> impl Iterator<Item = (&'static str, u64)> + '_ {
+        self.args.iter().flatten().copied()
+    }
+
+    /// Returns the outcome of the operation: the number of bytes transferred, or the error.
+    pub fn outcome(&self) -> Result<usize, Error> {
+        self.result
+    }
+
+    /// Returns the time at which the operation started.
+    pub fn started(&self) -> Instant {
+        self.started
+    }
+
+    /// Returns how long the operation took.
+    pub fn duration(&self) -> Duration {
+        self.duration
+    }
+}
+
+impl fmt::Display for TraceEntry {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}(", self.operation)?;
+        for (i, (name, value)) in self.args().enumerate() {
+            if i > 0 {
+                f.write_str(", ")?;
+            }
+            if name == "endpoint" || name == "request_type" {
+                write!(f, "{}=0x{:02x}", name, value)?;
+            } else {
+                write!(f, "{}={}", name, value)?;
+            }
+        }
+        f.write_str(") -> ")?;
+        match self.result {
+            Ok(n) => write!(f, "Ok({})", n)?,
+            Err(e) => write!(f, "Err({})", e)?,
+        }
+        write!(f, " in {}us", self.duration.as_micros())
+    }
+}
+
+/// The bounded ring of recent operations kept by a handle.
+pub(crate) struct OperationTrace {
+    ring: Mutex<Ring>,
+}
+
+struct Ring {
+    capacity: usize,
+    entries: VecDeque<TraceEntry>,
+}
+
+impl OperationTrace {
+    pub(crate) fn new(capacity: usize) -> OperationTrace {
+        OperationTrace {
+            ring: Mutex::new(Ring {
+                capacity,
+                entries: VecDeque::with_capacity(capacity),
+            }),
+        }
+    }
+
+    /// Indicates whether operations are being recorded.
+    pub(crate) fn is_enabled(&self) -> bool {
+        self.lock().capacity > 0
+    }
+
+    pub(crate) fn push(&self, entry: TraceEntry) {
+        let mut ring = self.lock();
+        if ring.capacity == 0 {
+            return;
+        }
+        while ring.entries.len() >= ring.capacity {
+            ring.entries.pop_front();
+        }
+        ring.entries.push_back(entry);
+    }
+
+    pub(crate) fn set_capacity(&self, capacity: usize) {
+        let mut ring = self.lock();
+        ring.capacity = capacity;
+        while ring.entries.len() > capacity {
+            ring.entries.pop_front();
+        }
+    }
+
+    pub(crate) fn entries(&self) -> Vec<TraceEntry> {
+        self.lock().entries.iter().cloned().collect()
+    }
+
+    pub(crate) fn clear(&self) {
+        self.lock().entries.clear();
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Ring> {
+        self.ring
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+/// Formats entries one per line, oldest first.
+pub(crate) fn format(entries: &[TraceEntry]) -> String {
+    let mut dump = String::new();
+    let last = entries.last().map(|e| e.started);
+
+    for entry in entries {
+        let ago = last
+            .map(|l| l.saturating_duration_since(entry.started))
+            .unwrap_or_default();
+        dump.push_str(&format!("-{:>8}us  {}\n", ago.as_micros(), entry));
+    }
+    dump
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn entry(n: u64) -> TraceEntry {
+        TraceEntry::new("bulk_read", Instant::now())
+            .arg("endpoint", 0x81)
+            .arg("requested", n)
+            .result(&Ok(()), n as usize)
+    }
+
+    #[test]
+    fn it_keeps_the_last_entries() {
+        let trace = OperationTrace::new(3);
+        for n in 0..5 {
+            trace.push(entry(n));
+        }
+
+        let requested: Vec<u64> = trace
+            .entries()
+            .iter()
+            .map(|e| e.args().nth(1).unwrap().1)
+            .collect();
+        assert_eq!(vec![2, 3, 4], requested);
+    }
+
+    #[test]
+    fn it_records_nothing_without_capacity() {
+        let trace = OperationTrace::new(0);
+        trace.push(entry(1));
+
+        assert!(!trace.is_enabled());
+        assert!(trace.entries().is_empty());
+    }
+
+    #[test]
+    fn it_shrinks() {
+        let trace = OperationTrace::new(4);
+        for n in 0..4 {
+            trace.push(entry(n));
+        }
+
+        trace.set_capacity(1);
+        assert_eq!(1, trace.entries().len());
+        assert_eq!(Some(("requested", 3)), trace.entries()[0].args().nth(1));
+    }
+
+    #[test]
+    fn it_formats_entries() {
+        let failed = TraceEntry::new("write_control", Instant::now())
+            .arg("request_type", 0x40)
+            .arg("request", 9)
+            .result::<()>(&Err(Error::Pipe), 0);
+
+        let text = failed.to_string();
+        assert!(
+            text.starts_with("write_control(request_type=0x40, request=9) -> Err(Pipe error) in ")
+        );
+        assert_eq!(2, format(&[entry(1), failed]).lines().count());
+    }
+}
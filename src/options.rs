@@ -1,12 +1,61 @@
-use crate::{error, UsbContext};
-use libusb1_sys::{constants::*, libusb_set_option};
+use std::{ptr, sync::Arc};
+
+use crate::{
+    buffer_allocator, error, secure_buffer::SecureAllocator, BufferAllocator, LogLevel, UsbContext,
+};
+use libusb1_sys::{constants::*, libusb_context, libusb_set_option};
+
+// available since libusb 1.0.22, but not bound by libusb1-sys
+const LIBUSB_OPTION_NO_DEVICE_DISCOVERY: u32 = 2;
 
 /// A `libusb` runtime option that can be enabled for a context.
+///
+/// `libusb` has no option controlling which kinds of devices are enumerated. In particular,
+/// nothing needs to be enabled to see hubs on Windows: the default WinUSB backend lists external
+/// hubs, as well as a root hub for each host controller, alongside other devices, so the device
+/// tree can be rebuilt from the bus and port numbers of each device as on other platforms.
+#[derive(Clone)]
 pub struct UsbOption {
     inner: OptionInner,
 }
 
 impl UsbOption {
+    /// Sets the level of the messages `libusb` prints for the context.
+    ///
+    /// Unlike [`UsbContext::set_log_level`](trait.UsbContext.html#method.set_log_level), which
+    /// can only be called on a context once it is created, this also covers the messages printed
+    /// while [`Context::with_options`](struct.Context.html#method.with_options) sets the other
+    /// options. The `LIBUSB_DEBUG` environment variable still takes precedence.
+    pub fn log_level(level: LogLevel) -> Self {
+        Self {
+            inner: OptionInner::LogLevel(level),
+        }
+    }
+
+    /// Allocates the buffers rusb needs for the context's transfers and descriptors with
+    /// `allocator`, see [`BufferAllocator`](trait.BufferAllocator.html).
+    ///
+    /// The allocator is dropped with the context.
+    pub fn buffer_allocator(allocator: Arc<dyn BufferAllocator>) -> Self {
+        Self {
+            inner: OptionInner::BufferAllocator(allocator),
+        }
+    }
+
+    /// Allocates the buffers rusb needs for the context's transfers and descriptors locked in
+    /// memory, and zeroes them once rusb no longer uses them, like a
+    /// [`SecureBuffer`](struct.SecureBuffer.html).
+    ///
+    /// This installs a [`BufferAllocator`](trait.BufferAllocator.html) pooling the locked buffers,
+    /// in place of any set with [`buffer_allocator`](#method.buffer_allocator). Locking is best
+    /// effort, since allocations can't fail: past the process's `RLIMIT_MEMLOCK` limit, buffers
+    /// are only zeroed. Buffers handed over to the application, e.g. the data returned by a
+    /// `ReadFuture`, are the application's to wipe. For device memory, see
+    /// [`DeviceHandle::alloc_secure_transfer_buffer`](struct.DeviceHandle.html#method.alloc_secure_transfer_buffer).
+    pub fn secure_buffers() -> Self {
+        Self::buffer_allocator(Arc::new(SecureAllocator::default()))
+    }
+
     /// Use the [UsbDk] backend if available.
     ///
     /// **Note**: This method is available on **Windows** only!
@@ -19,21 +68,73 @@ impl UsbOption {
         }
     }
 
-    pub(crate) fn apply<T: UsbContext>(&self, ctx: &mut T) -> crate::Result<()> {
+    /// Don't scan for devices when the context is created.
+    ///
+    /// Required to open devices with
+    /// [`UsbContext::open_device_with_fd`](trait.UsbContext.html#method.open_device_with_fd) when
+    /// the process can't enumerate the USB buses itself, e.g. an unprivileged Android application
+    /// that was handed a file descriptor by the USB manager. Devices can't be listed and hotplug
+    /// notifications aren't delivered on such a context.
+    ///
+    /// `libusb` only honours this option before a context is initialized, so
+    /// [`Context::with_options`](struct.Context.html#method.with_options) sets it as a default
+    /// option: it also applies to every context created afterwards in the process, and can't be
+    /// reset. Only the Linux backend supports it.
+    pub fn no_device_discovery() -> Self {
+        Self {
+            inner: OptionInner::NoDeviceDiscovery,
+        }
+    }
+
+    /// Indicates whether the option must be set before the context is initialized.
+    pub(crate) fn is_default(&self) -> bool {
         match self.inner {
-            OptionInner::UseUsbdk => {
-                let err = unsafe { libusb_set_option(ctx.as_raw(), LIBUSB_OPTION_USE_USBDK) };
-                if err == LIBUSB_SUCCESS {
-                    Ok(())
-                } else {
-                    Err(error::from_libusb(err))
+            OptionInner::LogLevel(_) | OptionInner::BufferAllocator(_) | OptionInner::UseUsbdk => {
+                false
+            }
+            OptionInner::NoDeviceDiscovery => true,
+        }
+    }
+
+    /// Sets the option for the contexts initialized from now on.
+    pub(crate) fn apply_default(&self) -> crate::Result<()> {
+        self.set(ptr::null_mut())
+    }
+
+    pub(crate) fn apply<T: UsbContext>(&self, ctx: &mut T) -> crate::Result<()> {
+        self.set(ctx.as_raw())
+    }
+
+    fn set(&self, ctx: *mut libusb_context) -> crate::Result<()> {
+        let err = unsafe {
+            match self.inner {
+                OptionInner::LogLevel(level) => {
+                    libusb_set_option(ctx, LIBUSB_OPTION_LOG_LEVEL, level.as_c_int())
+                }
+                // not a libusb option
+                OptionInner::BufferAllocator(ref allocator) => {
+                    buffer_allocator::set(ctx, Some(allocator.clone()));
+                    LIBUSB_SUCCESS
+                }
+                OptionInner::UseUsbdk => libusb_set_option(ctx, LIBUSB_OPTION_USE_USBDK),
+                OptionInner::NoDeviceDiscovery => {
+                    libusb_set_option(ctx, LIBUSB_OPTION_NO_DEVICE_DISCOVERY)
                 }
             }
+        };
+        if err == LIBUSB_SUCCESS {
+            Ok(())
+        } else {
+            Err(error::from_libusb(err))
         }
     }
 }
 
+#[derive(Clone)]
 enum OptionInner {
+    LogLevel(LogLevel),
+    BufferAllocator(Arc<dyn BufferAllocator>),
     #[cfg_attr(not(windows), allow(dead_code))] // only constructed on Windows
     UseUsbdk,
+    NoDeviceDiscovery,
 }
@@ -0,0 +1,325 @@
+use std::{
+    thread,
+    time::{Duration, Instant},
+};
+
+use libusb1_sys::constants::*;
+
+use crate::{
+    async_io::{AsyncGroup, Transfer, TransferStatus},
+    Context, DeviceHandle, Error, Result, UsbContext,
+};
+
+/// Schedules periodic work on absolute deadlines.
+///
+/// Sleeping for the period after each iteration adds the time spent working, and the sleep's own
+/// overshoot, to every period, so the rate drifts. The pacer instead computes each deadline from
+/// the first one, `start + n * period`, so lateness in one iteration is caught up by the next
+/// instead of accumulating.
+///
+/// When an iteration is so late that whole periods went by, the missed deadlines are skipped
+/// rather than run in a burst, and counted in [`Tick::skipped`](struct.Tick.html#method.skipped).
+/// Isochronous OUT streams, which need their own transfers, can be paced with
+/// [`wait`](#method.wait) directly; [`PacedWriter`](struct.PacedWriter.html) does it for
+/// interrupt OUT reports.
+#[derive(Debug, Clone)]
+pub struct Pacer {
+    period: Duration,
+    start: Option<Instant>,
+    next: u64,
+    skipped: u64,
+}
+
+/// A deadline reached by a [`Pacer`](struct.Pacer.html).
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Tick {
+    index: u64,
+    deadline: Instant,
+    skipped: u64,
+}
+
+impl Tick {
+    /// Returns the number of the period this tick starts, counting from 0.
+    pub fn index(&self) -> u64 {
+        self.index
+    }
+
+    /// Returns the time at which this tick was due.
+    pub fn deadline(&self) -> Instant {
+        self.deadline
+    }
+
+    /// Returns the number of deadlines skipped right before this tick, because they were missed.
+    pub fn skipped(&self) -> u64 {
+        self.skipped
+    }
+}
+
+impl Pacer {
+    /// Creates a pacer whose first deadline is the first call to [`wait`](#method.wait).
+    ///
+    /// ## Panics
+    ///
+    /// Panics if `period` is zero.
+    pub fn new(period: Duration) -> Pacer {
+        assert!(period > Duration::ZERO, "the period must not be zero");
+
+        Pacer {
+            period,
+            start: None,
+            next: 0,
+            skipped: 0,
+        }
+    }
+
+    /// Returns the period between deadlines.
+    pub fn period(&self) -> Duration {
+        self.period
+    }
+
+    /// Returns the total number of deadlines skipped so far.
+    pub fn skipped(&self) -> u64 {
+        self.skipped
+    }
+
+    /// Starts again from the next call to [`wait`](#method.wait), e.g. after a pause.
+    pub fn reset(&mut self) {
+        self.start = None;
+        self.next = 0;
+    }
+
+    /// Sleeps until the next deadline, and returns it.
+    ///
+    /// Returns right away if the deadline has already passed.
+    pub fn wait(&mut self) -> Tick {
+        let tick = self.advance(Instant::now());
+
+        let now = Instant::now();
+        if tick.deadline > now {
+            thread::sleep(tick.deadline - now);
+        }
+        tick
+    }
+
+    /// Moves on to the next deadline, skipping the ones that were missed by a whole period as of
+    /// `now`.
+    fn advance(&mut self, now: Instant) -> Tick {
+        let start = *self.start.get_or_insert(now);
+
+        let due = now.saturating_duration_since(start).as_nanos() / self.period.as_nanos();
+        let due = due.min(u64::MAX as u128) as u64;
+
+        let mut skipped = 0;
+        if due > self.next {
+            skipped = due - self.next;
+            self.next = due;
+            self.skipped += skipped;
+        }
+
+        let index = self.next;
+        self.next += 1;
+
+        Tick {
+            index,
+            deadline: deadline(start, self.period, index),
+            skipped,
+        }
+    }
+}
+
+fn deadline(start: Instant, period: Duration, index: u64) -> Instant {
+    let offset = period.as_nanos() * u128::from(index);
+    start
+        + Duration::new(
+            (offset / 1_000_000_000) as u64,
+            (offset % 1_000_000_000) as u32,
+        )
+}
+
+/// Sends fixed-size interrupt OUT reports on a fixed period, e.g. to drive LED controllers or
+/// haptics from the host.
+///
+/// Each report is submitted at its [`Pacer`](struct.Pacer.html) deadline. Several transfers can
+/// be in flight, so a report that takes longer than a period to complete doesn't delay the next
+/// one; once they are all in flight, [`send`](#method.send) waits for the oldest to complete.
+pub struct PacedWriter<'d, T: UsbContext> {
+    group: AsyncGroup<'d, T>,
+    report_size: usize,
+    free: Vec<Transfer<'d, T>>,
+    in_flight: usize,
+    pacer: Pacer,
+}
+
+impl<'d, T: UsbContext> PacedWriter<'d, T> {
+    /// Prepares to send reports of `report_size` bytes to `endpoint` every `period`.
+    ///
+    /// Up to `buffer.len() / report_size` reports are in flight at once; any remainder of
+    /// `buffer` is left unused.
+    ///
+    /// ## Errors
+    ///
+    /// * `InvalidParam` if `endpoint` is not an OUT endpoint, `report_size` is zero, `buffer` is
+    ///   too small to hold a single report, or `period` is zero.
+    pub fn new(
+        context: &'d Context,
+        handle: &'d DeviceHandle<T>,
+        endpoint: u8,
+        buffer: &'d mut [u8],
+        report_size: usize,
+        period: Duration,
+        timeout: Duration,
+    ) -> Result<PacedWriter<'d, T>> {
+        if endpoint & LIBUSB_ENDPOINT_DIR_MASK != LIBUSB_ENDPOINT_OUT
+            || report_size == 0
+            || buffer.len() < report_size
+            || period == Duration::ZERO
+        {
+            return Err(Error::InvalidParam);
+        }
+
+        let free = buffer
+            .chunks_exact_mut(report_size)
+            .map(|chunk| Transfer::interrupt(handle, endpoint, chunk, timeout))
+            .collect();
+
+        Ok(PacedWriter {
+            group: AsyncGroup::new(context),
+            report_size,
+            free,
+            in_flight: 0,
+            pacer: Pacer::new(period),
+        })
+    }
+
+    /// Returns the pacer scheduling the reports, e.g. to check how many deadlines were missed.
+    pub fn pacer(&self) -> &Pacer {
+        &self.pacer
+    }
+
+    /// Returns the number of reports submitted but not completed yet.
+    pub fn in_flight(&self) -> usize {
+        self.in_flight
+    }
+
+    /// Waits for the next deadline and submits `report`.
+    ///
+    /// ## Errors
+    ///
+    /// * `InvalidParam` if `report` isn't `report_size` bytes long.
+    /// * The error of an earlier report that failed, reported by the call that had to wait for
+    ///   its transfer; `report` isn't sent in that case.
+    /// * Any error returned when submitting the transfer. The transfer is freed, so one less
+    ///   report can be in flight afterwards.
+    pub fn send(&mut self, report: &[u8]) -> Result<Tick> {
+        if report.len() != self.report_size {
+            return Err(Error::InvalidParam);
+        }
+
+        let mut transfer = match self.free.pop() {
+            Some(transfer) => transfer,
+            None => self.reclaim()?,
+        };
+        transfer.buffer().copy_from_slice(report);
+
+        let tick = self.pacer.wait();
+        self.group.submit(transfer)?;
+        self.in_flight += 1;
+        Ok(tick)
+    }
+
+    /// Waits for every report in flight to complete.
+    ///
+    /// ## Errors
+    ///
+    /// Returns the error of the first report that failed, after waiting for all of them.
+    pub fn flush(&mut self) -> Result<()> {
+        let mut res = Ok(());
+        while self.in_flight > 0 {
+            if let Err(e) = self.reclaim().map(|t| self.free.push(t)) {
+                res = res.and(Err(e));
+            }
+        }
+        res
+    }
+
+    /// Waits for the oldest report in flight, and returns its transfer for reuse.
+    fn reclaim(&mut self) -> Result<Transfer<'d, T>> {
+        let transfer = self.group.wait_any()?;
+        self.in_flight -= 1;
+
+        let error = match transfer.status() {
+            TransferStatus::Success => return Ok(transfer),
+            TransferStatus::Timeout => Error::Timeout,
+            TransferStatus::Stall => Error::Pipe,
+            TransferStatus::NoDevice => Error::NoDevice,
+            TransferStatus::Overflow => Error::Overflow,
+            TransferStatus::Cancelled => Error::Interrupted,
+            TransferStatus::Error | TransferStatus::Unknown => Error::Io,
+        };
+
+        self.free.push(transfer);
+        Err(error)
+    }
+}
+
+impl<'d, T: UsbContext> Drop for PacedWriter<'d, T> {
+    fn drop(&mut self) {
+        self.group.cancel_all().ok();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const PERIOD: Duration = Duration::from_millis(10);
+
+    #[test]
+    fn it_schedules_on_absolute_deadlines() {
+        let mut pacer = Pacer::new(PERIOD);
+        let start = Instant::now();
+
+        let first = pacer.advance(start);
+        assert_eq!((0, start), (first.index(), first.deadline()));
+
+        // a late iteration doesn't push the following deadlines back
+        let second = pacer.advance(start + Duration::from_millis(13));
+        assert_eq!(1, second.index());
+        assert_eq!(start + PERIOD, second.deadline());
+
+        let third = pacer.advance(start + Duration::from_millis(14));
+        assert_eq!(start + 2 * PERIOD, third.deadline());
+        assert_eq!(0, third.skipped());
+    }
+
+    #[test]
+    fn it_skips_missed_deadlines() {
+        let mut pacer = Pacer::new(PERIOD);
+        let start = Instant::now();
+        pacer.advance(start);
+
+        let tick = pacer.advance(start + Duration::from_millis(45));
+        assert_eq!(4, tick.index());
+        assert_eq!(3, tick.skipped());
+        assert_eq!(start + 4 * PERIOD, tick.deadline());
+        assert_eq!(3, pacer.skipped());
+
+        assert_eq!(5, pacer.advance(start + Duration::from_millis(46)).index());
+    }
+
+    #[test]
+    fn it_restarts_after_reset() {
+        let mut pacer = Pacer::new(PERIOD);
+        let start = Instant::now();
+        pacer.advance(start);
+        pacer.advance(start);
+
+        pacer.reset();
+        let later = start + Duration::from_secs(1);
+        let tick = pacer.advance(later);
+        assert_eq!(
+            (0, later, 0),
+            (tick.index(), tick.deadline(), tick.skipped())
+        );
+    }
+}
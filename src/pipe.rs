@@ -0,0 +1,667 @@
+use std::{
+    cell::RefCell,
+    ops::DerefMut,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::{self, Receiver, RecvTimeoutError, Sender, TryRecvError},
+        Arc,
+    },
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+use libusb1_sys::constants::*;
+
+use crate::{
+    device_io::DeviceIo, endpoint_descriptor::EndpointDescriptor, error::Error,
+    fields::TransferType, secure_buffer::SecureBuffer,
+};
+
+/// The transfer size used when neither it nor the endpoint's burst size is known.
+const DEFAULT_TRANSFER_SIZE: usize = 16 * 1024;
+
+/// A processing stage applied to data received on an [`InPipe`](struct.InPipe.html).
+///
+/// A transform receives each completed transfer's data and returns the data to pass on, `None` to
+/// drop it, or an error to deliver to the consumer in its place. Transforms can keep state between
+/// calls, e.g. to reassemble frames that span several transfers.
+///
+/// The trait is implemented for closures of the matching signature.
+pub trait Transform: Send {
+    /// Processes one buffer of received data.
+    fn apply(&mut self, data: Vec<u8>) -> crate::Result<Option<Vec<u8>>>;
+}
+
+impl<F> Transform for F
+where
+    F: FnMut(Vec<u8>) -> crate::Result<Option<Vec<u8>>> + Send,
+{
+    fn apply(&mut self, data: Vec<u8>) -> crate::Result<Option<Vec<u8>>> {
+        self(data)
+    }
+}
+
+/// What an [`InPipe`](struct.InPipe.html) does when the device sends more data than a read can
+/// hold.
+///
+/// The data of the overflowing transfer is lost in every case, and `Error::Overflow` is delivered
+/// in its place.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum OnOverflow {
+    /// Stop the pipe, as for any other fatal error.
+    Stop,
+
+    /// Keep reading with the same transfer size.
+    Continue,
+
+    /// Keep reading with a larger transfer size: the next multiple of the maximum packet size set
+    /// with [`max_packet_size`](struct.InPipeBuilder.html#method.max_packet_size), or twice the
+    /// current size if none was set.
+    Grow,
+}
+
+/// What an [`InPipe`](struct.InPipe.html) does with a report identical to the one received just
+/// before it.
+///
+/// Some devices, e.g. sensors and gamepads, keep sending their current state at a high rate even
+/// when it doesn't change.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum OnRepeat {
+    /// Deliver every report.
+    Deliver,
+
+    /// Drop the report, so the consumer only wakes up when the data changes. Repeats are compared
+    /// before the transform stages run.
+    Drop,
+}
+
+/// Configures and starts an [`InPipe`](struct.InPipe.html).
+pub struct InPipeBuilder {
+    endpoint: u8,
+    transfer_type: TransferType,
+    transfer_size: Option<usize>,
+    max_packet_size: Option<usize>,
+    burst_size: Option<usize>,
+    burst_multiplier: usize,
+    on_overflow: OnOverflow,
+    on_repeat: OnRepeat,
+    secure: bool,
+    timeout: Duration,
+    transforms: Vec<Box<dyn Transform>>,
+}
+
+impl InPipeBuilder {
+    /// Creates a builder for a pipe reading from the bulk IN `endpoint`.
+    pub fn bulk(endpoint: u8) -> InPipeBuilder {
+        InPipeBuilder::new(endpoint, TransferType::Bulk)
+    }
+
+    /// Creates a builder for a pipe reading from the interrupt IN `endpoint`.
+    pub fn interrupt(endpoint: u8) -> InPipeBuilder {
+        InPipeBuilder::new(endpoint, TransferType::Interrupt)
+    }
+
+    fn new(endpoint: u8, transfer_type: TransferType) -> InPipeBuilder {
+        InPipeBuilder {
+            endpoint,
+            transfer_type,
+            transfer_size: None,
+            max_packet_size: None,
+            burst_size: None,
+            burst_multiplier: 4,
+            on_overflow: OnOverflow::Stop,
+            on_repeat: OnRepeat::Deliver,
+            secure: false,
+            timeout: Duration::from_millis(100),
+            transforms: Vec::new(),
+        }
+    }
+
+    /// Sets the size of each read, overriding the size derived from the endpoint's burst size.
+    ///
+    /// Defaults to the burst size times the [burst multiplier](#method.burst_multiplier) for
+    /// SuperSpeed endpoints whose [descriptor](#method.endpoint_descriptor) was given, and to
+    /// 16KiB otherwise.
+    pub fn transfer_size(mut self, size: usize) -> InPipeBuilder {
+        self.transfer_size = Some(size);
+        self
+    }
+
+    /// Takes the maximum packet size and, for SuperSpeed endpoints, the burst size from the
+    /// endpoint's descriptor.
+    ///
+    /// Reads shorter than a few bursts leave a SuperSpeed endpoint idle between them, which is
+    /// the usual cause of poor USB 3 throughput, so unless a
+    /// [transfer size](#method.transfer_size) is set, each read is sized to the burst size
+    /// times the [burst multiplier](#method.burst_multiplier).
+    pub fn endpoint_descriptor(mut self, descriptor: &EndpointDescriptor<'_>) -> InPipeBuilder {
+        self.max_packet_size =
+            Some(usize::from(descriptor.max_packet_size() & 0x07FF)).filter(|&s| s > 0);
+        self.burst_size = descriptor
+            .max_burst()
+            .map(|_| descriptor.burst_size())
+            .filter(|&s| s > 0);
+        self
+    }
+
+    /// Sets how many bursts each read spans, when its size is derived from the endpoint's burst
+    /// size. Defaults to 4.
+    pub fn burst_multiplier(mut self, multiplier: usize) -> InPipeBuilder {
+        self.burst_multiplier = multiplier;
+        self
+    }
+
+    /// Sets the maximum packet size of the endpoint, as found in its
+    /// [`EndpointDescriptor`](struct.EndpointDescriptor.html).
+    ///
+    /// The transfer size is rounded up to a multiple of it, so a device sending full packets can't
+    /// overflow a read.
+    pub fn max_packet_size(mut self, size: usize) -> InPipeBuilder {
+        self.max_packet_size = Some(size).filter(|&s| s > 0);
+        self
+    }
+
+    /// Sets what happens when the device sends more data than a read can hold. Defaults to
+    /// [`OnOverflow::Stop`](enum.OnOverflow.html#variant.Stop).
+    pub fn on_overflow(mut self, policy: OnOverflow) -> InPipeBuilder {
+        self.on_overflow = policy;
+        self
+    }
+
+    /// Sets what happens to reports identical to the previous one. Defaults to
+    /// [`OnRepeat::Deliver`](enum.OnRepeat.html#variant.Deliver).
+    ///
+    /// To deliver repeats but handle them at once, see
+    /// [`InPipe::recv_coalesced`](struct.InPipe.html#method.recv_coalesced).
+    pub fn on_repeat(mut self, policy: OnRepeat) -> InPipeBuilder {
+        self.on_repeat = policy;
+        self
+    }
+
+    /// Reads into a [`SecureBuffer`](struct.SecureBuffer.html), locked in memory and zeroed when
+    /// the pipe stops, for endpoints carrying secrets.
+    ///
+    /// Only the buffer the device writes into is protected: the data delivered by the pipe is
+    /// copied out of it, and is the consumer's responsibility.
+    pub fn secure_buffer(mut self, secure: bool) -> InPipeBuilder {
+        self.secure = secure;
+        self
+    }
+
+    /// Sets the timeout of each read. Defaults to 100ms.
+    ///
+    /// Reads that time out without data are retried, so this only bounds how long dropping the
+    /// pipe waits for the reader thread to notice.
+    pub fn poll_timeout(mut self, timeout: Duration) -> InPipeBuilder {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Adds a transform stage. Stages are applied in the order they were added.
+    pub fn transform<X: Transform + 'static>(mut self, transform: X) -> InPipeBuilder {
+        self.transforms.push(Box::new(transform));
+        self
+    }
+
+    fn effective_transfer_size(&self) -> usize {
+        match (self.transfer_size, self.burst_size) {
+            (Some(size), _) => size,
+            (None, Some(burst)) => burst * self.burst_multiplier,
+            (None, None) => DEFAULT_TRANSFER_SIZE,
+        }
+    }
+
+    /// Starts reading from the endpoint.
+    ///
+    /// Data is read on a dedicated thread. When transforms are configured, they run on a second
+    /// worker thread so that slow transforms don't delay the next read.
+    ///
+    /// Returns `Error::InvalidParam` if the endpoint is not an IN endpoint or the transfer size
+    /// (or burst multiplier) is zero, and the error of
+    /// [`SecureBuffer::new`](struct.SecureBuffer.html#method.new) if a secure buffer can't be
+    /// allocated.
+    pub fn start<D>(self, device: Arc<D>) -> crate::Result<InPipe>
+    where
+        D: DeviceIo + Send + Sync + 'static,
+    {
+        let transfer_size = self.effective_transfer_size();
+        if self.endpoint & LIBUSB_ENDPOINT_DIR_MASK != LIBUSB_ENDPOINT_IN || transfer_size == 0 {
+            return Err(Error::InvalidParam);
+        }
+
+        let stop = Arc::new(AtomicBool::new(false));
+        let mut threads = Vec::new();
+
+        let (sender, receiver) = mpsc::channel();
+        let (reader_sender, transform_receiver) = if self.transforms.is_empty() {
+            (sender, None)
+        } else {
+            let (reader_sender, transform_receiver) = mpsc::channel();
+            (reader_sender, Some((transform_receiver, sender)))
+        };
+
+        let buffer = allocate(round_up(transfer_size, self.max_packet_size), self.secure)?;
+        let reader = Reader {
+            endpoint: self.endpoint,
+            transfer_type: self.transfer_type,
+            max_packet_size: self.max_packet_size,
+            on_overflow: self.on_overflow,
+            on_repeat: self.on_repeat,
+            secure: self.secure,
+            timeout: self.timeout,
+            stop: stop.clone(),
+        };
+        threads.push(thread::spawn(move || {
+            reader.run(&*device, buffer, reader_sender)
+        }));
+
+        if let Some((input, output)) = transform_receiver {
+            let mut transforms = self.transforms;
+            threads.push(thread::spawn(move || {
+                run_transforms(&mut transforms, input, output)
+            }));
+        }
+
+        Ok(InPipe {
+            receiver,
+            next: RefCell::new(None),
+            stop,
+            threads,
+        })
+    }
+}
+
+/// A continuously read IN endpoint delivering received data through a queue.
+///
+/// Data is read on a background thread from the moment the pipe is started until it is dropped or
+/// the endpoint fails. Each received buffer, after passing through the configured
+/// [`Transform`](trait.Transform.html) stages, is returned by [`recv`](#method.recv) in the order
+/// it was received. A fatal error (e.g. `NoDevice` or `Pipe`) is delivered once, after which the
+/// reader stops and the pipe reports `Error::NotFound`.
+pub struct InPipe {
+    receiver: Receiver<crate::Result<Vec<u8>>>,
+    /// Received by `recv_coalesced` while looking for repeats, and delivered next.
+    next: RefCell<Option<crate::Result<Vec<u8>>>>,
+    stop: Arc<AtomicBool>,
+    threads: Vec<JoinHandle<()>>,
+}
+
+impl InPipe {
+    /// Waits for the next buffer of data.
+    ///
+    /// Returns `Error::NotFound` once the pipe has stopped and all data has been received.
+    pub fn recv(&self) -> crate::Result<Vec<u8>> {
+        if let Some(data) = self.next.borrow_mut().take() {
+            return data;
+        }
+        self.receiver.recv().unwrap_or(Err(Error::NotFound))
+    }
+
+    /// Waits up to `timeout` for the next buffer of data.
+    ///
+    /// Returns `Error::Timeout` if no data arrived in time.
+    pub fn recv_timeout(&self, timeout: Duration) -> crate::Result<Vec<u8>> {
+        if let Some(data) = self.next.borrow_mut().take() {
+            return data;
+        }
+        match self.receiver.recv_timeout(timeout) {
+            Ok(data) => data,
+            Err(RecvTimeoutError::Timeout) => Err(Error::Timeout),
+            Err(RecvTimeoutError::Disconnected) => Err(Error::NotFound),
+        }
+    }
+
+    /// Returns the next buffer of data if one is available, without waiting.
+    pub fn try_recv(&self) -> Option<crate::Result<Vec<u8>>> {
+        if let Some(data) = self.next.borrow_mut().take() {
+            return Some(data);
+        }
+        match self.receiver.try_recv() {
+            Ok(data) => Some(data),
+            Err(TryRecvError::Empty) => None,
+            Err(TryRecvError::Disconnected) => Some(Err(Error::NotFound)),
+        }
+    }
+
+    /// Waits for the next buffer of data, and folds the identical buffers already queued after it
+    /// into it. Returns the data with the number of times it was received in a row.
+    ///
+    /// This doesn't wait for more repeats, so a consumer falling behind a device repeating its
+    /// state catches up in one call, without delaying changes.
+    pub fn recv_coalesced(&self) -> crate::Result<(Vec<u8>, usize)> {
+        let data = self.recv()?;
+        let mut count = 1;
+
+        loop {
+            match self.receiver.try_recv() {
+                Ok(Ok(ref next)) if *next == data => count += 1,
+                Ok(next) => {
+                    *self.next.borrow_mut() = Some(next);
+                    break;
+                }
+                Err(_) => break,
+            }
+        }
+
+        Ok((data, count))
+    }
+}
+
+impl Drop for InPipe {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::SeqCst);
+        for thread in self.threads.drain(..) {
+            thread.join().ok();
+        }
+    }
+}
+
+type ReadBuffer = Box<dyn DerefMut<Target = [u8]> + Send>;
+
+fn allocate(size: usize, secure: bool) -> crate::Result<ReadBuffer> {
+    if secure {
+        Ok(Box::new(SecureBuffer::new(size)?))
+    } else {
+        Ok(Box::new(vec![0u8; size]))
+    }
+}
+
+struct Reader {
+    endpoint: u8,
+    transfer_type: TransferType,
+    max_packet_size: Option<usize>,
+    on_overflow: OnOverflow,
+    on_repeat: OnRepeat,
+    secure: bool,
+    timeout: Duration,
+    stop: Arc<AtomicBool>,
+}
+
+impl Reader {
+    #[cfg_attr(feature = "async-only", allow(deprecated))]
+    fn run<D: DeviceIo>(
+        &self,
+        device: &D,
+        mut buf: ReadBuffer,
+        output: Sender<crate::Result<Vec<u8>>>,
+    ) {
+        let mut last: Option<Vec<u8>> = None;
+
+        while !self.stop.load(Ordering::SeqCst) {
+            let res = match self.transfer_type {
+                TransferType::Interrupt => {
+                    device.read_interrupt(self.endpoint, &mut buf, self.timeout)
+                }
+                _ => device.read_bulk(self.endpoint, &mut buf, self.timeout),
+            };
+
+            match res {
+                Ok(len) => {
+                    if self.on_repeat == OnRepeat::Drop {
+                        if last.as_deref() == Some(&buf[..len]) {
+                            continue;
+                        }
+                        last = Some(buf[..len].to_vec());
+                    }
+                    if output.send(Ok(buf[..len].to_vec())).is_err() {
+                        return;
+                    }
+                }
+                Err(Error::Timeout) | Err(Error::Interrupted) => thread::yield_now(),
+                Err(Error::Overflow) if self.on_overflow != OnOverflow::Stop => {
+                    if self.on_overflow == OnOverflow::Grow {
+                        let size = match self.max_packet_size {
+                            Some(_) => round_up(buf.len() + 1, self.max_packet_size),
+                            None => buf.len() * 2,
+                        };
+                        buf = match allocate(size, self.secure) {
+                            Ok(buf) => buf,
+                            Err(e) => {
+                                output.send(Err(e)).ok();
+                                return;
+                            }
+                        };
+                    }
+                    if output.send(Err(Error::Overflow)).is_err() {
+                        return;
+                    }
+                }
+                Err(e) => {
+                    output.send(Err(e)).ok();
+                    return;
+                }
+            }
+        }
+    }
+}
+
+/// Rounds `size` up to a multiple of the maximum packet size, if known.
+fn round_up(size: usize, max_packet_size: Option<usize>) -> usize {
+    match max_packet_size {
+        Some(packet) => (size + packet - 1) / packet * packet,
+        None => size,
+    }
+}
+
+fn run_transforms(
+    transforms: &mut [Box<dyn Transform>],
+    input: Receiver<crate::Result<Vec<u8>>>,
+    output: Sender<crate::Result<Vec<u8>>>,
+) {
+    for data in input {
+        let mut data = data.map(Some);
+
+        for transform in transforms.iter_mut() {
+            data = match data {
+                Ok(Some(d)) => transform.apply(d),
+                other => other,
+            };
+        }
+
+        let send = match data {
+            Ok(Some(d)) => output.send(Ok(d)),
+            Ok(None) => Ok(()),
+            Err(e) => output.send(Err(e)),
+        };
+        if send.is_err() {
+            return;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::fake::FakeDevice;
+
+    const TIMEOUT: Duration = Duration::from_secs(5);
+
+    #[test]
+    fn it_delivers_data_in_order() {
+        let device = Arc::new(FakeDevice::new());
+        device.push_in(0x81, &[1]);
+        device.push_in(0x81, &[2, 3]);
+
+        let pipe = InPipeBuilder::bulk(0x81).start(device).unwrap();
+
+        assert_eq!(Ok(vec![1]), pipe.recv_timeout(TIMEOUT));
+        assert_eq!(Ok(vec![2, 3]), pipe.recv_timeout(TIMEOUT));
+    }
+
+    #[test]
+    fn it_applies_transforms_in_order() {
+        let device = Arc::new(FakeDevice::new());
+        device.push_in(0x82, &[1, 2]);
+        device.push_in(0x82, &[]);
+        device.push_in(0x82, &[3]);
+
+        let pipe = InPipeBuilder::interrupt(0x82)
+            .transform(|data: Vec<u8>| {
+                if data.is_empty() {
+                    Ok(None)
+                } else {
+                    Ok(Some(data.iter().map(|b| b * 2).collect()))
+                }
+            })
+            .transform(|mut data: Vec<u8>| {
+                data.push(0xFF);
+                Ok(Some(data))
+            })
+            .start(device)
+            .unwrap();
+
+        assert_eq!(Ok(vec![2, 4, 0xFF]), pipe.recv_timeout(TIMEOUT));
+        assert_eq!(Ok(vec![6, 0xFF]), pipe.recv_timeout(TIMEOUT));
+    }
+
+    #[test]
+    fn it_delivers_fatal_errors_and_stops() {
+        let device = Arc::new(FakeDevice::new());
+        device.push_in(0x81, &[1]);
+        device.push_in_error(0x81, Error::NoDevice);
+
+        let pipe = InPipeBuilder::bulk(0x81).start(device).unwrap();
+
+        assert_eq!(Ok(vec![1]), pipe.recv_timeout(TIMEOUT));
+        assert_eq!(Err(Error::NoDevice), pipe.recv_timeout(TIMEOUT));
+        assert_eq!(Err(Error::NotFound), pipe.recv_timeout(TIMEOUT));
+    }
+
+    #[test]
+    fn it_rounds_transfer_size_to_max_packet_size() {
+        let device = Arc::new(FakeDevice::new());
+        device.push_in(0x81, &[0; 64]);
+
+        let pipe = InPipeBuilder::bulk(0x81)
+            .transfer_size(40)
+            .max_packet_size(64)
+            .start(device)
+            .unwrap();
+
+        assert_eq!(Ok(vec![0; 64]), pipe.recv_timeout(TIMEOUT));
+    }
+
+    #[test]
+    fn it_sizes_transfers_from_the_burst_size() {
+        let extra = [6, 0x30, 15, 0, 0, 0];
+        let descriptor = libusb1_sys::libusb_endpoint_descriptor {
+            extra: extra.as_ptr(),
+            extra_length: extra.len() as i32,
+            ..endpoint_descriptor!(bEndpointAddress: 0x81, bmAttributes: 0x02, wMaxPacketSize: 1024)
+        };
+        let descriptor = crate::endpoint_descriptor::from_libusb(&descriptor);
+
+        let builder = InPipeBuilder::bulk(0x81).endpoint_descriptor(&descriptor);
+        assert_eq!(Some(1024), builder.max_packet_size);
+        assert_eq!(64 * 1024, builder.effective_transfer_size());
+
+        let builder = builder.burst_multiplier(1);
+        assert_eq!(16 * 1024, builder.effective_transfer_size());
+
+        let builder = builder.transfer_size(4096);
+        assert_eq!(4096, builder.effective_transfer_size());
+    }
+
+    #[test]
+    fn it_keeps_the_default_transfer_size_below_superspeed() {
+        let descriptor =
+            endpoint_descriptor!(bEndpointAddress: 0x81, bmAttributes: 0x02, wMaxPacketSize: 512);
+        let descriptor = crate::endpoint_descriptor::from_libusb(&descriptor);
+
+        let builder = InPipeBuilder::bulk(0x81).endpoint_descriptor(&descriptor);
+
+        assert_eq!(Some(512), builder.max_packet_size);
+        assert_eq!(DEFAULT_TRANSFER_SIZE, builder.effective_transfer_size());
+    }
+
+    #[test]
+    fn it_grows_buffers_on_overflow() {
+        let device = Arc::new(FakeDevice::new());
+        device.push_in(0x81, &[1; 12]);
+        device.push_in(0x81, &[2; 12]);
+
+        let pipe = InPipeBuilder::bulk(0x81)
+            .transfer_size(8)
+            .on_overflow(OnOverflow::Grow)
+            .start(device)
+            .unwrap();
+
+        assert_eq!(Err(Error::Overflow), pipe.recv_timeout(TIMEOUT));
+        assert_eq!(Ok(vec![2; 12]), pipe.recv_timeout(TIMEOUT));
+    }
+
+    #[test]
+    fn it_stops_on_overflow_by_default() {
+        let device = Arc::new(FakeDevice::new());
+        device.push_in(0x81, &[1; 12]);
+
+        let pipe = InPipeBuilder::bulk(0x81)
+            .transfer_size(8)
+            .start(device)
+            .unwrap();
+
+        assert_eq!(Err(Error::Overflow), pipe.recv_timeout(TIMEOUT));
+        assert_eq!(Err(Error::NotFound), pipe.recv_timeout(TIMEOUT));
+    }
+
+    #[test]
+    fn it_drops_repeated_reports() {
+        let device = Arc::new(FakeDevice::new());
+        for report in &[[1], [1], [2], [2], [1]] {
+            device.push_in(0x81, report);
+        }
+
+        let pipe = InPipeBuilder::interrupt(0x81)
+            .on_repeat(OnRepeat::Drop)
+            .start(device)
+            .unwrap();
+
+        assert_eq!(Ok(vec![1]), pipe.recv_timeout(TIMEOUT));
+        assert_eq!(Ok(vec![2]), pipe.recv_timeout(TIMEOUT));
+        assert_eq!(Ok(vec![1]), pipe.recv_timeout(TIMEOUT));
+    }
+
+    #[test]
+    fn it_coalesces_queued_repeats() {
+        let (sender, receiver) = mpsc::channel();
+        for report in [Ok(vec![1]), Ok(vec![1]), Ok(vec![1]), Ok(vec![2])] {
+            sender.send(report).unwrap();
+        }
+        sender.send(Err(Error::NoDevice)).unwrap();
+        drop(sender);
+
+        let pipe = InPipe {
+            receiver,
+            next: RefCell::new(None),
+            stop: Arc::new(AtomicBool::new(false)),
+            threads: Vec::new(),
+        };
+
+        assert_eq!(Ok((vec![1], 3)), pipe.recv_coalesced());
+        assert_eq!(Ok((vec![2], 1)), pipe.recv_coalesced());
+        assert_eq!(Err(Error::NoDevice), pipe.recv_coalesced());
+        assert_eq!(Err(Error::NotFound), pipe.recv());
+    }
+
+    #[test]
+    fn it_reads_into_secure_buffers() {
+        let device = Arc::new(FakeDevice::new());
+        device.push_in(0x81, &[1, 2]);
+
+        // locking may be refused by RLIMIT_MEMLOCK in the test environment
+        if let Ok(pipe) = InPipeBuilder::bulk(0x81)
+            .transfer_size(64)
+            .secure_buffer(true)
+            .start(device)
+        {
+            assert_eq!(Ok(vec![1, 2]), pipe.recv_timeout(TIMEOUT));
+        }
+    }
+
+    #[test]
+    fn it_rejects_out_endpoints() {
+        let device = Arc::new(FakeDevice::new());
+
+        assert!(InPipeBuilder::bulk(0x01).start(device).is_err());
+    }
+}
@@ -0,0 +1,307 @@
+use std::{
+    fmt,
+    sync::{Arc, Mutex, MutexGuard},
+    time::Duration,
+};
+
+use libc::{c_int, c_short, c_void, timeval};
+#[cfg(unix)]
+use libc::{POLLIN, POLLOUT};
+use libusb1_sys::*;
+
+use crate::{context::UsbContext, error, event_loop};
+
+// available since libusb 1.0.20, but not bound by libusb1-sys
+extern "system" {
+    fn libusb_free_pollfds(pollfds: *const *mut libusb_pollfd);
+}
+
+// libusb has no file descriptors on Windows; these are the `WSAPoll` events
+#[cfg(not(unix))]
+const POLLIN: c_short = 0x0300;
+#[cfg(not(unix))]
+const POLLOUT: c_short = 0x0010;
+
+/// The notifier slots of the contexts, keyed by the address of their `libusb_context`.
+static SLOTS: Mutex<Vec<(usize, Arc<Slot>)>> = Mutex::new(Vec::new());
+
+/// Holds the notifier of a context, with the id of the registration that installed it.
+///
+/// `libusb` keeps calling the slot from whichever thread opens or closes devices, so it lives as
+/// long as the context, and registrations only swap the notifier it holds.
+#[derive(Default)]
+struct Slot {
+    notifier: Mutex<Option<(u64, Box<dyn PollFdNotifier>)>>,
+    next_id: Mutex<u64>,
+}
+
+impl Slot {
+    fn notifier(&self) -> MutexGuard<'_, Option<(u64, Box<dyn PollFdNotifier>)>> {
+        self.notifier.lock().unwrap_or_else(|p| p.into_inner())
+    }
+
+    /// Installs `notifier`, and returns the id of its registration.
+    fn install(&self, notifier: Box<dyn PollFdNotifier>) -> u64 {
+        let id = {
+            let mut next_id = self.next_id.lock().unwrap_or_else(|p| p.into_inner());
+            *next_id += 1;
+            *next_id
+        };
+
+        let previous = self.notifier().replace((id, notifier));
+        drop(previous);
+        id
+    }
+
+    /// Removes the notifier installed by the registration `id`, unless another one replaced it.
+    fn remove(&self, id: u64) {
+        let removed = {
+            let mut notifier = self.notifier();
+            match *notifier {
+                Some((installed, _)) if installed == id => notifier.take(),
+                _ => None,
+            }
+        };
+        drop(removed);
+    }
+}
+
+/// Returns the slot of `context`, installing it the first time.
+fn slot(context: *mut libusb_context) -> Arc<Slot> {
+    let mut slots = SLOTS.lock().unwrap_or_else(|p| p.into_inner());
+    if let Some((_, slot)) = slots.iter().find(|(c, _)| *c == context as usize) {
+        return slot.clone();
+    }
+
+    let slot = Arc::new(Slot::default());
+    unsafe {
+        libusb_set_pollfd_notifiers(
+            context,
+            pollfd_added,
+            pollfd_removed,
+            Arc::as_ptr(&slot) as *mut c_void,
+        );
+    }
+    slots.push((context as usize, slot.clone()));
+    slot
+}
+
+/// Frees the slot of `context`, once it was `exited` so `libusb` no longer calls it.
+///
+/// A context borrowed from foreign code isn't exited, and `libusb` keeps calling the slot, so only
+/// its notifier is dropped and the slot itself is leaked.
+pub(crate) fn forget_context(context: *mut libusb_context, exited: bool) {
+    let removed: Vec<_> = {
+        let mut slots = SLOTS.lock().unwrap_or_else(|p| p.into_inner());
+        let (removed, kept) = slots.drain(..).partition(|(c, _)| *c == context as usize);
+        *slots = kept;
+        removed
+    };
+
+    for (_, slot) in removed {
+        if !exited {
+            let notifier = slot.notifier().take();
+            drop(notifier);
+            std::mem::forget(slot);
+        }
+    }
+}
+
+/// A file descriptor `libusb` needs polled to handle the events of a context.
+///
+/// Applications with their own event loop, e.g. based on `epoll` or mio, register these file
+/// descriptors with it instead of blocking a thread in
+/// [`UsbContext::handle_events`](trait.UsbContext.html#method.handle_events). When one becomes
+/// ready, or when the timeout from
+/// [`UsbContext::next_timeout`](trait.UsbContext.html#method.next_timeout) expires, they call
+/// [`UsbContext::handle_pending_events`](trait.UsbContext.html#method.handle_pending_events).
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct PollFd {
+    fd: i32,
+    events: i16,
+}
+
+impl PollFd {
+    /// Returns the file descriptor.
+    pub fn fd(&self) -> i32 {
+        self.fd
+    }
+
+    /// Returns the `poll` events to wait for, e.g. `POLLIN`.
+    pub fn events(&self) -> i16 {
+        self.events
+    }
+
+    /// Indicates whether the file descriptor must be polled for reading.
+    pub fn readable(&self) -> bool {
+        self.events & POLLIN != 0
+    }
+
+    /// Indicates whether the file descriptor must be polled for writing.
+    pub fn writable(&self) -> bool {
+        self.events & POLLOUT != 0
+    }
+}
+
+/// Receives the file descriptors `libusb` adds and removes, see
+/// [`UsbContext::set_pollfd_notifiers`](trait.UsbContext.html#method.set_pollfd_notifiers).
+///
+/// The methods are called from the thread whose call made `libusb` change its file descriptors,
+/// e.g. while opening or closing a device. They must not drop their own registration. A panic
+/// doesn't unwind into that call: it is reported by the panic hook, and otherwise ignored.
+pub trait PollFdNotifier: Send {
+    fn added(&mut self, fd: PollFd);
+    fn removed(&mut self, fd: i32);
+}
+
+/// Keeps a [`PollFdNotifier`](trait.PollFdNotifier.html) registered, until it is dropped.
+///
+/// A context has one notifier at a time: registering another one replaces it, and dropping the
+/// replaced registration then leaves the new notifier registered. Dropping a registration waits
+/// for a notification running on another thread to return.
+#[must_use = "the notifier is deregistered when the registration is dropped"]
+pub struct PollFdRegistration<T: UsbContext> {
+    _context: T,
+    slot: Arc<Slot>,
+    id: u64,
+}
+
+impl<T: UsbContext> PollFdRegistration<T> {
+    pub(crate) fn register(
+        context: &T,
+        notifier: Box<dyn PollFdNotifier>,
+    ) -> PollFdRegistration<T> {
+        let slot = slot(context.as_raw());
+        let id = slot.install(notifier);
+
+        PollFdRegistration {
+            _context: context.clone(),
+            slot,
+            id,
+        }
+    }
+}
+
+impl<T: UsbContext> Drop for PollFdRegistration<T> {
+    fn drop(&mut self) {
+        self.slot.remove(self.id);
+    }
+}
+
+impl<T: UsbContext> fmt::Debug for PollFdRegistration<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PollFdRegistration")
+            .field("id", &self.id)
+            .finish()
+    }
+}
+
+extern "system" fn pollfd_added(fd: c_int, events: c_short, user_data: *mut c_void) {
+    let slot = unsafe { &*(user_data as *const Slot) };
+    event_loop::discard_callback_panic(|| {
+        if let Some((_, notifier)) = slot.notifier().as_mut() {
+            notifier.added(PollFd { fd, events });
+        }
+    });
+}
+
+extern "system" fn pollfd_removed(fd: c_int, user_data: *mut c_void) {
+    let slot = unsafe { &*(user_data as *const Slot) };
+    event_loop::discard_callback_panic(|| {
+        if let Some((_, notifier)) = slot.notifier().as_mut() {
+            notifier.removed(fd);
+        }
+    });
+}
+
+/// Returns the file descriptors `libusb` currently needs polled for `context`.
+pub(crate) fn pollfds(context: *mut libusb_context) -> crate::Result<Vec<PollFd>> {
+    let list = unsafe { libusb_get_pollfds(context) };
+    if list.is_null() {
+        // e.g. on Windows, where libusb doesn't use file descriptors
+        return Err(crate::Error::NotSupported);
+    }
+
+    let mut fds = Vec::new();
+    unsafe {
+        let mut entry = list;
+        while !(*entry).is_null() {
+            let pollfd = &**entry;
+            fds.push(PollFd {
+                fd: pollfd.fd,
+                events: pollfd.events,
+            });
+            entry = entry.add(1);
+        }
+        libusb_free_pollfds(list);
+    }
+
+    Ok(fds)
+}
+
+/// Returns when `libusb` next needs to handle a timeout for `context`.
+pub(crate) fn next_timeout(context: *mut libusb_context) -> crate::Result<Option<Duration>> {
+    let mut tv = timeval {
+        tv_sec: 0,
+        tv_usec: 0,
+    };
+
+    match unsafe { libusb_get_next_timeout(context, &mut tv) } {
+        0 => Ok(None),
+        n if n < 0 => Err(error::from_libusb(n)),
+        _ => Ok(Some(
+            Duration::from_secs(tv.tv_sec.max(0) as u64)
+                + Duration::from_micros(tv.tv_usec.max(0) as u64),
+        )),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn it_decodes_poll_events() {
+        let fd = PollFd {
+            fd: 3,
+            events: POLLIN,
+        };
+        assert!(fd.readable() && !fd.writable());
+
+        let fd = PollFd {
+            fd: 4,
+            events: POLLIN | POLLOUT,
+        };
+        assert!(fd.readable() && fd.writable());
+    }
+
+    struct Recorder(Arc<Mutex<Vec<i32>>>);
+
+    impl PollFdNotifier for Recorder {
+        fn added(&mut self, fd: PollFd) {
+            self.0.lock().unwrap().push(fd.fd);
+        }
+
+        fn removed(&mut self, fd: i32) {
+            self.0.lock().unwrap().push(-fd);
+        }
+    }
+
+    #[test]
+    fn it_keeps_newer_notifiers_when_older_registrations_are_dropped() {
+        let slot = Slot::default();
+        let (older, newer) = (Arc::default(), Arc::default());
+
+        let older_id = slot.install(Box::new(Recorder(Arc::clone(&older))));
+        let newer_id = slot.install(Box::new(Recorder(Arc::clone(&newer))));
+        slot.remove(older_id);
+        pollfd_added(3, POLLIN, &slot as *const Slot as *mut c_void);
+
+        assert!(older.lock().unwrap().is_empty());
+        assert_eq!(vec![3], *newer.lock().unwrap());
+
+        slot.remove(newer_id);
+        pollfd_removed(3, &slot as *const Slot as *mut c_void);
+        assert_eq!(vec![3], *newer.lock().unwrap());
+    }
+}
@@ -0,0 +1,170 @@
+use std::fmt::Write as _;
+
+use crate::{device_descriptor::DeviceDescriptor, fields::Speed};
+
+/// What is known of a device's serial number when building its profile string.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub(crate) enum Serial<'a> {
+    /// The device has no serial number.
+    Absent,
+
+    /// The serial number wasn't read, or reading it failed.
+    Unknown,
+
+    /// The serial number.
+    Known(&'a str),
+}
+
+impl<'a> Serial<'a> {
+    /// Picks the serial number matching `descriptor`, given the result of reading it, if it was.
+    pub(crate) fn of(
+        descriptor: Option<&DeviceDescriptor>,
+        read: Option<&'a crate::Result<String>>,
+    ) -> Serial<'a> {
+        match (descriptor.map(|d| d.serial_number_string_index()), read) {
+            (Some(None), _) => Serial::Absent,
+            (_, Some(Ok(serial))) => Serial::Known(serial),
+            _ => Serial::Unknown,
+        }
+    }
+}
+
+/// Builds the single-line identity of a device, e.g.
+/// `1234:5678 bcd=1.00 serial=A1B2C3 speed=high port=1-2.3`.
+///
+/// Every field is always present, `?` standing for what couldn't be found out and `-` for a
+/// device without a serial number, so a device is found in logs by grepping any part of it. The
+/// serial number is kept to a single token by replacing whitespace, control characters, quotes,
+/// backslashes and `=` with `_`.
+pub(crate) fn render(
+    descriptor: Option<&DeviceDescriptor>,
+    serial: Serial<'_>,
+    speed: Speed,
+    bus: u8,
+    ports: Option<&[u8]>,
+) -> String {
+    let mut profile = String::new();
+
+    match descriptor {
+        Some(descriptor) => {
+            let version = descriptor.device_version();
+            write!(
+                profile,
+                "{:04x}:{:04x} bcd={}.{}{}",
+                descriptor.vendor_id(),
+                descriptor.product_id(),
+                version.major(),
+                version.minor(),
+                version.sub_minor()
+            )
+            .ok();
+        }
+        None => profile.push_str("????:???? bcd=?"),
+    }
+
+    profile.push_str(" serial=");
+    match serial {
+        Serial::Unknown => profile.push('?'),
+        Serial::Absent | Serial::Known("") => profile.push('-'),
+        Serial::Known(serial) => profile.extend(serial.chars().map(|c| {
+            if c.is_ascii_graphic() && !matches!(c, '"' | '\\' | '=') {
+                c
+            } else {
+                '_'
+            }
+        })),
+    }
+
+    let speed = match speed {
+        Speed::Unknown => "?",
+        Speed::Low => "low",
+        Speed::Full => "full",
+        Speed::High => "high",
+        Speed::Super => "super",
+    };
+    write!(profile, " speed={} port={}", speed, bus).ok();
+
+    match ports {
+        Some(ports) => {
+            for (i, port) in ports.iter().enumerate() {
+                write!(profile, "{}{}", if i == 0 { '-' } else { '.' }, port).ok();
+            }
+        }
+        None => profile.push_str("-?"),
+    }
+
+    profile
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn descriptor(serial_index: u8) -> DeviceDescriptor {
+        crate::device_descriptor::from_libusb(device_descriptor!(iSerialNumber: serial_index))
+    }
+
+    #[test]
+    fn it_renders_every_field() {
+        assert_eq!(
+            "1234:5678 bcd=1.23 serial=A1B2C3 speed=high port=1-2.3",
+            render(
+                Some(&descriptor(3)),
+                Serial::Known("A1B2C3"),
+                Speed::High,
+                1,
+                Some(&[2, 3])
+            )
+        );
+    }
+
+    #[test]
+    fn it_marks_what_is_unknown() {
+        assert_eq!(
+            "????:???? bcd=? serial=? speed=? port=4-?",
+            render(None, Serial::Unknown, Speed::Unknown, 4, None)
+        );
+        assert_eq!(
+            "1234:5678 bcd=1.23 serial=- speed=super port=2",
+            render(
+                Some(&descriptor(0)),
+                Serial::Absent,
+                Speed::Super,
+                2,
+                Some(&[])
+            )
+        );
+    }
+
+    #[test]
+    fn it_keeps_the_serial_number_a_single_token() {
+        let profile = render(
+            None,
+            Serial::Known("AB 12=\"x\"\n"),
+            Speed::Full,
+            1,
+            Some(&[1]),
+        );
+        assert!(profile.contains(" serial=AB_12__x__ "));
+    }
+
+    #[test]
+    fn it_picks_the_serial_number() {
+        let read = Ok("A1".to_string());
+        let failed = Err(crate::Error::Pipe);
+
+        assert_eq!(
+            Serial::Absent,
+            Serial::of(Some(&descriptor(0)), Some(&read))
+        );
+        assert_eq!(
+            Serial::Known("A1"),
+            Serial::of(Some(&descriptor(3)), Some(&read))
+        );
+        assert_eq!(
+            Serial::Unknown,
+            Serial::of(Some(&descriptor(3)), Some(&failed))
+        );
+        assert_eq!(Serial::Unknown, Serial::of(Some(&descriptor(3)), None));
+    }
+}
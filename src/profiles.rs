@@ -0,0 +1,435 @@
+//! Declarative device configuration profiles.
+//!
+//! A [`Profile`] describes how a device should be set up once it appears: which configuration to
+//! select, which interfaces to claim with which alternate settings, and which control requests to
+//! send to initialize it. Profiles are collected in a [`ProfileSet`], which can be built in code
+//! or, with the `profiles-toml` feature, loaded from a TOML document:
+//!
+//! ```toml
+//! [[profile]]
+//! name = "logger"
+//! vendor_id = 0x1234
+//! product_id = 0x5678
+//! configuration = 1
+//! interfaces = [{ number = 0, alt_setting = 1 }]
+//! control = [{ request_type = 0x40, request = 0x01, value = 1, index = 0, data = [] }]
+//! ```
+//!
+//! [`ProfileSet::watch`] connects a set to the context's hotplug support so that matching devices
+//! are configured automatically as they are attached.
+
+use std::{
+    sync::{
+        mpsc::{self, Receiver, Sender},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    device::Device,
+    device_descriptor::DeviceDescriptor,
+    device_handle::DeviceHandle,
+    error::Error,
+    hotplug::{Hotplug, Registration},
+    UsbContext,
+};
+
+/// An interface to claim when applying a profile.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct InterfaceSetting {
+    /// The interface number.
+    pub number: u8,
+
+    /// The alternate setting to select after claiming the interface.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub alt_setting: u8,
+}
+
+/// A control request sent to the device when applying a profile.
+///
+/// Requests are sent with the data stage given in `data`, so `request_type` must specify a
+/// host-to-device transfer.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct ControlStep {
+    /// The `bmRequestType` field of the setup packet.
+    pub request_type: u8,
+
+    /// The `bRequest` field of the setup packet.
+    pub request: u8,
+
+    /// The `wValue` field of the setup packet.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub value: u16,
+
+    /// The `wIndex` field of the setup packet.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub index: u16,
+
+    /// The data stage.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub data: Vec<u8>,
+}
+
+/// How a device should be configured when it appears.
+///
+/// A profile matches a device when every matching field that is set equals the corresponding
+/// field of the device descriptor. Applying a profile performs the following steps in order,
+/// stopping at the first error:
+///
+/// 1. enable automatic kernel driver detachment, if `detach_kernel_driver` is set,
+/// 2. select `configuration`, if set and not already active,
+/// 3. claim each of `interfaces` and select its alternate setting,
+/// 4. send each of the `control` requests.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
+pub struct Profile {
+    /// A name identifying the profile in reports.
+    pub name: String,
+
+    /// The vendor ID to match.
+    pub vendor_id: Option<u16>,
+
+    /// The product ID to match.
+    pub product_id: Option<u16>,
+
+    /// The device class to match.
+    pub class: Option<u8>,
+
+    /// The configuration value to select.
+    pub configuration: Option<u8>,
+
+    /// Whether kernel drivers should be detached from claimed interfaces.
+    pub detach_kernel_driver: bool,
+
+    /// The interfaces to claim.
+    pub interfaces: Vec<InterfaceSetting>,
+
+    /// The control requests to send once the interfaces are claimed.
+    pub control: Vec<ControlStep>,
+
+    /// The timeout for each control request. Defaults to one second.
+    pub timeout_ms: Option<u64>,
+}
+
+impl Profile {
+    /// Indicates whether the profile applies to a device.
+    pub fn matches(&self, descriptor: &DeviceDescriptor) -> bool {
+        self.vendor_id.map_or(true, |v| v == descriptor.vendor_id())
+            && self
+                .product_id
+                .map_or(true, |p| p == descriptor.product_id())
+            && self.class.map_or(true, |c| c == descriptor.class_code())
+    }
+
+    /// Configures an open device according to the profile.
+    #[cfg_attr(feature = "async-only", deprecated(note = "blocks the calling thread"))]
+    #[cfg_attr(feature = "async-only", allow(deprecated))]
+    pub fn apply<T: UsbContext>(&self, handle: &mut DeviceHandle<T>) -> crate::Result<()> {
+        let timeout = Duration::from_millis(self.timeout_ms.unwrap_or(1000));
+
+        if self.detach_kernel_driver {
+            match handle.set_auto_detach_kernel_driver(true) {
+                Ok(()) | Err(Error::NotSupported) => (),
+                Err(e) => return Err(e),
+            }
+        }
+
+        if let Some(config) = self.configuration {
+            if handle.active_configuration()? != config {
+                handle.set_active_configuration(config)?;
+            }
+        }
+
+        for iface in &self.interfaces {
+            handle.claim_interface(iface.number)?;
+            handle.set_alternate_setting(iface.number, iface.alt_setting)?;
+        }
+
+        for step in &self.control {
+            handle.write_control(
+                step.request_type,
+                step.request,
+                step.value,
+                step.index,
+                &step.data,
+                timeout,
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+/// A collection of profiles, consulted in the order they were added.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct ProfileSet {
+    #[cfg_attr(feature = "serde", serde(default, rename = "profile"))]
+    profiles: Vec<Profile>,
+}
+
+impl ProfileSet {
+    /// Creates an empty set.
+    pub fn new() -> ProfileSet {
+        ProfileSet::default()
+    }
+
+    /// Parses a set of profiles from a TOML document with one `[[profile]]` table per profile.
+    ///
+    /// Returns `Error::InvalidParam` if the document can't be parsed.
+    #[cfg(feature = "profiles-toml")]
+    pub fn from_toml(document: &str) -> crate::Result<ProfileSet> {
+        toml::from_str(document).map_err(|_| Error::InvalidParam)
+    }
+
+    /// Adds a profile to the set.
+    pub fn add(&mut self, profile: Profile) {
+        self.profiles.push(profile);
+    }
+
+    /// Returns the profiles in the set.
+    pub fn profiles(&self) -> &[Profile] {
+        &self.profiles
+    }
+
+    /// Returns the first profile matching a device.
+    pub fn find(&self, descriptor: &DeviceDescriptor) -> Option<&Profile> {
+        self.profiles.iter().find(|p| p.matches(descriptor))
+    }
+
+    /// Opens a device and applies the first matching profile to it.
+    ///
+    /// Returns `Error::NotFound` if no profile matches the device.
+    #[cfg_attr(feature = "async-only", deprecated(note = "blocks the calling thread"))]
+    #[cfg_attr(feature = "async-only", allow(deprecated))]
+    pub fn configure<T: UsbContext>(
+        &self,
+        device: &Device<T>,
+    ) -> crate::Result<(&Profile, DeviceHandle<T>)> {
+        let descriptor = device.device_descriptor()?;
+        let profile = self.find(&descriptor).ok_or(Error::NotFound)?;
+
+        let mut handle = device.open()?;
+        profile.apply(&mut handle)?;
+
+        Ok((profile, handle))
+    }
+
+    /// Configures matching devices as they are attached.
+    ///
+    /// This registers a hotplug callback on `context` that queues every arriving device matched
+    /// by a profile. Profiles are not applied from within the callback, because libusb forbids
+    /// synchronous transfers while it is delivering hotplug events; instead the returned
+    /// [`ProfileWatcher`] applies them from the application's thread. Hotplug events are only
+    /// delivered while the context's events are being handled.
+    pub fn watch<T: UsbContext + 'static>(self, context: &T) -> crate::Result<ProfileWatcher<T>> {
+        let profiles = Arc::new(self);
+        let (sender, receiver) = mpsc::channel();
+
+        let registration = context.hotplug().register(Box::new(ProfileHotplug {
+            profiles: profiles.clone(),
+            sender,
+        }))?;
+
+        Ok(ProfileWatcher {
+            context: context.clone(),
+            profiles,
+            receiver,
+            _registration: registration,
+        })
+    }
+}
+
+struct ProfileHotplug<T: UsbContext> {
+    profiles: Arc<ProfileSet>,
+    sender: Sender<Device<T>>,
+}
+
+impl<T: UsbContext> Hotplug<T> for ProfileHotplug<T> {
+    fn device_arrived(&mut self, device: Device<T>) {
+        let matched = match device.device_descriptor() {
+            Ok(descriptor) => self.profiles.find(&descriptor).is_some(),
+            Err(_) => false,
+        };
+
+        if matched {
+            self.sender.send(device).ok();
+        }
+    }
+
+    fn device_left(&mut self, _device: Device<T>) {}
+}
+
+/// The result of applying a profile to a device that was attached.
+pub struct Configured<T: UsbContext> {
+    /// The device that was attached.
+    pub device: Device<T>,
+
+    /// The name of the profile that was applied.
+    pub profile: String,
+
+    /// The configured handle, or the error that stopped the profile from being applied.
+    pub handle: crate::Result<DeviceHandle<T>>,
+}
+
+/// Applies profiles to devices as they are attached, see [`ProfileSet::watch`].
+///
+/// The hotplug callback is deregistered when the watcher is dropped.
+pub struct ProfileWatcher<T: UsbContext> {
+    context: T,
+    profiles: Arc<ProfileSet>,
+    receiver: Receiver<Device<T>>,
+    /// Deregisters the callback when the watcher is dropped.
+    _registration: Registration<T>,
+}
+
+impl<T: UsbContext> ProfileWatcher<T> {
+    /// Returns the profiles being applied.
+    pub fn profiles(&self) -> &ProfileSet {
+        &self.profiles
+    }
+
+    /// Handles pending events and configures the next attached device, waiting up to `timeout`
+    /// for one to arrive.
+    ///
+    /// Returns `None` if no matching device arrived in time.
+    #[cfg_attr(feature = "async-only", deprecated(note = "blocks the calling thread"))]
+    #[cfg_attr(feature = "async-only", allow(deprecated))]
+    pub fn next(&mut self, timeout: Duration) -> crate::Result<Option<Configured<T>>> {
+        if let Some(configured) = self.try_next() {
+            return Ok(Some(configured));
+        }
+
+        // unrelated events, e.g. completed transfers, end `handle_events` early, so it's called
+        // again for the remaining time rather than returning before the deadline
+        let deadline = Instant::now() + timeout;
+        loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            self.context.handle_events(Some(remaining))?;
+
+            if let Some(configured) = self.try_next() {
+                return Ok(Some(configured));
+            }
+            if Instant::now() >= deadline {
+                return Ok(None);
+            }
+        }
+    }
+
+    /// Configures the next device that has already been attached, without handling events.
+    #[cfg_attr(feature = "async-only", deprecated(note = "blocks the calling thread"))]
+    #[cfg_attr(feature = "async-only", allow(deprecated))]
+    pub fn try_next(&mut self) -> Option<Configured<T>> {
+        let device = self.receiver.try_recv().ok()?;
+
+        let (profile, handle) = match self.profiles.configure(&device) {
+            Ok((profile, handle)) => (profile.name.clone(), Ok(handle)),
+            Err(e) => {
+                let name = device
+                    .device_descriptor()
+                    .ok()
+                    .and_then(|d| self.profiles.find(&d).map(|p| p.name.clone()))
+                    .unwrap_or_default();
+                (name, Err(e))
+            }
+        };
+
+        Some(Configured {
+            device,
+            profile,
+            handle,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::device_descriptor;
+
+    #[test]
+    fn it_matches_on_set_fields_only() {
+        let descriptor = device_descriptor::from_libusb(device_descriptor!(
+            idVendor: 0x1234,
+            idProduct: 0x5678,
+            bDeviceClass: 0xFF
+        ));
+
+        let any = Profile::default();
+        assert!(any.matches(&descriptor));
+
+        let vendor = Profile {
+            vendor_id: Some(0x1234),
+            ..Profile::default()
+        };
+        assert!(vendor.matches(&descriptor));
+
+        let other = Profile {
+            vendor_id: Some(0x1234),
+            product_id: Some(0x0001),
+            ..Profile::default()
+        };
+        assert!(!other.matches(&descriptor));
+
+        let class = Profile {
+            class: Some(0x03),
+            ..Profile::default()
+        };
+        assert!(!class.matches(&descriptor));
+    }
+
+    #[test]
+    fn it_finds_first_matching_profile() {
+        let descriptor = device_descriptor::from_libusb(device_descriptor!(idVendor: 0x1234));
+
+        let mut set = ProfileSet::new();
+        set.add(Profile {
+            name: "other".into(),
+            vendor_id: Some(0x4321),
+            ..Profile::default()
+        });
+        set.add(Profile {
+            name: "first".into(),
+            vendor_id: Some(0x1234),
+            ..Profile::default()
+        });
+        set.add(Profile {
+            name: "second".into(),
+            ..Profile::default()
+        });
+
+        assert_eq!("first", set.find(&descriptor).unwrap().name);
+    }
+
+    #[cfg(feature = "profiles-toml")]
+    #[test]
+    fn it_loads_profiles_from_toml() {
+        let set = ProfileSet::from_toml(
+            r#"
+            [[profile]]
+            name = "logger"
+            vendor_id = 0x1234
+            configuration = 1
+            interfaces = [{ number = 0, alt_setting = 1 }]
+            control = [{ request_type = 0x40, request = 0x01, value = 1 }]
+            "#,
+        )
+        .unwrap();
+
+        let profile = &set.profiles()[0];
+        assert_eq!("logger", profile.name);
+        assert_eq!(Some(0x1234), profile.vendor_id);
+        assert_eq!(None, profile.product_id);
+        assert_eq!(Some(1), profile.configuration);
+        assert_eq!(1, profile.interfaces[0].alt_setting);
+        assert_eq!(0x40, profile.control[0].request_type);
+        assert!(profile.control[0].data.is_empty());
+    }
+}
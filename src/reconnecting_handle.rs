@@ -0,0 +1,448 @@
+use std::{
+    fmt, thread,
+    time::{Duration, Instant},
+};
+
+use crate::{device_handle::DeviceHandle, error::Error, managed_context::DeviceId, UsbContext};
+
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Configures and opens a [`ReconnectingHandle`](struct.ReconnectingHandle.html).
+#[derive(Debug, Clone)]
+pub struct ReconnectingHandleBuilder {
+    interfaces: Vec<u8>,
+    alt_settings: Vec<(u8, u8)>,
+    timeout: Duration,
+    retries: u32,
+}
+
+impl ReconnectingHandleBuilder {
+    /// Creates a builder for a handle waiting up to 10 seconds for the device to come back, and
+    /// retrying an operation once.
+    pub fn new() -> ReconnectingHandleBuilder {
+        ReconnectingHandleBuilder {
+            interfaces: Vec::new(),
+            alt_settings: Vec::new(),
+            timeout: Duration::from_secs(10),
+            retries: 1,
+        }
+    }
+
+    /// Adds an interface claimed whenever the device is opened.
+    pub fn interface(mut self, iface: u8) -> ReconnectingHandleBuilder {
+        self.interfaces.push(iface);
+        self
+    }
+
+    /// Selects an alternate setting of an interface whenever the device is opened, after the
+    /// interfaces are claimed.
+    pub fn alternate_setting(mut self, iface: u8, setting: u8) -> ReconnectingHandleBuilder {
+        self.alt_settings.retain(|&(i, _)| i != iface);
+        self.alt_settings.push((iface, setting));
+        self
+    }
+
+    /// Sets how long to wait for the device to reappear after it was lost.
+    pub fn timeout(mut self, timeout: Duration) -> ReconnectingHandleBuilder {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Sets how many times an operation is retried after reconnecting, 0 to only reconnect so
+    /// that the next operation finds the device configured.
+    pub fn retries(mut self, retries: u32) -> ReconnectingHandleBuilder {
+        self.retries = retries;
+        self
+    }
+
+    /// Opens the device identified by `id` in `context`, and configures it.
+    ///
+    /// ## Errors
+    ///
+    /// * `NoDevice` if no attached device matches `id`.
+    /// * Any error opening the device, claiming an interface or selecting an alternate setting.
+    #[cfg_attr(feature = "async-only", deprecated(note = "blocks the calling thread"))]
+    pub fn open<T: UsbContext>(
+        self,
+        context: T,
+        id: DeviceId,
+    ) -> crate::Result<ReconnectingHandle<T>> {
+        let mut handle = ReconnectingHandle {
+            context,
+            id,
+            builder: self,
+            handle: None,
+            reconnects: 0,
+        };
+        handle.connect()?;
+        Ok(handle)
+    }
+}
+
+impl Default for ReconnectingHandleBuilder {
+    fn default() -> Self {
+        ReconnectingHandleBuilder::new()
+    }
+}
+
+/// A device handle that reopens its device whenever it disappears, for devices that reset or
+/// re-enumerate unexpectedly, or on purpose during a firmware update.
+///
+/// Every operation goes through [`with`](#method.with). When it fails with `NoDevice` or `Io`,
+/// the handle is closed, and the device identified by its [`DeviceId`](struct.DeviceId.html) is
+/// looked for until it is back or the [timeout](struct.ReconnectingHandleBuilder.html#method.timeout)
+/// expires. The device is then opened and configured again, i.e. its interfaces are claimed and
+/// their alternate settings selected, and the operation is retried.
+///
+/// The devices are polled rather than watched with hotplug, so reconnecting works on every
+/// platform. A device coming back with a different vendor or product ID, e.g. a bootloader, isn't
+/// the same device; see
+/// [`UsbContext::wait_for_reenumeration`](trait.UsbContext.html#method.wait_for_reenumeration)
+/// for that case.
+///
+/// An operation is retried as a whole, so it must be safe to repeat: a write that reached the
+/// device before it went away is written again.
+pub struct ReconnectingHandle<T: UsbContext> {
+    context: T,
+    id: DeviceId,
+    builder: ReconnectingHandleBuilder,
+    /// `None` while the device is lost.
+    handle: Option<DeviceHandle<T>>,
+    reconnects: u32,
+}
+
+impl<T: UsbContext> ReconnectingHandle<T> {
+    /// Performs `f` with the device handle, reconnecting and retrying it if the device is lost.
+    ///
+    /// If the device was lost by an earlier call and didn't come back in time, it is waited for
+    /// again first.
+    ///
+    /// ## Errors
+    ///
+    /// * `NoDevice` if the device didn't reappear before the timeout.
+    /// * Any error configuring the device again.
+    /// * The error returned by `f`, once it isn't retried anymore.
+    #[cfg_attr(feature = "async-only", deprecated(note = "blocks the calling thread"))]
+    #[cfg_attr(feature = "async-only", allow(deprecated))]
+    pub fn with<R, F>(&mut self, mut f: F) -> crate::Result<R>
+    where
+        F: FnMut(&mut DeviceHandle<T>) -> crate::Result<R>,
+    {
+        if self.handle.is_none() {
+            self.reconnect()?;
+        }
+
+        let retries = self.builder.retries;
+        let mut state = (self, &mut f);
+
+        retry(
+            retries,
+            |(this, f)| match this.handle.as_mut() {
+                Some(handle) => f(handle),
+                None => Err(Error::NoDevice),
+            },
+            |(this, _)| this.reconnect(),
+            &mut state,
+        )
+    }
+
+    /// Closes the device and waits for it to come back, as if it was lost.
+    ///
+    /// A device that is back but can't be opened or configured yet with `Access` or `Busy`,
+    /// e.g. until udev applied its permissions or the kernel driver let go of an interface, is
+    /// retried until the timeout too.
+    ///
+    /// ## Errors
+    ///
+    /// * `NoDevice` if the device didn't reappear before the timeout.
+    /// * `Access` or `Busy` if the device was still refused when the timeout expired.
+    /// * Any other error configuring the device again.
+    #[cfg_attr(feature = "async-only", deprecated(note = "blocks the calling thread"))]
+    pub fn reconnect(&mut self) -> crate::Result<()> {
+        self.handle = None;
+        let deadline = Instant::now() + self.builder.timeout;
+
+        poll_until(deadline, POLL_INTERVAL, || self.connect())?;
+        self.reconnects += 1;
+        Ok(())
+    }
+
+    /// Indicates whether the device is open, i.e. wasn't lost since it was last reconnected.
+    pub fn is_connected(&self) -> bool {
+        self.handle.is_some()
+    }
+
+    /// Returns how many times the device was reconnected.
+    pub fn reconnects(&self) -> u32 {
+        self.reconnects
+    }
+
+    /// Returns the id of the device.
+    pub fn id(&self) -> &DeviceId {
+        &self.id
+    }
+
+    /// Returns the context the device is opened in.
+    pub fn context(&self) -> &T {
+        &self.context
+    }
+
+    /// Opens the device and configures it.
+    #[cfg_attr(feature = "async-only", allow(deprecated))]
+    fn connect(&mut self) -> crate::Result<()> {
+        let mut handle = self.context.open_by_id(&self.id)?;
+        configure(
+            &self.builder,
+            &mut handle,
+            |handle, iface| handle.claim_interface(iface),
+            |handle, iface, setting| handle.set_alternate_setting(iface, setting),
+        )?;
+
+        self.handle = Some(handle);
+        Ok(())
+    }
+}
+
+impl<T: UsbContext> fmt::Debug for ReconnectingHandle<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ReconnectingHandle")
+            .field("id", &self.id)
+            .field("interfaces", &self.builder.interfaces)
+            .field("alt_settings", &self.builder.alt_settings)
+            .field("connected", &self.handle.is_some())
+            .field("reconnects", &self.reconnects)
+            .finish()
+    }
+}
+
+/// Indicates whether `error` means that the device went away.
+fn is_disconnect(error: &Error) -> bool {
+    matches!(error, Error::NoDevice | Error::Io)
+}
+
+/// Claims the interfaces of `builder` with `claim`, then selects their alternate settings with
+/// `select`, stopping at the first error.
+fn configure<H>(
+    builder: &ReconnectingHandleBuilder,
+    handle: &mut H,
+    mut claim: impl FnMut(&mut H, u8) -> crate::Result<()>,
+    mut select: impl FnMut(&mut H, u8, u8) -> crate::Result<()>,
+) -> crate::Result<()> {
+    for &iface in &builder.interfaces {
+        claim(handle, iface)?;
+    }
+    for &(iface, setting) in &builder.alt_settings {
+        select(handle, iface, setting)?;
+    }
+    Ok(())
+}
+
+/// Calls `connect` every `interval` until it succeeds, or fails with an error that waiting
+/// doesn't help with, or `deadline` passes.
+///
+/// The device missing and the device refusing to be opened or configured are both waited out.
+/// At the deadline, a refusal is returned as is, and a missing device as `NoDevice`.
+fn poll_until(
+    deadline: Instant,
+    interval: Duration,
+    mut connect: impl FnMut() -> crate::Result<()>,
+) -> crate::Result<()> {
+    loop {
+        let refused = match connect() {
+            Ok(()) => return Ok(()),
+            Err(e @ Error::Access) | Err(e @ Error::Busy) => Some(e),
+            Err(e) if is_disconnect(&e) || e == Error::NotFound => None,
+            Err(e) => return Err(e),
+        };
+        if Instant::now() >= deadline {
+            return Err(refused.unwrap_or(Error::NoDevice));
+        }
+        thread::sleep(interval);
+    }
+}
+
+/// Performs `op`, and after each failure caused by a disconnect, `reconnect` then `op` again, up
+/// to `retries` times.
+fn retry<S, R>(
+    retries: u32,
+    mut op: impl FnMut(&mut S) -> crate::Result<R>,
+    mut reconnect: impl FnMut(&mut S) -> crate::Result<()>,
+    state: &mut S,
+) -> crate::Result<R> {
+    let mut attempts = 0;
+    loop {
+        match op(state) {
+            Err(e) if is_disconnect(&e) => {
+                reconnect(state)?;
+                if attempts == retries {
+                    return Err(e);
+                }
+                attempts += 1;
+            }
+            result => return result,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn it_retries_after_reconnecting() {
+        let mut state = (vec![Err(Error::Io), Ok(7)].into_iter(), 0);
+
+        let result = retry(
+            1,
+            |(results, _)| results.next().unwrap(),
+            |(_, reconnects)| {
+                *reconnects += 1;
+                Ok(())
+            },
+            &mut state,
+        );
+
+        assert_eq!(Ok(7), result);
+        assert_eq!(1, state.1);
+    }
+
+    #[test]
+    fn it_gives_up_after_the_retries() {
+        let mut state = (0, 0);
+
+        let result: crate::Result<()> = retry(
+            2,
+            |(attempts, _)| {
+                *attempts += 1;
+                Err(Error::NoDevice)
+            },
+            |(_, reconnects)| {
+                *reconnects += 1;
+                Ok(())
+            },
+            &mut state,
+        );
+
+        assert_eq!(Err(Error::NoDevice), result);
+        assert_eq!((3, 3), state);
+    }
+
+    #[test]
+    fn it_does_not_retry_other_errors() {
+        let mut reconnects = 0;
+
+        let result: crate::Result<()> = retry(
+            1,
+            |_| Err(Error::Pipe),
+            |reconnects| {
+                *reconnects += 1;
+                Ok(())
+            },
+            &mut reconnects,
+        );
+
+        assert_eq!(Err(Error::Pipe), result);
+        assert_eq!(0, reconnects);
+    }
+
+    #[test]
+    fn it_claims_interfaces_before_selecting_settings() {
+        let builder = ReconnectingHandleBuilder::new()
+            .alternate_setting(1, 2)
+            .interface(0)
+            .interface(1)
+            .alternate_setting(1, 3);
+        let mut calls = Vec::new();
+
+        let res = configure(
+            &builder,
+            &mut calls,
+            |calls, iface| {
+                calls.push((iface, None));
+                Ok(())
+            },
+            |calls, iface, setting| {
+                calls.push((iface, Some(setting)));
+                Ok(())
+            },
+        );
+
+        assert_eq!(Ok(()), res);
+        assert_eq!(vec![(0, None), (1, None), (1, Some(3))], calls);
+    }
+
+    #[test]
+    fn it_stops_configuring_at_the_first_error() {
+        let builder = ReconnectingHandleBuilder::new()
+            .interface(0)
+            .interface(1)
+            .alternate_setting(0, 1);
+        let mut claimed = Vec::new();
+
+        let res = configure(
+            &builder,
+            &mut claimed,
+            |claimed, iface| {
+                claimed.push(iface);
+                Err(Error::Busy)
+            },
+            |_, _, _| panic!("selected a setting of an unclaimed interface"),
+        );
+
+        assert_eq!(Err(Error::Busy), res);
+        assert_eq!(vec![0], claimed);
+    }
+
+    #[test]
+    fn it_waits_for_the_device_to_come_back_and_be_accessible() {
+        let mut results = vec![
+            Err(Error::NotFound),
+            Err(Error::NoDevice),
+            Err(Error::Access),
+            Err(Error::Busy),
+            Ok(()),
+        ]
+        .into_iter();
+        let deadline = Instant::now() + Duration::from_secs(10);
+
+        let res = poll_until(deadline, Duration::from_millis(1), || {
+            results.next().unwrap()
+        });
+
+        assert_eq!(Ok(()), res);
+        assert_eq!(0, results.len());
+    }
+
+    #[test]
+    fn it_gives_up_at_the_deadline() {
+        let deadline = Instant::now() + Duration::from_millis(20);
+        let missing = poll_until(deadline, Duration::from_millis(1), || Err(Error::NotFound));
+        assert_eq!(Err(Error::NoDevice), missing);
+
+        let deadline = Instant::now() + Duration::from_millis(20);
+        let refused = poll_until(deadline, Duration::from_millis(1), || Err(Error::Access));
+        assert_eq!(Err(Error::Access), refused);
+    }
+
+    #[test]
+    fn it_does_not_wait_out_other_connect_errors() {
+        let mut attempts = 0;
+        let deadline = Instant::now() + Duration::from_secs(10);
+
+        let res = poll_until(deadline, Duration::from_millis(1), || {
+            attempts += 1;
+            Err(Error::InvalidParam)
+        });
+
+        assert_eq!(Err(Error::InvalidParam), res);
+        assert_eq!(1, attempts);
+    }
+
+    #[test]
+    fn it_reports_reconnect_failures() {
+        let result: crate::Result<()> =
+            retry(1, |_| Err(Error::Io), |_| Err(Error::NoDevice), &mut ());
+
+        assert_eq!(Err(Error::NoDevice), result);
+    }
+}
@@ -0,0 +1,66 @@
+//! Predefined `bmRequestType` values for control transfers.
+//!
+//! Each constant is built with [`request_type`](../fn.request_type.html), so protocol tables can
+//! be declared as constants:
+//!
+//! ```
+//! use rusb::request_types::{VENDOR_IN_DEVICE, VENDOR_OUT_DEVICE};
+//!
+//! const COMMANDS: [(u8, u8); 2] = [(VENDOR_IN_DEVICE, 0x01), (VENDOR_OUT_DEVICE, 0x02)];
+//! # assert_eq!(0xC0, COMMANDS[0].0);
+//! ```
+
+use crate::fields::{request_type, Direction, Recipient, RequestType};
+
+macro_rules! request_types {
+    ($($name:ident => ($direction:ident, $request_type:ident, $recipient:ident),)*) => {
+        $(
+            #[doc = concat!(
+                "A ", stringify!($request_type), " request, ",
+                stringify!($direction), " direction, to the ", stringify!($recipient), ".",
+            )]
+            pub const $name: u8 = request_type(
+                Direction::$direction,
+                RequestType::$request_type,
+                Recipient::$recipient,
+            );
+        )*
+    };
+}
+
+request_types! {
+    STANDARD_IN_DEVICE => (In, Standard, Device),
+    STANDARD_OUT_DEVICE => (Out, Standard, Device),
+    STANDARD_IN_INTERFACE => (In, Standard, Interface),
+    STANDARD_OUT_INTERFACE => (Out, Standard, Interface),
+    STANDARD_IN_ENDPOINT => (In, Standard, Endpoint),
+    STANDARD_OUT_ENDPOINT => (Out, Standard, Endpoint),
+    CLASS_IN_DEVICE => (In, Class, Device),
+    CLASS_OUT_DEVICE => (Out, Class, Device),
+    CLASS_IN_INTERFACE => (In, Class, Interface),
+    CLASS_OUT_INTERFACE => (Out, Class, Interface),
+    CLASS_IN_ENDPOINT => (In, Class, Endpoint),
+    CLASS_OUT_ENDPOINT => (Out, Class, Endpoint),
+    VENDOR_IN_DEVICE => (In, Vendor, Device),
+    VENDOR_OUT_DEVICE => (Out, Vendor, Device),
+    VENDOR_IN_INTERFACE => (In, Vendor, Interface),
+    VENDOR_OUT_INTERFACE => (Out, Vendor, Interface),
+    VENDOR_IN_ENDPOINT => (In, Vendor, Endpoint),
+    VENDOR_OUT_ENDPOINT => (Out, Vendor, Endpoint),
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn it_encodes_common_request_types() {
+        assert_eq!(0x80, STANDARD_IN_DEVICE);
+        assert_eq!(0x01, STANDARD_OUT_INTERFACE);
+        assert_eq!(0x02, STANDARD_OUT_ENDPOINT);
+        assert_eq!(0xA1, CLASS_IN_INTERFACE);
+        assert_eq!(0x21, CLASS_OUT_INTERFACE);
+        assert_eq!(0xC0, VENDOR_IN_DEVICE);
+        assert_eq!(0x40, VENDOR_OUT_DEVICE);
+    }
+}
@@ -0,0 +1,229 @@
+use std::{
+    ops::{Deref, DerefMut},
+    ptr,
+    sync::{
+        atomic::{compiler_fence, Ordering},
+        Mutex,
+    },
+};
+
+use crate::{buffer_allocator::BufferAllocator, error::Error};
+
+/// A transfer buffer for sensitive data, locked in memory and zeroed when dropped.
+///
+/// Devices such as security keys and HSM dongles exchange secrets over their endpoints. Data in an
+/// ordinary buffer can be swapped out to disk, and lingers in freed memory after the buffer is
+/// dropped. A `SecureBuffer` is locked with `mlock` so that it is never swapped out, and its
+/// contents are overwritten with zeros before it is freed.
+///
+/// The buffer dereferences to a byte slice, so it can be passed to any transfer function:
+///
+/// ```no_run
+/// # fn main() -> rusb::Result<()> {
+/// # let handle: rusb::DeviceHandle<rusb::GlobalContext> = unimplemented!();
+/// use rusb::SecureBuffer;
+/// use std::time::Duration;
+///
+/// let mut buf = SecureBuffer::new(64)?;
+/// let len = handle.read_interrupt(0x81, &mut buf, Duration::from_secs(1))?;
+/// # Ok(())
+/// # }
+/// ```
+///
+/// Copies made from the buffer, e.g. with `to_vec()`, are not protected. The buffers rusb
+/// allocates itself are protected with
+/// [`UsbOption::secure_buffers`](struct.UsbOption.html#method.secure_buffers), and device memory
+/// with
+/// [`DeviceHandle::alloc_secure_transfer_buffer`](struct.DeviceHandle.html#method.alloc_secure_transfer_buffer).
+pub struct SecureBuffer {
+    data: Box<[u8]>,
+    locked: bool,
+}
+
+impl SecureBuffer {
+    /// Allocates a zeroed buffer of `len` bytes and locks it in memory.
+    ///
+    /// ## Errors
+    ///
+    /// * `NoMem` if the buffer can't be locked, typically because the process exceeds its
+    ///   `RLIMIT_MEMLOCK` limit.
+    /// * `NotSupported` on platforms without `mlock`.
+    pub fn new(len: usize) -> crate::Result<SecureBuffer> {
+        let mut buffer = SecureBuffer::unlocked(len);
+        lock(&mut buffer.data)?;
+        buffer.locked = true;
+        Ok(buffer)
+    }
+
+    /// Allocates a zeroed buffer of `len` bytes that is zeroed on drop, but not locked in memory.
+    ///
+    /// This is a fallback for platforms or processes where locking memory isn't possible.
+    pub fn unlocked(len: usize) -> SecureBuffer {
+        SecureBuffer {
+            data: vec![0u8; len].into_boxed_slice(),
+            locked: false,
+        }
+    }
+
+    /// Indicates whether the buffer is locked in memory.
+    pub fn is_locked(&self) -> bool {
+        self.locked
+    }
+
+    /// Overwrites the buffer with zeros.
+    pub fn zeroize(&mut self) {
+        zeroize(&mut self.data);
+    }
+}
+
+impl Deref for SecureBuffer {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.data
+    }
+}
+
+impl DerefMut for SecureBuffer {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        &mut self.data
+    }
+}
+
+impl Drop for SecureBuffer {
+    fn drop(&mut self) {
+        self.zeroize();
+
+        if self.locked {
+            unlock(&mut self.data);
+        }
+    }
+}
+
+/// Hands out buffers locked in memory, and zeroes them when they are released, see
+/// [`UsbOption::secure_buffers`](struct.UsbOption.html#method.secure_buffers).
+///
+/// Released buffers are kept, still locked, for the next allocations, so the locked memory
+/// doesn't grow past the buffers in use at once.
+#[derive(Default)]
+pub(crate) struct SecureAllocator {
+    pool: Mutex<Vec<Vec<u8>>>,
+}
+
+impl SecureAllocator {
+    fn pool(&self) -> std::sync::MutexGuard<'_, Vec<Vec<u8>>> {
+        self.pool.lock().unwrap_or_else(|p| p.into_inner())
+    }
+}
+
+impl BufferAllocator for SecureAllocator {
+    fn allocate(&self, len: usize) -> Vec<u8> {
+        let mut pool = self.pool();
+        if let Some(i) = pool.iter().position(|buffer| buffer.capacity() >= len) {
+            return pool.swap_remove(i);
+        }
+        drop(pool);
+
+        let mut buffer = vec![0u8; len];
+        // best effort: the buffer is still zeroed on release if the process can't lock more
+        // memory
+        lock(&mut buffer).ok();
+        buffer
+    }
+
+    fn release(&self, mut buffer: Vec<u8>) {
+        // the whole allocation may have held data, not only the current length
+        buffer.resize(buffer.capacity(), 0);
+        zeroize(&mut buffer);
+        self.pool().push(buffer);
+    }
+}
+
+impl Drop for SecureAllocator {
+    fn drop(&mut self) {
+        for mut buffer in self.pool().drain(..) {
+            unlock(&mut buffer);
+        }
+    }
+}
+
+/// Overwrites `data` with zeros.
+pub(crate) fn zeroize(data: &mut [u8]) {
+    for byte in data.iter_mut() {
+        // volatile, so the writes aren't optimized away as dead stores
+        unsafe { ptr::write_volatile(byte, 0) };
+    }
+    compiler_fence(Ordering::SeqCst);
+}
+
+#[cfg(unix)]
+pub(crate) fn lock(data: &mut [u8]) -> crate::Result<()> {
+    if data.is_empty() {
+        return Ok(());
+    }
+
+    match unsafe { libc::mlock(data.as_ptr() as *const libc::c_void, data.len()) } {
+        0 => Ok(()),
+        _ => Err(Error::NoMem),
+    }
+}
+
+#[cfg(unix)]
+pub(crate) fn unlock(data: &mut [u8]) {
+    if !data.is_empty() {
+        unsafe { libc::munlock(data.as_ptr() as *const libc::c_void, data.len()) };
+    }
+}
+
+#[cfg(not(unix))]
+pub(crate) fn lock(_data: &mut [u8]) -> crate::Result<()> {
+    Err(Error::NotSupported)
+}
+
+#[cfg(not(unix))]
+pub(crate) fn unlock(_data: &mut [u8]) {}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn it_starts_zeroed() {
+        let buffer = SecureBuffer::unlocked(16);
+
+        assert_eq!(&[0; 16], &*buffer);
+        assert!(!buffer.is_locked());
+    }
+
+    #[test]
+    fn it_zeroizes() {
+        let mut buffer = SecureBuffer::unlocked(4);
+        buffer.copy_from_slice(&[1, 2, 3, 4]);
+
+        buffer.zeroize();
+        assert_eq!(&[0; 4], &*buffer);
+    }
+
+    #[test]
+    fn it_zeroes_released_buffers_for_reuse() {
+        let allocator = SecureAllocator::default();
+        let mut buffer = allocator.allocate(8);
+        buffer.copy_from_slice(b"secret!!");
+        buffer.truncate(2);
+
+        allocator.release(buffer);
+        let buffer = allocator.allocate(4);
+
+        assert_eq!(8, buffer.len());
+        assert_eq!(&[0; 8], &buffer[..]);
+    }
+
+    #[test]
+    fn it_locks_small_buffers() {
+        // a few bytes fit in the default RLIMIT_MEMLOCK of every common platform
+        if let Ok(buffer) = SecureBuffer::new(32) {
+            assert!(buffer.is_locked());
+            assert_eq!(32, buffer.len());
+        }
+    }
+}
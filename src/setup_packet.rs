@@ -0,0 +1,168 @@
+use std::convert::TryFrom;
+
+use libusb1_sys::constants::{LIBUSB_ENDPOINT_DIR_MASK, LIBUSB_ENDPOINT_IN};
+
+use crate::{error::Error, fields::Direction};
+
+/// The setup packet starting a control transfer.
+///
+/// The control methods of [`DeviceHandle`](struct.DeviceHandle.html) build it from their
+/// arguments. It is also useful on its own, e.g. to decode the control transfers of a capture or
+/// the requests a gadget receives.
+///
+/// ```
+/// use rusb::SetupPacket;
+///
+/// // GET_DESCRIPTOR for the device descriptor
+/// let setup = SetupPacket::new(0x80, 0x06, 0x0100, 0, 18);
+/// let bytes = setup.to_bytes();
+/// assert_eq!([0x80, 0x06, 0x00, 0x01, 0x00, 0x00, 0x12, 0x00], bytes);
+/// assert_eq!(setup, SetupPacket::from(bytes));
+/// ```
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct SetupPacket {
+    /// The `bmRequestType` field, see [`request_type`](fn.request_type.html).
+    pub request_type: u8,
+
+    /// The `bRequest` field.
+    pub request: u8,
+
+    /// The `wValue` field.
+    pub value: u16,
+
+    /// The `wIndex` field.
+    pub index: u16,
+
+    /// The `wLength` field, the length of the data stage.
+    pub length: u16,
+}
+
+impl SetupPacket {
+    /// The size of a setup packet, which precedes the data stage in control transfer buffers.
+    pub const SIZE: usize = 8;
+
+    /// Creates a setup packet from its fields.
+    pub fn new(request_type: u8, request: u8, value: u16, index: u16, length: u16) -> SetupPacket {
+        SetupPacket {
+            request_type,
+            request,
+            value,
+            index,
+            length,
+        }
+    }
+
+    /// Returns the direction of the data stage, from `bmRequestType`.
+    pub fn direction(&self) -> Direction {
+        if self.request_type & LIBUSB_ENDPOINT_DIR_MASK == LIBUSB_ENDPOINT_IN {
+            Direction::In
+        } else {
+            Direction::Out
+        }
+    }
+
+    /// Returns the setup packet as sent on the bus, with the 16-bit fields in little endian.
+    pub fn to_bytes(&self) -> [u8; SetupPacket::SIZE] {
+        let [value_lo, value_hi] = self.value.to_le_bytes();
+        let [index_lo, index_hi] = self.index.to_le_bytes();
+        let [length_lo, length_hi] = self.length.to_le_bytes();
+        [
+            self.request_type,
+            self.request,
+            value_lo,
+            value_hi,
+            index_lo,
+            index_hi,
+            length_lo,
+            length_hi,
+        ]
+    }
+
+    /// Writes the setup packet at the start of `buffer`, e.g. the buffer of a control transfer.
+    ///
+    /// ## Panics
+    ///
+    /// Panics if `buffer` is shorter than [`SIZE`](#associatedconstant.SIZE).
+    pub fn write_to(&self, buffer: &mut [u8]) {
+        buffer[..SetupPacket::SIZE].copy_from_slice(&self.to_bytes());
+    }
+}
+
+impl From<[u8; SetupPacket::SIZE]> for SetupPacket {
+    fn from(bytes: [u8; SetupPacket::SIZE]) -> SetupPacket {
+        SetupPacket {
+            request_type: bytes[0],
+            request: bytes[1],
+            value: u16::from_le_bytes([bytes[2], bytes[3]]),
+            index: u16::from_le_bytes([bytes[4], bytes[5]]),
+            length: u16::from_le_bytes([bytes[6], bytes[7]]),
+        }
+    }
+}
+
+impl From<SetupPacket> for [u8; SetupPacket::SIZE] {
+    fn from(setup: SetupPacket) -> [u8; SetupPacket::SIZE] {
+        setup.to_bytes()
+    }
+}
+
+/// Parses the setup packet at the start of `bytes`, ignoring the data stage after it.
+///
+/// ## Errors
+///
+/// * `InvalidParam` if `bytes` is shorter than a setup packet.
+impl TryFrom<&[u8]> for SetupPacket {
+    type Error = Error;
+
+    fn try_from(bytes: &[u8]) -> crate::Result<SetupPacket> {
+        match bytes.get(..SetupPacket::SIZE) {
+            Some(setup) => {
+                let mut raw = [0; SetupPacket::SIZE];
+                raw.copy_from_slice(setup);
+                Ok(SetupPacket::from(raw))
+            }
+            None => Err(Error::InvalidParam),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn it_round_trips_through_bytes() {
+        let setup = SetupPacket::new(0x21, 0x09, 0x0200, 0x0001, 0x0040);
+        assert_eq!(
+            [0x21, 0x09, 0x00, 0x02, 0x01, 0x00, 0x40, 0x00],
+            setup.to_bytes()
+        );
+        assert_eq!(setup, SetupPacket::from(setup.to_bytes()));
+    }
+
+    #[test]
+    fn it_parses_the_start_of_a_control_buffer() {
+        let buffer = [0xC0, 0x01, 0x34, 0x12, 0x02, 0x00, 0x02, 0x00, 0xAA, 0xBB];
+
+        let setup = SetupPacket::try_from(&buffer[..]).unwrap();
+        assert_eq!(SetupPacket::new(0xC0, 0x01, 0x1234, 0x0002, 2), setup);
+        assert_eq!(Direction::In, setup.direction());
+
+        assert_eq!(
+            Err(Error::InvalidParam),
+            SetupPacket::try_from(&buffer[..7])
+        );
+    }
+
+    #[test]
+    fn it_has_direction() {
+        assert_eq!(
+            Direction::Out,
+            SetupPacket::new(0x40, 0, 0, 0, 0).direction()
+        );
+        assert_eq!(
+            Direction::In,
+            SetupPacket::new(0x80, 0, 0, 0, 0).direction()
+        );
+    }
+}
@@ -0,0 +1,323 @@
+use std::time::Duration;
+
+use libusb1_sys::constants::*;
+
+use crate::{
+    device::Device,
+    device_handle::DeviceHandle,
+    device_io::DeviceIo,
+    error::Error,
+    fields::{Direction, TransferType},
+    interface_descriptor::InterfaceDescriptor,
+    UsbContext,
+};
+
+/// A vendor-specific interface consisting of one bulk IN and one bulk OUT endpoint.
+///
+/// This is the interface most microcontroller firmwares expose for a custom protocol (and the
+/// shape WinUSB devices typically have). Opening one finds the interface, claims it and remembers
+/// its endpoints, so the device can be used with [`send`](#method.send) and
+/// [`recv`](#method.recv) straight away.
+///
+/// `send` and `recv` exchange whole messages, using USB transfer boundaries as framing: a
+/// message ends with a packet shorter than the endpoint's maximum packet size, and a zero-length
+/// packet is sent after messages whose length is a multiple of it. This matches the behavior of
+/// most device-side USB stacks. [`write`](#method.write) and [`read`](#method.read) perform
+/// single transfers for protocols that frame data differently.
+pub struct SimpleVendorDevice<T: UsbContext> {
+    handle: DeviceHandle<T>,
+    interface: u8,
+    setting: u8,
+    endpoint_in: u8,
+    endpoint_out: u8,
+    max_packet_size: usize,
+}
+
+impl<T: UsbContext> SimpleVendorDevice<T> {
+    /// Opens `device` and claims its first vendor-specific bulk interface.
+    ///
+    /// ## Errors
+    ///
+    /// * `NotFound` if the active configuration has no vendor-specific interface with exactly
+    ///   one bulk IN and one bulk OUT endpoint.
+    /// * Any error returned when opening the device or claiming the interface.
+    pub fn open(device: &Device<T>) -> crate::Result<SimpleVendorDevice<T>> {
+        SimpleVendorDevice::from_handle(device.open()?)
+    }
+
+    /// Claims the first vendor-specific bulk interface of an already open device.
+    ///
+    /// Kernel drivers bound to the interface are detached where the platform supports it.
+    ///
+    /// ## Errors
+    ///
+    /// * `NotFound` if the active configuration has no vendor-specific interface with exactly
+    ///   one bulk IN and one bulk OUT endpoint.
+    /// * Any error returned when claiming the interface.
+    pub fn from_handle(mut handle: DeviceHandle<T>) -> crate::Result<SimpleVendorDevice<T>> {
+        let config = handle.device().active_config_descriptor()?;
+
+        let found = config
+            .interfaces()
+            .flat_map(|interface| interface.descriptors())
+            .find_map(|descriptor| BulkPair::find(&descriptor))
+            .ok_or(Error::NotFound)?;
+
+        // not every platform can detach drivers, and vendor interfaces rarely have one
+        handle.set_auto_detach_kernel_driver(true).ok();
+        handle.claim_interface(found.interface)?;
+        if found.setting != 0 {
+            handle.set_alternate_setting(found.interface, found.setting)?;
+        }
+
+        Ok(SimpleVendorDevice {
+            handle,
+            interface: found.interface,
+            setting: found.setting,
+            endpoint_in: found.endpoint_in,
+            endpoint_out: found.endpoint_out,
+            max_packet_size: found.max_packet_size,
+        })
+    }
+
+    /// Returns the underlying device handle.
+    pub fn handle(&self) -> &DeviceHandle<T> {
+        &self.handle
+    }
+
+    /// Releases the interface and returns the underlying device handle.
+    pub fn into_handle(mut self) -> DeviceHandle<T> {
+        self.handle.release_interface(self.interface).ok();
+        self.handle
+    }
+
+    /// Returns the number of the claimed interface.
+    pub fn interface_number(&self) -> u8 {
+        self.interface
+    }
+
+    /// Returns the selected alternate setting of the claimed interface.
+    pub fn setting_number(&self) -> u8 {
+        self.setting
+    }
+
+    /// Returns the address of the bulk IN endpoint.
+    pub fn in_endpoint(&self) -> u8 {
+        self.endpoint_in
+    }
+
+    /// Returns the address of the bulk OUT endpoint.
+    pub fn out_endpoint(&self) -> u8 {
+        self.endpoint_out
+    }
+
+    /// Returns the smaller of the two endpoints' maximum packet sizes.
+    pub fn max_packet_size(&self) -> usize {
+        self.max_packet_size
+    }
+
+    /// Sends a message, terminating it with a zero-length packet if needed.
+    ///
+    /// `timeout` applies to each transfer making up the message.
+    #[cfg_attr(feature = "async-only", deprecated(note = "blocks the calling thread"))]
+    pub fn send(&self, message: &[u8], timeout: Duration) -> crate::Result<()> {
+        send_message(
+            &self.handle,
+            self.endpoint_out,
+            self.max_packet_size,
+            message,
+            timeout,
+        )
+    }
+
+    /// Receives a message, reading until the device sends a short or zero-length packet.
+    ///
+    /// `timeout` applies to each transfer making up the message.
+    #[cfg_attr(feature = "async-only", deprecated(note = "blocks the calling thread"))]
+    pub fn recv(&self, timeout: Duration) -> crate::Result<Vec<u8>> {
+        recv_message(
+            &self.handle,
+            self.endpoint_in,
+            self.max_packet_size,
+            timeout,
+        )
+    }
+
+    /// Writes `buf` to the OUT endpoint in a single transfer.
+    #[cfg_attr(feature = "async-only", deprecated(note = "blocks the calling thread"))]
+    #[cfg_attr(feature = "async-only", allow(deprecated))]
+    pub fn write(&self, buf: &[u8], timeout: Duration) -> crate::Result<usize> {
+        self.handle.write_bulk(self.endpoint_out, buf, timeout)
+    }
+
+    /// Reads from the IN endpoint in a single transfer.
+    #[cfg_attr(feature = "async-only", deprecated(note = "blocks the calling thread"))]
+    #[cfg_attr(feature = "async-only", allow(deprecated))]
+    pub fn read(&self, buf: &mut [u8], timeout: Duration) -> crate::Result<usize> {
+        self.handle.read_bulk(self.endpoint_in, buf, timeout)
+    }
+}
+
+struct BulkPair {
+    interface: u8,
+    setting: u8,
+    endpoint_in: u8,
+    endpoint_out: u8,
+    max_packet_size: usize,
+}
+
+impl BulkPair {
+    fn find(descriptor: &InterfaceDescriptor<'_>) -> Option<BulkPair> {
+        if descriptor.class_code() != LIBUSB_CLASS_VENDOR_SPEC || descriptor.num_endpoints() != 2 {
+            return None;
+        }
+
+        let mut endpoint_in = None;
+        let mut endpoint_out = None;
+
+        for endpoint in descriptor.endpoint_descriptors() {
+            if endpoint.transfer_type() != TransferType::Bulk {
+                return None;
+            }
+
+            let slot = match endpoint.direction() {
+                Direction::In => &mut endpoint_in,
+                Direction::Out => &mut endpoint_out,
+            };
+            *slot = Some((endpoint.address(), endpoint.max_packet_size() as usize));
+        }
+
+        let (endpoint_in, in_size) = endpoint_in?;
+        let (endpoint_out, out_size) = endpoint_out?;
+
+        Some(BulkPair {
+            interface: descriptor.interface_number(),
+            setting: descriptor.setting_number(),
+            endpoint_in,
+            endpoint_out,
+            max_packet_size: in_size.min(out_size).max(1),
+        })
+    }
+}
+
+#[cfg_attr(feature = "async-only", allow(deprecated))]
+fn send_message<D: DeviceIo>(
+    device: &D,
+    endpoint: u8,
+    max_packet_size: usize,
+    message: &[u8],
+    timeout: Duration,
+) -> crate::Result<()> {
+    let mut sent = 0;
+    while sent < message.len() {
+        sent += device.write_bulk(endpoint, &message[sent..], timeout)?;
+    }
+
+    if message.len() % max_packet_size == 0 {
+        device.write_bulk(endpoint, &[], timeout)?;
+    }
+
+    Ok(())
+}
+
+#[cfg_attr(feature = "async-only", allow(deprecated))]
+fn recv_message<D: DeviceIo>(
+    device: &D,
+    endpoint: u8,
+    max_packet_size: usize,
+    timeout: Duration,
+) -> crate::Result<Vec<u8>> {
+    // a whole number of packets, so a short read always means a short packet
+    let chunk = max_packet_size * (4096 / max_packet_size).max(1);
+
+    let mut message = Vec::new();
+    loop {
+        let start = message.len();
+        message.resize(start + chunk, 0);
+
+        let len = device.read_bulk(endpoint, &mut message[start..], timeout)?;
+        message.truncate(start + len);
+
+        if len < chunk {
+            return Ok(message);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::fake::FakeDevice;
+
+    const TIMEOUT: Duration = Duration::from_secs(1);
+
+    fn find(mut descriptor: libusb1_sys::libusb_interface_descriptor) -> Option<BulkPair> {
+        descriptor.bInterfaceNumber = 2;
+        let iface = interface!(descriptor);
+        let iface = unsafe { crate::interface_descriptor::from_libusb(&iface) };
+        let found = iface.descriptors().find_map(|d| BulkPair::find(&d));
+        found
+    }
+
+    #[test]
+    fn it_finds_vendor_bulk_pairs() {
+        let mut descriptor = interface_descriptor!(
+            endpoint_descriptor!(bEndpointAddress: 0x81, bmAttributes: 0x02, wMaxPacketSize: 512),
+            endpoint_descriptor!(bEndpointAddress: 0x02, bmAttributes: 0x02, wMaxPacketSize: 512)
+        );
+        descriptor.bInterfaceClass = LIBUSB_CLASS_VENDOR_SPEC;
+
+        let pair = find(descriptor).unwrap();
+        assert_eq!(2, pair.interface);
+        assert_eq!(0x81, pair.endpoint_in);
+        assert_eq!(0x02, pair.endpoint_out);
+        assert_eq!(512, pair.max_packet_size);
+    }
+
+    #[test]
+    fn it_ignores_other_interfaces() {
+        let descriptor = interface_descriptor!(
+            endpoint_descriptor!(bEndpointAddress: 0x81, bmAttributes: 0x02),
+            endpoint_descriptor!(bEndpointAddress: 0x02, bmAttributes: 0x02)
+        );
+        assert!(find(descriptor).is_none());
+
+        let mut descriptor = interface_descriptor!(
+            endpoint_descriptor!(bEndpointAddress: 0x81, bmAttributes: 0x03),
+            endpoint_descriptor!(bEndpointAddress: 0x02, bmAttributes: 0x02)
+        );
+        descriptor.bInterfaceClass = LIBUSB_CLASS_VENDOR_SPEC;
+        assert!(find(descriptor).is_none());
+
+        let mut descriptor = interface_descriptor!(
+            endpoint_descriptor!(bEndpointAddress: 0x81, bmAttributes: 0x02),
+            endpoint_descriptor!(bEndpointAddress: 0x82, bmAttributes: 0x02)
+        );
+        descriptor.bInterfaceClass = LIBUSB_CLASS_VENDOR_SPEC;
+        assert!(find(descriptor).is_none());
+    }
+
+    #[test]
+    fn it_terminates_full_packets_with_a_zero_length_packet() {
+        let device = FakeDevice::new();
+
+        send_message(&device, 0x02, 4, &[1, 2, 3], TIMEOUT).unwrap();
+        send_message(&device, 0x02, 4, &[1, 2, 3, 4], TIMEOUT).unwrap();
+
+        assert_eq!(
+            vec![vec![1, 2, 3], vec![1, 2, 3, 4], vec![]],
+            device.take_out(0x02)
+        );
+    }
+
+    #[test]
+    fn it_reads_until_a_short_packet() {
+        let device = FakeDevice::new();
+        device.push_in(0x81, &[7; 4096]);
+        device.push_in(0x81, &[8; 10]);
+
+        let message = recv_message(&device, 0x81, 512, TIMEOUT).unwrap();
+        assert_eq!(4106, message.len());
+        assert_eq!(8, message[4105]);
+    }
+}
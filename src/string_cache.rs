@@ -0,0 +1,352 @@
+use std::{collections::HashMap, sync::Mutex, time::Duration};
+
+use crate::{
+    device_descriptor::DeviceDescriptor, device_handle::DeviceHandle, error::Error,
+    language::Language, UsbContext,
+};
+
+/// String descriptors already read through a handle, keyed by language (`None` for the ASCII
+/// reads) and index.
+#[derive(Default)]
+pub(crate) struct StringCache {
+    entries: Mutex<HashMap<(Option<u16>, u8), String>>,
+
+    /// The `LANGID`s the device supports, once read.
+    languages: Mutex<Option<Vec<u16>>>,
+}
+
+/// The timeout of [`Strings`](struct.Strings.html) reads, unless set otherwise.
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(1);
+
+impl StringCache {
+    fn get(&self, key: (Option<u16>, u8)) -> Option<String> {
+        self.lock().get(&key).cloned()
+    }
+
+    fn insert(&self, key: (Option<u16>, u8), value: &str) {
+        self.lock().insert(key, value.to_string());
+    }
+
+    fn languages(&self) -> Option<Vec<u16>> {
+        self.languages
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .clone()
+    }
+
+    fn set_languages(&self, languages: Vec<u16>) {
+        *self
+            .languages
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner()) = Some(languages);
+    }
+
+    pub(crate) fn clear(&self) {
+        self.lock().clear();
+        self.languages
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .take();
+    }
+
+    fn len(&self) -> usize {
+        self.lock().len()
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<(Option<u16>, u8), String>> {
+        // the map stays consistent even if a panic happened while it was locked
+        self.entries
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+/// Reads string descriptors through a handle, remembering them for later reads.
+///
+/// Some devices answer string requests slowly, while logging code tends to fetch the same strings
+/// over and over. Successful reads are kept by the handle, shared between threads, until the
+/// device is reset with [`DeviceHandle::reset`](struct.DeviceHandle.html#method.reset) or the
+/// cache is [`invalidate`](#method.invalidate)d. Failed reads are not cached.
+///
+/// Obtained from [`DeviceHandle::cached_strings`](struct.DeviceHandle.html#method.cached_strings).
+pub struct CachedStrings<'a, T: UsbContext> {
+    handle: &'a DeviceHandle<T>,
+    cache: &'a StringCache,
+}
+
+impl<'a, T: UsbContext> CachedStrings<'a, T> {
+    pub(crate) fn new(handle: &'a DeviceHandle<T>, cache: &'a StringCache) -> Self {
+        CachedStrings { handle, cache }
+    }
+
+    /// Reads a string descriptor, see
+    /// [`DeviceHandle::read_string_descriptor`](struct.DeviceHandle.html#method.read_string_descriptor).
+    #[cfg_attr(feature = "async-only", deprecated(note = "blocks the calling thread"))]
+    #[cfg_attr(feature = "async-only", allow(deprecated))]
+    pub fn read_string_descriptor(
+        &self,
+        language: Language,
+        index: u8,
+        timeout: Duration,
+    ) -> crate::Result<String> {
+        let key = (Some(language.lang_id()), index);
+        if let Some(string) = self.cache.get(key) {
+            return Ok(string);
+        }
+
+        let string = self
+            .handle
+            .read_string_descriptor(language, index, timeout)?;
+        self.cache.insert(key, &string);
+        Ok(string)
+    }
+
+    /// Reads an ASCII string descriptor, see
+    /// [`DeviceHandle::read_string_descriptor_ascii`](struct.DeviceHandle.html#method.read_string_descriptor_ascii).
+    #[cfg_attr(feature = "async-only", deprecated(note = "blocks the calling thread"))]
+    #[cfg_attr(feature = "async-only", allow(deprecated))]
+    pub fn read_string_descriptor_ascii(&self, index: u8) -> crate::Result<String> {
+        let key = (None, index);
+        if let Some(string) = self.cache.get(key) {
+            return Ok(string);
+        }
+
+        let string = self.handle.read_string_descriptor_ascii(index)?;
+        self.cache.insert(key, &string);
+        Ok(string)
+    }
+
+    /// Reads the device's manufacturer string descriptor.
+    #[cfg_attr(feature = "async-only", deprecated(note = "blocks the calling thread"))]
+    pub fn read_manufacturer_string(
+        &self,
+        language: Language,
+        device: &DeviceDescriptor,
+        timeout: Duration,
+    ) -> crate::Result<String> {
+        self.read_index(language, device.manufacturer_string_index(), timeout)
+    }
+
+    /// Reads the device's product string descriptor.
+    #[cfg_attr(feature = "async-only", deprecated(note = "blocks the calling thread"))]
+    pub fn read_product_string(
+        &self,
+        language: Language,
+        device: &DeviceDescriptor,
+        timeout: Duration,
+    ) -> crate::Result<String> {
+        self.read_index(language, device.product_string_index(), timeout)
+    }
+
+    /// Reads the device's serial number string descriptor.
+    #[cfg_attr(feature = "async-only", deprecated(note = "blocks the calling thread"))]
+    pub fn read_serial_number_string(
+        &self,
+        language: Language,
+        device: &DeviceDescriptor,
+        timeout: Duration,
+    ) -> crate::Result<String> {
+        self.read_index(language, device.serial_number_string_index(), timeout)
+    }
+
+    /// Returns the number of cached strings.
+    pub fn len(&self) -> usize {
+        self.cache.len()
+    }
+
+    /// Indicates whether no string is cached.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Forgets every cached string, e.g. after the device's firmware changed them.
+    pub fn invalidate(&self) {
+        self.cache.clear();
+    }
+
+    #[cfg_attr(feature = "async-only", allow(deprecated))]
+    fn read_index(
+        &self,
+        language: Language,
+        index: Option<u8>,
+        timeout: Duration,
+    ) -> crate::Result<String> {
+        match index {
+            None => Err(Error::InvalidParam),
+            Some(n) => self.read_string_descriptor(language, n, timeout),
+        }
+    }
+}
+
+/// Reads the strings of a device in one call each, handling languages and caching.
+///
+/// Reading a string descriptor takes a language, which has to be picked from the languages the
+/// device lists, and a timeout. This reader picks the first language the device lists, usually
+/// English (United States), unless told otherwise with [`language`](#method.language), and
+/// decodes the UTF-16 strings, including characters outside the Basic Multilingual Plane.
+/// Strings and languages are cached in the handle, like with
+/// [`CachedStrings`](struct.CachedStrings.html).
+///
+/// ```no_run
+/// # fn main() -> rusb::Result<()> {
+/// # let handle: rusb::DeviceHandle<rusb::GlobalContext> = unimplemented!();
+/// let strings = handle.strings();
+/// println!("{} {}", strings.manufacturer()?, strings.product()?);
+/// # Ok(())
+/// # }
+/// ```
+///
+/// Obtained from [`DeviceHandle::strings`](struct.DeviceHandle.html#method.strings).
+pub struct Strings<'a, T: UsbContext> {
+    handle: &'a DeviceHandle<T>,
+    cached: CachedStrings<'a, T>,
+    language: Option<Language>,
+    timeout: Duration,
+}
+
+impl<'a, T: UsbContext> Strings<'a, T> {
+    pub(crate) fn new(handle: &'a DeviceHandle<T>, cache: &'a StringCache) -> Self {
+        Strings {
+            handle,
+            cached: CachedStrings::new(handle, cache),
+            language: None,
+            timeout: DEFAULT_TIMEOUT,
+        }
+    }
+
+    /// Reads the strings in `language` instead of the first language the device lists.
+    pub fn language(mut self, language: Language) -> Self {
+        self.language = Some(language);
+        self
+    }
+
+    /// Sets the timeout of each read. Defaults to one second.
+    ///
+    /// A timeout set with
+    /// [`DeviceHandle::set_descriptor_timeout`](struct.DeviceHandle.html#method.set_descriptor_timeout)
+    /// takes precedence.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Returns the languages of the device's strings.
+    #[cfg_attr(feature = "async-only", deprecated(note = "blocks the calling thread"))]
+    #[cfg_attr(feature = "async-only", allow(deprecated))]
+    pub fn languages(&self) -> crate::Result<Vec<Language>> {
+        let lang_ids = match self.cached.cache.languages() {
+            Some(lang_ids) => lang_ids,
+            None => {
+                let languages = self.handle.read_languages(self.timeout)?;
+                let lang_ids = languages.iter().map(|l| l.lang_id()).collect();
+                self.cached.cache.set_languages(lang_ids);
+                return Ok(languages);
+            }
+        };
+
+        Ok(lang_ids
+            .into_iter()
+            .map(crate::language::from_lang_id)
+            .collect())
+    }
+
+    /// Reads the manufacturer string.
+    ///
+    /// ## Errors
+    ///
+    /// * `InvalidParam` if the device has no manufacturer string.
+    /// * `NotFound` if the device lists no language.
+    #[cfg_attr(feature = "async-only", deprecated(note = "blocks the calling thread"))]
+    pub fn manufacturer(&self) -> crate::Result<String> {
+        self.read_index(self.descriptor()?.manufacturer_string_index())
+    }
+
+    /// Reads the product string.
+    ///
+    /// ## Errors
+    ///
+    /// * `InvalidParam` if the device has no product string.
+    /// * `NotFound` if the device lists no language.
+    #[cfg_attr(feature = "async-only", deprecated(note = "blocks the calling thread"))]
+    pub fn product(&self) -> crate::Result<String> {
+        self.read_index(self.descriptor()?.product_string_index())
+    }
+
+    /// Reads the serial number string.
+    ///
+    /// ## Errors
+    ///
+    /// * `InvalidParam` if the device has no serial number string.
+    /// * `NotFound` if the device lists no language.
+    #[cfg_attr(feature = "async-only", deprecated(note = "blocks the calling thread"))]
+    pub fn serial_number(&self) -> crate::Result<String> {
+        self.read_index(self.descriptor()?.serial_number_string_index())
+    }
+
+    /// Reads the string at `index`, e.g. the description of a configuration or interface.
+    ///
+    /// ## Errors
+    ///
+    /// * `NotFound` if the device lists no language.
+    #[cfg_attr(feature = "async-only", deprecated(note = "blocks the calling thread"))]
+    #[cfg_attr(feature = "async-only", allow(deprecated))]
+    pub fn read(&self, index: u8) -> crate::Result<String> {
+        let language = match self.language {
+            Some(language) => language,
+            None => *self.languages()?.first().ok_or(Error::NotFound)?,
+        };
+
+        self.cached
+            .read_string_descriptor(language, index, self.timeout)
+    }
+
+    fn descriptor(&self) -> crate::Result<DeviceDescriptor> {
+        self.handle.device().device_descriptor()
+    }
+
+    #[cfg_attr(feature = "async-only", allow(deprecated))]
+    fn read_index(&self, index: Option<u8>) -> crate::Result<String> {
+        match index {
+            None => Err(Error::InvalidParam),
+            Some(n) => self.read(n),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn it_keeps_strings_per_language_and_index() {
+        let cache = StringCache::default();
+        cache.insert((Some(0x0409), 1), "ACME");
+        cache.insert((None, 1), "acme");
+
+        assert_eq!(Some("ACME".to_string()), cache.get((Some(0x0409), 1)));
+        assert_eq!(Some("acme".to_string()), cache.get((None, 1)));
+        assert_eq!(None, cache.get((Some(0x0407), 1)));
+        assert_eq!(2, cache.len());
+    }
+
+    #[test]
+    fn it_clears() {
+        let cache = StringCache::default();
+        cache.insert((Some(0x0409), 1), "ACME");
+        cache.set_languages(vec![0x0409]);
+
+        cache.clear();
+        assert_eq!(None, cache.get((Some(0x0409), 1)));
+        assert_eq!(0, cache.len());
+        assert_eq!(None, cache.languages());
+    }
+
+    #[test]
+    fn it_keeps_languages() {
+        let cache = StringCache::default();
+        assert_eq!(None, cache.languages());
+
+        cache.set_languages(vec![0x0409, 0x0407]);
+        assert_eq!(Some(vec![0x0409, 0x0407]), cache.languages());
+    }
+}
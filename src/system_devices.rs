@@ -0,0 +1,281 @@
+//! Read-only device enumeration from sysfs on Linux, without libusb.
+//!
+//! libusb can list devices it can't open, but reading their string descriptors needs a handle, so
+//! a device picker running without access to the devices shows nothing but IDs. The kernel
+//! already knows the descriptors and strings of every attached device, and this module reads them
+//! from sysfs. Each [`SystemDevice`] also tells whether the current user can open it, so an
+//! application can explain how to fix access (e.g. a udev rule) instead of failing silently.
+//!
+//! The module is only available on Linux. There is no backend for the IOKit registry on macOS or
+//! SetupAPI on Windows, where this information has to come from libusb.
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    device_descriptor::DeviceDescriptor,
+    error::{self, Error},
+    fields::Speed,
+};
+
+const SYSFS_USB_DEVICES: &str = "/sys/bus/usb/devices";
+
+const USBFS_DEVICES: &str = "/dev/bus/usb";
+
+/// A device attached to the system, as described by the operating system.
+#[derive(Debug)]
+pub struct SystemDevice {
+    bus_number: u8,
+    address: u8,
+    port_numbers: Vec<u8>,
+    speed: Speed,
+    descriptor: DeviceDescriptor,
+    descriptors: Vec<u8>,
+    manufacturer: Option<String>,
+    product: Option<String>,
+    serial_number: Option<String>,
+    node: PathBuf,
+}
+
+impl SystemDevice {
+    /// Returns the number of the bus the device is connected to.
+    pub fn bus_number(&self) -> u8 {
+        self.bus_number
+    }
+
+    /// Returns the device's address on the bus.
+    pub fn address(&self) -> u8 {
+        self.address
+    }
+
+    /// Returns the ports from the root hub to the device, empty for root hubs.
+    pub fn port_numbers(&self) -> &[u8] {
+        &self.port_numbers
+    }
+
+    /// Returns the speed the device is operating at.
+    pub fn speed(&self) -> Speed {
+        self.speed
+    }
+
+    /// Returns the device descriptor.
+    pub fn device_descriptor(&self) -> &DeviceDescriptor {
+        &self.descriptor
+    }
+
+    /// Returns the raw configuration descriptors the operating system read from the device.
+    ///
+    /// Each one can be decoded with
+    /// [`ConfigDescriptorView::parse`](../struct.ConfigDescriptorView.html#method.parse).
+    pub fn config_descriptors(&self) -> Vec<&[u8]> {
+        split_configs(&self.descriptors[self.descriptor_len()..])
+    }
+
+    /// Returns the manufacturer string, if the device has one.
+    pub fn manufacturer(&self) -> Option<&str> {
+        self.manufacturer.as_deref()
+    }
+
+    /// Returns the product string, if the device has one.
+    pub fn product(&self) -> Option<&str> {
+        self.product.as_deref()
+    }
+
+    /// Returns the serial number string, if the device has one.
+    pub fn serial_number(&self) -> Option<&str> {
+        self.serial_number.as_deref()
+    }
+
+    /// Returns the device node libusb opens, e.g. `/dev/bus/usb/001/004`.
+    pub fn device_node(&self) -> &Path {
+        &self.node
+    }
+
+    /// Checks whether the current user can open the device with libusb.
+    ///
+    /// ## Errors
+    ///
+    /// * `Access` if the device node can't be opened for reading and writing, which usually
+    ///   calls for a udev rule or group membership.
+    /// * `NotFound` if the device node doesn't exist, e.g. the device was unplugged.
+    pub fn check_access(&self) -> crate::Result<()> {
+        fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(&self.node)
+            .map(drop)
+            .map_err(|e| error::from_io_error(&e))
+    }
+
+    fn descriptor_len(&self) -> usize {
+        (self.descriptors[0] as usize).clamp(18, self.descriptors.len())
+    }
+}
+
+/// Lists the devices attached to the system, without opening them.
+///
+/// Devices whose attributes can't be read, e.g. because they are unplugged during the scan, are
+/// left out.
+///
+/// ## Errors
+///
+/// * `Access`, `NotFound` or `Io` if sysfs can't be read.
+pub fn devices() -> crate::Result<Vec<SystemDevice>> {
+    scan(Path::new(SYSFS_USB_DEVICES), Path::new(USBFS_DEVICES))
+}
+
+fn scan(sysfs: &Path, usbfs: &Path) -> crate::Result<Vec<SystemDevice>> {
+    let entries = fs::read_dir(sysfs).map_err(|e| error::from_io_error(&e))?;
+
+    let mut devices: Vec<SystemDevice> = entries
+        .filter_map(|entry| {
+            let entry = entry.ok()?;
+            let name = entry.file_name().into_string().ok()?;
+            // interfaces are listed next to the devices, e.g. `1-4:1.0`
+            if name.contains(':') {
+                return None;
+            }
+            read_device(&sysfs.join(&name), &name, usbfs).ok()
+        })
+        .collect();
+
+    devices.sort_by_key(|d| (d.bus_number, d.address));
+    Ok(devices)
+}
+
+fn read_device(dir: &Path, name: &str, usbfs: &Path) -> crate::Result<SystemDevice> {
+    let bus_number = read_number(&dir.join("busnum"))?;
+    let address = read_number(&dir.join("devnum"))?;
+    let descriptors = fs::read(dir.join("descriptors")).map_err(|e| error::from_io_error(&e))?;
+    let descriptor = DeviceDescriptor::from_bytes(&descriptors).ok_or(Error::Other)?;
+
+    Ok(SystemDevice {
+        bus_number,
+        address,
+        port_numbers: port_numbers(name),
+        speed: read_string(&dir.join("speed"))
+            .map(|s| parse_speed(&s))
+            .unwrap_or(Speed::Unknown),
+        descriptor,
+        descriptors,
+        manufacturer: read_string(&dir.join("manufacturer")),
+        product: read_string(&dir.join("product")),
+        serial_number: read_string(&dir.join("serial")),
+        node: usbfs
+            .join(format!("{:03}", bus_number))
+            .join(format!("{:03}", address)),
+    })
+}
+
+fn read_string(path: &Path) -> Option<String> {
+    let value = fs::read_to_string(path).ok()?;
+    Some(value.trim_end_matches('\n').to_owned())
+}
+
+fn read_number(path: &Path) -> crate::Result<u8> {
+    let value = fs::read_to_string(path).map_err(|e| error::from_io_error(&e))?;
+    value.trim().parse().map_err(|_| Error::Other)
+}
+
+/// Returns the ports in the sysfs name of a device, e.g. `[4, 2]` for `1-4.2`.
+fn port_numbers(name: &str) -> Vec<u8> {
+    match name.split_once('-') {
+        Some((_, ports)) => ports.split('.').filter_map(|p| p.parse().ok()).collect(),
+        None => Vec::new(),
+    }
+}
+
+/// Decodes the `speed` attribute, in Mbps.
+fn parse_speed(value: &str) -> Speed {
+    match value.trim() {
+        "1.5" => Speed::Low,
+        "12" => Speed::Full,
+        "480" => Speed::High,
+        "5000" | "10000" | "20000" => Speed::Super,
+        _ => Speed::Unknown,
+    }
+}
+
+/// Splits concatenated configuration descriptors by their `wTotalLength`, stopping at the first
+/// one that is truncated.
+fn split_configs(mut raw: &[u8]) -> Vec<&[u8]> {
+    let mut configs = Vec::new();
+
+    while raw.len() >= 4 {
+        let len = u16::from_le_bytes([raw[2], raw[3]]) as usize;
+        if len < 4 || len > raw.len() {
+            break;
+        }
+        let (config, rest) = raw.split_at(len);
+        configs.push(config);
+        raw = rest;
+    }
+
+    configs
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn it_decodes_port_numbers() {
+        assert_eq!(vec![4, 2], port_numbers("1-4.2"));
+        assert!(port_numbers("usb1").is_empty());
+    }
+
+    #[test]
+    fn it_decodes_speeds() {
+        assert_eq!(Speed::Low, parse_speed("1.5\n"));
+        assert_eq!(Speed::High, parse_speed("480\n"));
+        assert_eq!(Speed::Super, parse_speed("10000\n"));
+        assert_eq!(Speed::Unknown, parse_speed("53.3\n"));
+    }
+
+    #[test]
+    fn it_splits_configurations() {
+        let raw = [
+            9, 2, 9, 0, 1, 1, 0, 0x80, 50, 9, 2, 12, 0, 1, 2, 0, 0x80, 50, 0, 0, 0, 9, 2,
+        ];
+
+        let configs = split_configs(&raw);
+
+        assert_eq!(2, configs.len());
+        assert_eq!(9, configs[0].len());
+        assert_eq!(12, configs[1].len());
+    }
+
+    #[test]
+    fn it_scans_sysfs() {
+        let root = std::env::temp_dir().join(format!("rusb-sysfs-{}", std::process::id()));
+        let device = root.join("1-4");
+        fs::create_dir_all(&device).unwrap();
+        fs::create_dir_all(root.join("1-4:1.0")).unwrap();
+        fs::write(device.join("busnum"), "1\n").unwrap();
+        fs::write(device.join("devnum"), "7\n").unwrap();
+        fs::write(device.join("speed"), "480\n").unwrap();
+        fs::write(device.join("product"), "Widget\n").unwrap();
+        let mut descriptors = vec![
+            18, 1, 0, 2, 0, 0, 0, 64, 0x34, 0x12, 0x78, 0x56, 0, 1, 1, 2, 0, 1,
+        ];
+        descriptors.extend_from_slice(&[9, 2, 9, 0, 0, 1, 0, 0x80, 50]);
+        fs::write(device.join("descriptors"), &descriptors).unwrap();
+
+        let devices = scan(&root, Path::new("/dev/bus/usb"));
+        fs::remove_dir_all(&root).unwrap();
+
+        let devices = devices.unwrap();
+        assert_eq!(1, devices.len());
+        let device = &devices[0];
+        assert_eq!((1, 7), (device.bus_number(), device.address()));
+        assert_eq!(&[4], device.port_numbers());
+        assert_eq!(Speed::High, device.speed());
+        assert_eq!(0x1234, device.device_descriptor().vendor_id());
+        assert_eq!(Some("Widget"), device.product());
+        assert_eq!(None, device.manufacturer());
+        assert_eq!(1, device.config_descriptors().len());
+        assert_eq!(Path::new("/dev/bus/usb/001/007"), device.device_node());
+    }
+}
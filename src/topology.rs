@@ -0,0 +1,166 @@
+use std::{cmp::Reverse, collections::HashMap, fmt};
+
+use crate::{device::Device, UsbContext};
+
+/// A device in the tree of hubs and devices built by
+/// [`UsbContext::topology`](trait.UsbContext.html#method.topology), with the devices attached
+/// behind it.
+///
+/// A device is identified by its bus number and the ports leading to it from the root hub, which
+/// stay the same across reboots as long as it is plugged into the same physical port, unlike its
+/// address.
+pub struct TopologyNode<T: UsbContext> {
+    device: Device<T>,
+    bus_number: u8,
+    port_numbers: Vec<u8>,
+    children: Vec<TopologyNode<T>>,
+}
+
+impl<T: UsbContext> TopologyNode<T> {
+    /// Returns the device.
+    pub fn device(&self) -> &Device<T> {
+        &self.device
+    }
+
+    /// Returns the number of the bus the device is connected to.
+    pub fn bus_number(&self) -> u8 {
+        self.bus_number
+    }
+
+    /// Returns the port numbers from the root hub down to the device, empty for a root hub.
+    pub fn port_numbers(&self) -> &[u8] {
+        &self.port_numbers
+    }
+
+    /// Returns the devices attached behind this one, ordered by port.
+    pub fn children(&self) -> &[TopologyNode<T>] {
+        &self.children
+    }
+
+    /// Looks for the device plugged into `port_numbers` on `bus_number`, among this device and
+    /// the devices behind it.
+    pub fn find(&self, bus_number: u8, port_numbers: &[u8]) -> Option<&TopologyNode<T>> {
+        if self.bus_number != bus_number || !port_numbers.starts_with(&self.port_numbers) {
+            return None;
+        }
+        if self.port_numbers == port_numbers {
+            return Some(self);
+        }
+
+        self.children
+            .iter()
+            .find_map(|child| child.find(bus_number, port_numbers))
+    }
+}
+
+impl<T: UsbContext> fmt::Debug for TopologyNode<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TopologyNode")
+            .field("bus_number", &self.bus_number)
+            .field("port_numbers", &self.port_numbers)
+            .field("address", &self.device.address())
+            .field("children", &self.children)
+            .finish()
+    }
+}
+
+/// Arranges `devices` into trees, one per root device.
+pub(crate) fn build<T: UsbContext>(devices: Vec<Device<T>>) -> Vec<TopologyNode<T>> {
+    let keys: Vec<_> = devices
+        .iter()
+        .map(|device| {
+            (
+                device.bus_number(),
+                device.port_numbers().unwrap_or_default(),
+            )
+        })
+        .collect();
+    let parents = parents(&keys);
+    let depths: Vec<usize> = keys.iter().map(|(_, ports)| ports.len()).collect();
+
+    let mut nodes: Vec<_> = devices
+        .into_iter()
+        .zip(keys)
+        .map(|(device, (bus_number, port_numbers))| {
+            Some(TopologyNode {
+                device,
+                bus_number,
+                port_numbers,
+                children: Vec::new(),
+            })
+        })
+        .collect();
+
+    // children are moved into their parent before the parent itself is moved, deepest first
+    let mut order: Vec<usize> = (0..nodes.len()).collect();
+    order.sort_by_key(|&i| Reverse(depths[i]));
+
+    let mut roots = Vec::new();
+    for i in order {
+        let node = match nodes[i].take() {
+            Some(node) => node,
+            None => continue,
+        };
+        match parents[i].and_then(|parent| nodes[parent].as_mut()) {
+            Some(parent) => parent.children.push(node),
+            None => roots.push(node),
+        }
+    }
+
+    sort(&mut roots);
+    roots
+}
+
+fn sort<T: UsbContext>(nodes: &mut [TopologyNode<T>]) {
+    nodes.sort_by(|a, b| (a.bus_number, &a.port_numbers).cmp(&(b.bus_number, &b.port_numbers)));
+    for node in nodes {
+        sort(&mut node.children);
+    }
+}
+
+/// Returns the index of the parent of each device identified by its bus and port numbers: the
+/// closest device on the same bus whose port numbers are a prefix of its own.
+///
+/// A device whose hub isn't listed, e.g. because a device policy hides it, is attached to the
+/// closest ancestor that is listed.
+fn parents(keys: &[(u8, Vec<u8>)]) -> Vec<Option<usize>> {
+    let mut index = HashMap::new();
+    for (i, (bus, ports)) in keys.iter().enumerate() {
+        index.entry((*bus, ports.as_slice())).or_insert(i);
+    }
+
+    keys.iter()
+        .enumerate()
+        .map(|(i, (bus, ports))| {
+            (0..ports.len())
+                .rev()
+                .filter_map(|len| index.get(&(*bus, &ports[..len])).copied())
+                .find(|&parent| parent != i)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn keys(keys: &[(u8, &[u8])]) -> Vec<(u8, Vec<u8>)> {
+        keys.iter()
+            .map(|(bus, ports)| (*bus, ports.to_vec()))
+            .collect()
+    }
+
+    #[test]
+    fn it_attaches_devices_to_their_hub() {
+        let keys = keys(&[(1, &[]), (1, &[2]), (1, &[2, 3]), (2, &[]), (2, &[2])]);
+
+        assert_eq!(vec![None, Some(0), Some(1), None, Some(3)], parents(&keys));
+    }
+
+    #[test]
+    fn it_skips_hubs_that_are_not_listed() {
+        let keys = keys(&[(1, &[]), (1, &[2, 3, 1]), (3, &[4])]);
+
+        assert_eq!(vec![None, Some(0), None], parents(&keys));
+    }
+}
@@ -0,0 +1,100 @@
+use crate::error::Error;
+
+/// The outcome of a synchronous transfer, including any data moved before it failed.
+///
+/// libusb reports how many bytes were transferred even when a transfer times out, stalls or is
+/// interrupted. The plain `read_*`/`write_*` methods of `DeviceHandle` fold this into a single
+/// `Result`, which makes a timeout after partial data indistinguishable from a short transfer and
+/// discards partial data in other cases. The `*_partial` methods return a `TransferOutcome`
+/// instead, so protocols that can resume from partial reads and writes don't lose data.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct TransferOutcome {
+    len: usize,
+    error: Option<Error>,
+}
+
+impl TransferOutcome {
+    pub(crate) fn new(len: usize, error: Option<Error>) -> TransferOutcome {
+        TransferOutcome { len, error }
+    }
+
+    /// Returns the number of bytes transferred, whether or not the transfer completed.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Returns true if no bytes were transferred.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Returns the error that ended the transfer, or `None` if it completed.
+    pub fn error(&self) -> Option<Error> {
+        self.error
+    }
+
+    /// Indicates whether the transfer completed without error.
+    pub fn is_complete(&self) -> bool {
+        self.error.is_none()
+    }
+
+    /// Indicates whether the transfer timed out, with or without partial data.
+    pub fn timed_out(&self) -> bool {
+        self.error == Some(Error::Timeout)
+    }
+
+    /// Indicates whether the endpoint stalled, with or without partial data.
+    pub fn stalled(&self) -> bool {
+        self.error == Some(Error::Pipe)
+    }
+
+    /// Indicates whether the transfer failed after moving some, but not all, of its data.
+    pub fn is_partial(&self) -> bool {
+        self.error.is_some() && self.len > 0
+    }
+
+    /// Converts the outcome to a `Result`, returning the error even if some data was transferred.
+    pub fn into_result(self) -> crate::Result<usize> {
+        match self.error {
+            None => Ok(self.len),
+            Some(e) => Err(e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn it_reports_complete_transfers() {
+        let outcome = TransferOutcome::new(8, None);
+
+        assert!(outcome.is_complete());
+        assert!(!outcome.is_partial());
+        assert!(!outcome.timed_out());
+        assert_eq!(Ok(8), outcome.into_result());
+    }
+
+    #[test]
+    fn it_distinguishes_empty_and_partial_timeouts() {
+        let empty = TransferOutcome::new(0, Some(Error::Timeout));
+        assert!(empty.timed_out());
+        assert!(empty.is_empty());
+        assert!(!empty.is_partial());
+
+        let partial = TransferOutcome::new(3, Some(Error::Timeout));
+        assert!(partial.timed_out());
+        assert!(partial.is_partial());
+        assert_eq!(3, partial.len());
+        assert_eq!(Err(Error::Timeout), partial.into_result());
+    }
+
+    #[test]
+    fn it_reports_stalls() {
+        let outcome = TransferOutcome::new(0, Some(Error::Pipe));
+
+        assert!(outcome.stalled());
+        assert!(!outcome.timed_out());
+    }
+}
@@ -0,0 +1,181 @@
+use std::{
+    fmt,
+    marker::PhantomData,
+    ops::{Deref, DerefMut},
+    ptr::{self, NonNull},
+    slice,
+};
+
+use libc::{c_int, c_uchar, size_t};
+use libusb1_sys::libusb_device_handle;
+
+use crate::secure_buffer;
+
+// available since libusb 1.0.21, but not bound by libusb1-sys
+extern "system" {
+    fn libusb_dev_mem_alloc(dev_handle: *mut libusb_device_handle, length: size_t) -> *mut c_uchar;
+    fn libusb_dev_mem_free(
+        dev_handle: *mut libusb_device_handle,
+        buffer: *mut c_uchar,
+        length: size_t,
+    ) -> c_int;
+}
+
+/// A transfer buffer allocated by the kernel for a device, so transfers skip a copy.
+///
+/// Created by [`DeviceHandle::alloc_transfer_buffer`](struct.DeviceHandle.html#method.alloc_transfer_buffer).
+/// On Linux, libusb maps memory from the kernel's USB stack that the host controller reads and
+/// writes directly, instead of copying every transfer between the process and the kernel. For
+/// high-throughput devices, e.g. capture devices streaming hundreds of megabytes per second, the
+/// copies otherwise dominate CPU time. Where the platform can't provide such memory, the buffer
+/// is an ordinary heap allocation, see [`is_device_memory`](#method.is_device_memory).
+///
+/// The buffer dereferences to a byte slice, so it can be passed to the synchronous transfers or
+/// to a [`Transfer`](struct.Transfer.html), including isochronous ones. It borrows the handle
+/// it was allocated for, since the memory must be released before the device is closed.
+pub struct UsbMemory<'h> {
+    data: NonNull<u8>,
+    len: usize,
+    /// The handle the memory was mapped for, or `None` for a heap allocation.
+    handle: Option<NonNull<libusb_device_handle>>,
+    /// Whether the memory is zeroed when dropped, and unlocked if it is a heap allocation.
+    secure: bool,
+    _handle: PhantomData<&'h ()>,
+}
+
+// the buffer is plain memory, which the handle only needs to release
+unsafe impl Send for UsbMemory<'_> {}
+unsafe impl Sync for UsbMemory<'_> {}
+
+impl<'h> UsbMemory<'h> {
+    /// Allocates `len` zeroed bytes of device memory for `handle`, or heap memory if the
+    /// platform doesn't support it.
+    ///
+    /// ## Safety
+    ///
+    /// `handle` must be an open handle that outlives `'h`.
+    pub(crate) unsafe fn alloc(handle: *mut libusb_device_handle, len: usize) -> UsbMemory<'h> {
+        if len > 0 {
+            if let Some(data) = NonNull::new(libusb_dev_mem_alloc(handle, len)) {
+                ptr::write_bytes(data.as_ptr(), 0, len);
+
+                return UsbMemory {
+                    data,
+                    len,
+                    handle: NonNull::new(handle),
+                    secure: false,
+                    _handle: PhantomData,
+                };
+            }
+        }
+
+        UsbMemory::heap(len)
+    }
+
+    /// Allocates `len` zeroed bytes of heap memory.
+    pub(crate) fn heap(len: usize) -> UsbMemory<'h> {
+        let data = Box::into_raw(vec![0u8; len].into_boxed_slice());
+
+        UsbMemory {
+            // a boxed slice is never null, even when empty
+            data: unsafe { NonNull::new_unchecked(data as *mut u8) },
+            len,
+            handle: None,
+            secure: false,
+            _handle: PhantomData,
+        }
+    }
+
+    /// Allocates `len` zeroed bytes like [`alloc`](#method.alloc), locking heap memory, and
+    /// zeroes them when dropped.
+    ///
+    /// ## Safety
+    ///
+    /// `handle` must be an open handle that outlives `'h`.
+    pub(crate) unsafe fn alloc_secure(
+        handle: *mut libusb_device_handle,
+        len: usize,
+    ) -> crate::Result<UsbMemory<'h>> {
+        let mut memory = UsbMemory::alloc(handle, len);
+        // device memory is mapped from the kernel, which never swaps it out
+        if !memory.is_device_memory() {
+            secure_buffer::lock(&mut memory)?;
+        }
+        memory.secure = true;
+        Ok(memory)
+    }
+
+    /// Indicates whether the buffer is device memory, rather than the heap fallback.
+    pub fn is_device_memory(&self) -> bool {
+        self.handle.is_some()
+    }
+}
+
+impl Deref for UsbMemory<'_> {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        unsafe { slice::from_raw_parts(self.data.as_ptr(), self.len) }
+    }
+}
+
+impl DerefMut for UsbMemory<'_> {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        unsafe { slice::from_raw_parts_mut(self.data.as_ptr(), self.len) }
+    }
+}
+
+impl Drop for UsbMemory<'_> {
+    fn drop(&mut self) {
+        if self.secure {
+            secure_buffer::zeroize(self);
+            if !self.is_device_memory() {
+                secure_buffer::unlock(self);
+            }
+        }
+
+        unsafe {
+            match self.handle {
+                Some(handle) => {
+                    libusb_dev_mem_free(handle.as_ptr(), self.data.as_ptr(), self.len);
+                }
+                None => drop(Box::from_raw(ptr::slice_from_raw_parts_mut(
+                    self.data.as_ptr(),
+                    self.len,
+                ))),
+            }
+        }
+    }
+}
+
+impl fmt::Debug for UsbMemory<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("UsbMemory")
+            .field("len", &self.len)
+            .field("device_memory", &self.is_device_memory())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn it_falls_back_to_zeroed_heap_memory() {
+        let mut buffer = UsbMemory::heap(16);
+
+        assert!(!buffer.is_device_memory());
+        assert_eq!(&[0; 16], &buffer[..]);
+
+        buffer[3] = 7;
+        assert_eq!(7, buffer[3]);
+    }
+
+    #[test]
+    fn it_allocates_empty_buffers() {
+        let buffer = UsbMemory::heap(0);
+
+        assert!(buffer.is_empty());
+    }
+}
@@ -0,0 +1,58 @@
+//! Runs traffic through a FunctionFS loopback gadget. Skipped unless a gadget can be set up,
+//! which takes root privileges and a device controller such as `dummy_hcd`.
+#![cfg(all(feature = "loopback", target_os = "linux"))]
+// the tests perform synchronous transfers, deprecated with the `async-only` feature
+#![allow(deprecated)]
+
+use std::time::Duration;
+
+use rusb::{loopback::Loopback, Context};
+
+const TIMEOUT: Duration = Duration::from_secs(2);
+
+#[test]
+fn it_echoes_bulk_transfers() {
+    let loopback = match Loopback::setup("rusb_test", None) {
+        Ok(loopback) => loopback,
+        Err(e) => {
+            eprintln!("skipping, no loopback gadget: {}", e);
+            return;
+        }
+    };
+
+    let context = Context::new().unwrap();
+    let device = loopback.open(&context, Duration::from_secs(10)).unwrap();
+
+    // short, packet-sized, and multi-packet transfers at both full and high speed sizes
+    for &size in &[1, 63, 64, 65, 511, 512, 513, 4096, 65536 + 7] {
+        let data: Vec<u8> = (0..size).map(|i| (i * 7) as u8).collect();
+        assert_eq!(
+            data,
+            device.round_trip(&data, TIMEOUT).unwrap(),
+            "size {}",
+            size
+        );
+    }
+}
+
+#[test]
+fn it_times_out_reading_without_data() {
+    let loopback = match Loopback::setup("rusb_test_timeout", None) {
+        Ok(loopback) => loopback,
+        Err(e) => {
+            eprintln!("skipping, no loopback gadget: {}", e);
+            return;
+        }
+    };
+
+    let context = Context::new().unwrap();
+    let device = loopback.open(&context, Duration::from_secs(10)).unwrap();
+
+    let mut buf = [0u8; 64];
+    assert_eq!(
+        Err(rusb::Error::Timeout),
+        device
+            .handle()
+            .read_bulk(device.endpoint_in(), &mut buf, Duration::from_millis(100))
+    );
+}
@@ -0,0 +1,62 @@
+//! Checks at compile time which types can be sent to or shared between threads. The types that
+//! must not be shared are checked by the compile-fail cases in `tests/ui`.
+
+use rusb::{
+    AsyncGroup, Context, ContextPool, Device, DeviceHandle, DeviceList, EventLoop, GlobalContext,
+    HotplugEvents, InterruptPoller, OwnedTransfer, ReadFuture, Registration, Transfer, UsbMemory,
+    WriteFuture,
+};
+
+fn send<T: Send>() {}
+fn sync<T: Sync>() {}
+
+#[test]
+fn it_shares_contexts_devices_and_handles() {
+    send::<Context>();
+    sync::<Context>();
+    send::<GlobalContext>();
+    sync::<GlobalContext>();
+    send::<ContextPool>();
+    sync::<ContextPool>();
+
+    send::<Device<Context>>();
+    sync::<Device<Context>>();
+    send::<DeviceList<Context>>();
+    sync::<DeviceList<Context>>();
+
+    // a handle can be used from several threads at once, e.g. behind an `Arc`, without a `Mutex`
+    send::<DeviceHandle<Context>>();
+    sync::<DeviceHandle<Context>>();
+}
+
+#[test]
+fn it_sends_transfers() {
+    send::<Transfer<'static, Context>>();
+    sync::<Transfer<'static, Context>>();
+    send::<AsyncGroup<'static, Context>>();
+    send::<InterruptPoller<'static, Context>>();
+
+    send::<ReadFuture>();
+    send::<WriteFuture>();
+    send::<OwnedTransfer<Context>>();
+
+    send::<UsbMemory<'static>>();
+    sync::<UsbMemory<'static>>();
+}
+
+#[test]
+fn it_sends_event_handling_helpers() {
+    send::<EventLoop<Context>>();
+    sync::<EventLoop<Context>>();
+    send::<Registration<Context>>();
+    sync::<Registration<Context>>();
+    send::<HotplugEvents<Context>>();
+    sync::<HotplugEvents<Context>>();
+}
+
+// the completion state of pending transfers is only synchronized with the thread handling events,
+// not between threads sharing them
+#[test]
+fn it_rejects_sharing_pending_transfers() {
+    trybuild::TestCases::new().compile_fail("tests/ui/share_*.rs");
+}
@@ -0,0 +1,11 @@
+use rusb::{AsyncGroup, Context};
+
+fn share(shared: &AsyncGroup<'_, Context>) {
+    std::thread::scope(|scope| {
+        scope.spawn(move || {
+            let _shared = shared;
+        });
+    });
+}
+
+fn main() {}
@@ -0,0 +1,11 @@
+use rusb::{Context, InterruptPoller};
+
+fn share(shared: &InterruptPoller<'_, Context>) {
+    std::thread::scope(|scope| {
+        scope.spawn(move || {
+            let _shared = shared;
+        });
+    });
+}
+
+fn main() {}
@@ -0,0 +1,11 @@
+use rusb::{Context, OwnedTransfer};
+
+fn share(shared: &OwnedTransfer<Context>) {
+    std::thread::scope(|scope| {
+        scope.spawn(move || {
+            let _shared = shared;
+        });
+    });
+}
+
+fn main() {}
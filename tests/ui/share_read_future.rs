@@ -0,0 +1,11 @@
+use rusb::ReadFuture;
+
+fn share(shared: &ReadFuture) {
+    std::thread::scope(|scope| {
+        scope.spawn(move || {
+            let _shared = shared;
+        });
+    });
+}
+
+fn main() {}
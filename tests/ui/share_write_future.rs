@@ -0,0 +1,11 @@
+use rusb::WriteFuture;
+
+fn share(shared: &WriteFuture) {
+    std::thread::scope(|scope| {
+        scope.spawn(move || {
+            let _shared = shared;
+        });
+    });
+}
+
+fn main() {}